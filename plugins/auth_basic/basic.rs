@@ -1,5 +1,7 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
+mod config;
+
 use argon2::{
     password_hash::{rand_core::OsRng, SaltString},
     Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
@@ -11,7 +13,8 @@ use common::{
     AuthAccountDeleteError, AuthAccountSetError, AuthSaveToFileError, Authenticator, LogEntry,
     LogLevel, LogSource, Username, ValidateLoginResponse, ValidateResponse,
 };
-use headers::authorization::{Basic, Credentials};
+use config::{ApiToken, Config};
+use headers::authorization::{Basic, Bearer, Credentials};
 use http::{header, HeaderMap, HeaderValue, StatusCode};
 use plugin::{
     types::{NewAuthError, NewAuthFn, Templates},
@@ -23,6 +26,7 @@ use std::{
     fs::{self, File},
     io::Write,
     path::{Path, PathBuf},
+    str::FromStr,
     sync::Arc,
 };
 use tokio::{runtime::Handle, sync::Mutex};
@@ -72,6 +76,7 @@ impl plugin::Plugin for AuthBasicPlugin {
 
 pub struct BasicAuth {
     data: Mutex<BasicAuthData>,
+    api_tokens: Vec<ApiToken>,
 
     // Limit parallel hashing operations to mitigate resource exhaustion attacks.
     hash_lock: Mutex<()>,
@@ -85,10 +90,13 @@ impl BasicAuth {
     pub fn new(
         rt_handle: Handle,
         configs_dir: &Path,
+        raw_env_config: &str,
         logger: ArcLogger,
     ) -> Result<ArcAuth, NewAuthError> {
         use NewAuthError::*;
 
+        let config = Config::from_str(raw_env_config).map_err(|e| ParseEnvConfig(e.to_string()))?;
+
         let path = configs_dir.join("accounts.json");
         let path_string = path.to_string_lossy().to_string();
 
@@ -113,6 +121,7 @@ impl BasicAuth {
 
         let auth = BasicAuth {
             data: Mutex::new(data),
+            api_tokens: config.api_tokens,
             hash_lock: Mutex::new(()),
             logger,
             rt_handle,
@@ -135,6 +144,10 @@ impl BasicAuth {
             return None;
         };
 
+        if let Some(bearer) = decode_bearer(auth_header) {
+            return self.validate_api_token(bearer.token());
+        }
+
         let Some(auth_header) = Basic::decode(auth_header) else {
             return None;
         };
@@ -180,6 +193,28 @@ impl BasicAuth {
         }
     }
 
+    // Every token is compared to not leak which one matched.
+    fn validate_api_token(&self, token: &str) -> Option<ValidateLoginResponse> {
+        let mut response = None;
+        for api_token in &self.api_tokens {
+            if constant_time_eq(api_token.token.as_bytes(), token.as_bytes()) {
+                response = Some(ValidateLoginResponse {
+                    is_admin: api_token.admin,
+                    token: String::new(),
+                });
+            }
+        }
+        if response.is_none() {
+            self.logger.log(LogEntry::new(
+                LogLevel::Warning,
+                "auth",
+                None,
+                "failed login: invalid api token".to_owned(),
+            ));
+        }
+        response
+    }
+
     async fn passwords_match(&self, hash: String, plaintext: String) -> bool {
         // Lock hash_lock to prevent parallel password verifications.
         let _hash_guard = self.hash_lock.lock();
@@ -205,6 +240,15 @@ impl Authenticator for BasicAuth {
             return None;
         };
 
+        // Browsers don't send bearer tokens automatically, CSRF isn't possible.
+        if is_bearer(headers) {
+            return Some(ValidateResponse {
+                is_admin: valid_login.is_admin,
+                token: valid_login.token,
+                token_valid: true,
+            });
+        }
+
         let token_matches = || {
             let Some(csrf_header) = headers.get("X-CSRF-TOKEN") else {
                 return false;
@@ -361,6 +405,30 @@ async fn generate_password_hash(rt_handle: &Handle, plain_password: String) -> S
         .expect("join")
 }
 
+fn is_bearer(headers: &HeaderMap<HeaderValue>) -> bool {
+    headers
+        .get("Authorization")
+        .and_then(decode_bearer)
+        .is_some()
+}
+
+// `Bearer::decode` doesn't check the scheme.
+fn decode_bearer(value: &HeaderValue) -> Option<Bearer> {
+    let scheme = value.as_bytes().get(..Bearer::SCHEME.len())?;
+    if !scheme.eq_ignore_ascii_case(Bearer::SCHEME.as_bytes()) {
+        return None;
+    }
+    Bearer::decode(value)
+}
+
+// Compares two byte slices in constant time to avoid timing leaks.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
 // Generates a CSRF-token.
 fn gen_token() -> String {
     rand::thread_rng()
@@ -502,6 +570,16 @@ mod tests {
 
         let auth = BasicAuth {
             data: Mutex::new(data),
+            api_tokens: vec![
+                ApiToken {
+                    token: "api_user".to_owned(),
+                    admin: false,
+                },
+                ApiToken {
+                    token: "api_admin".to_owned(),
+                    admin: true,
+                },
+            ],
             hash_lock: Mutex::new(()),
             logger: DummyLogger::new(),
             rt_handle: tokio::runtime::Handle::current(),
@@ -550,6 +628,27 @@ mod tests {
         }
     }
 
+    #[test_case(None, None; "missing")]
+    #[test_case(Some("Bearer wrong"), None; "wrong")]
+    #[test_case(Some("Bearer api_use"), None; "prefix")]
+    #[test_case(Some("Bearer api_user"), Some(false); "user")]
+    #[test_case(Some("Bearer api_admin"), Some(true); "admin")]
+    #[tokio::test]
+    async fn test_auth_validate_request_bearer(auth_header: Option<&str>, is_admin: Option<bool>) {
+        let (_, auth) = new_test_auth();
+
+        let mut headers = HeaderMap::new();
+        if let Some(auth_header) = auth_header {
+            headers.insert("Authorization", HeaderValue::from_str(auth_header).unwrap());
+        }
+
+        let result = auth.validate_request(&headers).await;
+        assert_eq!(is_admin, result.as_ref().map(|v| v.is_admin));
+        if let Some(valid_login) = result {
+            assert!(valid_login.token_valid);
+        }
+    }
+
     #[tokio::test]
     async fn test_auth_accounts() {
         let (_, auth) = new_test_auth();
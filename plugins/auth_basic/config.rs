@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use serde::Deserialize;
+use std::str::FromStr;
+use thiserror::Error;
+use toml::Value;
+
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+pub(crate) struct Config {
+    // Static tokens accepted in the "Authorization: Bearer <token>" header.
+    #[serde(default)]
+    pub(crate) api_tokens: Vec<ApiToken>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub(crate) struct ApiToken {
+    pub(crate) token: String,
+
+    #[serde(default)]
+    pub(crate) admin: bool,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum ParseConfigError {
+    #[error("no table")]
+    NoTable,
+
+    #[error("deserialize: {0}")]
+    Deserialize(#[from] toml::de::Error),
+
+    #[error("empty api token")]
+    EmptyToken,
+}
+
+impl FromStr for Config {
+    type Err = ParseConfigError;
+
+    // The default config is returned if there's no auth_basic section.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use ParseConfigError::*;
+        let value: Value = toml::from_str(s)?;
+        let Value::Table(table) = value else {
+            return Err(NoTable);
+        };
+        let Some(Value::Array(plugins)) = table.get("plugin") else {
+            return Ok(Self::default());
+        };
+        for plugin in plugins {
+            let Value::Table(plugin) = plugin else {
+                continue;
+            };
+            let Some(Value::String(name)) = plugin.get("name") else {
+                continue;
+            };
+            if name != "auth_basic" {
+                continue;
+            }
+            let config = plugin.to_owned().try_into::<Config>()?;
+            if config.api_tokens.iter().any(|v| v.token.is_empty()) {
+                return Err(EmptyToken);
+            }
+            return Ok(config);
+        }
+        Ok(Self::default())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let raw = "
+port = 2020
+
+[[plugin]]
+name = \"auth_basic\"
+enable = true
+api_tokens = [
+    { token = \"a\" },
+    { token = \"b\", admin = true },
+]
+
+[[plugin]]
+name = \"auth_none\"
+enable = false";
+        assert_eq!(
+            Config {
+                api_tokens: vec![
+                    ApiToken {
+                        token: "a".to_owned(),
+                        admin: false,
+                    },
+                    ApiToken {
+                        token: "b".to_owned(),
+                        admin: true,
+                    },
+                ],
+            },
+            Config::from_str(raw).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_config_empty_token() {
+        let raw = "
+[[plugin]]
+name = \"auth_basic\"
+enable = true
+api_tokens = [{ token = \"\" }]";
+        assert!(matches!(
+            Config::from_str(raw),
+            Err(ParseConfigError::EmptyToken)
+        ));
+    }
+}
@@ -67,6 +67,7 @@ impl NoneAuth {
     pub fn new(
         rt_handle: Handle,
         configs_dir: &Path,
+        _: &str,
         _: ArcLogger,
     ) -> Result<ArcAuth, NewAuthError> {
         use NewAuthError::*;
@@ -392,7 +392,11 @@ async fn enable_handler(
             .into_response();
     };
 
-    if let Err(e) = s.monitor_manager.monitor_set_and_restart(new_config).await {
+    if let Err(e) = s
+        .monitor_manager
+        .monitor_set_and_restart_graceful(new_config)
+        .await
+    {
         return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
     }
 
@@ -424,7 +428,11 @@ async fn disable_handler(
         None => old_config.clone(),
     };
 
-    if let Err(e) = s.monitor_manager.monitor_set_and_restart(new_config).await {
+    if let Err(e) = s
+        .monitor_manager
+        .monitor_set_and_restart_graceful(new_config)
+        .await
+    {
         return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
     }
 
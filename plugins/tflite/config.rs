@@ -1,6 +1,10 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
-use crate::detector::{DetectorName, Thresholds};
+use crate::{
+    cooldown::CooldownConfig,
+    detector::{DetectorName, Thresholds},
+    zone::Zones,
+};
 use common::{
     monitor::MonitorConfig,
     recording::{denormalize, DurationSec, FeedRateSec},
@@ -8,7 +12,10 @@ use common::{
 };
 use serde::Deserialize;
 use serde_json::Value;
-use std::{num::NonZeroU16, ops::Deref};
+use std::{
+    num::{NonZeroU16, NonZeroU32},
+    ops::Deref,
+};
 use thiserror::Error;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -17,10 +24,15 @@ pub(crate) struct TfliteConfig {
     pub thresholds: Thresholds,
     pub crop: Crop,
     pub mask: Mask,
+    pub zones: Zones,
+    pub cooldown: Option<CooldownConfig>,
     pub detector_name: DetectorName,
     pub feed_rate: FeedRateSec,
+    pub detect_every_n_frames: NonZeroU32,
     pub duration: DurationSec,
     pub use_sub_stream: bool,
+    pub score_histogram: bool,
+    pub oversized_frame: OversizedFrame,
 }
 
 #[derive(Deserialize)]
@@ -30,15 +42,52 @@ struct RawConfigV1 {
     crop: Crop,
     mask: Mask,
 
+    #[serde(default)]
+    zones: Zones,
+
+    #[serde(default)]
+    cooldown: Option<CooldownConfig>,
+
     #[serde(rename = "detectorName")]
     detector_name: DetectorName,
 
     #[serde(rename = "feedRate")]
     feed_rate: FeedRateSec,
+
+    // Frames between detections, detections are kept for the skipped frames.
+    #[serde(
+        rename = "detectEveryNFrames",
+        default = "default_detect_every_n_frames"
+    )]
+    detect_every_n_frames: NonZeroU32,
+
     duration: DurationSec,
 
     #[serde(rename = "useSubStream")]
     use_sub_stream: bool,
+
+    // Record the raw scores of all detections, see `ScoreHistogram`.
+    #[serde(rename = "scoreHistogram", default)]
+    score_histogram: bool,
+
+    #[serde(rename = "oversizedFrame", default)]
+    oversized_frame: OversizedFrame,
+}
+
+fn default_detect_every_n_frames() -> NonZeroU32 {
+    NonZeroU32::MIN
+}
+
+// What to do with frames that are larger than the detector input.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OversizedFrame {
+    // Downscale the frames and log a warning.
+    #[default]
+    Downscale,
+
+    // Stop detection with a `FrameTooLarge` error.
+    Error,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
@@ -83,10 +132,15 @@ impl TfliteConfig {
             thresholds: c.thresholds,
             crop: c.crop,
             mask: c.mask,
+            zones: c.zones,
+            cooldown: c.cooldown,
             detector_name: c.detector_name,
             feed_rate: c.feed_rate,
+            detect_every_n_frames: c.detect_every_n_frames,
             duration: c.duration,
             use_sub_stream: c.use_sub_stream,
+            score_histogram: c.score_histogram,
+            oversized_frame: c.oversized_frame,
         }))
     }
 }
@@ -272,10 +326,15 @@ mod tests {
                 "thresholds":   {"5": 6},
                 "crop":         [7, 8, 9],
                 "mask":         {"enable": true, "area": [[10,11],[12,13]]},
+                "zones":        {"exclude": [[[14,15],[16,17],[18,19]]], "overlap": 20},
+                "cooldown":     {"duration": 21, "iou": 22},
                 "detectorName": "14",
                 "feedRate":     0.2,
+                "detectEveryNFrames": 3,
                 "duration":     15,
-                "useSubStream": true
+                "useSubStream": true,
+                "scoreHistogram": true,
+                "oversizedFrame": "error"
             }
         });
 
@@ -298,14 +357,51 @@ mod tests {
                     PointNormalized { x: 12, y: 13 },
                 ],
             },
+            zones: Zones {
+                include: Vec::new(),
+                exclude: vec![vec![
+                    PointNormalized { x: 14, y: 15 },
+                    PointNormalized { x: 16, y: 17 },
+                    PointNormalized { x: 18, y: 19 },
+                ]],
+                overlap: Some(20.try_into().unwrap()),
+            },
+            cooldown: Some(CooldownConfig {
+                duration: DurationSec::new(Duration::from_secs(21)),
+                iou: 22.try_into().unwrap(),
+            }),
             detector_name: "14".to_owned().try_into().unwrap(),
             feed_rate: FeedRateSec::new(Duration::from_secs(5)),
+            detect_every_n_frames: NonZeroU32::new(3).unwrap(),
             duration: DurationSec::new(Duration::from_secs(15)),
             use_sub_stream: true,
+            score_histogram: true,
+            oversized_frame: OversizedFrame::Error,
         };
         assert_eq!(want, got);
     }
 
+    #[test]
+    fn test_parse_config_oversized_frame_default() {
+        let mut raw = json!({
+            "tflite": {
+                "enable": true,
+                "thresholds": {},
+                "crop": { "size": 1_000_000, "x": 0, "y": 0 },
+                "mask": { "enable": false, "area": [] },
+                "detectorName": "test",
+                "feedRate": 0.2,
+                "duration": 0,
+                "useSubStream": true
+            }
+        });
+        let got = parse(&raw).unwrap();
+        assert_eq!(OversizedFrame::Downscale, got.oversized_frame);
+
+        raw["tflite"]["oversizedFrame"] = json!("x");
+        assert!(TfliteConfig::parse(raw, DummyLogger::new()).is_err());
+    }
+
     #[test]
     fn test_parse_config_empty() {
         let raw = serde_json::Value::String(String::new());
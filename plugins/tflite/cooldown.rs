@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::config::Percent;
+use common::{
+    recording::DurationSec,
+    time::{Duration, UnixNano},
+    Detection, Label, RectangleNormalized,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use tflite_lib::Tracker;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub(crate) struct CooldownConfig {
+    pub duration: DurationSec,
+
+    // Minimum IoU for a detection to be the same object as a previous one.
+    pub iou: Percent,
+}
+
+// Suppresses events for a duration after each emitted event. The cooldown
+// is cut short if a new object appears. Objects are tracked across frames
+// by the IoU tracker, objects that haven't been seen for the cooldown
+// duration are forgotten.
+pub(crate) struct EventCooldown {
+    duration: Duration,
+    last_event: Option<UnixNano>,
+    tracker: Tracker,
+    last_seen: HashMap<u64, UnixNano>,
+
+    // The tracker matches classes, labels are assigned a class on first use.
+    classes: HashMap<Label, u8>,
+}
+
+impl EventCooldown {
+    pub(crate) fn new(config: &CooldownConfig) -> Self {
+        Self {
+            duration: *config.duration,
+            last_event: None,
+            // Tracks are removed by time instead of by frame count.
+            tracker: Tracker::new(config.iou.as_f32() / 100.0, u32::MAX),
+            last_seen: HashMap::new(),
+            classes: HashMap::new(),
+        }
+    }
+
+    // Returns true if a event should be emitted for the detections.
+    // `time` must not decrease between calls.
+    pub(crate) fn check(&mut self, time: UnixNano, detections: &[Detection]) -> bool {
+        let forget_before = time.checked_sub(self.duration.into()).unwrap_or_default();
+        self.last_seen
+            .retain(|_, last_seen| !last_seen.before(forget_before));
+        self.tracker.retain(|id| self.last_seen.contains_key(&id));
+
+        let mut boxes = Vec::with_capacity(detections.len());
+        for d in detections {
+            let Some(rect) = &d.region.rectangle else {
+                continue;
+            };
+            let Some(class) = self.class(&d.label) else {
+                continue;
+            };
+            boxes.push(to_box(class, rect));
+        }
+
+        let mut new_object = false;
+        for tracked in self.tracker.update(boxes) {
+            if self.last_seen.insert(tracked.track_id, time).is_none() {
+                new_object = true;
+            }
+        }
+
+        let cooldown_over = self.last_event.map_or(true, |last_event| {
+            last_event
+                .checked_add(self.duration.into())
+                .map_or(true, |end| !time.before(end))
+        });
+        if cooldown_over || new_object {
+            self.last_event = Some(time);
+            return true;
+        }
+        false
+    }
+
+    // Returns None if there are more than 256 labels.
+    fn class(&mut self, label: &Label) -> Option<u8> {
+        if let Some(class) = self.classes.get(label) {
+            return Some(*class);
+        }
+        let class = u8::try_from(self.classes.len()).ok()?;
+        self.classes.insert(label.clone(), class);
+        Some(class)
+    }
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::as_conversions)]
+fn to_box(class: u8, rect: &RectangleNormalized) -> tflite_lib::Detection {
+    let x = f64::from(rect.x);
+    let y = f64::from(rect.y);
+    tflite_lib::Detection {
+        score: 0.0,
+        class,
+        top: y as f32,
+        left: x as f32,
+        bottom: (y + f64::from(rect.height.get())) as f32,
+        right: (x + f64::from(rect.width.get())) as f32,
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::Region;
+    use std::num::NonZeroU32;
+
+    fn detection(label: &str, x: u32) -> Detection {
+        Detection {
+            label: label.to_owned().try_into().unwrap(),
+            score: 50.0,
+            region: Region {
+                rectangle: Some(RectangleNormalized {
+                    x,
+                    y: 0,
+                    width: NonZeroU32::new(100).unwrap(),
+                    height: NonZeroU32::new(100).unwrap(),
+                }),
+                polygon: None,
+            },
+        }
+    }
+
+    fn secs(secs: u32) -> UnixNano {
+        UnixNano::from(Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn test_event_cooldown() {
+        let mut cooldown = EventCooldown::new(&CooldownConfig {
+            duration: DurationSec::new(Duration::from_secs(10)),
+            iou: 50.try_into().unwrap(),
+        });
+
+        assert!(cooldown.check(secs(0), &[detection("a", 0)]));
+
+        // Same object moving slightly, suppressed.
+        assert!(!cooldown.check(secs(1), &[detection("a", 10)]));
+        assert!(!cooldown.check(secs(2), &[detection("a", 20)]));
+
+        // New object, fires.
+        assert!(cooldown.check(secs(3), &[detection("a", 20), detection("a", 500)]));
+        assert!(!cooldown.check(secs(4), &[detection("a", 20), detection("a", 500)]));
+
+        // Different label at the same position, fires.
+        assert!(cooldown.check(secs(5), &[detection("b", 20)]));
+
+        // Cooldown is over.
+        assert!(cooldown.check(secs(15), &[detection("a", 20)]));
+    }
+
+    #[test]
+    fn test_event_cooldown_forget() {
+        let mut cooldown = EventCooldown::new(&CooldownConfig {
+            duration: DurationSec::new(Duration::from_secs(10)),
+            iou: 50.try_into().unwrap(),
+        });
+        assert!(cooldown.check(secs(0), &[detection("a", 0)]));
+        assert!(cooldown.check(secs(5), &[detection("a", 500)]));
+
+        // The first object hasn't been seen for 10 seconds and is forgotten.
+        assert!(cooldown.check(secs(11), &[detection("a", 0)]));
+    }
+}
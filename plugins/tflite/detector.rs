@@ -7,36 +7,41 @@ use crate::{
     Fetcher,
 };
 use common::{
-    ArcMsgLogger, Detection, Detections, Label, Labels, LogLevel, RectangleNormalized, Region,
+    ArcMsgLogger, DetectFilter, Detection, Detections, Label, Labels, LogLevel, ObjectDetector,
+    RectangleNormalized, Region,
 };
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::Debug,
-    num::{NonZeroU16, NonZeroU32, NonZeroU8},
+    num::{NonZeroU16, NonZeroU32, NonZeroU8, NonZeroUsize},
     ops::Deref,
-    path::Path,
+    path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tflite_lib::{
-    debug_device, edgetpu_verbosity, list_edgetpu_devices, EdgetpuDevice, NewDetectorError,
+    debug_device, edgetpu_verbosity, list_edgetpu_devices, new_transient_detector, ChannelOrder,
+    ColorMatrix, EdgetpuDevice, EdgetpuDeviceType, FrameFormat, Image, ModelFormat, ModelOutput,
+    ModelRegistry, NewDetectorError, NewTransientDetectorError, NmsMode,
 };
 use thiserror::Error;
 use tokio::{
     runtime::Handle,
-    sync::{mpsc, oneshot},
+    sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore},
 };
 use url::Url;
 
-#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Default, Deserialize, PartialEq)]
 #[serde(default)]
 struct RawDetectorConfigs {
+    max_concurrent_detections: Option<NonZeroUsize>,
     detector_cpu: Vec<RawDetectorConfigCpu>,
     detector_edgetpu: Vec<RawDetectorConfigEdgeTpu>,
+    detector_onnx: Vec<RawDetectorConfigOnnx>,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, PartialEq)]
 struct RawDetectorConfigCpu {
     enable: bool,
     name: DetectorName,
@@ -46,9 +51,28 @@ struct RawDetectorConfigCpu {
     sha256sum: ModelChecksum,
     label_map: Url,
     threads: NonZeroU8,
+
+    #[serde(default)]
+    ignored_classes: HashSet<u16>,
+
+    #[serde(default)]
+    nms: Option<RawNmsMode>,
+
+    // Use the XNNPACK delegate.
+    #[serde(default)]
+    cpu_accel: bool,
+
+    #[serde(default)]
+    channel_order: RawChannelOrder,
+
+    // Normalized box area bounds.
+    #[serde(default)]
+    min_area: f32,
+    #[serde(default = "default_max_area")]
+    max_area: f32,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, PartialEq)]
 struct RawDetectorConfigEdgeTpu {
     enable: bool,
     name: DetectorName,
@@ -58,6 +82,129 @@ struct RawDetectorConfigEdgeTpu {
     sha256sum: ModelChecksum,
     label_map: Url,
     device: String,
+
+    #[serde(default)]
+    ignored_classes: HashSet<u16>,
+
+    #[serde(default)]
+    nms: Option<RawNmsMode>,
+
+    #[serde(default)]
+    channel_order: RawChannelOrder,
+
+    // Normalized box area bounds.
+    #[serde(default)]
+    min_area: f32,
+    #[serde(default = "default_max_area")]
+    max_area: f32,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct RawDetectorConfigOnnx {
+    enable: bool,
+    name: DetectorName,
+    width: NonZeroU16,
+    height: NonZeroU16,
+    model: Url,
+    sha256sum: ModelChecksum,
+    label_map: Url,
+    threads: NonZeroU8,
+
+    #[serde(default)]
+    ignored_classes: HashSet<u16>,
+
+    #[serde(default)]
+    nms: Option<RawNmsMode>,
+
+    #[serde(default)]
+    channel_order: RawChannelOrder,
+
+    // Normalized box area bounds.
+    #[serde(default)]
+    min_area: f32,
+    #[serde(default = "default_max_area")]
+    max_area: f32,
+}
+
+// Non-max suppression applied to the model output. Most models
+// already suppress overlapping boxes, this is disabled by default.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum RawNmsMode {
+    Suppress,
+    Fusion,
+}
+
+impl From<RawNmsMode> for NmsMode {
+    fn from(value: RawNmsMode) -> Self {
+        match value {
+            RawNmsMode::Suppress => NmsMode::Suppress,
+            RawNmsMode::Fusion => NmsMode::Fusion,
+        }
+    }
+}
+
+// Channel order of the model input.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum RawChannelOrder {
+    #[default]
+    Rgb,
+    Bgr,
+}
+
+impl From<RawChannelOrder> for ChannelOrder {
+    fn from(value: RawChannelOrder) -> Self {
+        match value {
+            RawChannelOrder::Rgb => ChannelOrder::Rgb,
+            RawChannelOrder::Bgr => ChannelOrder::Bgr,
+        }
+    }
+}
+
+const NMS_IOU_THRESHOLD: f32 = 0.5;
+
+fn default_max_area() -> f32 {
+    1.0
+}
+
+// Drops detections with a normalized area outside the bounds, the bounds
+// are inclusive. Tiny boxes and full frame boxes are usually noise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct AreaFilter {
+    min: f32,
+    max: f32,
+}
+
+impl AreaFilter {
+    // Returns None if the bounds are outside 0.0 to 1.0 or min is larger than max.
+    fn new(min: f32, max: f32) -> Option<Self> {
+        ((0.0..=1.0).contains(&min) && (0.0..=1.0).contains(&max) && min <= max)
+            .then_some(Self { min, max })
+    }
+
+    // A bound of 0.0 or 1.0 never drops anything.
+    fn apply(self, detections: Vec<tflite_lib::Detection>) -> Vec<tflite_lib::Detection> {
+        let check_min = self.min > 0.0;
+        let check_max = self.max < 1.0;
+        if !check_min && !check_max {
+            return detections;
+        }
+        detections
+            .into_iter()
+            .filter(|d| {
+                let area = visible_area(d);
+                (!check_min || self.min <= area) && (!check_max || area <= self.max)
+            })
+            .collect()
+    }
+}
+
+// Area of the part of the box that is inside the frame.
+fn visible_area(d: &tflite_lib::Detection) -> f32 {
+    let width = d.right.min(1.0) - d.left.max(0.0);
+    let height = d.bottom.min(1.0) - d.top.max(0.0);
+    width.max(0.0) * height.max(0.0)
 }
 
 type DetectorConfigs = HashMap<DetectorName, DetectorConfig>;
@@ -139,12 +286,15 @@ pub(crate) struct Detector {
     detect_tx: async_channel::Sender<DetectRequest>,
     width: NonZeroU16,
     height: NonZeroU16,
+
+    // Shared by all detectors, limits the number of concurrent detections.
+    limit: Option<Arc<Semaphore>>,
 }
 
 #[derive(Debug, Error)]
 pub(crate) enum DetectError {
     #[error["{0}"]]
-    Detect(#[from] tflite_lib::DetectError),
+    Detect(#[from] common::DetectError),
 
     #[error("detector did not repond in 1 second")]
     DetectorTimeout,
@@ -155,10 +305,25 @@ pub(crate) enum DetectError {
 
 impl Detector {
     #[allow(clippy::similar_names)]
-    pub(crate) async fn detect(&self, data: Vec<u8>) -> Result<Option<Detections>, DetectError> {
+    pub(crate) async fn detect(
+        &self,
+        data: Vec<u8>,
+        filter: DetectFilter,
+    ) -> Result<Option<Detections>, DetectError> {
         use DetectError::*;
+        // Waiting for a permit doesn't count towards the timeouts. The worker
+        // holds the permit until the detection is done, even after a timeout.
+        let permit = match &self.limit {
+            Some(limit) => Some(limit.clone().acquire_owned().await.expect("not closed")),
+            None => None,
+        };
         let (res_tx, res_rx) = oneshot::channel();
-        let req = DetectRequest { data, res: res_tx };
+        let req = DetectRequest {
+            data,
+            filter,
+            res: res_tx,
+            permit,
+        };
 
         let sleep = |secs: u64| {
             let _enter = self.rt_handle.enter();
@@ -191,7 +356,9 @@ impl Detector {
 #[derive(Debug)]
 struct DetectRequest {
     data: Vec<u8>,
-    res: oneshot::Sender<Result<Detections, tflite_lib::DetectError>>,
+    filter: DetectFilter,
+    res: oneshot::Sender<Result<Detections, common::DetectError>>,
+    permit: Option<OwnedSemaphorePermit>,
 }
 
 pub(crate) type Thresholds = HashMap<Label, Percent>;
@@ -199,6 +366,7 @@ pub(crate) type Thresholds = HashMap<Label, Percent>;
 pub(crate) struct DetectorManager {
     detectors: Detectors,
     configs: DetectorConfigs,
+    transient_detectors: TransientDetectors,
 }
 
 #[derive(Debug, Error)]
@@ -232,6 +400,12 @@ pub(crate) enum DetectorManagerError {
 
     #[error("create detector: {0}")]
     CreateDetector(#[from] NewDetectorError),
+
+    #[error("create onnx detector: {0}")]
+    CreateOnnxDetector(#[from] onnx_lib::NewDetectorError),
+
+    #[error("detector '{0}': invalid min_area or max_area")]
+    InvalidArea(DetectorName),
 }
 
 impl DetectorManager {
@@ -265,7 +439,7 @@ impl DetectorManager {
             std::fs::create_dir(&models_dir).map_err(CreateDir)?;
         }
 
-        let mut model_cache = ModelCache::new(logger.clone(), fetcher, models_dir)?;
+        let mut model_cache = ModelCache::new(logger.clone(), fetcher, models_dir.clone())?;
 
         let raw_config = std::fs::read_to_string(config_path).map_err(ReadConfig)?;
         let detector_configs = parse_raw_detector_configs(&raw_config)?;
@@ -278,6 +452,7 @@ impl DetectorManager {
             logger,
             &mut model_cache,
             &mut label_cache,
+            &models_dir,
             detector_configs,
         )
         .await
@@ -291,6 +466,106 @@ impl DetectorManager {
     pub(crate) fn get_detector(&self, name: &DetectorName) -> Option<Arc<Detector>> {
         self.detectors.get(name).cloned()
     }
+
+    pub(crate) fn transient_detectors(&self) -> TransientDetectors {
+        self.transient_detectors.clone()
+    }
+}
+
+// Runs models that aren't in the config on a single image, used to compare
+// models without changing the config. A new CPU detector is created for each
+// request and it waits for the same permit as the configured detectors.
+#[derive(Clone)]
+pub(crate) struct TransientDetectors {
+    rt_handle: Handle,
+    models: Arc<ModelRegistry>,
+    models_dir: PathBuf,
+    limit: Arc<Semaphore>,
+}
+
+// Limit used by the transient detectors if `max_concurrent_detections` isn't set.
+const DEFAULT_TRANSIENT_DETECTIONS: usize = 1;
+
+#[derive(Debug, Error)]
+pub(crate) enum TransientDetectError {
+    #[error("model path must be a relative path inside the models directory")]
+    InvalidModelPath,
+
+    #[error("{0}")]
+    NewDetector(#[from] NewTransientDetectorError),
+
+    #[error("detect: {0}")]
+    Detect(#[from] tflite_lib::DetectError),
+
+    #[error("join: {0}")]
+    Join(#[from] tokio::task::JoinError),
+}
+
+// Frame passed to `TransientDetectors::detect`, the color
+// matrix is only used for YUV formats.
+pub(crate) struct TransientFrame {
+    pub buf: Vec<u8>,
+    pub format: FrameFormat,
+    pub matrix: ColorMatrix,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl From<Image> for TransientFrame {
+    fn from(image: Image) -> Self {
+        Self {
+            buf: image.rgb,
+            format: FrameFormat::Rgb24,
+            matrix: ColorMatrix::default(),
+            width: image.width,
+            height: image.height,
+        }
+    }
+}
+
+impl TransientDetectors {
+    // The model path is relative to the models directory.
+    pub(crate) async fn detect(
+        &self,
+        model: &Path,
+        format: ModelFormat,
+        top_k: usize,
+        frame: TransientFrame,
+    ) -> Result<ModelOutput, TransientDetectError> {
+        let Some(model_path) = resolve_model_path(&self.models_dir, model) else {
+            return Err(TransientDetectError::InvalidModelPath);
+        };
+        let permit = self
+            .limit
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("not closed");
+        let models = self.models.clone();
+        self.rt_handle
+            .spawn_blocking(move || -> Result<ModelOutput, TransientDetectError> {
+                let _permit = permit;
+                let mut detector = new_transient_detector(&models, &model_path, format, None)?;
+                Ok(detector.run_frame(
+                    &frame.buf,
+                    frame.format,
+                    frame.matrix,
+                    frame.width,
+                    frame.height,
+                    top_k,
+                )?)
+            })
+            .await?
+    }
+}
+
+// Returns None if the path could point outside the models directory.
+fn resolve_model_path(models_dir: &Path, model: &Path) -> Option<PathBuf> {
+    let mut components = model.components().peekable();
+    components.peek()?;
+    components
+        .all(|v| matches!(v, std::path::Component::Normal(_)))
+        .then(|| models_dir.join(model))
 }
 
 fn get_log_level() -> u8 {
@@ -314,17 +589,93 @@ pub(crate) fn write_detector_config(path: &Path) -> Result<(), std::io::Error> {
     std::fs::write(path, DEFAULT_CONFIG)
 }
 
+// Validates the detector config without starting the detectors. Models
+// that aren't cached must have a http(s) url so they can be downloaded.
+pub(crate) fn check_detector_config(config_dir: &Path) -> Vec<String> {
+    use DetectorManagerError::*;
+    let config_path = config_dir.join("tflite.toml");
+    if !config_path.exists() {
+        // The default config is generated on start.
+        return Vec::new();
+    }
+    let raw_config = match std::fs::read_to_string(config_path) {
+        Ok(v) => v,
+        Err(e) => return vec![ReadConfig(e).to_string()],
+    };
+    let configs = match parse_raw_detector_configs(&raw_config) {
+        Ok(v) => v,
+        Err(e) => return vec![DeserializeConfig(e).to_string()],
+    };
+
+    let models_dir = config_dir.join(".tflite").join("models");
+    let check_model = |name: &DetectorName, url: &Url, checksum: &ModelChecksum| {
+        let cached = models_dir.join(checksum.as_string()).exists();
+        let fetchable = matches!(url.scheme(), "http" | "https");
+        (!cached && !fetchable).then(|| format!("detector '{name}': model not found '{url}'"))
+    };
+
+    let mut problems = Vec::new();
+    let mut names = HashSet::new();
+    for cpu in configs.detector_cpu.iter().filter(|v| v.enable) {
+        if !names.insert(&cpu.name) {
+            problems.push(Duplicate(cpu.name.clone()).to_string());
+        }
+        if AreaFilter::new(cpu.min_area, cpu.max_area).is_none() {
+            problems.push(InvalidArea(cpu.name.clone()).to_string());
+        }
+        problems.extend(check_model(&cpu.name, &cpu.model, &cpu.sha256sum));
+    }
+
+    let mut device_cache = DeviceCache::new();
+    for edgetpu in configs.detector_edgetpu.iter().filter(|v| v.enable) {
+        if !names.insert(&edgetpu.name) {
+            problems.push(Duplicate(edgetpu.name.clone()).to_string());
+        }
+        if AreaFilter::new(edgetpu.min_area, edgetpu.max_area).is_none() {
+            problems.push(InvalidArea(edgetpu.name.clone()).to_string());
+        }
+        problems.extend(check_model(
+            &edgetpu.name,
+            &edgetpu.model,
+            &edgetpu.sha256sum,
+        ));
+        if device_cache.device(&edgetpu.device).is_none() {
+            problems.push(format!(
+                "detector '{}': device not found '{}'",
+                edgetpu.name, edgetpu.device
+            ));
+        }
+    }
+
+    for onnx in configs.detector_onnx.iter().filter(|v| v.enable) {
+        if !names.insert(&onnx.name) {
+            problems.push(Duplicate(onnx.name.clone()).to_string());
+        }
+        if AreaFilter::new(onnx.min_area, onnx.max_area).is_none() {
+            problems.push(InvalidArea(onnx.name.clone()).to_string());
+        }
+        problems.extend(check_model(&onnx.name, &onnx.model, &onnx.sha256sum));
+    }
+    problems
+}
+
+#[allow(clippy::too_many_lines)]
 async fn parse_detector_configs(
     rt_handle: &Handle,
     shutdown_complete_tx: mpsc::Sender<()>,
     logger: ArcMsgLogger,
     model_cache: &mut ModelCache,
     label_cache: &mut LabelCache,
+    models_dir: &Path,
     configs: RawDetectorConfigs,
 ) -> Result<DetectorManager, DetectorManagerError> {
     use DetectorManagerError::*;
     let mut detectors = HashMap::new();
     let mut detector_configs = HashMap::new();
+    let models = Arc::new(ModelRegistry::new());
+    let limit = configs
+        .max_concurrent_detections
+        .map(|v| Arc::new(Semaphore::new(v.get())));
 
     for cpu in configs.detector_cpu {
         if !cpu.enable {
@@ -334,6 +685,9 @@ async fn parse_detector_configs(
             );
             continue;
         }
+        let Some(area_filter) = AreaFilter::new(cpu.min_area, cpu.max_area) else {
+            return Err(InvalidArea(cpu.name));
+        };
         let model_path = model_cache.get(&cpu.model, &cpu.sha256sum).await?;
         let label_map = label_cache.get(&cpu.label_map).await?;
         if detector_configs.contains_key(&cpu.name) {
@@ -349,17 +703,25 @@ async fn parse_detector_configs(
             rt_handle.clone(),
             &shutdown_complete_tx,
             &logger,
+            &models,
             &cpu.name,
             cpu.width,
             cpu.height,
             &model_path,
             cpu.threads,
             &label_map,
+            &cpu.ignored_classes,
+            cpu.nms.map(Into::into),
+            area_filter,
+            cpu.cpu_accel,
+            cpu.channel_order.into(),
+            limit.clone(),
         )?;
         detectors.insert(cpu.name, Arc::new(detector));
     }
 
     let mut device_cache = DeviceCache::new();
+    let claimed_devices = ClaimedDevices::default();
     for edgetpu in configs.detector_edgetpu {
         if !edgetpu.enable {
             logger.log(
@@ -368,6 +730,9 @@ async fn parse_detector_configs(
             );
             continue;
         }
+        let Some(area_filter) = AreaFilter::new(edgetpu.min_area, edgetpu.max_area) else {
+            return Err(InvalidArea(edgetpu.name));
+        };
         let model_path = model_cache.get(&edgetpu.model, &edgetpu.sha256sum).await?;
         let label_map = label_cache.get(&edgetpu.label_map).await?;
         if detector_configs.contains_key(&edgetpu.name) {
@@ -383,20 +748,75 @@ async fn parse_detector_configs(
             rt_handle.clone(),
             shutdown_complete_tx.clone(),
             &logger,
+            models.clone(),
             &edgetpu.name,
             edgetpu.width,
             edgetpu.height,
             &model_path,
             label_map,
+            edgetpu.ignored_classes,
+            edgetpu.nms.map(Into::into),
+            area_filter,
+            edgetpu.channel_order.into(),
             edgetpu.device,
             &mut device_cache,
+            claimed_devices.clone(),
+            limit.clone(),
         )?;
         detectors.insert(edgetpu.name, Arc::new(detector));
     }
 
+    for onnx in configs.detector_onnx {
+        if !onnx.enable {
+            logger.log(
+                LogLevel::Debug,
+                &format!("detector '{}' disabled", onnx.name),
+            );
+            continue;
+        }
+        let Some(area_filter) = AreaFilter::new(onnx.min_area, onnx.max_area) else {
+            return Err(InvalidArea(onnx.name));
+        };
+        let model_path = model_cache.get(&onnx.model, &onnx.sha256sum).await?;
+        let label_map = label_cache.get(&onnx.label_map).await?;
+        if detector_configs.contains_key(&onnx.name) {
+            return Err(Duplicate(onnx.name));
+        };
+        let config = DetectorConfig {
+            width: onnx.width,
+            height: onnx.height,
+            labels: label_map.values().cloned().collect(),
+        };
+        detector_configs.insert(onnx.name.clone(), config);
+        let detector = new_onnx_detector(
+            rt_handle.clone(),
+            &shutdown_complete_tx,
+            &logger,
+            &models,
+            &onnx.name,
+            onnx.width,
+            onnx.height,
+            &model_path,
+            onnx.threads,
+            &label_map,
+            &onnx.ignored_classes,
+            onnx.nms.map(Into::into),
+            area_filter,
+            onnx.channel_order.into(),
+            limit.clone(),
+        )?;
+        detectors.insert(onnx.name, Arc::new(detector));
+    }
+
     Ok(DetectorManager {
         detectors,
         configs: detector_configs,
+        transient_detectors: TransientDetectors {
+            rt_handle: rt_handle.clone(),
+            models,
+            models_dir: models_dir.to_owned(),
+            limit: limit.unwrap_or_else(|| Arc::new(Semaphore::new(DEFAULT_TRANSIENT_DETECTIONS))),
+        },
     })
 }
 
@@ -405,43 +825,59 @@ fn new_cpu_detector(
     rt_handle: Handle,
     shutdown_complete_tx: &mpsc::Sender<()>,
     logger: &ArcMsgLogger,
+    models: &ModelRegistry,
     name: &DetectorName,
     width: NonZeroU16,
     height: NonZeroU16,
     model_path: &Path,
     threads: NonZeroU8,
     label_map: &LabelMap,
+    ignored_classes: &HashSet<u16>,
+    nms: Option<NmsMode>,
+    area_filter: AreaFilter,
+    cpu_accel: bool,
+    channel_order: ChannelOrder,
+    limit: Option<Arc<Semaphore>>,
 ) -> Result<Detector, NewDetectorError> {
     let (detect_tx, detect_rx) = async_channel::bounded::<DetectRequest>(1);
     for i in 0..threads.get() {
         logger.log(LogLevel::Info, &format!("starting detector '{name}' T{i}"));
-        let shutdown_complete_tx = shutdown_complete_tx.clone();
-        let rt_handle2 = rt_handle.clone();
-        let detect_rx = detect_rx.clone();
-        let mut detector = tflite_lib::Detector::new(model_path, None)?;
-        let label_map = label_map.clone();
-
-        rt_handle.spawn(async move {
-            let _shutdown_complete_tx = shutdown_complete_tx;
-            while let Ok(req) = detect_rx.recv().await {
-                let result;
-                (detector, result) = rt_handle2
-                    .spawn_blocking(move || {
-                        let result = detector.detect(&req.data);
-                        (detector, result)
-                    })
-                    .await
-                    .expect("join");
-                let result = result.map(|v| parse_detections(&label_map, v));
-                _ = req.res.send(result);
-            }
-        });
+        let mut detector = tflite_lib::Detector::new(
+            models,
+            model_path,
+            None,
+            ModelFormat::Detector,
+            cpu_accel,
+            channel_order,
+        )?;
+        if cpu_accel && !detector.cpu_accel() {
+            logger.log(
+                LogLevel::Warning,
+                &format!("'{name}' T{i}: failed to enable cpu_accel, continuing without it"),
+            );
+        }
+        log_warmup(logger, &format!("'{name}' T{i}"), &mut detector);
+        spawn_detector(
+            &rt_handle,
+            shutdown_complete_tx.clone(),
+            Box::new(TfliteDetector {
+                detector,
+                label_map: label_map.clone(),
+                ignored_classes: ignored_classes.clone(),
+                nms,
+                area_filter,
+                width,
+                height,
+            }),
+            detect_rx.clone(),
+        );
     }
     Ok(Detector {
         rt_handle,
         detect_tx,
         width,
         height,
+        limit,
     })
 }
 
@@ -450,13 +886,20 @@ fn new_edgetpu_detector(
     rt_handle: Handle,
     shutdown_complete_tx: mpsc::Sender<()>,
     logger: &ArcMsgLogger,
+    models: Arc<ModelRegistry>,
     name: &DetectorName,
     width: NonZeroU16,
     height: NonZeroU16,
     model_path: &Path,
     label_map: LabelMap,
+    ignored_classes: HashSet<u16>,
+    nms: Option<NmsMode>,
+    area_filter: AreaFilter,
+    channel_order: ChannelOrder,
     device_path: String,
     device_cache: &mut DeviceCache,
+    claimed_devices: ClaimedDevices,
+    limit: Option<Arc<Semaphore>>,
 ) -> Result<Detector, NewDetectorError> {
     logger.log(LogLevel::Info, &format!("starting detector '{name}'"));
 
@@ -464,7 +907,15 @@ fn new_edgetpu_detector(
         let err = debug_device(device_path, device_cache.devices());
         return Err(NewDetectorError::DebugDevice(err));
     };
-    let mut detector = match tflite_lib::Detector::new(model_path, Some(device)) {
+    let device_type = device.typ;
+    let mut detector = match tflite_lib::Detector::new(
+        &models,
+        model_path,
+        Some(device),
+        ModelFormat::Detector,
+        false,
+        channel_order,
+    ) {
         Ok(v) => v,
         Err(e) => {
             if matches!(e, NewDetectorError::EdgetpuDelegateCreate) {
@@ -474,29 +925,473 @@ fn new_edgetpu_detector(
         }
     };
 
+    log_warmup(logger, &format!("'{name}'"), &mut detector);
+    claimed_devices
+        .lock()
+        .expect("not poisoned")
+        .insert(device_path.clone());
+
+    let connect: ConnectFunc = {
+        let logger = logger.clone();
+        let name = name.clone();
+        let model_path = model_path.to_owned();
+        Box::new(
+            move |device: &EdgetpuDevice| -> Result<Box<dyn EdgetpuBackend>, _> {
+                let mut detector = tflite_lib::Detector::new(
+                    &models,
+                    &model_path,
+                    Some(device),
+                    ModelFormat::Detector,
+                    false,
+                    channel_order,
+                )?;
+                log_warmup(&logger, &format!("'{name}'"), &mut detector);
+                Ok(Box::new(detector))
+            },
+        )
+    };
+    let reconnector = Reconnector {
+        logger: logger.clone(),
+        name: name.clone(),
+        device_path,
+        device_type,
+        claimed_devices,
+        list_devices: Box::new(list_edgetpu_devices),
+        connect,
+        state: ReconnectState::Connected {
+            backend: Box::new(detector),
+            errors: 0,
+        },
+    };
+
+    let (detect_tx, detect_rx) = async_channel::bounded::<DetectRequest>(1);
+    spawn_detector(
+        &rt_handle,
+        shutdown_complete_tx,
+        Box::new(EdgetpuDetector {
+            reconnector,
+            label_map,
+            ignored_classes,
+            nms,
+            area_filter,
+            width,
+            height,
+        }),
+        detect_rx,
+    );
+    Ok(Detector {
+        rt_handle,
+        detect_tx,
+        width,
+        height,
+        limit,
+    })
+}
+
+struct TfliteDetector {
+    detector: tflite_lib::Detector,
+    label_map: LabelMap,
+    ignored_classes: HashSet<u16>,
+    nms: Option<NmsMode>,
+    area_filter: AreaFilter,
+    width: NonZeroU16,
+    height: NonZeroU16,
+}
+
+impl ObjectDetector for TfliteDetector {
+    fn detect(
+        &mut self,
+        buf: &[u8],
+        filter: &DetectFilter,
+    ) -> Result<Detections, common::DetectError> {
+        let detections = self
+            .detector
+            .detect(buf)
+            .map_err(common::DetectError::new)?;
+        Ok(process_detections(
+            &self.label_map,
+            &self.ignored_classes,
+            filter,
+            self.nms,
+            self.area_filter,
+            detections,
+        ))
+    }
+
+    fn input_dims(&self) -> (u16, u16) {
+        (self.width.get(), self.height.get())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn new_onnx_detector(
+    rt_handle: Handle,
+    shutdown_complete_tx: &mpsc::Sender<()>,
+    logger: &ArcMsgLogger,
+    models: &ModelRegistry,
+    name: &DetectorName,
+    width: NonZeroU16,
+    height: NonZeroU16,
+    model_path: &Path,
+    threads: NonZeroU8,
+    label_map: &LabelMap,
+    ignored_classes: &HashSet<u16>,
+    nms: Option<NmsMode>,
+    area_filter: AreaFilter,
+    channel_order: ChannelOrder,
+    limit: Option<Arc<Semaphore>>,
+) -> Result<Detector, onnx_lib::NewDetectorError> {
     let (detect_tx, detect_rx) = async_channel::bounded::<DetectRequest>(1);
+    for i in 0..threads.get() {
+        logger.log(LogLevel::Info, &format!("starting detector '{name}' T{i}"));
+        let detector = onnx_lib::Detector::new(models, model_path, channel_order)?;
+        spawn_detector(
+            &rt_handle,
+            shutdown_complete_tx.clone(),
+            Box::new(OnnxDetector {
+                detector,
+                label_map: label_map.clone(),
+                ignored_classes: ignored_classes.clone(),
+                nms,
+                area_filter,
+                width,
+                height,
+            }),
+            detect_rx.clone(),
+        );
+    }
+    Ok(Detector {
+        rt_handle,
+        detect_tx,
+        width,
+        height,
+        limit,
+    })
+}
+
+struct OnnxDetector {
+    detector: onnx_lib::Detector,
+    label_map: LabelMap,
+    ignored_classes: HashSet<u16>,
+    nms: Option<NmsMode>,
+    area_filter: AreaFilter,
+    width: NonZeroU16,
+    height: NonZeroU16,
+}
+
+impl ObjectDetector for OnnxDetector {
+    fn detect(
+        &mut self,
+        buf: &[u8],
+        filter: &DetectFilter,
+    ) -> Result<Detections, common::DetectError> {
+        let detections = self
+            .detector
+            .detect(buf)
+            .map_err(common::DetectError::new)?;
+        Ok(process_detections(
+            &self.label_map,
+            &self.ignored_classes,
+            filter,
+            self.nms,
+            self.area_filter,
+            detections,
+        ))
+    }
+
+    fn input_dims(&self) -> (u16, u16) {
+        (self.width.get(), self.height.get())
+    }
+}
+
+// Edgetpu inference backend, allows the device to be replaced in tests.
+trait EdgetpuBackend: Send {
+    fn detect(&mut self, buf: &[u8])
+        -> Result<Vec<tflite_lib::Detection>, tflite_lib::DetectError>;
+}
+
+impl EdgetpuBackend for tflite_lib::Detector {
+    fn detect(
+        &mut self,
+        buf: &[u8],
+    ) -> Result<Vec<tflite_lib::Detection>, tflite_lib::DetectError> {
+        self.detect(buf)
+    }
+}
+
+type ListDevicesFunc = Box<dyn Fn() -> Vec<EdgetpuDevice> + Send>;
+
+// Paths of the devices used by the edgetpu detectors.
+type ClaimedDevices = Arc<std::sync::Mutex<HashSet<String>>>;
+type ConnectFunc =
+    Box<dyn Fn(&EdgetpuDevice) -> Result<Box<dyn EdgetpuBackend>, NewDetectorError> + Send>;
+
+// Consecutive invoke errors before the device is assumed to be unplugged.
+const RECONNECT_AFTER_ERRORS: u32 = 3;
+
+// Reconnect attempts are delayed by this much after a failed attempt.
+// The delay is doubled for every failure up to the max.
+const RECONNECT_MIN_BACKOFF: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+enum ReconnectState {
+    Connected {
+        backend: Box<dyn EdgetpuBackend>,
+        errors: u32,
+    },
+    Disconnected {
+        retry_at: Instant,
+        backoff: Duration,
+    },
+}
+
+#[derive(Debug, Error)]
+enum EdgetpuDetectError {
+    #[error("{0}")]
+    Detect(#[from] tflite_lib::DetectError),
+
+    #[error("edgetpu device '{0}' is disconnected")]
+    Disconnected(String),
+}
+
+#[derive(Debug, Error)]
+enum ReconnectError {
+    #[error("device not found")]
+    NotFound,
+
+    #[error("create detector: {0}")]
+    CreateDetector(#[from] NewDetectorError),
+}
+
+// Rebuilds the detector after repeated invoke errors. A replugged
+// USB device may come back with a different path.
+struct Reconnector {
+    logger: ArcMsgLogger,
+    name: DetectorName,
+    device_path: String,
+    device_type: EdgetpuDeviceType,
+    claimed_devices: ClaimedDevices,
+    list_devices: ListDevicesFunc,
+    connect: ConnectFunc,
+    state: ReconnectState,
+}
+
+impl Reconnector {
+    fn detect_at(
+        &mut self,
+        buf: &[u8],
+        now: Instant,
+    ) -> Result<Vec<tflite_lib::Detection>, EdgetpuDetectError> {
+        use EdgetpuDetectError::*;
+        if let ReconnectState::Disconnected { retry_at, backoff } = self.state {
+            if now < retry_at {
+                return Err(Disconnected(self.device_path.clone()));
+            }
+            match self.reconnect() {
+                Ok(backend) => {
+                    self.log(LogLevel::Info, "reconnected");
+                    self.state = ReconnectState::Connected { backend, errors: 0 };
+                }
+                Err(e) => {
+                    self.log(LogLevel::Debug, &format!("reconnect: {e}"));
+                    self.state = ReconnectState::Disconnected {
+                        retry_at: now + backoff,
+                        backoff: (backoff * 2).min(RECONNECT_MAX_BACKOFF),
+                    };
+                    return Err(Disconnected(self.device_path.clone()));
+                }
+            }
+        }
+
+        let ReconnectState::Connected { backend, errors } = &mut self.state else {
+            unreachable!("connected above");
+        };
+        match backend.detect(buf) {
+            Ok(v) => {
+                *errors = 0;
+                Ok(v)
+            }
+            Err(e @ tflite_lib::DetectError::Detect(_)) => {
+                *errors += 1;
+                if *errors >= RECONNECT_AFTER_ERRORS {
+                    self.log(
+                        LogLevel::Warning,
+                        &format!("device '{}' lost, reconnecting: {e}", self.device_path),
+                    );
+                    // Drops the stale handle.
+                    self.state = ReconnectState::Disconnected {
+                        retry_at: now,
+                        backoff: RECONNECT_MIN_BACKOFF,
+                    };
+                }
+                Err(Detect(e))
+            }
+            Err(e) => Err(Detect(e)),
+        }
+    }
+
+    fn reconnect(&mut self) -> Result<Box<dyn EdgetpuBackend>, ReconnectError> {
+        let devices = (self.list_devices)();
+        let device = {
+            let mut claimed = self.claimed_devices.lock().expect("not poisoned");
+            let device = find_device(&devices, &self.device_path, self.device_type, &claimed)
+                .ok_or(ReconnectError::NotFound)?;
+            if device.path != self.device_path {
+                claimed.remove(&self.device_path);
+                claimed.insert(device.path.clone());
+            }
+            device
+        };
+        if device.path != self.device_path {
+            self.log(
+                LogLevel::Info,
+                &format!(
+                    "device moved from '{}' to '{}'",
+                    self.device_path, device.path
+                ),
+            );
+            self.device_path.clone_from(&device.path);
+        }
+        Ok((self.connect)(device)?)
+    }
+
+    fn log(&self, level: LogLevel, msg: &str) {
+        self.logger
+            .log(level, &format!("detector '{}': {msg}", self.name));
+    }
+}
+
+// Returns the device at `path`. A USB device that was replugged into
+// another port is only matched if it's the only USB device that
+// isn't used by another detector.
+fn find_device<'a>(
+    devices: &'a [EdgetpuDevice],
+    path: &str,
+    typ: EdgetpuDeviceType,
+    claimed: &HashSet<String>,
+) -> Option<&'a EdgetpuDevice> {
+    if let Some(device) = devices.iter().find(|d| d.path == path) {
+        return Some(device);
+    }
+    if typ != EdgetpuDeviceType::Usb {
+        return None;
+    }
+    let mut usb_devices = devices
+        .iter()
+        .filter(|d| d.typ == EdgetpuDeviceType::Usb && !claimed.contains(&d.path));
+    match (usb_devices.next(), usb_devices.next()) {
+        (Some(device), None) => Some(device),
+        _ => None,
+    }
+}
+
+struct EdgetpuDetector {
+    reconnector: Reconnector,
+    label_map: LabelMap,
+    ignored_classes: HashSet<u16>,
+    nms: Option<NmsMode>,
+    area_filter: AreaFilter,
+    width: NonZeroU16,
+    height: NonZeroU16,
+}
+
+impl ObjectDetector for EdgetpuDetector {
+    fn detect(
+        &mut self,
+        buf: &[u8],
+        filter: &DetectFilter,
+    ) -> Result<Detections, common::DetectError> {
+        let detections = self
+            .reconnector
+            .detect_at(buf, Instant::now())
+            .map_err(common::DetectError::new)?;
+        Ok(process_detections(
+            &self.label_map,
+            &self.ignored_classes,
+            filter,
+            self.nms,
+            self.area_filter,
+            detections,
+        ))
+    }
+
+    fn input_dims(&self) -> (u16, u16) {
+        (self.width.get(), self.height.get())
+    }
+}
+
+// Runs detect requests on the backend until the channel is closed.
+fn spawn_detector(
+    rt_handle: &Handle,
+    shutdown_complete_tx: mpsc::Sender<()>,
+    mut detector: Box<dyn ObjectDetector>,
+    detect_rx: async_channel::Receiver<DetectRequest>,
+) {
     let rt_handle2 = rt_handle.clone();
     rt_handle.spawn(async move {
         let _shutdown_complete_tx = shutdown_complete_tx;
         while let Ok(req) = detect_rx.recv().await {
+            let DetectRequest {
+                data,
+                filter,
+                res,
+                permit,
+            } = req;
             let result;
             (detector, result) = rt_handle2
                 .spawn_blocking(move || {
-                    let result = detector.detect(&req.data);
+                    let result = detector.detect(&data, &filter);
                     (detector, result)
                 })
                 .await
                 .expect("join");
-            let result = result.map(|v| parse_detections(&label_map, v));
-            _ = req.res.send(result);
+            drop(permit);
+            _ = res.send(result);
         }
     });
-    Ok(Detector {
-        rt_handle,
-        detect_tx,
-        width,
-        height,
-    })
+}
+
+const WARMUP_ITERATIONS: usize = 3;
+
+// The first inferences are slow, run them before the monitors start.
+fn log_warmup(logger: &ArcMsgLogger, name: &str, detector: &mut tflite_lib::Detector) {
+    match detector.warmup(WARMUP_ITERATIONS) {
+        Ok(latency) => logger.log(
+            LogLevel::Debug,
+            &format!("detector {name} latency: {}ms", latency.as_millis()),
+        ),
+        Err(e) => logger.log(
+            LogLevel::Warning,
+            &format!("detector {name} warmup failed: {e}"),
+        ),
+    }
+}
+
+fn apply_nms(
+    nms: Option<NmsMode>,
+    detections: Vec<tflite_lib::Detection>,
+) -> Vec<tflite_lib::Detection> {
+    match nms {
+        Some(mode) => tflite_lib::non_max_suppression(detections, NMS_IOU_THRESHOLD, mode),
+        None => detections,
+    }
+}
+
+// Detections of ignored classes and detections rejected by
+// the filter are dropped before any further processing.
+fn process_detections(
+    label_map: &LabelMap,
+    ignored_classes: &HashSet<u16>,
+    filter: &DetectFilter,
+    nms: Option<NmsMode>,
+    area_filter: AreaFilter,
+    mut detections: Vec<tflite_lib::Detection>,
+) -> Detections {
+    detections.retain(|d| {
+        !ignored_classes.contains(&u16::from(d.class))
+            && parse_rect(d.top, d.left, d.bottom, d.right).is_some_and(|r| filter.allows(&r))
+    });
+    parse_detections(label_map, area_filter.apply(apply_nms(nms, detections)))
 }
 
 fn parse_detections(label_map: &LabelMap, input: Vec<tflite_lib::Detection>) -> Detections {
@@ -567,6 +1462,7 @@ impl DeviceCache {
 mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[test]
     fn test_parse_detector_config() {
@@ -580,6 +1476,7 @@ mod tests {
             sha256sum = \"5555555555555555555555555555555555555555555555555555555555555555\"
             label_map = \"file:///6\"
             threads = 7
+            cpu_accel = true
 
             [[detector_edgetpu]]
             enable = true
@@ -590,9 +1487,26 @@ mod tests {
             sha256sum = \"1212121212121212121212121212121212121212121212121212121212121212\"
             label_map = \"file:///13\"
             device = \"14\"
+            ignored_classes = [15, 16]
+            nms = \"fusion\"
+            channel_order = \"bgr\"
+            min_area = 0.01
+            max_area = 0.9
+
+            [[detector_onnx]]
+            enable = true
+            name = \"17\"
+            width = 18
+            height = 19
+            model = \"file:///20\"
+            sha256sum = \"2121212121212121212121212121212121212121212121212121212121212121\"
+            label_map = \"file:///22\"
+            threads = 23
+            nms = \"suppress\"
         ";
         let got = parse_raw_detector_configs(raw).unwrap();
         let want = RawDetectorConfigs {
+            max_concurrent_detections: None,
             detector_cpu: vec![RawDetectorConfigCpu {
                 enable: false,
                 name: "1".to_owned().try_into().unwrap(),
@@ -604,6 +1518,12 @@ mod tests {
                     .unwrap(),
                 label_map: "file:///6".parse().unwrap(),
                 threads: NonZeroU8::new(7).unwrap(),
+                ignored_classes: HashSet::new(),
+                nms: None,
+                cpu_accel: true,
+                channel_order: RawChannelOrder::Rgb,
+                min_area: 0.0,
+                max_area: 1.0,
             }],
             detector_edgetpu: vec![RawDetectorConfigEdgeTpu {
                 enable: true,
@@ -616,10 +1536,74 @@ mod tests {
                     .unwrap(),
                 label_map: "file:///13".parse().unwrap(),
                 device: "14".parse().unwrap(),
+                ignored_classes: HashSet::from([15, 16]),
+                nms: Some(RawNmsMode::Fusion),
+                channel_order: RawChannelOrder::Bgr,
+                min_area: 0.01,
+                max_area: 0.9,
+            }],
+            detector_onnx: vec![RawDetectorConfigOnnx {
+                enable: true,
+                name: "17".to_owned().try_into().unwrap(),
+                width: NonZeroU16::new(18).unwrap(),
+                height: NonZeroU16::new(19).unwrap(),
+                model: "file:///20".parse().unwrap(),
+                sha256sum: "2121212121212121212121212121212121212121212121212121212121212121"
+                    .parse()
+                    .unwrap(),
+                label_map: "file:///22".parse().unwrap(),
+                threads: NonZeroU8::new(23).unwrap(),
+                ignored_classes: HashSet::new(),
+                nms: Some(RawNmsMode::Suppress),
+                channel_order: RawChannelOrder::Rgb,
+                min_area: 0.0,
+                max_area: 1.0,
             }],
         };
         assert_eq!(want, got);
     }
+
+    #[test]
+    fn test_check_detector_config() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let models_dir = temp_dir.path().join(".tflite").join("models");
+        std::fs::create_dir_all(&models_dir).unwrap();
+        let checksum = "1111111111111111111111111111111111111111111111111111111111111111";
+        std::fs::write(models_dir.join(checksum), "x").unwrap();
+
+        let config = |model: &str| {
+            format!(
+                "
+                [[detector_cpu]]
+                enable = true
+                name = \"1\"
+                width = 2
+                height = 3
+                model = \"{model}\"
+                sha256sum = \"{checksum}\"
+                label_map = \"file:///4\"
+                threads = 5
+                "
+            )
+        };
+        let config_path = temp_dir.path().join("tflite.toml");
+
+        // Cached.
+        std::fs::write(&config_path, config("file:///6")).unwrap();
+        assert!(check_detector_config(temp_dir.path()).is_empty());
+
+        // Missing.
+        std::fs::remove_file(models_dir.join(checksum)).unwrap();
+        assert_eq!(
+            vec!["detector '1': model not found 'file:///6'"],
+            check_detector_config(temp_dir.path())
+        );
+
+        // Downloaded on start.
+        std::fs::write(&config_path, config("https://x/6")).unwrap();
+        assert!(check_detector_config(temp_dir.path()).is_empty());
+    }
+
     #[test]
     fn test_parse_detector_config_empty() {
         assert_eq!(
@@ -627,4 +1611,560 @@ mod tests {
             parse_raw_detector_configs("").unwrap()
         );
     }
+
+    fn detection(class: u8) -> tflite_lib::Detection {
+        tflite_lib::Detection {
+            score: 0.5,
+            class,
+            top: 0.1,
+            left: 0.1,
+            bottom: 0.2,
+            right: 0.2,
+        }
+    }
+
+    fn no_area_filter() -> AreaFilter {
+        AreaFilter::new(0.0, 1.0).unwrap()
+    }
+
+    #[test]
+    fn test_process_detections_ignored_classes() {
+        let label_map = LabelMap::from([
+            (1, "a".to_owned().try_into().unwrap()),
+            (2, "b".to_owned().try_into().unwrap()),
+        ]);
+        let ignored_classes = HashSet::from([2]);
+        let input = vec![detection(1), detection(2), detection(3)];
+
+        let got: Vec<String> = process_detections(
+            &label_map,
+            &ignored_classes,
+            &DetectFilter::allow_all(),
+            None,
+            no_area_filter(),
+            input,
+        )
+        .into_iter()
+        .map(|d| d.label.to_string())
+        .collect();
+        assert_eq!(vec!["a", "unknown3"], got);
+    }
+
+    #[test]
+    fn test_process_detections_ignored_overlap() {
+        let label_map = LabelMap::from([(1, "a".to_owned().try_into().unwrap())]);
+        let ignored_classes = HashSet::from([2]);
+        // The ignored box has a higher score and overlaps the kept box.
+        let ignored = tflite_lib::Detection {
+            score: 0.9,
+            class: 2,
+            top: 0.1,
+            left: 0.15,
+            bottom: 0.2,
+            right: 0.25,
+        };
+        let input = || vec![detection(1), ignored.clone()];
+
+        let want = parse_detections(&label_map, vec![detection(1)]);
+        for nms in [None, Some(NmsMode::Suppress), Some(NmsMode::Fusion)] {
+            let got = process_detections(
+                &label_map,
+                &ignored_classes,
+                &DetectFilter::allow_all(),
+                nms,
+                no_area_filter(),
+                input(),
+            );
+            assert_eq!(want, got);
+        }
+    }
+
+    #[test]
+    fn test_process_detections_filter_before_nms() {
+        let label_map = LabelMap::from([(1, "a".to_owned().try_into().unwrap())]);
+        // The filtered box has a higher score and would suppress the kept box.
+        let filtered = tflite_lib::Detection {
+            score: 0.9,
+            class: 1,
+            top: 0.1,
+            left: 0.11,
+            bottom: 0.2,
+            right: 0.21,
+        };
+        let input = vec![detection(1), filtered];
+        let filter = DetectFilter::new(|rect| rect.x < 105_000);
+
+        let got = process_detections(
+            &label_map,
+            &HashSet::new(),
+            &filter,
+            Some(NmsMode::Suppress),
+            no_area_filter(),
+            input,
+        );
+        assert_eq!(parse_detections(&label_map, vec![detection(1)]), got);
+    }
+
+    // Square box at the top left corner.
+    fn square(size: f32) -> tflite_lib::Detection {
+        tflite_lib::Detection {
+            score: 0.5,
+            class: 0,
+            top: 0.0,
+            left: 0.0,
+            bottom: size,
+            right: size,
+        }
+    }
+
+    #[test]
+    fn test_area_filter() {
+        let input = || {
+            vec![
+                square(0.0),
+                square(0.25),
+                square(0.5),
+                square(0.75),
+                square(1.0),
+            ]
+        };
+        let areas = |filter: AreaFilter| -> Vec<f32> {
+            filter
+                .apply(input())
+                .iter()
+                .map(tflite_lib::Detection::area)
+                .collect()
+        };
+
+        // The bounds are inclusive.
+        let filter = AreaFilter::new(0.0625, 0.5625).unwrap();
+        assert_eq!(vec![0.0625, 0.25, 0.5625], areas(filter));
+
+        // Zero and one disable the bounds.
+        let filter = AreaFilter::new(0.0, 1.0).unwrap();
+        assert_eq!(vec![0.0, 0.0625, 0.25, 0.5625, 1.0], areas(filter));
+        let filter = AreaFilter::new(0.2, 1.0).unwrap();
+        assert_eq!(vec![0.25, 0.5625, 1.0], areas(filter));
+        let filter = AreaFilter::new(0.0, 0.2).unwrap();
+        assert_eq!(vec![0.0, 0.0625], areas(filter));
+
+        // Boxes that extend outside the frame are clamped.
+        let oversized = || {
+            vec![tflite_lib::Detection {
+                score: 0.5,
+                class: 0,
+                top: -0.1,
+                left: -0.1,
+                bottom: 1.1,
+                right: 1.1,
+            }]
+        };
+        let filter = AreaFilter::new(0.0, 1.0).unwrap();
+        assert_eq!(1, filter.apply(oversized()).len());
+        let filter = AreaFilter::new(0.5, 1.0).unwrap();
+        assert_eq!(1, filter.apply(oversized()).len());
+        let filter = AreaFilter::new(0.0, 0.9).unwrap();
+        assert!(filter.apply(oversized()).is_empty());
+
+        assert_eq!(None, AreaFilter::new(-0.1, 1.0));
+        assert_eq!(None, AreaFilter::new(0.0, 1.1));
+        assert_eq!(None, AreaFilter::new(0.5, 0.4));
+    }
+
+    struct StubDetector;
+
+    impl ObjectDetector for StubDetector {
+        fn detect(
+            &mut self,
+            buf: &[u8],
+            _: &DetectFilter,
+        ) -> Result<Detections, common::DetectError> {
+            // Echo the input as classes.
+            let label_map = LabelMap::from([(1, "a".to_owned().try_into().unwrap())]);
+            let input = buf.iter().map(|class| detection(*class)).collect();
+            Ok(process_detections(
+                &label_map,
+                &HashSet::from([2]),
+                &DetectFilter::allow_all(),
+                None,
+                no_area_filter(),
+                input,
+            ))
+        }
+
+        fn input_dims(&self) -> (u16, u16) {
+            (2, 3)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spawn_detector() {
+        let detector: Box<dyn ObjectDetector> = Box::new(StubDetector);
+        assert_eq!((2, 3), detector.input_dims());
+
+        let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel(1);
+        let (detect_tx, detect_rx) = async_channel::bounded::<DetectRequest>(1);
+        spawn_detector(
+            &Handle::current(),
+            shutdown_complete_tx,
+            detector,
+            detect_rx,
+        );
+
+        let (res_tx, res_rx) = oneshot::channel();
+        detect_tx
+            .send(DetectRequest {
+                data: vec![1, 2, 3],
+                filter: DetectFilter::allow_all(),
+                res: res_tx,
+                permit: None,
+            })
+            .await
+            .unwrap();
+        let got: Vec<String> = res_rx
+            .await
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|d| d.label.to_string())
+            .collect();
+        assert_eq!(vec!["a", "unknown3"], got);
+
+        // The worker exits when the channel is closed.
+        drop(detect_tx);
+        assert!(shutdown_complete_rx.recv().await.is_none());
+    }
+
+    // Records the highest number of detections running at the same time.
+    struct SlowDetector {
+        running: Arc<AtomicUsize>,
+        max_running: Arc<AtomicUsize>,
+    }
+
+    impl ObjectDetector for SlowDetector {
+        fn detect(
+            &mut self,
+            _: &[u8],
+            _: &DetectFilter,
+        ) -> Result<Detections, common::DetectError> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(Vec::new())
+        }
+
+        fn input_dims(&self) -> (u16, u16) {
+            (1, 1)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_max_concurrent_detections() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let limit = Arc::new(Semaphore::new(1));
+        let (shutdown_complete_tx, _shutdown_complete_rx) = mpsc::channel(1);
+
+        // One detector for each monitor.
+        let detectors: Vec<_> = (0..4)
+            .map(|_| {
+                let (detect_tx, detect_rx) = async_channel::bounded::<DetectRequest>(1);
+                spawn_detector(
+                    &Handle::current(),
+                    shutdown_complete_tx.clone(),
+                    Box::new(SlowDetector {
+                        running: running.clone(),
+                        max_running: max_running.clone(),
+                    }),
+                    detect_rx,
+                );
+                Arc::new(Detector {
+                    rt_handle: Handle::current(),
+                    detect_tx,
+                    width: NonZeroU16::new(1).unwrap(),
+                    height: NonZeroU16::new(1).unwrap(),
+                    limit: Some(limit.clone()),
+                })
+            })
+            .collect();
+
+        let tasks: Vec<_> = detectors
+            .into_iter()
+            .map(|detector| {
+                tokio::spawn(
+                    async move { detector.detect(Vec::new(), DetectFilter::allow_all()).await },
+                )
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(Some(Vec::new()), task.await.unwrap().unwrap());
+        }
+        assert_eq!(1, max_running.load(Ordering::SeqCst));
+    }
+
+    // Blocks until `unblock` is sent.
+    struct BlockingDetector {
+        unblock: std::sync::mpsc::Receiver<()>,
+    }
+
+    impl ObjectDetector for BlockingDetector {
+        fn detect(
+            &mut self,
+            _: &[u8],
+            _: &DetectFilter,
+        ) -> Result<Detections, common::DetectError> {
+            self.unblock.recv().unwrap();
+            Ok(Vec::new())
+        }
+
+        fn input_dims(&self) -> (u16, u16) {
+            (1, 1)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_permit_held_after_timeout() {
+        let limit = Arc::new(Semaphore::new(1));
+        let (unblock_tx, unblock_rx) = std::sync::mpsc::channel();
+        let (shutdown_complete_tx, _shutdown_complete_rx) = mpsc::channel(1);
+        let (detect_tx, detect_rx) = async_channel::bounded::<DetectRequest>(1);
+        spawn_detector(
+            &Handle::current(),
+            shutdown_complete_tx,
+            Box::new(BlockingDetector {
+                unblock: unblock_rx,
+            }),
+            detect_rx,
+        );
+
+        // The caller gives up on the response.
+        let (res_tx, res_rx) = oneshot::channel();
+        detect_tx
+            .send(DetectRequest {
+                data: Vec::new(),
+                filter: DetectFilter::allow_all(),
+                res: res_tx,
+                permit: Some(limit.clone().acquire_owned().await.unwrap()),
+            })
+            .await
+            .unwrap();
+        drop(res_rx);
+
+        // The permit is released when the detection is done.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(0, limit.available_permits());
+        unblock_tx.send(()).unwrap();
+        _ = tokio::time::timeout(Duration::from_secs(1), limit.acquire())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_transient_detectors() {
+        let limit = Arc::new(Semaphore::new(1));
+        let transient_detectors = TransientDetectors {
+            rt_handle: Handle::current(),
+            models: Arc::new(ModelRegistry::new()),
+            models_dir: PathBuf::from("/models"),
+            limit: limit.clone(),
+        };
+
+        // Wait while a configured detector holds the permit.
+        let permit = limit.acquire().await.unwrap();
+        let tasks: Vec<_> = [ModelFormat::Detector, ModelFormat::Classifier]
+            .into_iter()
+            .map(|format| {
+                let transient_detectors = transient_detectors.clone();
+                let image = Image {
+                    width: 1,
+                    height: 1,
+                    rgb: vec![0, 0, 0],
+                };
+                tokio::spawn(async move {
+                    transient_detectors
+                        .detect(Path::new("nil.tflite"), format, 5, image.into())
+                        .await
+                })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(tasks.iter().all(|task| !task.is_finished()));
+        drop(permit);
+
+        for task in tasks {
+            let Err(e) = task.await.unwrap() else {
+                panic!("expected error");
+            };
+            assert!(
+                matches!(
+                    e,
+                    TransientDetectError::NewDetector(NewTransientDetectorError::NewDetector(
+                        NewDetectorError::ReadModel(..)
+                    ))
+                ),
+                "{e}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve_model_path() {
+        let models_dir = Path::new("/models");
+        assert_eq!(
+            Some(PathBuf::from("/models/a/b.tflite")),
+            resolve_model_path(models_dir, Path::new("a/b.tflite"))
+        );
+        assert_eq!(None, resolve_model_path(models_dir, Path::new("")));
+        assert_eq!(None, resolve_model_path(models_dir, Path::new("/b.tflite")));
+        assert_eq!(
+            None,
+            resolve_model_path(models_dir, Path::new("../b.tflite"))
+        );
+        assert_eq!(None, resolve_model_path(models_dir, Path::new("a/../../b")));
+        assert_eq!(
+            None,
+            resolve_model_path(models_dir, Path::new("./b.tflite"))
+        );
+    }
+
+    // Fails with an invoke error if `fail` is set.
+    struct StubBackend {
+        fail: bool,
+    }
+
+    impl EdgetpuBackend for StubBackend {
+        fn detect(
+            &mut self,
+            _: &[u8],
+        ) -> Result<Vec<tflite_lib::Detection>, tflite_lib::DetectError> {
+            if self.fail {
+                return Err(tflite_lib::DetectError::Detect(1));
+            }
+            Ok(vec![detection(1)])
+        }
+    }
+
+    fn usb_device(path: &str) -> EdgetpuDevice {
+        EdgetpuDevice {
+            typ: EdgetpuDeviceType::Usb,
+            path: path.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_reconnector() {
+        use std::sync::Mutex;
+
+        let devices: Arc<Mutex<Vec<String>>> = Arc::default();
+        let list_calls = Arc::new(Mutex::new(0));
+        let connected = Arc::new(Mutex::new(Vec::new()));
+
+        let list_devices: ListDevicesFunc = {
+            let devices = devices.clone();
+            let list_calls = list_calls.clone();
+            Box::new(move || {
+                *list_calls.lock().unwrap() += 1;
+                devices
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|v| usb_device(v))
+                    .collect()
+            })
+        };
+        let connect: ConnectFunc = {
+            let connected = connected.clone();
+            Box::new(
+                move |device: &EdgetpuDevice| -> Result<Box<dyn EdgetpuBackend>, _> {
+                    connected.lock().unwrap().push(device.path.clone());
+                    Ok(Box::new(StubBackend { fail: false }))
+                },
+            )
+        };
+        let mut r = Reconnector {
+            logger: common::new_dummy_msg_logger(),
+            name: "x".to_owned().try_into().unwrap(),
+            device_path: "/sys/bus/usb/devices/1-1".to_owned(),
+            device_type: EdgetpuDeviceType::Usb,
+            claimed_devices: Arc::new(Mutex::new(HashSet::from([
+                "/sys/bus/usb/devices/1-1".to_owned(),
+                "/sys/bus/usb/devices/3-1".to_owned(),
+            ]))),
+            list_devices,
+            connect,
+            state: ReconnectState::Connected {
+                backend: Box::new(StubBackend { fail: true }),
+                errors: 0,
+            },
+        };
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        // Device unplugged.
+        for _ in 0..RECONNECT_AFTER_ERRORS {
+            assert!(matches!(
+                r.detect_at(&[], start),
+                Err(EdgetpuDetectError::Detect(_))
+            ));
+        }
+        assert_eq!(0, *list_calls.lock().unwrap());
+
+        // Device missing.
+        assert!(matches!(
+            r.detect_at(&[], start),
+            Err(EdgetpuDetectError::Disconnected(_))
+        ));
+        assert_eq!(1, *list_calls.lock().unwrap());
+
+        // Backoff. The device of another detector is never used.
+        devices.lock().unwrap().extend([
+            "/sys/bus/usb/devices/3-1".to_owned(),
+            "/sys/bus/usb/devices/2-1".to_owned(),
+        ]);
+        assert!(matches!(
+            r.detect_at(&[], at(500)),
+            Err(EdgetpuDetectError::Disconnected(_))
+        ));
+        assert_eq!(1, *list_calls.lock().unwrap());
+
+        // Device replugged into another port.
+        assert_eq!(1, r.detect_at(&[], at(1000)).unwrap().len());
+        assert_eq!(2, *list_calls.lock().unwrap());
+        assert_eq!(
+            vec!["/sys/bus/usb/devices/2-1".to_owned()],
+            *connected.lock().unwrap()
+        );
+        assert_eq!("/sys/bus/usb/devices/2-1", r.device_path);
+        assert_eq!(
+            HashSet::from([
+                "/sys/bus/usb/devices/2-1".to_owned(),
+                "/sys/bus/usb/devices/3-1".to_owned(),
+            ]),
+            *r.claimed_devices.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_find_device() {
+        let none = HashSet::new();
+        let devices = [usb_device("a"), usb_device("b")];
+        let got = find_device(&devices, "b", EdgetpuDeviceType::Usb, &none);
+        assert_eq!("b", got.unwrap().path);
+
+        // Ambiguous.
+        assert!(find_device(&devices, "c", EdgetpuDeviceType::Usb, &none).is_none());
+
+        // The only device that isn't claimed by another detector.
+        let claimed = HashSet::from(["a".to_owned()]);
+        let got = find_device(&devices, "c", EdgetpuDeviceType::Usb, &claimed);
+        assert_eq!("b", got.unwrap().path);
+
+        let devices = [usb_device("a")];
+        let got = find_device(&devices, "c", EdgetpuDeviceType::Usb, &none);
+        assert_eq!("a", got.unwrap().path);
+        assert!(find_device(&devices, "c", EdgetpuDeviceType::Pci, &none).is_none());
+        assert!(find_device(&devices, "c", EdgetpuDeviceType::Usb, &claimed).is_none());
+    }
 }
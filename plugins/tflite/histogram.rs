@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use common::{
+    time::{Duration, UnixNano, MINUTE},
+    Detection, Label, MonitorId, ScoreHistogramWindow, SCORE_HISTOGRAM_BINS,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+// Scores older than this are not counted.
+const SCORE_HISTOGRAM_WINDOW: Duration = Duration::new(10 * MINUTE);
+
+pub(crate) type Bins = [u64; SCORE_HISTOGRAM_BINS];
+
+// Raw detection scores of each label within the window,
+// recorded before the thresholds are applied.
+#[derive(Default)]
+pub(crate) struct LabelHistograms(Mutex<HashMap<Label, ScoreHistogramWindow>>);
+
+impl LabelHistograms {
+    pub(crate) fn record(&self, time: UnixNano, detections: &[Detection]) {
+        if detections.is_empty() {
+            return;
+        }
+        let mut windows = self.0.lock().expect("not poisoned");
+        for d in detections {
+            windows
+                .entry(d.label.clone())
+                .or_insert_with(|| ScoreHistogramWindow::new(SCORE_HISTOGRAM_WINDOW))
+                .add(time, std::slice::from_ref(d));
+        }
+    }
+
+    // Labels without scores in the window are removed.
+    pub(crate) fn counts(&self, now: UnixNano) -> HashMap<Label, Bins> {
+        let mut windows = self.0.lock().expect("not poisoned");
+        let mut counts = HashMap::new();
+        windows.retain(|label, window| {
+            let bins = window.histogram(now).bins;
+            if bins.iter().all(|v| *v == 0) {
+                return false;
+            }
+            counts.insert(label.clone(), bins);
+            true
+        });
+        counts
+    }
+
+    pub(crate) fn reset(&self) {
+        self.0.lock().expect("not poisoned").clear();
+    }
+}
+
+// Histograms of the monitors that have `scoreHistogram` enabled.
+// The histogram of a monitor is kept when the monitor restarts.
+#[derive(Clone, Default)]
+pub(crate) struct ScoreHistograms(Arc<Mutex<HashMap<MonitorId, Arc<LabelHistograms>>>>);
+
+impl ScoreHistograms {
+    pub(crate) fn get_or_create(&self, monitor_id: &MonitorId) -> Arc<LabelHistograms> {
+        self.0
+            .lock()
+            .expect("not poisoned")
+            .entry(monitor_id.clone())
+            .or_default()
+            .clone()
+    }
+
+    pub(crate) fn get(&self, monitor_id: &MonitorId) -> Option<Arc<LabelHistograms>> {
+        self.0
+            .lock()
+            .expect("not poisoned")
+            .get(monitor_id)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use common::Region;
+    use pretty_assertions::assert_eq;
+
+    fn detection(label: &str, score: f32) -> Detection {
+        Detection {
+            label: label.to_owned().try_into().unwrap(),
+            score,
+            region: Region::default(),
+        }
+    }
+
+    #[test]
+    fn test_label_histograms() {
+        let histograms = LabelHistograms::default();
+        let start = UnixNano::new(0);
+        histograms.record(
+            start,
+            &[
+                detection("a", 0.0),
+                detection("a", 4.9),
+                detection("a", 5.0),
+                detection("a", 55.0),
+                detection("a", 100.0),
+                detection("a", f32::NAN),
+                detection("b", 99.0),
+            ],
+        );
+        let later = UnixNano::from(Duration::from_secs(60));
+        histograms.record(later, &[detection("a", 56.0)]);
+
+        let mut a = [0; SCORE_HISTOGRAM_BINS];
+        a[0] = 2;
+        a[1] = 1;
+        a[11] = 2;
+        a[19] = 1;
+        let mut b = [0; SCORE_HISTOGRAM_BINS];
+        b[19] = 1;
+        let want = HashMap::from([
+            ("a".to_owned().try_into().unwrap(), a),
+            ("b".to_owned().try_into().unwrap(), b),
+        ]);
+        assert_eq!(want, histograms.counts(later));
+
+        // Only the last score is within the window.
+        let mut a = [0; SCORE_HISTOGRAM_BINS];
+        a[11] = 1;
+        let want = HashMap::from([("a".to_owned().try_into().unwrap(), a)]);
+        let now = start
+            .checked_add(SCORE_HISTOGRAM_WINDOW.into())
+            .unwrap()
+            .checked_add(Duration::from_secs(1).into())
+            .unwrap();
+        assert_eq!(want, histograms.counts(now));
+
+        histograms.reset();
+        assert!(histograms.counts(later).is_empty());
+    }
+}
@@ -81,8 +81,15 @@ export function _tflite(hls, detectors, hasSubStream, getMonitorId) {
 			initial: 0.2,
 		}
 	);
+	fields.detectEveryNFrames = fieldTemplate.integer("Detect every nth frame", "", 1);
 	fields.duration = fieldTemplate.integer("Trigger duration (sec)", "", 120);
 	fields.useSubStream = fieldTemplate.toggle("Use sub stream", true);
+	fields.scoreHistogram = fieldTemplate.toggle("Score histogram", false);
+	fields.oversizedFrame = fieldTemplate.select(
+		"Oversized frames",
+		["downscale", "error"],
+		"downscale"
+	);
 	//fields.preview = preview()
 
 	const form = newForm(fields);
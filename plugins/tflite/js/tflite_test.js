@@ -9,6 +9,7 @@ const defaultConfig = {
 		x: 0,
 		y: 0,
 	},
+	detectEveryNFrames: 1,
 	detectorName: "",
 	duration: 120,
 	enable: false,
@@ -22,6 +23,8 @@ const defaultConfig = {
 		],
 		enable: false,
 	},
+	oversizedFrame: "downscale",
+	scoreHistogram: false,
 	thresholds: {},
 	useSubStream: true,
 };
@@ -0,0 +1,262 @@
+use ort::{
+    session::Session,
+    tensor::TensorElementType,
+    value::{DynValue, Tensor, ValueType},
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+use tflite_lib::{
+    apply_channel_order, parse_detection_tensors, ChannelOrder, Detection, ModelRegistry,
+    ParseOutputTensorsError,
+};
+use thiserror::Error;
+
+// Loaded if `ORT_DYLIB_PATH` isn't set.
+const DEFAULT_RUNTIME_PATH: &str = "libonnxruntime.so";
+
+#[derive(Clone, Debug, Error)]
+pub enum LoadRuntimeError {
+    #[error("load '{0}': {1}, set ORT_DYLIB_PATH to the ONNX Runtime library")]
+    Load(String, String),
+
+    #[error("init: {0}")]
+    Init(String),
+}
+
+// The ONNX Runtime library is loaded at runtime and only once. ort panics
+// if the library is missing, check that it can be loaded before using it.
+fn load_runtime() -> Result<(), LoadRuntimeError> {
+    use LoadRuntimeError::*;
+    static RUNTIME: OnceLock<Result<(), LoadRuntimeError>> = OnceLock::new();
+    RUNTIME
+        .get_or_init(|| {
+            let path = std::env::var("ORT_DYLIB_PATH")
+                .ok()
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| DEFAULT_RUNTIME_PATH.to_owned());
+            // Safety: ort loads the same library right after.
+            unsafe { libloading::Library::new(&path) }
+                .map_err(|e| Load(path.clone(), e.to_string()))?;
+            ort::init_from(path)
+                .with_name("sentryshot")
+                .commit()
+                .map_err(|e| Init(e.to_string()))?;
+            Ok(())
+        })
+        .clone()
+}
+
+#[derive(Debug, Error)]
+pub enum NewDetectorError {
+    #[error("load onnx runtime: {0}")]
+    LoadRuntime(#[from] LoadRuntimeError),
+
+    #[error("read model {0:?}: {1}")]
+    ReadModel(PathBuf, std::io::Error),
+
+    #[error("create session: {0}")]
+    CreateSession(ort::Error),
+
+    #[error("expected one input tensor, got {0}")]
+    InputTensorCount(usize),
+
+    #[error("input tensor type must be uint8 or float32: {0:?}")]
+    InputTensorType(ValueType),
+
+    #[error("input tensor shape must be [1, height, width, 3]: {0:?}")]
+    InputTensorShape(Vec<i64>),
+
+    #[error("expected 4 output tensors, got {0}")]
+    OutputTensorCount(usize),
+}
+
+#[derive(Debug, Error)]
+pub enum DetectError {
+    #[error("buffer size, expected {0} got {1}")]
+    BufferSize(usize, usize),
+
+    #[error("create input tensor: {0}")]
+    CreateInput(ort::Error),
+
+    #[error("run: {0}")]
+    Run(ort::Error),
+
+    #[error("extract output tensor {0}: {1}")]
+    ExtractOutput(usize, ort::Error),
+
+    #[error("parse output tensors: {0}")]
+    ParseOutputTensors(#[from] ParseOutputTensorsError),
+}
+
+// Element type of the input tensor. Pixels are never quantized, uint8
+// input gets the raw pixels and float32 input gets them scaled to 0-1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum InputType {
+    Uint8,
+    Float32,
+}
+
+// Detector that runs ONNX models with the same input and outputs
+// as the tflite detectors, a NHWC RGB image and the boxes, classes,
+// scores and count tensors.
+pub struct Detector {
+    session: Session,
+    input_type: InputType,
+    input_shape: [i64; 4],
+    input_tensor_size: usize,
+    channel_order: ChannelOrder,
+}
+
+impl Detector {
+    pub fn new(
+        models: &ModelRegistry,
+        model_path: &Path,
+        channel_order: ChannelOrder,
+    ) -> Result<Self, NewDetectorError> {
+        use NewDetectorError::*;
+        load_runtime()?;
+        let model = models
+            .load(model_path)
+            .map_err(|e| ReadModel(model_path.to_owned(), e))?;
+        let session = Session::builder()
+            .and_then(|v| v.with_intra_threads(1))
+            .and_then(|v| v.commit_from_memory(&model))
+            .map_err(CreateSession)?;
+
+        let [input] = session.inputs.as_slice() else {
+            return Err(InputTensorCount(session.inputs.len()));
+        };
+        let (input_type, dimensions) = match &input.input_type {
+            ValueType::Tensor {
+                ty: TensorElementType::Uint8,
+                dimensions,
+                ..
+            } => (InputType::Uint8, dimensions),
+            ValueType::Tensor {
+                ty: TensorElementType::Float32,
+                dimensions,
+                ..
+            } => (InputType::Float32, dimensions),
+            v => return Err(InputTensorType(v.clone())),
+        };
+        let input_shape =
+            parse_input_shape(dimensions).ok_or_else(|| InputTensorShape(dimensions.clone()))?;
+        let input_tensor_size = input_shape
+            .iter()
+            .map(|v| usize::try_from(*v).ok())
+            .product::<Option<usize>>()
+            .ok_or_else(|| InputTensorShape(dimensions.clone()))?;
+
+        if session.outputs.len() != 4 {
+            return Err(OutputTensorCount(session.outputs.len()));
+        }
+
+        Ok(Self {
+            session,
+            input_type,
+            input_shape,
+            input_tensor_size,
+            channel_order,
+        })
+    }
+
+    // Width and height of the input tensor.
+    #[must_use]
+    pub fn input_dims(&self) -> (i64, i64) {
+        (self.input_shape[2], self.input_shape[1])
+    }
+
+    // Runs the model on a RGB frame of the input size.
+    pub fn detect(&mut self, buf: &[u8]) -> Result<Vec<Detection>, DetectError> {
+        use DetectError::*;
+        if buf.len() != self.input_tensor_size {
+            return Err(BufferSize(self.input_tensor_size, buf.len()));
+        }
+        let buf = apply_channel_order(buf, self.channel_order);
+        let input = create_input(self.input_type, self.input_shape, &buf).map_err(CreateInput)?;
+
+        let outputs = self.session.run([input.into()]).map_err(Run)?;
+        let tensor = |i: usize| {
+            outputs[i]
+                .try_extract_raw_tensor::<f32>()
+                .map(|(_, v)| v)
+                .map_err(|e| ExtractOutput(i, e))
+        };
+        Ok(parse_detection_tensors(
+            tensor(0)?,
+            tensor(1)?,
+            tensor(2)?,
+            tensor(3)?,
+        )?)
+    }
+}
+
+// Returns None unless the shape is [1, height, width, 3]. Dynamic
+// dimensions are -1 and aren't supported.
+fn parse_input_shape(dimensions: &[i64]) -> Option<[i64; 4]> {
+    match *dimensions {
+        [1, height, width, 3] if height > 0 && width > 0 => Some([1, height, width, 3]),
+        _ => None,
+    }
+}
+
+fn create_input(typ: InputType, shape: [i64; 4], buf: &[u8]) -> Result<DynValue, ort::Error> {
+    Ok(match typ {
+        InputType::Uint8 => Tensor::from_array((shape, buf.to_vec()))?.into_dyn(),
+        InputType::Float32 => Tensor::from_array((shape, u8_to_unit_f32(buf)))?.into_dyn(),
+    })
+}
+
+fn u8_to_unit_f32(buf: &[u8]) -> Vec<f32> {
+    buf.iter().map(|v| f32::from(*v) / 255.0).collect()
+}
+
+#[allow(clippy::unwrap_used, clippy::float_cmp)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_input_shape() {
+        assert_eq!(Some([1, 2, 3, 3]), parse_input_shape(&[1, 2, 3, 3]));
+        assert_eq!(None, parse_input_shape(&[1, -1, -1, 3]));
+        assert_eq!(None, parse_input_shape(&[1, 3, 2, 2]));
+        assert_eq!(None, parse_input_shape(&[2, 2, 2, 3]));
+        assert_eq!(None, parse_input_shape(&[2, 2, 3]));
+    }
+
+    #[test]
+    fn test_u8_to_unit_f32() {
+        assert_eq!(vec![0.0, 0.2, 1.0], u8_to_unit_f32(&[0, 51, 255]));
+    }
+
+    // testdata/identity.onnx has a float32 [1, 2, 2, 3] input that is
+    // reshaped into the [1, 3, 4] boxes output. The classes [0, 1, 2],
+    // scores [0.9, 0.8, 0.7] and count [2] outputs are constants.
+    #[test]
+    #[ignore = "requires the ONNX Runtime library"]
+    fn test_detect() {
+        let model_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join("identity.onnx");
+        let models = ModelRegistry::new();
+        let mut detector = Detector::new(&models, &model_path, ChannelOrder::Bgr).unwrap();
+        assert_eq!((2, 2), detector.input_dims());
+        assert!(matches!(
+            detector.detect(&[0; 3]),
+            Err(DetectError::BufferSize(12, 3))
+        ));
+
+        let buf = [102, 51, 0, 255, 204, 153, 0, 51, 0, 0, 0, 0];
+        let detections = detector.detect(&buf).unwrap();
+        let got: Vec<_> = detections
+            .iter()
+            .map(|d| (d.score, d.class, d.top, d.left, d.bottom, d.right))
+            .collect();
+        let want = vec![(0.9, 0, 0.0, 0.2, 0.4, 0.6), (0.8, 1, 0.8, 1.0, 0.0, 0.2)];
+        assert_eq!(want, got);
+    }
+}
@@ -1,33 +1,45 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
 mod config;
+mod cooldown;
 mod detector;
+mod histogram;
 mod label;
 mod model;
-
-use crate::{config::TfliteConfig, detector::DetectorManager};
+mod zone;
+
+use crate::{
+    config::TfliteConfig,
+    detector::{
+        check_detector_config, DetectorManager, TransientDetectError, TransientDetectors,
+        TransientFrame,
+    },
+};
 use async_trait::async_trait;
 use axum::{
-    extract::{Path, State},
+    body::Bytes,
+    extract::{Path, Query, State},
     http::StatusCode,
     middleware,
     response::{IntoResponse, Response},
-    routing::patch,
-    Router,
+    routing::{get, patch, post},
+    Json, Router,
 };
 use common::{
     monitor::{ArcMonitor, ArcMonitorManager, ArcSource, DecoderError, SubscribeDecodedError},
     recording::{vertex_inside_poly2, FrameRateLimiter},
     time::{DurationH264, UnixH264, UnixNano},
-    ArcAuth, ArcLogger, ArcMsgLogger, Detection, Detections, DynEnvConfig, Event, LogEntry,
-    LogLevel, LogSource, MonitorId, MsgLogger, RectangleNormalized, Region,
+    ArcAuth, ArcLogger, ArcMsgLogger, DetectFilter, Detection, Detections, DynEnvConfig, Event,
+    LogEntry, LogLevel, LogSource, MonitorId, MsgLogger, RectangleNormalized, Region,
 };
-use config::{set_enable, Crop, Mask};
+use config::{set_enable, Crop, Mask, OversizedFrame};
+use cooldown::EventCooldown;
 use detector::{DetectError, Detector, DetectorName, Thresholds};
+use histogram::{LabelHistograms, ScoreHistograms};
 use hyper::{body::HttpBody, http::uri::InvalidUri};
 use hyper_rustls::HttpsConnectorBuilder;
 use plugin::{
-    types::{admin, Assets},
+    types::{admin, Assets, CheckConfigFn},
     Application, Plugin, PreLoadPlugin,
 };
 use sentryshot_convert::{
@@ -36,16 +48,24 @@ use sentryshot_convert::{
 use sentryshot_filter::{crop, pad, CropError, PadError};
 use sentryshot_scale::{CreateScalerError, Scaler, ScalerError};
 use sentryshot_util::ImageCopyToBufferError;
+use serde::Deserialize;
+use serde_json::json;
 use std::{
     borrow::Cow,
     num::{NonZeroU16, NonZeroU32, TryFromIntError},
+    path::PathBuf,
     sync::Arc,
     time::Duration,
 };
+use tflite_lib::{
+    parse_ppm, ColorMatrix, FrameFormat, ModelFormat, ModelOutput, NewDetectorError,
+    NewTransientDetectorError,
+};
 use thiserror::Error;
 use tokio::{io::AsyncWriteExt, runtime::Handle, sync::mpsc};
 use tokio_util::sync::CancellationToken;
 use url::Url;
+use zone::Zones;
 
 #[no_mangle]
 pub extern "Rust" fn version() -> String {
@@ -62,6 +82,9 @@ impl PreLoadPlugin for PreLoadTflite {
         #[allow(clippy::unwrap_used)]
         Some("tflite".try_into().unwrap())
     }
+    fn check_config(&self) -> Option<CheckConfigFn> {
+        Some(check_detector_config)
+    }
 }
 
 #[no_mangle]
@@ -95,6 +118,7 @@ pub struct TflitePlugin {
     auth: ArcAuth,
     monitor_manager: ArcMonitorManager,
     detector_manager: DetectorManager,
+    histograms: ScoreHistograms,
 }
 
 impl TflitePlugin {
@@ -132,6 +156,7 @@ impl TflitePlugin {
             auth,
             monitor_manager,
             detector_manager,
+            histograms: ScoreHistograms::default(),
         }
     }
 }
@@ -174,6 +199,8 @@ impl Plugin for TflitePlugin {
         let state = HandlerState {
             logger: self.logger.clone(),
             monitor_manager: self.monitor_manager.clone(),
+            histograms: self.histograms.clone(),
+            transient_detectors: self.detector_manager.transient_detectors(),
         };
         router
             .route(
@@ -186,6 +213,21 @@ impl Plugin for TflitePlugin {
             .route(
                 "/api/monitor/:id/tflite/disable",
                 patch(disable_handler)
+                    .with_state(state.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), admin))
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/api/monitor/:id/tflite/histogram",
+                get(histogram_handler)
+                    .delete(histogram_reset_handler)
+                    .with_state(state.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), admin))
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/api/tflite/detect",
+                post(detect_handler)
                     .with_state(state)
                     .route_layer(middleware::from_fn_with_state(self.auth.clone(), admin))
                     .with_state(self.auth.clone()),
@@ -222,6 +264,9 @@ enum RunError {
     #[error("calculate outputs: {0}")]
     CalculateOutputs(#[from] CalculateOutputsError),
 
+    #[error("{0}")]
+    FrameTooLarge(#[from] FrameTooLargeError),
+
     #[error("process frame: {0}")]
     ProcessFrame(#[from] ProcessFrameError),
 
@@ -277,10 +322,23 @@ impl TflitePlugin {
             .get_detector(&detector_name)
             .ok_or(GetDetector(detector_name))?;
 
+        let histogram = config
+            .score_histogram
+            .then(|| self.histograms.get_or_create(monitor.config().id()));
+
+        let mut warned_oversized = false;
         loop {
             msg_logger.log(LogLevel::Debug, "run");
             if let Err(e) = self
-                .run(&msg_logger, &monitor, &config, &source, &detector)
+                .run(
+                    &msg_logger,
+                    &monitor,
+                    &config,
+                    &source,
+                    &detector,
+                    histogram.as_deref(),
+                    &mut warned_oversized,
+                )
                 .await
             {
                 msg_logger.log(LogLevel::Error, &format!("run: {e}"));
@@ -297,6 +355,7 @@ impl TflitePlugin {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn run(
         &self,
         msg_logger: &ArcMsgLogger,
@@ -304,6 +363,8 @@ impl TflitePlugin {
         config: &TfliteConfig,
         source: &ArcSource,
         detector: &Detector,
+        histogram: Option<&LabelHistograms>,
+        warned_oversized: &mut bool,
     ) -> Result<(), RunError> {
         use RunError::*;
         let Some(muxer) = source.muxer().await else {
@@ -337,6 +398,28 @@ impl TflitePlugin {
         let mut feed = feed?;
 
         let (outputs, uncrop) = calculate_outputs(config.crop, &inputs)?;
+        if check_frame_size(config.oversized_frame, &inputs, &outputs)? && !*warned_oversized {
+            *warned_oversized = true;
+            msg_logger.log(
+                LogLevel::Warning,
+                &format!(
+                    "frames are {width}x{height} which is larger than the detector input, downscaling to {}x{}",
+                    outputs.scaled_width, outputs.scaled_height
+                ),
+            );
+        }
+        let uncrop = Arc::new(uncrop);
+        let filter = zone_filter(config.zones.clone(), uncrop.clone());
+
+        let mut cooldown = config.cooldown.as_ref().map(EventCooldown::new);
+        let mut interval = FrameInterval::new(config.detect_every_n_frames);
+
+        // Events last until the next detection.
+        let event_duration = common::time::Duration::new(
+            config
+                .feed_rate
+                .saturating_mul(i64::from(config.detect_every_n_frames.get())),
+        );
 
         let mut state = DetectorState {
             frame_processed: vec![0; outputs.output_size],
@@ -349,6 +432,9 @@ impl TflitePlugin {
                 return Ok(());
             };
             let frame = frame?;
+            if !interval.should_detect() {
+                continue;
+            }
 
             let time = UnixNano::from(UnixH264::new(frame.pts()));
 
@@ -358,10 +444,16 @@ impl TflitePlugin {
                 .await
                 .expect("join")?;
 
-            let Some(detections) = detector.detect(state.frame_processed.clone()).await? else {
+            let detections = detector
+                .detect(state.frame_processed.clone(), filter.clone())
+                .await?;
+            let Some(detections) = detections else {
                 // Canceled.
                 return Ok(());
             };
+            if let Some(histogram) = histogram {
+                histogram.record(time, &detections);
+            }
             let detections =
                 parse_detections(&config.thresholds, &config.mask, &uncrop, detections)?;
 
@@ -370,6 +462,12 @@ impl TflitePlugin {
                 continue;
             };
 
+            if let Some(cooldown) = &mut cooldown {
+                if !cooldown.check(time, &detections) {
+                    continue;
+                }
+            }
+
             msg_logger.log(
                 LogLevel::Debug,
                 &format!("trigger: label:{} score:{:.1}", d.label, d.score),
@@ -378,7 +476,7 @@ impl TflitePlugin {
             monitor
                 .send_event(Event {
                     time,
-                    duration: *config.feed_rate,
+                    duration: event_duration,
                     rec_duration: *config.duration,
                     detections,
                     source: Some("tflite".to_owned().try_into().expect("valid")),
@@ -388,6 +486,24 @@ impl TflitePlugin {
     }
 }
 
+// Selects every nth frame for detection, starting with the first frame.
+struct FrameInterval {
+    n: NonZeroU32,
+    count: u32,
+}
+
+impl FrameInterval {
+    fn new(n: NonZeroU32) -> Self {
+        Self { n, count: 0 }
+    }
+
+    fn should_detect(&mut self) -> bool {
+        let detect = self.count == 0;
+        self.count = (self.count + 1) % self.n;
+        detect
+    }
+}
+
 struct DetectorState {
     outputs: Outputs,
     frame_processed: Vec<u8>,
@@ -414,7 +530,7 @@ struct Outputs {
     output_size: usize,
 }
 
-type UncropFn = Box<dyn Fn(u32) -> u32 + Send>;
+type UncropFn = Box<dyn Fn(u32) -> u32 + Send + Sync>;
 
 pub(crate) struct Uncrop {
     uncrop_x_fn: UncropFn,
@@ -553,6 +669,32 @@ fn calculate_outputs(crop: Crop, i: &Inputs) -> Result<(Outputs, Uncrop), Calcul
     ))
 }
 
+#[derive(Debug, Error)]
+#[error("frames are {0}x{1} which is larger than the detector input {2}x{3}, use a smaller stream or set oversized frames to downscale")]
+struct FrameTooLargeError(u16, u16, u16, u16);
+
+// Returns true if the frames have to be downscaled.
+fn check_frame_size(
+    oversized: OversizedFrame,
+    i: &Inputs,
+    o: &Outputs,
+) -> Result<bool, FrameTooLargeError> {
+    let (width, height) = (i.input_width.get(), i.input_height.get());
+    let (scaled_width, scaled_height) = (o.scaled_width.get(), o.scaled_height.get());
+    if width <= scaled_width && height <= scaled_height {
+        return Ok(false);
+    }
+    match oversized {
+        OversizedFrame::Downscale => Ok(true),
+        OversizedFrame::Error => Err(FrameTooLargeError(
+            width,
+            height,
+            scaled_width,
+            scaled_height,
+        )),
+    }
+}
+
 #[derive(Debug, Error)]
 enum ProcessFrameError {
     #[error("unsupported pixel format: {0}")]
@@ -649,6 +791,22 @@ enum ParseDetectionsError {
     Zero,
 }
 
+// Zones are checked by the detector before non-max suppression, a
+// box outside the zones could otherwise suppress a box inside them.
+fn zone_filter(zones: Zones, uncrop: Arc<Uncrop>) -> DetectFilter {
+    if zones.include.is_empty() && zones.exclude.is_empty() {
+        return DetectFilter::allow_all();
+    }
+    DetectFilter::new(move |rect| {
+        zones.allows(
+            (uncrop.uncrop_y_fn)(rect.y),
+            (uncrop.uncrop_x_fn)(rect.x),
+            (uncrop.uncrop_y_fn)(rect.y + rect.height.get()),
+            (uncrop.uncrop_x_fn)(rect.x + rect.width.get()),
+        )
+    })
+}
+
 fn parse_detections(
     thresholds: &Thresholds,
     mask: &Mask,
@@ -793,6 +951,8 @@ impl Fetcher for Fetch {
 struct HandlerState {
     logger: ArcLogger,
     monitor_manager: ArcMonitorManager,
+    histograms: ScoreHistograms,
+    transient_detectors: TransientDetectors,
 }
 
 async fn enable_handler(
@@ -815,7 +975,11 @@ async fn enable_handler(
             .into_response();
     };
 
-    if let Err(e) = s.monitor_manager.monitor_set_and_restart(new_config).await {
+    if let Err(e) = s
+        .monitor_manager
+        .monitor_set_and_restart_graceful(new_config)
+        .await
+    {
         return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
     }
 
@@ -847,7 +1011,11 @@ async fn disable_handler(
         None => old_config.clone(),
     };
 
-    if let Err(e) = s.monitor_manager.monitor_set_and_restart(new_config).await {
+    if let Err(e) = s
+        .monitor_manager
+        .monitor_set_and_restart_graceful(new_config)
+        .await
+    {
         return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
     }
 
@@ -861,6 +1029,139 @@ async fn disable_handler(
     StatusCode::OK.into_response()
 }
 
+// Responds with the score histogram of the monitor,
+// a list of bucket counts for each label.
+async fn histogram_handler(
+    State(s): State<HandlerState>,
+    Path(monitor_id): Path<MonitorId>,
+) -> Response {
+    let Some(histogram) = s.histograms.get(&monitor_id) else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("score histogram is not enabled for monitor '{monitor_id}'"),
+        )
+            .into_response();
+    };
+    Json(histogram.counts(UnixNano::now())).into_response()
+}
+
+async fn histogram_reset_handler(
+    State(s): State<HandlerState>,
+    Path(monitor_id): Path<MonitorId>,
+) -> Response {
+    let Some(histogram) = s.histograms.get(&monitor_id) else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("score histogram is not enabled for monitor '{monitor_id}'"),
+        )
+            .into_response();
+    };
+    histogram.reset();
+    StatusCode::OK.into_response()
+}
+
+#[derive(Deserialize)]
+struct DetectQuery {
+    model: PathBuf,
+    format: Option<String>,
+    top_k: Option<usize>,
+    pix_fmt: Option<String>,
+    color_matrix: Option<String>,
+    width: Option<usize>,
+    height: Option<usize>,
+}
+
+const DEFAULT_TOP_K: usize = 5;
+
+// Runs a model that isn't in the config on the PPM image in the body,
+// or on a raw NV12 or I420 frame if `pix_fmt` is set.
+async fn detect_handler(
+    State(s): State<HandlerState>,
+    Query(query): Query<DetectQuery>,
+    body: Bytes,
+) -> Response {
+    let format = match query.format.as_deref().map(str::parse).transpose() {
+        Ok(v) => v.unwrap_or(ModelFormat::Detector),
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let frame = match parse_detect_frame(&query, &body) {
+        Ok(v) => v,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let top_k = query.top_k.unwrap_or(DEFAULT_TOP_K);
+    match s
+        .transient_detectors
+        .detect(&query.model, format, top_k, frame)
+        .await
+    {
+        Ok(output) => Json(model_output_json(&output)).into_response(),
+        Err(TransientDetectError::NewDetector(NewTransientDetectorError::NewDetector(
+            NewDetectorError::ReadModel(_, e),
+        ))) => {
+            // Don't leak file system details.
+            let msg = if e.kind() == std::io::ErrorKind::NotFound {
+                "model not found"
+            } else {
+                "failed to read model"
+            };
+            (StatusCode::BAD_REQUEST, msg).into_response()
+        }
+        Err(
+            e @ (TransientDetectError::InvalidModelPath | TransientDetectError::NewDetector(_)),
+        ) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+fn parse_detect_frame(query: &DetectQuery, body: &[u8]) -> Result<TransientFrame, String> {
+    let pix_fmt: FrameFormat = match query.pix_fmt.as_deref().map(str::parse).transpose() {
+        Ok(v) => v.unwrap_or_default(),
+        Err(e) => return Err(e.to_string()),
+    };
+    let matrix: ColorMatrix = match query.color_matrix.as_deref().map(str::parse).transpose() {
+        Ok(v) => v.unwrap_or_default(),
+        Err(e) => return Err(e.to_string()),
+    };
+    if pix_fmt == FrameFormat::Rgb24 {
+        let image = parse_ppm(body).map_err(|e| format!("parse image: {e}"))?;
+        return Ok(image.into());
+    }
+    let (Some(width), Some(height)) = (query.width, query.height) else {
+        return Err(format!("width and height are required for {pix_fmt}"));
+    };
+    Ok(TransientFrame {
+        buf: body.to_vec(),
+        format: pix_fmt,
+        matrix,
+        width,
+        height,
+    })
+}
+
+fn model_output_json(output: &ModelOutput) -> serde_json::Value {
+    match output {
+        ModelOutput::Detections(detections) => json!({
+            "detections": detections
+                .iter()
+                .map(|d| json!({
+                    "class": d.class,
+                    "score": d.score,
+                    "top": d.top,
+                    "left": d.left,
+                    "bottom": d.bottom,
+                    "right": d.right,
+                }))
+                .collect::<Vec<_>>(),
+        }),
+        ModelOutput::Classes(classes) => json!({
+            "classes": classes
+                .iter()
+                .map(|(class, score)| json!({"class": class, "score": score}))
+                .collect::<Vec<_>>(),
+        }),
+    }
+}
+
 #[allow(clippy::too_many_arguments, clippy::unwrap_used)]
 #[cfg(test)]
 mod tests {
@@ -925,6 +1226,69 @@ mod tests {
         assert_eq!(want, got);
     }
 
+    #[test_case(600, 400, OversizedFrame::Downscale, Some(true); "downscale")]
+    #[test_case(600, 400, OversizedFrame::Error, None; "error")]
+    #[test_case(300, 300, OversizedFrame::Downscale, Some(false); "fits downscale")]
+    #[test_case(300, 300, OversizedFrame::Error, Some(false); "fits error")]
+    fn test_check_frame_size(
+        input_width: u16,
+        input_height: u16,
+        oversized: OversizedFrame,
+        want: Option<bool>,
+    ) {
+        let inputs = Inputs {
+            input_width: NonZeroU16::new(input_width).unwrap(),
+            input_height: NonZeroU16::new(input_height).unwrap(),
+            output_width: NonZeroU16::new(300).unwrap(),
+            output_height: NonZeroU16::new(300).unwrap(),
+        };
+        let crop = Crop {
+            x: CropValue::new_testing(0),
+            y: CropValue::new_testing(0),
+            size: CropSize::new_testing(NonZeroU16::new(100).unwrap()),
+        };
+        let (outputs, _) = calculate_outputs(crop, &inputs).unwrap();
+        let got = check_frame_size(oversized, &inputs, &outputs).ok();
+        assert_eq!(want, got);
+    }
+
+    #[test]
+    fn test_frame_interval() {
+        let mut interval = FrameInterval::new(NonZeroU32::new(3).unwrap());
+        let got: Vec<usize> = (0..8).filter(|_| interval.should_detect()).collect();
+        assert_eq!(vec![0, 3, 6], got);
+
+        let mut interval = FrameInterval::new(NonZeroU32::MIN);
+        assert!((0..3).all(|_| interval.should_detect()));
+    }
+
+    fn detect_query(pix_fmt: Option<&str>, width: Option<usize>) -> DetectQuery {
+        DetectQuery {
+            model: PathBuf::from("model.tflite"),
+            format: None,
+            top_k: None,
+            pix_fmt: pix_fmt.map(ToOwned::to_owned),
+            color_matrix: Some("bt709".to_owned()),
+            width,
+            height: Some(2),
+        }
+    }
+
+    #[test]
+    fn test_parse_detect_frame() {
+        let frame = parse_detect_frame(&detect_query(Some("nv12"), Some(4)), &[0; 12]).unwrap();
+        assert_eq!(FrameFormat::Nv12, frame.format);
+        assert_eq!(ColorMatrix::Bt709, frame.matrix);
+        assert_eq!((4, 2), (frame.width, frame.height));
+
+        let frame = parse_detect_frame(&detect_query(None, None), b"P6 1 1 255\n\0\0\0").unwrap();
+        assert_eq!(FrameFormat::Rgb24, frame.format);
+        assert_eq!(vec![0, 0, 0], frame.buf);
+
+        assert!(parse_detect_frame(&detect_query(Some("i420"), None), &[0; 12]).is_err());
+        assert!(parse_detect_frame(&detect_query(Some("x"), Some(4)), &[0; 12]).is_err());
+    }
+
     fn label(s: &str) -> Label {
         s.to_owned().try_into().unwrap()
     }
@@ -1040,6 +1404,37 @@ mod tests {
             .is_empty());
     }
 
+    #[test]
+    fn test_zone_filter() {
+        // Triangle covering the bottom left half of the frame.
+        let triangle = [(0, 0), (0, 100), (100, 100)]
+            .map(|(x, y)| PointNormalized {
+                x: normalize(x, 100),
+                y: normalize(y, 100),
+            })
+            .to_vec();
+        let zones = Zones {
+            include: Vec::new(),
+            exclude: vec![triangle],
+            overlap: None,
+        };
+        let uncrop = Uncrop {
+            uncrop_x_fn: Box::new(|v| v),
+            uncrop_y_fn: Box::new(|v| v * 2),
+        };
+        let filter = zone_filter(zones, Arc::new(uncrop));
+        let rect = |x, y| RectangleNormalized {
+            x: normalize(x, 100),
+            y: normalize(y, 100),
+            width: NonZeroU32::new(normalize(10, 100)).unwrap(),
+            height: NonZeroU32::new(normalize(10, 100)).unwrap(),
+        };
+
+        // The center 35,25 is uncropped to 35,50 which is inside the triangle.
+        assert!(!filter.allows(&rect(30, 20)));
+        assert!(filter.allows(&rect(30, 5)));
+    }
+
     #[test]
     fn test_crop_size_eroor() {
         // Landscape.
@@ -0,0 +1,546 @@
+use crate::Detection;
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+use thiserror::Error;
+
+// Pixel format of the frames passed to the detector.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrameFormat {
+    // Interleaved RGB, used as is.
+    #[default]
+    Rgb24,
+
+    // Y plane followed by a interleaved UV plane.
+    Nv12,
+
+    // Y plane followed by a U plane and a V plane.
+    I420,
+}
+
+impl FrameFormat {
+    // Size in bytes of a single frame.
+    #[must_use]
+    pub fn frame_size(self, width: usize, height: usize) -> usize {
+        match self {
+            FrameFormat::Rgb24 => width * height * 3,
+            FrameFormat::Nv12 | FrameFormat::I420 => width * height * 3 / 2,
+        }
+    }
+}
+
+impl Display for FrameFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameFormat::Rgb24 => write!(f, "rgb24"),
+            FrameFormat::Nv12 => write!(f, "nv12"),
+            FrameFormat::I420 => write!(f, "i420"),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown frame format '{0}', expected 'rgb24', 'nv12' or 'i420'")]
+pub struct UnknownFrameFormat(String);
+
+impl FromStr for FrameFormat {
+    type Err = UnknownFrameFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rgb24" => Ok(Self::Rgb24),
+            "nv12" => Ok(Self::Nv12),
+            "i420" => Ok(Self::I420),
+            _ => Err(UnknownFrameFormat(s.to_owned())),
+        }
+    }
+}
+
+// YUV to RGB coefficients, limited range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorMatrix {
+    // Standard definition video.
+    #[default]
+    Bt601,
+
+    // High definition video.
+    Bt709,
+}
+
+impl ColorMatrix {
+    // Fixed point coefficients multiplied by 256: cr to red,
+    // cb to green, cr to green and cb to blue.
+    fn coefficients(self) -> (i32, i32, i32, i32) {
+        match self {
+            ColorMatrix::Bt601 => (409, 100, 208, 516),
+            ColorMatrix::Bt709 => (459, 55, 136, 541),
+        }
+    }
+}
+
+impl Display for ColorMatrix {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorMatrix::Bt601 => write!(f, "bt601"),
+            ColorMatrix::Bt709 => write!(f, "bt709"),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown color matrix '{0}', expected 'bt601' or 'bt709'")]
+pub struct UnknownColorMatrix(String);
+
+impl FromStr for ColorMatrix {
+    type Err = UnknownColorMatrix;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bt601" => Ok(Self::Bt601),
+            "bt709" => Ok(Self::Bt709),
+            _ => Err(UnknownColorMatrix(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConvertError {
+    #[error("width and height must be even: {0}x{1}")]
+    OddSize(usize, usize),
+
+    #[error("source size: expected {0} got {1}")]
+    SrcSize(usize, usize),
+
+    #[error("destination size: expected {0} got {1}")]
+    DstSize(usize, usize),
+
+    #[error("can't upscale {0}x{1} to {2}x{3}")]
+    Upscale(usize, usize, usize, usize),
+
+    #[error("can't letterbox {0}x{1} into {2}x{3}")]
+    LetterboxZeroSize(usize, usize, usize, usize),
+}
+
+// Converts a NV12 frame to RGB24.
+pub fn nv12_to_rgb(
+    src: &[u8],
+    width: usize,
+    height: usize,
+    matrix: ColorMatrix,
+    dst: &mut [u8],
+) -> Result<(), ConvertError> {
+    check_sizes(src, FrameFormat::Nv12, width, height, dst)?;
+    let (y_plane, uv_plane) = src.split_at(width * height);
+    yuv_to_rgb(y_plane, width, height, matrix, dst, |x, y| {
+        let i = (y / 2) * width + (x / 2) * 2;
+        (uv_plane[i], uv_plane[i + 1])
+    });
+    Ok(())
+}
+
+// Converts a I420 frame to RGB24.
+pub fn i420_to_rgb(
+    src: &[u8],
+    width: usize,
+    height: usize,
+    matrix: ColorMatrix,
+    dst: &mut [u8],
+) -> Result<(), ConvertError> {
+    check_sizes(src, FrameFormat::I420, width, height, dst)?;
+    let (y_plane, uv_planes) = src.split_at(width * height);
+    let (u_plane, v_plane) = uv_planes.split_at(width * height / 4);
+    yuv_to_rgb(y_plane, width, height, matrix, dst, |x, y| {
+        let i = (y / 2) * (width / 2) + (x / 2);
+        (u_plane[i], v_plane[i])
+    });
+    Ok(())
+}
+
+// Downscales a RGB24 frame using nearest neighbor sampling.
+pub fn downscale_rgb(
+    src: &[u8],
+    src_width: usize,
+    src_height: usize,
+    dst: &mut [u8],
+    dst_width: usize,
+    dst_height: usize,
+) -> Result<(), ConvertError> {
+    use ConvertError::*;
+    if dst_width > src_width || dst_height > src_height {
+        return Err(Upscale(src_width, src_height, dst_width, dst_height));
+    }
+    let src_size = FrameFormat::Rgb24.frame_size(src_width, src_height);
+    if src.len() != src_size {
+        return Err(SrcSize(src_size, src.len()));
+    }
+    let dst_size = FrameFormat::Rgb24.frame_size(dst_width, dst_height);
+    if dst.len() != dst_size {
+        return Err(DstSize(dst_size, dst.len()));
+    }
+    for row in 0..dst_height {
+        let src_row = row * src_height / dst_height;
+        for col in 0..dst_width {
+            let src_col = col * src_width / dst_width;
+            let src_pos = (src_row * src_width + src_col) * 3;
+            let dst_pos = (row * dst_width + col) * 3;
+            dst[dst_pos..dst_pos + 3].copy_from_slice(&src[src_pos..src_pos + 3]);
+        }
+    }
+    Ok(())
+}
+
+// Gray used for the padding of letterboxed frames.
+const LETTERBOX_FILL: u8 = 128;
+
+// Placement of a source frame inside a letterboxed model input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Letterbox {
+    // Model input size.
+    pub dst_width: usize,
+    pub dst_height: usize,
+
+    // Position and size of the scaled frame, the rest is padding.
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Letterbox {
+    // Fits the source frame inside the model input while keeping the aspect ratio.
+    pub fn new(
+        src_width: usize,
+        src_height: usize,
+        dst_width: usize,
+        dst_height: usize,
+    ) -> Result<Self, ConvertError> {
+        if src_width == 0 || src_height == 0 || dst_width == 0 || dst_height == 0 {
+            return Err(ConvertError::LetterboxZeroSize(
+                src_width, src_height, dst_width, dst_height,
+            ));
+        }
+        let (width, height) = if src_width * dst_height >= src_height * dst_width {
+            (dst_width, (src_height * dst_width / src_width).max(1))
+        } else {
+            ((src_width * dst_height / src_height).max(1), dst_height)
+        };
+        Ok(Self {
+            dst_width,
+            dst_height,
+            x: (dst_width - width) / 2,
+            y: (dst_height - height) / 2,
+            width,
+            height,
+        })
+    }
+
+    // Source pixels per model input pixel.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss, clippy::as_conversions)]
+    pub fn scale(&self, src_width: usize) -> f32 {
+        src_width as f32 / self.width as f32
+    }
+
+    // Maps a normalized point in the model input to a normalized point
+    // in the source frame. Points in the padding are clamped to the edge.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss, clippy::as_conversions)]
+    pub fn to_source(&self, x: f32, y: f32) -> (f32, f32) {
+        let x = (x * self.dst_width as f32 - self.x as f32) / self.width as f32;
+        let y = (y * self.dst_height as f32 - self.y as f32) / self.height as f32;
+        (x.clamp(0.0, 1.0), y.clamp(0.0, 1.0))
+    }
+
+    // Maps a detection made on the letterboxed frame back to the source frame.
+    #[must_use]
+    pub fn map_detection(&self, d: &Detection) -> Detection {
+        let (left, top) = self.to_source(d.left, d.top);
+        let (right, bottom) = self.to_source(d.right, d.bottom);
+        Detection {
+            score: d.score,
+            class: d.class,
+            top,
+            left,
+            bottom,
+            right,
+        }
+    }
+}
+
+// Scales a RGB24 frame to fit the destination using nearest neighbor sampling,
+// the aspect ratio is kept and the remaining area is filled with gray.
+pub fn letterbox_rgb(
+    src: &[u8],
+    src_width: usize,
+    src_height: usize,
+    dst: &mut [u8],
+    dst_width: usize,
+    dst_height: usize,
+) -> Result<Letterbox, ConvertError> {
+    use ConvertError::*;
+    let src_size = FrameFormat::Rgb24.frame_size(src_width, src_height);
+    if src.len() != src_size || src_size == 0 {
+        return Err(SrcSize(src_size, src.len()));
+    }
+    let dst_size = FrameFormat::Rgb24.frame_size(dst_width, dst_height);
+    if dst.len() != dst_size || dst_size == 0 {
+        return Err(DstSize(dst_size, dst.len()));
+    }
+
+    let letterbox = Letterbox::new(src_width, src_height, dst_width, dst_height)?;
+    dst.fill(LETTERBOX_FILL);
+    for row in 0..letterbox.height {
+        let src_row = row * src_height / letterbox.height;
+        for col in 0..letterbox.width {
+            let src_col = col * src_width / letterbox.width;
+            let src_pos = (src_row * src_width + src_col) * 3;
+            let dst_pos = ((letterbox.y + row) * dst_width + letterbox.x + col) * 3;
+            dst[dst_pos..dst_pos + 3].copy_from_slice(&src[src_pos..src_pos + 3]);
+        }
+    }
+    Ok(letterbox)
+}
+
+fn check_sizes(
+    src: &[u8],
+    format: FrameFormat,
+    width: usize,
+    height: usize,
+    dst: &[u8],
+) -> Result<(), ConvertError> {
+    use ConvertError::*;
+    if width % 2 != 0 || height % 2 != 0 {
+        return Err(OddSize(width, height));
+    }
+    let src_size = format.frame_size(width, height);
+    if src.len() != src_size {
+        return Err(SrcSize(src_size, src.len()));
+    }
+    let dst_size = FrameFormat::Rgb24.frame_size(width, height);
+    if dst.len() != dst_size {
+        return Err(DstSize(dst_size, dst.len()));
+    }
+    Ok(())
+}
+
+// Limited range, fixed point.
+fn yuv_to_rgb<F>(
+    y_plane: &[u8],
+    width: usize,
+    height: usize,
+    matrix: ColorMatrix,
+    dst: &mut [u8],
+    uv: F,
+) where
+    F: Fn(usize, usize) -> (u8, u8),
+{
+    let (cr_r, cb_g, cr_g, cb_b) = matrix.coefficients();
+    for row in 0..height {
+        for col in 0..width {
+            let (u, v) = uv(col, row);
+            let luma = i32::from(y_plane[row * width + col]) - 16;
+            let cb = i32::from(u) - 128;
+            let cr = i32::from(v) - 128;
+
+            let pos = (row * width + col) * 3;
+            dst[pos] = clamp((298 * luma + cr_r * cr + 128) >> 8);
+            dst[pos + 1] = clamp((298 * luma - cb_g * cb - cr_g * cr + 128) >> 8);
+            dst[pos + 2] = clamp((298 * luma + cb_b * cb + 128) >> 8);
+        }
+    }
+}
+
+fn clamp(v: i32) -> u8 {
+    u8::try_from(v.clamp(0, 255)).expect("value to be clamped")
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4x2 frame, the left 2x2 block is red and the right block is gray.
+    #[rustfmt::skip]
+    const WANT: [u8; 24] = [
+        255, 0, 0,  255, 0, 0,  0, 0, 0,        255, 255, 255,
+        255, 0, 0,  255, 0, 0,  128, 128, 128,  128, 128, 128,
+    ];
+
+    #[test]
+    fn test_nv12_to_rgb() {
+        #[rustfmt::skip]
+        let src = [
+            81, 81, 16, 235, // Y.
+            81, 81, 126, 126,
+            90, 240, 128, 128, // UV.
+        ];
+        let mut dst = [0; 24];
+        nv12_to_rgb(&src, 4, 2, ColorMatrix::Bt601, &mut dst).unwrap();
+        assert_eq!(WANT, dst);
+    }
+
+    #[test]
+    fn test_i420_to_rgb() {
+        #[rustfmt::skip]
+        let src = [
+            81, 81, 16, 235, // Y.
+            81, 81, 126, 126,
+            90, 128, // U.
+            240, 128, // V.
+        ];
+        let mut dst = [0; 24];
+        i420_to_rgb(&src, 4, 2, ColorMatrix::Bt601, &mut dst).unwrap();
+        assert_eq!(WANT, dst);
+    }
+
+    #[test]
+    fn test_nv12_to_rgb_color_matrix() {
+        // 2x2 block with a single chroma sample.
+        let src = [16, 235, 126, 81, 100, 150];
+
+        // Floating point reference values.
+        #[rustfmt::skip]
+        let cases: [(ColorMatrix, [u8; 12]); 2] = [
+            (ColorMatrix::Bt601, [
+                35, 0, 0,  255, 248, 199,
+                163, 121, 72,  111, 69, 19,
+            ]),
+            (ColorMatrix::Bt709, [
+                39, 0, 0,  255, 249, 196,
+                168, 122, 69,  115, 70, 17,
+            ]),
+        ];
+        for (matrix, want) in cases {
+            let mut got = [0; 12];
+            nv12_to_rgb(&src, 2, 2, matrix, &mut got).unwrap();
+            for (want, got) in want.iter().zip(got) {
+                assert!(
+                    want.abs_diff(got) <= 1,
+                    "{matrix} want {want:?} got {got:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_color_matrix() {
+        assert_eq!(ColorMatrix::Bt709, "BT709".parse().unwrap());
+        assert!("bt2020".parse::<ColorMatrix>().is_err());
+    }
+
+    #[test]
+    fn test_downscale_rgb() {
+        // 4x2 to 2x1, every other pixel of the first row is kept.
+        let mut dst = [0; 6];
+        downscale_rgb(&WANT, 4, 2, &mut dst, 2, 1).unwrap();
+        assert_eq!([255, 0, 0, 0, 0, 0], dst);
+
+        assert!(matches!(
+            downscale_rgb(&WANT, 4, 2, &mut [0; 30], 5, 2),
+            Err(ConvertError::Upscale(4, 2, 5, 2))
+        ));
+    }
+
+    fn detection(top: f32, left: f32, bottom: f32, right: f32) -> Detection {
+        Detection {
+            score: 0.5,
+            class: 1,
+            top,
+            left,
+            bottom,
+            right,
+        }
+    }
+
+    fn assert_rect_eq(want: &Detection, got: &Detection) {
+        let want = [want.top, want.left, want.bottom, want.right];
+        let got = [got.top, got.left, got.bottom, got.right];
+        for (want, got) in want.iter().zip(got) {
+            assert!((want - got).abs() < 0.01, "want {want:?} got {got:?}");
+        }
+    }
+
+    #[test]
+    fn test_letterbox_rgb_wide() {
+        // 8x2 red frame into a 4x4 model input.
+        let src = [255, 0, 0].repeat(16);
+        let mut dst = [0; 48];
+        let letterbox = letterbox_rgb(&src, 8, 2, &mut dst, 4, 4).unwrap();
+        assert_eq!(
+            Letterbox {
+                dst_width: 4,
+                dst_height: 4,
+                x: 0,
+                y: 1,
+                width: 4,
+                height: 1,
+            },
+            letterbox
+        );
+        assert!((letterbox.scale(8) - 2.0).abs() < f32::EPSILON);
+
+        let gray = [128_u8].repeat(12);
+        let red = [255_u8, 0, 0].repeat(4);
+        assert_eq!([gray.clone(), red, gray.clone(), gray].concat(), dst);
+
+        // Right half of the frame.
+        let got = letterbox.map_detection(&detection(0.25, 0.5, 0.5, 1.0));
+        assert_rect_eq(&detection(0.0, 0.5, 1.0, 1.0), &got);
+    }
+
+    #[test]
+    fn test_letterbox_rgb_tall() {
+        // 2x8 frame into a 4x4 model input.
+        let src = [255, 0, 0].repeat(16);
+        let mut dst = [0; 48];
+        let letterbox = letterbox_rgb(&src, 2, 8, &mut dst, 4, 4).unwrap();
+        assert_eq!(
+            Letterbox {
+                dst_width: 4,
+                dst_height: 4,
+                x: 1,
+                y: 0,
+                width: 1,
+                height: 4,
+            },
+            letterbox
+        );
+        for row in 0..4 {
+            let pixel = |col: usize| &dst[(row * 4 + col) * 3..(row * 4 + col) * 3 + 3];
+            assert_eq!([128_u8, 128, 128], pixel(0));
+            assert_eq!([255_u8, 0, 0], pixel(1));
+            assert_eq!([128_u8, 128, 128], pixel(2));
+        }
+
+        // Bottom quarter of the frame, the box overlaps the padding.
+        let got = letterbox.map_detection(&detection(0.75, 0.0, 1.0, 0.5));
+        assert_rect_eq(&detection(0.75, 0.0, 1.0, 1.0), &got);
+    }
+
+    #[test]
+    fn test_letterbox_zero_size() {
+        assert!(matches!(
+            Letterbox::new(0, 2, 4, 4),
+            Err(ConvertError::LetterboxZeroSize(0, 2, 4, 4))
+        ));
+        assert!(matches!(
+            Letterbox::new(2, 2, 4, 0),
+            Err(ConvertError::LetterboxZeroSize(2, 2, 4, 0))
+        ));
+        assert!(Letterbox::new(1, 1, 1, 1).is_ok());
+    }
+
+    #[test]
+    fn test_convert_invalid_size() {
+        let mut dst = [0; 24];
+        assert!(matches!(
+            nv12_to_rgb(&[0; 11], 4, 2, ColorMatrix::Bt601, &mut dst),
+            Err(ConvertError::SrcSize(12, 11))
+        ));
+        assert!(matches!(
+            i420_to_rgb(&[0; 12], 3, 2, ColorMatrix::Bt601, &mut dst),
+            Err(ConvertError::OddSize(3, 2))
+        ));
+    }
+}
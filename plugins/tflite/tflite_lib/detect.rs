@@ -0,0 +1,438 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+// Runs a detection or classification model over images outside of the
+// monitor loop and prints the results as newline delimited JSON, one line
+// per image. Lines are written as soon as each image is done.
+
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    io::Write,
+    num::ParseIntError,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+use tflite_lib::{
+    new_transient_detector, parse_ppm, ColorMatrix, FrameFormat, ModelFormat, ModelOutput,
+    ModelRegistry, NewTransientDetectorError,
+};
+use thiserror::Error;
+
+fn main() -> ExitCode {
+    let mut pargs = pico_args::Arguments::from_env();
+
+    if pargs.contains(["-h", "--help"]) {
+        print!("{HELP}");
+        return ExitCode::SUCCESS;
+    }
+    let verbose = pargs.contains(["-v", "--verbose"]);
+    let labels_path: Option<PathBuf> = match pargs.opt_value_from_str("--labels") {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("invalid args: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let format: ModelFormat = match pargs.opt_value_from_str("--format") {
+        Ok(v) => v.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("invalid args: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let top_k: usize = match pargs.opt_value_from_str("--top-k") {
+        Ok(v) => v.unwrap_or(DEFAULT_TOP_K),
+        Err(e) => {
+            eprintln!("invalid args: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let width: Option<usize> = match pargs.opt_value_from_str("--width") {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("invalid args: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let height: Option<usize> = match pargs.opt_value_from_str("--height") {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("invalid args: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let input_size = match (width, height) {
+        (Some(width), Some(height)) => Some((width, height)),
+        (None, None) => None,
+        _ => {
+            eprintln!("invalid args: --width and --height must be set together");
+            return ExitCode::FAILURE;
+        }
+    };
+    let Ok(model_path) = pargs.free_from_str::<PathBuf>() else {
+        eprintln!("missing model");
+        return ExitCode::FAILURE;
+    };
+    let Ok(path) = pargs.free_from_str::<PathBuf>() else {
+        eprintln!("missing path");
+        return ExitCode::FAILURE;
+    };
+
+    let labels = match labels_path {
+        Some(labels_path) => match read_labels(&labels_path) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("error: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => HashMap::new(),
+    };
+
+    let opts = DetectOptions {
+        format,
+        top_k,
+        input_size,
+        verbose,
+    };
+    let mut out = std::io::stdout().lock();
+    match detect(&mut out, &model_path, &path, &labels, &opts) {
+        Ok(0) => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+const DEFAULT_TOP_K: usize = 5;
+
+#[derive(Debug, Error)]
+enum DetectCmdError {
+    #[error("new detector: {0}")]
+    NewDetector(#[from] NewTransientDetectorError),
+
+    #[error("list images: {0}")]
+    ListImages(std::io::Error),
+
+    #[error("write output: {0}")]
+    Write(std::io::Error),
+}
+
+struct DetectOptions {
+    format: ModelFormat,
+    top_k: usize,
+    // Width and height of non-square inputs.
+    input_size: Option<(usize, usize)>,
+    verbose: bool,
+}
+
+// Writes a line to `out` for each image and
+// returns the number of images that failed.
+fn detect<W: Write>(
+    out: &mut W,
+    model_path: &Path,
+    path: &Path,
+    labels: &Labels,
+    opts: &DetectOptions,
+) -> Result<usize, DetectCmdError> {
+    use DetectCmdError::*;
+    let mut detector = new_transient_detector(
+        &ModelRegistry::new(),
+        model_path,
+        opts.format,
+        opts.input_size,
+    )?;
+    if opts.verbose {
+        let size = detector.input_tensor_size();
+        let (width, height) = opts.input_size.unwrap_or_else(|| {
+            let side = detector.input_side().unwrap_or_default();
+            (side, side)
+        });
+        eprintln!("input tensor: [1, {height}, {width}, 3] {size} bytes");
+    }
+
+    let images = list_images(path).map_err(ListImages)?;
+    let mut failed = 0;
+    for image_path in images {
+        let result = std::fs::read(&image_path)
+            .map_err(|e| format!("read image: {e}"))
+            .and_then(|raw| parse_ppm(&raw).map_err(|e| e.to_string()))
+            .and_then(|image| {
+                detector
+                    .run_frame(
+                        &image.rgb,
+                        FrameFormat::Rgb24,
+                        ColorMatrix::default(),
+                        image.width,
+                        image.height,
+                        opts.top_k,
+                    )
+                    .map_err(|e| e.to_string())
+            });
+        if opts.verbose {
+            if let Some([boxes, classes, scores, count]) = detector.output_tensor_sizes() {
+                eprintln!(
+                    "output tensors: boxes={boxes} classes={classes} scores={scores} count={count} bytes"
+                );
+            }
+        }
+        if result.is_err() {
+            failed += 1;
+        }
+        write_result(out, &image_path, result, labels).map_err(Write)?;
+    }
+    Ok(failed)
+}
+
+// Returns the `.ppm` files in the directory sorted
+// by name, or the path itself if it's a file.
+fn list_images(path: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_owned()]);
+    }
+    let mut images = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|v| v == "ppm") {
+            images.push(path);
+        }
+    }
+    images.sort();
+    Ok(images)
+}
+
+type Labels = HashMap<u16, String>;
+
+fn write_result<W: Write>(
+    out: &mut W,
+    path: &Path,
+    result: Result<ModelOutput, String>,
+    labels: &Labels,
+) -> Result<(), std::io::Error> {
+    let line = match result {
+        Ok(ModelOutput::Detections(detections)) => json!({
+            "path": path,
+            "detections": detections
+                .iter()
+                .map(|d| json!({
+                    "label": labels.get(&u16::from(d.class)),
+                    "class": d.class,
+                    "score": d.score,
+                    "top": d.top,
+                    "left": d.left,
+                    "bottom": d.bottom,
+                    "right": d.right,
+                }))
+                .collect::<Vec<_>>(),
+        }),
+        Ok(ModelOutput::Classes(classes)) => json!({
+            "path": path,
+            "classes": classes
+                .iter()
+                .map(|(class, score)| json!({
+                    "label": labels.get(class),
+                    "class": class,
+                    "score": score,
+                }))
+                .collect::<Vec<_>>(),
+        }),
+        Err(e) => json!({
+            "path": path,
+            "error": e,
+        }),
+    };
+    serde_json::to_writer(&mut *out, &line)?;
+    out.write_all(b"\n")?;
+    out.flush()
+}
+
+#[derive(Debug, Error)]
+enum ReadLabelsError {
+    #[error("read labels: {0}")]
+    Read(std::io::Error),
+
+    #[error("parse labels: line={0}: split key and label")]
+    SplitLine(usize),
+
+    #[error("parse labels: line={0}: parse key: {1}")]
+    ParseKey(usize, ParseIntError),
+}
+
+fn read_labels(path: &Path) -> Result<Labels, ReadLabelsError> {
+    let raw = std::fs::read_to_string(path).map_err(ReadLabelsError::Read)?;
+    parse_labels(&raw)
+}
+
+// Same format as the label maps used by the plugin, `<class> <label>` on each line.
+fn parse_labels(raw: &str) -> Result<Labels, ReadLabelsError> {
+    use ReadLabelsError::*;
+    let mut labels = HashMap::new();
+    for (i, line) in raw.trim().lines().enumerate() {
+        let (key, label) = line.split_once(' ').ok_or(SplitLine(i))?;
+        let key = key.parse().map_err(|e| ParseKey(i, e))?;
+        labels.insert(key, label.trim().to_owned());
+    }
+    Ok(labels)
+}
+
+const HELP: &str = "\
+Run a detection or classification model over images and print
+the results as newline delimited JSON, one line per image
+
+Usage: tflite_detect [OPTIONS] <MODEL> <PATH>
+
+Arguments:
+  <MODEL>  Path to the tflite model
+  <PATH>   Binary PPM image or directory of .ppm images
+
+Options:
+      --format <FORMAT>  Model format, 'detector' or 'classifier' [default: detector]
+      --top-k <N>        Number of classes printed for classifiers [default: 5]
+      --labels <FILE>    Label map with a '<class> <label>' pair on each line
+      --width <N>        Input width of models with a non-square input
+      --height <N>       Input height of models with a non-square input
+  -v, --verbose          Print the input and output tensor sizes to stderr
+  -h, --help             Print help
+";
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+    use tflite_lib::Detection;
+
+    // 2x2 model with four float32 outputs, each output is the input
+    // casted to float, so the input bytes become the output tensors.
+    const CAST_MODEL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/cast.tflite");
+
+    fn parse_lines(out: &[u8]) -> Vec<serde_json::Value> {
+        std::str::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_detect() {
+        let temp_dir = tempdir().unwrap();
+        let image = temp_dir.path().join("a.ppm");
+        // Count 1, score 1, class 1 and the box 1,0,1,1.
+        let mut ppm = b"P6\n2 2\n255\n".to_vec();
+        ppm.extend_from_slice(&[1, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        std::fs::write(&image, ppm).unwrap();
+        std::fs::write(temp_dir.path().join("b.ppm"), b"x").unwrap();
+
+        let labels = parse_labels("1 person").unwrap();
+        let opts = DetectOptions {
+            format: ModelFormat::Detector,
+            top_k: DEFAULT_TOP_K,
+            input_size: None,
+            verbose: false,
+        };
+        let mut out = Vec::new();
+        let failed = detect(
+            &mut out,
+            Path::new(CAST_MODEL),
+            temp_dir.path(),
+            &labels,
+            &opts,
+        )
+        .unwrap();
+        assert_eq!(1, failed);
+
+        let want = vec![
+            json!({
+                "path": image,
+                "detections": [{
+                    "label": "person",
+                    "class": 1,
+                    "score": 1.0,
+                    "top": 1.0,
+                    "left": 0.0,
+                    "bottom": 1.0,
+                    "right": 1.0,
+                }],
+            }),
+            json!({
+                "path": temp_dir.path().join("b.ppm"),
+                "error": parse_ppm(b"x").unwrap_err().to_string(),
+            }),
+        ];
+        assert_eq!(want, parse_lines(&out));
+    }
+
+    #[test]
+    fn test_write_result() {
+        let labels = parse_labels("0 person\n1 bicycle").unwrap();
+        let detection = |class, score| Detection {
+            score,
+            class,
+            top: 0.25,
+            left: 0.5,
+            bottom: 0.75,
+            right: 1.0,
+        };
+
+        // The same image through a detector and a classifier.
+        let mut out = Vec::new();
+        write_result(
+            &mut out,
+            Path::new("a.ppm"),
+            Ok(ModelOutput::Detections(vec![
+                detection(0, 0.5),
+                detection(2, 0.25),
+            ])),
+            &labels,
+        )
+        .unwrap();
+        write_result(
+            &mut out,
+            Path::new("a.ppm"),
+            Ok(ModelOutput::Classes(vec![(1, 0.75), (3, 0.125)])),
+            &labels,
+        )
+        .unwrap();
+        write_result(&mut out, Path::new("b.ppm"), Err("x".to_owned()), &labels).unwrap();
+
+        let lines = parse_lines(&out);
+        let want = vec![
+            json!({
+                "path": "a.ppm",
+                "detections": [
+                    {
+                        "label": "person",
+                        "class": 0,
+                        "score": 0.5,
+                        "top": 0.25,
+                        "left": 0.5,
+                        "bottom": 0.75,
+                        "right": 1.0,
+                    },
+                    {
+                        "label": null,
+                        "class": 2,
+                        "score": 0.25,
+                        "top": 0.25,
+                        "left": 0.5,
+                        "bottom": 0.75,
+                        "right": 1.0,
+                    },
+                ],
+            }),
+            json!({
+                "path": "a.ppm",
+                "classes": [
+                    {"label": "bicycle", "class": 1, "score": 0.75},
+                    {"label": null, "class": 3, "score": 0.125},
+                ],
+            }),
+            json!({"path": "b.ppm", "error": "x"}),
+        ];
+        assert_eq!(want, lines);
+    }
+}
@@ -1,4 +1,20 @@
+mod convert;
+mod model;
+mod nms;
+mod ppm;
+mod tracker;
+
+pub use convert::{
+    downscale_rgb, i420_to_rgb, letterbox_rgb, nv12_to_rgb, ColorMatrix, ConvertError, FrameFormat,
+    Letterbox, UnknownColorMatrix, UnknownFrameFormat,
+};
+pub use model::ModelRegistry;
+pub use nms::{non_max_suppression, NmsMode};
+pub use ppm::{parse_ppm, Image, ParsePpmError};
+pub use tracker::{TrackedDetection, Tracker};
+
 use std::{
+    borrow::Cow,
     ffi::{c_uint, CStr, CString, NulError},
     fmt::{Debug, Display, Formatter},
     os::raw::c_int,
@@ -6,11 +22,13 @@ use std::{
     process::{Command, Stdio},
     slice::{self, from_raw_parts},
     str::FromStr,
-    time::Duration,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use tflite_sys::{
-    c_detector_allocate, c_detector_detect, c_detector_free, c_detector_load_model, c_free_devices,
-    c_list_devices, c_poke_devices, c_probe_device, CDetector,
+    c_detector_allocate, c_detector_classifier_output, c_detector_classify, c_detector_detect,
+    c_detector_free, c_detector_load_model, c_free_devices, c_list_devices, c_poke_devices,
+    c_probe_device, CDetector,
 };
 use thiserror::Error;
 
@@ -19,8 +37,8 @@ pub enum NewDetectorError {
     #[error("detector is null")]
     DetectorNull,
 
-    #[error("path to string: {0}")]
-    PathToString(PathBuf),
+    #[error("read model {0:?}: {1}")]
+    ReadModel(PathBuf, std::io::Error),
 
     #[error("convert to CString: {0}")]
     ConvertToCString(#[from] NulError),
@@ -34,12 +52,18 @@ pub enum NewDetectorError {
     #[error("input tensor count")]
     InputTensorCount,
 
-    #[error("input tensor type")]
+    #[error("input tensor type, only uint8 is supported")]
     InputTensorType,
 
     #[error("output tensor count")]
     OutputTensorCount,
 
+    #[error("output tensor dimensions")]
+    OutputTensorDims,
+
+    #[error("output tensor type")]
+    OutputTensorType,
+
     #[error("create edgetpu delegate")]
     EdgetpuDelegateCreate,
 
@@ -59,12 +83,17 @@ const ERROR_INPUT_TENSOR_COUNT: c_int = 10002;
 const ERROR_INPUT_TENSOR_TYPE: c_int = 10003;
 const ERROR_OUTPUT_TENSOR_COUNT: c_int = 10004;
 const ERROR_EDGETPU_DELEGATE_CREATE: c_int = 10005;
+const ERROR_OUTPUT_TENSOR_DIMS: c_int = 10006;
+const ERROR_OUTPUT_TENSOR_TYPE_UNSUPPORTED: c_int = 10007;
 
 #[derive(Debug, Error)]
 pub enum DetectError {
     #[error("buffer size: {0}vs{1}")]
     BufferSize(usize, usize),
 
+    #[error("frame is larger than the model input, got {got} bytes expected {expected} bytes, the frame must be downscaled first")]
+    FrameTooLarge { got: usize, expected: usize },
+
     #[error("output tensor type")]
     OutputTensorType,
 
@@ -73,27 +102,145 @@ pub enum DetectError {
 
     #[error("parse output tensors: {0:?} {1}")]
     ParseOutputTensors([usize; 4], ParseOutputTensorsError),
+
+    #[error("convert frame: {0}")]
+    Convert(#[from] ConvertError),
+
+    #[error("wrong model format: {0:?}")]
+    WrongModelFormat(ModelFormat),
+
+    #[error("parse classifier output: {0}")]
+    ParseClassifierOutput(#[from] ParseClassifierOutputError),
 }
 
 const ERROR_OUTPUT_TENSOR_TYPE: i32 = 20000;
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ModelFormat {
+    // Four output tensors: boxes, classes, scores and count.
+    #[default]
+    Detector,
+
+    // A single output tensor with one score per class for the whole frame.
+    Classifier,
+}
+
+impl ModelFormat {
+    fn as_uint(self) -> c_uint {
+        match self {
+            ModelFormat::Detector => 0,
+            ModelFormat::Classifier => 1,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("unknown model format '{0}', expected 'detector' or 'classifier'")]
+pub struct UnknownModelFormat(String);
+
+impl FromStr for ModelFormat {
+    type Err = UnknownModelFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "detector" => Ok(Self::Detector),
+            "classifier" => Ok(Self::Classifier),
+            _ => Err(UnknownModelFormat(s.to_owned())),
+        }
+    }
+}
+
+// Output of `run_frame`, depends on the model format.
+#[derive(Clone)]
+pub enum ModelOutput {
+    Detections(Vec<Detection>),
+
+    // Class and score pairs, highest score first.
+    Classes(Vec<(u16, f32)>),
+}
+
+// Channel order of the model input. Frames are RGB, models
+// trained with OpenCV usually expect BGR instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChannelOrder {
+    #[default]
+    Rgb,
+    Bgr,
+}
+
+// Returns the RGB buffer in the channel order of the model.
+#[must_use]
+pub fn apply_channel_order(buf: &[u8], order: ChannelOrder) -> Cow<'_, [u8]> {
+    match order {
+        ChannelOrder::Rgb => Cow::Borrowed(buf),
+        ChannelOrder::Bgr => {
+            let mut bgr = buf.to_owned();
+            for pixel in bgr.chunks_exact_mut(3) {
+                pixel.swap(0, 2);
+            }
+            Cow::Owned(bgr)
+        }
+    }
+}
+
+// What `detect_frame` does with frames larger than the model input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OversizedFrame {
+    // Return `FrameTooLarge`.
+    #[default]
+    Error,
+
+    // Downscale to the model input size.
+    Downscale {
+        width: usize,
+        height: usize,
+    },
+}
+
+type ConvertFunc = fn(&[u8], usize, usize, ColorMatrix, &mut [u8]) -> Result<(), ConvertError>;
+
+// Called with a warning the first time a frame is downscaled.
+pub type WarnFunc = Box<dyn Fn(&str) + Send>;
+
+#[allow(clippy::struct_field_names)]
 pub struct Detector {
     c_detector: *mut CDetector,
     input_tensor_size: usize,
+    format: ModelFormat,
+    cpu_accel: bool,
+    channel_order: ChannelOrder,
+    oversized: OversizedFrame,
+    warn: Option<WarnFunc>,
+
+    // Byte sizes of the output tensors from the last detection.
+    output_tensor_sizes: Option<[usize; 4]>,
+
+    // Output type of classifier models.
+    classifier_output: Option<ClassifierOutput>,
+
+    // Must outlive the interpreter.
+    _model: Arc<[u8]>,
 }
 
 unsafe impl Send for Detector {}
 
 impl Detector {
+    // `cpu_accel` enables the XNNPACK delegate when no edgetpu is used.
+    // The detector falls back to the default kernels if the delegate
+    // cannot be created, check `cpu_accel()` to see if it was enabled.
+    // Input buffers are swapped to `channel_order` before inference.
     pub fn new(
+        models: &ModelRegistry,
         model_path: &Path,
         edgetpu: Option<&EdgetpuDevice>,
+        format: ModelFormat,
+        cpu_accel: bool,
+        channel_order: ChannelOrder,
     ) -> Result<Self, NewDetectorError> {
         use NewDetectorError::*;
-        let model_path = model_path
-            .to_str()
-            .ok_or_else(|| NewDetectorError::PathToString(model_path.to_path_buf()))?;
-        let model_path = CString::new(model_path)?;
+        let model = models
+            .load(model_path)
+            .map_err(|e| ReadModel(model_path.to_owned(), e))?;
 
         unsafe {
             let c_detector = c_detector_allocate();
@@ -102,6 +249,7 @@ impl Detector {
             }
 
             let mut input_tensor_size = 0;
+            let mut cpu_accel_enabled = 0;
             let res = match edgetpu {
                 Some(device) => {
                     if let Err(e) = probe_device(&device.path) {
@@ -110,18 +258,26 @@ impl Detector {
                     let path = CString::new(device.path.clone())?;
                     c_detector_load_model(
                         c_detector,
-                        model_path.as_ptr(),
+                        model.as_ptr(),
+                        model.len(),
                         &mut input_tensor_size,
                         path.as_ptr(),
                         device.typ.as_uint(),
+                        format.as_uint(),
+                        0,
+                        &mut cpu_accel_enabled,
                     )
                 }
                 None => c_detector_load_model(
                     c_detector,
-                    model_path.as_ptr(),
+                    model.as_ptr(),
+                    model.len(),
                     &mut input_tensor_size,
                     std::ptr::null(),
                     0,
+                    format.as_uint(),
+                    c_int::from(cpu_accel),
+                    &mut cpu_accel_enabled,
                 ),
             };
             if res != 0 {
@@ -132,23 +288,71 @@ impl Detector {
                     ERROR_INPUT_TENSOR_TYPE => InputTensorType,
                     ERROR_OUTPUT_TENSOR_COUNT => OutputTensorCount,
                     ERROR_EDGETPU_DELEGATE_CREATE => EdgetpuDelegateCreate,
+                    ERROR_OUTPUT_TENSOR_DIMS => OutputTensorDims,
+                    ERROR_OUTPUT_TENSOR_TYPE_UNSUPPORTED => OutputTensorType,
                     _ => LoadModel(res),
                 });
             }
 
+            let classifier_output = match format {
+                ModelFormat::Detector => None,
+                ModelFormat::Classifier => {
+                    let mut typ = 0;
+                    let mut scale = 0.0;
+                    let mut zero_point = 0;
+                    c_detector_classifier_output(c_detector, &mut typ, &mut scale, &mut zero_point);
+                    Some(ClassifierOutput::new(typ, scale, zero_point).ok_or(OutputTensorType)?)
+                }
+            };
+
             Ok(Self {
                 c_detector,
                 input_tensor_size,
+                format,
+                cpu_accel: cpu_accel_enabled != 0,
+                channel_order,
+                oversized: OversizedFrame::default(),
+                warn: None,
+                output_tensor_sizes: None,
+                classifier_output,
+                _model: model,
             })
         }
     }
 
+    // Returns true if the XNNPACK delegate is in use.
+    #[must_use]
+    pub fn cpu_accel(&self) -> bool {
+        self.cpu_accel
+    }
+
+    // Size of the input tensor in bytes.
+    #[must_use]
+    pub fn input_tensor_size(&self) -> usize {
+        self.input_tensor_size
+    }
+
+    // Width and height of the input tensor if it's a square RGB image.
+    #[must_use]
+    pub fn input_side(&self) -> Option<usize> {
+        input_side(self.input_tensor_size)
+    }
+
+    // Byte sizes of the boxes, classes, scores and count output
+    // tensors. None until the first detection has run.
+    #[must_use]
+    pub fn output_tensor_sizes(&self) -> Option<[usize; 4]> {
+        self.output_tensor_sizes
+    }
+
     pub fn detect(&mut self, buf: &[u8]) -> Result<Vec<Detection>, DetectError> {
         use DetectError::*;
-        assert_eq!(self.input_tensor_size, buf.len());
-        if self.input_tensor_size != buf.len() {
-            return Err(BufferSize(self.input_tensor_size, buf.len()));
+        if self.format != ModelFormat::Detector {
+            return Err(WrongModelFormat(self.format));
         }
+        check_buffer_size(self.input_tensor_size, buf.len())?;
+        // The input tensor is uint8, pixels are copied without quantization.
+        let buf = apply_channel_order(buf, self.channel_order);
         unsafe {
             let t0_data: *mut *mut u8 = &mut std::ptr::null_mut();
             let t1_data: *mut *mut u8 = &mut std::ptr::null_mut();
@@ -179,6 +383,8 @@ impl Detector {
                 });
             }
 
+            self.output_tensor_sizes = Some([t0_size, t1_size, t2_size, t3_size]);
+
             let t0 = from_raw_parts(*t0_data, t0_size);
             let t1 = from_raw_parts(*t1_data, t1_size);
             let t2 = from_raw_parts(*t2_data, t2_size);
@@ -188,6 +394,217 @@ impl Detector {
                 .map_err(|e| ParseOutputTensors([t0_size, t1_size, t2_size, t3_size], e))
         }
     }
+
+    // Runs a classifier model and returns the `top_k` classes
+    // and their scores, sorted by highest score first.
+    pub fn classify(&mut self, buf: &[u8], top_k: usize) -> Result<Vec<(u16, f32)>, DetectError> {
+        use DetectError::*;
+        let Some(output_type) = &self.classifier_output else {
+            return Err(WrongModelFormat(self.format));
+        };
+        check_buffer_size(self.input_tensor_size, buf.len())?;
+        let buf = apply_channel_order(buf, self.channel_order);
+        unsafe {
+            let data: *mut *mut u8 = &mut std::ptr::null_mut();
+            let mut size = 0;
+
+            let res =
+                c_detector_classify(self.c_detector, buf.as_ptr(), buf.len(), data, &mut size);
+            if res != 0 {
+                return Err(Detect(res));
+            }
+
+            let output = from_raw_parts(*data, size);
+            Ok(parse_classifier_output(output, output_type, top_k)?)
+        }
+    }
+}
+
+impl Detector {
+    // Runs inference on a zeroed input `iterations` times and returns the median
+    // duration. The first invocations are slow because the interpreter and delegate
+    // are initialized lazily, this moves the stall to startup.
+    pub fn warmup(&mut self, iterations: usize) -> Result<Duration, DetectError> {
+        let buf = vec![0; self.input_tensor_size];
+        let mut durations = Vec::with_capacity(iterations);
+        for _ in 0..iterations {
+            let start = Instant::now();
+            match self.format {
+                ModelFormat::Detector => {
+                    self.detect(&buf)?;
+                }
+                ModelFormat::Classifier => {
+                    self.classify(&buf, 0)?;
+                }
+            }
+            durations.push(start.elapsed());
+        }
+        Ok(median(durations))
+    }
+
+    // Sets how `detect_frame` handles frames larger than the model input.
+    // `warn` is called the first time a frame is downscaled.
+    pub fn set_oversized_frame(&mut self, oversized: OversizedFrame, warn: WarnFunc) {
+        self.oversized = oversized;
+        self.warn = Some(warn);
+    }
+
+    // Same as `detect` but the frame is converted to RGB from its native format first.
+    // The color matrix is only used for YUV formats.
+    pub fn detect_frame(
+        &mut self,
+        buf: &[u8],
+        format: FrameFormat,
+        matrix: ColorMatrix,
+        width: usize,
+        height: usize,
+    ) -> Result<Vec<Detection>, DetectError> {
+        let rgb = self.prepare_frame(buf, format, matrix, width, height)?;
+        self.detect(&rgb)
+    }
+
+    // Runs `detect` or `classify` depending on the model format, the
+    // frame is prepared the same way as `detect_frame`.
+    pub fn run_frame(
+        &mut self,
+        buf: &[u8],
+        format: FrameFormat,
+        matrix: ColorMatrix,
+        width: usize,
+        height: usize,
+        top_k: usize,
+    ) -> Result<ModelOutput, DetectError> {
+        let rgb = self.prepare_frame(buf, format, matrix, width, height)?;
+        match self.format {
+            ModelFormat::Detector => Ok(ModelOutput::Detections(self.detect(&rgb)?)),
+            ModelFormat::Classifier => Ok(ModelOutput::Classes(self.classify(&rgb, top_k)?)),
+        }
+    }
+
+    // Converts the frame to RGB and downscales it if allowed.
+    fn prepare_frame<'a>(
+        &mut self,
+        buf: &'a [u8],
+        format: FrameFormat,
+        matrix: ColorMatrix,
+        width: usize,
+        height: usize,
+    ) -> Result<Cow<'a, [u8]>, DetectError> {
+        let convert: Option<ConvertFunc> = match format {
+            FrameFormat::Rgb24 => None,
+            FrameFormat::Nv12 => Some(nv12_to_rgb),
+            FrameFormat::I420 => Some(i420_to_rgb),
+        };
+        let rgb = if let Some(convert) = convert {
+            let mut rgb = vec![0; FrameFormat::Rgb24.frame_size(width, height)];
+            convert(buf, width, height, matrix, &mut rgb)?;
+            Cow::Owned(rgb)
+        } else {
+            Cow::Borrowed(buf)
+        };
+        let (rgb, downscaled) =
+            fit_frame(rgb, width, height, self.input_tensor_size, self.oversized)?;
+        if downscaled {
+            if let Some(warn) = self.warn.take() {
+                warn(&format!(
+                    "frames are {width}x{height} which is larger than the model input, downscaling"
+                ));
+            }
+        }
+        Ok(rgb)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum NewTransientDetectorError {
+    #[error("{0}")]
+    NewDetector(#[from] NewDetectorError),
+
+    #[error("input tensor isn't a square RGB image: {0} bytes")]
+    InputTensorSize(usize),
+
+    #[error("input size {0}x{1} doesn't match the input tensor: {2} bytes")]
+    InputSizeMismatch(usize, usize, usize),
+}
+
+// Creates a CPU detector for a model that isn't in the config, used to
+// compare models. Edgetpu devices are never used, they are owned by the
+// configured detectors. Larger frames are downscaled to the model input.
+// The input is assumed to be square unless `input_size` is set.
+pub fn new_transient_detector(
+    models: &ModelRegistry,
+    model_path: &Path,
+    format: ModelFormat,
+    input_size: Option<(usize, usize)>,
+) -> Result<Detector, NewTransientDetectorError> {
+    use NewTransientDetectorError::*;
+    let mut detector = Detector::new(models, model_path, None, format, false, ChannelOrder::Rgb)?;
+    let input_tensor_size = detector.input_tensor_size();
+    let (width, height) = if let Some((width, height)) = input_size {
+        if FrameFormat::Rgb24.frame_size(width, height) != input_tensor_size {
+            return Err(InputSizeMismatch(width, height, input_tensor_size));
+        }
+        (width, height)
+    } else {
+        let side = detector
+            .input_side()
+            .ok_or(InputTensorSize(input_tensor_size))?;
+        (side, side)
+    };
+    detector.set_oversized_frame(
+        OversizedFrame::Downscale { width, height },
+        Box::new(|_: &str| {}),
+    );
+    Ok(detector)
+}
+
+// Width and height of a square RGB input tensor.
+fn input_side(input_tensor_size: usize) -> Option<usize> {
+    let pixels = input_tensor_size / 3;
+    let side = (1..=pixels).find(|v| v * v >= pixels)?;
+    (side * side * 3 == input_tensor_size).then_some(side)
+}
+
+fn check_buffer_size(input_tensor_size: usize, got: usize) -> Result<(), DetectError> {
+    use DetectError::*;
+    if got > input_tensor_size {
+        return Err(FrameTooLarge {
+            got,
+            expected: input_tensor_size,
+        });
+    }
+    if got != input_tensor_size {
+        return Err(BufferSize(input_tensor_size, got));
+    }
+    Ok(())
+}
+
+// Downscales RGB frames that are larger than the model input if allowed.
+// Returns true if the frame was downscaled.
+fn fit_frame(
+    rgb: Cow<'_, [u8]>,
+    width: usize,
+    height: usize,
+    input_tensor_size: usize,
+    oversized: OversizedFrame,
+) -> Result<(Cow<'_, [u8]>, bool), DetectError> {
+    if rgb.len() <= input_tensor_size {
+        return Ok((rgb, false));
+    }
+    match oversized {
+        OversizedFrame::Error => Err(DetectError::FrameTooLarge {
+            got: rgb.len(),
+            expected: input_tensor_size,
+        }),
+        OversizedFrame::Downscale {
+            width: dst_width,
+            height: dst_height,
+        } => {
+            let mut dst = vec![0; FrameFormat::Rgb24.frame_size(dst_width, dst_height)];
+            downscale_rgb(&rgb, width, height, &mut dst, dst_width, dst_height)?;
+            Ok((Cow::Owned(dst), true))
+        }
+    }
 }
 
 impl Drop for Detector {
@@ -214,23 +631,34 @@ pub enum ParseOutputTensorsError {
     RectBounds(usize),
 }
 
-#[allow(
-    clippy::cast_sign_loss,
-    clippy::cast_possible_truncation,
-    clippy::as_conversions
-)]
 fn parse_output_tensors(
     t0: &[u8],
     t1: &[u8],
     t2: &[u8],
     t3: &[u8],
 ) -> Result<Vec<Detection>, ParseOutputTensorsError> {
-    use ParseOutputTensorsError::*;
-    let t0 = u8_to_f32(t0);
-    let t1 = u8_to_f32(t1);
-    let t2 = u8_to_f32(t2);
-    let t3 = u8_to_f32(t3);
+    parse_detection_tensors(
+        &u8_to_f32(t0),
+        &u8_to_f32(t1),
+        &u8_to_f32(t2),
+        &u8_to_f32(t3),
+    )
+}
 
+// Parses the boxes, classes, scores and count outputs of a SSD
+// postprocess model. Boxes are top, left, bottom, right.
+#[allow(
+    clippy::cast_sign_loss,
+    clippy::cast_possible_truncation,
+    clippy::as_conversions
+)]
+pub fn parse_detection_tensors(
+    t0: &[f32],
+    t1: &[f32],
+    t2: &[f32],
+    t3: &[f32],
+) -> Result<Vec<Detection>, ParseOutputTensorsError> {
+    use ParseOutputTensorsError::*;
     let mut detections = Vec::new();
     let count = *t3.first().ok_or(GetCount)? as usize;
     for i in 0..count {
@@ -263,6 +691,75 @@ fn parse_output_tensors(
     Ok(detections)
 }
 
+fn median(mut durations: Vec<Duration>) -> Duration {
+    durations.sort_unstable();
+    durations
+        .get(durations.len() / 2)
+        .copied()
+        .unwrap_or_default()
+}
+
+const TENSOR_TYPE_FLOAT32: c_int = 1;
+const TENSOR_TYPE_INT8: c_int = 9;
+
+// Type of the classifier output tensor.
+enum ClassifierOutput {
+    Float32,
+    // Dequantized value of every possible byte.
+    Int8(Box<[f32; 256]>),
+}
+
+impl ClassifierOutput {
+    // Returns None if the type is unsupported.
+    fn new(typ: c_int, scale: f32, zero_point: i32) -> Option<Self> {
+        match typ {
+            TENSOR_TYPE_FLOAT32 => Some(Self::Float32),
+            TENSOR_TYPE_INT8 => Some(Self::Int8(Box::new(dequantize_table(scale, zero_point)))),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ParseClassifierOutputError {
+    #[error("too many classes: {0}")]
+    ClassCount(usize),
+}
+
+fn parse_classifier_output(
+    output: &[u8],
+    output_type: &ClassifierOutput,
+    top_k: usize,
+) -> Result<Vec<(u16, f32)>, ParseClassifierOutputError> {
+    use ParseClassifierOutputError::*;
+    let scores = match output_type {
+        ClassifierOutput::Float32 => u8_to_f32(output),
+        ClassifierOutput::Int8(table) => output.iter().map(|v| table[usize::from(*v)]).collect(),
+    };
+    let mut classes = scores
+        .into_iter()
+        .enumerate()
+        .map(|(i, score)| Ok((u16::try_from(i).map_err(|_| ClassCount(i))?, score)))
+        .collect::<Result<Vec<_>, _>>()?;
+    classes.sort_by(|a, b| b.1.total_cmp(&a.1));
+    classes.truncate(top_k);
+    Ok(classes)
+}
+
+#[allow(clippy::cast_precision_loss, clippy::as_conversions)]
+fn dequantize(value: i8, scale: f32, zero_point: i32) -> f32 {
+    scale * (i32::from(value) - zero_point) as f32
+}
+
+// Dequantized value of every possible byte, indexed by the raw byte.
+fn dequantize_table(scale: f32, zero_point: i32) -> [f32; 256] {
+    let mut table = [0.0; 256];
+    for (byte, v) in (0..=u8::MAX).zip(&mut table) {
+        *v = dequantize(i8::from_ne_bytes([byte]), scale, zero_point);
+    }
+    table
+}
+
 fn u8_to_f32(input: &[u8]) -> Vec<f32> {
     input
         .chunks_exact(4)
@@ -270,6 +767,7 @@ fn u8_to_f32(input: &[u8]) -> Vec<f32> {
         .collect()
 }
 
+#[derive(Clone)]
 pub struct Detection {
     pub score: f32,
     pub class: u8,
@@ -279,6 +777,26 @@ pub struct Detection {
     pub right: f32,
 }
 
+impl Detection {
+    #[must_use]
+    pub fn area(&self) -> f32 {
+        (self.right - self.left).max(0.0) * (self.bottom - self.top).max(0.0)
+    }
+
+    // Intersection over union of the two boxes, 0.0 to 1.0.
+    #[must_use]
+    pub fn iou(&self, other: &Detection) -> f32 {
+        let width = self.right.min(other.right) - self.left.max(other.left);
+        let height = self.bottom.min(other.bottom) - self.top.max(other.top);
+        let intersection = width.max(0.0) * height.max(0.0);
+        let union = self.area() + other.area() - intersection;
+        if union <= 0.0 {
+            return 0.0;
+        }
+        intersection / union
+    }
+}
+
 impl Debug for Detection {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -577,12 +1095,94 @@ fn poke_devices() {
     unsafe { c_poke_devices() }
 }
 
-#[allow(clippy::needless_pass_by_value)]
+#[allow(clippy::needless_pass_by_value, clippy::unwrap_used)]
 #[cfg(test)]
 mod tests {
     use super::*;
     use test_case::test_case;
 
+    // 2x2 model with four float32 outputs, each output is the input
+    // casted to float, so the input bytes become the output tensors.
+    const CAST_MODEL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/cast.tflite");
+
+    #[test_case(false; "default")]
+    #[test_case(true; "cpu_accel")]
+    fn test_detector_float_model(cpu_accel: bool) {
+        let mut detector = Detector::new(
+            &ModelRegistry::new(),
+            Path::new(CAST_MODEL),
+            None,
+            ModelFormat::Detector,
+            cpu_accel,
+            ChannelOrder::Rgb,
+        )
+        .unwrap();
+        assert_eq!(Some(2), detector.input_side());
+
+        // Count 1, score 1, class 1 and the box 1,0,1,1.
+        let buf = [1, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+        let got = detector.detect(&buf).unwrap();
+        assert_eq!(1, got.len());
+        let d = &got[0];
+        assert_eq!(1, d.class);
+        assert_eq!(
+            vec![1.0, 1.0, 0.0, 1.0, 1.0],
+            vec![d.score, d.top, d.left, d.bottom, d.right]
+        );
+        assert_eq!(Some([48, 48, 48, 48]), detector.output_tensor_sizes());
+    }
+
+    #[test_case(ChannelOrder::Rgb, &[1, 2, 3, 4, 5, 6, 7, 8, 9]; "rgb")]
+    #[test_case(ChannelOrder::Bgr, &[3, 2, 1, 6, 5, 4, 9, 8, 7]; "bgr")]
+    fn test_apply_channel_order(order: ChannelOrder, want: &[u8]) {
+        let frame = [1, 2, 3, 4, 5, 6, 7, 8, 9];
+        assert_eq!(want, &*apply_channel_order(&frame, order));
+    }
+
+    #[test]
+    fn test_fit_frame_oversized() {
+        // 4x4 frame for a 2x2 model.
+        let frame: Vec<u8> = (0..48).collect();
+        let input_tensor_size = 12;
+
+        assert!(matches!(
+            fit_frame(
+                Cow::Borrowed(&frame),
+                4,
+                4,
+                input_tensor_size,
+                OversizedFrame::Error
+            ),
+            Err(DetectError::FrameTooLarge {
+                got: 48,
+                expected: 12
+            })
+        ));
+
+        let downscale = OversizedFrame::Downscale {
+            width: 2,
+            height: 2,
+        };
+        let (got, downscaled) =
+            fit_frame(Cow::Borrowed(&frame), 4, 4, input_tensor_size, downscale).unwrap();
+        assert!(downscaled);
+        assert_eq!(
+            vec![0, 1, 2, 6, 7, 8, 24, 25, 26, 30, 31, 32],
+            got.into_owned()
+        );
+
+        let (got, downscaled) = fit_frame(
+            Cow::Borrowed(&frame[..12]),
+            2,
+            2,
+            input_tensor_size,
+            downscale,
+        )
+        .unwrap();
+        assert!(!downscaled);
+        assert_eq!(&frame[..12], &*got);
+    }
+
     #[allow(clippy::needless_pass_by_value)]
     #[test_case("", None; "empty")]
     #[test_case("/sys/bus/usb/devices", None; "empty2")]
@@ -611,4 +1211,65 @@ mod tests {
     fn test_parse_device_path(input: &str, want: Option<DevicePath>) {
         assert_eq!(want, DevicePath::new(input));
     }
+
+    #[test_case(300 * 300 * 3, Some(300); "square")]
+    #[test_case(300 * 200 * 3, None; "not_square")]
+    #[test_case(0, None; "empty")]
+    fn test_input_side(input_tensor_size: usize, want: Option<usize>) {
+        assert_eq!(want, input_side(input_tensor_size));
+    }
+
+    #[test_case("detector", ModelFormat::Detector; "detector")]
+    #[test_case("Classifier", ModelFormat::Classifier; "classifier")]
+    fn test_parse_model_format(input: &str, want: ModelFormat) {
+        assert_eq!(want, input.parse().unwrap());
+    }
+
+    #[test_case(&[], 0; "empty")]
+    #[test_case(&[5], 5; "one")]
+    #[test_case(&[9, 1, 5], 5; "odd")]
+    #[test_case(&[9, 1, 5, 7], 7; "even")]
+    fn test_median(input: &[u64], want: u64) {
+        let input = input.iter().map(|v| Duration::from_millis(*v)).collect();
+        assert_eq!(Duration::from_millis(want), median(input));
+    }
+
+    fn f32_to_u8(input: &[f32]) -> Vec<u8> {
+        input.iter().flat_map(|v| v.to_ne_bytes()).collect()
+    }
+
+    #[test_case(3, vec![(1, 0.7), (2, 0.2), (0, 0.1)]; "all")]
+    #[test_case(2, vec![(1, 0.7), (2, 0.2)]; "top 2")]
+    #[test_case(5, vec![(1, 0.7), (2, 0.2), (0, 0.1)]; "top 5")]
+    #[test_case(0, vec![]; "top 0")]
+    fn test_parse_classifier_output(top_k: usize, want: Vec<(u16, f32)>) {
+        let output = f32_to_u8(&[0.1, 0.7, 0.2]);
+        let got = parse_classifier_output(&output, &ClassifierOutput::Float32, top_k).unwrap();
+        assert_eq!(want, got);
+    }
+
+    #[test]
+    fn test_parse_classifier_output_int8() {
+        // real_value = scale * (quantized_value - zero_point)
+        let output = [-128_i8, 52, -78].map(|v| v.to_ne_bytes()[0]);
+        let output_type = ClassifierOutput::new(TENSOR_TYPE_INT8, 0.5, -128).unwrap();
+        let got = parse_classifier_output(&output, &output_type, 2).unwrap();
+        assert_eq!(vec![(1, 90.0), (2, 25.0)], got);
+    }
+
+    #[test]
+    fn test_dequantize_table() {
+        for (scale, zero_point) in [(0.5, -128), (0.003_906_25, 0), (0.1, 17)] {
+            let table = dequantize_table(scale, zero_point);
+            for byte in 0..=u8::MAX {
+                let want = dequantize(i8::from_ne_bytes([byte]), scale, zero_point);
+                assert_eq!(want.to_bits(), table[usize::from(byte)].to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn test_classifier_output_type() {
+        assert!(ClassifierOutput::new(3, 0.0, 0).is_none());
+    }
 }
@@ -0,0 +1,64 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+};
+
+// Model files loaded into memory. Detectors created from the same file
+// share the data instead of loading their own copy. The data is freed
+// when the last detector using it is dropped.
+#[derive(Default)]
+pub struct ModelRegistry {
+    models: Mutex<HashMap<PathBuf, Weak<[u8]>>>,
+}
+
+impl ModelRegistry {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the model data, the file is only read if no detector is
+    // currently using it.
+    pub fn load(&self, path: &Path) -> Result<Arc<[u8]>, std::io::Error> {
+        let mut models = self.models.lock().expect("not poisoned");
+        if let Some(data) = models.get(path).and_then(Weak::upgrade) {
+            return Ok(data);
+        }
+        let data: Arc<[u8]> = std::fs::read(path)?.into();
+        models.retain(|_, v| v.strong_count() != 0);
+        models.insert(path.to_owned(), Arc::downgrade(&data));
+        Ok(data)
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_model_registry() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("model.tflite");
+        std::fs::write(&path, [1, 2, 3]).unwrap();
+
+        let registry = ModelRegistry::new();
+        let a = registry.load(&path).unwrap();
+
+        // The second detector gets the already loaded data,
+        // the file on disk is not read again.
+        std::fs::write(&path, [4, 5, 6]).unwrap();
+        let b = registry.load(&path).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!([1, 2, 3], *b);
+
+        // Reloaded once all users are dropped.
+        drop(a);
+        drop(b);
+        assert_eq!([4, 5, 6], *registry.load(&path).unwrap());
+
+        assert!(registry.load(&temp_dir.path().join("x")).is_err());
+    }
+}
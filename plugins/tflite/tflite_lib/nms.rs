@@ -0,0 +1,163 @@
+use crate::Detection;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NmsMode {
+    // Overlapping boxes are dropped in favor of the highest scoring box.
+    #[default]
+    Suppress,
+
+    // Overlapping boxes are merged into a single box, the coordinates are
+    // averaged weighted by score. The merged box keeps the highest score.
+    // Large objects that are detected as multiple boxes flicker less.
+    Fusion,
+}
+
+// Removes detections that overlap a higher scoring detection of the same class
+// by at least `iou_threshold`. The output is sorted by score, highest first.
+#[must_use]
+pub fn non_max_suppression(
+    mut detections: Vec<Detection>,
+    iou_threshold: f32,
+    mode: NmsMode,
+) -> Vec<Detection> {
+    detections.sort_by(|a, b| b.score.total_cmp(&a.score));
+    match mode {
+        NmsMode::Suppress => suppress(detections, iou_threshold),
+        NmsMode::Fusion => fuse(detections, iou_threshold),
+    }
+}
+
+fn suppress(detections: Vec<Detection>, iou_threshold: f32) -> Vec<Detection> {
+    let mut kept: Vec<Detection> = Vec::with_capacity(detections.len());
+    for detection in detections {
+        let overlaps = kept
+            .iter()
+            .any(|k| k.class == detection.class && k.iou(&detection) >= iou_threshold);
+        if !overlaps {
+            kept.push(detection);
+        }
+    }
+    kept
+}
+
+// Boxes of a cluster, the coordinates are weighted sums.
+struct Cluster {
+    fused: Detection,
+    total_score: f32,
+    top: f32,
+    left: f32,
+    bottom: f32,
+    right: f32,
+}
+
+impl Cluster {
+    fn new(d: Detection) -> Self {
+        Self {
+            total_score: d.score,
+            top: d.top * d.score,
+            left: d.left * d.score,
+            bottom: d.bottom * d.score,
+            right: d.right * d.score,
+            fused: d,
+        }
+    }
+
+    fn add(&mut self, d: &Detection) {
+        self.total_score += d.score;
+        self.top += d.top * d.score;
+        self.left += d.left * d.score;
+        self.bottom += d.bottom * d.score;
+        self.right += d.right * d.score;
+        if self.total_score <= 0.0 {
+            return;
+        }
+        self.fused.top = self.top / self.total_score;
+        self.fused.left = self.left / self.total_score;
+        self.fused.bottom = self.bottom / self.total_score;
+        self.fused.right = self.right / self.total_score;
+    }
+}
+
+// Each detection is added to the first cluster with the same class that
+// it overlaps. The clusters are ordered by the score of their first box.
+fn fuse(detections: Vec<Detection>, iou_threshold: f32) -> Vec<Detection> {
+    let mut clusters: Vec<Cluster> = Vec::with_capacity(detections.len());
+    for detection in detections {
+        let cluster = clusters
+            .iter_mut()
+            .find(|c| c.fused.class == detection.class && c.fused.iou(&detection) >= iou_threshold);
+        match cluster {
+            Some(cluster) => cluster.add(&detection),
+            None => clusters.push(Cluster::new(detection)),
+        }
+    }
+    clusters.into_iter().map(|c| c.fused).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection(score: f32, class: u8, left: f32, right: f32) -> Detection {
+        Detection {
+            score,
+            class,
+            top: 0.0,
+            left,
+            bottom: 0.4,
+            right,
+        }
+    }
+
+    fn overlapping() -> Vec<Detection> {
+        vec![
+            detection(0.1, 0, 0.05, 0.45),
+            detection(0.6, 0, 0.0, 0.4),
+            detection(0.3, 0, 0.1, 0.5),
+            // Other classes are never merged.
+            detection(0.2, 1, 0.0, 0.4),
+        ]
+    }
+
+    fn assert_box(want: &Detection, got: &Detection) {
+        let eq = |a: f32, b: f32| (a - b).abs() < 1e-6;
+        assert!(
+            want.class == got.class
+                && eq(want.score, got.score)
+                && eq(want.top, got.top)
+                && eq(want.left, got.left)
+                && eq(want.bottom, got.bottom)
+                && eq(want.right, got.right),
+            "want: {want:?}\ngot: {got:?}"
+        );
+    }
+
+    #[test]
+    fn test_non_max_suppression_suppress() {
+        let got = non_max_suppression(overlapping(), 0.5, NmsMode::Suppress);
+        assert_eq!(2, got.len());
+        assert_box(&detection(0.6, 0, 0.0, 0.4), &got[0]);
+        assert_box(&detection(0.2, 1, 0.0, 0.4), &got[1]);
+    }
+
+    #[test]
+    fn test_non_max_suppression_fusion() {
+        let got = non_max_suppression(overlapping(), 0.5, NmsMode::Fusion);
+        assert_eq!(2, got.len());
+        // left = 0.6*0.0 + 0.3*0.1 + 0.1*0.05
+        // right = 0.6*0.4 + 0.3*0.5 + 0.1*0.45
+        assert_box(&detection(0.6, 0, 0.035, 0.435), &got[0]);
+        assert_box(&detection(0.2, 1, 0.0, 0.4), &got[1]);
+    }
+
+    #[test]
+    fn test_non_max_suppression_no_overlap() {
+        let input = vec![detection(0.5, 0, 0.0, 0.2), detection(0.6, 0, 0.5, 0.7)];
+        for mode in [NmsMode::Suppress, NmsMode::Fusion] {
+            let got = non_max_suppression(input.clone(), 0.5, mode);
+            assert_eq!(2, got.len());
+            assert_box(&input[1], &got[0]);
+            assert_box(&input[0], &got[1]);
+        }
+    }
+}
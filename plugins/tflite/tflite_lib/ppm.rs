@@ -0,0 +1,103 @@
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParsePpmError {
+    #[error("not a binary ppm image")]
+    Magic,
+
+    #[error("invalid header")]
+    Header,
+
+    #[error("only 8 bit images are supported, max value: {0}")]
+    MaxValue(usize),
+
+    #[error("pixel data is {0} bytes, expected {1} bytes")]
+    Size(usize, usize),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgb: Vec<u8>,
+}
+
+// Parses a binary PPM (P6) image with 8 bit samples.
+pub fn parse_ppm(raw: &[u8]) -> Result<Image, ParsePpmError> {
+    use ParsePpmError::*;
+    let rest = raw.strip_prefix(b"P6").ok_or(Magic)?;
+
+    let mut pos = 0;
+    let mut fields = [0; 3];
+    for field in &mut fields {
+        // Skip whitespace and comments.
+        loop {
+            match rest.get(pos) {
+                Some(b) if b.is_ascii_whitespace() => pos += 1,
+                Some(b'#') => {
+                    while rest.get(pos).is_some_and(|b| *b != b'\n') {
+                        pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+        let start = pos;
+        while rest.get(pos).is_some_and(u8::is_ascii_digit) {
+            pos += 1;
+        }
+        *field = rest
+            .get(start..pos)
+            .and_then(|v| std::str::from_utf8(v).ok())
+            .and_then(|v| v.parse().ok())
+            .ok_or(Header)?;
+    }
+    let [width, height, max_value] = fields;
+
+    // A single whitespace character separates the header from the pixels.
+    if !rest.get(pos).is_some_and(u8::is_ascii_whitespace) {
+        return Err(Header);
+    }
+    if max_value != 255 {
+        return Err(MaxValue(max_value));
+    }
+    let rgb = rest.get(pos + 1..).unwrap_or_default();
+    let expected = width
+        .checked_mul(height)
+        .and_then(|v| v.checked_mul(3))
+        .ok_or(Header)?;
+    if rgb.len() != expected {
+        return Err(Size(rgb.len(), expected));
+    }
+    Ok(Image {
+        width,
+        height,
+        rgb: rgb.to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use test_case::test_case;
+
+    #[test]
+    fn test_parse_ppm() {
+        let raw = b"P6\n# comment\n2 1\n255\n\x01\x02\x03\x04\x05\x06";
+        let want = Image {
+            width: 2,
+            height: 1,
+            rgb: vec![1, 2, 3, 4, 5, 6],
+        };
+        assert_eq!(Ok(want), parse_ppm(raw));
+    }
+
+    #[test_case(b"P5 1 1 255 \x00", ParsePpmError::Magic; "magic")]
+    #[test_case(b"P6 1 255 \x00\x00\x00", ParsePpmError::Header; "header")]
+    #[test_case(b"P6 1 1 65535 \x00\x00\x00", ParsePpmError::MaxValue(65535); "max_value")]
+    #[test_case(b"P6 1 1 255 \x00\x00", ParsePpmError::Size(2, 3); "size")]
+    fn test_parse_ppm_error(raw: &[u8], want: ParsePpmError) {
+        assert_eq!(Err(want), parse_ppm(raw));
+    }
+}
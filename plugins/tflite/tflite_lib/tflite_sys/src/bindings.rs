@@ -268,13 +268,20 @@ extern "C" {
 pub const edgetpu_device_type_EDGETPU_APEX_PCI: edgetpu_device_type = 0;
 pub const edgetpu_device_type_EDGETPU_APEX_USB: edgetpu_device_type = 1;
 pub type edgetpu_device_type = ::std::os::raw::c_uint;
+pub const model_format_MODEL_FORMAT_DETECTOR: model_format = 0;
+pub const model_format_MODEL_FORMAT_CLASSIFIER: model_format = 1;
+pub type model_format = ::std::os::raw::c_uint;
 extern "C" {
     pub fn c_detector_load_model(
         d: *mut CDetector,
-        model_path: *const ::std::os::raw::c_char,
+        model_data: *const u8,
+        model_size: usize,
         input_tensor_size: *mut usize,
         device: *const ::std::os::raw::c_char,
         device_type: edgetpu_device_type,
+        format: model_format,
+        cpu_accel: ::std::os::raw::c_int,
+        cpu_accel_enabled: *mut ::std::os::raw::c_int,
     ) -> ::std::os::raw::c_int;
 }
 extern "C" {
@@ -292,6 +299,23 @@ extern "C" {
         t3_size: *mut usize,
     ) -> ::std::os::raw::c_int;
 }
+extern "C" {
+    pub fn c_detector_classify(
+        d: *mut CDetector,
+        buf: *const u8,
+        buf_size: usize,
+        data: *mut *mut u8,
+        size: *mut usize,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn c_detector_classifier_output(
+        d: *mut CDetector,
+        type_: *mut ::std::os::raw::c_int,
+        scale: *mut f32,
+        zero_point: *mut i32,
+    );
+}
 extern "C" {
     pub fn c_detector_free(d: *mut CDetector);
 }
@@ -19,13 +19,13 @@ limitations under the License. */
 /// TfLiteModel wraps a loaded TensorFlow Lite model.
 typedef struct TfLiteModel TfLiteModel;
 
-/// Same as `TfLiteModelCreateFromFile` with customizble error reporter.
+/// Same as `TfLiteModelCreate` with customizble error reporter.
 /// * `reporter` takes the provided `user_data` object, as well as a C-style
 ///   format string and arg list (see also vprintf).
 /// * `user_data` is optional. If non-null, it is owned by the client and must
 ///   remain valid for the duration of the interpreter lifetime.
-extern TfLiteModel *TfLiteModelCreateFromFileWithErrorReporter(
-    const char *model_path,
+extern TfLiteModel *TfLiteModelCreateWithErrorReporter(
+    const void *model_data, size_t model_size,
     void (*reporter)(void *user_data, const char *format, va_list args),
     void *user_data);
 
@@ -157,6 +157,32 @@ extern size_t TfLiteTensorByteSize(const TfLiteTensor *tensor);
 /// Returns the type of a tensor element.
 extern int TfLiteTensorType(const TfLiteTensor *tensor);
 
+/// Returns the number of dimensions that the tensor has.  Returns -1 in case
+/// the 'opaque_tensor' does not have its dimensions property set.
+extern int32_t TfLiteTensorNumDims(const TfLiteTensor *tensor);
+
+/// Returns the length of the tensor in the "dim_index" dimension.
+/// REQUIRES: 0 <= dim_index < TFLiteTensorNumDims(tensor)
+extern int32_t TfLiteTensorDim(const TfLiteTensor *tensor, int32_t dim_index);
+
+/// Legacy. Will be deprecated in favor of TfLiteAffineQuantization.
+/// If per-layer quantization is specified this field will still be populated in
+/// addition to TfLiteAffineQuantization.
+/// Parameters for asymmetric quantization. Quantized values can be converted
+/// back to float using:
+///     real_value = scale * (quantized_value - zero_point)
+typedef struct TfLiteQuantizationParams {
+  float scale;
+  int32_t zero_point;
+} TfLiteQuantizationParams;
+
+/// Returns the parameters for asymmetric quantization. The quantization
+/// parameters are only valid when the tensor type is `kTfLiteUInt8` and the
+/// `scale != 0`. Quantized values can be converted back to float using:
+///    real_value = scale * (quantized_value - zero_point);
+extern TfLiteQuantizationParams
+TfLiteTensorQuantizationParams(const TfLiteTensor *tensor);
+
 /// Copies from the provided input buffer into the tensor's buffer.
 /// REQUIRES: input_data_size == TfLiteTensorByteSize(tensor)
 extern int TfLiteTensorCopyFromBuffer(TfLiteTensor *tensor,
@@ -247,3 +273,14 @@ void edgetpu_free_devices(struct edgetpu_device *dev);
 // Sets verbosity of operating logs related to edge TPU.
 // Verbosity level can be set to [0-10], in which 10 is the most verbose.
 void edgetpu_verbosity(int verbosity);
+
+typedef struct TfLiteXNNPackDelegateOptions TfLiteXNNPackDelegateOptions;
+
+// Creates a new delegate instance that need to be destroyed with
+// `TfLiteXNNPackDelegateDelete` when delegate is no longer used by TFLite.
+// When `options` is set to `nullptr`, default values are used.
+TfLiteOpaqueDelegate *
+TfLiteXNNPackDelegateCreate(const TfLiteXNNPackDelegateOptions *options);
+
+// Destroys a delegate created with `TfLiteXNNPackDelegateCreate` call.
+void TfLiteXNNPackDelegateDelete(TfLiteOpaqueDelegate *delegate);
@@ -1,8 +1,9 @@
 mod bindings;
 
 pub use bindings::{
-    c_detector_allocate, c_detector_detect, c_detector_free, c_detector_load_model, c_free_devices,
-    c_list_devices, c_poke_devices, c_probe_device, edgetpu_device, CDetector,
+    c_detector_allocate, c_detector_classifier_output, c_detector_classify, c_detector_detect,
+    c_detector_free, c_detector_load_model, c_free_devices, c_list_devices, c_poke_devices,
+    c_probe_device, edgetpu_device, model_format, CDetector,
 };
 
 extern "C" {
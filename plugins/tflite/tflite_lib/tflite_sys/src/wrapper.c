@@ -20,6 +20,7 @@ typedef struct {
   TfLiteInterpreter *interpreter;
   TfLiteTensor *input_tensor;
   TfLiteOpaqueDelegate *delegate;
+  TfLiteOpaqueDelegate *xnnpack_delegate;
 } CDetector;
 
 CDetector *c_detector_allocate() {
@@ -27,24 +28,35 @@ CDetector *c_detector_allocate() {
   d->interpreter = NULL;
   d->input_tensor = NULL;
   d->delegate = NULL;
+  d->xnnpack_delegate = NULL;
   return d;
 }
 
-int c_detector_load_model(CDetector *d, const char *model_path,
-                          size_t *input_tensor_size, const char *device,
-                          const enum edgetpu_device_type device_type) {
+enum model_format {
+  MODEL_FORMAT_DETECTOR = 0,
+  MODEL_FORMAT_CLASSIFIER = 1,
+};
+
+int c_detector_load_model(CDetector *d, const uint8_t *model_data,
+                          size_t model_size, size_t *input_tensor_size,
+                          const char *device,
+                          const enum edgetpu_device_type device_type,
+                          const enum model_format format,
+                          const int cpu_accel, int *cpu_accel_enabled) {
 #define ERROR_CREATE_FROM_FILE 10000;
 #define ERROR_INTERPRETER_CREATE 10001;
 #define ERROR_INPUT_TENSOR_COUNT 10002;
 #define ERROR_INPUT_TENSOR_TYPE 10003;
 #define ERROR_OUTPUT_TENSOR_COUNT 10004;
 #define ERROR_EDGETPU_DELEGATE_CREATE 10005;
+#define ERROR_OUTPUT_TENSOR_DIMS 10006;
+#define ERROR_OUTPUT_TENSOR_TYPE_UNSUPPORTED 10007;
 
   int ret;
 
   // Load model.
-  TfLiteModel *model =
-      TfLiteModelCreateFromFileWithErrorReporter(model_path, reporter, NULL);
+  TfLiteModel *model = TfLiteModelCreateWithErrorReporter(
+      model_data, model_size, reporter, NULL);
   if (model == NULL) {
     return ERROR_CREATE_FROM_FILE;
   }
@@ -60,12 +72,30 @@ int c_detector_load_model(CDetector *d, const char *model_path,
       return ERROR_EDGETPU_DELEGATE_CREATE
     }
     TfLiteInterpreterOptionsAddDelegate(options, d->delegate);
+  } else if (cpu_accel) {
+    // Create XNNPACK delegate, the model runs without it if this fails.
+    d->xnnpack_delegate = TfLiteXNNPackDelegateCreate(NULL);
+    if (d->xnnpack_delegate != NULL) {
+      TfLiteInterpreterOptionsAddDelegate(options, d->xnnpack_delegate);
+    }
   }
 
   d->interpreter = TfLiteInterpreterCreate(model, options);
+  if (d->interpreter == NULL && d->xnnpack_delegate != NULL) {
+    // The delegate may not support the model, retry without it.
+    TfLiteInterpreterOptionsDelete(options);
+    TfLiteXNNPackDelegateDelete(d->xnnpack_delegate);
+    d->xnnpack_delegate = NULL;
+
+    options = TfLiteInterpreterOptionsCreate();
+    TfLiteInterpreterOptionsSetNumThreads(options, 1);
+    TfLiteInterpreterOptionsSetErrorReporter(options, reporter, NULL);
+    d->interpreter = TfLiteInterpreterCreate(model, options);
+  }
   if (d->interpreter == NULL) {
     return ERROR_INTERPRETER_CREATE
   }
+  *cpu_accel_enabled = d->xnnpack_delegate != NULL;
   TfLiteModelDelete(model);
   TfLiteInterpreterOptionsDelete(options);
 
@@ -91,9 +121,30 @@ int c_detector_load_model(CDetector *d, const char *model_path,
 
   int32_t output_tensor_count =
       TfLiteInterpreterGetOutputTensorCount(d->interpreter);
-  if (output_tensor_count != 4) {
+  if (format == MODEL_FORMAT_DETECTOR) {
+    if (output_tensor_count != 4) {
+      return ERROR_OUTPUT_TENSOR_COUNT;
+    }
+    return 0;
+  }
+
+  // Classifier.
+  if (output_tensor_count != 1) {
     return ERROR_OUTPUT_TENSOR_COUNT;
   }
+  const TfLiteTensor *t = TfLiteInterpreterGetOutputTensor(d->interpreter, 0);
+
+  // A leading batch dimension of 1 is allowed.
+  int32_t num_dims = TfLiteTensorNumDims(t);
+  if (num_dims != 1 && !(num_dims == 2 && TfLiteTensorDim(t, 0) == 1)) {
+    return ERROR_OUTPUT_TENSOR_DIMS;
+  }
+
+  // Float32 or int8.
+  int output_tensor_type = TfLiteTensorType(t);
+  if (output_tensor_type != 1 && output_tensor_type != 9) {
+    return ERROR_OUTPUT_TENSOR_TYPE_UNSUPPORTED;
+  }
 
   return 0;
 }
@@ -137,6 +188,37 @@ int c_detector_detect(CDetector *d, const uint8_t *buf, size_t buf_size,
   return 0;
 }
 
+int c_detector_classify(CDetector *d, const uint8_t *buf, size_t buf_size,
+                        uint8_t **data, size_t *size) {
+  // Populate input tensor data.
+  int ret;
+  if ((ret = TfLiteTensorCopyFromBuffer(d->input_tensor, buf, buf_size)) != 0) {
+    return ret;
+  }
+
+  // Execute inference.
+  if ((ret = TfLiteInterpreterInvoke(d->interpreter)) != 0) {
+    return ret;
+  }
+
+  const TfLiteTensor *t = TfLiteInterpreterGetOutputTensor(d->interpreter, 0);
+
+  *data = TfLiteTensorData(t);
+  *size = TfLiteTensorByteSize(t);
+
+  return 0;
+}
+
+void c_detector_classifier_output(CDetector *d, int *type, float *scale,
+                                  int32_t *zero_point) {
+  const TfLiteTensor *t = TfLiteInterpreterGetOutputTensor(d->interpreter, 0);
+  TfLiteQuantizationParams params = TfLiteTensorQuantizationParams(t);
+
+  *type = TfLiteTensorType(t);
+  *scale = params.scale;
+  *zero_point = params.zero_point;
+}
+
 void c_detector_free(CDetector *d) {
   if (d->interpreter != NULL) {
     TfLiteInterpreterDelete(d->interpreter);
@@ -147,6 +229,9 @@ void c_detector_free(CDetector *d) {
   if (d->delegate != NULL) {
     edgetpu_free_delegate(d->delegate);
   }
+  if (d->xnnpack_delegate != NULL) {
+    TfLiteXNNPackDelegateDelete(d->xnnpack_delegate);
+  }
   free(d);
 }
 
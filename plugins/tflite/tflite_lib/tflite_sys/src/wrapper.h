@@ -12,15 +12,30 @@ enum edgetpu_device_type {
   EDGETPU_APEX_USB = 1,
 };
 
-int c_detector_load_model(CDetector *d, const char *model_path,
-                          size_t *input_tensor_size, const char *device,
-                          const enum edgetpu_device_type device_type);
+enum model_format {
+  MODEL_FORMAT_DETECTOR = 0,
+  MODEL_FORMAT_CLASSIFIER = 1,
+};
+
+int c_detector_load_model(CDetector *d, const uint8_t *model_data,
+                          size_t model_size, size_t *input_tensor_size,
+                          const char *device,
+                          const enum edgetpu_device_type device_type,
+                          const enum model_format format,
+                          const int cpu_accel, int *cpu_accel_enabled);
 
 int c_detector_detect(CDetector *d, const uint8_t *buf, size_t buf_size,
                       uint8_t **t0_data, uint8_t **t1_data, uint8_t **t2_data,
                       uint8_t **t3_data, size_t *t0_size, size_t *t1_size,
                       size_t *t2_size, size_t *t3_size);
 
+int c_detector_classify(CDetector *d, const uint8_t *buf, size_t buf_size,
+                        uint8_t **data, size_t *size);
+
+// Returns the type and quantization parameters of the classifier output.
+void c_detector_classifier_output(CDetector *d, int *type, float *scale,
+                                  int32_t *zero_point);
+
 void c_detector_free(CDetector *d);
 
 struct edgetpu_device {
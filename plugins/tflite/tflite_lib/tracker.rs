@@ -0,0 +1,188 @@
+use crate::Detection;
+
+// Detection with a ID that is stable across frames.
+#[derive(Clone, Debug)]
+pub struct TrackedDetection {
+    pub track_id: u64,
+    pub detection: Detection,
+}
+
+struct Track {
+    id: u64,
+    detection: Detection,
+
+    // Number of consecutive frames without a match.
+    missed: u32,
+}
+
+// IoU based object tracker, detections are matched to the tracks of the
+// previous frame. Tracks are dropped after `max_age` frames without a match.
+pub struct Tracker {
+    iou_threshold: f32,
+    max_age: u32,
+    next_id: u64,
+    tracks: Vec<Track>,
+}
+
+impl Tracker {
+    #[must_use]
+    pub fn new(iou_threshold: f32, max_age: u32) -> Self {
+        Self {
+            iou_threshold,
+            max_age,
+            next_id: 0,
+            tracks: Vec::new(),
+        }
+    }
+
+    // Assigns track IDs to the detections of a single frame. Detections are
+    // matched greedily, highest IoU first, to tracks of the same class.
+    // Unmatched detections start new tracks.
+    pub fn update(&mut self, detections: Vec<Detection>) -> Vec<TrackedDetection> {
+        let mut pairs = Vec::new();
+        for (track_index, track) in self.tracks.iter().enumerate() {
+            for (detection_index, detection) in detections.iter().enumerate() {
+                if track.detection.class != detection.class {
+                    continue;
+                }
+                let iou = track.detection.iou(detection);
+                if iou >= self.iou_threshold {
+                    pairs.push((iou, track_index, detection_index));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut track_ids: Vec<Option<u64>> = vec![None; detections.len()];
+        let mut matched_tracks = vec![false; self.tracks.len()];
+        for (_, track_index, detection_index) in pairs {
+            if matched_tracks[track_index] || track_ids[detection_index].is_some() {
+                continue;
+            }
+            matched_tracks[track_index] = true;
+            track_ids[detection_index] = Some(self.tracks[track_index].id);
+        }
+
+        for (track, matched) in self.tracks.iter_mut().zip(matched_tracks) {
+            if !matched {
+                track.missed = track.missed.saturating_add(1);
+            }
+        }
+        let max_age = self.max_age;
+        self.tracks.retain(|track| track.missed <= max_age);
+
+        let mut tracked = Vec::with_capacity(detections.len());
+        for (detection, track_id) in detections.into_iter().zip(track_ids) {
+            let track_id = if let Some(id) = track_id {
+                let track = self
+                    .tracks
+                    .iter_mut()
+                    .find(|track| track.id == id)
+                    .expect("matched track to exist");
+                track.detection = detection.clone();
+                track.missed = 0;
+                id
+            } else {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.tracks.push(Track {
+                    id,
+                    detection: detection.clone(),
+                    missed: 0,
+                });
+                id
+            };
+            tracked.push(TrackedDetection {
+                track_id,
+                detection,
+            });
+        }
+        tracked
+    }
+
+    // Removes the tracks for which `keep` returns false.
+    pub fn retain<F: FnMut(u64) -> bool>(&mut self, mut keep: F) {
+        self.tracks.retain(|track| keep(track.id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection(class: u8, top: f32, left: f32) -> Detection {
+        Detection {
+            score: 0.9,
+            class,
+            top,
+            left,
+            bottom: top + 0.2,
+            right: left + 0.2,
+        }
+    }
+
+    fn ids(tracked: &[TrackedDetection]) -> Vec<u64> {
+        tracked.iter().map(|d| d.track_id).collect()
+    }
+
+    #[test]
+    fn test_tracker_moving_box() {
+        let mut tracker = Tracker::new(0.3, 1);
+
+        let first = tracker.update(vec![detection(0, 0.1, 0.1)]);
+        let second = tracker.update(vec![detection(0, 0.12, 0.14)]);
+        assert_eq!(ids(&first), ids(&second));
+
+        // The track survives a single missing frame.
+        assert!(tracker.update(Vec::new()).is_empty());
+        let fourth = tracker.update(vec![detection(0, 0.14, 0.18)]);
+        assert_eq!(ids(&first), ids(&fourth));
+
+        // And is dropped after `max_age` missing frames.
+        tracker.update(Vec::new());
+        tracker.update(Vec::new());
+        let seventh = tracker.update(vec![detection(0, 0.14, 0.18)]);
+        assert_ne!(ids(&first), ids(&seventh));
+    }
+
+    #[test]
+    fn test_tracker_multiple() {
+        let mut tracker = Tracker::new(0.3, 0);
+
+        let first = tracker.update(vec![
+            detection(0, 0.1, 0.1),
+            detection(0, 0.6, 0.6),
+            detection(1, 0.1, 0.1),
+        ]);
+        assert_eq!(vec![0, 1, 2], ids(&first));
+
+        // Order doesn't matter, the class must match and far away boxes start new tracks.
+        let second = tracker.update(vec![
+            detection(1, 0.1, 0.1),
+            detection(0, 0.62, 0.62),
+            detection(0, 0.1, 0.8),
+        ]);
+        assert_eq!(vec![2, 1, 3], ids(&second));
+    }
+
+    #[test]
+    fn test_tracker_retain() {
+        let mut tracker = Tracker::new(0.3, u32::MAX);
+        let first = tracker.update(vec![detection(0, 0.1, 0.1), detection(0, 0.6, 0.6)]);
+        assert_eq!(vec![0, 1], ids(&first));
+
+        // A removed track isn't matched again.
+        tracker.retain(|id| id != 0);
+        let second = tracker.update(vec![detection(0, 0.1, 0.1), detection(0, 0.6, 0.6)]);
+        assert_eq!(vec![2, 1], ids(&second));
+    }
+
+    #[test]
+    fn test_detection_iou() {
+        let a = detection(0, 0.0, 0.0);
+        assert!((a.area() - 0.04).abs() < 1e-6);
+        assert!((a.iou(&a) - 1.0).abs() < 1e-6);
+        assert!((a.iou(&detection(0, 0.0, 0.1)) - 1.0 / 3.0).abs() < 1e-6);
+        assert!(a.iou(&detection(0, 0.5, 0.5)).abs() < 1e-6);
+    }
+}
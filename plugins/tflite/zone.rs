@@ -0,0 +1,210 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::config::Percent;
+use common::{recording::vertex_inside_poly2, PointNormalized, PolygonNormalized};
+use serde::Deserialize;
+
+// Include and exclude zones in normalized coordinates.
+// Empty lists allow everything.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub(crate) struct Zones {
+    #[serde(default)]
+    pub include: Vec<PolygonNormalized>,
+
+    #[serde(default)]
+    pub exclude: Vec<PolygonNormalized>,
+
+    // Fraction of the detection area that must overlap a zone for the
+    // detection to be considered inside it. Only the center is checked if unset.
+    #[serde(default)]
+    pub overlap: Option<Percent>,
+}
+
+impl Zones {
+    // Returns false if the detection is outside all include zones or inside any exclude zone.
+    pub(crate) fn allows(&self, top: u32, left: u32, bottom: u32, right: u32) -> bool {
+        let inside = |zone: &PolygonNormalized| match self.overlap {
+            Some(overlap) => {
+                overlap_fraction(top, left, bottom, right, zone) * 100.0
+                    >= f64::from(*overlap).max(f64::MIN_POSITIVE)
+            }
+            None => vertex_inside_poly2(
+                left + ((right - left) / 2),
+                top + ((bottom - top) / 2),
+                zone,
+            ),
+        };
+        if !self.include.is_empty() && !self.include.iter().any(inside) {
+            return false;
+        }
+        !self.exclude.iter().any(inside)
+    }
+}
+
+// Returns the fraction of the rectangle that is covered by the polygon, 0.0 to 1.0.
+fn overlap_fraction(top: u32, left: u32, bottom: u32, right: u32, poly: &PolygonNormalized) -> f64 {
+    let (top, left, bottom, right) = (
+        f64::from(top),
+        f64::from(left),
+        f64::from(bottom),
+        f64::from(right),
+    );
+    let rect_area = (right - left) * (bottom - top);
+    if rect_area <= 0.0 {
+        return 0.0;
+    }
+
+    // Sutherland-Hodgman, the rectangle is convex so the polygon may be concave.
+    let mut clipped: Vec<(f64, f64)> = poly
+        .iter()
+        .map(|PointNormalized { x, y }| (f64::from(*x), f64::from(*y)))
+        .collect();
+    clipped = clip(&clipped, |p| p.0 >= left, |a, b| intersect_x(a, b, left));
+    clipped = clip(&clipped, |p| p.0 <= right, |a, b| intersect_x(a, b, right));
+    clipped = clip(&clipped, |p| p.1 >= top, |a, b| intersect_y(a, b, top));
+    clipped = clip(
+        &clipped,
+        |p| p.1 <= bottom,
+        |a, b| intersect_y(a, b, bottom),
+    );
+
+    (polygon_area(&clipped) / rect_area).min(1.0)
+}
+
+fn clip<F, G>(poly: &[(f64, f64)], inside: F, intersect: G) -> Vec<(f64, f64)>
+where
+    F: Fn((f64, f64)) -> bool,
+    G: Fn((f64, f64), (f64, f64)) -> (f64, f64),
+{
+    let mut out = Vec::with_capacity(poly.len() + 4);
+    let Some(mut prev) = poly.last().copied() else {
+        return out;
+    };
+    for &cur in poly {
+        match (inside(prev), inside(cur)) {
+            (true, true) => out.push(cur),
+            (true, false) => out.push(intersect(prev, cur)),
+            (false, true) => {
+                out.push(intersect(prev, cur));
+                out.push(cur);
+            }
+            (false, false) => {}
+        }
+        prev = cur;
+    }
+    out
+}
+
+fn intersect_x(a: (f64, f64), b: (f64, f64), x: f64) -> (f64, f64) {
+    let t = (x - a.0) / (b.0 - a.0);
+    (x, a.1 + t * (b.1 - a.1))
+}
+
+fn intersect_y(a: (f64, f64), b: (f64, f64), y: f64) -> (f64, f64) {
+    let t = (y - a.1) / (b.1 - a.1);
+    (a.0 + t * (b.0 - a.0), y)
+}
+
+// Shoelace formula.
+fn polygon_area(poly: &[(f64, f64)]) -> f64 {
+    let Some(mut prev) = poly.last().copied() else {
+        return 0.0;
+    };
+    let mut sum = 0.0;
+    for &cur in poly {
+        sum += prev.0 * cur.1 - cur.0 * prev.1;
+        prev = cur;
+    }
+    (sum / 2.0).abs()
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::recording::normalize;
+    use test_case::test_case;
+
+    fn p(x: u16, y: u16) -> PointNormalized {
+        PointNormalized {
+            x: normalize(x, 100),
+            y: normalize(y, 100),
+        }
+    }
+
+    // Triangle covering the bottom left half of the frame.
+    fn triangle() -> PolygonNormalized {
+        vec![p(0, 0), p(0, 100), p(100, 100)]
+    }
+
+    fn allows(zones: &Zones, top: u16, left: u16, bottom: u16, right: u16) -> bool {
+        zones.allows(
+            normalize(top, 100),
+            normalize(left, 100),
+            normalize(bottom, 100),
+            normalize(right, 100),
+        )
+    }
+
+    #[test_case(70, 10, 90, 30, false; "inside")]
+    #[test_case(10, 70, 30, 90, true; "outside")]
+    #[test_case(30, 50, 50, 70, true; "center outside")]
+    #[test_case(30, 20, 50, 40, false; "center inside")]
+    fn test_zones_exclude_triangle_center(
+        top: u16,
+        left: u16,
+        bottom: u16,
+        right: u16,
+        want: bool,
+    ) {
+        let zones = Zones {
+            include: Vec::new(),
+            exclude: vec![triangle()],
+            overlap: None,
+        };
+        assert_eq!(want, allows(&zones, top, left, bottom, right));
+    }
+
+    // The box 40,40-60,60 straddles the diagonal and is half inside.
+    #[test_case(40, false; "below threshold")]
+    #[test_case(50, false; "at threshold")]
+    #[test_case(60, true; "above threshold")]
+    fn test_zones_exclude_triangle_overlap(overlap: u8, want: bool) {
+        let zones = Zones {
+            include: Vec::new(),
+            exclude: vec![triangle()],
+            overlap: Some(overlap.try_into().unwrap()),
+        };
+        assert_eq!(want, allows(&zones, 40, 40, 60, 60));
+    }
+
+    #[test]
+    fn test_zones_include() {
+        let zones = Zones {
+            include: vec![triangle()],
+            exclude: Vec::new(),
+            overlap: None,
+        };
+        assert!(allows(&zones, 70, 10, 90, 30));
+        assert!(!allows(&zones, 10, 70, 30, 90));
+    }
+
+    #[test]
+    fn test_zones_empty() {
+        assert!(allows(&Zones::default(), 10, 10, 20, 20));
+    }
+
+    #[test]
+    fn test_overlap_fraction() {
+        let n = |v| normalize(v, 100);
+        let square = vec![p(0, 0), p(0, 50), p(50, 50), p(50, 0)];
+        let got = overlap_fraction(n(25), n(25), n(75), n(75), &square);
+        assert!((got - 0.25).abs() < 1e-9, "{got}");
+
+        let got = overlap_fraction(n(10), n(10), n(20), n(20), &square);
+        assert!((got - 1.0).abs() < 1e-9, "{got}");
+
+        let got = overlap_fraction(n(60), n(60), n(70), n(70), &square);
+        assert!(got.abs() < 1e-9, "{got}");
+    }
+}
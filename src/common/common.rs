@@ -15,8 +15,18 @@ use bytesize::{ByteSize, MB};
 use http::{HeaderMap, HeaderValue};
 use serde::{Deserialize, Serialize};
 use std::{
-    borrow::Cow, collections::HashMap, convert::TryFrom, fmt, io::Cursor, ops::Deref, path::Path,
-    str::FromStr, sync::Arc, task::Poll,
+    borrow::Cow,
+    collections::HashMap,
+    convert::TryFrom,
+    fmt,
+    io::Cursor,
+    net::SocketAddr,
+    num::{NonZeroU16, NonZeroU32, NonZeroUsize},
+    ops::Deref,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    task::Poll,
 };
 use thiserror::Error;
 use time::{DtsOffset, DurationH264, UnixH264};
@@ -44,15 +54,189 @@ pub type DynEnvConfig = Box<dyn EnvConfig + Send + Sync>;
 
 pub trait EnvConfig {
     fn port(&self) -> u16;
+    fn listen(&self) -> &ListenAddr;
     fn storage_dir(&self) -> &Path;
     fn recordings_dir(&self) -> &Path;
     fn config_dir(&self) -> &Path;
     fn plugin_dir(&self) -> &Path;
     fn max_disk_usage(&self) -> ByteSize;
+    fn log_max_age(&self) -> Option<std::time::Duration>;
+    fn log_dedupe_window(&self) -> Option<std::time::Duration>;
+    fn log_sync_interval(&self) -> Option<std::time::Duration>;
+    fn vod_cache_entries(&self) -> Option<NonZeroUsize>;
+    fn vod_cache_ttl(&self) -> Option<std::time::Duration>;
+    fn vod_disk_cache_size(&self) -> Option<ByteSize>;
+    fn vod_max_duration(&self) -> Option<std::time::Duration>;
+    fn vod_rate_limit(&self) -> Option<RateLimit>;
+    fn vod_max_recordings(&self) -> Option<NonZeroUsize>;
+    fn vod_max_samples(&self) -> Option<NonZeroUsize>;
+    fn vod_max_size(&self) -> Option<ByteSize>;
+    fn recording_index(&self) -> bool;
+    fn tls(&self) -> Option<&TlsConfig>;
+    fn archive(&self) -> Option<&ArchiveConfig>;
+    fn recording_key(&self) -> Option<&MasterKey>;
+    fn cors(&self) -> Option<&CorsConfig>;
+    fn log_format(&self) -> LogFormat;
+    fn log_levels(&self) -> LogLevels;
     fn plugins(&self) -> &Option<Vec<EnvPlugin>>;
     fn raw(&self) -> &str;
 }
 
+// Address the web server listens on. IPv6 addresses must be in brackets.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddr {
+    Ip(SocketAddr),
+
+    // Resolved when the server starts.
+    Host(String, u16),
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseListenAddrError {
+    #[error("missing port: '{0}'")]
+    MissingPort(String),
+
+    #[error("invalid port: '{0}'")]
+    InvalidPort(String),
+
+    #[error("invalid host: '{0}', IPv6 addresses must be in brackets")]
+    InvalidHost(String),
+}
+
+impl FromStr for ListenAddr {
+    type Err = ParseListenAddrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use ParseListenAddrError::*;
+        if let Ok(addr) = s.parse() {
+            return Ok(Self::Ip(addr));
+        }
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| MissingPort(s.to_owned()))?;
+        let port = port.parse().map_err(|_| InvalidPort(s.to_owned()))?;
+        let valid_host = !host.is_empty()
+            && host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+        if !valid_host {
+            return Err(InvalidHost(s.to_owned()));
+        }
+        Ok(Self::Host(host.to_owned(), port))
+    }
+}
+
+impl<'de> Deserialize<'de> for ListenAddr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ip(addr) => write!(f, "{addr}"),
+            Self::Host(host, port) => write!(f, "{host}:{port}"),
+        }
+    }
+}
+
+// PEM encoded certificate chain and private key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+// Second recordings directory, usually on a larger and slower disk.
+// Recordings are moved there once they're older than `max_age`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveConfig {
+    pub dir: PathBuf,
+    pub max_age: std::time::Duration,
+}
+
+// Cross-origin requests from browsers are allowed from these origins.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorsConfig {
+    pub allowed_origins: CorsOrigins,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+
+    // Cookies and auth headers are sent. Not allowed with `CorsOrigins::Any`.
+    pub allow_credentials: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CorsOrigins {
+    // "*"
+    Any,
+    List(Vec<String>),
+}
+
+// Key that the per-recording encryption keys are wrapped with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MasterKey([u8; 32]);
+
+impl MasterKey {
+    #[must_use]
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+// The key must never end up in the logs.
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MasterKey(..)")
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("key must be 64 hex characters")]
+pub struct ParseMasterKeyError;
+
+impl FromStr for MasterKey {
+    type Err = ParseMasterKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 64 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ParseMasterKeyError);
+        }
+        let mut key = [0; 32];
+        for (i, b) in key.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).map_err(|_| ParseMasterKeyError)?;
+        }
+        Ok(Self(key))
+    }
+}
+
+impl<'de> Deserialize<'de> for MasterKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+// Token bucket limits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: NonZeroU32,
+    pub per_minute: NonZeroU32,
+}
+
 impl NonZeroGb {
     #[must_use]
     pub fn new(size: ByteSize) -> Option<Self> {
@@ -125,7 +309,7 @@ impl LogEntry {
             .to_owned()
             .try_into()
             .expect("source should be valid");
-        let message = match LogMessage::try_from(message) {
+        let message = match LogMessage::try_from(truncate_log_message(message)) {
             Ok(v) => v,
             Err(e) => LogMessage::try_from(format!("bad message: {e}"))
                 .expect("error message should be a valid log message"),
@@ -139,6 +323,22 @@ impl LogEntry {
     }
 }
 
+// Long messages are cut on a character boundary and
+// end with an ellipsis instead of being rejected.
+fn truncate_log_message(mut s: String) -> String {
+    const MARKER: &str = "…";
+    if s.len() <= LOG_MESSAGE_MAX_LENGTH {
+        return s;
+    }
+    let mut end = LOG_MESSAGE_MAX_LENGTH - MARKER.len();
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s.truncate(end);
+    s.push_str(MARKER);
+    s
+}
+
 /// Severity of the log message.
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -168,6 +368,33 @@ impl LogLevel {
     }
 }
 
+// Format of the log lines printed to stdout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+
+    // One JSON object per line.
+    Json,
+}
+
+// Minimum log levels, entries below the level of their source are dropped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LogLevels {
+    // Level of sources without an override, everything is kept if unset.
+    pub default: Option<LogLevel>,
+    pub sources: HashMap<LogSource, LogLevel>,
+}
+
+impl LogLevels {
+    #[must_use]
+    pub fn enabled(&self, level: LogLevel, source: &LogSource) -> bool {
+        let min_level = self.sources.get(source).copied().or(self.default);
+        min_level.map_or(true, |min_level| level.as_u8() <= min_level.as_u8())
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ParseLogLevelError {
     #[error("invalid value: '{0}'")]
@@ -239,7 +466,7 @@ macro_rules! impl_deserialize_try_from_and_display {
 
 pub const MONITOR_ID_MAX_LENGTH: usize = 24;
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub struct MonitorId(String);
 impl_deserialize_try_from_and_display!(MonitorId);
 
@@ -410,7 +637,7 @@ pub enum ParseLogMessageError {
     TooLong,
 }
 
-const LOG_MESSAGE_MAX_LENGTH: usize = 1024 * 4;
+pub const LOG_MESSAGE_MAX_LENGTH: usize = 1024 * 4;
 
 impl TryFrom<String> for LogMessage {
     type Error = ParseLogMessageError;
@@ -683,6 +910,14 @@ impl VideoSample {
     }
 }
 
+// Single AAC frame.
+#[derive(Clone, Debug, Default)]
+pub struct AudioSample {
+    pub pts: UnixH264, // Absolute presentation timestamp in 90khz.
+    pub duration: DurationH264,
+    pub data: Bytes,
+}
+
 impl std::fmt::Debug for StreamType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.name())
@@ -695,6 +930,9 @@ pub struct PartFinalized {
 
     pub is_independent: bool,
     pub video_samples: Arc<Vec<VideoSample>>,
+
+    // Not included in the rendered content, only used by the recorder.
+    pub audio_samples: Arc<Vec<AudioSample>>,
     pub rendered_content: Option<Bytes>,
     pub rendered_duration: DurationH264,
 }
@@ -853,6 +1091,9 @@ pub type ArcHlsMuxer = Arc<dyn HlsMuxer + Send + Sync>;
 pub trait HlsMuxer {
     fn params(&self) -> &TrackParameters;
 
+    // ID of the segments produced by this muxer.
+    fn muxer_id(&self) -> u16;
+
     // Returns none if cancelled.
     async fn next_segment(
         &self,
@@ -860,12 +1101,23 @@ pub trait HlsMuxer {
     ) -> Option<Arc<SegmentFinalized>>;
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TrackParameters {
     pub width: u16,
     pub height: u16,
     pub codec: String,
     pub extra_data: Vec<u8>,
+    pub audio: Option<AudioParameters>,
+}
+
+// AAC audio track.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AudioParameters {
+    pub channel_count: u16,
+    pub sample_rate: u32,
+
+    // AudioSpecificConfig.
+    pub config: Vec<u8>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -903,10 +1155,31 @@ pub fn new_dummy_msg_logger() -> Arc<impl MsgLogger> {
     Arc::new(DummyMsgLogger {})
 }
 
+pub type ArcTranscoder = Arc<dyn Transcoder + Send + Sync>;
+
+// Re-encodes mp4 videos. Implemented by plugins so
+// the core doesn't have to depend on a video encoder.
+#[async_trait]
+pub trait Transcoder {
+    // Returns a H.264 mp4 scaled to `height`, the aspect ratio is kept.
+    // Lower `crf` values result in better quality and larger files.
+    async fn transcode(
+        &self,
+        input: Box<dyn AsyncRead + Send + Unpin>,
+        height: NonZeroU16,
+        crf: u8,
+    ) -> Result<Box<dyn AsyncRead + Send + Unpin>, TranscodeError>;
+}
+
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct TranscodeError(pub String);
+
 #[cfg(test)]
-#[allow(clippy::unwrap_used)]
+#[allow(clippy::unwrap_used, clippy::needless_pass_by_value)]
 mod tests {
     use super::*;
+    use test_case::test_case;
 
     #[test]
     fn test_parse_monitor_name() {
@@ -930,6 +1203,34 @@ mod tests {
         LogMessage::try_from(String::new()).unwrap_err();
     }
 
+    #[test]
+    fn test_log_entry_truncate_message() {
+        // Two byte characters, the cut lands between them.
+        let entry = LogEntry::new(LogLevel::Info, "app", None, "é".repeat(35_000));
+        assert_eq!(4095, entry.message.len());
+        assert!(entry.message.ends_with("é…"));
+
+        let entry = LogEntry::new(LogLevel::Info, "app", None, "a".repeat(4096));
+        assert_eq!("a".repeat(4096), *entry.message);
+    }
+
+    #[test_case("0.0.0.0:2020", ListenAddr::Ip(([0, 0, 0, 0], 2020).into()); "ipv4")]
+    #[test_case("[::]:2020", ListenAddr::Ip((std::net::Ipv6Addr::UNSPECIFIED, 2020).into()); "ipv6")]
+    #[test_case("localhost:2020", ListenAddr::Host("localhost".to_owned(), 2020); "hostname")]
+    fn test_parse_listen_addr(input: &str, want: ListenAddr) {
+        let got: ListenAddr = input.parse().unwrap();
+        assert_eq!(want, got);
+        assert_eq!(input, got.to_string());
+    }
+
+    #[test_case("0.0.0.0", ParseListenAddrError::MissingPort("0.0.0.0".to_owned()); "missing port")]
+    #[test_case("localhost:x", ParseListenAddrError::InvalidPort("localhost:x".to_owned()); "invalid port")]
+    #[test_case("::1:2020", ParseListenAddrError::InvalidHost("::1:2020".to_owned()); "ipv6 without brackets")]
+    #[test_case(":2020", ParseListenAddrError::InvalidHost(":2020".to_owned()); "empty host")]
+    fn test_parse_listen_addr_error(input: &str, want: ParseListenAddrError) {
+        assert_eq!(Err(want), input.parse::<ListenAddr>());
+    }
+
     #[test]
     fn test_parse_account_id() {
         AccountId::try_from("a222222222222222".to_owned()).unwrap();
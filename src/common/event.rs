@@ -3,9 +3,15 @@
 use crate::{
     impl_deserialize_try_from_and_display,
     time::{Duration, UnixNano},
+    MonitorId,
 };
 use serde::{Deserialize, Serialize};
-use std::num::NonZeroU32;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    num::NonZeroU32,
+    sync::Arc,
+};
 use thiserror::Error;
 
 // Recording trigger event.
@@ -32,6 +38,198 @@ pub struct Detection {
     pub region: Region,
 }
 
+// Single detection sent to live detection feed subscribers.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DetectionEvent {
+    #[serde(rename = "monitorID")]
+    pub monitor_id: MonitorId,
+    pub time: UnixNano,
+    pub label: Label,
+    pub score: f32,
+    pub region: Region,
+}
+
+pub const SCORE_HISTOGRAM_BINS: usize = 20;
+
+// Detection scores grouped into 5% wide bins. Bin `i` counts
+// scores from `i*5` up to `(i+1)*5`, the last bin includes 100.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ScoreHistogram {
+    pub bins: [u64; SCORE_HISTOGRAM_BINS],
+}
+
+impl ScoreHistogram {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::as_conversions
+    )]
+    fn bin(score: f32) -> Option<usize> {
+        if score.is_nan() {
+            return None;
+        }
+        let bin = (score.clamp(0.0, 100.0) / 5.0) as usize;
+        Some(bin.min(SCORE_HISTOGRAM_BINS - 1))
+    }
+}
+
+// Accumulates detection scores over a sliding time window,
+// used to find a suitable score threshold.
+#[derive(Debug)]
+pub struct ScoreHistogramWindow {
+    window: Duration,
+    scores: VecDeque<(UnixNano, usize)>,
+    histogram: ScoreHistogram,
+}
+
+impl ScoreHistogramWindow {
+    #[must_use]
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            scores: VecDeque::new(),
+            histogram: ScoreHistogram::default(),
+        }
+    }
+
+    // Adds the scores of the detections, `time` must not decrease between calls.
+    pub fn add(&mut self, time: UnixNano, detections: &[Detection]) {
+        self.prune(time);
+        for d in detections {
+            let Some(bin) = ScoreHistogram::bin(d.score) else {
+                continue;
+            };
+            self.histogram.bins[bin] += 1;
+            self.scores.push_back((time, bin));
+        }
+    }
+
+    // Returns the histogram of scores added within the window before `now`.
+    pub fn histogram(&mut self, now: UnixNano) -> ScoreHistogram {
+        self.prune(now);
+        self.histogram.clone()
+    }
+
+    // Removes the scores that are outside the window.
+    fn prune(&mut self, now: UnixNano) {
+        let start = now.checked_sub(self.window.into()).unwrap_or_default();
+        while let Some((time, bin)) = self.scores.front() {
+            if !time.before(start) {
+                break;
+            }
+            self.histogram.bins[*bin] -= 1;
+            self.scores.pop_front();
+        }
+    }
+}
+
+// Caps the number of events per label within a one minute window.
+// Labels without a limit are never limited.
+#[derive(Debug)]
+pub struct EventRateLimiter {
+    limits: HashMap<Label, u32>,
+    emitted: HashMap<Label, VecDeque<UnixNano>>,
+}
+
+impl EventRateLimiter {
+    #[must_use]
+    pub fn new(limits: HashMap<Label, u32>) -> Self {
+        Self {
+            limits,
+            emitted: HashMap::new(),
+        }
+    }
+
+    // Removes the detections of labels that have reached their limit and
+    // returns the number of removed detections. A event counts once per label.
+    // The event should be dropped if all its detections were removed.
+    // Event times must not decrease between calls.
+    pub fn apply(&mut self, event: &mut Event) -> usize {
+        if self.limits.is_empty() {
+            return 0;
+        }
+        let start = event
+            .time
+            .checked_sub(Duration::from_secs(60).into())
+            .unwrap_or_default();
+
+        let mut limited: Vec<Label> = Vec::new();
+        let mut counted: Vec<&Label> = Vec::new();
+        for d in &event.detections {
+            if limited.contains(&d.label) || counted.contains(&&d.label) {
+                continue;
+            }
+            let Some(limit) = self.limits.get(&d.label) else {
+                continue;
+            };
+            let emitted = self.emitted.entry(d.label.clone()).or_default();
+            while emitted.front().is_some_and(|time| time.before(start)) {
+                emitted.pop_front();
+            }
+            if emitted.len() >= usize::try_from(*limit).unwrap_or(usize::MAX) {
+                limited.push(d.label.clone());
+            } else {
+                emitted.push_back(event.time);
+                counted.push(&d.label);
+            }
+        }
+
+        if limited.is_empty() {
+            return 0;
+        }
+        let n_before = event.detections.len();
+        event.detections.retain(|d| !limited.contains(&d.label));
+        n_before - event.detections.len()
+    }
+}
+
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct DetectError(Box<dyn std::error::Error + Send + Sync>);
+
+impl DetectError {
+    pub fn new<E: std::error::Error + Send + Sync + 'static>(e: E) -> Self {
+        Self(Box::new(e))
+    }
+}
+
+// Drops detections before any post-processing like non-max suppression.
+// The rectangle is normalized to the detector input.
+#[derive(Clone)]
+pub struct DetectFilter(Arc<dyn Fn(&RectangleNormalized) -> bool + Send + Sync>);
+
+impl DetectFilter {
+    pub fn new<F: Fn(&RectangleNormalized) -> bool + Send + Sync + 'static>(f: F) -> Self {
+        Self(Arc::new(f))
+    }
+
+    #[must_use]
+    pub fn allow_all() -> Self {
+        Self::new(|_| true)
+    }
+
+    #[must_use]
+    pub fn allows(&self, rect: &RectangleNormalized) -> bool {
+        (self.0)(rect)
+    }
+}
+
+impl fmt::Debug for DetectFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DetectFilter(..)")
+    }
+}
+
+// Inference backend run by the detector workers. The tflite
+// plugin implements it for tflite, edgetpu and ONNX models.
+pub trait ObjectDetector: Send {
+    // `buf` is a RGB24 frame with the dimensions returned by `input_dims`.
+    fn detect(&mut self, buf: &[u8], filter: &DetectFilter) -> Result<Detections, DetectError>;
+
+    // Width and height.
+    fn input_dims(&self) -> (u16, u16);
+}
+
 // Region where detection occurred.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Region {
@@ -151,6 +349,114 @@ mod tests {
         Label::try_from("{".to_owned()).unwrap_err();
     }
 
+    fn detections(scores: &[f32]) -> Detections {
+        scores
+            .iter()
+            .map(|score| Detection {
+                label: "a".to_owned().try_into().unwrap(),
+                score: *score,
+                region: Region::default(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_score_histogram_window() {
+        let mut window = ScoreHistogramWindow::new(Duration::from_secs(10));
+        window.add(
+            UnixNano::new(0),
+            &detections(&[0.0, 4.9, 5.0, 50.0, 99.0, 100.0, 150.0, f32::NAN]),
+        );
+        window.add(
+            UnixNano::from(Duration::from_secs(5)),
+            &detections(&[50.0, 52.5, -1.0]),
+        );
+
+        let mut want = ScoreHistogram::default();
+        want.bins[0] = 3;
+        want.bins[1] = 1;
+        want.bins[10] = 3;
+        want.bins[19] = 3;
+        assert_eq!(
+            want,
+            window.histogram(UnixNano::from(Duration::from_secs(10)))
+        );
+
+        // The first scores are outside the window.
+        let mut want = ScoreHistogram::default();
+        want.bins[0] = 1;
+        want.bins[10] = 2;
+        assert_eq!(
+            want,
+            window.histogram(UnixNano::from(Duration::from_secs(11)))
+        );
+
+        assert_eq!(
+            ScoreHistogram::default(),
+            window.histogram(UnixNano::from(Duration::from_secs(16)))
+        );
+    }
+
+    #[test]
+    fn test_score_histogram_window_prune_on_add() {
+        let mut window = ScoreHistogramWindow::new(Duration::from_secs(10));
+        for i in 0..100 {
+            window.add(UnixNano::from(Duration::from_secs(i)), &detections(&[50.0]));
+        }
+        assert_eq!(11, window.scores.len());
+        assert_eq!(11, window.histogram.bins[10]);
+    }
+
+    fn label_event(secs: u32, labels: &[&str]) -> Event {
+        Event {
+            time: UnixNano::from(Duration::from_secs(secs)),
+            duration: Duration::from_secs(1),
+            rec_duration: Duration::from_secs(1),
+            detections: labels
+                .iter()
+                .map(|label| Detection {
+                    label: (*label).to_owned().try_into().unwrap(),
+                    score: 50.0,
+                    region: Region::default(),
+                })
+                .collect(),
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_event_rate_limiter() {
+        let mut limiter =
+            EventRateLimiter::new(HashMap::from([("a".to_owned().try_into().unwrap(), 3)]));
+
+        // 10 events within a minute, only the first 3 are emitted.
+        let mut n_emitted = 0;
+        let mut n_limited = 0;
+        for i in 0..10 {
+            let mut event = label_event(i, &["a", "a"]);
+            n_limited += limiter.apply(&mut event);
+            if !event.detections.is_empty() {
+                n_emitted += 1;
+            }
+        }
+        assert_eq!(3, n_emitted);
+        assert_eq!(14, n_limited);
+
+        // Labels without a limit are kept.
+        let mut event = label_event(10, &["a", "b"]);
+        assert_eq!(1, limiter.apply(&mut event));
+        assert_eq!(label_event(10, &["b"]), event);
+
+        // The first two events are outside the window.
+        let mut event = label_event(62, &["a"]);
+        assert_eq!(0, limiter.apply(&mut event));
+        assert_eq!(1, event.detections.len());
+        let mut event = label_event(62, &["a"]);
+        assert_eq!(0, limiter.apply(&mut event));
+        let mut event = label_event(62, &["a"]);
+        assert_eq!(1, limiter.apply(&mut event));
+    }
+
     #[test]
     fn test_event_source() {
         EventSource::try_from("abcdefg".to_owned()).unwrap();
@@ -3,15 +3,25 @@
 #![allow(clippy::module_name_repetitions)]
 
 use crate::{
-    recording::{FrameRateLimiter, FrameRateLimiterError},
-    time::{Duration, MINUTE},
-    ArcHlsMuxer, ArcMsgLogger, Event, H264Data, MonitorId, MonitorName, StreamType,
+    recording::{DurationSec, FrameRateLimiter, FrameRateLimiterError},
+    time::{Duration, UnixNano, MINUTE, SECOND},
+    ArcHlsMuxer, ArcMsgLogger, DetectionEvent, Event, H264Data, Label, MonitorId, MonitorName,
+    PaddedBytes, StreamType,
 };
 use async_trait::async_trait;
 use sentryshot_ffmpeg_h264::{H264BuilderError, ReceiveFrameError, SendPacketError};
 use sentryshot_util::Frame;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::{collections::HashMap, ops::Deref, str::FromStr, sync::Arc};
+use std::{
+    collections::HashMap,
+    num::NonZeroU16,
+    ops::Deref,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 use thiserror::Error;
 use tokio::{
     runtime::Handle,
@@ -104,6 +114,56 @@ impl MonitorConfig {
         Duration::from_f64(self.config.video_length * (MINUTE as f64))
     }
 
+    // Recordings are rotated at the next segment once the
+    // video data reaches this size. None if unlimited.
+    #[must_use]
+    pub fn video_max_size(&self) -> Option<u64> {
+        let mb = self.config.video_max_size;
+        (mb != 0).then(|| u64::from(mb) * 1_000_000)
+    }
+
+    // Write a seek index next to each recording.
+    #[must_use]
+    pub fn record_index(&self) -> bool {
+        self.config.record_index
+    }
+
+    // Encrypt the video data of new recordings.
+    #[must_use]
+    pub fn encrypt_recordings(&self) -> bool {
+        self.config.encrypt_recordings
+    }
+
+    // Thumbnails are downscaled to fit within this size. None if unlimited.
+    #[must_use]
+    pub fn thumb_max_size(&self) -> Option<NonZeroU16> {
+        NonZeroU16::new(self.config.thumb_max_size)
+    }
+
+    #[must_use]
+    pub fn thumb_quality(&self) -> u8 {
+        self.config.thumb_quality.clamp(1, 100)
+    }
+
+    // A warning is logged if the main stream has a longer
+    // interval between keyframes. None if disabled.
+    #[must_use]
+    pub fn keyframe_warn_interval(&self) -> Option<Duration> {
+        let secs = self.config.keyframe_warn_interval;
+        (secs != 0).then(|| Duration::from_secs(secs))
+    }
+
+    #[must_use]
+    pub fn event_rate_limit(&self) -> &HashMap<Label, u32> {
+        &self.config.event_rate_limit
+    }
+
+    // Detection trigger for recordings, unused if always record is enabled.
+    #[must_use]
+    pub fn record_trigger(&self) -> Option<&RecordTrigger> {
+        self.config.record_trigger.as_ref()
+    }
+
     /*
         // TimestampOffset returns the timestamp offset.
         func (c Config) TimestampOffset() string {
@@ -117,6 +177,7 @@ impl MonitorConfig {
     */
 }
 
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct Config {
     pub id: MonitorId,
@@ -129,6 +190,80 @@ pub struct Config {
 
     #[serde(rename = "videoLength")]
     pub video_length: f64,
+
+    // Megabytes, 0 is unlimited.
+    #[serde(rename = "videoMaxSize", default)]
+    pub video_max_size: u32,
+
+    #[serde(rename = "recordIndex", default)]
+    pub record_index: bool,
+
+    // Encrypt the video data with the recording key.
+    #[serde(rename = "encryptRecordings", default)]
+    pub encrypt_recordings: bool,
+
+    // Longest side of the thumbnail in pixels, 0 keeps the source size.
+    #[serde(rename = "thumbMaxSize", default)]
+    pub thumb_max_size: u16,
+
+    // JPEG quality of the thumbnail, 1-100.
+    #[serde(rename = "thumbQuality", default = "default_thumb_quality")]
+    pub thumb_quality: u8,
+
+    // Seconds, 0 disables the warning.
+    #[serde(
+        rename = "keyframeWarnInterval",
+        default = "default_keyframe_warn_interval"
+    )]
+    pub keyframe_warn_interval: u32,
+
+    // Maximum number of events per minute for each label.
+    #[serde(rename = "eventRateLimit", default)]
+    pub event_rate_limit: HashMap<Label, u32>,
+
+    #[serde(rename = "recordTrigger", default)]
+    pub record_trigger: Option<RecordTrigger>,
+}
+
+fn default_thumb_quality() -> u8 {
+    75
+}
+
+fn default_keyframe_warn_interval() -> u32 {
+    10
+}
+
+// Records a clip around each detection that matches the
+// thresholds. Overlapping clips are merged into one recording.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RecordTrigger {
+    // Minimum score for each label, other labels never trigger.
+    pub thresholds: HashMap<Label, f32>,
+
+    // Time recorded before the first detection.
+    #[serde(rename = "preRoll")]
+    pub pre_roll: DurationSec,
+
+    // Time recorded after the last detection.
+    #[serde(rename = "postRoll")]
+    pub post_roll: DurationSec,
+}
+
+impl RecordTrigger {
+    // Returns the end time of the clip triggered by the
+    // event, None if no detection matches the thresholds.
+    #[must_use]
+    pub fn clip_end(&self, event: &Event) -> Option<UnixNano> {
+        let triggered = event.detections.iter().any(|d| {
+            self.thresholds
+                .get(&d.label)
+                .is_some_and(|min_score| d.score >= *min_score)
+        });
+        if !triggered {
+            return None;
+        }
+        event.time.checked_add((*self.post_roll).into())
+    }
 }
 
 impl Serialize for MonitorConfig {
@@ -199,6 +334,65 @@ pub struct SourceRtspConfig {
 
     #[serde(rename = "subStream")]
     pub sub_stream: Option<RtspUrl>,
+
+    // Treat samples with a SEI recovery point as keyframes.
+    #[serde(rename = "seiRecoveryPoint", default)]
+    pub sei_recovery_point: bool,
+
+    // Record the AAC audio stream of the main input.
+    #[serde(default)]
+    pub audio: bool,
+
+    #[serde(
+        rename = "connectTimeout",
+        default,
+        deserialize_with = "deserialize_timeout"
+    )]
+    pub connect_timeout: Option<DurationSec>,
+
+    #[serde(
+        rename = "readTimeout",
+        default,
+        deserialize_with = "deserialize_timeout"
+    )]
+    pub read_timeout: Option<DurationSec>,
+}
+
+// A zero timeout would fail every request.
+fn deserialize_timeout<'de, D>(deserializer: D) -> Result<Option<DurationSec>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    let Some(secs) = Option::<u32>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    if secs == 0 {
+        return Err(Error::custom("timeout must be greater than zero"));
+    }
+    Ok(Some(DurationSec::new(Duration::from_secs(secs))))
+}
+
+const DEFAULT_SOURCE_TIMEOUT: Duration = Duration::new(20 * SECOND);
+
+impl SourceRtspConfig {
+    // Maximum duration of each request while connecting to the camera.
+    #[must_use]
+    pub fn connect_timeout(&self) -> Duration {
+        self.connect_timeout
+            .as_deref()
+            .copied()
+            .unwrap_or(DEFAULT_SOURCE_TIMEOUT)
+    }
+
+    // Maximum duration between two packets.
+    #[must_use]
+    pub fn read_timeout(&self) -> Duration {
+        self.read_timeout
+            .as_deref()
+            .copied()
+            .unwrap_or(DEFAULT_SOURCE_TIMEOUT)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
@@ -262,9 +456,6 @@ impl Deref for RtspUrl {
 
 #[derive(Debug, Error)]
 pub enum DecoderError {
-    #[error("dropped frames")]
-    DroppedFrames,
-
     #[error("{0}")]
     SendFrame(#[from] SendPacketError),
 
@@ -363,6 +554,9 @@ pub enum MonitorSetError {
 
     #[error("rename tempoary file: {0}")]
     RenameTempFile(std::io::Error),
+
+    #[error("encryptRecordings requires the recording_key env option")]
+    NoRecordingKey,
 }
 
 #[derive(Debug, Error)]
@@ -383,6 +577,18 @@ pub enum MonitorDeleteError {
     RemoveFile(#[from] std::io::Error),
 }
 
+#[derive(Debug, Error)]
+pub enum MonitorApplyConfigError {
+    #[error("config with id '{1}' stored under '{0}'")]
+    IdMismatch(String, String),
+
+    #[error("set config '{0}': {1}")]
+    Set(String, MonitorSetError),
+
+    #[error("remove config '{0}': {1}")]
+    RemoveFile(String, std::io::Error),
+}
+
 #[derive(Debug, Serialize, PartialEq, Eq)]
 pub struct MonitorInfo {
     id: MonitorId,
@@ -405,6 +611,211 @@ impl MonitorInfo {
     }
 }
 
+pub type ArcMonitorStats = Arc<MonitorStats>;
+
+// Runtime counters for a single monitor, reset when the monitor restarts.
+#[derive(Debug, Default)]
+pub struct MonitorStats {
+    frames_main: AtomicU64,
+    frames_sub: AtomicU64,
+    frames_dropped: AtomicU64,
+    detections: AtomicU64,
+    detections_rate_limited: AtomicU64,
+
+    // Zero if no frame has been received.
+    last_frame: AtomicI64,
+    recording: AtomicBool,
+
+    // Nanoseconds, zero if unknown.
+    gop_length: AtomicI64,
+}
+
+impl MonitorStats {
+    pub fn add_frame(&self, stream_type: StreamType) {
+        match stream_type {
+            StreamType::Main => self.frames_main.fetch_add(1, Ordering::Relaxed),
+            StreamType::Sub => self.frames_sub.fetch_add(1, Ordering::Relaxed),
+        };
+        self.last_frame.store(*UnixNano::now(), Ordering::Relaxed);
+    }
+
+    pub fn add_frames_dropped(&self, n: u64) {
+        self.frames_dropped.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn add_detections(&self, n: u64) {
+        self.detections.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn add_detections_rate_limited(&self, n: u64) {
+        self.detections_rate_limited.fetch_add(n, Ordering::Relaxed);
+    }
+
+    // Number of frames received from the source.
+    #[must_use]
+    pub fn frames(&self, stream_type: StreamType) -> u64 {
+        match stream_type {
+            StreamType::Main => self.frames_main.load(Ordering::Relaxed),
+            StreamType::Sub => self.frames_sub.load(Ordering::Relaxed),
+        }
+    }
+
+    // Number of frames that decoded feed subscribers skipped
+    // because they couldn't keep up with the source.
+    #[must_use]
+    pub fn frames_dropped(&self) -> u64 {
+        self.frames_dropped.load(Ordering::Relaxed)
+    }
+
+    // Number of detections in events sent to the recorder.
+    #[must_use]
+    pub fn detections(&self) -> u64 {
+        self.detections.load(Ordering::Relaxed)
+    }
+
+    // Number of detections removed by the event rate limit.
+    #[must_use]
+    pub fn detections_rate_limited(&self) -> u64 {
+        self.detections_rate_limited.load(Ordering::Relaxed)
+    }
+
+    // Time of the last frame received from any source.
+    #[must_use]
+    pub fn last_frame(&self) -> Option<UnixNano> {
+        match self.last_frame.load(Ordering::Relaxed) {
+            0 => None,
+            v => Some(UnixNano::new(v)),
+        }
+    }
+
+    pub fn set_recording(&self, recording: bool) {
+        self.recording.store(recording, Ordering::Relaxed);
+    }
+
+    // If a recording is currently open.
+    #[must_use]
+    pub fn recording(&self) -> bool {
+        self.recording.load(Ordering::Relaxed)
+    }
+
+    pub fn set_gop_length(&self, gop_length: Option<Duration>) {
+        let v = gop_length.map_or(0, |v| *v);
+        self.gop_length.store(v, Ordering::Relaxed);
+    }
+
+    // Last observed interval between keyframes in the main stream.
+    #[must_use]
+    pub fn gop_length(&self) -> Option<Duration> {
+        match self.gop_length.load(Ordering::Relaxed) {
+            0 => None,
+            v => Some(Duration::new(v)),
+        }
+    }
+}
+
+pub type ArcLatestFrame = Arc<LatestFrame>;
+
+// Slot holding the most recent keyframe of the main stream. Keyframes
+// can be decoded on their own, the slot is only decoded on request
+// and the jpeg is kept until the next keyframe.
+#[derive(Debug, Default)]
+pub struct LatestFrame(Mutex<Option<LatestFrameState>>);
+
+#[derive(Debug)]
+struct LatestFrameState {
+    keyframe: Keyframe,
+    jpeg: Option<Arc<Vec<u8>>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Keyframe {
+    pub avcc: Arc<PaddedBytes>,
+    pub extradata: Arc<Vec<u8>>,
+    pub time: UnixNano,
+}
+
+// Latest keyframe encoded as a jpeg.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub jpeg: Arc<Vec<u8>>,
+    pub time: UnixNano,
+}
+
+impl LatestFrame {
+    pub fn set(&self, keyframe: Keyframe) {
+        *self.0.lock().expect("not poisoned") = Some(LatestFrameState {
+            keyframe,
+            jpeg: None,
+        });
+    }
+
+    // Called when the stream stops.
+    pub fn clear(&self) {
+        *self.0.lock().expect("not poisoned") = None;
+    }
+
+    // Returns None if no keyframe has been received.
+    #[must_use]
+    pub fn get(&self) -> Option<Keyframe> {
+        Some(
+            self.0
+                .lock()
+                .expect("not poisoned")
+                .as_ref()?
+                .keyframe
+                .clone(),
+        )
+    }
+
+    // Returns None if the latest keyframe hasn't been encoded.
+    #[must_use]
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        let state = self.0.lock().expect("not poisoned");
+        let state = state.as_ref()?;
+        Some(Snapshot {
+            jpeg: state.jpeg.clone()?,
+            time: state.keyframe.time,
+        })
+    }
+
+    // Caches the jpeg unless a newer keyframe has been set.
+    pub fn set_jpeg(&self, keyframe: &Keyframe, jpeg: Arc<Vec<u8>>) {
+        let mut state = self.0.lock().expect("not poisoned");
+        if let Some(state) = state.as_mut() {
+            if Arc::ptr_eq(&state.keyframe.avcc, &keyframe.avcc) {
+                state.jpeg = Some(jpeg);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum LatestFrameError {
+    #[error("monitor is not running '{0}'")]
+    NotRunning(String),
+
+    #[error("encode jpeg: {0}")]
+    Encode(String),
+}
+
+// Runtime status of a single monitor.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct MonitorStatus {
+    pub id: MonitorId,
+    pub running: bool,
+
+    #[serde(rename = "lastFrame")]
+    pub last_frame: Option<UnixNano>,
+
+    // Time the monitor was started.
+    pub started: Option<UnixNano>,
+
+    pub recording: bool,
+
+    #[serde(rename = "gopLength")]
+    pub gop_length: Option<Duration>,
+}
+
 pub type ArcMonitorManager = Arc<dyn IMonitorManager + Send + Sync>;
 
 #[async_trait]
@@ -416,10 +827,88 @@ pub trait IMonitorManager {
         &self,
         config: MonitorConfig,
     ) -> Result<bool, MonitorSetAndRestartError>;
+    async fn monitor_set_and_restart_graceful(
+        &self,
+        config: MonitorConfig,
+    ) -> Result<bool, MonitorSetAndRestartError>;
     async fn monitor_delete(&self, id: MonitorId) -> Result<(), MonitorDeleteError>;
     async fn monitors_info(&self) -> HashMap<MonitorId, MonitorInfo>;
     async fn monitor_config(&self, monitor_id: MonitorId) -> Option<MonitorConfig>;
     async fn monitor_configs(&self) -> MonitorConfigs;
     async fn stop(&self);
     async fn monitor_is_running(&self, monitor_id: MonitorId) -> bool;
+    async fn monitors_stats(&self) -> HashMap<MonitorId, ArcMonitorStats>;
+    async fn apply_config(&self, configs: MonitorConfigs) -> Result<(), MonitorApplyConfigError>;
+    async fn status(&self) -> Vec<MonitorStatus>;
+
+    // Returns the latest frame of the monitor as a jpeg,
+    // None if the monitor hasn't received a keyframe yet.
+    async fn latest_frame_jpeg(
+        &self,
+        monitor_id: MonitorId,
+    ) -> Result<Option<Snapshot>, LatestFrameError>;
+
+    // Subscribe to the detections of all monitors as they occur.
+    fn subscribe_detections(&self) -> broadcast::Receiver<DetectionEvent>;
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: i64) -> Keyframe {
+        Keyframe {
+            avcc: Arc::new(PaddedBytes::new(vec![0, 0, 0, 1, 0x65])),
+            extradata: Arc::new(Vec::new()),
+            time: UnixNano::new(time),
+        }
+    }
+
+    #[test]
+    fn test_source_rtsp_config_timeout() {
+        let parse = |timeout: &str| {
+            serde_json::from_str::<SourceRtspConfig>(&format!(
+                r#"{{
+                    "protocol": "tcp",
+                    "mainStream": "rtsp://x",
+                    "connectTimeout": {timeout}
+                }}"#
+            ))
+        };
+        let config = parse("5").unwrap();
+        assert_eq!(Duration::from_secs(5), config.connect_timeout());
+        assert_eq!(DEFAULT_SOURCE_TIMEOUT, config.read_timeout());
+        assert!(parse("null").unwrap().connect_timeout.is_none());
+        assert!(parse("0").is_err());
+    }
+
+    #[test]
+    fn test_latest_frame_snapshot() {
+        let latest_frame = LatestFrame::default();
+        assert!(latest_frame.get().is_none());
+
+        let first = keyframe(1);
+        latest_frame.set(first.clone());
+        assert!(latest_frame.snapshot().is_none());
+
+        let jpeg = Arc::new(vec![1]);
+        latest_frame.set_jpeg(&first, jpeg.clone());
+        let want = Snapshot {
+            jpeg,
+            time: UnixNano::new(1),
+        };
+        assert_eq!(Some(want), latest_frame.snapshot());
+
+        // A new keyframe invalidates the jpeg.
+        latest_frame.set(keyframe(2));
+        assert!(latest_frame.snapshot().is_none());
+
+        // Jpeg of the old keyframe.
+        latest_frame.set_jpeg(&first, Arc::new(vec![1]));
+        assert!(latest_frame.snapshot().is_none());
+
+        latest_frame.clear();
+        assert!(latest_frame.get().is_none());
+    }
 }
@@ -1,12 +1,14 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::{
     fmt::Display,
     ops::{Add, Deref, Sub},
+    str::FromStr,
     time::{SystemTime, UNIX_EPOCH},
 };
+use thiserror::Error;
 
 pub const NANOSECOND: i64 = 1;
 pub const MICROSECOND: i64 = NANOSECOND * 1000;
@@ -132,7 +134,7 @@ pub struct Duration(i64);
 
 impl Duration {
     #[must_use]
-    pub fn new(v: i64) -> Self {
+    pub const fn new(v: i64) -> Self {
         Self(v)
     }
 
@@ -201,7 +203,7 @@ pub const H264_MILLISECOND: i64 = H264_SECOND / 1000;
 
 // 90khz time since the Unix epoch.
 #[repr(transparent)]
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct UnixH264(i64);
 
 impl UnixH264 {
@@ -412,6 +414,46 @@ impl Deref for DtsOffset {
     }
 }
 
+// IANA time zone, for example "Europe/Stockholm". Only used to
+// format timestamps in responses, times are always stored in UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeZone(chrono_tz::Tz);
+
+impl TimeZone {
+    // Formats the time as RFC 3339 with the UTC offset that was
+    // in effect at that time, DST transitions are accounted for.
+    #[must_use]
+    pub fn format(&self, time: UnixNano) -> String {
+        DateTime::<Utc>::from(time)
+            .with_timezone(&self.0)
+            .to_rfc3339_opts(SecondsFormat::Millis, false)
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("unknown time zone: {0}")]
+pub struct ParseTimeZoneError(String);
+
+impl FromStr for TimeZone {
+    type Err = ParseTimeZoneError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+            .map(Self)
+            .map_err(|_| ParseTimeZoneError(s.to_owned()))
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeZone {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 // Converts value in nanoseconds into a different timescale.
 #[must_use]
 pub fn nano_to_timescale(value: i64, timescale: i64) -> i64 {
@@ -421,6 +463,7 @@ pub fn nano_to_timescale(value: i64, timescale: i64) -> i64 {
 }
 
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
     use test_case::test_case;
@@ -435,4 +478,24 @@ mod tests {
     fn test_nano_to_timescale(input: i64, scale: i64, want: i64) {
         assert_eq!(want, nano_to_timescale(input, scale));
     }
+
+    // 2024-03-31 00:59:59.5 UTC, one second before Europe/Stockholm switches to DST.
+    const BEFORE_DST: i64 = 1_711_846_799_500_000_000;
+
+    #[test_case("UTC", BEFORE_DST, "2024-03-31T00:59:59.500+00:00"; "utc")]
+    #[test_case("America/New_York", BEFORE_DST, "2024-03-30T20:59:59.500-04:00"; "new_york")]
+    #[test_case("Europe/Stockholm", BEFORE_DST, "2024-03-31T01:59:59.500+01:00"; "before_dst")]
+    #[test_case("Europe/Stockholm", BEFORE_DST + SECOND, "2024-03-31T03:00:00.500+02:00"; "after_dst")]
+    fn test_time_zone_format(tz: &str, time: i64, want: &str) {
+        let tz: TimeZone = tz.parse().unwrap();
+        assert_eq!(want, tz.format(UnixNano::new(time)));
+    }
+
+    #[test]
+    fn test_time_zone_parse_error() {
+        assert_eq!(
+            Err(ParseTimeZoneError("Mars/Olympus".to_owned())),
+            "Mars/Olympus".parse::<TimeZone>()
+        );
+    }
 }
@@ -1,6 +1,11 @@
 # Port app will be served on.
 port = 2020
 
+# Address and port app will be served on, overrides `port`. IPv6
+# addresses must be in brackets, use "[::]:2020" to listen on all
+# IPv4 and IPv6 interfaces. Default "0.0.0.0:<port>".
+#listen = "[::]:2020"
+
 # Directory where recordings will be stored.
 storage_dir = "{{ cwd }}/storage"
 
@@ -15,6 +20,86 @@ plugin_dir = "{{ cwd }}/plugins"
 # Recordings are delete automatically before this limit is exceeded.
 max_disk_usage = 100
 
+# Delete logs older than this many days.
+# Logs are only limited by disk usage if unset.
+#log_max_age_days = 30
+
+# Collapse identical consecutive log entries within
+# this many seconds into a single entry with a repeat count.
+#log_dedupe_window_secs = 10
+
+# Sync the active log file to disk every this many seconds. This bounds
+# how many log entries can be lost on power loss. Disabled if unset.
+#log_sync_interval_secs = 5
+
+# Format of the logs printed to stdout, "text" or "json".
+# The json format prints one object per line.
+#log_format = "text"
+
+# Drop log entries below this level, "error", "warning", "info" or "debug".
+# Overrides set the level of individual sources. Everything is logged if unset.
+#log_level = "info"
+#log_level_overrides = { rtsp = "debug" }
+
+# Number of generated videos that are kept in memory and how many seconds
+# they're kept. Default is 10 videos and no time limit. The time limit
+# also applies to the disk cache.
+#vod_cache_entries = 10
+#vod_cache_ttl_secs = 600
+
+# Store generated video metadata in "storage_dir/vodcache" so that
+# playback is fast after a restart. Maximum size in MegaBytes.
+#vod_disk_cache_mb = 500
+
+# Longest video that can be requested from the vod endpoint in hours.
+# Longer videos use more memory. Default is 12 hours.
+#vod_max_duration_hours = 12
+
+# Limit how many videos each client IP can request from the vod endpoint
+# per minute. Cached videos aren't counted. Burst defaults to the rate.
+#vod_rate_limit_per_minute = 30
+#vod_rate_limit_burst = 10
+
+# Limits for a single video from the vod endpoint, the video is truncated
+# and a warning is logged once the samples reach either the sample count
+# or the video size in MegaBytes. Bounds the memory used by queries over
+# many small recordings. Default is 100000 recordings and no sample limits.
+#vod_max_recordings = 100000
+#vod_max_samples = 1000000
+#vod_max_size_mb = 4000
+
+# Keep an index of the recordings in "storage_dir/recording_index" so that
+# listing recordings doesn't have to read every directory. The index is
+# rebuilt from the recordings directory if the file is deleted and checked
+# against the directory in the background on startup.
+#recording_index = false
+
+# Move recordings older than "archive_after_days" from the recordings
+# directory to "archive_dir", usually a larger and slower disk. Recordings
+# can be viewed from both directories. The archive is not pruned.
+#archive_dir = "/mnt/archive"
+#archive_after_days = 7
+
+# Master key for monitors with "Encrypt recordings" enabled, 64 hex characters.
+# Each recording is encrypted with its own key, which is stored next to the
+# recording wrapped by this key. Recordings can't be viewed without it.
+# Generate one with "openssl rand -hex 32".
+#recording_key = ""
+
+# Serve the app over https with this PEM encoded certificate chain
+# and private key. Send SIGHUP to reload them after renewal.
+#tls_cert = "/path/to/fullchain.pem"
+#tls_key = "/path/to/privkey.pem"
+
+# Allow browser apps on these origins to call the API, "*" allows any origin.
+# Credentials, like cookies and basic auth, can't be used with "*".
+# Methods default to GET, POST, PUT, PATCH and DELETE, headers default
+# to Authorization, Content-Type and X-CSRF-TOKEN.
+#cors_allowed_origins = ["https://example.com"]
+#cors_allowed_methods = ["GET"]
+#cors_allowed_headers = ["Authorization"]
+#cors_allow_credentials = false
+
 
 
 # PLUGINS
@@ -22,9 +107,11 @@ max_disk_usage = 100
 # Authentication. One must be enabled.
 
 # Basic Auth.
+# Static tokens can be used in the "Authorization: Bearer <token>" header.
 [[plugin]]
 name = "auth_basic"
 enable = false
+#api_tokens = [{ token = "secret", admin = false }]
 
 # No authentication.
 [[plugin]]
@@ -1,13 +1,19 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
 use bytesize::ByteSize;
-use common::{EnvConfig, EnvPlugin, NonZeroGb};
+use common::{
+    ArchiveConfig, CorsConfig, CorsOrigins, EnvConfig, EnvPlugin, ListenAddr, LogFormat, LogLevel,
+    LogLevels, LogSource, MasterKey, NonZeroGb, RateLimit, TlsConfig,
+};
 use serde::Deserialize;
 use std::{
     collections::HashMap,
     fs::{self, File},
     io::Write,
+    net::{Ipv4Addr, SocketAddr},
+    num::{NonZeroU32, NonZeroUsize},
     path::{Path, PathBuf},
+    time::Duration,
 };
 use thiserror::Error;
 
@@ -15,11 +21,32 @@ use thiserror::Error;
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct EnvConf {
     port: u16,
+    listen: ListenAddr,
     storage_dir: PathBuf,
     recordings_dir: PathBuf,
     config_dir: PathBuf,
     plugin_dir: PathBuf,
     max_disk_usage: NonZeroGb,
+    log_max_age_days: Option<NonZeroU32>,
+    log_dedupe_window_secs: Option<NonZeroU32>,
+    log_sync_interval_secs: Option<NonZeroU32>,
+    log_format: Option<LogFormat>,
+    log_level: Option<LogLevel>,
+    log_level_overrides: Option<HashMap<LogSource, LogLevel>>,
+    vod_cache_entries: Option<NonZeroU32>,
+    vod_cache_ttl_secs: Option<NonZeroU32>,
+    vod_disk_cache_mb: Option<NonZeroU32>,
+    vod_max_duration_hours: Option<NonZeroU32>,
+    vod_rate_limit_per_minute: Option<NonZeroU32>,
+    vod_rate_limit_burst: Option<NonZeroU32>,
+    vod_max_recordings: Option<NonZeroU32>,
+    vod_max_samples: Option<NonZeroU32>,
+    vod_max_size_mb: Option<NonZeroU32>,
+    recording_index: Option<bool>,
+    tls: Option<TlsConfig>,
+    archive: Option<ArchiveConfig>,
+    recording_key: Option<MasterKey>,
+    cors: Option<CorsConfig>,
     plugin: Option<Vec<EnvPlugin>>,
     raw: String,
 }
@@ -27,10 +54,36 @@ pub struct EnvConf {
 #[derive(Debug, Deserialize)]
 pub struct RawEnvConf {
     port: u16,
+    listen: Option<ListenAddr>,
     storage_dir: PathBuf,
     config_dir: PathBuf,
     plugin_dir: PathBuf,
     max_disk_usage: NonZeroGb,
+    log_max_age_days: Option<NonZeroU32>,
+    log_dedupe_window_secs: Option<NonZeroU32>,
+    log_sync_interval_secs: Option<NonZeroU32>,
+    log_format: Option<LogFormat>,
+    log_level: Option<LogLevel>,
+    log_level_overrides: Option<HashMap<LogSource, LogLevel>>,
+    vod_cache_entries: Option<NonZeroU32>,
+    vod_cache_ttl_secs: Option<NonZeroU32>,
+    vod_disk_cache_mb: Option<NonZeroU32>,
+    vod_max_duration_hours: Option<NonZeroU32>,
+    vod_rate_limit_per_minute: Option<NonZeroU32>,
+    vod_rate_limit_burst: Option<NonZeroU32>,
+    vod_max_recordings: Option<NonZeroU32>,
+    vod_max_samples: Option<NonZeroU32>,
+    vod_max_size_mb: Option<NonZeroU32>,
+    recording_index: Option<bool>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    archive_dir: Option<PathBuf>,
+    archive_after_days: Option<NonZeroU32>,
+    recording_key: Option<MasterKey>,
+    cors_allowed_origins: Option<Vec<String>>,
+    cors_allowed_methods: Option<Vec<String>>,
+    cors_allowed_headers: Option<Vec<String>>,
+    cors_allow_credentials: Option<bool>,
     plugin: Option<Vec<EnvPlugin>>,
 }
 
@@ -60,6 +113,9 @@ impl EnvConfig for EnvConf {
     fn port(&self) -> u16 {
         self.port
     }
+    fn listen(&self) -> &ListenAddr {
+        &self.listen
+    }
     fn storage_dir(&self) -> &Path {
         &self.storage_dir
     }
@@ -75,6 +131,79 @@ impl EnvConfig for EnvConf {
     fn max_disk_usage(&self) -> ByteSize {
         *self.max_disk_usage
     }
+    fn log_max_age(&self) -> Option<Duration> {
+        const DAY: u64 = 24 * 60 * 60;
+        self.log_max_age_days
+            .map(|days| Duration::from_secs(u64::from(days.get()) * DAY))
+    }
+    fn log_dedupe_window(&self) -> Option<Duration> {
+        self.log_dedupe_window_secs
+            .map(|secs| Duration::from_secs(u64::from(secs.get())))
+    }
+    fn log_sync_interval(&self) -> Option<Duration> {
+        self.log_sync_interval_secs
+            .map(|secs| Duration::from_secs(u64::from(secs.get())))
+    }
+    fn log_format(&self) -> LogFormat {
+        self.log_format.unwrap_or_default()
+    }
+    fn log_levels(&self) -> LogLevels {
+        LogLevels {
+            default: self.log_level,
+            sources: self.log_level_overrides.clone().unwrap_or_default(),
+        }
+    }
+    fn vod_cache_entries(&self) -> Option<NonZeroUsize> {
+        self.vod_cache_entries
+            .and_then(|v| NonZeroUsize::try_from(v).ok())
+    }
+    fn vod_cache_ttl(&self) -> Option<Duration> {
+        self.vod_cache_ttl_secs
+            .map(|secs| Duration::from_secs(u64::from(secs.get())))
+    }
+    fn vod_disk_cache_size(&self) -> Option<ByteSize> {
+        self.vod_disk_cache_mb
+            .map(|mb| ByteSize::mb(u64::from(mb.get())))
+    }
+    fn vod_max_duration(&self) -> Option<Duration> {
+        const HOUR: u64 = 60 * 60;
+        self.vod_max_duration_hours
+            .map(|hours| Duration::from_secs(u64::from(hours.get()) * HOUR))
+    }
+    fn vod_rate_limit(&self) -> Option<RateLimit> {
+        let per_minute = self.vod_rate_limit_per_minute?;
+        Some(RateLimit {
+            burst: self.vod_rate_limit_burst.unwrap_or(per_minute),
+            per_minute,
+        })
+    }
+    fn vod_max_recordings(&self) -> Option<NonZeroUsize> {
+        self.vod_max_recordings
+            .and_then(|v| NonZeroUsize::try_from(v).ok())
+    }
+    fn vod_max_samples(&self) -> Option<NonZeroUsize> {
+        self.vod_max_samples
+            .and_then(|v| NonZeroUsize::try_from(v).ok())
+    }
+    fn vod_max_size(&self) -> Option<ByteSize> {
+        self.vod_max_size_mb
+            .map(|mb| ByteSize::mb(u64::from(mb.get())))
+    }
+    fn recording_index(&self) -> bool {
+        self.recording_index.unwrap_or(false)
+    }
+    fn tls(&self) -> Option<&TlsConfig> {
+        self.tls.as_ref()
+    }
+    fn archive(&self) -> Option<&ArchiveConfig> {
+        self.archive.as_ref()
+    }
+    fn recording_key(&self) -> Option<&MasterKey> {
+        self.recording_key.as_ref()
+    }
+    fn cors(&self) -> Option<&CorsConfig> {
+        self.cors.as_ref()
+    }
     fn plugins(&self) -> &Option<Vec<EnvPlugin>> {
         &self.plugin
     }
@@ -163,6 +292,21 @@ pub enum ParseEnvConfigError {
 
     #[error("canonicalize path: {0:?} {1}")]
     Canonicalize(PathBuf, std::io::Error),
+
+    #[error("tls_cert and tls_key must be set together")]
+    TlsIncomplete,
+
+    #[error("create archive dir: {0} {1}")]
+    CreateArchiveDir(PathBuf, std::io::Error),
+
+    #[error("archive_dir and archive_after_days must be set together")]
+    ArchiveIncomplete,
+
+    #[error("the other cors options require cors_allowed_origins")]
+    CorsIncomplete,
+
+    #[error("cors_allow_credentials can't be combined with the \"*\" origin")]
+    CorsCredentialsWildcard,
 }
 
 fn parse_config(env_toml: String) -> Result<EnvConf, ParseEnvConfigError> {
@@ -203,18 +347,114 @@ fn parse_config(env_toml: String) -> Result<EnvConf, ParseEnvConfigError> {
         .canonicalize()
         .map_err(|e| Canonicalize(raw.plugin_dir, e))?;
 
+    let tls = match (raw.tls_cert, raw.tls_key) {
+        (Some(cert), Some(key)) => Some(TlsConfig { cert, key }),
+        (None, None) => None,
+        _ => return Err(TlsIncomplete),
+    };
+
+    let archive = match (raw.archive_dir, raw.archive_after_days) {
+        (Some(dir), Some(days)) => {
+            const DAY: u64 = 24 * 60 * 60;
+            if !dir.is_absolute() {
+                return Err(PathNotAbsolute("archive_dir".to_owned(), dir));
+            }
+            std::fs::create_dir_all(&dir).map_err(|e| CreateArchiveDir(dir.clone(), e))?;
+            Some(ArchiveConfig {
+                dir: dir.canonicalize().map_err(|e| Canonicalize(dir, e))?,
+                max_age: Duration::from_secs(u64::from(days.get()) * DAY),
+            })
+        }
+        (None, None) => None,
+        _ => return Err(ArchiveIncomplete),
+    };
+
+    let cors = parse_cors(
+        raw.cors_allowed_origins,
+        raw.cors_allowed_methods,
+        raw.cors_allowed_headers,
+        raw.cors_allow_credentials,
+    )?;
+
+    // Listen on all IPv4 interfaces by default.
+    let listen = raw.listen.unwrap_or(ListenAddr::Ip(SocketAddr::new(
+        Ipv4Addr::UNSPECIFIED.into(),
+        raw.port,
+    )));
+
     Ok(EnvConf {
         port: raw.port,
+        listen,
         storage_dir,
         recordings_dir,
         config_dir,
         plugin_dir,
         max_disk_usage: raw.max_disk_usage,
+        log_max_age_days: raw.log_max_age_days,
+        log_dedupe_window_secs: raw.log_dedupe_window_secs,
+        log_sync_interval_secs: raw.log_sync_interval_secs,
+        log_format: raw.log_format,
+        log_level: raw.log_level,
+        log_level_overrides: raw.log_level_overrides,
+        vod_cache_entries: raw.vod_cache_entries,
+        vod_cache_ttl_secs: raw.vod_cache_ttl_secs,
+        vod_disk_cache_mb: raw.vod_disk_cache_mb,
+        vod_max_duration_hours: raw.vod_max_duration_hours,
+        vod_rate_limit_per_minute: raw.vod_rate_limit_per_minute,
+        vod_rate_limit_burst: raw.vod_rate_limit_burst,
+        vod_max_recordings: raw.vod_max_recordings,
+        vod_max_samples: raw.vod_max_samples,
+        vod_max_size_mb: raw.vod_max_size_mb,
+        recording_index: raw.recording_index,
+        tls,
+        archive,
+        recording_key: raw.recording_key,
+        cors,
         plugin: raw.plugin,
         raw: env_toml,
     })
 }
 
+// The other cors options require `cors_allowed_origins`.
+fn parse_cors(
+    origins: Option<Vec<String>>,
+    methods: Option<Vec<String>>,
+    headers: Option<Vec<String>>,
+    allow_credentials: Option<bool>,
+) -> Result<Option<CorsConfig>, ParseEnvConfigError> {
+    use ParseEnvConfigError::*;
+    let Some(origins) = origins else {
+        if methods.is_some() || headers.is_some() || allow_credentials.is_some() {
+            return Err(CorsIncomplete);
+        }
+        return Ok(None);
+    };
+
+    let allow_credentials = allow_credentials.unwrap_or(false);
+    let allowed_origins = if origins.iter().any(|v| v == "*") {
+        if allow_credentials {
+            return Err(CorsCredentialsWildcard);
+        }
+        CorsOrigins::Any
+    } else {
+        CorsOrigins::List(origins)
+    };
+    Ok(Some(CorsConfig {
+        allowed_origins,
+        allowed_methods: methods.unwrap_or_else(|| {
+            ["GET", "POST", "PUT", "PATCH", "DELETE"]
+                .map(str::to_owned)
+                .to_vec()
+        }),
+        allowed_headers: headers.unwrap_or_else(|| {
+            ["Authorization", "Content-Type", "X-CSRF-TOKEN"]
+                .map(str::to_owned)
+                .to_vec()
+        }),
+        allow_credentials,
+    }))
+}
+
 #[allow(clippy::unwrap_used)]
 #[cfg(test)]
 mod tests {
@@ -240,30 +480,97 @@ mod tests {
         let storage_dir = temp_dir.path().join("storage");
         let config_dir = temp_dir.path().join("config");
         let plugin_dir = temp_dir.path().join("plugin");
+        let archive_dir = temp_dir.path().join("archive");
         let storage_dir = storage_dir.to_str().unwrap();
         let config_dir = config_dir.to_str().unwrap();
         let plugin_dir = plugin_dir.to_str().unwrap();
+        let archive_dir = archive_dir.to_str().unwrap();
         std::fs::create_dir(config_dir).unwrap();
         std::fs::create_dir(plugin_dir).unwrap();
 
         let config = format!(
             "
             port = 2020
+            listen = \"[::1]:8080\"
             storage_dir = \"{storage_dir}\"
             config_dir = \"{config_dir}\"
             plugin_dir = \"/{plugin_dir}\"
             max_disk_usage = 1
+            log_max_age_days = 30
+            log_dedupe_window_secs = 10
+            log_sync_interval_secs = 5
+            log_format = \"json\"
+            log_level = \"info\"
+            log_level_overrides = {{ rtsp = \"debug\" }}
+            vod_cache_entries = 20
+            vod_cache_ttl_secs = 600
+            vod_disk_cache_mb = 500
+            vod_max_duration_hours = 24
+            vod_rate_limit_per_minute = 6
+            vod_rate_limit_burst = 3
+            vod_max_recordings = 1000
+            vod_max_samples = 2000
+            vod_max_size_mb = 3000
+            recording_index = true
+            tls_cert = \"/cert.pem\"
+            tls_key = \"/key.pem\"
+            archive_dir = \"{archive_dir}\"
+            archive_after_days = 7
+            recording_key = \"{}\"
+            cors_allowed_origins = [\"https://a.com\"]
+            cors_allowed_methods = [\"GET\"]
+            cors_allow_credentials = true
         ",
+            "01".repeat(32),
         );
 
         let storage_dir: PathBuf = storage_dir.parse().unwrap();
         let want = EnvConf {
             port: 2020,
+            listen: ListenAddr::Ip((std::net::Ipv6Addr::LOCALHOST, 8080).into()),
             storage_dir: storage_dir.clone(),
             recordings_dir: storage_dir.join("recordings"),
             config_dir: config_dir.parse().unwrap(),
             plugin_dir: plugin_dir.parse().unwrap(),
             max_disk_usage: NonZeroGb::new(ByteSize(GB)).unwrap(),
+            log_max_age_days: NonZeroU32::new(30),
+            log_dedupe_window_secs: NonZeroU32::new(10),
+            log_sync_interval_secs: NonZeroU32::new(5),
+            log_format: Some(LogFormat::Json),
+            log_level: Some(LogLevel::Info),
+            log_level_overrides: Some(HashMap::from([(
+                "rtsp".try_into().unwrap(),
+                LogLevel::Debug,
+            )])),
+            vod_cache_entries: NonZeroU32::new(20),
+            vod_cache_ttl_secs: NonZeroU32::new(600),
+            vod_disk_cache_mb: NonZeroU32::new(500),
+            vod_max_duration_hours: NonZeroU32::new(24),
+            vod_rate_limit_per_minute: NonZeroU32::new(6),
+            vod_rate_limit_burst: NonZeroU32::new(3),
+            vod_max_recordings: NonZeroU32::new(1000),
+            vod_max_samples: NonZeroU32::new(2000),
+            vod_max_size_mb: NonZeroU32::new(3000),
+            recording_index: Some(true),
+            tls: Some(TlsConfig {
+                cert: "/cert.pem".into(),
+                key: "/key.pem".into(),
+            }),
+            archive: Some(ArchiveConfig {
+                dir: archive_dir.parse().unwrap(),
+                max_age: Duration::from_secs(7 * 24 * 60 * 60),
+            }),
+            recording_key: Some(MasterKey::new([1; 32])),
+            cors: Some(CorsConfig {
+                allowed_origins: CorsOrigins::List(vec!["https://a.com".to_owned()]),
+                allowed_methods: vec!["GET".to_owned()],
+                allowed_headers: vec![
+                    "Authorization".to_owned(),
+                    "Content-Type".to_owned(),
+                    "X-CSRF-TOKEN".to_owned(),
+                ],
+                allow_credentials: true,
+            }),
             plugin: None,
             raw: config.clone(),
         };
@@ -308,6 +615,109 @@ mod tests {
         ));
     }
     #[test]
+    fn test_parse_config_listen() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().to_str().unwrap();
+        let config = |listen: &str| {
+            format!(
+                "
+                port = 2020
+                {listen}
+                storage_dir = \"{dir}\"
+                config_dir = \"{dir}\"
+                plugin_dir = \"{dir}\"
+                max_disk_usage = 1
+            "
+            )
+        };
+
+        // Defaults to the port on all IPv4 interfaces.
+        let env = parse_config(config("")).unwrap();
+        assert_eq!(&ListenAddr::Ip(([0, 0, 0, 0], 2020).into()), env.listen());
+
+        let env = parse_config(config("listen = \"localhost:8080\"")).unwrap();
+        assert_eq!(
+            &ListenAddr::Host("localhost".to_owned(), 8080),
+            env.listen()
+        );
+
+        let err = parse_config(config("listen = \"::1\"")).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("IPv6 addresses must be in brackets"),
+            "{err}"
+        );
+    }
+    #[test]
+    fn test_parse_config_tls_incomplete() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().to_str().unwrap();
+        let config = format!(
+            "
+            port = 2020
+            storage_dir = \"{dir}\"
+            config_dir = \"{dir}\"
+            plugin_dir = \"{dir}\"
+            max_disk_usage = 1
+            tls_cert = \"/cert.pem\"
+        "
+        );
+        assert!(matches!(
+            parse_config(config),
+            Err(ParseEnvConfigError::TlsIncomplete)
+        ));
+    }
+    #[test]
+    fn test_parse_config_archive_incomplete() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().to_str().unwrap();
+        let config = format!(
+            "
+            port = 2020
+            storage_dir = \"{dir}\"
+            config_dir = \"{dir}\"
+            plugin_dir = \"{dir}\"
+            max_disk_usage = 1
+            archive_dir = \"{dir}/archive\"
+        "
+        );
+        assert!(matches!(
+            parse_config(config),
+            Err(ParseEnvConfigError::ArchiveIncomplete)
+        ));
+    }
+    #[test]
+    fn test_parse_config_cors() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().to_str().unwrap();
+        let config = |cors: &str| {
+            format!(
+                "
+                port = 2020
+                storage_dir = \"{dir}\"
+                config_dir = \"{dir}\"
+                plugin_dir = \"{dir}\"
+                max_disk_usage = 1
+                {cors}
+            "
+            )
+        };
+
+        let env = parse_config(config("cors_allowed_origins = [\"*\"]")).unwrap();
+        assert_eq!(CorsOrigins::Any, env.cors().unwrap().allowed_origins);
+
+        assert!(matches!(
+            parse_config(config(
+                "cors_allowed_origins = [\"*\"]\ncors_allow_credentials = true"
+            )),
+            Err(ParseEnvConfigError::CorsCredentialsWildcard)
+        ));
+        assert!(matches!(
+            parse_config(config("cors_allow_credentials = false")),
+            Err(ParseEnvConfigError::CorsIncomplete)
+        ));
+    }
+    #[test]
     fn test_parse_config_plugin_dir_abs_error() {
         let config = "
             port = 2020
@@ -4,6 +4,7 @@ mod dir_fs;
 mod error;
 mod map_fs;
 mod sub_fs;
+mod union_fs;
 
 #[cfg(test)]
 mod test;
@@ -14,6 +15,7 @@ mod test_fs;
 pub use dir_fs::dir_fs;
 pub use error::FsError;
 pub use map_fs::{MapEntry, MapFs};
+pub use union_fs::union_fs;
 
 use crate::sub_fs::SubFs;
 use std::{
@@ -1,6 +1,8 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
-use crate::{dir_fs, error::FsError, test_fs::test_file_system, Fs, MapEntry, MapFs};
+use crate::{
+    dir_fs, error::FsError, test_fs::test_file_system, union_fs, DynFs, Fs, MapEntry, MapFs, Open,
+};
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
@@ -110,6 +112,57 @@ fn test_dir_fs() {
     assert!(d.open(Path::new("NUL")).is_err(), "open NUL succeeded");
 }
 
+#[allow(clippy::unwrap_used)]
+#[test]
+fn test_union_fs() {
+    let map_fs = |files: &[(&str, &str)]| -> DynFs {
+        Box::new(MapFs(
+            files
+                .iter()
+                .map(|(name, data)| {
+                    (
+                        PathBuf::from(name),
+                        MapEntry {
+                            data: data.as_bytes().to_vec(),
+                            is_file: true,
+                            is_symlink: false,
+                        },
+                    )
+                })
+                .collect(),
+        ))
+    };
+    let fs = union_fs(vec![
+        map_fs(&[("dir/a", "1"), ("dir/b", "1")]),
+        map_fs(&[("dir/b", "2"), ("dir/c", "2"), ("d", "2")]),
+    ]);
+
+    let Open::Dir(mut dir) = fs.open(Path::new("dir")).unwrap() else {
+        panic!("expected dir");
+    };
+    let names: Vec<_> = dir
+        .read_dir_file()
+        .unwrap()
+        .iter()
+        .map(|v| v.name().to_owned())
+        .collect();
+    assert_eq!(
+        vec![PathBuf::from("a"), PathBuf::from("b"), PathBuf::from("c")],
+        names
+    );
+
+    let Open::File(mut file) = fs.open(Path::new("dir/b")).unwrap() else {
+        panic!("expected file");
+    };
+    assert_eq!(b"1".to_vec(), file.read().unwrap());
+
+    assert!(matches!(fs.open(Path::new("d")), Ok(Open::File(_))));
+    assert!(matches!(
+        fs.open(Path::new("x")),
+        Err(FsError::OpenNotExist)
+    ));
+}
+
 /*fn test_dir_fs_root_dir() {
     let cwd = current_dir().unwrap();
 
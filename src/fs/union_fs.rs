@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::{error::FsError, Dir, DynFs, Entry, Fs, Open};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+// Returns a file system that merges the directories of the layers.
+// Files that exist in more than one layer are opened from the first one.
+#[must_use]
+pub fn union_fs(layers: Vec<DynFs>) -> DynFs {
+    Box::new(UnionFs(layers))
+}
+
+struct UnionFs(Vec<DynFs>);
+
+fn is_not_exist(e: &FsError) -> bool {
+    match e {
+        FsError::OpenNotExist => true,
+        FsError::Io(e) => e.kind() == std::io::ErrorKind::NotFound,
+        _ => false,
+    }
+}
+
+impl Fs for UnionFs {
+    fn open(&self, path: &Path) -> Result<Open, FsError> {
+        let mut dirs = Vec::new();
+        for layer in &self.0 {
+            match layer.open(path) {
+                Ok(Open::Dir(dir)) => dirs.push(dir),
+                Ok(v) => {
+                    if dirs.is_empty() {
+                        return Ok(v);
+                    }
+                }
+                Err(e) if is_not_exist(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if dirs.is_empty() {
+            return Err(FsError::OpenNotExist);
+        }
+        if dirs.len() == 1 {
+            return Ok(Open::Dir(dirs.pop().expect("one dir")));
+        }
+        let name = PathBuf::from(path.file_name().unwrap_or(path.as_os_str()));
+        Ok(Open::Dir(Box::new(UnionDir { name, dirs })))
+    }
+
+    fn clone(&self) -> DynFs {
+        Box::new(UnionFs(self.0.iter().map(|v| Fs::clone(&**v)).collect()))
+    }
+}
+
+struct UnionDir {
+    name: PathBuf,
+    dirs: Vec<Box<dyn Dir>>,
+}
+
+impl Dir for UnionDir {
+    fn name(&self) -> &Path {
+        &self.name
+    }
+
+    fn read_dir_file(&mut self) -> Result<Vec<Entry>, FsError> {
+        let mut seen = HashSet::new();
+        let mut entries = Vec::new();
+        for dir in &mut self.dirs {
+            for entry in dir.read_dir_file()? {
+                if seen.insert(entry.name().to_owned()) {
+                    entries.push(entry);
+                }
+            }
+        }
+        entries.sort_by_key(|v| v.name().to_owned());
+        Ok(entries)
+    }
+}
@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use common::{CorsConfig, CorsOrigins};
+use http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use std::sync::Arc;
+
+// Browsers cache preflight responses for this many seconds.
+const PREFLIGHT_MAX_AGE: &str = "600";
+
+// Adds CORS headers to responses for allowed origins and answers
+// preflight requests before they reach the auth middleware.
+// Requests from other origins are passed through without the
+// headers, the browser then blocks the response.
+pub async fn cors(State(config): State<Arc<CorsConfig>>, request: Request, next: Next) -> Response {
+    let origin = request
+        .headers()
+        .get(header::ORIGIN)
+        .filter(|origin| is_allowed(&config, origin))
+        .cloned();
+
+    let is_preflight = request.method() == Method::OPTIONS
+        && request
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    if is_preflight {
+        let Some(origin) = origin else {
+            let mut response = StatusCode::FORBIDDEN.into_response();
+            add_vary(response.headers_mut(), &config);
+            return response;
+        };
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        add_headers(headers, &config, origin);
+        add_vary(headers, &config);
+        if let Ok(v) = HeaderValue::from_str(&config.allowed_methods.join(", ")) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, v);
+        }
+        if let Ok(v) = HeaderValue::from_str(&config.allowed_headers.join(", ")) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, v);
+        }
+        headers.insert(
+            header::ACCESS_CONTROL_MAX_AGE,
+            HeaderValue::from_static(PREFLIGHT_MAX_AGE),
+        );
+        return response;
+    }
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    if let Some(origin) = origin {
+        add_headers(headers, &config, origin);
+    }
+    add_vary(headers, &config);
+    response
+}
+
+fn is_allowed(config: &CorsConfig, origin: &HeaderValue) -> bool {
+    match &config.allowed_origins {
+        CorsOrigins::Any => true,
+        CorsOrigins::List(origins) => origins.iter().any(|v| v.as_bytes() == origin.as_bytes()),
+    }
+}
+
+fn add_headers(headers: &mut HeaderMap, config: &CorsConfig, origin: HeaderValue) {
+    match config.allowed_origins {
+        CorsOrigins::Any => {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                HeaderValue::from_static("*"),
+            );
+        }
+        CorsOrigins::List(_) => {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        }
+    }
+    if config.allow_credentials {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+}
+
+// With a origin list the response depends on the origin, including responses
+// to other origins and requests without one, they must not be cached for others.
+fn add_vary(headers: &mut HeaderMap, config: &CorsConfig) {
+    if matches!(config.allowed_origins, CorsOrigins::List(_)) {
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+    }
+}
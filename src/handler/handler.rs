@@ -5,35 +5,65 @@
 #[cfg(test)]
 mod test;
 
+mod cors;
+mod rate_limit;
+
+pub use cors::cors;
+pub use rate_limit::RateLimiter;
+
 use axum::{
     body::Body,
-    extract::{Path, Query, State, WebSocketUpgrade},
-    http::{header, HeaderMap, Method, StatusCode, Uri},
-    response::{IntoResponse, Response},
+    extract::{ConnectInfo, Path, Query, RawQuery, State, WebSocketUpgrade},
+    http::{header, HeaderMap, HeaderName, Method, StatusCode, Uri},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     Json,
 };
 use common::{
-    monitor::{ArcMonitorManager, MonitorConfig, MonitorConfigs, MonitorDeleteError},
+    monitor::{
+        ArcMonitorManager, ArcMonitorStats, LatestFrameError, MonitorConfig, MonitorConfigs,
+        MonitorDeleteError, MonitorSetError, MonitorStatus,
+    },
     recording::RecordingId,
-    AccountId, AccountSetRequest, AccountsMap, ArcAuth, ArcLogger, AuthAccountDeleteError, ILogger,
-    LogEntry, LogLevel, MonitorId,
+    time::{TimeZone, UnixNano, MICROSECOND, SECOND},
+    AccountId, AccountSetRequest, AccountsMap, ArcAuth, ArcLogger, ArcTranscoder,
+    AuthAccountDeleteError, DetectionEvent, ILogger, LogEntry, LogLevel, MonitorId, StreamType,
 };
+use csv::deserialize_csv_option;
+use flate2::{write::GzEncoder, Compression};
+use futures_lite::{Stream, StreamExt};
 use hls::{HlsQuery, HlsServer};
 use http::{HeaderValue, Request};
 use log::{
-    log_db::{LogDbHandle, LogQuery},
-    Logger,
+    log_db::{LogDbHandle, LogDbStats, LogFeedItem, LogQuery, QueryLogsError},
+    LogEntryWithTime, Logger, UnixMicro,
 };
 use monitor_groups::ArcMonitorGroups;
-use recdb::{DeleteRecordingError, RecDb, RecDbQuery, RecordingResponse};
+use recdb::{DeleteRecordingError, LockRecordingError, RecDb, RecDbQuery, RecordingResponse};
 use recording::{new_video_reader, VideoCache};
 use rust_embed::EmbeddedFiles;
-use serde::Deserialize;
-use std::{path::PathBuf, sync::Arc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    io::{SeekFrom, Write as _},
+    net::SocketAddr,
+    num::NonZeroU16,
+    path::PathBuf,
+    sync::Arc,
+};
 use thiserror::Error;
-use tokio::sync::{broadcast::error::RecvError, Mutex};
-use tokio_util::io::ReaderStream;
-use vod::{CreateVodReaderError, VodCache, VodQuery, VodReader};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+    sync::{broadcast::error::RecvError, Mutex},
+};
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
+use vod::{
+    export_transcoded, query_events_vtt, CreateVodReaderError, ExportTranscodedError,
+    MultiVodQuery, VodCache, VodConfig, VodQuery, VodReader,
+};
 use web::{serve_mp4_content, Templater};
 
 #[derive(Clone)]
@@ -231,32 +261,314 @@ pub struct VodHandlerState {
     pub logger: Arc<Logger>,
     pub recdb: Arc<RecDb>,
     pub cache: VodCache,
+    pub config: VodConfig,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 pub async fn vod_handler(
     State(state): State<VodHandlerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     query: Query<VodQuery>,
     headers: HeaderMap,
 ) -> Response {
     use CreateVodReaderError::*;
     let monitor_id = query.0.monitor_id.clone();
-    let reader = match VodReader::new(&state.recdb, &state.cache, query.0).await {
+    if query.0.events {
+        return match query_events_vtt(&state.recdb, &state.config, &query.0).await {
+            Ok(Some(v)) => ([(header::CONTENT_TYPE, "text/vtt; charset=UTF-8")], v).into_response(),
+            Ok(None) => (StatusCode::NOT_FOUND, "no video found").into_response(),
+            Err(e @ (NegativeDuration | MaxDuration { .. })) => {
+                (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+            }
+            Err(e) => {
+                state.logger.log(LogEntry::new(
+                    LogLevel::Error,
+                    "app",
+                    Some(monitor_id),
+                    format!("vod events handler: {e}"),
+                ));
+                (StatusCode::INTERNAL_SERVER_ERROR, "error printed to logs").into_response()
+            }
+        };
+    }
+    let etag = query.0.etag();
+    let reader = match new_vod_reader(&state, addr, query.0).await {
+        Ok(v) => v,
+        Err(response) => return response,
+    };
+    if reader.truncated() {
+        state.logger.log(LogEntry::new(
+            LogLevel::Warning,
+            "app",
+            Some(monitor_id),
+            "vod handler: query exceeds the vod limits, the video is truncated".to_owned(),
+        ));
+    }
+    serve_mp4_content(
+        &Method::GET,
+        &headers,
+        reader.last_modified(),
+        Some(&etag),
+        reader.size(),
+        reader,
+    )
+    .await
+}
+
+// Responds with 429 and a `Retry-After` header if the client has no tokens left.
+#[allow(clippy::ref_option, clippy::result_large_err)]
+fn check_rate_limit(limiter: &Option<Arc<RateLimiter>>, addr: SocketAddr) -> Result<(), Response> {
+    let Some(limiter) = limiter else {
+        return Ok(());
+    };
+    let Err(retry_after) = limiter.check(addr.ip()) else {
+        return Ok(());
+    };
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() != 0);
+    Err((
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, secs.to_string())],
+        "too many requests",
+    )
+        .into_response())
+}
+
+// Cache hits are cheap and don't count towards the limit.
+async fn new_vod_reader(
+    state: &VodHandlerState,
+    addr: SocketAddr,
+    q: VodQuery,
+) -> Result<VodReader, Response> {
+    use CreateVodReaderError::*;
+    if state.rate_limiter.is_some() && !state.cache.contains(&q).await {
+        check_rate_limit(&state.rate_limiter, addr)?;
+    }
+    let monitor_id = q.monitor_id.clone();
+    match VodReader::new(&state.recdb, &state.cache, &state.config, q).await {
+        Ok(Some(v)) => Ok(v),
+        Ok(None) => Err((StatusCode::NOT_FOUND, "no video found").into_response()),
+        Err(e @ (NegativeDuration | MaxDuration { .. })) => {
+            Err((StatusCode::BAD_REQUEST, e.to_string()).into_response())
+        }
+        Err(e) => {
+            state.logger.log(LogEntry::new(
+                LogLevel::Error,
+                "app",
+                Some(monitor_id),
+                format!("vod handler: {e}"),
+            ));
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "error printed to logs").into_response())
+        }
+    }
+}
+
+// HLS media playlist of a fragmented query, the segment URIs
+// are relative and include the query of the playlist.
+pub async fn vod_hls_playlist_handler(
+    State(state): State<VodHandlerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    query: Query<VodQuery>,
+    RawQuery(raw_query): RawQuery,
+) -> Response {
+    let monitor_id = query.0.monitor_id.clone();
+    let reader = match new_vod_reader(&state, addr, fragmented(query.0)).await {
+        Ok(v) => v,
+        Err(response) => return response,
+    };
+    if reader.truncated() {
+        state.logger.log(LogEntry::new(
+            LogLevel::Warning,
+            "app",
+            Some(monitor_id),
+            "vod hls handler: query exceeds the vod limits, the video is truncated".to_owned(),
+        ));
+    }
+    let raw_query = raw_query.unwrap_or_default();
+    let playlist = reader
+        .hls_playlist(&format!("hls/init.mp4?{raw_query}"), |i| {
+            format!("hls/segment.m4s?{raw_query}&index={i}")
+        })
+        .expect("reader should be fragmented");
+    (
+        [(header::CONTENT_TYPE, "application/vnd.apple.mpegurl")],
+        playlist,
+    )
+        .into_response()
+}
+
+pub async fn vod_hls_init_handler(
+    State(state): State<VodHandlerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    query: Query<VodQuery>,
+) -> Response {
+    let reader = match new_vod_reader(&state, addr, fragmented(query.0)).await {
+        Ok(v) => v,
+        Err(response) => return response,
+    };
+    let init = reader
+        .hls_init()
+        .expect("reader should be fragmented")
+        .to_vec();
+    ([(header::CONTENT_TYPE, "video/mp4")], init).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct HlsSegmentQuery {
+    pub index: usize,
+}
+
+pub async fn vod_hls_segment_handler(
+    State(state): State<VodHandlerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    query: Query<VodQuery>,
+    segment_query: Query<HlsSegmentQuery>,
+) -> Response {
+    let mut reader = match new_vod_reader(&state, addr, fragmented(query.0)).await {
+        Ok(v) => v,
+        Err(response) => return response,
+    };
+    let segments = reader.hls_segments().expect("reader should be fragmented");
+    let Some(segment) = segments.get(segment_query.index) else {
+        return (StatusCode::NOT_FOUND, "segment not found").into_response();
+    };
+    if let Err(e) = reader.seek(SeekFrom::Start(segment.offset)).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    (
+        [
+            (header::CONTENT_TYPE, "video/mp4".to_owned()),
+            (header::CONTENT_LENGTH, segment.size.to_string()),
+        ],
+        Body::from_stream(ReaderStream::new(reader.take(segment.size))),
+    )
+        .into_response()
+}
+
+fn fragmented(mut q: VodQuery) -> VodQuery {
+    q.fragmented = true;
+    q
+}
+
+// Multi monitor queries are never cached and always count towards the rate limit.
+// Monitors without any recordings in the window don't get a track, the
+// `X-Track-Monitors` header lists the monitor of each track in track order.
+pub async fn multi_vod_handler(
+    State(state): State<VodHandlerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    query: Query<MultiVodQuery>,
+    headers: HeaderMap,
+) -> Response {
+    use CreateVodReaderError::*;
+    if let Err(res) = check_rate_limit(&state.rate_limiter, addr) {
+        return res;
+    }
+    let etag = query.0.etag();
+    let result = VodReader::new_multi(&state.recdb, &state.config, &query.0).await;
+    let (reader, track_monitors) = match result {
         Ok(Some(v)) => v,
         Ok(None) => return (StatusCode::NOT_FOUND, "no video found").into_response(),
-        Err(e @ (NegativeDuration | MaxDuration)) => {
+        Err(e @ (NegativeDuration | MaxDuration { .. } | NoMonitors | TooManyMonitors { .. })) => {
             return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
         }
         Err(e) => {
             state.logger.log(LogEntry::new(
+                LogLevel::Error,
+                "app",
+                None,
+                format!("multi vod handler: {e}"),
+            ));
+            return (StatusCode::INTERNAL_SERVER_ERROR, "error printed to logs").into_response();
+        }
+    };
+    if reader.truncated() {
+        state.logger.log(LogEntry::new(
+            LogLevel::Warning,
+            "app",
+            None,
+            "multi vod handler: query exceeds the vod limits, the video is truncated".to_owned(),
+        ));
+    }
+    let track_monitors: Vec<String> = track_monitors.iter().map(ToString::to_string).collect();
+    let mut response = serve_mp4_content(
+        &Method::GET,
+        &headers,
+        reader.last_modified(),
+        Some(&etag),
+        reader.size(),
+        reader,
+    )
+    .await;
+    if let Ok(v) = HeaderValue::from_str(&track_monitors.join(",")) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static("x-track-monitors"), v);
+    }
+    response
+}
+
+#[derive(Clone)]
+pub struct TranscodedVodHandlerState {
+    pub vod: VodHandlerState,
+
+    // None if no plugin provides a transcoder.
+    pub transcoder: Option<ArcTranscoder>,
+}
+
+#[derive(Deserialize)]
+pub struct TranscodeQuery {
+    pub height: NonZeroU16,
+    pub crf: u8,
+}
+
+// Transcoded videos are never cached and always count towards the rate limit.
+// The size isn't known in advance, so range requests aren't supported.
+pub async fn transcoded_vod_handler(
+    State(state): State<TranscodedVodHandlerState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    query: Query<VodQuery>,
+    transcode_query: Query<TranscodeQuery>,
+) -> Response {
+    use CreateVodReaderError::*;
+    use ExportTranscodedError::*;
+    let Some(transcoder) = &state.transcoder else {
+        return (StatusCode::NOT_IMPLEMENTED, "no transcoder plugin enabled").into_response();
+    };
+    let vod = &state.vod;
+    if let Err(res) = check_rate_limit(&vod.rate_limiter, addr) {
+        return res;
+    }
+    let monitor_id = query.0.monitor_id.clone();
+    let reader = match export_transcoded(
+        &vod.recdb,
+        &vod.cache,
+        &vod.config,
+        transcoder,
+        query.0,
+        transcode_query.height,
+        transcode_query.crf,
+    )
+    .await
+    {
+        Ok(Some(v)) => v,
+        Ok(None) => return (StatusCode::NOT_FOUND, "no video found").into_response(),
+        Err(e @ (InvalidCrf(_) | CreateVodReader(NegativeDuration | MaxDuration { .. }))) => {
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+        Err(e) => {
+            vod.logger.log(LogEntry::new(
                 LogLevel::Error,
                 "app",
                 Some(monitor_id),
-                format!("vod handler: {e}"),
+                format!("transcoded vod handler: {e}"),
             ));
             return (StatusCode::INTERNAL_SERVER_ERROR, "error printed to logs").into_response();
         }
     };
-    serve_mp4_content(&Method::GET, &headers, None, reader.size(), reader).await
+    (
+        [(header::CONTENT_TYPE, "video/mp4")],
+        Body::from_stream(ReaderStream::new(reader)),
+    )
+        .into_response()
 }
 
 const API_HTML: &str = include_str!("./api.html");
@@ -322,12 +634,61 @@ pub struct RecordingQueryHandlerState {
     pub rec_db: Arc<RecDb>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct TimeZoneQuery {
+    // Timestamps are also returned formatted in this time zone.
+    tz: Option<TimeZone>,
+}
+
+fn format_micro(tz: TimeZone, time: UnixMicro) -> String {
+    let nanos = i64::try_from(*time)
+        .unwrap_or(i64::MAX)
+        .saturating_mul(MICROSECOND);
+    tz.format(UnixNano::new(nanos))
+}
+
+#[derive(Debug, Serialize)]
+pub struct LocalRecordingResponse {
+    #[serde(flatten)]
+    rec: RecordingResponse,
+
+    // Only set if the query has a time zone and included data.
+    #[serde(rename = "localStart", skip_serializing_if = "Option::is_none")]
+    local_start: Option<String>,
+    #[serde(rename = "localEnd", skip_serializing_if = "Option::is_none")]
+    local_end: Option<String>,
+}
+
+impl LocalRecordingResponse {
+    fn new(rec: RecordingResponse, tz: Option<TimeZone>) -> Self {
+        let data = if let RecordingResponse::Finalized(v) = &rec {
+            v.data()
+        } else {
+            None
+        };
+        let (local_start, local_end) = match (tz, data) {
+            (Some(tz), Some(data)) => (Some(tz.format(data.start)), Some(tz.format(data.end))),
+            _ => (None, None),
+        };
+        Self {
+            rec,
+            local_start,
+            local_end,
+        }
+    }
+}
+
 pub async fn recording_query_handler(
     State(s): State<RecordingQueryHandlerState>,
     query: Query<RecDbQuery>,
-) -> Result<Json<Vec<RecordingResponse>>, StatusCode> {
+    tz: Query<TimeZoneQuery>,
+) -> Result<Json<Vec<LocalRecordingResponse>>, StatusCode> {
     match s.rec_db.recordings_by_query(&query.0).await {
-        Ok(v) => Ok(Json(v)),
+        Ok(v) => Ok(Json(
+            v.into_iter()
+                .map(|rec| LocalRecordingResponse::new(rec, tz.tz))
+                .collect(),
+        )),
         Err(e) => {
             s.logger.log(LogEntry::new(
                 LogLevel::Error,
@@ -397,14 +758,248 @@ pub async fn log_feed_handler(
     })
 }
 
+#[derive(Debug, Deserialize)]
+pub struct DetectionFeedQuery {
+    // All monitors are included if empty.
+    #[serde(default, deserialize_with = "deserialize_csv_option")]
+    pub monitors: Vec<MonitorId>,
+
+    #[serde(rename = "min-score", default)]
+    pub min_score: f32,
+}
+
+impl DetectionFeedQuery {
+    fn matches(&self, event: &DetectionEvent) -> bool {
+        (self.monitors.is_empty() || self.monitors.contains(&event.monitor_id))
+            && event.score >= self.min_score
+    }
+}
+
+#[derive(Clone)]
+pub struct DetectionFeedHandlerState {
+    pub auth: ArcAuth,
+    pub monitor_manager: ArcMonitorManager,
+}
+
+// Streams detections as JSON messages as they occur. The socket is
+// also read so the task exits when the client disconnects, even if
+// there are no detections to send.
+pub async fn detection_feed_handler(
+    State(s): State<DetectionFeedHandlerState>,
+    headers: HeaderMap,
+    query: Query<DetectionFeedQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    use axum::extract::ws::Message;
+
+    let q = query.0;
+    ws.on_upgrade(move |mut socket| async move {
+        let mut feed = s.monitor_manager.subscribe_detections();
+
+        loop {
+            let event = tokio::select! {
+                msg = socket.recv() => match msg {
+                    None | Some(Err(_) | Ok(Message::Close(_))) => return,
+                    Some(Ok(_)) => continue,
+                },
+                event = feed.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => return,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+            };
+
+            if !q.matches(&event) {
+                continue;
+            }
+
+            // Validate auth before each message.
+            if s.auth.validate_request(&headers).await.is_none() {
+                return;
+            }
+
+            let event_json =
+                serde_json::to_string(&event).expect("serializing `DetectionEvent` to never fail");
+
+            if socket.send(Message::Text(event_json)).await.is_err() {
+                return;
+            }
+        }
+    })
+}
+
+#[derive(Clone)]
+pub struct LogStreamHandlerState {
+    pub log_db: Arc<LogDbHandle>,
+    pub token: CancellationToken,
+}
+
+// Streams saved log entries matching the query as Server-Sent Events.
+// Skipped entries are reported with a "lagged" event. The subscription
+// is dropped when the client disconnects.
+pub async fn log_stream_handler(
+    State(s): State<LogStreamHandlerState>,
+    query: Query<LogQuery>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let stream = s
+        .log_db
+        .subscribe(query.0, s.token.child_token())
+        .map(|item| match item {
+            LogFeedItem::Entry(entry) => Event::default().json_data(entry),
+            LogFeedItem::Lagged(n) => Ok(Event::default().event("lagged").data(n.to_string())),
+        });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+// Position in the log after the last entry of a page. Entry
+// times are unique, so no entries are skipped or repeated.
+// Encoded as 16 hex digits to keep it opaque and URL-safe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogCursor(UnixMicro);
+
+impl Serialize for LogCursor {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:016x}", *self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for LogCursor {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s.len() != 16 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(serde::de::Error::custom("invalid cursor"));
+        }
+        let v =
+            u64::from_str_radix(&s, 16).map_err(|_| serde::de::Error::custom("invalid cursor"))?;
+        Ok(Self(UnixMicro::new(v)))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LogCursorQuery {
+    // The `nextCursor` of the previous page, replaces `time`.
+    cursor: Option<LogCursor>,
+}
+
+#[derive(Debug, Serialize)]
+struct LocalLogEntry {
+    #[serde(flatten)]
+    entry: LogEntryWithTime,
+
+    // Only set if the query has a time zone.
+    #[serde(rename = "localTime", skip_serializing_if = "Option::is_none")]
+    local_time: Option<String>,
+}
+
+impl LocalLogEntry {
+    fn new(entry: LogEntryWithTime, tz: Option<TimeZone>) -> Self {
+        Self {
+            local_time: tz.map(|tz| format_micro(tz, entry.time)),
+            entry,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LogQueryResponse {
+    entries: Vec<LocalLogEntry>,
+
+    // None if this is the last page.
+    next_cursor: Option<LogCursor>,
+}
+
 pub async fn log_query_handler(
     State(log_db): State<Arc<LogDbHandle>>,
     query: Query<LogQuery>,
+    cursor: Query<LogCursorQuery>,
+    tz: Query<TimeZoneQuery>,
 ) -> Response {
-    match log_db.query(query.0).await {
-        Ok(v) => Json::from(v).into_response(),
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    let mut query = query.0;
+    if let Some(cursor) = cursor.0.cursor {
+        query.time = Some(cursor.0);
     }
+    let limit = query.limit;
+    let Ok(entries) = log_db.query(query).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    // A page with fewer entries than the limit is the last one.
+    let next_cursor = match (limit, entries.last()) {
+        (Some(limit), Some(last)) if entries.len() >= limit.get() => Some(LogCursor(last.time)),
+        _ => None,
+    };
+    Json(LogQueryResponse {
+        entries: entries
+            .into_iter()
+            .map(|v| LocalLogEntry::new(v, tz.tz))
+            .collect(),
+        next_cursor,
+    })
+    .into_response()
+}
+
+// Size of the buffer between the export task and the response body.
+const LOG_EXPORT_BUFFER_SIZE: usize = 64 * 1024;
+
+// Streams the entries matching the query, newest first, as gzip compressed
+// NDJSON. Pages are only queried as fast as the client reads the response.
+pub async fn log_export_handler(
+    State(log_db): State<Arc<LogDbHandle>>,
+    query: Query<LogQuery>,
+    tz: Query<TimeZoneQuery>,
+) -> Response {
+    let (reader, writer) = tokio::io::duplex(LOG_EXPORT_BUFFER_SIZE);
+    tokio::spawn(async move {
+        // The gzip stream is left unfinished on error,
+        // so the client can't mistake it for a complete export.
+        _ = export_logs_gzip(&log_db, query.0, tz.tz, writer).await;
+    });
+    (
+        [
+            (header::CONTENT_TYPE, "application/gzip"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"logs.ndjson.gz\"",
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(reader)),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Error)]
+enum ExportLogsError {
+    #[error("query: {0}")]
+    Query(#[from] QueryLogsError),
+
+    #[error("serialize: {0}")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("write: {0}")]
+    Write(#[from] std::io::Error),
+}
+
+async fn export_logs_gzip<W: AsyncWrite + Unpin>(
+    log_db: &LogDbHandle,
+    q: LogQuery,
+    tz: Option<TimeZone>,
+    mut out: W,
+) -> Result<(), ExportLogsError> {
+    let mut pages = log_db.query_pages(q);
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    while let Some(entries) = pages.next().await? {
+        for entry in entries {
+            serde_json::to_writer(&mut gz, &LocalLogEntry::new(entry, tz))?;
+            gz.write_all(b"\n")?;
+        }
+
+        // Blocks until the client has read enough of the previous pages.
+        out.write_all(gz.get_ref()).await?;
+        gz.get_mut().clear();
+    }
+    out.write_all(&gz.finish()?).await?;
+    out.shutdown().await?;
+    Ok(())
 }
 
 #[derive(Debug, Deserialize)]
@@ -436,6 +1031,9 @@ pub async fn monitor_put_handler(
                     StatusCode::OK.into_response()
                 }
             }
+            Err(e @ MonitorSetError::NoRecordingKey) => {
+                (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+            }
             Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
         }
     })
@@ -460,6 +1058,274 @@ pub async fn monitors_handler(
     Json(monitor_manager.monitor_configs().await.clone())
 }
 
+pub async fn monitors_status_handler(
+    State(monitor_manager): State<ArcMonitorManager>,
+) -> Json<Vec<MonitorStatus>> {
+    Json(monitor_manager.status().await)
+}
+
+// Returns the latest keyframe of the monitor as a jpeg,
+// the capture time is in the `X-Capture-Time` header.
+pub async fn monitor_snapshot_handler(
+    State(monitor_manager): State<ArcMonitorManager>,
+    Path(monitor_id): Path<MonitorId>,
+) -> Response {
+    match monitor_manager.latest_frame_jpeg(monitor_id).await {
+        Ok(Some(snapshot)) => (
+            [
+                (header::CONTENT_TYPE, "image/jpeg".to_owned()),
+                (header::CACHE_CONTROL, "no-store".to_owned()),
+                (
+                    HeaderName::from_static("x-capture-time"),
+                    snapshot.time.to_string(),
+                ),
+            ],
+            (*snapshot.jpeg).clone(),
+        )
+            .into_response(),
+        Ok(None) => (StatusCode::SERVICE_UNAVAILABLE, "no frame received yet").into_response(),
+        Err(e @ LatestFrameError::NotRunning(_)) => {
+            (StatusCode::NOT_FOUND, e.to_string()).into_response()
+        }
+        Err(e @ LatestFrameError::Encode(_)) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HealthHandlerState {
+    pub monitor_manager: ArcMonitorManager,
+    pub log_db: Arc<LogDbHandle>,
+    pub rec_db: Arc<RecDb>,
+}
+
+// Running monitors that haven't received a frame for this long are unhealthy,
+// also used as the grace period for the first frame after the monitor starts.
+const HEALTH_MAX_FRAME_AGE: i64 = 30 * SECOND;
+
+// The log database is unhealthy if a save failed within this window.
+const HEALTH_LOG_ERROR_WINDOW: i64 = 60 * SECOND;
+
+#[derive(Debug, Serialize)]
+struct Health {
+    healthy: bool,
+
+    // Omitted from the unauthenticated check.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failing: Option<Vec<HealthFailure>>,
+}
+
+#[derive(Debug, Serialize)]
+struct HealthFailure {
+    subsystem: &'static str,
+    message: String,
+}
+
+// Readiness check, responds with 503 if unhealthy. The failing
+// subsystems are only included by the authenticated health check.
+pub async fn healthz_handler(State(s): State<HealthHandlerState>) -> Response {
+    health_response(&s, false).await
+}
+
+// Readiness check with the failing subsystems.
+pub async fn health_handler(State(s): State<HealthHandlerState>) -> Response {
+    health_response(&s, true).await
+}
+
+async fn health_response(s: &HealthHandlerState, details: bool) -> Response {
+    let failures = health_failures(s, UnixNano::now()).await;
+    let status = if failures.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let health = Health {
+        healthy: failures.is_empty(),
+        failing: details.then_some(failures),
+    };
+    (status, Json(health)).into_response()
+}
+
+async fn health_failures(s: &HealthHandlerState, now: UnixNano) -> Vec<HealthFailure> {
+    let mut failures = Vec::new();
+    for monitor in s.monitor_manager.status().await {
+        if !monitor.running {
+            continue;
+        }
+        if let Some(last_frame) = monitor.last_frame {
+            let age = *now - *last_frame;
+            if age > HEALTH_MAX_FRAME_AGE {
+                failures.push(HealthFailure {
+                    subsystem: "monitor",
+                    message: format!(
+                        "monitor '{}' hasn't received a frame in {}s",
+                        monitor.id,
+                        age / SECOND
+                    ),
+                });
+            }
+        } else if let Some(started) = monitor.started {
+            // Monitors without a frame are still connecting during the grace period.
+            let age = *now - *started;
+            if age > HEALTH_MAX_FRAME_AGE {
+                failures.push(HealthFailure {
+                    subsystem: "monitor",
+                    message: format!(
+                        "monitor '{}' hasn't received a frame since it started {}s ago",
+                        monitor.id,
+                        age / SECOND
+                    ),
+                });
+            }
+        }
+    }
+
+    if let Some(last_error) = s.log_db.last_save_error() {
+        if *now - *last_error < HEALTH_LOG_ERROR_WINDOW {
+            failures.push(HealthFailure {
+                subsystem: "log_db",
+                message: "failed to save log entry".to_owned(),
+            });
+        }
+    }
+
+    match s.rec_db.disk_usage_percent().await {
+        Ok(percent) if percent >= 100.0 => failures.push(HealthFailure {
+            subsystem: "disk",
+            message: format!("disk usage is {percent:.0}% of max_disk_usage"),
+        }),
+        Ok(_) => {}
+        Err(e) => failures.push(HealthFailure {
+            subsystem: "disk",
+            message: format!("calculate disk usage: {e}"),
+        }),
+    }
+    failures
+}
+
+#[derive(Clone)]
+pub struct MetricsHandlerState {
+    pub monitor_manager: ArcMonitorManager,
+    pub log_db: Arc<LogDbHandle>,
+    pub rec_db: Arc<RecDb>,
+}
+
+// Prometheus metrics in the text exposition format.
+pub async fn metrics_handler(State(s): State<MetricsHandlerState>) -> Response {
+    let monitors = s.monitor_manager.monitors_stats().await;
+    let disk_usage = s.rec_db.disk_usage().await.ok();
+    let body = render_metrics(&monitors, disk_usage, s.log_db.stats());
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response()
+}
+
+fn render_metrics(
+    monitors: &HashMap<MonitorId, ArcMonitorStats>,
+    disk_usage: Option<u64>,
+    log: LogDbStats,
+) -> String {
+    // Monitor IDs are alphanumeric and don't need to be escaped.
+    let mut monitors: Vec<_> = monitors.iter().collect();
+    monitors.sort_by_key(|(id, _)| id.to_string());
+
+    let mut out = String::new();
+    write_metric_header(
+        &mut out,
+        "sentryshot_frames_total",
+        "counter",
+        "Frames received from the monitor source.",
+    );
+    for (id, stats) in &monitors {
+        for stream_type in [StreamType::Main, StreamType::Sub] {
+            _ = writeln!(
+                out,
+                "sentryshot_frames_total{{monitor=\"{id}\",stream=\"{}\"}} {}",
+                stream_type.name(),
+                stats.frames(stream_type),
+            );
+        }
+    }
+
+    write_metric_header(
+        &mut out,
+        "sentryshot_frames_dropped_total",
+        "counter",
+        "Frames skipped by decoders that couldn't keep up with the source.",
+    );
+    for (id, stats) in &monitors {
+        _ = writeln!(
+            out,
+            "sentryshot_frames_dropped_total{{monitor=\"{id}\"}} {}",
+            stats.frames_dropped(),
+        );
+    }
+
+    write_metric_header(
+        &mut out,
+        "sentryshot_detections_total",
+        "counter",
+        "Detections emitted by the monitor detectors.",
+    );
+    for (id, stats) in &monitors {
+        _ = writeln!(
+            out,
+            "sentryshot_detections_total{{monitor=\"{id}\"}} {}",
+            stats.detections(),
+        );
+    }
+
+    write_metric_header(
+        &mut out,
+        "sentryshot_detections_rate_limited_total",
+        "counter",
+        "Detections dropped by the monitor event rate limit.",
+    );
+    for (id, stats) in &monitors {
+        _ = writeln!(
+            out,
+            "sentryshot_detections_rate_limited_total{{monitor=\"{id}\"}} {}",
+            stats.detections_rate_limited(),
+        );
+    }
+
+    if let Some(disk_usage) = disk_usage {
+        write_metric_header(
+            &mut out,
+            "sentryshot_storage_used_bytes",
+            "gauge",
+            "Disk space used by the storage directory.",
+        );
+        _ = writeln!(out, "sentryshot_storage_used_bytes {disk_usage}");
+    }
+
+    for (name, help, value) in [
+        (
+            "sentryshot_log_entries_written_total",
+            "Log entries written to the log database.",
+            log.entries_written,
+        ),
+        (
+            "sentryshot_log_entries_dropped_total",
+            "Log entries dropped because the log database fell behind.",
+            log.entries_dropped_lagged,
+        ),
+        (
+            "sentryshot_log_save_errors_total",
+            "Log entries that failed to be written.",
+            log.save_errors,
+        ),
+    ] {
+        write_metric_header(&mut out, name, "counter", help);
+        _ = writeln!(out, "{name} {value}");
+    }
+    out
+}
+
+fn write_metric_header(out: &mut String, name: &str, typ: &str, help: &str) {
+    _ = writeln!(out, "# HELP {name} {help}");
+    _ = writeln!(out, "# TYPE {name} {typ}");
+}
+
 pub async fn monitor_groups_get_handler(
     State(monitor_groups): State<ArcMonitorGroups>,
 ) -> Json<monitor_groups::Groups> {
@@ -494,6 +1360,35 @@ pub async fn recording_delete_handler(
     }
 }
 
+pub async fn recording_lock_get_handler(
+    State(rec_db): State<Arc<RecDb>>,
+    Path(rec_id): Path<RecordingId>,
+) -> Json<bool> {
+    Json(rec_db.is_locked(&rec_id).await)
+}
+
+pub async fn recording_lock_put_handler(
+    State(rec_db): State<Arc<RecDb>>,
+    Path(rec_id): Path<RecordingId>,
+) -> Response {
+    recording_set_locked(&rec_db, &rec_id, true).await
+}
+
+pub async fn recording_lock_delete_handler(
+    State(rec_db): State<Arc<RecDb>>,
+    Path(rec_id): Path<RecordingId>,
+) -> Response {
+    recording_set_locked(&rec_db, &rec_id, false).await
+}
+
+async fn recording_set_locked(rec_db: &RecDb, rec_id: &RecordingId, locked: bool) -> Response {
+    match rec_db.set_locked(rec_id, locked).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(LockRecordingError::NotExist) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
 pub async fn recording_thumbnail_handler(
     State(rec_db): State<Arc<RecDb>>,
     Path(rec_id): Path<RecordingId>,
@@ -545,7 +1440,21 @@ pub async fn recording_video_handler(
         return (StatusCode::NOT_FOUND).into_response();
     };
 
-    let video = match new_video_reader(path, query.cache_id, &Some(state.video_cache)).await {
+    let key = match state.rec_db.recording_key(&rec_id).await {
+        Ok(v) => v,
+        Err(e) => {
+            state.logger.log(LogEntry::new(
+                LogLevel::Error,
+                "app",
+                None,
+                format!("video request: recording key: {e}"),
+            ));
+            return (StatusCode::INTERNAL_SERVER_ERROR, "see logs for details").into_response();
+        }
+    };
+
+    let cache = Some(state.video_cache);
+    let video = match new_video_reader(path, query.cache_id, &cache, key.as_ref()).await {
         Ok(v) => v,
         Err(e) => {
             state.logger.log(LogEntry::new(
@@ -562,6 +1471,7 @@ pub async fn recording_video_handler(
         &Method::GET,
         &headers,
         Some(video.last_modified()),
+        None,
         video.size(),
         video,
     )
@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use common::RateLimit;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Token bucket rate limiter keyed by client IP.
+pub struct RateLimiter {
+    burst: f64,
+
+    // Tokens per second.
+    refill_rate: f64,
+
+    state: Mutex<State>,
+}
+
+struct State {
+    buckets: HashMap<IpAddr, Bucket>,
+    next_prune: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// New clients are limited until the next prune if there are this many buckets.
+const MAX_BUCKETS: usize = 1000;
+
+// How often full buckets are removed.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            burst: f64::from(limit.burst.get()),
+            refill_rate: f64::from(limit.per_minute.get()) / 60.0,
+            state: Mutex::new(State {
+                buckets: HashMap::new(),
+                next_prune: Instant::now() + PRUNE_INTERVAL,
+            }),
+        }
+    }
+
+    // Takes a token from the client's bucket. Returns how
+    // long the client has to wait if the bucket is empty.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut state = self.state.lock().expect("not poisoned");
+        if now >= state.next_prune {
+            state
+                .buckets
+                .retain(|_, bucket| self.tokens(bucket, now) < self.burst);
+            state.next_prune = now + PRUNE_INTERVAL;
+        }
+        if state.buckets.len() >= MAX_BUCKETS && !state.buckets.contains_key(&ip) {
+            return Err(state.next_prune.saturating_duration_since(now));
+        }
+
+        let bucket = state.buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.tokens(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            let wait = (1.0 - bucket.tokens) / self.refill_rate;
+            return Err(Duration::from_secs_f64(wait));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    fn tokens(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.refill_rate).min(self.burst)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use std::{net::Ipv4Addr, num::NonZeroU32};
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(RateLimit {
+            burst: NonZeroU32::new(2).unwrap(),
+            per_minute: NonZeroU32::new(60).unwrap(),
+        });
+        let ip1: IpAddr = [1, 1, 1, 1].into();
+        let ip2: IpAddr = [2, 2, 2, 2].into();
+        let start = Instant::now();
+
+        limiter.check_at(ip1, start).unwrap();
+        limiter.check_at(ip1, start).unwrap();
+        assert_eq!(Err(Duration::from_secs(1)), limiter.check_at(ip1, start));
+
+        // Other clients have their own bucket.
+        limiter.check_at(ip2, start).unwrap();
+
+        // Refill.
+        let later = start + Duration::from_millis(1500);
+        limiter.check_at(ip1, later).unwrap();
+        assert_eq!(
+            Err(Duration::from_millis(500)),
+            limiter.check_at(ip1, later)
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter_max_buckets() {
+        let limiter = RateLimiter::new(RateLimit {
+            burst: NonZeroU32::new(2).unwrap(),
+            per_minute: NonZeroU32::new(60).unwrap(),
+        });
+        let next_prune = limiter.state.lock().unwrap().next_prune;
+        let start = next_prune.checked_sub(PRUNE_INTERVAL).unwrap();
+        for i in 0..MAX_BUCKETS {
+            let ip: IpAddr = Ipv4Addr::from(u32::try_from(i).unwrap()).into();
+            limiter.check_at(ip, start).unwrap();
+        }
+
+        // New clients must wait for the next prune.
+        let ip1: IpAddr = [1, 1, 1, 1].into();
+        let ip2: IpAddr = [0, 0, 0, 0].into();
+        let later = start + Duration::from_secs(10);
+        assert_eq!(
+            Err(PRUNE_INTERVAL.saturating_sub(Duration::from_secs(10))),
+            limiter.check_at(ip1, later)
+        );
+        limiter.check_at(ip2, later).unwrap();
+
+        // The buckets have refilled.
+        let later = start + PRUNE_INTERVAL;
+        limiter.check_at(ip1, later).unwrap();
+        assert_eq!(1, limiter.state.lock().unwrap().buckets.len());
+    }
+}
@@ -2,15 +2,55 @@
 
 #![allow(clippy::unwrap_used)]
 
-use crate::asset_handler;
+use crate::{
+    asset_handler, cors, detection_feed_handler, health_failures, health_handler, healthz_handler,
+    log_export_handler, log_query_handler, metrics_handler, monitor_snapshot_handler,
+    transcoded_vod_handler, vod_handler, DetectionFeedHandlerState, HealthHandlerState, LogCursor,
+    LogCursorQuery, MetricsHandlerState, RateLimiter, TimeZoneQuery, TranscodedVodHandlerState,
+    VodHandlerState,
+};
+use async_trait::async_trait;
 use axum::{
     body::to_bytes,
-    extract::{Path, State},
+    extract::{ConnectInfo, Path, Query, State},
     response::IntoResponse,
+    routing::get,
+    Router,
+};
+use bytesize::ByteSize;
+use common::{
+    monitor::{
+        ArcLatestFrame, ArcMonitorHooks, ArcMonitorStats, IMonitorManager, Keyframe,
+        LatestFrameError, MonitorApplyConfigError, MonitorConfig, MonitorConfigs,
+        MonitorDeleteError, MonitorInfo, MonitorRestartError, MonitorSetAndRestartError,
+        MonitorSetError, MonitorStatus, Snapshot,
+    },
+    time::{UnixNano, SECOND},
+    AccountId, AccountSetRequest, AccountsMap, AuthAccountDeleteError, AuthAccountSetError,
+    Authenticator, CorsConfig, CorsOrigins, DetectionEvent, DummyLogger, LogLevel, MonitorId,
+    PaddedBytes, RateLimit, Region, StreamType, TranscodeError, Transcoder, ValidateResponse,
+};
+use flate2::read::GzDecoder;
+use futures_lite::StreamExt;
+use http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode, Uri};
+use log::{
+    log_db::{LogDb, LogDbHandle, LogQuery},
+    LogEntryWithTime, Logger,
 };
-use http::{header, HeaderMap, StatusCode};
 use pretty_assertions::assert_eq;
-use std::{borrow::Cow, collections::HashMap};
+use recdb::{Disk, RecDb};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    io::Read,
+    net::SocketAddr,
+    num::{NonZeroU16, NonZeroU32, NonZeroUsize},
+    sync::Arc,
+};
+use tempfile::tempdir;
+use tokio::{io::AsyncRead, sync::broadcast};
+use tokio_tungstenite::tungstenite::Message;
+use vod::{VodCache, VodConfig};
 
 #[tokio::test]
 async fn handle_assets_ok() {
@@ -45,3 +85,701 @@ async fn handle_assets_404() {
         to_bytes(response.into_body(), usize::MAX).await.unwrap()
     );
 }
+
+struct StubMonitorManager {
+    stats: HashMap<MonitorId, ArcMonitorStats>,
+    detection_tx: broadcast::Sender<DetectionEvent>,
+    latest_frame: ArcLatestFrame,
+}
+
+impl StubMonitorManager {
+    fn new(stats: HashMap<MonitorId, ArcMonitorStats>) -> Self {
+        Self {
+            stats,
+            detection_tx: broadcast::channel(16).0,
+            latest_frame: ArcLatestFrame::default(),
+        }
+    }
+}
+
+const STUB_JPEG: &[u8] = &[0xff, 0xd8, 0xff, 0xd9];
+
+#[async_trait]
+impl IMonitorManager for StubMonitorManager {
+    async fn start_monitors(&self, _: ArcMonitorHooks) {
+        unimplemented!()
+    }
+    async fn monitor_restart(&self, _: MonitorId) -> Result<(), MonitorRestartError> {
+        unimplemented!()
+    }
+    async fn monitor_set(&self, _: MonitorConfig) -> Result<bool, MonitorSetError> {
+        unimplemented!()
+    }
+    async fn monitor_set_and_restart(
+        &self,
+        _: MonitorConfig,
+    ) -> Result<bool, MonitorSetAndRestartError> {
+        unimplemented!()
+    }
+    async fn monitor_set_and_restart_graceful(
+        &self,
+        _: MonitorConfig,
+    ) -> Result<bool, MonitorSetAndRestartError> {
+        unimplemented!()
+    }
+    async fn monitor_delete(&self, _: MonitorId) -> Result<(), MonitorDeleteError> {
+        unimplemented!()
+    }
+    async fn monitors_info(&self) -> HashMap<MonitorId, MonitorInfo> {
+        unimplemented!()
+    }
+    async fn monitor_config(&self, _: MonitorId) -> Option<MonitorConfig> {
+        unimplemented!()
+    }
+    async fn monitor_configs(&self) -> MonitorConfigs {
+        unimplemented!()
+    }
+    async fn stop(&self) {
+        unimplemented!()
+    }
+    async fn monitor_is_running(&self, _: MonitorId) -> bool {
+        unimplemented!()
+    }
+    async fn monitors_stats(&self) -> HashMap<MonitorId, ArcMonitorStats> {
+        self.stats.clone()
+    }
+    async fn apply_config(&self, _: MonitorConfigs) -> Result<(), MonitorApplyConfigError> {
+        unimplemented!()
+    }
+    async fn status(&self) -> Vec<MonitorStatus> {
+        self.stats
+            .iter()
+            .map(|(id, stats)| MonitorStatus {
+                id: id.clone(),
+                running: true,
+                last_frame: stats.last_frame(),
+                started: Some(UnixNano::new(0)),
+                recording: stats.recording(),
+                gop_length: stats.gop_length(),
+            })
+            .collect()
+    }
+    async fn latest_frame_jpeg(
+        &self,
+        monitor_id: MonitorId,
+    ) -> Result<Option<Snapshot>, LatestFrameError> {
+        if !self.stats.contains_key(&monitor_id) {
+            return Err(LatestFrameError::NotRunning(monitor_id.to_string()));
+        }
+        Ok(self.latest_frame.get().map(|keyframe| Snapshot {
+            jpeg: Arc::new(STUB_JPEG.to_vec()),
+            time: keyframe.time,
+        }))
+    }
+    fn subscribe_detections(&self) -> broadcast::Receiver<DetectionEvent> {
+        self.detection_tx.subscribe()
+    }
+}
+
+struct StubAuth;
+
+#[async_trait]
+impl Authenticator for StubAuth {
+    async fn validate_request(&self, _: &HeaderMap<HeaderValue>) -> Option<ValidateResponse> {
+        Some(ValidateResponse {
+            is_admin: false,
+            token: String::new(),
+            token_valid: true,
+        })
+    }
+    async fn accounts(&self) -> AccountsMap {
+        unimplemented!()
+    }
+    async fn account_set(&self, _: AccountSetRequest) -> Result<bool, AuthAccountSetError> {
+        unimplemented!()
+    }
+    async fn account_delete(&self, _: &AccountId) -> Result<(), AuthAccountDeleteError> {
+        unimplemented!()
+    }
+}
+
+#[tokio::test]
+async fn handle_metrics() {
+    let temp_dir = tempdir().unwrap();
+    let log_dir = temp_dir.path().join("logs");
+    let rec_dir = temp_dir.path().join("recordings");
+    std::fs::create_dir(&rec_dir).unwrap();
+
+    let stats1 = ArcMonitorStats::default();
+    stats1.add_frame(StreamType::Main);
+    stats1.add_frame(StreamType::Main);
+    stats1.add_frame(StreamType::Sub);
+    stats1.add_detections(3);
+    let stats2 = ArcMonitorStats::default();
+    let monitor_manager = Arc::new(StubMonitorManager::new(HashMap::from([
+        ("a".to_owned().try_into().unwrap(), stats1),
+        ("b".to_owned().try_into().unwrap(), stats2),
+    ])));
+
+    let log_db = new_test_log_db(&log_dir, []).await;
+    let rec_db = RecDb::new(
+        DummyLogger::new(),
+        rec_dir.clone(),
+        Disk::new(rec_dir, ByteSize(1)),
+    );
+
+    let response = metrics_handler(State(MetricsHandlerState {
+        monitor_manager,
+        log_db,
+        rec_db: Arc::new(rec_db),
+    }))
+    .await
+    .into_response();
+    assert_eq!(StatusCode::OK, response.status());
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body = std::str::from_utf8(&body).unwrap();
+
+    // Every sample must be a known metric followed by a number.
+    let mut types = HashSet::new();
+    let mut samples = HashMap::new();
+    for line in body.lines() {
+        if let Some(line) = line.strip_prefix("# TYPE ") {
+            let (name, typ) = line.split_once(' ').unwrap();
+            assert!(typ == "counter" || typ == "gauge", "{line}");
+            types.insert(name.to_owned());
+            continue;
+        }
+        if line.starts_with("# HELP ") {
+            continue;
+        }
+        let (series, value) = line.rsplit_once(' ').unwrap();
+        let name = series.split('{').next().unwrap();
+        assert!(types.contains(name), "missing type: {line}");
+        if let Some(labels) = series.strip_prefix(name).filter(|v| !v.is_empty()) {
+            assert!(labels.starts_with("{monitor=\"") && labels.ends_with("\"}"));
+        }
+        samples.insert(series.to_owned(), value.parse::<f64>().unwrap());
+    }
+
+    assert_eq!(
+        Some(&2.0),
+        samples.get(r#"sentryshot_frames_total{monitor="a",stream="main"}"#)
+    );
+    assert_eq!(
+        Some(&1.0),
+        samples.get(r#"sentryshot_frames_total{monitor="a",stream="sub"}"#)
+    );
+    assert_eq!(
+        Some(&0.0),
+        samples.get(r#"sentryshot_frames_dropped_total{monitor="a"}"#)
+    );
+    assert_eq!(
+        Some(&3.0),
+        samples.get(r#"sentryshot_detections_total{monitor="a"}"#)
+    );
+    assert_eq!(
+        Some(&0.0),
+        samples.get(r#"sentryshot_detections_total{monitor="b"}"#)
+    );
+    assert_eq!(
+        Some(&0.0),
+        samples.get(r#"sentryshot_detections_rate_limited_total{monitor="a"}"#)
+    );
+    assert_eq!(
+        Some(&0.0),
+        samples.get("sentryshot_log_entries_written_total")
+    );
+    assert!(samples.contains_key("sentryshot_storage_used_bytes"));
+}
+
+async fn new_test_log_db(
+    dir: &std::path::Path,
+    entries: impl IntoIterator<Item = LogEntryWithTime>,
+) -> Arc<LogDbHandle> {
+    let log_db = Arc::new(LogDb::new_testing(dir.to_owned()));
+    for entry in entries {
+        log_db.save_log_testing(entry).await;
+    }
+    log_db
+}
+
+#[tokio::test]
+async fn handle_log_export() {
+    let temp_dir = tempdir().unwrap();
+    let entries = (1..=3).map(|time| LogEntryWithTime::new_testing(time, LogLevel::Info));
+    let log_db = new_test_log_db(temp_dir.path(), entries).await;
+
+    let export = |limit| {
+        let log_db = log_db.clone();
+        async move {
+            let query = LogQuery {
+                limit: NonZeroUsize::new(limit),
+                ..Default::default()
+            };
+            let response = log_export_handler(
+                State(log_db),
+                Query(query),
+                Query(TimeZoneQuery { tz: None }),
+            )
+            .await
+            .into_response();
+            assert_eq!(StatusCode::OK, response.status());
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+            let mut ndjson = String::new();
+            GzDecoder::new(body.as_ref())
+                .read_to_string(&mut ndjson)
+                .unwrap();
+            ndjson
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect::<Vec<LogEntryWithTime>>()
+        }
+    };
+
+    let want = log_db.query(LogQuery::default()).await.unwrap();
+    assert_eq!(3, want.len());
+    assert_eq!(want, export(0).await);
+    assert_eq!(want[..2], export(2).await);
+}
+
+#[tokio::test]
+async fn handle_log_query_cursor() {
+    let temp_dir = tempdir().unwrap();
+    let entries = (1..=5).map(|time| LogEntryWithTime::new_testing(time, LogLevel::Info));
+    let log_db = new_test_log_db(temp_dir.path(), entries).await;
+
+    let mut times = Vec::new();
+    let mut cursor = None;
+    for _ in 0..3 {
+        let query = LogQuery {
+            limit: NonZeroUsize::new(2),
+            ..Default::default()
+        };
+        let response = log_query_handler(
+            State(log_db.clone()),
+            Query(query),
+            Query(LogCursorQuery { cursor }),
+            Query(TimeZoneQuery { tz: None }),
+        )
+        .await;
+        assert_eq!(StatusCode::OK, response.status());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let entries: Vec<LogEntryWithTime> =
+            serde_json::from_value(body["entries"].clone()).unwrap();
+        times.extend(entries.iter().map(|v| *v.time));
+        cursor = serde_json::from_value::<Option<LogCursor>>(body["nextCursor"].clone()).unwrap();
+    }
+    assert_eq!(vec![5, 4, 3, 2, 1], times);
+    assert_eq!(None, cursor);
+}
+
+#[tokio::test]
+async fn handle_log_query_time_zone() {
+    let temp_dir = tempdir().unwrap();
+    // One second before and after Europe/Stockholm switched to DST.
+    let entries = [1_711_846_799_000_000, 1_711_846_800_000_000]
+        .map(|time| LogEntryWithTime::new_testing(time, LogLevel::Info));
+    let log_db = new_test_log_db(temp_dir.path(), entries).await;
+
+    let query = |tz: Option<&str>| {
+        let log_db = log_db.clone();
+        let tz = tz.map(|v| v.parse().unwrap());
+        async move {
+            let response = log_query_handler(
+                State(log_db),
+                Query(LogQuery::default()),
+                Query(LogCursorQuery { cursor: None }),
+                Query(TimeZoneQuery { tz }),
+            )
+            .await;
+            assert_eq!(StatusCode::OK, response.status());
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            body["entries"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v["localTime"].clone())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(
+        vec![
+            serde_json::json!("2024-03-31T03:00:00.000+02:00"),
+            serde_json::json!("2024-03-31T01:59:59.000+01:00"),
+        ],
+        query(Some("Europe/Stockholm")).await
+    );
+    assert_eq!(
+        vec![
+            serde_json::json!("2024-03-30T21:00:00.000-04:00"),
+            serde_json::json!("2024-03-30T20:59:59.000-04:00"),
+        ],
+        query(Some("America/New_York")).await
+    );
+    assert_eq!(
+        vec![serde_json::Value::Null, serde_json::Value::Null],
+        query(None).await
+    );
+}
+
+#[tokio::test]
+async fn handle_vod_rate_limit() {
+    let temp_dir = tempdir().unwrap();
+    let rec_dir = temp_dir.path().to_path_buf();
+    let rec_db = RecDb::new(
+        DummyLogger::new(),
+        rec_dir.clone(),
+        Disk::new(rec_dir, ByteSize(1)),
+    );
+    let state = VodHandlerState {
+        logger: Arc::new(Logger::new(Vec::new())),
+        recdb: Arc::new(rec_db),
+        cache: VodCache::new(),
+        config: VodConfig::default(),
+        rate_limiter: Some(Arc::new(RateLimiter::new(RateLimit {
+            burst: NonZeroU32::new(3).unwrap(),
+            per_minute: NonZeroU32::new(1).unwrap(),
+        }))),
+    };
+    let addr: SocketAddr = ([1, 2, 3, 4], 5).into();
+    let uri: Uri = "/vod/vod.mp4?monitor-id=x&start=2000000000&end=3000000000&cache-id=0"
+        .parse()
+        .unwrap();
+
+    let request = || {
+        vod_handler(
+            State(state.clone()),
+            ConnectInfo(addr),
+            Query::try_from_uri(&uri).unwrap(),
+            HeaderMap::new(),
+        )
+    };
+    for _ in 0..3 {
+        assert_eq!(StatusCode::NOT_FOUND, request().await.status());
+    }
+    let response = request().await;
+    assert_eq!(StatusCode::TOO_MANY_REQUESTS, response.status());
+    assert_eq!("60", response.headers().get(header::RETRY_AFTER).unwrap());
+}
+
+#[tokio::test]
+async fn handle_transcoded_vod() {
+    let temp_dir = tempdir().unwrap();
+    let rec_dir = temp_dir.path().to_path_buf();
+    let rec_db = RecDb::new(
+        DummyLogger::new(),
+        rec_dir.clone(),
+        Disk::new(rec_dir, ByteSize(1)),
+    );
+    let mut state = TranscodedVodHandlerState {
+        vod: VodHandlerState {
+            logger: Arc::new(Logger::new(Vec::new())),
+            recdb: Arc::new(rec_db),
+            cache: VodCache::new(),
+            config: VodConfig::default(),
+            rate_limiter: None,
+        },
+        transcoder: None,
+    };
+    let addr: SocketAddr = ([1, 2, 3, 4], 5).into();
+    let request = |state: TranscodedVodHandlerState, uri: &str| {
+        let uri: Uri = uri.parse().unwrap();
+        transcoded_vod_handler(
+            State(state),
+            ConnectInfo(addr),
+            Query::try_from_uri(&uri).unwrap(),
+            Query::try_from_uri(&uri).unwrap(),
+        )
+    };
+    let uri = "/vod/transcoded.mp4?monitor-id=x&start=2000000000&end=3000000000&cache-id=0&height=480&crf=23";
+    assert_eq!(
+        StatusCode::NOT_IMPLEMENTED,
+        request(state.clone(), uri).await.status()
+    );
+
+    state.transcoder = Some(Arc::new(StubTranscoder));
+    assert_eq!(
+        StatusCode::NOT_FOUND,
+        request(state.clone(), uri).await.status()
+    );
+    let uri = "/vod/transcoded.mp4?monitor-id=x&start=2000000000&end=3000000000&cache-id=0&height=480&crf=52";
+    assert_eq!(StatusCode::BAD_REQUEST, request(state, uri).await.status());
+}
+
+struct StubTranscoder;
+
+#[async_trait]
+impl Transcoder for StubTranscoder {
+    async fn transcode(
+        &self,
+        input: Box<dyn AsyncRead + Send + Unpin>,
+        _: NonZeroU16,
+        _: u8,
+    ) -> Result<Box<dyn AsyncRead + Send + Unpin>, TranscodeError> {
+        Ok(input)
+    }
+}
+
+async fn new_health_state(
+    temp_dir: &std::path::Path,
+    stats: ArcMonitorStats,
+) -> HealthHandlerState {
+    let log_dir = temp_dir.join("logs");
+    let rec_dir = temp_dir.join("recordings");
+    std::fs::create_dir(&rec_dir).unwrap();
+
+    let log_db = new_test_log_db(&log_dir, []).await;
+    let rec_db = RecDb::new(
+        DummyLogger::new(),
+        rec_dir.clone(),
+        Disk::new(rec_dir, ByteSize(1)),
+    );
+    HealthHandlerState {
+        monitor_manager: Arc::new(StubMonitorManager::new(HashMap::from([(
+            "a".to_owned().try_into().unwrap(),
+            stats,
+        )]))),
+        log_db,
+        rec_db: Arc::new(rec_db),
+    }
+}
+
+#[tokio::test]
+async fn handle_healthz_healthy() {
+    let temp_dir = tempdir().unwrap();
+    let stats = ArcMonitorStats::default();
+    stats.add_frame(StreamType::Main);
+    let state = new_health_state(temp_dir.path(), stats).await;
+
+    let response = healthz_handler(State(state.clone())).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(r#"{"healthy":true}"#, body);
+
+    let response = health_handler(State(state)).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(r#"{"healthy":true,"failing":[]}"#, body);
+}
+
+#[tokio::test]
+async fn handle_healthz_no_frame() {
+    let temp_dir = tempdir().unwrap();
+    let state = new_health_state(temp_dir.path(), ArcMonitorStats::default()).await;
+
+    // The stub monitor started at zero.
+    let failures = health_failures(&state, UnixNano::new(30 * SECOND)).await;
+    assert!(failures.is_empty());
+
+    let failures = health_failures(&state, UnixNano::new(31 * SECOND)).await;
+    assert_eq!(1, failures.len());
+    assert_eq!(
+        "monitor 'a' hasn't received a frame since it started 31s ago",
+        failures[0].message
+    );
+
+    // The details aren't public.
+    let response = healthz_handler(State(state)).await;
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(r#"{"healthy":false}"#, body);
+}
+
+#[tokio::test]
+async fn handle_healthz_stale_monitor() {
+    let temp_dir = tempdir().unwrap();
+    let stats = ArcMonitorStats::default();
+    stats.add_frame(StreamType::Main);
+    let last_frame = stats.last_frame().unwrap();
+    let state = new_health_state(temp_dir.path(), stats).await;
+
+    let now = last_frame + UnixNano::new(31 * SECOND);
+    let failures = health_failures(&state, now).await;
+    assert_eq!(1, failures.len());
+    assert_eq!("monitor", failures[0].subsystem);
+    assert_eq!(
+        "monitor 'a' hasn't received a frame in 31s",
+        failures[0].message
+    );
+}
+
+#[tokio::test]
+async fn handle_monitor_snapshot() {
+    let m_id = |s: &str| -> MonitorId { s.to_owned().try_into().unwrap() };
+    let monitor_manager = Arc::new(StubMonitorManager::new(HashMap::from([(
+        m_id("a"),
+        ArcMonitorStats::default(),
+    )])));
+    let snapshot =
+        |id: &str| monitor_snapshot_handler(State(monitor_manager.clone()), Path(m_id(id)));
+
+    let response = snapshot("a").await;
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+
+    let response = snapshot("x").await;
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+
+    monitor_manager.latest_frame.set(Keyframe {
+        avcc: Arc::new(PaddedBytes::new(vec![0, 0, 0, 1, 0x65])),
+        extradata: Arc::new(Vec::new()),
+        time: UnixNano::new(5),
+    });
+    let response = snapshot("a").await;
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(
+        "image/jpeg",
+        response.headers().get(header::CONTENT_TYPE).unwrap()
+    );
+    assert_eq!("5", response.headers().get("x-capture-time").unwrap());
+    assert_eq!(
+        STUB_JPEG,
+        to_bytes(response.into_body(), usize::MAX).await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn handle_detection_feed() {
+    let monitor_manager = Arc::new(StubMonitorManager::new(HashMap::new()));
+    let detection_tx = monitor_manager.detection_tx.clone();
+    let router = Router::new()
+        .route("/api/detection/feed", get(detection_feed_handler))
+        .with_state(DetectionFeedHandlerState {
+            auth: Arc::new(StubAuth),
+            monitor_manager,
+        });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    let url = format!("ws://{addr}/api/detection/feed?monitors=a&min-score=50");
+    let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+    // The handler subscribes after the upgrade.
+    while detection_tx.receiver_count() == 0 {
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    }
+
+    let event = |monitor_id: &str, time: i64, score: f32| DetectionEvent {
+        monitor_id: monitor_id.to_owned().try_into().unwrap(),
+        time: UnixNano::new(time),
+        label: "person".to_owned().try_into().unwrap(),
+        score,
+        region: Region::default(),
+    };
+    detection_tx.send(event("a", 1, 60.5)).unwrap();
+    detection_tx.send(event("b", 2, 60.5)).unwrap();
+    detection_tx.send(event("a", 3, 10.0)).unwrap();
+    detection_tx.send(event("a", 4, 50.0)).unwrap();
+
+    assert_eq!(
+        Message::Text(
+            r#"{"monitorID":"a","time":1,"label":"person","score":60.5,"region":{"rectangle":null,"polygon":null}}"#
+                .to_owned()
+        ),
+        ws.next().await.unwrap().unwrap()
+    );
+    assert_eq!(
+        Message::Text(
+            r#"{"monitorID":"a","time":4,"label":"person","score":50.0,"region":{"rectangle":null,"polygon":null}}"#
+                .to_owned()
+        ),
+        ws.next().await.unwrap().unwrap()
+    );
+
+    // The handler task must exit when the client disconnects.
+    ws.close(None).await.unwrap();
+    tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        while detection_tx.receiver_count() != 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+fn cors_router(allowed_origins: CorsOrigins, allow_credentials: bool) -> Router {
+    let config = CorsConfig {
+        allowed_origins,
+        allowed_methods: vec!["GET".to_owned(), "PUT".to_owned()],
+        allowed_headers: vec!["Authorization".to_owned()],
+        allow_credentials,
+    };
+    Router::new()
+        .route("/api/x", get(|| async { "x" }))
+        .layer(axum::middleware::from_fn_with_state(Arc::new(config), cors))
+}
+
+async fn cors_request(
+    router: &mut Router,
+    method: Method,
+    origin: &str,
+) -> axum::response::Response {
+    let mut request = Request::builder()
+        .method(method.clone())
+        .uri("/api/x")
+        .header(header::ORIGIN, origin);
+    if method == Method::OPTIONS {
+        request = request.header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT");
+    }
+    let request = request.body(axum::body::Body::empty()).unwrap();
+    tower::Service::call(router, request).await.unwrap()
+}
+
+#[tokio::test]
+async fn handle_cors_preflight() {
+    let mut router = cors_router(CorsOrigins::List(vec!["https://a.com".to_owned()]), true);
+
+    let response = cors_request(&mut router, Method::OPTIONS, "https://a.com").await;
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+    let headers = response.headers();
+    assert_eq!(
+        "https://a.com",
+        headers[header::ACCESS_CONTROL_ALLOW_ORIGIN]
+    );
+    assert_eq!("GET, PUT", headers[header::ACCESS_CONTROL_ALLOW_METHODS]);
+    assert_eq!(
+        "Authorization",
+        headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+    );
+    assert_eq!("true", headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS]);
+    assert_eq!("origin", headers[header::VARY]);
+
+    let response = cors_request(&mut router, Method::OPTIONS, "https://b.com").await;
+    assert_eq!(StatusCode::FORBIDDEN, response.status());
+    assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    assert_eq!("origin", response.headers()[header::VARY]);
+}
+
+#[tokio::test]
+async fn handle_cors_get() {
+    let mut router = cors_router(CorsOrigins::List(vec!["https://a.com".to_owned()]), false);
+
+    let response = cors_request(&mut router, Method::GET, "https://a.com").await;
+    assert_eq!(StatusCode::OK, response.status());
+    let headers = response.headers();
+    assert_eq!(
+        "https://a.com",
+        headers[header::ACCESS_CONTROL_ALLOW_ORIGIN]
+    );
+    assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+
+    // The request is still handled, the browser blocks the response.
+    let response = cors_request(&mut router, Method::GET, "https://b.com").await;
+    assert_eq!(StatusCode::OK, response.status());
+    assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    assert_eq!("origin", response.headers()[header::VARY]);
+
+    let mut router = cors_router(CorsOrigins::Any, false);
+    let response = cors_request(&mut router, Method::GET, "https://b.com").await;
+    assert_eq!("*", response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN]);
+    assert!(!response.headers().contains_key(header::VARY));
+}
@@ -124,6 +124,9 @@ pub enum AdjustPartDurationError {
 pub enum ParseParamsError {
     #[error("{0}")]
     TryFromInt(#[from] std::num::TryFromIntError),
+
+    #[error("unsupported AudioSpecificConfig")]
+    AudioSpecificConfig,
 }
 
 #[derive(Debug, Error)]
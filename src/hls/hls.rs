@@ -5,6 +5,7 @@ mod part;
 mod playlist;
 mod segment;
 mod segmenter;
+mod sei;
 mod types;
 
 use crate::error::PartHlsQueryError;
@@ -16,12 +17,13 @@ use common::{
 pub use error::ParseParamsError;
 pub use muxer::{HlsMuxer, NextSegmentGetter};
 pub use segmenter::H264Writer;
+pub use sei::avcc_has_recovery_point;
 use serde::Deserialize;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::{mpsc, oneshot};
 use tokio_util::sync::CancellationToken;
 use types::MuxerIdCounter;
-pub use types::{track_params_from_video_params, VIDEO_TRACK_ID};
+pub use types::{audio_params_from_retina, track_params_from_video_params, VIDEO_TRACK_ID};
 
 pub struct HlsServer {
     new_muxer_tx: mpsc::Sender<NewMuxerRequest>,
@@ -233,7 +235,8 @@ impl<'de> Deserialize<'de> for HlsQuery {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use common::{DummyLogger, HlsMuxer};
+    use bytes::Bytes;
+    use common::{time::UnixH264, AudioSample, DummyLogger, HlsMuxer, SegmentFinalized};
     use pretty_assertions::assert_eq;
 
     #[tokio::test]
@@ -246,6 +249,7 @@ mod tests {
             height: 64,
             codec: "test_codec".to_owned(),
             extra_data: Vec::new(),
+            audio: None,
         };
 
         let first_sample = H264Data {
@@ -326,6 +330,7 @@ seg7.mp4
             height: 64,
             codec: "test_codec".to_owned(),
             extra_data: Vec::new(),
+            audio: None,
         };
 
         let first_sample = H264Data {
@@ -420,6 +425,7 @@ seg7.mp4
             height: 64,
             codec: "test_codec".to_owned(),
             extra_data: Vec::new(),
+            audio: None,
         };
 
         let first_sample = H264Data {
@@ -484,6 +490,61 @@ seg7.mp4
         assert_eq!(muxer2.next_segment(Some(&seg8)).await.unwrap().id(), 7);
     }
 
+    #[tokio::test]
+    async fn test_audio_samples() {
+        let token = CancellationToken::new();
+        let server = HlsServer::new(token.clone(), DummyLogger::new());
+
+        let params = TrackParameters {
+            width: 64,
+            height: 64,
+            codec: "test_codec".to_owned(),
+            extra_data: Vec::new(),
+            audio: None,
+        };
+        let first_sample = H264Data {
+            random_access_present: true,
+            ..Default::default()
+        };
+        let (muxer, mut writer) = server
+            .new_muxer(
+                token,
+                "test".to_owned(),
+                params,
+                UnixNano::new(0),
+                first_sample,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        let audio = |pts| AudioSample {
+            pts: UnixH264::new(pts),
+            duration: DurationH264::new(1920),
+            data: Bytes::from(vec![1, 2]),
+        };
+        writer.write_audio(audio(10));
+        writer.write_audio(audio(1930));
+        writer.test_write(1_000_000, Vec::new(), true).await;
+        writer.write_audio(audio(1_000_010));
+        writer.test_write(2_000_000, Vec::new(), true).await;
+        writer.test_write(3_000_000, Vec::new(), true).await;
+
+        // The audio samples are stored in the part of the previous video sample.
+        let audio_pts = |seg: &SegmentFinalized| -> Vec<i64> {
+            seg.parts()
+                .iter()
+                .flat_map(|part| part.audio_samples.iter().map(|v| *v.pts))
+                .collect()
+        };
+        let seg7 = muxer.next_segment(None).await.unwrap();
+        assert_eq!(vec![10, 1930], audio_pts(&seg7));
+        let seg8 = muxer.next_segment(Some(&seg7)).await.unwrap();
+        assert_eq!(vec![1_000_010], audio_pts(&seg8));
+        let seg9 = muxer.next_segment(Some(&seg8)).await.unwrap();
+        assert!(audio_pts(&seg9).is_empty());
+    }
+
     async fn get_playlist(muxer: &muxer::HlsMuxer, opts: Option<(u64, u64, bool)>) -> String {
         let query = {
             if let Some((msn, part, is_delta_update)) = opts {
@@ -173,7 +173,8 @@ fn generate_trak(params: &TrackParameters) -> mp4::Boxes {
         }),
         // Mdia
         mp4::Boxes::new(mp4::Mdia {}).with_children3(
-            // Mdhd.
+            // Mdhd, the live stream always uses the 90khz timescale of the
+            // source timestamps. `recording::Timescale` doesn't apply here.
             mp4::Boxes::new(mp4::Mdhd {
                 timescale: H264_TIMESCALE,
                 language: *b"und",
@@ -235,6 +236,7 @@ mod tests {
                 0x60, 0x3c, 0x58, 0xb6, 0x58, 0x1, 0x0, 0x0,
             ],
             codec: String::new(),
+            audio: None,
         };
 
         let got = generate_init(&params).unwrap();
@@ -27,6 +27,7 @@ pub struct HlsMuxer {
     token: CancellationToken,
     playlist: Arc<Playlist>,
     params: TrackParameters,
+    id: u16,
 
     //videoLastSPS: []byte,
     //videoLastPPS: []byte,
@@ -69,6 +70,7 @@ impl HlsMuxer {
             token: token.clone(),
             playlist,
             params,
+            id,
             init_content: Mutex::new(Bytes::new()),
         };
         Ok((muxer, H264Writer::new(segmenter, token.drop_guard())))
@@ -138,6 +140,10 @@ impl common::HlsMuxer for HlsMuxer {
         &self.params
     }
 
+    fn muxer_id(&self) -> u16 {
+        self.id
+    }
+
     // Returns the first segment with a ID greater than prevID.
     // Will wait for new segments if the next segment isn't cached.
     async fn next_segment(
@@ -5,7 +5,7 @@ use crate::{
 use bytes::Bytes;
 use common::{
     time::{DurationH264, UnixH264, UnixNano},
-    PartFinalized, VideoSample,
+    AudioSample, PartFinalized, VideoSample,
 };
 use mp4::{ImmutableBox, ImmutableBoxSync, TfdtBaseMediaDecodeTime, TrunEntries};
 use std::sync::Arc;
@@ -157,6 +157,7 @@ pub struct MuxerPart {
     pub muxer_start_time: UnixNano,
     pub is_independent: bool,
     pub video_samples: Vec<VideoSample>,
+    pub audio_samples: Vec<AudioSample>,
 }
 
 impl std::fmt::Debug for MuxerPart {
@@ -178,6 +179,7 @@ impl MuxerPart {
             muxer_start_time,
             is_independent: false,
             video_samples: Vec::new(),
+            audio_samples: Vec::new(),
         }
     }
 
@@ -205,6 +207,7 @@ impl MuxerPart {
             id: self.id,
             is_independent: self.is_independent,
             video_samples: video_samples.clone(),
+            audio_samples: Arc::new(self.audio_samples),
             rendered_duration,
             rendered_content,
         })
@@ -216,6 +219,10 @@ impl MuxerPart {
         }
         self.video_samples.push(sample);
     }
+
+    pub fn write_audio(&mut self, samples: &mut Vec<AudioSample>) {
+        self.audio_samples.append(samples);
+    }
 }
 
 #[allow(clippy::unwrap_used)]
@@ -6,7 +6,7 @@ use crate::{
 };
 use common::{
     time::{DurationH264, UnixH264, UnixNano},
-    PartFinalized, SegmentFinalized, VideoSample,
+    AudioSample, PartFinalized, SegmentFinalized, VideoSample,
 };
 use std::{mem, sync::Arc};
 
@@ -55,6 +55,11 @@ impl Segment {
         self.start_dts
     }
 
+    // Audio samples are added to the current part.
+    pub fn write_audio(&mut self, samples: &mut Vec<AudioSample>) {
+        self.current_part.write_audio(samples);
+    }
+
     pub async fn write_h264(
         &mut self,
         sample: VideoSample,
@@ -132,6 +137,7 @@ mod tests {
             id: 0,
             is_independent: false,
             video_samples: Arc::new(Vec::new()),
+            audio_samples: Arc::new(Vec::new()),
             rendered_content: Some(Bytes::from(content)),
             rendered_duration: DurationH264::new(0),
         })
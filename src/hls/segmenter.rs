@@ -6,7 +6,7 @@ use crate::{
 };
 use common::{
     time::{DurationH264, UnixNano, H264_MILLISECOND, H264_SECOND},
-    H264Data, VideoSample,
+    AudioSample, H264Data, VideoSample,
 };
 use std::{collections::HashSet, sync::Arc};
 use tokio_util::sync::DropGuard;
@@ -29,6 +29,10 @@ impl H264Writer {
         self.segmenter.write_h264(data).await
     }
 
+    pub fn write_audio(&mut self, sample: AudioSample) {
+        self.segmenter.write_audio(sample);
+    }
+
     #[cfg(test)]
     #[allow(clippy::unwrap_used)]
     pub async fn test_write(&mut self, pts: i64, avcc: Vec<u8>, random_access: bool) {
@@ -62,6 +66,9 @@ pub struct Segmenter {
     first_segment_finalized: bool,
     sample_durations: HashSet<DurationH264>,
     adjusted_part_duration: DurationH264,
+
+    // Audio samples received since the last video sample.
+    pending_audio: Vec<AudioSample>,
 }
 
 impl Segmenter {
@@ -104,6 +111,7 @@ impl Segmenter {
             first_segment_finalized: false,
             sample_durations: HashSet::new(),
             adjusted_part_duration: DurationH264::new(0),
+            pending_audio: Vec::new(),
         })
     }
 
@@ -128,6 +136,12 @@ impl Segmenter {
         Ok(())
     }
 
+    // Audio samples are buffered until the next video sample so they always
+    // end up in a part with video, parts without video are never finalized.
+    pub fn write_audio(&mut self, sample: AudioSample) {
+        self.pending_audio.push(sample);
+    }
+
     pub async fn write_h264(&mut self, data: H264Data) -> Result<(), SegmenterWriteH264Error> {
         use crate::error::SegmenterWriteH264Error::*;
 
@@ -169,6 +183,7 @@ impl Segmenter {
         });
 
         let segment_start_dts = current_segment.start_dts();
+        current_segment.write_audio(&mut self.pending_audio);
         current_segment
             .write_h264(
                 sample,
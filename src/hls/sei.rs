@@ -0,0 +1,138 @@
+// H.264 SEI parsing, only the parts needed to find recovery points.
+
+const NAL_TYPE_SEI: u8 = 6;
+
+// ITU-T H.264 D.1.8
+const SEI_TYPE_RECOVERY_POINT: u32 = 6;
+
+// Returns true if the AVCC sample contains a SEI recovery point message.
+// Streams using gradual decoder refresh may never send IDRs, decoding can
+// start at any sample with a recovery point instead.
+#[must_use]
+pub fn avcc_has_recovery_point(avcc: &[u8]) -> bool {
+    let mut avcc = avcc;
+    while avcc.len() >= 4 {
+        let (size, rest) = avcc.split_at(4);
+        let size = usize::try_from(u32::from_be_bytes([size[0], size[1], size[2], size[3]]))
+            .unwrap_or(usize::MAX);
+        if size > rest.len() {
+            return false;
+        }
+        let (nalu, rest) = rest.split_at(size);
+        avcc = rest;
+
+        let Some(header) = nalu.first() else {
+            continue;
+        };
+        if header & 0x1f == NAL_TYPE_SEI
+            && sei_has_recovery_point(&remove_emulation_prevention(&nalu[1..]))
+        {
+            return true;
+        }
+    }
+    false
+}
+
+// ITU-T H.264 7.3.2.3.1
+fn sei_has_recovery_point(mut rbsp: &[u8]) -> bool {
+    // Stop at the rbsp trailing bits.
+    while rbsp.len() > 1 || rbsp.first().is_some_and(|b| *b != 0x80) {
+        let Some((payload_type, rest)) = read_sei_value(rbsp) else {
+            return false;
+        };
+        let Some((payload_size, rest)) = read_sei_value(rest) else {
+            return false;
+        };
+        if payload_type == SEI_TYPE_RECOVERY_POINT {
+            return true;
+        }
+        let Ok(payload_size) = usize::try_from(payload_size) else {
+            return false;
+        };
+        let Some(rest) = rest.get(payload_size..) else {
+            return false;
+        };
+        rbsp = rest;
+    }
+    false
+}
+
+// Reads a payload type or size, encoded as a series of 0xff bytes and a final byte.
+fn read_sei_value(buf: &[u8]) -> Option<(u32, &[u8])> {
+    let mut value = 0_u32;
+    for (i, b) in buf.iter().enumerate() {
+        value = value.checked_add(u32::from(*b))?;
+        if *b != 0xff {
+            return Some((value, &buf[i + 1..]));
+        }
+    }
+    None
+}
+
+// Removes the 0x03 in every 0x000003 sequence.
+fn remove_emulation_prevention(buf: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(buf.len());
+    let mut zeros = 0;
+    for b in buf {
+        if zeros >= 2 && *b == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        if *b == 0 {
+            zeros += 1;
+        } else {
+            zeros = 0;
+        }
+        out.push(*b);
+    }
+    out
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn avcc(nalus: &[&[u8]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for nalu in nalus {
+            out.extend_from_slice(&u32::try_from(nalu.len()).unwrap().to_be_bytes());
+            out.extend_from_slice(nalu);
+        }
+        out
+    }
+
+    // SEI with a recovery point, recovery_frame_cnt=0 exact_match=1.
+    const SEI_RECOVERY_POINT: &[u8] = &[0x06, 0x06, 0x01, 0xc4, 0x80];
+    // SEI with a user data unregistered message with a emulation prevention byte.
+    const SEI_USER_DATA: &[u8] = &[
+        0x06, 0x05, 0x12, 0, 0, 3, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 0x80,
+    ];
+    const SLICE: &[u8] = &[0x01, 0x9a, 0x00];
+    const IDR: &[u8] = &[0x65, 0x88, 0x84];
+
+    #[test_case(&[SEI_RECOVERY_POINT, SLICE], true; "recovery point")]
+    #[test_case(&[SEI_USER_DATA, SLICE], false; "user data")]
+    #[test_case(&[SLICE], false; "slice")]
+    #[test_case(&[IDR], false; "idr")]
+    #[test_case(&[], false; "empty")]
+    fn test_avcc_has_recovery_point(nalus: &[&[u8]], want: bool) {
+        assert_eq!(want, avcc_has_recovery_point(&avcc(nalus)));
+    }
+
+    #[test]
+    fn test_avcc_has_recovery_point_second_message() {
+        // User data followed by a recovery point in the same SEI.
+        let mut sei = SEI_USER_DATA[..SEI_USER_DATA.len() - 1].to_vec();
+        sei.extend_from_slice(&SEI_RECOVERY_POINT[1..]);
+        assert!(avcc_has_recovery_point(&avcc(&[&sei, SLICE])));
+    }
+
+    #[test]
+    fn test_avcc_has_recovery_point_truncated() {
+        let mut buf = avcc(&[SEI_RECOVERY_POINT]);
+        buf.truncate(6);
+        assert!(!avcc_has_recovery_point(&buf));
+    }
+}
@@ -1,4 +1,4 @@
-use common::TrackParameters;
+use common::{AudioParameters, TrackParameters};
 use retina::codec::VideoParameters;
 use std::convert::TryFrom;
 
@@ -47,9 +47,51 @@ pub fn track_params_from_video_params(
         height: u16::try_from(height)?,
         codec: params.rfc6381_codec().to_owned(),
         extra_data: params.extra_data().to_owned(),
+        audio: None,
     })
 }
 
+pub fn audio_params_from_retina(
+    params: &retina::codec::AudioParameters,
+) -> Result<AudioParameters, ParseParamsError> {
+    let config = params.extra_data();
+    Ok(AudioParameters {
+        channel_count: aac_channel_count(config).ok_or(ParseParamsError::AudioSpecificConfig)?,
+        sample_rate: params.clock_rate(),
+        config: config.to_owned(),
+    })
+}
+
+// Reads the channel configuration from a AudioSpecificConfig.
+// ISO/IEC 14496-3 1.6.2.1
+fn aac_channel_count(config: &[u8]) -> Option<u16> {
+    let mut pos = 0;
+    let mut read_bits = |n: usize| -> Option<u32> {
+        let mut v = 0;
+        for _ in 0..n {
+            let bit = (config.get(pos / 8)? >> (7 - pos % 8)) & 1;
+            v = (v << 1) | u32::from(bit);
+            pos += 1;
+        }
+        Some(v)
+    };
+
+    let object_type = read_bits(5)?;
+    if object_type == 31 {
+        read_bits(6)?;
+    }
+    let frequency_index = read_bits(4)?;
+    if frequency_index == 15 {
+        read_bits(24)?;
+    }
+    match read_bits(4)? {
+        v @ 1..=6 => u16::try_from(v).ok(),
+        7 => Some(8),
+        // Defined in a program config element or reserved.
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,6 +104,19 @@ mod tests {
         assert_eq!(2, counter.next_id());
     }
 
+    #[test]
+    fn test_aac_channel_count() {
+        // AAC-LC 44100hz stereo.
+        assert_eq!(Some(2), aac_channel_count(&[0x12, 0x10]));
+        // AAC-LC 8000hz mono.
+        assert_eq!(Some(1), aac_channel_count(&[0x15, 0x88]));
+        // Explicit 16000hz frequency, 7.1 channels.
+        assert_eq!(Some(8), aac_channel_count(&[0x17, 0x80, 0x1f, 0x40, 0x38]));
+        // Program config element.
+        assert_eq!(None, aac_channel_count(&[0x12, 0x00]));
+        assert_eq!(None, aac_channel_count(&[0x12]));
+    }
+
     #[test]
     fn test_muxer_id_counter() {
         let mut counter = MuxerIdCounter::new();
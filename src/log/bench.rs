@@ -93,6 +93,9 @@ impl Helper {
             log_dir.to_owned(),
             ByteSize(0),
             ByteSize(0),
+            None,
+            None,
+            None,
         )
         .unwrap();
 
@@ -152,6 +155,7 @@ impl Helper {
             monitor_id: Some(monitor_ids[self.rng.gen_range(0..monitor_ids.len())].clone()),
             message: message.try_into().unwrap(),
             time: UnixMicro::new(self.count),
+            repeat_count: 1,
         }
     }
 }
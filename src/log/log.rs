@@ -3,7 +3,7 @@
 pub mod log_db;
 pub mod rev_buf_reader;
 
-use common::{ILogger, LogEntry, LogLevel, LogMessage, LogSource, MonitorId};
+use common::{ILogger, LogEntry, LogFormat, LogLevel, LogLevels, LogMessage, LogSource, MonitorId};
 use serde::{Deserialize, Serialize};
 use std::{
     fmt,
@@ -19,6 +19,12 @@ pub struct Logger {
     feed: broadcast::Sender<LogEntryWithTime>,
 
     sources: Vec<LogSource>,
+
+    /// Format of the lines printed to stdout.
+    format: LogFormat,
+
+    /// Entries below the level of their source are dropped.
+    levels: LogLevels,
 }
 
 impl Logger {
@@ -33,7 +39,27 @@ impl Logger {
         sources.push("monitor".try_into().unwrap());
         sources.sort();
 
-        Self { feed, sources }
+        Self {
+            feed,
+            sources,
+            format: LogFormat::Text,
+            levels: LogLevels::default(),
+        }
+    }
+
+    /// Sets the format of the lines printed to stdout.
+    #[must_use]
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the minimum log levels, entries below them are dropped before
+    /// they are printed or sent to subscribers.
+    #[must_use]
+    pub fn with_levels(mut self, levels: LogLevels) -> Self {
+        self.levels = levels;
+        self
     }
 
     /// Subscribes to the log feed and returns a channel that receives all log entries.
@@ -57,16 +83,20 @@ impl Default for Logger {
 impl ILogger for Logger {
     /// Sends log entry to all subscribers. The timestamp is applied now.
     fn log(&self, log: LogEntry) {
+        if !self.levels.enabled(log.level, &log.source) {
+            return;
+        }
         let log = LogEntryWithTime {
             level: log.level,
             source: log.source,
             monitor_id: log.monitor_id,
             message: log.message,
             time: UnixMicro::now(),
+            repeat_count: 1,
         };
 
         // Print to stdout.
-        println!("{log}");
+        println!("{}", format_log(&log, self.format));
 
         // Only returns an error if there are no subscribers.
         self.feed.send(log).ok();
@@ -101,6 +131,11 @@ impl UnixMicro {
     pub fn checked_add(&self, rhs: Self) -> Option<Self> {
         Some(Self(self.0.checked_add(rhs.0)?))
     }
+
+    #[must_use]
+    pub fn checked_sub(&self, rhs: Self) -> Option<Self> {
+        Some(Self(self.0.checked_sub(rhs.0)?))
+    }
 }
 
 impl Deref for UnixMicro {
@@ -112,17 +147,59 @@ impl Deref for UnixMicro {
 }
 
 /// Log entry with timestamp.
-#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LogEntryWithTime {
     pub level: LogLevel,
     pub source: LogSource,
 
     /// Optional monitor ID if the message can be tied to a monitor.
-    #[serde(rename = "monitorID", skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "monitorID", default, skip_serializing_if = "Option::is_none")]
     pub monitor_id: Option<MonitorId>,
 
     pub message: LogMessage,
     pub time: UnixMicro,
+
+    /// Number of identical consecutive entries collapsed into this one.
+    #[serde(
+        rename = "repeatCount",
+        default = "default_repeat_count",
+        skip_serializing_if = "is_single"
+    )]
+    pub repeat_count: u32,
+}
+
+fn default_repeat_count() -> u32 {
+    1
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_single(repeat_count: &u32) -> bool {
+    *repeat_count == 1
+}
+
+impl LogEntryWithTime {
+    /// Reports whether the entries only differ in time and repeat count.
+    #[must_use]
+    pub fn is_repeat_of(&self, other: &Self) -> bool {
+        self.level == other.level
+            && self.source == other.source
+            && self.monitor_id == other.monitor_id
+            && self.message == other.message
+    }
+
+    /// Entry from the app source with the time as message.
+    #[must_use]
+    #[allow(clippy::unwrap_used)]
+    pub fn new_testing(time: u64, level: LogLevel) -> Self {
+        Self {
+            level,
+            source: "app".try_into().unwrap(),
+            monitor_id: None,
+            message: time.to_string().try_into().unwrap(),
+            time: UnixMicro::new(time),
+            repeat_count: 1,
+        }
+    }
 }
 
 impl fmt::Display for LogEntryWithTime {
@@ -147,6 +224,33 @@ impl fmt::Display for LogEntryWithTime {
     }
 }
 
+#[derive(Serialize)]
+struct JsonLogLine<'a> {
+    time: u64,
+    level: LogLevel,
+    source: &'a LogSource,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    monitor_id: Option<&'a MonitorId>,
+
+    message: &'a LogMessage,
+}
+
+/// Formats the entry as a single line without a trailing newline.
+fn format_log(log: &LogEntryWithTime, format: LogFormat) -> String {
+    match format {
+        LogFormat::Text => log.to_string(),
+        LogFormat::Json => serde_json::to_string(&JsonLogLine {
+            time: *log.time,
+            level: log.level,
+            source: &log.source,
+            monitor_id: log.monitor_id.as_ref(),
+            message: &log.message,
+        })
+        .expect("serialization to not fail"),
+    }
+}
+
 /// Make the first character in a string uppercase.
 fn make_ascii_titlecase(s: &mut str) {
     if let Some(r) = s.get_mut(0..1) {
@@ -160,6 +264,7 @@ mod tests {
     use super::*;
     use common::{ParseLogMessageError, ParseLogSourceError};
     use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
     use test_case::test_case;
 
     fn src(s: &'static str) -> LogSource {
@@ -217,6 +322,7 @@ mod tests {
                 monitor_id: Some(m_id("m1")),
                 message: msg("1"),
                 time: UnixMicro(0),
+                repeat_count: 1,
             },
             LogEntryWithTime {
                 level: LogLevel::Warning,
@@ -224,6 +330,7 @@ mod tests {
                 monitor_id: Some(m_id("m2")),
                 message: msg("2"),
                 time: UnixMicro(0),
+                repeat_count: 1,
             },
             LogEntryWithTime {
                 level: LogLevel::Error,
@@ -231,6 +338,7 @@ mod tests {
                 monitor_id: Some(m_id("m3")),
                 message: msg("3"),
                 time: UnixMicro(0),
+                repeat_count: 1,
             },
             LogEntryWithTime {
                 level: LogLevel::Debug,
@@ -238,12 +346,69 @@ mod tests {
                 monitor_id: Some(m_id("m4")),
                 message: msg("4"),
                 time: UnixMicro(0),
+                repeat_count: 1,
             },
         ];
 
         assert_eq!(actual, expected);
     }
 
+    #[tokio::test]
+    async fn logger_levels() {
+        let logger = Logger::new(Vec::new()).with_levels(LogLevels {
+            default: Some(LogLevel::Info),
+            sources: HashMap::from([(src("rtsp"), LogLevel::Debug)]),
+        });
+        let mut feed = logger.subscribe();
+
+        let entry = |level, source| LogEntry {
+            level,
+            source: src(source),
+            monitor_id: None,
+            message: msg("x"),
+        };
+        logger.log(entry(LogLevel::Debug, "app"));
+        logger.log(entry(LogLevel::Debug, "rtsp"));
+        logger.log(entry(LogLevel::Info, "app"));
+        drop(logger);
+
+        let mut actual = Vec::new();
+        while let Ok(v) = feed.recv().await {
+            actual.push((v.level, v.source));
+        }
+        let expected = vec![(LogLevel::Debug, src("rtsp")), (LogLevel::Info, src("app"))];
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_format_log_json() {
+        let log = LogEntryWithTime {
+            level: LogLevel::Warning,
+            source: src("app"),
+            monitor_id: Some(m_id("m1")),
+            message: msg("a \"b\"\nc"),
+            time: UnixMicro(123),
+            repeat_count: 1,
+        };
+        let line = format_log(&log, LogFormat::Json);
+        assert!(!line.contains('\n'));
+
+        let got: serde_json::Value = serde_json::from_str(&line).unwrap();
+        let want = serde_json::json!({
+            "time": 123,
+            "level": "warning",
+            "source": "app",
+            "monitor_id": "m1",
+            "message": "a \"b\"\nc",
+        });
+        assert_eq!(want, got);
+
+        assert_eq!(
+            "[WARNING] m1: App: a \"b\"\nc",
+            format_log(&log, LogFormat::Text)
+        );
+    }
+
     #[test_case("", ParseLogSourceError::Empty; "empty")]
     #[test_case("@",ParseLogSourceError::InvalidChars("@".to_owned()); "invalid_chars")]
     fn source_parse(input: &str, want: ParseLogSourceError) {
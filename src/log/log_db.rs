@@ -2,24 +2,32 @@ use super::{LogEntryWithTime, Logger, UnixMicro};
 use crate::rev_buf_reader::RevBufReader;
 use bytesize::ByteSize;
 use common::{
-    ArcLogger, LogEntry, LogLevel, LogSource, MonitorId, ParseLogLevelError, ParseLogMessageError,
-    ParseLogSourceError, ParseMonitorIdError, LOG_SOURCE_MAX_LENGTH, MONITOR_ID_MAX_LENGTH,
+    time::UnixNano, ArcLogger, LogEntry, LogLevel, LogSource, MonitorId, ParseLogLevelError,
+    ParseLogMessageError, ParseLogSourceError, ParseMonitorIdError, LOG_SOURCE_MAX_LENGTH,
+    MONITOR_ID_MAX_LENGTH,
 };
 use csv::{deserialize_csv_option, deserialize_csv_option2};
-use serde::Deserialize;
+use futures_lite::Stream;
+use serde::{Deserialize, Serialize};
 use std::{
     cmp::Ordering,
     io::SeekFrom,
     num::NonZeroUsize,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{self, AtomicI64, AtomicU64},
+        Arc,
+    },
     time::Duration,
 };
 use thiserror::Error;
 use tokio::{
     fs::File,
-    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
-    sync::{mpsc, Mutex},
+    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, Mutex,
+    },
 };
 use tokio_util::sync::CancellationToken;
 
@@ -40,20 +48,136 @@ use tokio_util::sync::CancellationToken;
 //     msgOffset u32
 //     msgSize u16
 //     level u8
+//     repeatCount u32 // Version 1.
+//     checksum u32 // Version 2, CRC-32 of the previous fields.
 // }
 
 // 16666 minutes or 27.7 hours.
 const CHUNK_DURATION: u64 = 1_000_000 * SECOND;
 const SECOND: u64 = 100_000;
 
-const CHUNK_API_VERSION: u8 = 0;
+const CHUNK_API_VERSION: u8 = 2;
 const CHUNK_ID_LENGTH: usize = 5;
 const CHUNK_HEADER_LENGTH: u64 = 1;
 
-const DATA_SIZE: usize = 47;
+const DATA_SIZE_V0: usize = 47;
+const DATA_SIZE_V1: usize = 51;
+const DATA_SIZE: usize = 55;
+
+// Returns the size of a data entry in a chunk with the version.
+fn data_size(version: u8) -> Option<usize> {
+    match version {
+        0 => Some(DATA_SIZE_V0),
+        1 => Some(DATA_SIZE_V1),
+        2 => Some(DATA_SIZE),
+        _ => None,
+    }
+}
+
+// CRC-32/ISO-HDLC, the checksum used by zlib.
+fn crc32(buf: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+    for b in buf {
+        crc ^= u32::from(*b);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub struct LogDbHandle {
+    db: Mutex<LogDb>,
+    counters: Counters,
+
+    // Entries are sent after they've been saved.
+    feed: broadcast::Sender<LogEntryWithTime>,
+}
+
+// Number of entries fetched per query by `LogPages`.
+const QUERY_PAGE_SIZE: usize = 1000;
+
+pub struct LogPages<'a> {
+    log_db: &'a LogDbHandle,
+    q: LogQuery,
+    remaining: Option<usize>,
+    page_size: usize,
+}
+
+impl LogPages<'_> {
+    // Returns None after the last page.
+    pub async fn next(&mut self) -> Result<Option<Vec<LogEntryWithTime>>, QueryLogsError> {
+        let page_size = self
+            .remaining
+            .map_or(self.page_size, |v| v.min(self.page_size));
+        let Some(page_size) = NonZeroUsize::new(page_size) else {
+            return Ok(None);
+        };
+        let entries = self
+            .log_db
+            .query(LogQuery {
+                levels: self.q.levels.clone(),
+                sources: self.q.sources.clone(),
+                time: self.q.time,
+                since: self.q.since,
+                monitors: self.q.monitors.clone(),
+                limit: Some(page_size),
+            })
+            .await?;
+        let Some(last_entry) = entries.last() else {
+            return Ok(None);
+        };
+        self.q.time = Some(last_entry.time);
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= entries.len();
+        }
+        Ok(Some(entries))
+    }
+}
+
+// Item of a log subscription.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LogFeedItem {
+    Entry(LogEntryWithTime),
+
+    // The subscriber fell behind and this many entries were skipped.
+    Lagged(u64),
+}
+
+#[derive(Default)]
+struct Counters {
+    entries_written: AtomicU64,
+    entries_dropped_lagged: AtomicU64,
+    save_errors: AtomicU64,
+
+    // Zero if no save has failed.
+    last_save_error: AtomicI64,
+}
 
+// Snapshot of the database counters.
 #[allow(clippy::module_name_repetitions)]
-pub struct LogDbHandle(Mutex<LogDb>);
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct LogDbStats {
+    pub entries_written: u64,
+
+    // Entries that were dropped because the database couldn't keep up with the logger.
+    pub entries_dropped_lagged: u64,
+
+    pub save_errors: u64,
+}
+
+// Result of compacting a chunk.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CompactStats {
+    pub entries_kept: usize,
+    pub entries_removed: usize,
+
+    // Combined size of the data and msg files.
+    pub size_before: ByteSize,
+    pub size_after: ByteSize,
+}
 
 impl LogDbHandle {
     pub async fn save_log_testing(&self, entry: LogEntryWithTime) {
@@ -61,16 +185,118 @@ impl LogDbHandle {
         self.save_log(entry).await.unwrap();
     }
 
-    async fn save_log(&self, entry: LogEntryWithTime) -> Result<(), SaveLogError> {
-        self.0.lock().await.save_log(entry).await
+    // Returns the ID of the chunk that the entry was written to.
+    pub async fn save_log(&self, entry: LogEntryWithTime) -> Result<String, SaveLogError> {
+        let res = self.db.lock().await.save_log(entry).await;
+        match res {
+            Ok((chunk_id, saved)) => {
+                self.counters
+                    .entries_written
+                    .fetch_add(1, atomic::Ordering::Relaxed);
+                if let Some(saved) = saved {
+                    // Only returns an error if there are no subscribers.
+                    self.feed.send(saved).ok();
+                }
+                Ok(chunk_id)
+            }
+            Err(e) => {
+                self.counters
+                    .save_errors
+                    .fetch_add(1, atomic::Ordering::Relaxed);
+                self.counters
+                    .last_save_error
+                    .store(*UnixNano::now(), atomic::Ordering::Relaxed);
+                Err(e)
+            }
+        }
     }
 
     pub async fn query(&self, q: LogQuery) -> Result<Vec<LogEntryWithTime>, QueryLogsError> {
-        self.0.lock().await.query(q).await
+        self.db.lock().await.query(q).await
+    }
+
+    // Returns a stream of newly saved entries that match the query filter.
+    // The time and limit fields are ignored. The stream ends when the
+    // token is cancelled, dropping the stream unsubscribes immediately.
+    pub fn subscribe(
+        &self,
+        q: LogQuery,
+        token: CancellationToken,
+    ) -> impl Stream<Item = LogFeedItem> + Send + 'static {
+        let feed = self.feed.subscribe();
+        futures_lite::stream::unfold((feed, q, token), |(mut feed, q, token)| async move {
+            loop {
+                let entry = tokio::select! {
+                    () = token.cancelled() => return None,
+                    entry = feed.recv() => entry,
+                };
+                let item = match entry {
+                    Ok(entry) => {
+                        if !q.entry_matches_filter(&entry) {
+                            continue;
+                        }
+                        LogFeedItem::Entry(entry)
+                    }
+                    Err(RecvError::Lagged(n)) => LogFeedItem::Lagged(n),
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((item, (feed, q, token)));
+            }
+        })
+    }
+
+    // Returns the entries matching the query one page at a time, newest entry
+    // first. Paging starts at `q.time` and `q.limit` caps the total number of entries.
+    #[must_use]
+    pub fn query_pages(&self, q: LogQuery) -> LogPages<'_> {
+        LogPages {
+            log_db: self,
+            remaining: q.limit.map(NonZeroUsize::get),
+            q,
+            page_size: QUERY_PAGE_SIZE,
+        }
     }
 
     async fn prune(&self) -> Result<(), PurgeError> {
-        self.0.lock().await.prune().await
+        self.db.lock().await.prune().await
+    }
+
+    // Returns the IDs of all chunks, oldest first.
+    pub async fn list_chunks(&self) -> Result<Vec<String>, std::io::Error> {
+        self.db.lock().await.list_chunks().await
+    }
+
+    // Rewrites a chunk without invalid entries and unreferenced message bytes.
+    pub async fn compact_chunk(&self, chunk_id: &str) -> Result<CompactStats, CompactChunkError> {
+        self.db.lock().await.compact_chunk(chunk_id).await
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> LogDbStats {
+        LogDbStats {
+            entries_written: self
+                .counters
+                .entries_written
+                .load(atomic::Ordering::Relaxed),
+            entries_dropped_lagged: self
+                .counters
+                .entries_dropped_lagged
+                .load(atomic::Ordering::Relaxed),
+            save_errors: self.counters.save_errors.load(atomic::Ordering::Relaxed),
+        }
+    }
+
+    // Time of the most recent failed save.
+    #[must_use]
+    pub fn last_save_error(&self) -> Option<UnixNano> {
+        match self
+            .counters
+            .last_save_error
+            .load(atomic::Ordering::Relaxed)
+        {
+            0 => None,
+            v => Some(UnixNano::new(v)),
+        }
     }
 
     // Saves logs from the logger into the database.
@@ -80,8 +306,15 @@ impl LogDbHandle {
             tokio::select! {
                 () = token.cancelled() => return,
                 log = feed.recv() => {
-                    let Ok(log) = log else {
-                        return
+                    let log = match log {
+                        Ok(v) => v,
+                        Err(RecvError::Lagged(n)) => {
+                            self.counters
+                                .entries_dropped_lagged
+                                .fetch_add(n, atomic::Ordering::Relaxed);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => return,
                     };
                     if let Err(e) = self.save_log(log.clone()).await {
                         eprintln!("could not save log: {} {}", log.message, e);
@@ -111,6 +344,29 @@ impl LogDbHandle {
             }
         }
     }
+
+    // Syncs the active chunk to disk every `sync_interval`.
+    // Returns immediately if the interval is unset.
+    pub async fn sync_loop(&self, token: CancellationToken, logger: ArcLogger) {
+        let Some(sync_interval) = self.db.lock().await.sync_interval else {
+            return;
+        };
+        loop {
+            tokio::select! {
+                () = token.cancelled() => return,
+                () = tokio::time::sleep(sync_interval) => {
+                    if let Err(e) = self.db.lock().await.sync().await {
+                        logger.log(LogEntry::new(
+                            LogLevel::Error,
+                            "app",
+                            None,
+                            format!("could not sync logs: {e}"),
+                        ));
+                    }
+                }
+            }
+        }
+    }
 }
 
 pub struct LogDb {
@@ -125,6 +381,20 @@ pub struct LogDb {
     disk_space: ByteSize,
     min_disk_usage: ByteSize,
 
+    // Chunks older than this are pruned regardless of disk usage.
+    max_age: Option<Duration>,
+
+    // Identical consecutive entries within this duration
+    // of the first entry are collapsed into a single entry.
+    dedupe_window: Option<Duration>,
+
+    // The last written entry, used for deduplication.
+    prev_entry: Option<LogEntryWithTime>,
+
+    // The active chunk is synced to disk this often. Entries are
+    // written immediately, but may only be in the page cache.
+    sync_interval: Option<Duration>,
+
     _shutdown_complete: mpsc::Sender<()>,
 }
 
@@ -132,6 +402,9 @@ pub struct LogDb {
 pub enum NewLogDbError {
     #[error("make log directory: {0} {1}")]
     MakeLogDir(String, std::io::Error),
+
+    #[error("recover compaction: {0}")]
+    RecoverCompaction(std::io::Error),
 }
 
 impl LogDb {
@@ -141,21 +414,55 @@ impl LogDb {
         log_dir: PathBuf,
         disk_space: ByteSize,
         min_disk_usage: ByteSize,
+        max_age: Option<Duration>,
+        dedupe_window: Option<Duration>,
+        sync_interval: Option<Duration>,
     ) -> Result<LogDbHandle, NewLogDbError> {
         std::fs::create_dir_all(&log_dir)
             .map_err(|e| NewLogDbError::MakeLogDir(log_dir.to_string_lossy().to_string(), e))?;
+        recover_compactions(&log_dir).map_err(NewLogDbError::RecoverCompaction)?;
+
+        Ok(LogDbHandle {
+            db: Mutex::new(Self {
+                log_dir,
+                encoder: None,
+                prev_entry_time: UnixMicro::new(0),
+                disk_space,
+                min_disk_usage,
+                max_age,
+                dedupe_window,
+                prev_entry: None,
+                sync_interval,
+                _shutdown_complete: shutdown_complete,
+            }),
+            counters: Counters::default(),
+            feed: broadcast::channel(64).0,
+        })
+    }
 
-        Ok(LogDbHandle(Mutex::new(Self {
+    // Database without any limits.
+    #[must_use]
+    #[allow(clippy::unwrap_used)]
+    pub fn new_testing(log_dir: PathBuf) -> LogDbHandle {
+        let (shutdown_complete_tx, _) = mpsc::channel::<()>(1);
+        Self::new(
+            shutdown_complete_tx,
             log_dir,
-            encoder: None,
-            prev_entry_time: UnixMicro::new(0),
-            disk_space,
-            min_disk_usage,
-            _shutdown_complete: shutdown_complete,
-        })))
+            ByteSize(0),
+            ByteSize(0),
+            None,
+            None,
+            None,
+        )
+        .unwrap()
     }
 
-    async fn save_log(&mut self, mut entry: LogEntryWithTime) -> Result<(), SaveLogError> {
+    // Returns the chunk ID and the entry as it was saved, the entry
+    // is None if it was merged into the previous entry as a repeat.
+    async fn save_log(
+        &mut self,
+        mut entry: LogEntryWithTime,
+    ) -> Result<(String, Option<LogEntryWithTime>), SaveLogError> {
         let chunk_id = time_to_id(entry.time)?;
 
         let encoder = if let Some(encoder) = &mut self.encoder {
@@ -165,15 +472,34 @@ impl LogDb {
                 let (encoder, prev_entry_time) =
                     ChunkEncoder::new(self.log_dir.clone(), chunk_id).await?;
                 self.prev_entry_time = prev_entry_time;
+                self.prev_entry = None;
                 self.encoder.insert(encoder)
             }
         } else {
             let (encoder, prev_entry_time) =
                 ChunkEncoder::new(self.log_dir.clone(), chunk_id).await?;
             self.prev_entry_time = prev_entry_time;
+            self.prev_entry = None;
             self.encoder.insert(encoder)
         };
 
+        if let (Some(window), Some(prev)) = (self.dedupe_window, &mut self.prev_entry) {
+            let window = u64::try_from(window.as_micros()).unwrap_or(u64::MAX);
+            let elapsed = entry.time.checked_sub(prev.time).map(|v| *v);
+            if encoder.data_size != DATA_SIZE_V0
+                && entry.is_repeat_of(prev)
+                && elapsed.is_some_and(|v| v < window)
+            {
+                let repeat_count = prev
+                    .repeat_count
+                    .checked_add(entry.repeat_count)
+                    .ok_or(SaveLogError::IncrementRepeatCount)?;
+                encoder.update_repeat_count(repeat_count).await?;
+                prev.repeat_count = repeat_count;
+                return Ok((encoder.chunk_id.clone(), None));
+            }
+        }
+
         if entry.time <= self.prev_entry_time {
             entry.time = self
                 .prev_entry_time
@@ -184,17 +510,30 @@ impl LogDb {
         encoder.encode(&entry).await?;
 
         self.prev_entry_time = entry.time;
+        self.prev_entry = Some(entry.clone());
 
-        Ok(())
+        Ok((encoder.chunk_id.clone(), Some(entry)))
     }
 
     // Query logs in database.
     async fn query(&self, mut q: LogQuery) -> Result<Vec<LogEntryWithTime>, QueryLogsError> {
         let chunk_ids = self.list_chunks_before(q.time).await?;
+        let since_id = q
+            .since
+            .map(time_to_id)
+            .transpose()
+            .map_err(QueryLogsError::SinceToId)?;
 
         let mut entries = Vec::new();
         //for i := len(chunkIDs) - 1; i >= 0; i-- {
         for chunk_id in chunk_ids.iter().rev() {
+            // Older chunks only contain entries before `since`.
+            if since_id
+                .as_ref()
+                .is_some_and(|since_id| chunk_id < since_id)
+            {
+                break;
+            }
             if let Err(e) = self.query_chunk(&q, &mut entries, chunk_id).await {
                 eprintln!("log store warning: {e}");
             }
@@ -242,6 +581,11 @@ impl LogDb {
                 Err(e) => return Err(QueryChunkError::Decode(e)),
             };
 
+            // Entries are ordered by time, the rest are older.
+            if q.since.is_some_and(|since| entry.time < since) {
+                break;
+            }
+
             if !q.entry_matches_filter(&entry) {
                 continue;
             }
@@ -313,22 +657,60 @@ impl LogDb {
         .expect("join")
     }
 
-    // Prunes a single chunk if needed.
     async fn prune(&self) -> Result<(), PurgeError> {
+        self.prune_at(UnixMicro::now()).await
+    }
+
+    async fn sync(&mut self) -> Result<(), std::io::Error> {
+        match &mut self.encoder {
+            Some(encoder) => encoder.sync().await,
+            None => Ok(()),
+        }
+    }
+
+    // Prunes all chunks older than `max_age` and a single chunk if
+    // the disk usage is too high. The active chunk is never pruned.
+    async fn prune_at(&self, now: UnixMicro) -> Result<(), PurgeError> {
         use PurgeError::*;
-        let dir_size = dir_size(self.log_dir.clone()).await?;
+        let active_chunk = self.encoder.as_ref().map(|v| v.chunk_id.as_str());
+        let mut chunks: Vec<String> = self
+            .list_chunks()
+            .await
+            .map_err(ListChunks)?
+            .into_iter()
+            .filter(|chunk| Some(chunk.as_str()) != active_chunk)
+            .collect();
+
+        if let Some(max_age) = self.max_age {
+            let max_age = UnixMicro::new(u64::try_from(max_age.as_micros()).unwrap_or(u64::MAX));
+            if let Some(cutoff) = now.checked_sub(max_age) {
+                // A chunk is expired if the next chunk starts before the cutoff.
+                let cutoff_id = time_to_id(cutoff)?;
+                let n_expired = chunks
+                    .iter()
+                    .take_while(|chunk| chunk.as_str() < cutoff_id.as_str())
+                    .count();
+                for chunk in chunks.drain(..n_expired) {
+                    self.remove_chunk(&chunk).await?;
+                }
+            }
+        }
 
+        let dir_size = dir_size(self.log_dir.clone()).await?;
         if dir_size <= ByteSize(self.disk_space.as_u64() / 100) || dir_size <= self.min_disk_usage {
             return Ok(());
         }
 
-        let chunks = self.list_chunks().await.map_err(ListChunks)?;
         let Some(chunk_to_remove) = chunks.first() else {
             // No chunks.
             return Ok(());
         };
+        self.remove_chunk(chunk_to_remove).await
+    }
 
-        let (data_path, msg_path) = chunk_id_to_paths(&self.log_dir, chunk_to_remove);
+    async fn remove_chunk(&self, chunk_id: &str) -> Result<(), PurgeError> {
+        use PurgeError::*;
+        let (data_path, msg_path) = chunk_id_to_paths(&self.log_dir, chunk_id);
 
         tokio::fs::remove_file(&data_path)
             .await
@@ -339,6 +721,94 @@ impl LogDb {
 
         Ok(())
     }
+
+    // The valid entries are written to temporary files which replace the
+    // chunk once complete, see `recover_compactions` for crash safety.
+    async fn compact_chunk(&mut self, chunk_id: &str) -> Result<CompactStats, CompactChunkError> {
+        use CompactChunkError::*;
+        if self
+            .encoder
+            .as_ref()
+            .is_some_and(|v| v.chunk_id == chunk_id)
+        {
+            // The encoder must reopen the chunk after it has been replaced.
+            self.encoder = None;
+            self.prev_entry = None;
+        }
+
+        let (data_path, msg_path) = chunk_id_to_paths(&self.log_dir, chunk_id);
+        let (data_tmp_path, msg_tmp_path, data_compact_path) =
+            compaction_paths(&self.log_dir, chunk_id);
+        let size_before = get_file_size(&data_path).await + get_file_size(&msg_path).await;
+
+        let mut decoder = ChunkDecoder::new(&self.log_dir, chunk_id).await?;
+        let mut data_file = BufWriter::new(File::create(&data_tmp_path).await.map_err(CreateFile)?);
+        let mut msg_file = BufWriter::new(File::create(&msg_tmp_path).await.map_err(CreateFile)?);
+
+        data_file
+            .write_all(&[decoder.version])
+            .await
+            .map_err(Write)?;
+
+        let mut stats = CompactStats::default();
+        let mut msg_pos = 0;
+        for i in 0..decoder.n_entries {
+            let entry = match decoder.decode(i).await {
+                Ok((v, _)) => v,
+                Err(DecodeError::RecoverableDecodeEntry(..)) => {
+                    stats.entries_removed += 1;
+                    continue;
+                }
+                Err(e) => return Err(Decode(e)),
+            };
+            let mut buf = Vec::with_capacity(DATA_SIZE);
+            encode_entry(&mut buf, &entry, &mut msg_file, &mut msg_pos).await?;
+            buf.truncate(decoder.data_size);
+            data_file.write_all(&buf).await.map_err(Write)?;
+            stats.entries_kept += 1;
+        }
+        drop(decoder);
+
+        data_file.flush().await.map_err(Write)?;
+        data_file.get_ref().sync_all().await.map_err(Sync)?;
+        msg_file.flush().await.map_err(Write)?;
+        msg_file.get_ref().sync_all().await.map_err(Sync)?;
+
+        // The compaction is committed once this rename has completed.
+        tokio::fs::rename(&data_tmp_path, &data_compact_path)
+            .await
+            .map_err(Rename)?;
+        finish_compaction(&self.log_dir, chunk_id).map_err(Rename)?;
+
+        stats.size_before = ByteSize(size_before);
+        stats.size_after =
+            ByteSize(get_file_size(&data_path).await + get_file_size(&msg_path).await);
+        Ok(stats)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CompactChunkError {
+    #[error("new chunk decoder: {0}")]
+    NewChunkDecoder(#[from] NewChunkDecoderError),
+
+    #[error("decode: {0}")]
+    Decode(DecodeError),
+
+    #[error("encode entry: {0}")]
+    EncodeEntry(#[from] EncodeEntryError),
+
+    #[error("create file: {0}")]
+    CreateFile(std::io::Error),
+
+    #[error("write: {0}")]
+    Write(std::io::Error),
+
+    #[error("sync: {0}")]
+    Sync(std::io::Error),
+
+    #[error("rename: {0}")]
+    Rename(std::io::Error),
 }
 
 #[derive(Debug, Error)]
@@ -366,10 +836,13 @@ pub enum ListChunksBeforeError {
 pub enum QueryLogsError {
     #[error("list chunks before: {0}")]
     ListChunksBefore(#[from] ListChunksBeforeError),
+
+    #[error("since to id: {0}")]
+    SinceToId(TimeToIdError),
 }
 
 #[derive(Debug, Error)]
-enum SaveLogError {
+pub enum SaveLogError {
     #[error("{0}")]
     TimeToId(#[from] TimeToIdError),
 
@@ -379,6 +852,9 @@ enum SaveLogError {
     #[error("increment prev time")]
     IncrementPrevTime,
 
+    #[error("increment repeat count")]
+    IncrementRepeatCount,
+
     #[error("{0}")]
     Encode(#[from] EncodeError),
 }
@@ -388,6 +864,9 @@ enum PurgeError {
     #[error("dir size: {0}")]
     DirSize(#[from] DirSizeError),
 
+    #[error("{0}")]
+    TimeToId(#[from] TimeToIdError),
+
     #[error("list chunks: {0}")]
     ListChunks(std::io::Error),
 
@@ -410,6 +889,9 @@ pub struct LogQuery {
 
     pub time: Option<UnixMicro>,
 
+    // Entries before this time are excluded, the query stops early.
+    pub since: Option<UnixMicro>,
+
     #[serde(default)]
     #[serde(deserialize_with = "deserialize_csv_option")]
     pub monitors: Vec<MonitorId>,
@@ -481,14 +963,63 @@ fn chunk_id_to_paths(log_dir: &Path, chunk_id: &str) -> (PathBuf, PathBuf) {
     (data_path, msg_path)
 }
 
+// Returns the temporary data and msg paths and the
+// path of the data file after the compaction is committed.
+fn compaction_paths(log_dir: &Path, chunk_id: &str) -> (PathBuf, PathBuf, PathBuf) {
+    let data_tmp_path = log_dir.join(chunk_id.to_owned() + ".data.tmp");
+    let msg_tmp_path = log_dir.join(chunk_id.to_owned() + ".msg.tmp");
+    let data_compact_path = log_dir.join(chunk_id.to_owned() + ".data.compact");
+    (data_tmp_path, msg_tmp_path, data_compact_path)
+}
+
+// Replaces the chunk files with the compacted files. The msg file
+// is replaced first, a crash in between is resolved on the next call.
+fn finish_compaction(log_dir: &Path, chunk_id: &str) -> Result<(), std::io::Error> {
+    let (data_path, msg_path) = chunk_id_to_paths(log_dir, chunk_id);
+    let (_, msg_tmp_path, data_compact_path) = compaction_paths(log_dir, chunk_id);
+    if msg_tmp_path.exists() {
+        std::fs::rename(msg_tmp_path, msg_path)?;
+    }
+    std::fs::rename(data_compact_path, data_path)
+}
+
+// Finishes committed compactions and removes the temporary
+// files of compactions that were interrupted before the commit.
+fn recover_compactions(log_dir: &Path) -> Result<(), std::io::Error> {
+    let mut names = Vec::new();
+    for file in std::fs::read_dir(log_dir)? {
+        if let Ok(name) = file?.file_name().into_string() {
+            names.push(name);
+        }
+    }
+    for name in &names {
+        if let Some(chunk_id) = name.strip_suffix(".data.compact") {
+            finish_compaction(log_dir, chunk_id)?;
+        }
+    }
+    for name in &names {
+        if name.ends_with(".data.tmp") || name.ends_with(".msg.tmp") {
+            match std::fs::remove_file(log_dir.join(name)) {
+                Ok(()) => {}
+                // Renamed by `finish_compaction`.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Ok(())
+}
+
 struct ChunkDecoder {
     n_entries: usize,
+    version: u8,
+    data_size: usize,
     data_file: RevBufReader<File>,
     msg_file: RevBufReader<File>,
 }
 
 #[derive(Debug, Error)]
-enum NewChunkDecoderError {
+pub enum NewChunkDecoderError {
     #[error("open data file: {0}")]
     OpenDataFile(std::io::Error),
 
@@ -525,9 +1056,7 @@ impl ChunkDecoder {
             .await
             .map_err(ReadVersion)?;
 
-        if version[0] != 0 {
-            return Err(UnknownChunkVersion);
-        }
+        let data_size = data_size(version[0]).ok_or(UnknownChunkVersion)?;
 
         let data_file_size = data_file.metadata().await.map_err(DataFileMetadata)?.len();
 
@@ -543,7 +1072,9 @@ impl ChunkDecoder {
         Ok(Self {
             msg_file,
             data_file,
-            n_entries: calculate_n_entries(data_file_size)?,
+            n_entries: calculate_n_entries(data_file_size, data_size)?,
+            version: version[0],
+            data_size,
         })
     }
 
@@ -557,21 +1088,46 @@ impl ChunkDecoder {
         let (mut l, mut r) = (0, self.n_entries - 1);
         while l <= r {
             let i = (l + r) / 2;
-            let (entry, _) = self.decode(i).await?;
+
+            // Corrupted entries are skipped by using the next valid entry.
+            let Some((valid_i, entry)) = self.decode_first_valid(i, r).await? else {
+                let Some(v) = i.checked_sub(1) else { break };
+                r = v;
+                continue;
+            };
 
             match entry.time.cmp(&time) {
-                Ordering::Less => l = i + 1,
-                Ordering::Equal => return Ok(i),
-                Ordering::Greater => r = i - 1,
+                Ordering::Less => l = valid_i + 1,
+                Ordering::Equal => return Ok(valid_i),
+                Ordering::Greater => {
+                    let Some(v) = i.checked_sub(1) else { break };
+                    r = v;
+                }
             }
         }
         Ok(l)
     }
 
+    // Returns the first entry in the range that can be decoded.
+    async fn decode_first_valid(
+        &mut self,
+        start: usize,
+        end: usize,
+    ) -> Result<Option<(usize, LogEntryWithTime)>, DecodeError> {
+        for i in start..=end {
+            match self.decode(i).await {
+                Ok((entry, _)) => return Ok(Some((i, entry))),
+                Err(DecodeError::RecoverableDecodeEntry(..)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
     async fn decode(&mut self, index: usize) -> Result<(LogEntryWithTime, u32), DecodeError> {
         use DecodeError::*;
         let index = u64::try_from(index)?;
-        let data_size_u64 = u64::try_from(DATA_SIZE)?;
+        let data_size_u64 = u64::try_from(self.data_size)?;
         let entry_pos: u64 = CHUNK_HEADER_LENGTH
             .checked_add(index.checked_mul(data_size_u64).ok_or(Mul)?)
             .ok_or(Add)?;
@@ -582,19 +1138,17 @@ impl ChunkDecoder {
             .map_err(Seek)?;
 
         let mut raw_entry = [0; DATA_SIZE];
-        self.data_file
-            .read_exact(&mut raw_entry)
-            .await
-            .map_err(Read)?;
+        let raw_entry = &mut raw_entry[..self.data_size];
+        self.data_file.read_exact(raw_entry).await.map_err(Read)?;
 
-        decode_entry(&raw_entry, &mut self.msg_file)
+        decode_entry(raw_entry, &mut self.msg_file)
             .await
             .map_err(|e| RecoverableDecodeEntry(index, entry_pos, e))
     }
 }
 
 #[derive(Error, Debug)]
-enum CalculateDataEndError {
+pub enum CalculateDataEndError {
     #[error("{0}")]
     CalculateEntries(#[from] CalculateEntriesError),
 
@@ -608,17 +1162,17 @@ enum CalculateDataEndError {
     Mul,
 }
 
-fn calculate_data_end(size: u64) -> Result<u64, CalculateDataEndError> {
+fn calculate_data_end(size: u64, data_size: usize) -> Result<u64, CalculateDataEndError> {
     use CalculateDataEndError::*;
-    let n_entries = calculate_n_entries(size)?;
+    let n_entries = calculate_n_entries(size, data_size)?;
 
     CHUNK_HEADER_LENGTH
-        .checked_add(u64::try_from(n_entries.checked_mul(DATA_SIZE).ok_or(Mul)?)?)
+        .checked_add(u64::try_from(n_entries.checked_mul(data_size).ok_or(Mul)?)?)
         .ok_or(Add)
 }
 
 #[derive(Debug, Error)]
-enum DecodeError {
+pub enum DecodeError {
     #[error("{0}")]
     TryFromInt(#[from] std::num::TryFromIntError),
 
@@ -645,7 +1199,7 @@ enum DecoderSearchError {
 }
 
 #[derive(Debug, Error)]
-enum CalculateEntriesError {
+pub enum CalculateEntriesError {
     #[error("{0}")]
     TryFromInt(#[from] std::num::TryFromIntError),
 
@@ -656,19 +1210,19 @@ enum CalculateEntriesError {
     Div,
 }
 
-fn calculate_n_entries(size: u64) -> Result<usize, CalculateEntriesError> {
+fn calculate_n_entries(size: u64, data_size: usize) -> Result<usize, CalculateEntriesError> {
     use CalculateEntriesError::*;
     // (size - chunkHeaderLength) / dataSize
     Ok(usize::try_from(
         size.checked_sub(CHUNK_HEADER_LENGTH)
             .ok_or(Sub)?
-            .checked_div(u64::try_from(DATA_SIZE)?)
+            .checked_div(u64::try_from(data_size)?)
             .ok_or(Div)?,
     )?)
 }
 
 #[derive(Debug, Error)]
-enum NewChunkEncoderError {
+pub enum NewChunkEncoderError {
     #[error("open file: {0}")]
     OpenFile(std::io::Error),
 
@@ -708,6 +1262,15 @@ struct ChunkEncoder {
     data_file: File,
     msg_file: File,
     msg_pos: u32,
+
+    // Existing chunks keep the version they were created with.
+    data_size: usize,
+
+    // The last entry that was written by this encoder.
+    last_record: Option<Vec<u8>>,
+
+    // Entries have been written since the last sync.
+    dirty: bool,
 }
 
 impl ChunkEncoder {
@@ -722,6 +1285,7 @@ impl ChunkEncoder {
         let data_file_size = get_file_size(&data_path).await;
         let mut prev_entry_time = UnixMicro::new(0);
         let mut msg_pos = 0;
+        let mut data_size = DATA_SIZE;
         if data_file_size == 0 {
             let mut file = tokio::fs::OpenOptions::new()
                 .create(true)
@@ -738,6 +1302,7 @@ impl ChunkEncoder {
             file.flush().await.map_err(Flush)?;
         } else {
             let mut decoder = ChunkDecoder::new(&log_dir, &chunk_id).await?;
+            data_size = decoder.data_size;
 
             // Find the first valid entry from the end.
             // Treat file as empty if no valid entry is found.
@@ -753,8 +1318,9 @@ impl ChunkEncoder {
                     };
 
                     prev_entry_time = last_entry.time;
-                    data_end = calculate_data_end(data_file_size)?;
+                    data_end = calculate_data_end(data_file_size, data_size)?;
                     msg_pos = msg_offset + u32::try_from(last_entry.message.len())? + 1;
+                    break;
                 }
             }
         }
@@ -790,6 +1356,9 @@ impl ChunkEncoder {
                 data_file,
                 msg_file,
                 msg_pos,
+                data_size,
+                last_record: None,
+                dirty: false,
             },
             prev_entry_time,
         ))
@@ -798,6 +1367,7 @@ impl ChunkEncoder {
     async fn encode(&mut self, entry: &LogEntryWithTime) -> Result<(), EncodeError> {
         let mut buf = Vec::with_capacity(DATA_SIZE);
         encode_entry(&mut buf, entry, &mut self.msg_file, &mut self.msg_pos).await?;
+        buf.truncate(self.data_size);
 
         self.data_file
             .write_all(&buf)
@@ -805,16 +1375,58 @@ impl ChunkEncoder {
             .map_err(EncodeError::Write)?;
 
         self.data_file.flush().await.map_err(EncodeError::Flush)?;
+        self.last_record = Some(buf);
+        self.dirty = true;
 
         Ok(())
     }
+
+    // Overwrites the repeat count of the last encoded entry.
+    async fn update_repeat_count(&mut self, repeat_count: u32) -> Result<(), EncodeError> {
+        use EncodeError::*;
+        let record = self.last_record.as_mut().ok_or(NoLastRecord)?;
+        record[DATA_SIZE_V0..DATA_SIZE_V1].copy_from_slice(&repeat_count.to_be_bytes());
+        if record.len() == DATA_SIZE {
+            let checksum = crc32(&record[..DATA_SIZE_V1]);
+            record[DATA_SIZE_V1..].copy_from_slice(&checksum.to_be_bytes());
+        }
+
+        self.data_file
+            .seek(SeekFrom::Current(-i64::try_from(record.len())?))
+            .await
+            .map_err(Seek)?;
+        self.data_file.write_all(record).await.map_err(Write)?;
+        self.data_file.flush().await.map_err(Flush)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    async fn sync(&mut self) -> Result<(), std::io::Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+        // The messages must be on disk before the entries that reference them.
+        self.msg_file.sync_data().await?;
+        self.data_file.sync_data().await?;
+        self.dirty = false;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Error)]
-enum EncodeError {
+pub enum EncodeError {
     #[error("encode entry: {0}")]
     EncodeEntry(#[from] EncodeEntryError),
 
+    #[error("no entry has been written")]
+    NoLastRecord,
+
+    #[error("{0}")]
+    TryFromInt(#[from] std::num::TryFromIntError),
+
+    #[error("seek: {0}")]
+    Seek(std::io::Error),
+
     #[error("write: {0}")]
     Write(std::io::Error),
 
@@ -823,7 +1435,7 @@ enum EncodeError {
 }
 
 #[derive(Debug, Error)]
-enum EncodeEntryError {
+pub enum EncodeEntryError {
     #[error("write: {0}")]
     Write(#[from] std::io::Error),
 
@@ -859,28 +1471,44 @@ async fn encode_entry(
     msg_file.write_all(&[b'\n']).await?;
     msg_file.flush().await.map_err(EncodeEntryError::Flush)?;
 
+    let mut record = Vec::with_capacity(DATA_SIZE);
+
     // Time.
-    buf.write_all(entry.time.to_be_bytes().as_slice()).await?;
+    record
+        .write_all(entry.time.to_be_bytes().as_slice())
+        .await?;
 
     // Source.
-    buf.write_all(entry.source.as_bytes()).await?;
-    buf.write_all(&b" ".repeat(LOG_SOURCE_MAX_LENGTH - src_length))
+    record.write_all(entry.source.as_bytes()).await?;
+    record
+        .write_all(&b" ".repeat(LOG_SOURCE_MAX_LENGTH - src_length))
         .await?;
 
     // Monitor ID.
     if let Some(monitor_id) = &entry.monitor_id {
-        buf.write_all(monitor_id.as_bytes()).await?;
+        record.write_all(monitor_id.as_bytes()).await?;
     }
-    buf.write_all(&b" ".repeat(MONITOR_ID_MAX_LENGTH - id_length))
+    record
+        .write_all(&b" ".repeat(MONITOR_ID_MAX_LENGTH - id_length))
         .await?;
 
     // Message offset and size.
-    buf.write_all(&msg_offset.to_be_bytes()).await?;
-    buf.write_all(&u16::try_from(entry.message.len())?.to_be_bytes())
+    record.write_all(&msg_offset.to_be_bytes()).await?;
+    record
+        .write_all(&u16::try_from(entry.message.len())?.to_be_bytes())
         .await?;
 
     // Level.
-    buf.write_all(&entry.level.as_u8().to_be_bytes()).await?;
+    record.write_all(&entry.level.as_u8().to_be_bytes()).await?;
+
+    // Repeat count.
+    record.write_all(&entry.repeat_count.to_be_bytes()).await?;
+
+    // Checksum.
+    let checksum = crc32(&record);
+    record.write_all(&checksum.to_be_bytes()).await?;
+
+    buf.write_all(&record).await?;
 
     // *msg_offset += entry.message.len() + 1
     *msg_offset = msg_offset
@@ -892,7 +1520,7 @@ async fn encode_entry(
 }
 
 #[derive(Debug, Error)]
-enum RecoverableDecodeEntryError {
+pub enum RecoverableDecodeEntryError {
     #[error("{0}")]
     TryFromSlice(#[from] std::array::TryFromSliceError),
 
@@ -916,14 +1544,24 @@ enum RecoverableDecodeEntryError {
 
     #[error("parse log message: {0}")]
     ParseLogMessage(#[from] ParseLogMessageError),
+
+    #[error("checksum mismatch")]
+    Checksum,
 }
 
 async fn decode_entry<T: AsyncRead + AsyncSeek + Unpin>(
-    buf: &[u8; DATA_SIZE],
+    buf: &[u8],
     msg_file: &mut T,
 ) -> Result<(LogEntryWithTime, u32), RecoverableDecodeEntryError> {
     use RecoverableDecodeEntryError::*;
 
+    // Partially written entries are detected by the checksum.
+    if let Some(checksum) = buf.get(DATA_SIZE_V1..DATA_SIZE) {
+        if crc32(&buf[..DATA_SIZE_V1]) != u32::from_be_bytes(checksum.try_into()?) {
+            return Err(Checksum);
+        }
+    }
+
     let time = u64::from_be_bytes(buf[..8].try_into()?);
     let source = String::from_utf8(buf[8..16].to_owned())?;
     let monitor_id = String::from_utf8(buf[16..40].to_owned())?;
@@ -931,6 +1569,10 @@ async fn decode_entry<T: AsyncRead + AsyncSeek + Unpin>(
     let msg_offset = u32::from_be_bytes(buf[40..44].try_into()?);
     let msg_size = u16::from_be_bytes(buf[44..46].try_into()?);
     let level = buf[46].to_owned();
+    let repeat_count = match buf.get(47..51) {
+        Some(v) => u32::from_be_bytes(v.try_into()?),
+        None => 1,
+    };
 
     msg_file
         .seek(SeekFrom::Start(msg_offset.into()))
@@ -955,6 +1597,7 @@ async fn decode_entry<T: AsyncRead + AsyncSeek + Unpin>(
             monitor_id,
             level: LogLevel::try_from(level)?,
             message: String::from_utf8(msg_buf)?.try_into()?,
+            repeat_count,
         },
         msg_offset,
     ))
@@ -988,7 +1631,8 @@ async fn get_file_size(path: &Path) -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use common::LogMessage;
+    use common::{DummyLogger, LogMessage, LOG_MESSAGE_MAX_LENGTH};
+    use futures_lite::StreamExt;
     use pretty_assertions::assert_eq;
     use std::io::Cursor;
     use tempfile::tempdir;
@@ -1001,6 +1645,9 @@ mod tests {
             log_dir.to_owned(),
             ByteSize(0),
             ByteSize(0),
+            None,
+            None,
+            None,
         )
         .unwrap()
     }
@@ -1022,6 +1669,7 @@ mod tests {
             monitor_id: Some(m_id("m1")),
             message: msg("msg1"),
             time: UnixMicro::new(4000),
+            repeat_count: 1,
         }
     }
     fn msg2() -> LogEntryWithTime {
@@ -1031,6 +1679,7 @@ mod tests {
             monitor_id: None,
             message: msg("msg2"),
             time: UnixMicro::new(3000),
+            repeat_count: 1,
         }
     }
     fn msg3() -> LogEntryWithTime {
@@ -1040,6 +1689,7 @@ mod tests {
             monitor_id: Some(m_id("m2")),
             message: msg("msg3"),
             time: UnixMicro::new(2000),
+            repeat_count: 1,
         }
     }
     /*msg4 := Log{
@@ -1188,6 +1838,7 @@ mod tests {
             monitor_id: None,
             time: UnixMicro::new(time),
             message: time.to_string().try_into().unwrap(),
+            repeat_count: 1,
         }
     }
 
@@ -1197,6 +1848,41 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_query_pages() {
+        let temp_dir = tempdir().unwrap();
+        let db = new_test_db(temp_dir.path());
+        for i in 1..=5 {
+            db.save_log_testing(new_test_entry(i)).await;
+        }
+
+        let times = |entries: Vec<LogEntryWithTime>| -> Vec<u64> {
+            entries.into_iter().map(|v| *v.time).collect()
+        };
+        let db = &db;
+        let new_pages = |time: Option<u64>, limit: Option<usize>| LogPages {
+            log_db: db,
+            remaining: limit,
+            q: LogQuery {
+                time: time.map(UnixMicro::new),
+                ..Default::default()
+            },
+            page_size: 2,
+        };
+
+        let mut pages = new_pages(None, None);
+        assert_eq!(vec![5, 4], times(pages.next().await.unwrap().unwrap()));
+        assert_eq!(vec![3, 2], times(pages.next().await.unwrap().unwrap()));
+        assert_eq!(vec![1], times(pages.next().await.unwrap().unwrap()));
+        assert!(pages.next().await.unwrap().is_none());
+
+        // Limit and time.
+        let mut pages = new_pages(Some(5), Some(3));
+        assert_eq!(vec![4, 3], times(pages.next().await.unwrap().unwrap()));
+        assert_eq!(vec![2], times(pages.next().await.unwrap().unwrap()));
+        assert!(pages.next().await.unwrap().is_none());
+    }
+
     #[tokio::test]
     async fn test_log_db_write_and_read() {
         let msg1 = new_test_entry(1);
@@ -1219,6 +1905,28 @@ mod tests {
         assert_eq!(vec![msg3, msg2, msg1], entries);
     }
 
+    #[tokio::test]
+    async fn test_log_db_long_message() {
+        let entry = LogEntry::new(LogLevel::Error, "x", None, "a".repeat(70 * 1024));
+        let entry = LogEntryWithTime {
+            level: entry.level,
+            source: entry.source,
+            monitor_id: entry.monitor_id,
+            time: UnixMicro::new(1),
+            message: entry.message,
+            repeat_count: 1,
+        };
+        assert_eq!(LOG_MESSAGE_MAX_LENGTH, entry.message.len());
+
+        let temp_dir = tempdir().unwrap();
+        let db = new_test_db(temp_dir.path());
+        db.save_log(entry.clone()).await.unwrap();
+
+        let entries = db.query(empty_query()).await.unwrap();
+        assert_eq!(vec![entry], entries);
+        assert!(entries[0].message.ends_with("a…"));
+    }
+
     #[tokio::test]
     async fn test_log_db_multiple_chunks() {
         let msg1 = new_test_entry(1);
@@ -1244,7 +1952,304 @@ mod tests {
             monitor_id: None,
             time: UnixMicro::new(time),
             message: msg(message),
+            repeat_count: 1,
+        }
+    }
+
+    fn new_test_db_dedupe(log_dir: &Path, window: Duration) -> LogDbHandle {
+        let (shutdown_complete_tx, _) = mpsc::channel::<()>(1);
+        LogDb::new(
+            shutdown_complete_tx,
+            log_dir.to_owned(),
+            ByteSize(0),
+            ByteSize(0),
+            None,
+            Some(window),
+            None,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_log_db_dedupe() {
+        let temp_dir = tempdir().unwrap();
+        let db = new_test_db_dedupe(temp_dir.path(), Duration::from_secs(1));
+
+        for time in 1..=5 {
+            db.save_log_testing(new_test_entry2(time, "a")).await;
+        }
+
+        let mut want = new_test_entry2(1, "a");
+        want.repeat_count = 5;
+        assert_eq!(vec![want.clone()], db.query(empty_query()).await.unwrap());
+
+        // The count survives a restart.
+        let db = new_test_db_dedupe(temp_dir.path(), Duration::from_secs(1));
+        assert_eq!(vec![want], db.query(empty_query()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_log_db_dedupe_breaks() {
+        let temp_dir = tempdir().unwrap();
+        let db = new_test_db_dedupe(temp_dir.path(), Duration::from_secs(1));
+
+        db.save_log_testing(new_test_entry2(1, "a")).await;
+        db.save_log_testing(new_test_entry2(2, "a")).await;
+        db.save_log_testing(new_test_entry2(3, "b")).await;
+        db.save_log_testing(new_test_entry2(4, "a")).await;
+        // Outside the window of the previous entry.
+        db.save_log_testing(new_test_entry2(1_000_004, "a")).await;
+
+        let got: Vec<_> = db
+            .query(empty_query())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| (*v.time, v.message.to_string(), v.repeat_count))
+            .collect();
+        let want = vec![
+            (1_000_004, "a".to_owned(), 1),
+            (4, "a".to_owned(), 1),
+            (3, "b".to_owned(), 1),
+            (1, "a".to_owned(), 2),
+        ];
+        assert_eq!(want, got);
+    }
+
+    #[tokio::test]
+    async fn test_log_db_sync_loop() {
+        let temp_dir = tempdir().unwrap();
+        let (shutdown_complete_tx, _) = mpsc::channel::<()>(1);
+        let db = Arc::new(
+            LogDb::new(
+                shutdown_complete_tx,
+                temp_dir.path().to_owned(),
+                ByteSize(0),
+                ByteSize(0),
+                None,
+                None,
+                Some(Duration::from_millis(10)),
+            )
+            .unwrap(),
+        );
+        let is_dirty = || async { db.db.lock().await.encoder.as_ref().unwrap().dirty };
+
+        db.save_log_testing(new_test_entry2(1, "a")).await;
+        assert!(is_dirty().await);
+
+        let token = CancellationToken::new();
+        let db2 = db.clone();
+        let token2 = token.clone();
+        let handle = tokio::spawn(async move { db2.sync_loop(token2, DummyLogger::new()).await });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!is_dirty().await);
+
+        token.cancel();
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_log_db_sync_loop_disabled() {
+        let temp_dir = tempdir().unwrap();
+        let (shutdown_complete_tx, _) = mpsc::channel::<()>(1);
+        let db = LogDb::new(
+            shutdown_complete_tx,
+            temp_dir.path().to_owned(),
+            ByteSize(0),
+            ByteSize(0),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        db.save_log_testing(new_test_entry2(1, "a")).await;
+
+        // Should return without being cancelled.
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            db.sync_loop(CancellationToken::new(), DummyLogger::new()),
+        )
+        .await
+        .unwrap();
+        assert!(db.db.lock().await.encoder.as_ref().unwrap().dirty);
+    }
+
+    #[tokio::test]
+    async fn test_log_db_read_v0_chunk() {
+        let temp_dir = tempdir().unwrap();
+        let (data_path, msg_path) = chunk_id_to_paths(temp_dir.path(), "00000");
+
+        let mut data = vec![0];
+        let mut msg_file = Cursor::new(Vec::new());
+        let mut msg_pos = 0;
+        encode_entry(
+            &mut data,
+            &new_test_entry2(1, "a"),
+            &mut msg_file,
+            &mut msg_pos,
+        )
+        .await
+        .unwrap();
+        data.truncate(1 + DATA_SIZE_V0);
+        std::fs::write(data_path, data).unwrap();
+        std::fs::write(msg_path, msg_file.into_inner()).unwrap();
+
+        // Version 0 chunks are appended to without deduplication.
+        let db = new_test_db_dedupe(temp_dir.path(), Duration::from_secs(1));
+        db.save_log_testing(new_test_entry2(2, "a")).await;
+
+        let want = vec![new_test_entry2(2, "a"), new_test_entry2(1, "a")];
+        assert_eq!(want, db.query(empty_query()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_log_db_read_v1_chunk() {
+        let temp_dir = tempdir().unwrap();
+        let (data_path, msg_path) = chunk_id_to_paths(temp_dir.path(), "00000");
+
+        let mut data = vec![1];
+        let mut msg_file = Cursor::new(Vec::new());
+        let mut msg_pos = 0;
+        encode_entry(
+            &mut data,
+            &new_test_entry2(1, "a"),
+            &mut msg_file,
+            &mut msg_pos,
+        )
+        .await
+        .unwrap();
+        data.truncate(1 + DATA_SIZE_V1);
+        std::fs::write(data_path, data).unwrap();
+        std::fs::write(msg_path, msg_file.into_inner()).unwrap();
+
+        // Version 1 chunks are deduplicated without checksums.
+        let db = new_test_db_dedupe(temp_dir.path(), Duration::from_secs(1));
+        db.save_log_testing(new_test_entry2(2, "a")).await;
+        db.save_log_testing(new_test_entry2(3, "a")).await;
+
+        let mut want = new_test_entry2(2, "a");
+        want.repeat_count = 2;
+        assert_eq!(
+            vec![want, new_test_entry2(1, "a")],
+            db.query(empty_query()).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_log_db_reopen_chunk() {
+        let temp_dir = tempdir().unwrap();
+        let db = new_test_db(temp_dir.path());
+        db.save_log(new_test_entry2(1, "a")).await.unwrap();
+        db.save_log(new_test_entry2(2, "b")).await.unwrap();
+
+        // New messages are appended after the last entry.
+        let db = new_test_db(temp_dir.path());
+        db.save_log(new_test_entry2(3, "c")).await.unwrap();
+
+        let want = vec![
+            new_test_entry2(3, "c"),
+            new_test_entry2(2, "b"),
+            new_test_entry2(1, "a"),
+        ];
+        assert_eq!(want, db.query(empty_query()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_log_db_checksum_mismatch() {
+        let temp_dir = tempdir().unwrap();
+        let db = new_test_db(temp_dir.path());
+        for time in 1..=3 {
+            db.save_log(new_test_entry2(time, "a")).await.unwrap();
         }
+
+        // Change the time of the second entry.
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(temp_dir.path().join("00000.data"))
+            .await
+            .unwrap();
+        file.seek(SeekFrom::Start(1 + 55 + 7)).await.unwrap();
+        file.write_all(&[9]).await.unwrap();
+        file.flush().await.unwrap();
+
+        let db = new_test_db(temp_dir.path());
+        let want = vec![new_test_entry2(3, "a"), new_test_entry2(1, "a")];
+        assert_eq!(want, db.query(empty_query()).await.unwrap());
+
+        let query = LogQuery {
+            time: Some(UnixMicro::new(3)),
+            ..Default::default()
+        };
+        let want = vec![new_test_entry2(1, "a")];
+        assert_eq!(want, db.query(query).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_log_db_subscribe() {
+        let temp_dir = tempdir().unwrap();
+        let db = new_test_db(temp_dir.path());
+        let token = CancellationToken::new();
+        let q = LogQuery {
+            levels: vec![LogLevel::Error],
+            ..Default::default()
+        };
+        let mut stream = Box::pin(db.subscribe(q, token.clone()));
+
+        let mut info = new_test_entry2(2, "b");
+        info.level = LogLevel::Info;
+        db.save_log_testing(new_test_entry2(1, "a")).await;
+        db.save_log_testing(info).await;
+        db.save_log_testing(new_test_entry2(3, "c")).await;
+
+        assert_eq!(
+            Some(LogFeedItem::Entry(new_test_entry2(1, "a"))),
+            stream.next().await
+        );
+        assert_eq!(
+            Some(LogFeedItem::Entry(new_test_entry2(3, "c"))),
+            stream.next().await
+        );
+
+        token.cancel();
+        assert_eq!(None, stream.next().await);
+    }
+
+    #[tokio::test]
+    async fn test_log_db_subscribe_saved_entry() {
+        let temp_dir = tempdir().unwrap();
+        let db = new_test_db_dedupe(temp_dir.path(), Duration::from_secs(1));
+        let mut stream = Box::pin(db.subscribe(LogQuery::default(), CancellationToken::new()));
+
+        db.save_log_testing(new_test_entry2(5, "a")).await;
+        // Merged into the previous entry.
+        db.save_log_testing(new_test_entry2(6, "a")).await;
+        // The time is moved after the previous entry.
+        db.save_log_testing(new_test_entry2(5, "b")).await;
+
+        assert_eq!(
+            Some(LogFeedItem::Entry(new_test_entry2(5, "a"))),
+            stream.next().await
+        );
+        let saved = db.query(empty_query()).await.unwrap().remove(0);
+        assert_eq!(6, *saved.time);
+        assert_eq!(Some(LogFeedItem::Entry(saved)), stream.next().await);
+    }
+
+    #[tokio::test]
+    async fn test_log_db_subscribe_lagged() {
+        let temp_dir = tempdir().unwrap();
+        let db = new_test_db(temp_dir.path());
+        let mut stream = Box::pin(db.subscribe(LogQuery::default(), CancellationToken::new()));
+
+        for time in 1..=66 {
+            db.save_log_testing(new_test_entry2(time, "a")).await;
+        }
+        assert_eq!(Some(LogFeedItem::Lagged(2)), stream.next().await);
+        assert_eq!(
+            Some(LogFeedItem::Entry(new_test_entry2(3, "a"))),
+            stream.next().await
+        );
     }
 
     #[tokio::test]
@@ -1287,8 +2292,8 @@ mod tests {
             .open(temp_dir.path().join("00000.data"))
             .await
             .unwrap();
-        file.seek(SeekFrom::Start(48)).await.unwrap();
-        file.write_all(&[0].repeat(48)).await.unwrap();
+        file.seek(SeekFrom::Start(56)).await.unwrap();
+        file.write_all(&[0].repeat(55)).await.unwrap();
         file.flush().await.unwrap();
 
         let db = new_test_db(temp_dir.path());
@@ -1360,6 +2365,98 @@ mod tests {
         assert_eq!([entry2].as_slice(), got.as_slice());
     }
 
+    #[tokio::test]
+    async fn test_compact_chunk() {
+        let temp_dir = tempdir().unwrap();
+        let data_path = temp_dir.path().join("00000.data");
+        let msg_path = temp_dir.path().join("00000.msg");
+
+        let entry1 = new_test_entry2(1, "good1");
+        let entry2 = new_test_entry2(2, "bad");
+        let entry3 = new_test_entry2(3, "good2");
+
+        let db = new_test_db(temp_dir.path());
+        db.save_log(entry1.clone()).await.unwrap();
+        db.save_log(entry2).await.unwrap();
+        db.save_log(entry3.clone()).await.unwrap();
+
+        // Overwrite second entry with zeros.
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&data_path)
+            .await
+            .unwrap();
+        file.seek(SeekFrom::Start(56)).await.unwrap();
+        file.write_all(&[0].repeat(55)).await.unwrap();
+        file.flush().await.unwrap();
+
+        // Orphaned message bytes.
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&msg_path)
+            .await
+            .unwrap();
+        file.write_all(b"orphan\n").await.unwrap();
+        file.flush().await.unwrap();
+
+        let want = db.query(empty_query()).await.unwrap();
+        assert_eq!(vec![entry3.clone(), entry1.clone()], want);
+
+        let stats = db.compact_chunk("00000").await.unwrap();
+        assert_eq!(2, stats.entries_kept);
+        assert_eq!(1, stats.entries_removed);
+        assert_eq!(ByteSize(1 + 3 * 55 + 23), stats.size_before);
+        assert_eq!(ByteSize(1 + 2 * 55 + 12), stats.size_after);
+
+        assert_eq!(want, db.query(empty_query()).await.unwrap());
+        assert_eq!(
+            b"good1\ngood2\n".to_vec(),
+            std::fs::read(&msg_path).unwrap()
+        );
+
+        // The active chunk can still be written to.
+        let entry4 = new_test_entry2(4, "good3");
+        db.save_log(entry4.clone()).await.unwrap();
+        assert_eq!(
+            vec![entry4, entry3, entry1],
+            db.query(empty_query()).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recover_compactions() {
+        let temp_dir = tempdir().unwrap();
+        let path = |name: &str| temp_dir.path().join(name);
+
+        // Crashed after the commit.
+        std::fs::write(path("00000.data"), "old").unwrap();
+        std::fs::write(path("00000.msg"), "old").unwrap();
+        std::fs::write(path("00000.data.compact"), "new").unwrap();
+        std::fs::write(path("00000.msg.tmp"), "new").unwrap();
+
+        // Crashed before the commit.
+        std::fs::write(path("00001.data"), "old").unwrap();
+        std::fs::write(path("00001.msg"), "old").unwrap();
+        std::fs::write(path("00001.data.tmp"), "new").unwrap();
+        std::fs::write(path("00001.msg.tmp"), "new").unwrap();
+
+        new_test_db(temp_dir.path());
+
+        let mut names: Vec<_> = std::fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|v| v.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            vec!["00000.data", "00000.msg", "00001.data", "00001.msg"],
+            names
+        );
+        assert_eq!("new", std::fs::read_to_string(path("00000.data")).unwrap());
+        assert_eq!("new", std::fs::read_to_string(path("00000.msg")).unwrap());
+        assert_eq!("old", std::fs::read_to_string(path("00001.data")).unwrap());
+        assert_eq!("old", std::fs::read_to_string(path("00001.msg")).unwrap());
+    }
+
     #[tokio::test]
     async fn test_log_db_order() {
         let temp_dir = tempdir().unwrap();
@@ -1382,6 +2479,46 @@ mod tests {
         assert_eq!(want, got);
     }
 
+    #[tokio::test]
+    async fn test_log_db_query_since() {
+        let temp_dir = tempdir().unwrap();
+        let db = new_test_db(temp_dir.path());
+
+        let msg1 = new_test_entry(CHUNK_DURATION - 2);
+        let msg2 = new_test_entry(CHUNK_DURATION - 1);
+        let msg3 = new_test_entry(CHUNK_DURATION);
+        let msg4 = new_test_entry(CHUNK_DURATION + 1);
+        let msg5 = new_test_entry(CHUNK_DURATION + 2);
+        for msg in [&msg1, &msg2, &msg3, &msg4, &msg5] {
+            db.save_log(msg.clone()).await.unwrap();
+        }
+
+        #[rustfmt::skip]
+        let cases = vec![
+            // Both bounds across the chunk boundary.
+            (Some(*msg5.time), *msg2.time, vec![&msg4, &msg3, &msg2]),
+            (Some(*msg5.time), *msg2.time + 1, vec![&msg4, &msg3]),
+            (Some(*msg4.time), *msg1.time, vec![&msg3, &msg2, &msg1]),
+            // Within a single chunk.
+            (Some(*msg3.time), *msg1.time, vec![&msg2, &msg1]),
+            (Some(*msg5.time + 1), *msg4.time, vec![&msg5, &msg4]),
+            (None, *msg3.time, vec![&msg5, &msg4, &msg3]),
+            (None, *msg5.time + 1, vec![]),
+        ];
+        for (time, since, want) in cases {
+            let got = db
+                .query(LogQuery {
+                    time: time.map(UnixMicro::new),
+                    since: Some(UnixMicro::new(since)),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            let want: Vec<LogEntryWithTime> = want.into_iter().cloned().collect();
+            assert_eq!(want, got);
+        }
+    }
+
     #[tokio::test]
     async fn test_log_db_search() {
         let temp_dir = tempdir().unwrap();
@@ -1462,6 +2599,9 @@ mod tests {
             new_dir.clone(),
             ByteSize(0),
             ByteSize(0),
+            None,
+            None,
+            None,
         )
         .unwrap();
 
@@ -1477,9 +2617,15 @@ mod tests {
             monitor_id: Some(m_id("aabbccddeeffgghhiijjkkll")),
             message: msg("a"),
             time: UnixMicro::new(5),
+            repeat_count: 1,
         }
     }
 
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+
     #[tokio::test]
     async fn test_log_entry_encode() {
         let mut buf = Vec::with_capacity(DATA_SIZE);
@@ -1499,6 +2645,8 @@ mod tests {
             0, 0, 0, 0, // Message offset.
             0, 1,  // Message size.
             48, // Level.
+            0, 0, 0, 1, // Repeat count.
+            88, 102, 3, 159, // Checksum.
         ];
 
         assert_eq!(want, buf);
@@ -1585,6 +2733,9 @@ mod tests {
             log_dir.to_owned(),
             ByteSize::kb(10),
             ByteSize(0),
+            None,
+            None,
+            None,
         )
         .unwrap();
 
@@ -1619,6 +2770,9 @@ mod tests {
             log_dir.to_owned(),
             ByteSize::kb(10),
             ByteSize(0),
+            None,
+            None,
+            None,
         )
         .unwrap();
 
@@ -1645,6 +2799,9 @@ mod tests {
             log_dir.to_owned(),
             ByteSize(0),
             ByteSize(100),
+            None,
+            None,
+            None,
         )
         .unwrap();
 
@@ -1671,6 +2828,9 @@ mod tests {
             log_dir.to_owned(),
             ByteSize(0),
             ByteSize(0),
+            None,
+            None,
+            None,
         )
         .unwrap();
 
@@ -1680,6 +2840,100 @@ mod tests {
         drop(db);
         _ = shutdown_complete_rx.recv().await;
     }
+
+    fn new_test_prune_db(
+        log_dir: &Path,
+        disk_space: ByteSize,
+        max_age_chunks: Option<u64>,
+    ) -> LogDbHandle {
+        let (shutdown_complete_tx, _) = mpsc::channel::<()>(1);
+        LogDb::new(
+            shutdown_complete_tx,
+            log_dir.to_owned(),
+            disk_space,
+            ByteSize(0),
+            max_age_chunks.map(|v| Duration::from_micros(v * CHUNK_DURATION)),
+            None,
+            None,
+        )
+        .unwrap()
+    }
+
+    async fn prune_at(db: &LogDbHandle, now: UnixMicro) {
+        db.db.lock().await.prune_at(now).await.unwrap();
+    }
+
+    fn write_test_chunks(log_dir: &Path) {
+        write_test_chunk(log_dir, "00000");
+        write_test_chunk(log_dir, "00001");
+        write_test_chunk(log_dir, "00002");
+    }
+
+    #[tokio::test]
+    async fn test_log_prune_max_age() {
+        let temp_dir = tempdir().unwrap();
+        let log_dir = temp_dir.path();
+        let db = new_test_prune_db(log_dir, ByteSize::gb(100), Some(1));
+        write_test_chunks(log_dir);
+
+        // Chunk 00001 ends exactly at the cutoff.
+        prune_at(&db, UnixMicro::new(3 * CHUNK_DURATION)).await;
+        let want = vec!["00002.data".to_owned(), "00002.msg".to_owned()];
+        assert_eq!(want, list_files(log_dir));
+
+        // Nothing is old enough.
+        prune_at(&db, UnixMicro::new(3 * CHUNK_DURATION)).await;
+        assert_eq!(want, list_files(log_dir));
+    }
+
+    #[tokio::test]
+    async fn test_log_prune_max_age_size_only() {
+        let temp_dir = tempdir().unwrap();
+        let log_dir = temp_dir.path();
+        let db = new_test_prune_db(log_dir, ByteSize::kb(10), Some(100));
+        write_test_chunks(log_dir);
+
+        prune_at(&db, UnixMicro::new(3 * CHUNK_DURATION)).await;
+        let want = vec![
+            "00001.data".to_owned(),
+            "00001.msg".to_owned(),
+            "00002.data".to_owned(),
+            "00002.msg".to_owned(),
+        ];
+        assert_eq!(want, list_files(log_dir));
+    }
+
+    #[tokio::test]
+    async fn test_log_prune_max_age_and_size() {
+        let temp_dir = tempdir().unwrap();
+        let log_dir = temp_dir.path();
+        let db = new_test_prune_db(log_dir, ByteSize::kb(10), Some(2));
+        write_test_chunks(log_dir);
+
+        // Age prunes chunk 00000 and size prunes chunk 00001.
+        prune_at(&db, UnixMicro::new(3 * CHUNK_DURATION)).await;
+        let want = vec!["00002.data".to_owned(), "00002.msg".to_owned()];
+        assert_eq!(want, list_files(log_dir));
+    }
+
+    #[tokio::test]
+    async fn test_log_prune_active_chunk() {
+        let temp_dir = tempdir().unwrap();
+        let log_dir = temp_dir.path();
+        let db = new_test_prune_db(log_dir, ByteSize(0), Some(1));
+        db.save_log_testing(LogEntryWithTime {
+            level: LogLevel::Info,
+            source: src("s1"),
+            monitor_id: None,
+            message: msg("a"),
+            time: UnixMicro::new(1),
+            repeat_count: 1,
+        })
+        .await;
+
+        prune_at(&db, UnixMicro::new(10 * CHUNK_DURATION)).await;
+        assert_eq!(1, chunk_count(log_dir).await);
+    }
     /*
         t.Run("diskSpaceErr", func(t *testing.T) {
             stubError := errors.New("stub")
@@ -1714,6 +2968,10 @@ mod tests {
             prev_entry_time: UnixMicro::new(0),
             disk_space: ByteSize(0),
             min_disk_usage: ByteSize(0),
+            max_age: None,
+            dedupe_window: None,
+            prev_entry: None,
+            sync_interval: None,
             _shutdown_complete: shutdown_complete,
         };
         db.list_chunks().await.unwrap().len()
@@ -1730,4 +2988,41 @@ mod tests {
         file_names.sort();
         file_names
     }
+
+    #[tokio::test]
+    async fn test_log_db_stats_lagged() {
+        const N: u64 = 200;
+
+        let temp_dir = tempdir().unwrap();
+        let db = Arc::new(new_test_db(temp_dir.path()));
+        let logger = Arc::new(Logger::new(Vec::new()));
+        let token = CancellationToken::new();
+
+        let db2 = db.clone();
+        let logger2 = logger.clone();
+        let token2 = token.clone();
+        tokio::spawn(async move { db2.save_logs(token2, logger2).await });
+        while logger.feed.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        // Overflow the channel without yielding.
+        for i in 0..N {
+            let mut entry = msg1();
+            entry.time = UnixMicro::new(4000 + i);
+            logger.feed.send(entry).unwrap();
+        }
+
+        let stats = loop {
+            let stats = db.stats();
+            if stats.entries_written + stats.entries_dropped_lagged == N {
+                break stats;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        };
+        token.cancel();
+
+        assert!(stats.entries_dropped_lagged > 0);
+        assert_eq!(0, stats.save_errors);
+    }
 }
@@ -6,15 +6,20 @@ mod source;
 use recdb::RecDb;
 pub use source::MonitorSource;
 
-use crate::{recorder::new_recorder, source::SourceRtsp};
+use crate::{
+    recorder::{keyframe_to_jpeg, new_recorder},
+    source::SourceRtsp,
+};
 use async_trait::async_trait;
 use common::{
     monitor::{
-        ArcMonitorHooks, ArcSource, IMonitor, IMonitorManager, MonitorConfig, MonitorConfigs,
+        ArcLatestFrame, ArcMonitorHooks, ArcMonitorStats, ArcSource, IMonitor, IMonitorManager,
+        LatestFrameError, MonitorApplyConfigError, MonitorConfig, MonitorConfigs,
         MonitorDeleteError, MonitorInfo, MonitorRestartError, MonitorSetAndRestartError,
-        MonitorSetError, SourceConfig,
+        MonitorSetError, MonitorStatus, Snapshot, SourceConfig,
     },
-    ArcLogger, Event, LogEntry, LogLevel, MonitorId, StreamType,
+    time::UnixNano,
+    ArcLogger, DetectionEvent, Event, EventRateLimiter, LogEntry, LogLevel, MonitorId, StreamType,
 };
 use hls::HlsServer;
 use std::{
@@ -26,7 +31,7 @@ use thiserror::Error;
 use tokio::{
     self,
     io::AsyncWriteExt,
-    sync::{mpsc, oneshot, Mutex},
+    sync::{broadcast, mpsc, oneshot, watch, Mutex},
 };
 use tokio_util::sync::CancellationToken;
 
@@ -37,7 +42,53 @@ pub struct Monitor {
     shutdown_complete: Mutex<mpsc::Receiver<()>>,
     source_main_tx: mpsc::Sender<oneshot::Sender<ArcSource>>,
     source_sub_tx: mpsc::Sender<oneshot::Sender<Option<ArcSource>>>,
+    recorder: Arc<Recorder>,
+    stats: ArcMonitorStats,
+    latest_frame: ArcLatestFrame,
+    rate_limiter: Mutex<EventRateLimiter>,
+    detection_tx: broadcast::Sender<DetectionEvent>,
+    started: UnixNano,
+}
+
+impl Monitor {
+    // Stops the monitor but keeps the recorder running, the
+    // recorder will wait for the main source to be replaced.
+    async fn stop_keep_recorder(&self) -> Arc<Recorder> {
+        self.recorder.source_main_tx.send_replace(None);
+        self.token.cancel();
+        self.shutdown_complete.lock().await.recv().await;
+        self.recorder.clone()
+    }
+}
+
+// The recorder is owned separately from the monitor
+// so it can outlive it during a graceful restart.
+struct Recorder {
+    token: CancellationToken,
+    shutdown_complete: Mutex<mpsc::Receiver<()>>,
+    source_main_tx: watch::Sender<Option<ArcSource>>,
     send_event_tx: mpsc::Sender<Event>,
+    stats: ArcMonitorStats,
+
+    // Config the recorder was started with.
+    config: MonitorConfig,
+}
+
+impl Recorder {
+    // Returns true if the new config can be applied without restarting the recorder.
+    fn can_keep(&self, config: &MonitorConfig) -> bool {
+        let old = &self.config;
+        old.name() == config.name()
+            && old.always_record() == config.always_record()
+            && old.video_length() == config.video_length()
+            && old.video_max_size() == config.video_max_size()
+            && old.record_index() == config.record_index()
+            && old.encrypt_recordings() == config.encrypt_recordings()
+            && old.record_trigger() == config.record_trigger()
+            && old.keyframe_warn_interval() == config.keyframe_warn_interval()
+            && old.thumb_max_size() == config.thumb_max_size()
+            && old.thumb_quality() == config.thumb_quality()
+    }
 }
 
 #[async_trait]
@@ -54,7 +105,9 @@ impl IMonitor for Monitor {
 
     async fn stop(&self) {
         self.token.cancel();
+        self.recorder.token.cancel();
         self.shutdown_complete.lock().await.recv().await;
+        self.recorder.shutdown_complete.lock().await.recv().await;
     }
 
     // Return sub stream if it exists otherwise returns main stream.
@@ -100,10 +153,32 @@ impl IMonitor for Monitor {
         }
     }
 
-    async fn send_event(&self, event: Event) {
+    async fn send_event(&self, mut event: Event) {
+        let n_limited = self.rate_limiter.lock().await.apply(&mut event);
+        if n_limited != 0 {
+            self.stats
+                .add_detections_rate_limited(u64::try_from(n_limited).unwrap_or(u64::MAX));
+            if event.detections.is_empty() {
+                return;
+            }
+        }
+        self.stats
+            .add_detections(u64::try_from(event.detections.len()).unwrap_or(u64::MAX));
+        if self.detection_tx.receiver_count() != 0 {
+            for d in &event.detections {
+                // Only fails if every subscriber was dropped in the meantime.
+                _ = self.detection_tx.send(DetectionEvent {
+                    monitor_id: self.config.id().to_owned(),
+                    time: event.time,
+                    label: d.label.clone(),
+                    score: d.score,
+                    region: d.region.clone(),
+                });
+            }
+        }
         tokio::select! {
             () = self.token.cancelled() => {},
-            _ = self.send_event_tx.send(event) => {},
+            _ = self.recorder.send_event_tx.send(event) => {},
         }
     }
 }
@@ -139,6 +214,9 @@ pub enum NewMonitorManagerError {
 
     #[error("config missing Id: {0}")]
     MissingId(String),
+
+    #[error("monitor '{0}' encrypts recordings but recording_key is not set")]
+    NoRecordingKey(MonitorId),
 }
 
 #[rustfmt::skip]
@@ -147,16 +225,27 @@ enum MonitorManagerRequest {
     MonitorRestart((oneshot::Sender<Result<(), MonitorRestartError>>, MonitorId)),
     MonitorSet((oneshot::Sender<Result<bool, MonitorSetError>>, MonitorConfig)),
     MonitorSetAndRestart((oneshot::Sender<Result<bool, MonitorSetAndRestartError>>, MonitorConfig)),
+    MonitorSetAndRestartGraceful((oneshot::Sender<Result<bool, MonitorSetAndRestartError>>, MonitorConfig)),
     MonitorDelete((oneshot::Sender<Result<(), MonitorDeleteError>>, MonitorId)),
     MonitorsInfo(oneshot::Sender<HashMap<MonitorId, MonitorInfo>>),
     MonitorConfig((oneshot::Sender<Option<MonitorConfig>>, MonitorId)),
     MonitorConfigs(oneshot::Sender<MonitorConfigs>),
     Stop(oneshot::Sender<()>),
     MonitorIsRunning((oneshot::Sender<bool>, MonitorId)),
+    MonitorsStats(oneshot::Sender<HashMap<MonitorId, ArcMonitorStats>>),
+    ApplyConfig((oneshot::Sender<Result<(), MonitorApplyConfigError>>, MonitorConfigs)),
+    Status(oneshot::Sender<Vec<MonitorStatus>>),
+    LatestFrame((oneshot::Sender<Option<(ArcLatestFrame, u8)>>, MonitorId)),
 }
 
+// Number of detections buffered for slow detection feed subscribers.
+const DETECTION_FEED_CAPACITY: usize = 64;
+
 #[derive(Clone)]
-pub struct MonitorManager(mpsc::Sender<MonitorManagerRequest>);
+pub struct MonitorManager {
+    tx: mpsc::Sender<MonitorManagerRequest>,
+    detection_tx: broadcast::Sender<DetectionEvent>,
+}
 
 impl MonitorManager {
     pub fn new(
@@ -166,35 +255,15 @@ impl MonitorManager {
         hls_server: Arc<HlsServer>,
         //hooks *Hooks,
     ) -> Result<Self, NewMonitorManagerError> {
-        use NewMonitorManagerError::*;
-        std::fs::create_dir_all(&config_path).map_err(CreateDir)?;
-
-        let mut configs = HashMap::new();
-        for entry in std::fs::read_dir(&config_path).map_err(ReadDir)? {
-            let entry = entry.map_err(StatFile)?;
-
-            if entry.metadata().map_err(GetFileMetadata)?.is_dir() {
-                continue;
-            }
-
-            let name = entry.file_name().to_string_lossy().to_string();
-            let is_json_file = Path::new(&name)
-                .extension()
-                .map_or(false, |ext| ext.eq_ignore_ascii_case("json"));
-            if !is_json_file {
-                continue;
-            }
-
-            let json = std::fs::read(entry.path()).map_err(ReadFile)?;
-            let config: MonitorConfig =
-                serde_json::from_slice(&json).map_err(|e| Deserialize(name, e))?;
-
-            configs.insert(config.id().to_owned(), config);
-        }
+        std::fs::create_dir_all(&config_path).map_err(NewMonitorManagerError::CreateDir)?;
+        let configs = read_monitor_configs(&config_path)?;
+        check_recording_key(&configs, rec_db.master_key().is_some())?;
 
         let (tx, rx) = mpsc::channel(1);
+        let (detection_tx, _) = broadcast::channel(DETECTION_FEED_CAPACITY);
 
         // This must be an actor in order to be callable from plugins.
+        let detection_tx2 = detection_tx.clone();
         tokio::spawn(async move {
             MonitorManagerState {
                 token: CancellationToken::new(),
@@ -205,12 +274,61 @@ impl MonitorManager {
                 hls_server,
                 path: config_path,
                 hooks: None,
+                detection_tx: detection_tx2,
             }
             .run(rx)
             .await;
         });
 
-        Ok(Self(tx))
+        Ok(Self { tx, detection_tx })
+    }
+}
+
+// Reads and parses the monitor configs in the directory.
+pub fn read_monitor_configs(dir: &Path) -> Result<MonitorConfigs, NewMonitorManagerError> {
+    use NewMonitorManagerError::*;
+    let mut configs = HashMap::new();
+    for entry in std::fs::read_dir(dir).map_err(ReadDir)? {
+        let entry = entry.map_err(StatFile)?;
+
+        if entry.metadata().map_err(GetFileMetadata)?.is_dir() {
+            continue;
+        }
+
+        let name = entry.file_name().to_string_lossy().to_string();
+        let is_json_file = Path::new(&name)
+            .extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("json"));
+        if !is_json_file {
+            continue;
+        }
+
+        let json = std::fs::read(entry.path()).map_err(ReadFile)?;
+        let config: MonitorConfig =
+            serde_json::from_slice(&json).map_err(|e| Deserialize(name, e))?;
+
+        configs.insert(config.id().to_owned(), config);
+    }
+    Ok(configs)
+}
+
+// Encrypted recordings can't be written without the master key.
+pub fn check_recording_key(
+    configs: &MonitorConfigs,
+    has_recording_key: bool,
+) -> Result<(), NewMonitorManagerError> {
+    if has_recording_key {
+        return Ok(());
+    }
+    let mut ids: Vec<_> = configs
+        .values()
+        .filter(|c| c.encrypt_recordings())
+        .map(|c| c.id().to_owned())
+        .collect();
+    ids.sort();
+    match ids.into_iter().next() {
+        Some(id) => Err(NewMonitorManagerError::NoRecordingKey(id)),
+        None => Ok(()),
     }
 }
 
@@ -218,7 +336,7 @@ impl MonitorManager {
 impl IMonitorManager for MonitorManager {
     async fn start_monitors(&self, hooks: ArcMonitorHooks) {
         let (tx, rx) = oneshot::channel();
-        self.0
+        self.tx
             .send(MonitorManagerRequest::StartMonitors((tx, hooks)))
             .await
             .expect("actor should still be active");
@@ -228,7 +346,7 @@ impl IMonitorManager for MonitorManager {
 
     async fn monitor_restart(&self, monitor_id: MonitorId) -> Result<(), MonitorRestartError> {
         let (tx, rx) = oneshot::channel();
-        self.0
+        self.tx
             .send(MonitorManagerRequest::MonitorRestart((tx, monitor_id)))
             .await
             .expect("actor should still be active");
@@ -238,7 +356,7 @@ impl IMonitorManager for MonitorManager {
 
     async fn monitor_set(&self, config: MonitorConfig) -> Result<bool, MonitorSetError> {
         let (tx, rx) = oneshot::channel();
-        self.0
+        self.tx
             .send(MonitorManagerRequest::MonitorSet((tx, config)))
             .await
             .expect("actor should still be active");
@@ -251,7 +369,7 @@ impl IMonitorManager for MonitorManager {
         config: MonitorConfig,
     ) -> Result<bool, MonitorSetAndRestartError> {
         let (tx, rx) = oneshot::channel();
-        self.0
+        self.tx
             .send(MonitorManagerRequest::MonitorSetAndRestart((tx, config)))
             .await
             .expect("actor should still be active");
@@ -259,9 +377,24 @@ impl IMonitorManager for MonitorManager {
         rx.await.expect("actor should respond")
     }
 
+    async fn monitor_set_and_restart_graceful(
+        &self,
+        config: MonitorConfig,
+    ) -> Result<bool, MonitorSetAndRestartError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(MonitorManagerRequest::MonitorSetAndRestartGraceful((
+                tx, config,
+            )))
+            .await
+            .expect("actor should still be active");
+
+        rx.await.expect("actor should respond")
+    }
+
     async fn monitor_delete(&self, id: MonitorId) -> Result<(), MonitorDeleteError> {
         let (tx, rx) = oneshot::channel();
-        self.0
+        self.tx
             .send(MonitorManagerRequest::MonitorDelete((tx, id)))
             .await
             .expect("actor should still be active");
@@ -271,7 +404,7 @@ impl IMonitorManager for MonitorManager {
 
     async fn monitors_info(&self) -> HashMap<MonitorId, MonitorInfo> {
         let (tx, rx) = oneshot::channel();
-        self.0
+        self.tx
             .send(MonitorManagerRequest::MonitorsInfo(tx))
             .await
             .expect("actor should still be active");
@@ -281,7 +414,7 @@ impl IMonitorManager for MonitorManager {
 
     async fn monitor_config(&self, monitor_id: MonitorId) -> Option<MonitorConfig> {
         let (tx, rx) = oneshot::channel();
-        self.0
+        self.tx
             .send(MonitorManagerRequest::MonitorConfig((tx, monitor_id)))
             .await
             .expect("actor should still be active");
@@ -291,7 +424,7 @@ impl IMonitorManager for MonitorManager {
 
     async fn monitor_configs(&self) -> MonitorConfigs {
         let (tx, rx) = oneshot::channel();
-        self.0
+        self.tx
             .send(MonitorManagerRequest::MonitorConfigs(tx))
             .await
             .expect("actor should still be active");
@@ -301,7 +434,7 @@ impl IMonitorManager for MonitorManager {
 
     async fn stop(&self) {
         let (tx, rx) = oneshot::channel();
-        self.0
+        self.tx
             .send(MonitorManagerRequest::Stop(tx))
             .await
             .expect("actor should still be active");
@@ -311,13 +444,81 @@ impl IMonitorManager for MonitorManager {
 
     async fn monitor_is_running(&self, monitor_id: MonitorId) -> bool {
         let (tx, rx) = oneshot::channel();
-        self.0
+        self.tx
             .send(MonitorManagerRequest::MonitorIsRunning((tx, monitor_id)))
             .await
             .expect("actor should still be active");
 
         rx.await.expect("actor should respond")
     }
+
+    async fn monitors_stats(&self) -> HashMap<MonitorId, ArcMonitorStats> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(MonitorManagerRequest::MonitorsStats(tx))
+            .await
+            .expect("actor should still be active");
+
+        rx.await.expect("actor should respond")
+    }
+
+    async fn apply_config(&self, configs: MonitorConfigs) -> Result<(), MonitorApplyConfigError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(MonitorManagerRequest::ApplyConfig((tx, configs)))
+            .await
+            .expect("actor should still be active");
+
+        rx.await.expect("actor should respond")
+    }
+
+    async fn status(&self) -> Vec<MonitorStatus> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(MonitorManagerRequest::Status(tx))
+            .await
+            .expect("actor should still be active");
+
+        rx.await.expect("actor should respond")
+    }
+
+    async fn latest_frame_jpeg(
+        &self,
+        monitor_id: MonitorId,
+    ) -> Result<Option<Snapshot>, LatestFrameError> {
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(MonitorManagerRequest::LatestFrame((tx, monitor_id.clone())))
+            .await
+            .expect("actor should still be active");
+
+        let Some((latest_frame, quality)) = rx.await.expect("actor should respond") else {
+            return Err(LatestFrameError::NotRunning(monitor_id.to_string()));
+        };
+        if let Some(snapshot) = latest_frame.snapshot() {
+            return Ok(Some(snapshot));
+        }
+        let Some(keyframe) = latest_frame.get() else {
+            return Ok(None);
+        };
+        // Decoded outside the actor to not block the other requests.
+        let (keyframe, jpeg) = tokio::task::spawn_blocking(move || {
+            let jpeg = keyframe_to_jpeg(&keyframe, quality);
+            (keyframe, jpeg)
+        })
+        .await
+        .expect("join");
+        let jpeg = Arc::new(jpeg.map_err(|e| LatestFrameError::Encode(e.to_string()))?);
+        latest_frame.set_jpeg(&keyframe, jpeg.clone());
+        Ok(Some(Snapshot {
+            jpeg,
+            time: keyframe.time,
+        }))
+    }
+
+    fn subscribe_detections(&self) -> broadcast::Receiver<DetectionEvent> {
+        self.detection_tx.subscribe()
+    }
 }
 
 struct MonitorManagerState {
@@ -332,6 +533,7 @@ struct MonitorManagerState {
     path: PathBuf,
 
     hooks: Option<ArcMonitorHooks>,
+    detection_tx: broadcast::Sender<DetectionEvent>,
 }
 
 impl MonitorManagerState {
@@ -359,6 +561,10 @@ impl MonitorManagerState {
                     res.send(self.monitor_set_and_restart(config).await)
                         .expect("caller should receive response");
                 }
+                MonitorManagerRequest::MonitorSetAndRestartGraceful((res, config)) => {
+                    res.send(self.monitor_set_and_restart_graceful(config).await)
+                        .expect("caller should receive response");
+                }
                 MonitorManagerRequest::MonitorDelete((res, monitor_id)) => {
                     res.send(self.monitor_delete(&monitor_id).await)
                         .expect("caller should receive response");
@@ -383,6 +589,26 @@ impl MonitorManagerState {
                     res.send(self.started_monitors.get(&monitor_id).is_some())
                         .expect("caller should receive response");
                 }
+                MonitorManagerRequest::MonitorsStats(res) => {
+                    res.send(self.monitors_stats())
+                        .expect("caller should receive response");
+                }
+                MonitorManagerRequest::ApplyConfig((res, configs)) => {
+                    res.send(self.apply_config(configs).await)
+                        .expect("caller should receive response");
+                }
+                MonitorManagerRequest::Status(res) => {
+                    res.send(self.status())
+                        .expect("caller should receive response");
+                }
+                MonitorManagerRequest::LatestFrame((res, monitor_id)) => {
+                    let latest_frame = self
+                        .started_monitors
+                        .get(&monitor_id)
+                        .map(|v| (v.latest_frame.clone(), v.config.thumb_quality()));
+                    res.send(latest_frame)
+                        .expect("caller should receive response");
+                }
             }
         }
     }
@@ -390,7 +616,7 @@ impl MonitorManagerState {
     pub async fn start_monitors(&mut self, hooks: ArcMonitorHooks) {
         self.hooks = Some(hooks);
         for (id, config) in &self.configs {
-            if let Some(monitor) = self.start_monitor(config.to_owned()).await {
+            if let Some(monitor) = self.start_monitor(config.to_owned(), None).await {
                 self.started_monitors.insert(id.to_owned(), monitor);
             }
         }
@@ -411,7 +637,7 @@ impl MonitorManagerState {
         }
 
         // Restart monitor.
-        if let Some(monitor) = self.start_monitor(raw_config.to_owned()).await {
+        if let Some(monitor) = self.start_monitor(raw_config.to_owned(), None).await {
             self.started_monitors.insert(id.to_owned(), monitor);
         }
 
@@ -422,17 +648,34 @@ impl MonitorManagerState {
     // Changes are not applied until the montior restarts.
     // Returns `true` if monitor was created.
     pub async fn monitor_set(&mut self, config: MonitorConfig) -> Result<bool, MonitorSetError> {
+        if config.encrypt_recordings() && self.rec_db.master_key().is_none() {
+            return Err(MonitorSetError::NoRecordingKey);
+        }
+        let id = config.id();
+        self.write_config(&config).await?;
+
+        let created = !self.configs.contains_key(id);
+        if created {
+            log_monitor(&self.logger, LogLevel::Info, id, "created");
+        } else {
+            log_monitor(&self.logger, LogLevel::Info, id, "saved");
+        }
+
+        self.configs.insert(id.to_owned(), config);
+        Ok(created)
+    }
+
+    // Writes the config to a temporary file and renames it over the old file.
+    async fn write_config(&self, config: &MonitorConfig) -> Result<(), MonitorSetError> {
         use MonitorSetError::*;
 
         let id = config.id();
-
-        // Write config to file.
         let path = self.config_path(id);
 
         let mut temp_path = path.clone();
         temp_path.set_file_name(id.to_string() + ".json.tmp");
 
-        let json = serde_json::to_vec_pretty(&config)?;
+        let json = serde_json::to_vec_pretty(config)?;
 
         let mut file = tokio::fs::OpenOptions::new()
             .create(true)
@@ -446,17 +689,7 @@ impl MonitorManagerState {
 
         tokio::fs::rename(temp_path, path)
             .await
-            .map_err(RenameTempFile)?;
-
-        let created = !self.configs.contains_key(id);
-        if created {
-            log_monitor(&self.logger, LogLevel::Info, id, "created");
-        } else {
-            log_monitor(&self.logger, LogLevel::Info, id, "saved");
-        }
-
-        self.configs.insert(id.to_owned(), config);
-        Ok(created)
+            .map_err(RenameTempFile)
     }
 
     pub async fn monitor_set_and_restart(
@@ -470,16 +703,53 @@ impl MonitorManagerState {
         Ok(created)
     }
 
+    // Same as `monitor_set_and_restart` but keeps the recorder running
+    // so the current recording isn't finalized. The recording continues
+    // in the same file if the codec and resolution are unchanged.
+    pub async fn monitor_set_and_restart_graceful(
+        &mut self,
+        config: MonitorConfig,
+    ) -> Result<bool, MonitorSetAndRestartError> {
+        use MonitorSetAndRestartError::*;
+        let id = config.id().clone();
+        let created = self.monitor_set(config).await.map_err(Set)?;
+        self.monitor_restart_graceful(&id).await.map_err(Restart)?;
+        Ok(created)
+    }
+
+    // Restarts the monitor without stopping the recorder. Falls back
+    // to a normal restart if the recorder settings have changed.
+    async fn monitor_restart_graceful(
+        &mut self,
+        id: &MonitorId,
+    ) -> Result<(), MonitorRestartError> {
+        use MonitorRestartError::*;
+        let Some(config) = self.configs.get(id).cloned() else {
+            return Err(NotExist(id.to_string()));
+        };
+        let can_keep_recorder = self
+            .started_monitors
+            .get(id)
+            .is_some_and(|v| config.enabled() && v.recorder.can_keep(&config));
+        if !can_keep_recorder {
+            return self.monitor_restart(id).await;
+        }
+        let monitor = self.started_monitors.remove(id).expect("monitor to exist");
+
+        log_monitor(&self.logger, LogLevel::Info, id, "restarting gracefully");
+        let recorder = monitor.stop_keep_recorder().await;
+
+        if let Some(monitor) = self.start_monitor(config, Some(recorder)).await {
+            self.started_monitors.insert(id.to_owned(), monitor);
+        }
+        Ok(())
+    }
+
     // MonitorDelete deletes monitor by id.
     pub async fn monitor_delete(&mut self, id: &MonitorId) -> Result<(), MonitorDeleteError> {
         use MonitorDeleteError::*;
 
-        if let Some(monitor) = self.started_monitors.remove(id) {
-            log_monitor(&self.logger, LogLevel::Info, id, "stopping");
-            monitor.stop().await;
-            log_monitor(&self.logger, LogLevel::Debug, id, "stopped");
-            self.started_monitors.remove(id);
-        };
+        self.stop_monitor(id).await;
 
         if self.configs.remove(id).is_none() {
             return Err(NotExist(id.to_string()));
@@ -490,6 +760,118 @@ impl MonitorManagerState {
         Ok(())
     }
 
+    // Replaces all monitor configs. New monitors are started, deleted
+    // monitors are stopped and changed monitors are restarted, unchanged
+    // monitors keep running. The config files are written first, if any
+    // of them fail the old files are restored and no monitor is touched.
+    pub async fn apply_config(
+        &mut self,
+        configs: MonitorConfigs,
+    ) -> Result<(), MonitorApplyConfigError> {
+        use MonitorApplyConfigError::*;
+        if let Some((id, config)) = configs.iter().find(|(id, config)| *id != config.id()) {
+            return Err(IdMismatch(id.to_string(), config.id().to_string()));
+        }
+        let has_recording_key = self.rec_db.master_key().is_some();
+        if let Err(NewMonitorManagerError::NoRecordingKey(id)) =
+            check_recording_key(&configs, has_recording_key)
+        {
+            return Err(Set(id.to_string(), MonitorSetError::NoRecordingKey));
+        }
+
+        let changed: Vec<MonitorId> = configs
+            .iter()
+            .filter(|(id, config)| self.configs.get(id) != Some(config))
+            .map(|(id, _)| id.to_owned())
+            .collect();
+        let removed: Vec<MonitorId> = self
+            .configs
+            .keys()
+            .filter(|id| !configs.contains_key(id))
+            .cloned()
+            .collect();
+
+        if let Err(e) = self.write_configs(&configs, &changed, &removed).await {
+            self.restore_configs(&changed, &removed).await;
+            return Err(e);
+        }
+
+        for id in &removed {
+            self.stop_monitor(id).await;
+            log_monitor(&self.logger, LogLevel::Info, id, "deleted");
+        }
+        for id in &changed {
+            if self.configs.contains_key(id) {
+                log_monitor(&self.logger, LogLevel::Info, id, "saved");
+            } else {
+                log_monitor(&self.logger, LogLevel::Info, id, "created");
+            }
+        }
+        self.configs = configs;
+
+        if self.hooks.is_none() {
+            // Monitors haven't been started yet.
+            return Ok(());
+        }
+        for id in changed {
+            self.stop_monitor(&id).await;
+            let config = self.configs[&id].clone();
+            if let Some(monitor) = self.start_monitor(config, None).await {
+                self.started_monitors.insert(id, monitor);
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_configs(
+        &self,
+        configs: &MonitorConfigs,
+        changed: &[MonitorId],
+        removed: &[MonitorId],
+    ) -> Result<(), MonitorApplyConfigError> {
+        use MonitorApplyConfigError::*;
+        for id in changed {
+            self.write_config(&configs[id])
+                .await
+                .map_err(|e| Set(id.to_string(), e))?;
+        }
+        for id in removed {
+            tokio::fs::remove_file(self.config_path(id))
+                .await
+                .map_err(|e| RemoveFile(id.to_string(), e))?;
+        }
+        Ok(())
+    }
+
+    // Restores the config files from the current configs.
+    async fn restore_configs(&self, changed: &[MonitorId], removed: &[MonitorId]) {
+        for id in changed.iter().chain(removed) {
+            let res = match self.configs.get(id) {
+                Some(config) => self.write_config(config).await.map_err(|e| e.to_string()),
+                None => match tokio::fs::remove_file(self.config_path(id)).await {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+                    _ => Ok(()),
+                },
+            };
+            if let Err(e) = res {
+                log_monitor(
+                    &self.logger,
+                    LogLevel::Error,
+                    id,
+                    &format!("restore config: {e}"),
+                );
+            }
+        }
+    }
+
+    async fn stop_monitor(&mut self, id: &MonitorId) {
+        if let Some(monitor) = self.started_monitors.remove(id) {
+            log_monitor(&self.logger, LogLevel::Info, id, "stopping");
+            monitor.stop().await;
+            log_monitor(&self.logger, LogLevel::Debug, id, "stopped");
+        }
+    }
+
     // Returns common information about the monitors.
     // This will be accessesable by normal users.
     #[must_use]
@@ -511,6 +893,39 @@ impl MonitorManagerState {
         configs
     }
 
+    // Returns the stats of the running monitors.
+    #[must_use]
+    pub fn monitors_stats(&self) -> HashMap<MonitorId, ArcMonitorStats> {
+        self.started_monitors
+            .iter()
+            .map(|(id, monitor)| (id.to_owned(), monitor.stats.clone()))
+            .collect()
+    }
+
+    // Returns the status of all monitors sorted by ID. The monitor
+    // stats are atomics and are read without blocking the monitors.
+    #[must_use]
+    pub fn status(&self) -> Vec<MonitorStatus> {
+        let mut status: Vec<_> = self
+            .configs
+            .keys()
+            .map(|id| {
+                let monitor = self.started_monitors.get(id);
+                let stats = monitor.map(|v| &v.stats);
+                MonitorStatus {
+                    id: id.to_owned(),
+                    running: stats.is_some(),
+                    last_frame: stats.and_then(|v| v.last_frame()),
+                    started: monitor.map(|v| v.started),
+                    recording: stats.is_some_and(|v| v.recording()),
+                    gop_length: stats.and_then(|v| v.gop_length()),
+                }
+            })
+            .collect();
+        status.sort_by_key(|v| v.id.to_string());
+        status
+    }
+
     fn config_path(&self, id: &MonitorId) -> PathBuf {
         fn monitor_config_path(path: &Path, id: String) -> PathBuf {
             path.join(id + ".json")
@@ -518,7 +933,12 @@ impl MonitorManagerState {
         monitor_config_path(&self.path, id.to_string())
     }
 
-    async fn start_monitor(&self, config: MonitorConfig) -> Option<Arc<Monitor>> {
+    // Starts the monitor. The recorder is reused if provided.
+    async fn start_monitor(
+        &self,
+        config: MonitorConfig,
+        recorder: Option<Arc<Recorder>>,
+    ) -> Option<Arc<Monitor>> {
         let hooks = self.hooks.clone().expect("hooks to be set");
 
         if !config.enabled() {
@@ -529,6 +949,10 @@ impl MonitorManagerState {
 
         let monitor_token = self.token.child_token();
         let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel(1);
+        let stats = recorder
+            .as_ref()
+            .map_or_else(ArcMonitorStats::default, |v| v.stats.clone());
+        let latest_frame = ArcLatestFrame::default();
 
         let (source_main, source_sub): (ArcSource, Option<ArcSource>) = match config.source() {
             SourceConfig::Rtsp(conf) => {
@@ -540,6 +964,8 @@ impl MonitorManagerState {
                     config.id().to_owned(),
                     conf.to_owned(),
                     StreamType::Main,
+                    stats.clone(),
+                    latest_frame.clone(),
                 )
                 .expect("source main should never be None");
 
@@ -551,6 +977,8 @@ impl MonitorManagerState {
                     config.id().to_owned(),
                     conf.to_owned(),
                     StreamType::Sub,
+                    stats.clone(),
+                    latest_frame.clone(),
                 );
 
                 (
@@ -563,16 +991,20 @@ impl MonitorManagerState {
             }
         };
 
-        let send_event_tx = new_recorder(
-            monitor_token.clone(),
-            shutdown_complete_tx.clone(),
-            hooks.clone(),
-            self.logger.clone(),
-            config.id().to_owned(),
-            source_main.clone(),
-            config.clone(),
-            self.rec_db.clone(),
-        );
+        let recorder = match recorder {
+            Some(recorder) => {
+                recorder
+                    .source_main_tx
+                    .send_replace(Some(source_main.clone()));
+                recorder
+            }
+            None => Arc::new(self.start_recorder(
+                hooks.clone(),
+                config.clone(),
+                source_main.clone(),
+                stats.clone(),
+            )),
+        };
 
         let (source_main_tx, mut source_main_rx) = mpsc::channel(1);
         let (source_sub_tx, mut source_sub_rx) = mpsc::channel(1);
@@ -583,7 +1015,12 @@ impl MonitorManagerState {
             shutdown_complete: Mutex::new(shutdown_complete_rx),
             source_main_tx,
             source_sub_tx,
-            send_event_tx,
+            recorder,
+            stats,
+            latest_frame,
+            rate_limiter: Mutex::new(EventRateLimiter::new(config.event_rate_limit().clone())),
+            detection_tx: self.detection_tx.clone(),
+            started: UnixNano::now(),
         });
 
         // Monitor actor.
@@ -614,6 +1051,39 @@ impl MonitorManagerState {
         Some(monitor)
     }
 
+    fn start_recorder(
+        &self,
+        hooks: ArcMonitorHooks,
+        config: MonitorConfig,
+        source_main: ArcSource,
+        stats: ArcMonitorStats,
+    ) -> Recorder {
+        let token = self.token.child_token();
+        let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel(1);
+        let (source_main_tx, source_main_rx) = watch::channel(Some(source_main));
+
+        let send_event_tx = new_recorder(
+            token.clone(),
+            shutdown_complete_tx,
+            hooks,
+            self.logger.clone(),
+            config.id().to_owned(),
+            source_main_rx,
+            config.clone(),
+            self.rec_db.clone(),
+            stats.clone(),
+        );
+
+        Recorder {
+            token,
+            shutdown_complete: Mutex::new(shutdown_complete_rx),
+            source_main_tx,
+            send_event_tx,
+            stats,
+            config,
+        }
+    }
+
     pub async fn stop(&mut self) {
         // Cancel token.
         self.token.cancel();
@@ -634,11 +1104,16 @@ mod tests {
     use super::*;
     use bytesize::ByteSize;
     use common::{
-        monitor::{Config, Protocol, SelectedSource, SourceConfig, SourceRtspConfig},
+        monitor::{
+            ArcMonitor, Config, MonitorHooks, Protocol, SelectedSource, SourceConfig,
+            SourceRtspConfig,
+        },
+        time::Duration,
         DummyLogger, MonitorName, ParseMonitorIdError,
     };
     use pretty_assertions::assert_eq;
     use recdb::Disk;
+    use sentryshot_util::Frame;
     use serde_json::json;
     use std::{fs, path::PathBuf};
     use tempfile::TempDir;
@@ -767,6 +1242,27 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_new_manager_no_recording_key() {
+        let (temp_dir, config_dir) = prepare_dir();
+
+        let mut config: serde_json::Value =
+            serde_json::from_slice(&fs::read(config_dir.join("2.json")).unwrap()).unwrap();
+        config["encryptRecordings"] = json!(true);
+        fs::write(config_dir.join("2.json"), config.to_string()).unwrap();
+
+        let token = CancellationToken::new();
+        assert!(matches!(
+            MonitorManager::new(
+                config_dir,
+                Arc::new(new_test_recdb(temp_dir.path())),
+                DummyLogger::new(),
+                Arc::new(HlsServer::new(token, DummyLogger::new())),
+            ),
+            Err(NewMonitorManagerError::NoRecordingKey(id)) if id == m_id("2")
+        ));
+    }
+
     #[tokio::test]
     async fn test_monitor_set_create_new() {
         let (_temp_dir, config_dir, manager) = new_test_manager();
@@ -779,11 +1275,23 @@ mod tests {
                 source: SelectedSource::Rtsp,
                 always_record: false,
                 video_length: 0.0,
+                video_max_size: 0,
+                record_index: false,
+                encrypt_recordings: false,
+                thumb_max_size: 0,
+                thumb_quality: 75,
+                keyframe_warn_interval: 10,
+                event_rate_limit: HashMap::new(),
+                record_trigger: None,
             },
             SourceConfig::Rtsp(SourceRtspConfig {
                 protocol: Protocol::Tcp,
                 main_stream: "rtsp://x1".parse().unwrap(),
                 sub_stream: None,
+                sei_recovery_point: false,
+                audio: false,
+                connect_timeout: None,
+                read_timeout: None,
             }),
             json!({
                 "id": "new",
@@ -831,11 +1339,23 @@ mod tests {
                 source: SelectedSource::Rtsp,
                 always_record: false,
                 video_length: 0.0,
+                video_max_size: 0,
+                record_index: false,
+                encrypt_recordings: false,
+                thumb_max_size: 0,
+                thumb_quality: 75,
+                keyframe_warn_interval: 10,
+                event_rate_limit: HashMap::new(),
+                record_trigger: None,
             },
             SourceConfig::Rtsp(SourceRtspConfig {
                 protocol: Protocol::Tcp,
                 main_stream: "rtsp://x1".parse().unwrap(),
                 sub_stream: None,
+                sei_recovery_point: false,
+                audio: false,
+                connect_timeout: None,
+                read_timeout: None,
             }),
             json!({
                 "id": "1",
@@ -893,6 +1413,8 @@ mod tests {
                             protocol: Protocol::Tcp,
                             main_stream: "rtsp://x".parse().unwrap(),
                             sub_stream: None,
+                            sei_recovery_point: false,
+                            audio: false,
                         }),
                         raw: serde_json::Value::Null,
                     },
@@ -910,6 +1432,8 @@ mod tests {
                             protocol: Protocol::Udp,
                             main_stream: "rtsp://x".parse().unwrap(),
                             sub_stream: None,
+                            sei_recovery_point: false,
+                            audio: false,
                         }),
                         raw: serde_json::Value::Null,
                     },
@@ -962,11 +1486,23 @@ mod tests {
                         source: SelectedSource::Rtsp,
                         always_record: false,
                         video_length: 0.0,
+                        video_max_size: 0,
+                        record_index: false,
+                        encrypt_recordings: false,
+                        thumb_max_size: 0,
+                        thumb_quality: 75,
+                        keyframe_warn_interval: 10,
+                        event_rate_limit: HashMap::new(),
+                        record_trigger: None,
                     },
                     SourceConfig::Rtsp(SourceRtspConfig {
                         protocol: Protocol::Tcp,
                         main_stream: "rtsp://x1".parse().unwrap(),
                         sub_stream: None,
+                        sei_recovery_point: false,
+                        audio: false,
+                        connect_timeout: None,
+                        read_timeout: None,
                     }),
                     json!({
                         "id": "1",
@@ -992,11 +1528,23 @@ mod tests {
                         source: SelectedSource::Rtsp,
                         always_record: false,
                         video_length: 0.0,
+                        video_max_size: 0,
+                        record_index: false,
+                        encrypt_recordings: false,
+                        thumb_max_size: 0,
+                        thumb_quality: 75,
+                        keyframe_warn_interval: 10,
+                        event_rate_limit: HashMap::new(),
+                        record_trigger: None,
                     },
                     SourceConfig::Rtsp(SourceRtspConfig {
                         protocol: Protocol::Udp,
                         main_stream: "rtsp://x1".parse().unwrap(),
                         sub_stream: Some("rtsp://x2".parse().unwrap()),
+                        sei_recovery_point: false,
+                        audio: false,
+                        connect_timeout: None,
+                        read_timeout: None,
                     }),
                     json!({
                         "id": "2",
@@ -1018,6 +1566,164 @@ mod tests {
         assert_eq!(want, got);
     }
 
+    struct StubHooks;
+
+    #[async_trait]
+    impl MonitorHooks for StubHooks {
+        async fn on_monitor_start(&self, _: CancellationToken, _: ArcMonitor) {}
+        fn on_thumb_save(&self, _: &MonitorConfig, frame: Frame) -> Frame {
+            frame
+        }
+        async fn on_event(&self, _: Event, _: MonitorConfig) {}
+    }
+
+    fn enabled_config(id: &str) -> MonitorConfig {
+        serde_json::from_value(json!({
+            "id": id,
+            "name": id,
+            "enable": true,
+            "source": "rtsp",
+            "sourcertsp": {
+                "protocol": "tcp",
+                "mainStream": "rtsp://x1",
+            },
+            "alwaysRecord": false,
+            "videoLength": 0.0,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_recorder_can_keep() {
+        let config = |thumb: serde_json::Value| -> MonitorConfig {
+            let mut config = serde_json::to_value(enabled_config("1")).unwrap();
+            config
+                .as_object_mut()
+                .unwrap()
+                .extend(thumb.as_object().unwrap().clone());
+            serde_json::from_value(config).unwrap()
+        };
+        let (_, shutdown_complete_rx) = mpsc::channel(1);
+        let recorder = Recorder {
+            token: CancellationToken::new(),
+            shutdown_complete: Mutex::new(shutdown_complete_rx),
+            source_main_tx: watch::channel(None).0,
+            send_event_tx: mpsc::channel(1).0,
+            stats: ArcMonitorStats::default(),
+            config: config(json!({"thumbMaxSize": 320, "thumbQuality": 80})),
+        };
+
+        assert!(recorder.can_keep(&config(json!({"thumbMaxSize": 320, "thumbQuality": 80}))));
+        assert!(!recorder.can_keep(&config(json!({"thumbMaxSize": 640, "thumbQuality": 80}))));
+        assert!(!recorder.can_keep(&config(json!({"thumbMaxSize": 320, "thumbQuality": 50}))));
+    }
+
+    #[tokio::test]
+    async fn test_apply_config() {
+        let (_temp_dir, config_dir, manager) = new_test_manager();
+        let configs = |ids: &[&str]| -> MonitorConfigs {
+            ids.iter()
+                .map(|id| (m_id(id), enabled_config(id)))
+                .collect()
+        };
+
+        manager.start_monitors(Arc::new(StubHooks)).await;
+        manager.apply_config(configs(&["1", "2"])).await.unwrap();
+        let stats = manager.monitors_stats().await;
+        assert!(manager.monitor_is_running(m_id("1")).await);
+        assert!(manager.monitor_is_running(m_id("2")).await);
+
+        // Add "3" and remove "2".
+        manager.apply_config(configs(&["1", "3"])).await.unwrap();
+        assert_eq!(configs(&["1", "3"]), manager.monitor_configs().await);
+        assert!(manager.monitor_is_running(m_id("1")).await);
+        assert!(!manager.monitor_is_running(m_id("2")).await);
+        assert!(manager.monitor_is_running(m_id("3")).await);
+
+        // "1" wasn't restarted.
+        let stats2 = manager.monitors_stats().await;
+        assert!(Arc::ptr_eq(&stats[&m_id("1")], &stats2[&m_id("1")]));
+
+        assert_eq!(enabled_config("3"), read_config(config_dir.join("3.json")));
+        assert!(!config_dir.join("2.json").exists());
+
+        manager.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let (_temp_dir, _, manager) = new_test_manager();
+        manager.start_monitors(Arc::new(StubHooks)).await;
+
+        // "1" is enabled and "2" is disabled.
+        let mut configs = manager.monitor_configs().await;
+        configs.insert(m_id("1"), enabled_config("1"));
+        manager.apply_config(configs).await.unwrap();
+
+        let want = vec![
+            MonitorStatus {
+                id: m_id("1"),
+                running: true,
+                last_frame: None,
+                started: None,
+                recording: false,
+                gop_length: None,
+            },
+            MonitorStatus {
+                id: m_id("2"),
+                running: false,
+                last_frame: None,
+                started: None,
+                recording: false,
+                gop_length: None,
+            },
+        ];
+        let mut got = manager.status().await;
+        assert!(got[0].started.is_some());
+        got[0].started = None;
+        assert_eq!(want, got);
+
+        let stats = &manager.monitors_stats().await[&m_id("1")];
+        stats.add_frame(StreamType::Main);
+        stats.set_recording(true);
+        stats.set_gop_length(Some(Duration::from_secs(2)));
+
+        let status = manager.status().await;
+        assert!(status[0].last_frame.is_some());
+        assert!(status[0].recording);
+        assert!(!status[1].recording);
+        assert_eq!(Some(Duration::from_secs(2)), status[0].gop_length);
+
+        manager.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_apply_config_id_mismatch() {
+        let (_temp_dir, config_dir, manager) = new_test_manager();
+        let configs = HashMap::from([(m_id("x"), enabled_config("3"))]);
+        assert!(matches!(
+            manager.apply_config(configs).await,
+            Err(MonitorApplyConfigError::IdMismatch(..))
+        ));
+        assert_eq!(2, manager.monitor_configs().await.len());
+        assert!(config_dir.join("1.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_apply_config_no_recording_key() {
+        let (_temp_dir, config_dir, manager) = new_test_manager();
+        let mut config = serde_json::to_value(enabled_config("3")).unwrap();
+        config["encryptRecordings"] = json!(true);
+        let mut configs = manager.monitor_configs().await;
+        configs.insert(m_id("3"), serde_json::from_value(config).unwrap());
+        assert!(matches!(
+            manager.apply_config(configs).await,
+            Err(MonitorApplyConfigError::Set(id, MonitorSetError::NoRecordingKey)) if id == "3"
+        ));
+        assert_eq!(2, manager.monitor_configs().await.len());
+        assert!(!config_dir.join("3.json").exists());
+    }
+
     #[tokio::test]
     async fn test_restart_monitor_not_exist_error() {
         let (_, _, manager) = new_test_manager();
@@ -2,15 +2,18 @@
 
 use crate::ArcMonitorHooks;
 use common::{
-    monitor::{ArcSource, MonitorConfig},
+    monitor::{ArcMonitorStats, ArcSource, Keyframe, MonitorConfig, MonitorStats},
     recording::{RecordingData, RecordingId},
-    time::{DurationH264, UnixH264, UnixNano},
+    time::{Duration, DurationH264, UnixH264, UnixNano},
     ArcHlsMuxer, ArcLogger, ArcMsgLogger, Event, LogEntry, LogLevel, MonitorId, MsgLogger,
-    SegmentFinalized, TrackParameters,
+    SegmentFinalized, TrackParameters, VideoSample,
 };
 use futures_lite::Future;
-use recdb::{NewRecordingError, OpenFileError, RecDb, RecordingHandle};
-use recording::{CreateVideoWriterError, MetaHeader, VideoWriter, WriteSampleError};
+use recdb::{NewKeyError, NewRecordingError, OpenFileError, RecDb, RecordingHandle};
+use recording::{
+    AudioWriter, CreateVideoWriterError, DetectionWriter, EncryptWriter, FrameDetections,
+    MetaHeader, VideoWriter, WriteDetectionsError, WriteSampleError,
+};
 use sentryshot_convert::{
     ConvertError, Frame, NewConverterError, PixelFormat, PixelFormatConverter,
 };
@@ -18,36 +21,71 @@ use sentryshot_ffmpeg_h264::{
     DrainError, H264BuilderError, H264DecoderBuilder, Packet, PaddedBytes, ReceiveFrameError,
     SendPacketError,
 };
+use sentryshot_scale::{CreateScalerError, Scaler, ScalerError};
 use sentryshot_util::ImageCopyToBufferError;
-use std::{pin::Pin, sync::Arc, task::Poll};
+use std::{collections::VecDeque, num::NonZeroU16, pin::Pin, sync::Arc, task::Poll};
 use thiserror::Error;
 use tokio::{
-    io::{AsyncWriteExt, BufWriter},
-    sync::{mpsc, Mutex},
+    io::{AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::{broadcast, mpsc, watch, Mutex},
     time::sleep,
 };
 use tokio_util::sync::CancellationToken;
 
-#[allow(clippy::too_many_arguments, clippy::module_name_repetitions)]
+// Main source of the recorder. The value is `None` while
+// the monitor is restarting and the source is being replaced.
+pub type RecorderSource = watch::Receiver<Option<ArcSource>>;
+
+#[allow(
+    clippy::too_many_arguments,
+    clippy::too_many_lines,
+    clippy::module_name_repetitions
+)]
 pub fn new_recorder(
     token: CancellationToken,
     shutdown_complete: mpsc::Sender<()>,
     hooks: ArcMonitorHooks,
     logger: ArcLogger,
     monitor_id: MonitorId,
-    source_main: ArcSource,
+    source_main: RecorderSource,
     config: MonitorConfig,
     rec_db: Arc<RecDb>,
+    stats: ArcMonitorStats,
 ) -> mpsc::Sender<Event> {
     let (send_event_tx, mut send_event_rx) = mpsc::channel::<Event>(1);
+
+    let logger: ArcMsgLogger = Arc::new(RecorderMsgLogger::new(logger, monitor_id));
+
+    let pre_roll = config
+        .record_trigger()
+        .filter(|_| !config.always_record())
+        .map(|trigger| {
+            let duration = DurationH264::from(*trigger.pre_roll);
+            Arc::new(Mutex::new(PreRollBuffer::new(duration)))
+        });
+    let keyframe_interval = KeyframeInterval::new(
+        logger.clone(),
+        stats.clone(),
+        config.keyframe_warn_interval().map(DurationH264::from),
+    );
+    tokio::spawn(follow_main_source(
+        token.clone(),
+        shutdown_complete.clone(),
+        source_main.clone(),
+        pre_roll.clone(),
+        keyframe_interval,
+    ));
+
     let c = RecordingContext {
         hooks: hooks.clone(),
-        logger: Arc::new(RecorderMsgLogger::new(logger, monitor_id)),
+        logger,
         source_main,
         prev_seg: Arc::new(Mutex::new(None)),
         config: config.clone(),
         rec_db,
         event_cache: Arc::new(EventCache::new()),
+        stats,
+        pre_roll,
     };
 
     // Recorder actor.
@@ -60,6 +98,7 @@ pub fn new_recorder(
             recording_session = Some(RecordingSession::new(
                 &token,
                 None,
+                None,
                 c.clone(),
                 shutdown_complete.clone(),
             ));
@@ -81,13 +120,8 @@ pub fn new_recorder(
                         };
                         hooks.on_event(event.clone(), config.clone()).await;
 
-
-                        let Some(end) = event.time.checked_add(event.rec_duration.into()) else {
-                            continue
-                        };
-
                         // Update timer if the monitor isn't set to always record.
-                        if let Some(timer_end) = session.timer_end {
+                        if let (Some(end), Some(timer_end)) = (event_end(&config, &event), session.timer_end) {
                             if end.after(timer_end) {
                                 c.log(LogLevel::Debug, "new event, already recording, updating timer");
                                 session.timer_end = Some(end);
@@ -123,17 +157,21 @@ pub fn new_recorder(
                         };
                         //r.hooks.Event(r, &event)
 
-                        let Some(end) = event.time.checked_add(event.rec_duration.into()) else {
+                        let Some(end) = event_end(&config, &event) else {
                             continue
                         };
                         if !end.after(UnixNano::now()) {
                             continue
                         }
+                        let clip_start = config
+                            .record_trigger()
+                            .and_then(|v| event.time.checked_sub((*v.pre_roll).into()));
 
                         c.event_cache.push(event).await;
                         recording_session = Some(RecordingSession::new(
                             &token,
                             Some(end),
+                            clip_start,
                             c.clone(),
                             shutdown_complete.clone()),
                         );
@@ -146,6 +184,221 @@ pub fn new_recorder(
     send_event_tx
 }
 
+// Returns the time the recording should end for the event,
+// None if the event shouldn't start or extend a recording.
+fn event_end(config: &MonitorConfig, event: &Event) -> Option<UnixNano> {
+    match config.record_trigger() {
+        Some(trigger) => trigger.clip_end(event),
+        None => event.time.checked_add(event.rec_duration.into()),
+    }
+}
+
+// Ring buffer of the latest segments, used to start
+// triggered recordings before the detection.
+struct PreRollBuffer {
+    duration: DurationH264,
+    segments: VecDeque<Arc<SegmentFinalized>>,
+}
+
+impl PreRollBuffer {
+    fn new(duration: DurationH264) -> Self {
+        Self {
+            duration,
+            segments: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, seg: Arc<SegmentFinalized>) {
+        // The buffered segments must be consecutive.
+        if let Some(last) = self.segments.back() {
+            if last.muxer_id() != seg.muxer_id() || last.id() + 1 != seg.id() {
+                self.segments.clear();
+            }
+        }
+
+        let end = *seg.start_time() + *seg.duration();
+        self.segments.push_back(seg);
+
+        // Drop the oldest segment if the rest cover the duration.
+        while self.segments.len() > 1 {
+            if end - *self.segments[1].start_time() < *self.duration {
+                break;
+            }
+            self.segments.pop_front();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.segments.clear();
+    }
+
+    // Returns the buffered segments of the muxer starting with the segment
+    // that contains `start`. Segments up to `prev_seg` are already recorded.
+    fn since(
+        &self,
+        start: UnixH264,
+        muxer_id: u16,
+        prev_seg: Option<&SegmentFinalized>,
+    ) -> VecDeque<Arc<SegmentFinalized>> {
+        let prev_id = prev_seg
+            .filter(|v| v.muxer_id() == muxer_id)
+            .map(SegmentFinalized::id);
+        let segments: Vec<_> = self
+            .segments
+            .iter()
+            .filter(|v| v.muxer_id() == muxer_id && prev_id.map_or(true, |prev| prev < v.id()))
+            .collect();
+        let i = segments
+            .iter()
+            .rposition(|v| !v.start_time().after(start))
+            .unwrap_or(0);
+        segments[i..].iter().map(|v| (*v).clone()).collect()
+    }
+}
+
+// Measures the interval between keyframes in the main stream. Playback
+// starts at a keyframe, long intervals can skip large parts of recordings.
+struct KeyframeInterval {
+    logger: ArcMsgLogger,
+    stats: ArcMonitorStats,
+    warn_interval: Option<DurationH264>,
+    prev: Option<UnixH264>,
+    warned: bool,
+}
+
+impl KeyframeInterval {
+    fn new(
+        logger: ArcMsgLogger,
+        stats: ArcMonitorStats,
+        warn_interval: Option<DurationH264>,
+    ) -> Self {
+        Self {
+            logger,
+            stats,
+            warn_interval,
+            prev: None,
+            warned: false,
+        }
+    }
+
+    fn push(&mut self, sample: &VideoSample) {
+        if !sample.random_access_present {
+            return;
+        }
+        let Some(prev) = self.prev.replace(sample.pts) else {
+            return;
+        };
+        let Some(interval) = sample.pts.checked_sub(prev).map(DurationH264::from) else {
+            return;
+        };
+        if *interval <= 0 {
+            return;
+        }
+        self.stats
+            .set_gop_length(Some(Duration::from_nanos(interval.as_nanos())));
+
+        let Some(warn_interval) = self.warn_interval else {
+            return;
+        };
+        if interval <= warn_interval {
+            self.warned = false;
+            return;
+        }
+        // Only warn once until the interval recovers.
+        if !self.warned {
+            self.warned = true;
+            self.logger.log(
+                LogLevel::Warning,
+                &format!(
+                    "keyframe interval {:.1}s exceeds {:.1}s, recordings may skip up to that long when played",
+                    interval.as_secs_f64(),
+                    warn_interval.as_secs_f64(),
+                ),
+            );
+        }
+    }
+
+    // Timestamps from different muxers can't be compared.
+    fn reset(&mut self) {
+        self.prev = None;
+    }
+}
+
+// Follows the segments from the main source to measure the
+// keyframe interval and keep the pre-roll buffer filled.
+async fn follow_main_source(
+    token: CancellationToken,
+    _shutdown_complete: mpsc::Sender<()>,
+    mut source_main: RecorderSource,
+    pre_roll: Option<Arc<Mutex<PreRollBuffer>>>,
+    mut keyframe_interval: KeyframeInterval,
+) {
+    loop {
+        let source = source_main.borrow_and_update().clone();
+        if let Some(source) = source {
+            follow_source(&token, &source, pre_roll.as_deref(), &mut keyframe_interval).await;
+        }
+
+        // Wait for the source to be replaced.
+        tokio::select! {
+            () = token.cancelled() => return,
+            res = source_main.changed() => {
+                if res.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Returns when the source is cancelled.
+async fn follow_source(
+    token: &CancellationToken,
+    source: &ArcSource,
+    pre_roll: Option<&Mutex<PreRollBuffer>>,
+    keyframe_interval: &mut KeyframeInterval,
+) {
+    loop {
+        let muxer = tokio::select! {
+            () = token.cancelled() => return,
+            res = source.muxer() => res,
+        };
+        let Some(muxer) = muxer else {
+            return;
+        };
+        keyframe_interval.reset();
+        if let Some(pre_roll) = pre_roll {
+            pre_roll.lock().await.clear();
+        }
+
+        let mut prev_seg = None;
+        loop {
+            let seg = tokio::select! {
+                () = token.cancelled() => return,
+                res = muxer.next_segment(prev_seg.as_deref()) => res,
+            };
+            let Some(seg) = seg else {
+                break;
+            };
+            for part in seg.parts() {
+                for sample in part.video_samples.iter() {
+                    keyframe_interval.push(sample);
+                }
+            }
+            if let Some(pre_roll) = pre_roll {
+                pre_roll.lock().await.push(seg.clone());
+            }
+            prev_seg = Some(seg);
+        }
+
+        // Muxer was closed, wait for the source to restart.
+        tokio::select! {
+            () = token.cancelled() => return,
+            () = sleep(std::time::Duration::from_secs(1)) => {}
+        }
+    }
+}
+
 struct RecordingSession {
     token: CancellationToken,
     logger: ArcMsgLogger,
@@ -157,6 +410,7 @@ impl RecordingSession {
     fn new(
         parent_token: &CancellationToken,
         timer_end: Option<UnixNano>,
+        clip_start: Option<UnixNano>,
         c: RecordingContext,
         shutdown_complete: mpsc::Sender<()>,
     ) -> Self {
@@ -175,6 +429,7 @@ impl RecordingSession {
             run_recording_session(
                 token,
                 c,
+                clip_start,
                 shutdown_complete,
                 std::time::Duration::from_secs(3),
             )
@@ -221,11 +476,14 @@ impl Future for Sleep {
 async fn run_recording_session(
     session_token: CancellationToken,
     c: RecordingContext,
+    mut clip_start: Option<UnixNano>,
     _shutdown_complete: mpsc::Sender<()>,
     restart_sleep: std::time::Duration,
 ) {
     loop {
-        if let Err(e) = run_recording(session_token.clone(), c.clone()).await {
+        // Only the first recording in the session includes the pre-roll.
+        let res = run_recording(session_token.clone(), c.clone(), clip_start.take()).await;
+        if let Err(e) = res {
             c.log(LogLevel::Error, &format!("recording crashed: {e}"));
 
             tokio::select! {
@@ -261,11 +519,13 @@ enum RunRecordingError {
 struct RecordingContext {
     hooks: ArcMonitorHooks,
     logger: ArcMsgLogger,
-    source_main: ArcSource,
+    source_main: RecorderSource,
     prev_seg: Arc<Mutex<Option<Arc<SegmentFinalized>>>>,
     config: MonitorConfig,
     rec_db: Arc<RecDb>,
     event_cache: Arc<EventCache>,
+    stats: ArcMonitorStats,
+    pre_roll: Option<Arc<Mutex<PreRollBuffer>>>,
 }
 
 impl RecordingContext {
@@ -274,16 +534,43 @@ impl RecordingContext {
     }
 }
 
+#[allow(clippy::too_many_lines)]
 async fn run_recording(
     token: CancellationToken,
     c: RecordingContext,
+    clip_start: Option<UnixNano>,
 ) -> Result<(), RunRecordingError> {
-    let Some(muxer) = c.source_main.muxer().await else {
+    // Wait if the source is being replaced.
+    let mut source_main = c.source_main.clone();
+    let source = tokio::select! {
+        () = token.cancelled() => None,
+        res = source_main.wait_for(Option::is_some) => res.ok().and_then(|v| v.clone()),
+    };
+    let Some(source) = source else {
+        c.log(LogLevel::Debug, "recording cancelled");
+        return Ok(());
+    };
+
+    let Some(muxer) = source.muxer().await else {
         c.log(LogLevel::Debug, "source cancelled");
         return Ok(());
     };
 
-    let Some(first_segment) = muxer.next_segment(c.prev_seg.lock().await.as_deref()).await else {
+    let prev_seg = c.prev_seg.lock().await.clone();
+    let mut buffered = match (clip_start, &c.pre_roll) {
+        (Some(clip_start), Some(pre_roll)) => {
+            pre_roll
+                .lock()
+                .await
+                .since(clip_start.into(), muxer.muxer_id(), prev_seg.as_deref())
+        }
+        _ => VecDeque::new(),
+    };
+    let first_segment = if let Some(v) = buffered.pop_front() {
+        v
+    } else if let Some(v) = muxer.next_segment(prev_seg.as_deref()).await {
+        v
+    } else {
         c.log(LogLevel::Debug, "muxer cancelled");
         return Ok(());
     };
@@ -295,15 +582,17 @@ async fn run_recording(
         .rec_db
         .new_recording(monitor_id.clone(), start_time)
         .await?;
+    let _recording = RecordingGuard::new(&c.stats);
 
     let video_length = DurationH264::from(c.config.video_length());
+    let video_max_size = c.config.video_max_size();
 
     c.log(
         LogLevel::Info,
         &format!("starting recording: {:?}", recording.id()),
     );
 
-    let params = muxer.params();
+    let params = muxer.params().clone();
 
     let result = generate_thumbnail(
         c.hooks.clone(),
@@ -321,15 +610,41 @@ async fn run_recording(
         );
     }
 
-    let (new_prev_seg, end_time) = generate_video(
-        token,
-        &recording,
-        &muxer,
-        first_segment,
-        params,
-        video_length,
-    )
-    .await?;
+    let sidecar_token = CancellationToken::new();
+    let (video_result, sidecar_result) = tokio::join!(
+        async {
+            let result = generate_video(
+                token,
+                &recording,
+                muxer,
+                &mut source_main,
+                &c.logger,
+                first_segment,
+                buffered,
+                &params,
+                video_length,
+                video_max_size,
+                c.config.record_index(),
+                c.config.encrypt_recordings(),
+            )
+            .await;
+            sidecar_token.cancel();
+            result
+        },
+        write_detections_sidecar(
+            &sidecar_token,
+            &recording,
+            &c.event_cache,
+            UnixNano::from(start_time),
+        ),
+    );
+    if let Err(e) = sidecar_result {
+        c.log(
+            LogLevel::Error,
+            &format!("failed to write detections sidecar: {e}"),
+        );
+    }
+    let (new_prev_seg, end_time) = video_result?;
     *c.prev_seg.lock().await = Some(new_prev_seg);
 
     c.log(
@@ -350,11 +665,30 @@ async fn run_recording(
     Ok(())
 }
 
+// Marks the monitor as recording until dropped.
+struct RecordingGuard<'a>(&'a MonitorStats);
+
+impl<'a> RecordingGuard<'a> {
+    fn new(stats: &'a MonitorStats) -> Self {
+        stats.set_recording(true);
+        Self(stats)
+    }
+}
+
+impl Drop for RecordingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.set_recording(false);
+    }
+}
+
 #[derive(Debug, Error)]
 enum GenerateVideoError {
     #[error("open file: {0}")]
     OpenFile(#[from] OpenFileError),
 
+    #[error("new key: {0}")]
+    NewKey(#[from] NewKeyError),
+
     #[error("new video writer: {0}")]
     NewVideoWriter(#[from] CreateVideoWriterError),
 
@@ -366,15 +700,25 @@ enum GenerateVideoError {
 
     #[error("skipped segment: expected: {0}, got: {1}. this may be a disk issue")]
     SkippedSegment(u64, u64),
+
+    #[error("write index: {0}")]
+    WriteIndex(std::io::Error),
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn generate_video(
     token: CancellationToken,
     recording: &RecordingHandle,
-    muxer: &ArcHlsMuxer,
+    mut muxer: ArcHlsMuxer,
+    source_main: &mut RecorderSource,
+    logger: &ArcMsgLogger,
     first_segment: Arc<SegmentFinalized>,
+    mut buffered: VecDeque<Arc<SegmentFinalized>>,
     params: &TrackParameters,
     max_duration: DurationH264,
+    max_size: Option<u64>,
+    write_index: bool,
+    encrypt: bool,
 ) -> Result<(Arc<SegmentFinalized>, UnixH264), GenerateVideoError> {
     use GenerateVideoError::*;
 
@@ -389,18 +733,47 @@ async fn generate_video(
     let mut meta = BufWriter::with_capacity(64 * 1024, &mut *meta);
 
     let mut mdat = recording.new_file("mdat").await?;
-    let mut mdat = BufWriter::with_capacity(64 * 1024, &mut *mdat);
+    let mdat: Box<dyn AsyncWrite + Unpin + Send + Sync + '_> = if encrypt {
+        let key = recording.new_key().await?;
+        Box::new(EncryptWriter::new(&mut *mdat, &key))
+    } else {
+        Box::new(&mut *mdat)
+    };
+    let mut mdat = BufWriter::with_capacity(64 * 1024, mdat);
+
+    // Audio is only recorded unencrypted, the chunk
+    // nonces can't be shared with a second file.
+    let mut audio_files = match (&params.audio, encrypt) {
+        (Some(_), false) => Some((
+            recording.new_file("ameta").await?,
+            recording.new_file("amdat").await?,
+        )),
+        _ => None,
+    };
+    let mut audio_bufs = audio_files.as_mut().map(|(meta, mdat)| {
+        (
+            BufWriter::with_capacity(16 * 1024, &mut **meta),
+            BufWriter::with_capacity(16 * 1024, &mut **mdat),
+        )
+    });
+    let mut aw = audio_bufs
+        .as_mut()
+        .map(|(meta, mdat)| AudioWriter::new(meta, mdat));
 
     let header = MetaHeader {
         start_time,
         width: params.width,
         height: params.height,
         extra_data: params.extra_data.clone(),
+        audio: aw.as_ref().and(params.audio.clone()),
     };
 
     let mut w = VideoWriter::new(&mut meta, &mut mdat, header).await?;
 
     w.write_parts(first_segment.parts()).await?;
+    if let Some(aw) = &mut aw {
+        aw.write_parts(first_segment.parts()).await?;
+    }
 
     let mut prev_seg = first_segment.clone();
     let mut end_time = first_segment
@@ -408,30 +781,84 @@ async fn generate_video(
         .checked_add(first_segment.duration().into())
         .ok_or(Add)?;
 
-    loop {
+    let last_seg = loop {
         if token.is_cancelled() {
-            return Ok((prev_seg, end_time));
+            break prev_seg;
         }
 
-        let Some(seg) = muxer.next_segment(Some(&prev_seg)).await else {
-            return Ok((prev_seg, end_time));
+        // Write the buffered pre-roll segments before asking the muxer.
+        let next_seg = match buffered.pop_front() {
+            Some(v) => Some(v),
+            None => muxer.next_segment(Some(&prev_seg)).await,
+        };
+        let seg = if let Some(seg) = next_seg {
+            if seg.id() != prev_seg.id() + 1 {
+                return Err(SkippedSegment(seg.id(), prev_seg.id() + 1));
+            }
+            seg
+        } else {
+            // The source may have been replaced by a graceful restart.
+            let Some(new_muxer) = next_muxer(&token, source_main, params).await else {
+                break prev_seg;
+            };
+            logger.log(LogLevel::Debug, "source replaced, continuing recording");
+            muxer = new_muxer;
+
+            // The segment IDs start over in the new muxer.
+            let Some(seg) = muxer.next_segment(None).await else {
+                break prev_seg;
+            };
+            seg
         };
-
-        if seg.id() != prev_seg.id() + 1 {
-            return Err(SkippedSegment(seg.id(), prev_seg.id() + 1));
-        }
 
         prev_seg = seg.clone();
         w.write_parts(seg.parts()).await?;
+        if let Some(aw) = &mut aw {
+            aw.write_parts(seg.parts()).await?;
+        }
         end_time = seg
             .start_time()
             .checked_add(seg.duration().into())
             .ok_or(Add)?;
 
-        if seg.start_time().after(stop_time) {
-            return Ok((seg, end_time));
+        // Rotate on segment boundaries, segments always start with
+        // an IDR so the next recording can be decoded on its own.
+        let too_large = max_size.is_some_and(|max| u64::from(w.mdat_size()) >= max);
+        if seg.start_time().after(stop_time) || too_large {
+            break seg;
         }
+    };
+
+    if write_index {
+        let mut idx = recording.new_file("idx").await?;
+        w.write_index(&mut *idx).await.map_err(WriteIndex)?;
     }
+
+    Ok((last_seg, end_time))
+}
+
+// Waits for the main source to be replaced and returns the new muxer if
+// the track parameters are unchanged. The recording can then continue
+// in the same file. Returns None if the source wasn't replaced or if
+// the parameters changed and the recording must be finalized.
+async fn next_muxer(
+    token: &CancellationToken,
+    source_main: &mut RecorderSource,
+    params: &TrackParameters,
+) -> Option<ArcHlsMuxer> {
+    let replacing = source_main.borrow().is_none();
+    if !replacing && !source_main.has_changed().unwrap_or(false) {
+        return None;
+    }
+    let source = tokio::select! {
+        () = token.cancelled() => return None,
+        res = source_main.wait_for(Option::is_some) => res.ok()?.clone()?,
+    };
+    let muxer = tokio::select! {
+        () = token.cancelled() => return None,
+        res = source.muxer() => res?,
+    };
+    (muxer.params() == params).then_some(muxer)
 }
 
 #[derive(Debug, Error)]
@@ -502,7 +929,7 @@ async fn generate_thumbnail(
 }
 
 #[derive(Debug, Error)]
-enum AvccToJpegError {
+pub(crate) enum AvccToJpegError {
     #[error("new h264 decoder: {0}")]
     NewH264Decoder(#[from] H264BuilderError),
 
@@ -521,6 +948,12 @@ enum AvccToJpegError {
     #[error("convert: {0}")]
     Convert(#[from] ConvertError),
 
+    #[error("create scaler: {0}")]
+    CreateScaler(#[from] CreateScalerError),
+
+    #[error("scale: {0}")]
+    Scale(#[from] ScalerError),
+
     #[error("copy to buffer: {0}")]
     CopyToBuffer(#[from] ImageCopyToBufferError),
 
@@ -534,6 +967,28 @@ fn avcc_to_jpeg(
     avcc: &PaddedBytes,
     extradata: PaddedBytes,
 ) -> Result<Vec<u8>, AvccToJpegError> {
+    let frame = decode_avcc(avcc, extradata)?;
+    let frame = hooks.on_thumb_save(config, frame);
+    let frame = match config.thumb_max_size() {
+        Some(max_size) => downscale(frame, max_size)?,
+        None => frame,
+    };
+    frame_to_jpeg(&frame, config.thumb_quality())
+}
+
+// Same as `avcc_to_jpeg` but without the thumbnail hooks and scaling.
+pub(crate) fn keyframe_to_jpeg(
+    keyframe: &Keyframe,
+    quality: u8,
+) -> Result<Vec<u8>, AvccToJpegError> {
+    let frame = decode_avcc(
+        &keyframe.avcc,
+        PaddedBytes::new((*keyframe.extradata).clone()),
+    )?;
+    frame_to_jpeg(&frame, quality)
+}
+
+fn decode_avcc(avcc: &PaddedBytes, extradata: PaddedBytes) -> Result<Frame, AvccToJpegError> {
     let mut decoder = H264DecoderBuilder::new().avcc(extradata)?;
 
     decoder.send_packet(&Packet::new(avcc))?;
@@ -542,9 +997,10 @@ fn avcc_to_jpeg(
 
     let mut frame = Frame::new();
     h264_decoder.receive_frame(&mut frame)?;
+    Ok(frame)
+}
 
-    let frame = hooks.on_thumb_save(config, frame);
-
+fn frame_to_jpeg(frame: &Frame, quality: u8) -> Result<Vec<u8>, AvccToJpegError> {
     let mut converter = PixelFormatConverter::new(
         frame.width(),
         frame.height(),
@@ -555,21 +1011,75 @@ fn avcc_to_jpeg(
 
     let mut rgb_frame = Frame::new();
 
-    converter.convert(&frame, &mut rgb_frame)?;
+    converter.convert(frame, &mut rgb_frame)?;
 
     let mut raw_rgb_frame = Vec::new();
     rgb_frame.copy_to_buffer(&mut raw_rgb_frame, 1)?;
 
-    let mut jpeg_buf = Vec::new();
-    let jpeg_encoder = jpeg_encoder::Encoder::new(&mut jpeg_buf, 75);
+    Ok(encode_jpeg(
+        &raw_rgb_frame,
+        rgb_frame.width(),
+        rgb_frame.height(),
+        quality,
+    )?)
+}
 
+// Downscales the frame to fit within `max_size`, preserving the aspect ratio.
+fn downscale(frame: Frame, max_size: NonZeroU16) -> Result<Frame, AvccToJpegError> {
+    let Some((width, height)) = thumbnail_size(frame.width(), frame.height(), max_size) else {
+        return Ok(frame);
+    };
+    let mut scaler = Scaler::new(
+        frame.width(),
+        frame.height(),
+        frame.pix_fmt(),
+        width,
+        height,
+    )?;
+    let mut scaled = Frame::new();
+    scaler.scale(&frame, &mut scaled)?;
+    Ok(scaled)
+}
+
+// Returns the size of a frame scaled to fit within `max_size`,
+// None if the frame already fits. Frames are never upscaled.
+fn thumbnail_size(
+    width: NonZeroU16,
+    height: NonZeroU16,
+    max_size: NonZeroU16,
+) -> Option<(NonZeroU16, NonZeroU16)> {
+    let (long, short) = (width.max(height), width.min(height));
+    if long <= max_size {
+        return None;
+    }
+    // short * max_size / long, rounded.
+    let long_u32 = u32::from(long.get());
+    let scaled = (u32::from(short.get()) * u32::from(max_size.get()) + long_u32 / 2) / long_u32;
+    let scaled = u16::try_from(scaled).expect("scaled is less than max_size");
+
+    // The converter doesn't support odd sizes.
+    let even = |v: u16| NonZeroU16::new((v & !1).max(2)).expect("not zero");
+    if width >= height {
+        Some((even(max_size.get()), even(scaled)))
+    } else {
+        Some((even(scaled), even(max_size.get())))
+    }
+}
+
+fn encode_jpeg(
+    raw_rgb: &[u8],
+    width: NonZeroU16,
+    height: NonZeroU16,
+    quality: u8,
+) -> Result<Vec<u8>, jpeg_encoder::EncodingError> {
+    let mut jpeg_buf = Vec::new();
+    let jpeg_encoder = jpeg_encoder::Encoder::new(&mut jpeg_buf, quality);
     jpeg_encoder.encode(
-        &raw_rgb_frame,
-        rgb_frame.width().get(),
-        rgb_frame.height().get(),
+        raw_rgb,
+        width.get(),
+        height.get(),
         jpeg_encoder::ColorType::Rgb,
     )?;
-
     Ok(jpeg_buf)
 }
 
@@ -620,21 +1130,138 @@ async fn save_recording(
     Ok(())
 }
 
-struct EventCache(Mutex<Vec<Event>>);
+#[derive(Debug, Error)]
+enum WriteSidecarError {
+    #[error("open file: {0}")]
+    OpenFile(#[from] OpenFileError),
+
+    #[error("write: {0}")]
+    Write(#[from] WriteDetectionsError),
+}
+
+// Writes the detections of all events after `start` to the ".det" sidecar
+// until the token is cancelled. The file is only created if there are any.
+async fn write_detections_sidecar(
+    token: &CancellationToken,
+    recording: &RecordingHandle,
+    event_cache: &EventCache,
+    start: UnixNano,
+) -> Result<(), WriteSidecarError> {
+    let (cached, mut feed) = event_cache.subscribe(start).await;
+    let mut cached = cached.into_iter().filter(|e| !e.detections.is_empty());
+
+    let first = match cached.next() {
+        Some(v) => v,
+        None => match next_detection_event(token, &mut feed).await {
+            Some(v) => v,
+            None => return Ok(()),
+        },
+    };
+
+    let mut file = recording.new_file("det").await?;
+    let mut w = DetectionWriter::new(&mut *file);
+    for event in [first].into_iter().chain(cached) {
+        w.write(&frame_detections(event)).await?;
+    }
+    loop {
+        // Lines are flushed on an interval even if no more events arrive.
+        let flush_deadline = w.flush_deadline();
+        let event = tokio::select! {
+            event = next_detection_event(token, &mut feed) => event,
+            () = sleep_until(flush_deadline) => {
+                w.flush().await?;
+                continue;
+            }
+        };
+        let Some(event) = event else {
+            break;
+        };
+        w.write(&frame_detections(event)).await?;
+    }
+    w.flush().await?;
+    Ok(())
+}
+
+// Never completes if `deadline` is None.
+async fn sleep_until(deadline: Option<std::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+// Returns the next event with detections, None if the token is cancelled.
+// Events that were sent before the cancellation are returned first.
+async fn next_detection_event(
+    token: &CancellationToken,
+    feed: &mut broadcast::Receiver<Event>,
+) -> Option<Event> {
+    loop {
+        let res = tokio::select! {
+            biased;
+            res = feed.recv() => res,
+            () = token.cancelled() => return None,
+        };
+        match res {
+            Ok(event) if !event.detections.is_empty() => return Some(event),
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+fn frame_detections(event: Event) -> FrameDetections {
+    FrameDetections {
+        pts: event.time.into(),
+        detections: event.detections,
+    }
+}
+
+const EVENT_FEED_CAPACITY: usize = 64;
+
+struct EventCache {
+    events: Mutex<Vec<Event>>,
+
+    // New events are also sent to the detections sidecar writer.
+    feed: broadcast::Sender<Event>,
+}
 
 impl EventCache {
     fn new() -> Self {
-        Self(Mutex::new(Vec::new()))
+        Self::with_events(Vec::new())
+    }
+
+    fn with_events(events: Vec<Event>) -> Self {
+        Self {
+            events: Mutex::new(events),
+            feed: broadcast::channel(EVENT_FEED_CAPACITY).0,
+        }
     }
 
     async fn push(&self, event: Event) {
-        self.0.lock().await.push(event);
+        let mut events = self.events.lock().await;
+        if self.feed.receiver_count() != 0 {
+            _ = self.feed.send(event.clone());
+        }
+        events.push(event);
+    }
+
+    // Returns the cached events at or after `start` and a
+    // receiver for the events pushed after this call.
+    async fn subscribe(&self, start: UnixNano) -> (Vec<Event>, broadcast::Receiver<Event>) {
+        let events = self.events.lock().await;
+        let cached = events
+            .iter()
+            .filter(|e| !e.time.before(start))
+            .cloned()
+            .collect();
+        (cached, self.feed.subscribe())
     }
 
     async fn query_and_prune(&self, start: UnixNano, end: UnixNano) -> Vec<Event> {
         let mut new_events: Vec<Event> = Vec::new();
         let mut return_events: Vec<Event> = Vec::new();
-        let mut events = self.0.lock().await;
+        let mut events = self.events.lock().await;
         for event in events.drain(..) {
             if event.time.before(start) {
                 // Discard events before start time.
@@ -681,16 +1308,21 @@ mod tests {
     use std::{num::NonZeroU32, path::Path};
 
     use super::*;
+    use async_trait::async_trait;
     use bytesize::ByteSize;
     use common::{
+        monitor::{Feed, FeedDecoded, Source, SubscribeDecodedError},
         new_dummy_msg_logger,
-        time::{Duration, MINUTE},
-        Detection, DummyLogger, PointNormalized, RectangleNormalized, Region,
+        recording::FrameRateLimiter,
+        time::{DtsOffset, Duration, H264_SECOND, MINUTE, SECOND},
+        AudioParameters, AudioSample, Detection, DummyLogger, HlsMuxer, PartFinalized,
+        PointNormalized, RectangleNormalized, Region, StreamType, VideoSample,
     };
     use pretty_assertions::assert_eq;
     use recdb::Disk;
+    use recording::{read_audio_meta, read_detections, read_meta};
     use tempfile::tempdir;
-    use tokio::io::AsyncReadExt;
+    use tokio::{io::AsyncReadExt, runtime::Handle};
     /*
     func newTestRecorder(t *testing.T) *Recorder {
         t.Helper()
@@ -992,7 +1624,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_save_recording() {
-        let event_cache = Arc::new(EventCache(Mutex::new(vec![
+        let event_cache = Arc::new(EventCache::with_events(vec![
             Event {
                 time: UnixNano::new(0),
                 duration: Duration::new(0),
@@ -1029,7 +1661,7 @@ mod tests {
                 detections: Vec::new(),
                 source: Some("monitor".to_owned().try_into().expect("valid")),
             },
-        ])));
+        ]));
 
         let start = UnixNano::new(MINUTE);
         let end = UnixNano::new(11 * MINUTE);
@@ -1090,4 +1722,588 @@ mod tests {
 }";
         assert_eq!(want, got);
     }
+
+    fn test_event(time: i64, label: Option<&str>) -> Event {
+        Event {
+            time: UnixNano::new(time),
+            duration: Duration::new(0),
+            rec_duration: Duration::new(0),
+            detections: label
+                .map(|label| {
+                    vec![Detection {
+                        label: label.to_owned().try_into().unwrap(),
+                        score: 50.0,
+                        region: Region::default(),
+                    }]
+                })
+                .unwrap_or_default(),
+            source: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_detections_sidecar() {
+        let tempdir = tempdir().unwrap();
+        let rec_db = new_test_recdb(&tempdir.path().join("recordings"));
+        let recording = rec_db.test_recording().await;
+
+        // The first event is before the recording start.
+        let event_cache = EventCache::with_events(vec![
+            test_event(SECOND, Some("a")),
+            test_event(3 * SECOND, Some("b")),
+        ]);
+        let token = CancellationToken::new();
+
+        let start = UnixNano::new(2 * SECOND);
+        let writer = write_detections_sidecar(&token, &recording, &event_cache, start);
+        let events = async {
+            // Wait for the writer to subscribe.
+            while event_cache.feed.receiver_count() == 0 {
+                tokio::task::yield_now().await;
+            }
+            event_cache.push(test_event(4 * SECOND, None)).await;
+            event_cache.push(test_event(5 * SECOND, Some("c"))).await;
+            token.cancel();
+        };
+        let (res, ()) = tokio::join!(writer, events);
+        res.unwrap();
+
+        let mut file = recording.open_file("det").await.unwrap();
+        let got: Vec<_> = read_detections(&mut *file)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.pts, v.detections[0].label.to_string()))
+            .collect();
+        let want: Vec<(UnixH264, String)> = vec![
+            (UnixNano::new(3 * SECOND).into(), "b".to_owned()),
+            (UnixNano::new(5 * SECOND).into(), "c".to_owned()),
+        ];
+        assert_eq!(want, got);
+    }
+
+    #[tokio::test]
+    async fn test_write_detections_sidecar_no_detections() {
+        let tempdir = tempdir().unwrap();
+        let rec_db = new_test_recdb(&tempdir.path().join("recordings"));
+        let recording = rec_db.test_recording().await;
+
+        let event_cache = EventCache::with_events(vec![test_event(SECOND, None)]);
+        let token = CancellationToken::new();
+        token.cancel();
+        write_detections_sidecar(&token, &recording, &event_cache, UnixNano::new(0))
+            .await
+            .unwrap();
+
+        assert!(recording.open_file("det").await.is_err());
+    }
+
+    // Returns None after the last segment like a stopped source.
+    struct StubMuxer {
+        params: TrackParameters,
+        segments: Vec<Arc<SegmentFinalized>>,
+    }
+
+    #[async_trait]
+    impl HlsMuxer for StubMuxer {
+        fn params(&self) -> &TrackParameters {
+            &self.params
+        }
+        fn muxer_id(&self) -> u16 {
+            self.segments.first().map_or(0, |v| v.muxer_id())
+        }
+        async fn next_segment(
+            &self,
+            prev_seg: Option<&SegmentFinalized>,
+        ) -> Option<Arc<SegmentFinalized>> {
+            let muxer_id = self.segments[0].muxer_id();
+            let prev_id = match prev_seg {
+                Some(seg) if seg.muxer_id() == muxer_id => seg.id(),
+                Some(_) | None => 0,
+            };
+            self.segments.iter().find(|v| prev_id < v.id()).cloned()
+        }
+    }
+
+    struct StubSource(ArcHlsMuxer);
+
+    #[async_trait]
+    impl Source for StubSource {
+        fn stream_type(&self) -> &StreamType {
+            &StreamType::Main
+        }
+        async fn muxer(&self) -> Option<ArcHlsMuxer> {
+            Some(self.0.clone())
+        }
+        async fn subscribe(&self) -> Option<Feed> {
+            unimplemented!()
+        }
+        async fn subscribe_decoded(
+            &self,
+            _: Handle,
+            _: ArcMsgLogger,
+            _: Option<FrameRateLimiter>,
+        ) -> Option<Result<FeedDecoded, SubscribeDecodedError>> {
+            unimplemented!()
+        }
+    }
+
+    fn stub_segment(id: u64, muxer_id: u16, start: i64, duration: i64) -> Arc<SegmentFinalized> {
+        Arc::new(SegmentFinalized::new(
+            id,
+            muxer_id,
+            UnixH264::new(start),
+            String::new(),
+            Vec::new(),
+            DurationH264::new(duration),
+        ))
+    }
+
+    fn new_stub_muxer(width: u16, segments: Vec<Arc<SegmentFinalized>>) -> ArcHlsMuxer {
+        Arc::new(StubMuxer {
+            params: TrackParameters {
+                width,
+                height: 480,
+                codec: "avc1.640016".to_owned(),
+                extra_data: Vec::new(),
+                audio: None,
+            },
+            segments,
+        })
+    }
+
+    // Muxer with two 1000 long segments.
+    fn stub_muxer(muxer_id: u16, width: u16, start: i64) -> ArcHlsMuxer {
+        new_stub_muxer(
+            width,
+            vec![
+                stub_segment(1, muxer_id, start, 1000),
+                stub_segment(2, muxer_id, start + 1000, 1000),
+            ],
+        )
+    }
+
+    fn stub_source(muxer: ArcHlsMuxer) -> ArcSource {
+        Arc::new(StubSource(muxer))
+    }
+
+    async fn generate_video_with_replaced_source(
+        replacement: ArcHlsMuxer,
+    ) -> (Arc<SegmentFinalized>, UnixH264) {
+        let tempdir = tempdir().unwrap();
+        let rec_db = new_test_recdb(tempdir.path());
+        let recording = rec_db.test_recording().await;
+
+        let muxer = stub_muxer(1, 640, 0);
+        let (source_tx, mut source_rx) = watch::channel(Some(stub_source(muxer.clone())));
+        source_rx.borrow_and_update();
+        source_tx.send_replace(Some(stub_source(replacement)));
+
+        let first_segment = muxer.next_segment(None).await.unwrap();
+        let params = muxer.params().clone();
+        let logger: ArcMsgLogger = new_dummy_msg_logger();
+        generate_video(
+            CancellationToken::new(),
+            &recording,
+            muxer,
+            &mut source_rx,
+            &logger,
+            first_segment,
+            VecDeque::new(),
+            &params,
+            DurationH264::new(1_000_000),
+            None,
+            false,
+            false,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_generate_video_source_replaced() {
+        // Non-structural change, the track parameters are the same.
+        let (last_seg, end_time) =
+            generate_video_with_replaced_source(stub_muxer(2, 640, 10000)).await;
+
+        // The recording wasn't rotated and continued with the new source.
+        assert_eq!(2, last_seg.muxer_id());
+        assert_eq!(UnixH264::new(12000), end_time);
+    }
+
+    #[tokio::test]
+    async fn test_generate_video_source_replaced_params_changed() {
+        let (last_seg, end_time) =
+            generate_video_with_replaced_source(stub_muxer(2, 1280, 10000)).await;
+
+        // The recording was finalized before the new source.
+        assert_eq!(1, last_seg.muxer_id());
+        assert_eq!(UnixH264::new(2000), end_time);
+    }
+
+    // 1000 long segment with a 100 byte IDR followed by
+    // a 100 byte frame and a single 10 byte audio frame.
+    fn stub_segment_with_samples(id: u64, start: i64) -> Arc<SegmentFinalized> {
+        let sample = |pts, random_access_present| VideoSample {
+            pts: UnixH264::new(pts),
+            dts_offset: DtsOffset::new(0),
+            avcc: Arc::new(PaddedBytes::new(vec![0; 100])),
+            random_access_present,
+            duration: DurationH264::new(500),
+        };
+        let part = PartFinalized {
+            id,
+            is_independent: true,
+            video_samples: Arc::new(vec![sample(start, true), sample(start + 500, false)]),
+            audio_samples: Arc::new(vec![AudioSample {
+                pts: UnixH264::new(start),
+                duration: DurationH264::new(1000),
+                data: vec![0; 10].into(),
+            }]),
+            rendered_content: None,
+            rendered_duration: DurationH264::new(1000),
+        };
+        Arc::new(SegmentFinalized::new(
+            id,
+            1,
+            UnixH264::new(start),
+            String::new(),
+            vec![Arc::new(part)],
+            DurationH264::new(1000),
+        ))
+    }
+
+    // Returns the ID of the last segment in the recording.
+    async fn generate_rotated_video(max_duration: i64, max_size: Option<u64>) -> u64 {
+        let tempdir = tempdir().unwrap();
+        let rec_db = new_test_recdb(tempdir.path());
+        let recording = rec_db.test_recording().await;
+
+        let segments = (1..=5)
+            .map(|id| stub_segment_with_samples(id, i64::try_from(id - 1).unwrap() * 1000))
+            .collect();
+        let muxer = new_stub_muxer(640, segments);
+        let (_source_tx, mut source_rx) = watch::channel(Some(stub_source(muxer.clone())));
+        source_rx.borrow_and_update();
+
+        let first_segment = muxer.next_segment(None).await.unwrap();
+        let params = muxer.params().clone();
+        let logger: ArcMsgLogger = new_dummy_msg_logger();
+        let (last_seg, _) = generate_video(
+            CancellationToken::new(),
+            &recording,
+            muxer.clone(),
+            &mut source_rx,
+            &logger,
+            first_segment,
+            VecDeque::new(),
+            &params,
+            DurationH264::new(max_duration),
+            max_size,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+
+        // The recording ends on a segment boundary.
+        let mut buf = Vec::new();
+        let mut meta = recording.open_file("meta").await.unwrap();
+        meta.read_to_end(&mut buf).await.unwrap();
+        let (_, samples) = read_meta(buf.as_slice(), u64::try_from(buf.len()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(usize::try_from(last_seg.id() * 2).unwrap(), samples.len());
+        assert!(samples[0].random_access_present);
+
+        // And the next recording starts with an IDR.
+        if let Some(next_seg) = muxer.next_segment(Some(&last_seg)).await {
+            assert!(next_seg.parts()[0].video_samples[0].random_access_present);
+        }
+        last_seg.id()
+    }
+
+    #[tokio::test]
+    async fn test_generate_video_rotation() {
+        // Duration, the third segment starts after 1500.
+        assert_eq!(3, generate_rotated_video(1500, None).await);
+
+        // Size, 400 bytes after the second segment.
+        assert_eq!(2, generate_rotated_video(1_000_000, Some(350)).await);
+
+        // Whichever comes first.
+        assert_eq!(2, generate_rotated_video(1500, Some(250)).await);
+        assert_eq!(3, generate_rotated_video(1500, Some(1000)).await);
+
+        // Neither, the source stopped.
+        assert_eq!(5, generate_rotated_video(1_000_000, None).await);
+    }
+
+    #[tokio::test]
+    async fn test_generate_video_audio() {
+        let tempdir = tempdir().unwrap();
+        let rec_db = new_test_recdb(tempdir.path());
+        let recording = rec_db.test_recording().await;
+
+        let audio = AudioParameters {
+            channel_count: 1,
+            sample_rate: 8000,
+            config: vec![0x15, 0x88],
+        };
+        let muxer: ArcHlsMuxer = Arc::new(StubMuxer {
+            params: TrackParameters {
+                width: 640,
+                height: 480,
+                codec: "avc1.640016".to_owned(),
+                extra_data: Vec::new(),
+                audio: Some(audio.clone()),
+            },
+            segments: vec![
+                stub_segment_with_samples(1, 0),
+                stub_segment_with_samples(2, 1000),
+            ],
+        });
+        let (_source_tx, mut source_rx) = watch::channel(Some(stub_source(muxer.clone())));
+        source_rx.borrow_and_update();
+
+        let first_segment = muxer.next_segment(None).await.unwrap();
+        let params = muxer.params().clone();
+        let logger: ArcMsgLogger = new_dummy_msg_logger();
+        generate_video(
+            CancellationToken::new(),
+            &recording,
+            muxer,
+            &mut source_rx,
+            &logger,
+            first_segment,
+            VecDeque::new(),
+            &params,
+            DurationH264::new(1_000_000),
+            None,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+
+        let mut buf = Vec::new();
+        let mut meta = recording.open_file("meta").await.unwrap();
+        meta.read_to_end(&mut buf).await.unwrap();
+        let (header, _) = read_meta(buf.as_slice(), u64::try_from(buf.len()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(Some(audio), header.audio);
+
+        let mut buf = Vec::new();
+        let mut ameta = recording.open_file("ameta").await.unwrap();
+        ameta.read_to_end(&mut buf).await.unwrap();
+        let samples = read_audio_meta(buf.as_slice(), u64::try_from(buf.len()).unwrap())
+            .await
+            .unwrap();
+        let pts: Vec<_> = samples.iter().map(|v| (v.pts, v.data_offset)).collect();
+        assert_eq!(vec![(UnixH264::new(0), 0), (UnixH264::new(1000), 10)], pts);
+
+        let mut buf = Vec::new();
+        let mut amdat = recording.open_file("amdat").await.unwrap();
+        amdat.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(20, buf.len());
+    }
+
+    fn detection_event(secs: i64, label: &str, score: f32) -> Event {
+        Event {
+            time: UnixNano::new(secs * SECOND),
+            duration: Duration::new(0),
+            rec_duration: Duration::new(0),
+            detections: vec![Detection {
+                label: label.to_owned().try_into().unwrap(),
+                score,
+                region: Region::default(),
+            }],
+            source: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_trigger_no_duplicate_segments() {
+        let config: MonitorConfig = serde_json::from_value(serde_json::json!({
+            "id": "x",
+            "name": "x",
+            "enable": true,
+            "source": "rtsp",
+            "sourcertsp": {
+                "protocol": "tcp",
+                "mainStream": "rtsp://x1",
+            },
+            "alwaysRecord": false,
+            "videoLength": 60.0,
+            "recordTrigger": {
+                "thresholds": { "person": 0.5 },
+                "preRoll": 3,
+                "postRoll": 5,
+            },
+        }))
+        .unwrap();
+
+        // One second segment starting at `i` seconds.
+        let segment = |i: i64| {
+            stub_segment(
+                u64::try_from(i + 1).unwrap(),
+                1,
+                i * H264_SECOND,
+                H264_SECOND,
+            )
+        };
+
+        // The buffer only keeps the pre-roll.
+        let mut pre_roll = PreRollBuffer::new(DurationH264::new(3 * H264_SECOND));
+        for i in 0..10 {
+            pre_roll.push(segment(i));
+        }
+        assert_eq!(3, pre_roll.segments.len());
+
+        let event = detection_event(10, "person", 0.9);
+        let pre_roll_duration = *config.record_trigger().unwrap().pre_roll;
+        let clip_start: UnixH264 = event
+            .time
+            .checked_sub(pre_roll_duration.into())
+            .unwrap()
+            .into();
+        let clip_end = event_end(&config, &event).unwrap();
+        assert_eq!(UnixNano::new(15 * SECOND), clip_end);
+
+        let ids = |segments: &VecDeque<Arc<SegmentFinalized>>| -> Vec<u64> {
+            segments.iter().map(|v| v.id()).collect()
+        };
+        assert_eq!(vec![8, 9, 10], ids(&pre_roll.since(clip_start, 1, None)));
+
+        // Segments from an older muxer are never returned.
+        assert!(pre_roll.since(clip_start, 2, None).is_empty());
+
+        // The previous recording ended with segment 9.
+        let prev_seg = segment(8);
+        let mut buffered = pre_roll.since(clip_start, 1, Some(&prev_seg));
+        assert_eq!(vec![10], ids(&buffered));
+
+        // The previous segment of an older muxer is ignored.
+        let old_seg = stub_segment(9, 2, 8 * H264_SECOND, H264_SECOND);
+        assert_eq!(
+            vec![8, 9, 10],
+            ids(&pre_roll.since(clip_start, 1, Some(&old_seg)))
+        );
+
+        let first_segment = buffered.pop_front().unwrap();
+
+        // Live segments until the post-roll.
+        let muxer = new_stub_muxer(640, (10..15).map(segment).collect());
+
+        let tempdir = tempdir().unwrap();
+        let rec_db = new_test_recdb(tempdir.path());
+        let recording = rec_db.test_recording().await;
+        let (_source_tx, mut source_rx) = watch::channel(Some(stub_source(muxer.clone())));
+        let params = muxer.params().clone();
+        let start_time = first_segment.start_time();
+        let logger: ArcMsgLogger = new_dummy_msg_logger();
+        let (_, end_time) = generate_video(
+            CancellationToken::new(),
+            &recording,
+            muxer,
+            &mut source_rx,
+            &logger,
+            first_segment,
+            buffered,
+            &params,
+            DurationH264::new(3600 * H264_SECOND),
+            None,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+
+        // The recording continues after the previous one without overlap.
+        assert_eq!(UnixNano::new(9 * SECOND), UnixNano::from(start_time));
+        assert_eq!(clip_end, UnixNano::from(end_time));
+    }
+
+    fn nz(v: u16) -> NonZeroU16 {
+        NonZeroU16::new(v).unwrap()
+    }
+
+    #[test]
+    fn test_thumbnail_size() {
+        let size =
+            |w, h, max| thumbnail_size(nz(w), nz(h), nz(max)).map(|(w, h)| (w.get(), h.get()));
+        assert_eq!(Some((320, 180)), size(1920, 1080, 320));
+        assert_eq!(Some((180, 320)), size(1080, 1920, 320));
+        // Odd sizes are rounded down.
+        assert_eq!(Some((320, 240)), size(641, 481, 321));
+        // Never upscale.
+        assert_eq!(None, size(640, 480, 640));
+        assert_eq!(None, size(640, 480, 1920));
+    }
+
+    #[test]
+    fn test_encode_jpeg_quality() {
+        let (width, height) = (nz(320), nz(180));
+        let mut seed = 1_u32;
+        let raw_rgb: Vec<u8> = (0..320 * 180 * 3)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                seed.to_be_bytes()[1]
+            })
+            .collect();
+
+        let low = encode_jpeg(&raw_rgb, width, height, 20).unwrap();
+        let high = encode_jpeg(&raw_rgb, width, height, 90).unwrap();
+        assert_eq!([0xff, 0xd8], low[..2]);
+        assert!(low.len() < high.len(), "{} {}", low.len(), high.len());
+    }
+
+    struct CaptureLogger(std::sync::Mutex<Vec<(LogLevel, String)>>);
+
+    impl MsgLogger for CaptureLogger {
+        fn log(&self, level: LogLevel, msg: &str) {
+            self.0.lock().unwrap().push((level, msg.to_owned()));
+        }
+    }
+
+    #[test]
+    fn test_keyframe_interval_sparse() {
+        let logger = Arc::new(CaptureLogger(std::sync::Mutex::new(Vec::new())));
+        let stats = Arc::new(MonitorStats::default());
+        let mut keyframe_interval = KeyframeInterval::new(
+            logger.clone(),
+            stats.clone(),
+            Some(DurationH264::new(10 * H264_SECOND)),
+        );
+        let sample = |secs: i64, random_access_present| VideoSample {
+            pts: UnixH264::new(secs * H264_SECOND),
+            random_access_present,
+            ..Default::default()
+        };
+
+        // Keyframes every 2 seconds.
+        for i in 0..10 {
+            keyframe_interval.push(&sample(i, i % 2 == 0));
+        }
+        assert!(logger.0.lock().unwrap().is_empty());
+        assert_eq!(Some(Duration::from_secs(2)), stats.gop_length());
+
+        // Sparse keyframes only warn once.
+        keyframe_interval.push(&sample(30, true));
+        keyframe_interval.push(&sample(60, true));
+        assert_eq!(Some(Duration::from_secs(30)), stats.gop_length());
+        let logs = logger.0.lock().unwrap().clone();
+        assert_eq!(1, logs.len());
+        assert_eq!(LogLevel::Warning, logs[0].0);
+        assert_eq!(
+            "keyframe interval 22.0s exceeds 10.0s, recordings may skip up to that long when played",
+            logs[0].1,
+        );
+
+        // Warn again after the interval recovered.
+        keyframe_interval.push(&sample(61, true));
+        keyframe_interval.push(&sample(81, true));
+        assert_eq!(2, logger.0.lock().unwrap().len());
+    }
 }
@@ -4,22 +4,22 @@ use crate::log_monitor;
 use async_trait::async_trait;
 use common::{
     monitor::{
-        DecoderError, Feed, FeedDecoded, Protocol, RtspUrl, Source, SourceRtspConfig,
-        SubscribeDecodedError,
+        ArcLatestFrame, ArcMonitorStats, DecoderError, Feed, FeedDecoded, Keyframe, Protocol,
+        RtspUrl, Source, SourceRtspConfig, SubscribeDecodedError,
     },
     recording::FrameRateLimiter,
-    time::{DtsOffset, UnixH264, UnixNano, H264_SECOND},
-    ArcHlsMuxer, ArcLogger, ArcMsgLogger, H264Data, LogEntry, LogLevel, MonitorId, MsgLogger,
-    StreamType,
+    time::{DtsOffset, DurationH264, UnixH264, UnixNano, H264_SECOND, H264_TIMESCALE},
+    ArcHlsMuxer, ArcLogger, ArcMsgLogger, AudioParameters, AudioSample, H264Data, LogEntry,
+    LogLevel, MonitorId, MsgLogger, StreamType,
 };
 use futures_lite::StreamExt;
 use hls::{
-    track_params_from_video_params, CreateSegmenterError, H264Writer, HlsServer, ParseParamsError,
-    SegmenterWriteH264Error,
+    audio_params_from_retina, avcc_has_recovery_point, track_params_from_video_params,
+    CreateSegmenterError, H264Writer, HlsServer, ParseParamsError, SegmenterWriteH264Error,
 };
 use retina::{
     client::Stream,
-    codec::{ParametersRef, VideoFrame},
+    codec::{AudioFrame, ParametersRef, VideoFrame},
 };
 use sentryshot_convert::Frame;
 use sentryshot_ffmpeg_h264::{
@@ -30,6 +30,7 @@ use thiserror::Error;
 use tokio::{
     runtime::Handle,
     sync::{broadcast, mpsc, oneshot},
+    time::timeout,
 };
 use tokio_util::sync::CancellationToken;
 use url::Url;
@@ -37,6 +38,7 @@ use url::Url;
 #[allow(clippy::module_name_repetitions)]
 pub struct MonitorSource {
     stream_type: StreamType,
+    stats: ArcMonitorStats,
     get_muxer_tx: mpsc::Sender<oneshot::Sender<ArcHlsMuxer>>,
     subscribe_tx: mpsc::Sender<oneshot::Sender<Feed>>,
 }
@@ -45,11 +47,13 @@ impl MonitorSource {
     #[must_use]
     pub fn new(
         stream_type: StreamType,
+        stats: ArcMonitorStats,
         get_muxer_tx: mpsc::Sender<oneshot::Sender<ArcHlsMuxer>>,
         subscribe_tx: mpsc::Sender<oneshot::Sender<Feed>>,
     ) -> Self {
         Self {
             stream_type,
+            stats,
             get_muxer_tx,
             subscribe_tx,
         }
@@ -110,7 +114,7 @@ impl Source for MonitorSource {
         Some(Ok(new_decoder(
             rt_handle,
             logger,
-            feed,
+            LossyFeed::new(feed, self.stats.clone()),
             h264_decoder,
             limiter,
         )))
@@ -165,6 +169,8 @@ pub struct SourceRtsp {
     monitor_id: MonitorId,
     config: SourceRtspConfig,
     stream_type: StreamType,
+    stats: ArcMonitorStats,
+    latest_frame: ArcLatestFrame,
 }
 
 impl SourceRtsp {
@@ -177,6 +183,8 @@ impl SourceRtsp {
         monitor_id: MonitorId,
         config: SourceRtspConfig,
         stream_type: StreamType,
+        stats: ArcMonitorStats,
+        latest_frame: ArcLatestFrame,
     ) -> Option<MonitorSource> {
         if stream_type.is_sub() && config.sub_stream.is_none() {
             log_monitor(&logger, LogLevel::Debug, &monitor_id, "no sub stream");
@@ -196,6 +204,8 @@ impl SourceRtsp {
             monitor_id,
             config,
             stream_type,
+            stats: stats.clone(),
+            latest_frame,
         };
 
         let (started_tx, mut started_rx) = mpsc::channel(1);
@@ -214,6 +224,10 @@ impl SourceRtsp {
                     Ok(()) => source.log(LogLevel::Debug, "cancelled"),
                     Err(e) => source.log(LogLevel::Error, &format!("crashed: {e}")),
                 };
+                if source.stream_type.is_main() {
+                    // Don't serve a frame from the stopped stream.
+                    source.latest_frame.clear();
+                }
 
                 tokio::select! {
                     () = token2.cancelled() => {}
@@ -272,13 +286,44 @@ impl SourceRtsp {
             }
         });
 
-        Some(MonitorSource::new(stream_type, get_muxer_tx, subscribe_tx))
+        Some(MonitorSource::new(
+            stream_type,
+            stats,
+            get_muxer_tx,
+            subscribe_tx,
+        ))
     }
 
     fn log(&self, level: LogLevel, msg: &str) {
         self.msg_logger.log(level, msg);
     }
 
+    // Only keyframes from the main stream are kept.
+    fn set_latest_frame(&self, data: &H264Data, extradata: &Arc<Vec<u8>>) {
+        if self.stream_type.is_main() && data.random_access_present {
+            self.latest_frame.set(Keyframe {
+                avcc: data.avcc.clone(),
+                extradata: extradata.clone(),
+                time: UnixNano::from(data.pts),
+            });
+        }
+    }
+
+    // Recording continues without audio if the parameters are unsupported.
+    fn audio_params(&self, stream: &Stream) -> Option<AudioParameters> {
+        let Some(ParametersRef::Audio(params)) = stream.parameters() else {
+            self.log(LogLevel::Warning, "audio stream has no parameters");
+            return None;
+        };
+        match audio_params_from_retina(params) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.log(LogLevel::Warning, &format!("audio: {e}"));
+                None
+            }
+        }
+    }
+
     #[allow(clippy::too_many_lines, clippy::similar_names)]
     async fn run(
         &self,
@@ -291,15 +336,22 @@ impl SourceRtsp {
         let creds = creds_from_url(url);
         let url = remove_creds_from_url(url.to_owned())?;
 
+        let connect_timeout = std_duration(self.config.connect_timeout())?;
+        let read_timeout = std_duration(self.config.read_timeout())?;
+
         let session_group = Arc::new(retina::client::SessionGroup::default());
-        let mut session = retina::client::Session::describe(
-            url.clone(),
-            retina::client::SessionOptions::default()
-                .creds(creds)
-                .session_group(session_group.clone())
-                .teardown(retina::client::TeardownPolicy::Always),
+        let mut session = timeout(
+            connect_timeout,
+            retina::client::Session::describe(
+                url.clone(),
+                retina::client::SessionOptions::default()
+                    .creds(creds)
+                    .session_group(session_group.clone())
+                    .teardown(retina::client::TeardownPolicy::Always),
+            ),
         )
         .await
+        .map_err(|_| ConnectTimeout("describe"))?
         .map_err(Describe)?;
 
         let video_stream_i = {
@@ -326,7 +378,21 @@ impl SourceRtsp {
             s
         };
 
-        let transport = match self.config.protocol {
+        // Only the main stream is recorded and only AAC is supported.
+        let audio_stream_i = if self.config.audio && self.stream_type.is_main() {
+            let s = session
+                .streams()
+                .iter()
+                .position(|s| s.media() == "audio" && s.encoding_name() == "mpeg4-generic");
+            if s.is_none() {
+                self.log(LogLevel::Warning, "no AAC audio stream found");
+            }
+            s
+        } else {
+            None
+        };
+
+        let transport = || match self.config.protocol {
             Protocol::Tcp => {
                 retina::client::Transport::Tcp(retina::client::TcpTransportOptions::default())
             }
@@ -335,20 +401,28 @@ impl SourceRtsp {
             }
         };
 
-        session
-            .setup(
-                video_stream_i,
-                retina::client::SetupOptions::default().transport(transport),
+        for stream_i in std::iter::once(video_stream_i).chain(audio_stream_i) {
+            timeout(
+                connect_timeout,
+                session.setup(
+                    stream_i,
+                    retina::client::SetupOptions::default().transport(transport()),
+                ),
             )
             .await
+            .map_err(|_| ConnectTimeout("setup"))?
             .map_err(SourceRtspRunError::Setup)?;
+        }
 
-        let mut session = session
-            .play(retina::client::PlayOptions::default())
-            .await
-            .map_err(Play)?
-            .demuxed()
-            .map_err(Demuxed)?;
+        let mut session = timeout(
+            connect_timeout,
+            session.play(retina::client::PlayOptions::default()),
+        )
+        .await
+        .map_err(|_| ConnectTimeout("play"))?
+        .map_err(Play)?
+        .demuxed()
+        .map_err(Demuxed)?;
 
         // Buffer 10 frame to reduce dropped frames.
         let (feed_tx, _) = broadcast::channel(10);
@@ -359,8 +433,8 @@ impl SourceRtsp {
                 () = token.cancelled() => {
                     return Ok(());
                 },
-                pkt = session.next() => {
-                    let Some(pkt) = pkt else {
+                pkt = next_packet(&mut session, read_timeout) => {
+                    let Some(pkt) = pkt? else {
                         return Err(Eof);
                     };
                     match pkt {
@@ -370,13 +444,16 @@ impl SourceRtsp {
                                     frame,
                                     stream_started.start_time,
                                     stream_started.first_sample_pts,
+                                    self.config.sei_recovery_point,
                                 )?;
                                 check_clock_drift(data.pts)?;
+                                self.set_latest_frame(&data, &stream_started.extradata);
                                 stream_started.hls_writer.write_h264(data.clone()).await?;
                                 _ = feed_tx.send(data);
+                                self.stats.add_frame(self.stream_type);
 
                             } else {
-                                if !frame.is_random_access_point() {
+                                if !is_random_access(&frame, self.config.sei_recovery_point) {
                                     // Wait for IDR.
                                     continue
                                 }
@@ -385,11 +462,22 @@ impl SourceRtsp {
                                 if let Some(ParametersRef::Video(params)) = stream.parameters() {
                                     let start_time = UnixNano::now();
                                     let first_sample_pts = UnixH264::new(frame.timestamp().pts());
-                                    let first_sample = parse_frame(frame, start_time, first_sample_pts)?;
+                                    let first_sample = parse_frame(
+                                        frame,
+                                        start_time,
+                                        first_sample_pts,
+                                        self.config.sei_recovery_point,
+                                    )?;
+                                    let mut track_params = track_params_from_video_params(params)?;
+                                    track_params.audio = audio_stream_i
+                                        .and_then(|i| self.audio_params(&session.streams()[i]));
+                                    let has_audio = track_params.audio.is_some();
+                                    let extradata = Arc::new(track_params.extra_data.clone());
+                                    self.set_latest_frame(&first_sample, &extradata);
                                     let result = self.hls_server.new_muxer(
                                         token.clone(),
                                         self.hls_name(),
-                                        track_params_from_video_params(params)?,
+                                        track_params,
                                         start_time,
                                         first_sample.clone(),
                                     ).await?;
@@ -397,12 +485,21 @@ impl SourceRtsp {
                                         // Cancelled.
                                         return Ok(());
                                     };
-                                    stream_started = Some(StreamStarted{ hls_writer, start_time, first_sample_pts });
+                                    stream_started = Some(StreamStarted{ hls_writer, start_time, first_sample_pts, extradata, has_audio, audio_start: None });
                                     // Notify successful start.
                                     _ = started_tx.send((muxer, feed_tx.clone())).await;
                                 };
                             }
                         },
+                        Ok(retina::codec::CodecItem::AudioFrame(frame)) => {
+                            // Frames before the first keyframe are dropped.
+                            let Some(started) = stream_started.as_mut().filter(|v| v.has_audio) else {
+                                continue
+                            };
+                            if let Some(sample) = parse_audio_frame(&frame, &mut started.audio_start) {
+                                started.hls_writer.write_audio(sample);
+                            }
+                        },
                         Ok(_) => {},
                         Err(e) => return Err(Stream(e)),
                     }
@@ -435,6 +532,11 @@ struct StreamStarted {
     hls_writer: H264Writer,
     start_time: UnixNano,
     first_sample_pts: UnixH264,
+    extradata: Arc<Vec<u8>>,
+    has_audio: bool,
+
+    // Elapsed RTP time and wall-clock time of the first audio frame.
+    audio_start: Option<(i64, UnixH264)>,
 }
 
 #[derive(Debug, Error)]
@@ -451,6 +553,7 @@ fn parse_frame(
     frame: VideoFrame,
     start_time: UnixNano,
     first_sample_time: UnixH264,
+    sei_recovery_point: bool,
 ) -> Result<H264Data, ParseFrameError> {
     use ParseFrameError::*;
     let timestamp = frame.timestamp();
@@ -468,11 +571,76 @@ fn parse_frame(
     Ok(H264Data {
         pts,
         dts_offset,
-        random_access_present: frame.is_random_access_point(),
+        random_access_present: is_random_access(&frame, sei_recovery_point),
         avcc: Arc::new(PaddedBytes::new(frame.into_data())),
     })
 }
 
+// The audio and video RTP clocks are unrelated, the first audio frame
+// is assumed to have been captured when it was received. The following
+// frames are timed relative to the first one.
+fn parse_audio_frame(
+    frame: &AudioFrame,
+    audio_start: &mut Option<(i64, UnixH264)>,
+) -> Option<AudioSample> {
+    let timestamp = frame.timestamp();
+    let elapsed = timestamp.elapsed();
+    let start = *audio_start.get_or_insert_with(|| (elapsed, UnixH264::now()));
+    let (pts, duration) = audio_sample_time(
+        start,
+        elapsed,
+        timestamp.clock_rate().get(),
+        frame.frame_length().get(),
+    )?;
+    Some(AudioSample {
+        pts,
+        duration,
+        data: frame.data().clone(),
+    })
+}
+
+// Converts the RTP time of a audio frame to 90khz.
+fn audio_sample_time(
+    start: (i64, UnixH264),
+    elapsed: i64,
+    clock_rate: u32,
+    frame_length: u32,
+) -> Option<(UnixH264, DurationH264)> {
+    let to_h264 = |v: i64| {
+        v.checked_mul(H264_TIMESCALE.into())?
+            .checked_div(clock_rate.into())
+    };
+    let (start_elapsed, start_time) = start;
+    let pts =
+        start_time.checked_add(UnixH264::new(to_h264(elapsed.checked_sub(start_elapsed)?)?))?;
+    let duration = DurationH264::new(to_h264(frame_length.into())?);
+    Some((pts, duration))
+}
+
+fn is_random_access(frame: &VideoFrame, sei_recovery_point: bool) -> bool {
+    frame.is_random_access_point() || (sei_recovery_point && avcc_has_recovery_point(frame.data()))
+}
+
+// Returns the next packet or `ReadTimeout` if none was received within `read_timeout`.
+async fn next_packet<S>(
+    session: &mut S,
+    read_timeout: std::time::Duration,
+) -> Result<Option<S::Item>, SourceRtspRunError>
+where
+    S: futures_lite::Stream + Unpin,
+{
+    timeout(read_timeout, session.next())
+        .await
+        .map_err(|_| SourceRtspRunError::ReadTimeout(read_timeout.as_secs()))
+}
+
+fn std_duration(d: common::time::Duration) -> Result<std::time::Duration, SourceRtspRunError> {
+    match d.as_std() {
+        Some(v) if !v.is_zero() => Ok(v),
+        _ => Err(SourceRtspRunError::InvalidTimeout(d.as_seconds())),
+    }
+}
+
 fn check_clock_drift(pts: UnixH264) -> Result<(), SourceRtspRunError> {
     let now = UnixH264::now();
     let diff = (pts - now).abs();
@@ -493,6 +661,15 @@ enum SourceRtspRunError {
     #[error("end of file")]
     Eof,
 
+    #[error("{0}: connect timeout")]
+    ConnectTimeout(&'static str),
+
+    #[error("no packet received in {0} seconds")]
+    ReadTimeout(u64),
+
+    #[error("invalid timeout: {0} seconds")]
+    InvalidTimeout(i64),
+
     #[error("describe: {0}")]
     Describe(retina::Error),
 
@@ -585,10 +762,53 @@ fn format_streams(streams: &[Stream]) -> String {
         .concat()
 }
 
+// Feed that drops the oldest frames when the subscriber falls behind
+// the source. The source is never blocked, recordings stay complete.
+struct LossyFeed {
+    feed: Feed,
+    stats: ArcMonitorStats,
+    wait_for_keyframe: bool,
+}
+
+impl LossyFeed {
+    fn new(feed: Feed, stats: ArcMonitorStats) -> Self {
+        Self {
+            feed,
+            stats,
+            wait_for_keyframe: false,
+        }
+    }
+
+    // Returns None when the source is closed. Frames are dropped until
+    // the next keyframe after a lag since they may reference dropped frames.
+    async fn recv(&mut self) -> Option<H264Data> {
+        use broadcast::error::RecvError;
+        loop {
+            let frame = match self.feed.recv().await {
+                Ok(v) => v,
+                Err(RecvError::Closed) => return None,
+                Err(RecvError::Lagged(n)) => {
+                    self.stats.add_frames_dropped(n);
+                    self.wait_for_keyframe = true;
+                    continue;
+                }
+            };
+            if self.wait_for_keyframe {
+                if !frame.random_access_present {
+                    self.stats.add_frames_dropped(1);
+                    continue;
+                }
+                self.wait_for_keyframe = false;
+            }
+            return Some(frame);
+        }
+    }
+}
+
 fn new_decoder(
     rt_handle: Handle,
     logger: ArcMsgLogger,
-    mut feed: Feed,
+    mut feed: LossyFeed,
     mut h264_decoder: H264Decoder<Ready>,
     mut frame_rate_limiter: Option<FrameRateLimiter>,
 ) -> FeedDecoded {
@@ -597,17 +817,9 @@ fn new_decoder(
     rt_handle.clone().spawn(async move {
         use DecoderError::*;
         loop {
-            use broadcast::error::RecvError;
-            let frame = match feed.recv().await {
-                Ok(v) => v,
-                Err(RecvError::Closed) => {
-                    // Close receiver by dropping sender.
-                    return;
-                }
-                Err(RecvError::Lagged(_)) => {
-                    _ = frame_tx.send(Err(DroppedFrames)).await;
-                    return;
-                }
+            let Some(frame) = feed.recv().await else {
+                // Close receiver by dropping sender.
+                return;
             };
 
             // State juggling to avoid lifetime issue.
@@ -668,3 +880,91 @@ fn new_decoder(
 
     frame_rx
 }
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::monitor::MonitorStats;
+    use std::time::{Duration, Instant};
+
+    fn frame(pts: i64, random_access_present: bool) -> H264Data {
+        H264Data {
+            pts: UnixH264::new(pts),
+            dts_offset: DtsOffset::new(0),
+            avcc: Arc::new(PaddedBytes::new(Vec::new())),
+            random_access_present,
+        }
+    }
+
+    #[test]
+    fn test_audio_sample_time() {
+        let start = (1000, UnixH264::new(5_000_000));
+        let time = |elapsed| audio_sample_time(start, elapsed, 48000, 1024).unwrap();
+
+        let duration = DurationH264::new(1920);
+        assert_eq!((UnixH264::new(5_000_000), duration), time(1000));
+        assert_eq!((UnixH264::new(5_001_920), duration), time(2024));
+        assert_eq!((UnixH264::new(5_090_000), duration), time(49000));
+
+        // Zero clock rate.
+        assert!(audio_sample_time(start, 2024, 0, 1024).is_none());
+    }
+
+    #[test]
+    fn test_std_duration() {
+        let secs = |v| common::time::Duration::from_secs(v);
+        assert_eq!(Duration::from_secs(5), std_duration(secs(5)).unwrap());
+        assert!(matches!(
+            std_duration(secs(0)),
+            Err(SourceRtspRunError::InvalidTimeout(0))
+        ));
+        assert!(std_duration(common::time::Duration::new(-1)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_lossy_feed() {
+        let (feed_tx, feed_rx) = broadcast::channel(8);
+        let stats = Arc::new(MonitorStats::default());
+        let mut feed = LossyFeed::new(feed_rx, stats.clone());
+
+        // The source never blocks and the queue stays bounded.
+        for pts in 0..100 {
+            feed_tx.send(frame(pts, pts % 8 == 0)).unwrap();
+            assert!(feed_tx.len() <= 8);
+        }
+
+        // The 92 oldest frames were dropped by the channel and frames
+        // 92 to 95 are dropped while waiting for the next keyframe.
+        let got = feed.recv().await.unwrap();
+        assert_eq!(UnixH264::new(96), got.pts);
+        assert_eq!(96, stats.frames_dropped());
+
+        for pts in 97..100 {
+            assert_eq!(UnixH264::new(pts), feed.recv().await.unwrap().pts);
+        }
+        assert_eq!(96, stats.frames_dropped());
+
+        drop(feed_tx);
+        assert!(feed.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_next_packet_timeout() {
+        let mut stream = futures_lite::stream::pending::<()>();
+        let start = Instant::now();
+        let result = next_packet(&mut stream, Duration::from_millis(50)).await;
+        assert!(matches!(result, Err(SourceRtspRunError::ReadTimeout(0))));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let mut stream = futures_lite::stream::once(1);
+        assert!(matches!(
+            next_packet(&mut stream, Duration::from_millis(50)).await,
+            Ok(Some(1))
+        ));
+        assert!(matches!(
+            next_packet(&mut stream, Duration::from_millis(50)).await,
+            Ok(None)
+        ));
+    }
+}
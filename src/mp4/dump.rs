@@ -0,0 +1,118 @@
+use crate::{box_type_str, BoxType};
+use std::io::{Read, Seek, SeekFrom, Write};
+use thiserror::Error;
+
+// Crafted files could otherwise nest boxes until the stack overflows.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Error)]
+pub enum DumpError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("box at offset {0}: truncated header")]
+    TruncatedHeader(u64),
+
+    #[error("box '{typ}' at offset {offset}: invalid size {size}")]
+    InvalidSize { typ: String, offset: u64, size: u64 },
+}
+
+// Reads the box headers of a mp4 file and writes the box tree with
+// types, offsets and sizes. The box contents are not parsed.
+pub fn dump<R: Read + Seek>(r: &mut R, out: &mut dyn Write) -> Result<(), DumpError> {
+    let end = r.seek(SeekFrom::End(0))?;
+    dump_boxes(r, out, 0, end, 0)
+}
+
+fn dump_boxes<R: Read + Seek>(
+    r: &mut R,
+    out: &mut dyn Write,
+    start: u64,
+    end: u64,
+    depth: usize,
+) -> Result<(), DumpError> {
+    let mut offset = start;
+    while offset < end {
+        let header = read_header(r, offset, end)?;
+        let box_end = offset + header.size;
+        writeln!(
+            out,
+            "{:indent$}[{}] offset={offset} size={}",
+            "",
+            box_type_str(header.typ),
+            header.size,
+            indent = depth * 2,
+        )?;
+
+        if let Some(skip) = children_offset(header.typ) {
+            let children_start = offset + header.header_size + skip;
+            if depth < MAX_DEPTH && children_start <= box_end {
+                dump_boxes(r, out, children_start, box_end, depth + 1)?;
+            }
+        }
+        offset = box_end;
+    }
+    Ok(())
+}
+
+struct BoxHeader {
+    typ: BoxType,
+    size: u64,
+    header_size: u64,
+}
+
+fn read_header<R: Read + Seek>(r: &mut R, offset: u64, end: u64) -> Result<BoxHeader, DumpError> {
+    use DumpError::*;
+    let remaining = end - offset;
+    if remaining < 8 {
+        return Err(TruncatedHeader(offset));
+    }
+    r.seek(SeekFrom::Start(offset))?;
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    let typ: BoxType = buf[4..].try_into().expect("4 bytes");
+
+    let (size, header_size) = match u32::from_be_bytes(buf[..4].try_into().expect("4 bytes")) {
+        // The box extends to the end of the file or parent.
+        0 => (remaining, 8),
+        // 64 bit size follows the type.
+        1 => {
+            if remaining < 16 {
+                return Err(TruncatedHeader(offset));
+            }
+            r.read_exact(&mut buf)?;
+            (u64::from_be_bytes(buf), 16)
+        }
+        v => (u64::from(v), 8),
+    };
+    if size < header_size || size > remaining {
+        return Err(InvalidSize {
+            typ: box_type_str(typ),
+            offset,
+            size,
+        });
+    }
+    Ok(BoxHeader {
+        typ,
+        size,
+        header_size,
+    })
+}
+
+// Returns the offset of the first child box from the end of the header,
+// or None if the box type is unknown or has no children.
+fn children_offset(typ: BoxType) -> Option<u64> {
+    match &typ {
+        b"moov" | b"trak" | b"mdia" | b"minf" | b"stbl" | b"dinf" | b"edts" | b"mvex" | b"moof"
+        | b"traf" | b"mfra" | b"udta" | b"sinf" | b"schi" => Some(0),
+        // Full box.
+        b"meta" => Some(4),
+        // Full box and entry count.
+        b"stsd" | b"dref" => Some(8),
+        // Visual sample entry.
+        b"avc1" | b"avc3" | b"hvc1" | b"hev1" | b"encv" => Some(78),
+        // Audio sample entry.
+        b"mp4a" => Some(28),
+        _ => None,
+    }
+}
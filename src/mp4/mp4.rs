@@ -1,6 +1,10 @@
 #[cfg(test)]
 mod test;
 
+mod dump;
+
+pub use dump::{dump, DumpError};
+
 use async_trait::async_trait;
 use std::io::Write;
 use thiserror::Error;
@@ -18,6 +22,37 @@ pub trait ImmutableBox {
     // The size must be known before marshaling
     // since the box header contains the size.
     fn size(&self) -> usize;
+
+    // Key fields for debugging and tests, see `Boxes::describe`.
+    fn fields(&self) -> Fields {
+        Fields::new()
+    }
+}
+
+// Box fields by name.
+pub type Fields = serde_json::Map<String, serde_json::Value>;
+
+// Box type as a string, non ascii bytes are escaped.
+fn box_type_str(typ: BoxType) -> String {
+    typ.escape_ascii().to_string()
+}
+
+fn describe(
+    mp4_box: &dyn ImmutableBox,
+    size: usize,
+    children: Vec<serde_json::Value>,
+) -> serde_json::Value {
+    let mut out = Fields::new();
+    out.insert("type".to_owned(), box_type_str(mp4_box.box_type()).into());
+    out.insert("size".to_owned(), size.into());
+    let fields = mp4_box.fields();
+    if !fields.is_empty() {
+        out.insert("fields".to_owned(), fields.into());
+    }
+    if !children.is_empty() {
+        out.insert("children".to_owned(), children.into());
+    }
+    out.into()
 }
 
 pub trait ImmutableBoxSync: ImmutableBox {
@@ -72,6 +107,39 @@ macro_rules! impl_from {
     };
 }
 
+// Implements the box traits for a container box without any fields.
+macro_rules! impl_empty_box {
+    ($x:ident, $typ:ident) => {
+        impl_from!($x);
+
+        impl ImmutableBox for $x {
+            fn box_type(&self) -> BoxType {
+                $typ
+            }
+
+            fn size(&self) -> usize {
+                0
+            }
+        }
+
+        impl ImmutableBoxSync for $x {
+            fn marshal(&self, _: &mut dyn Write) -> Result<(), Mp4Error> {
+                Ok(())
+            }
+        }
+
+        #[async_trait]
+        impl ImmutableBoxAsync for $x {
+            async fn marshal(
+                &self,
+                _: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+            ) -> Result<(), Mp4Error> {
+                Ok(())
+            }
+        }
+    };
+}
+
 #[derive(Debug, Error)]
 pub enum Mp4Error {
     #[error("write: {0}")]
@@ -96,53 +164,54 @@ impl Boxes {
         }
     }
 
+    // Appends children in order.
     #[must_use]
-    pub fn with_child(mut self, child: Boxes) -> Self {
-        self.children.push(child);
+    pub fn with_children<I: IntoIterator<Item = Boxes>>(mut self, children: I) -> Self {
+        self.children.extend(children);
         self
     }
 
     #[must_use]
-    pub fn with_children2(mut self, child1: Boxes, child2: Boxes) -> Self {
-        self.children.extend([child1, child2]);
-        self
+    pub fn with_child(self, child: Boxes) -> Self {
+        self.with_children([child])
     }
+
     #[must_use]
+    pub fn with_children2(self, child1: Boxes, child2: Boxes) -> Self {
+        self.with_children([child1, child2])
+    }
 
-    pub fn with_children3(mut self, child1: Boxes, child2: Boxes, child3: Boxes) -> Self {
-        self.children.extend([child1, child2, child3]);
-        self
+    #[must_use]
+    pub fn with_children3(self, child1: Boxes, child2: Boxes, child3: Boxes) -> Self {
+        self.with_children([child1, child2, child3])
     }
 
     #[must_use]
     pub fn with_children4(
-        mut self,
+        self,
         child1: Boxes,
         child2: Boxes,
         child3: Boxes,
         child4: Boxes,
     ) -> Self {
-        self.children.extend([child1, child2, child3, child4]);
-        self
+        self.with_children([child1, child2, child3, child4])
     }
 
     #[must_use]
     pub fn with_children5(
-        mut self,
+        self,
         child1: Boxes,
         child2: Boxes,
         child3: Boxes,
         child4: Boxes,
         child5: Boxes,
     ) -> Self {
-        self.children
-            .extend([child1, child2, child3, child4, child5]);
-        self
+        self.with_children([child1, child2, child3, child4, child5])
     }
 
     #[must_use]
     pub fn with_children6(
-        mut self,
+        self,
         child1: Boxes,
         child2: Boxes,
         child3: Boxes,
@@ -150,15 +219,13 @@ impl Boxes {
         child5: Boxes,
         child6: Boxes,
     ) -> Self {
-        self.children
-            .extend([child1, child2, child3, child4, child5, child6]);
-        self
+        self.with_children([child1, child2, child3, child4, child5, child6])
     }
 
     #[must_use]
     #[allow(clippy::too_many_arguments)]
     pub fn with_children7(
-        mut self,
+        self,
         child1: Boxes,
         child2: Boxes,
         child3: Boxes,
@@ -167,9 +234,7 @@ impl Boxes {
         child6: Boxes,
         child7: Boxes,
     ) -> Self {
-        self.children
-            .extend([child1, child2, child3, child4, child5, child6, child7]);
-        self
+        self.with_children([child1, child2, child3, child4, child5, child6, child7])
     }
 
     // Size returns the total size of the box including children.
@@ -185,6 +250,14 @@ impl Boxes {
         total
     }
 
+    // Returns a JSON description of the tree with the
+    // type, size and key fields of every box.
+    #[must_use]
+    pub fn describe(&self) -> serde_json::Value {
+        let children = self.children.iter().map(Self::describe).collect();
+        describe(self.mp4_box.as_ref(), self.size(), children)
+    }
+
     // Marshal box including children.
     pub fn marshal<W: Write>(&self, w: &mut W) -> Result<(), Mp4Error> {
         let size = self.size();
@@ -218,58 +291,59 @@ impl BoxesAsync {
         }
     }
 
+    // Appends children in order.
     #[must_use]
-    pub fn with_child(mut self, child: BoxesAsync) -> Self {
-        self.children.push(child);
+    pub fn with_children<I: IntoIterator<Item = BoxesAsync>>(mut self, children: I) -> Self {
+        self.children.extend(children);
         self
     }
 
     #[must_use]
-    pub fn with_children2(mut self, child1: BoxesAsync, child2: BoxesAsync) -> Self {
-        self.children.extend([child1, child2]);
-        self
+    pub fn with_child(self, child: BoxesAsync) -> Self {
+        self.with_children([child])
     }
+
     #[must_use]
+    pub fn with_children2(self, child1: BoxesAsync, child2: BoxesAsync) -> Self {
+        self.with_children([child1, child2])
+    }
 
+    #[must_use]
     pub fn with_children3(
-        mut self,
+        self,
         child1: BoxesAsync,
         child2: BoxesAsync,
         child3: BoxesAsync,
     ) -> Self {
-        self.children.extend([child1, child2, child3]);
-        self
+        self.with_children([child1, child2, child3])
     }
 
     #[must_use]
     pub fn with_children4(
-        mut self,
+        self,
         child1: BoxesAsync,
         child2: BoxesAsync,
         child3: BoxesAsync,
         child4: BoxesAsync,
     ) -> Self {
-        self.children.extend([child1, child2, child3, child4]);
-        self
+        self.with_children([child1, child2, child3, child4])
     }
 
     #[must_use]
     pub fn with_children5(
-        mut self,
+        self,
         child1: BoxesAsync,
         child2: BoxesAsync,
         child3: BoxesAsync,
         child4: BoxesAsync,
         child5: BoxesAsync,
     ) -> Self {
-        self.children
-            .extend([child1, child2, child3, child4, child5]);
-        self
+        self.with_children([child1, child2, child3, child4, child5])
     }
 
     #[must_use]
     pub fn with_children6(
-        mut self,
+        self,
         child1: BoxesAsync,
         child2: BoxesAsync,
         child3: BoxesAsync,
@@ -277,15 +351,13 @@ impl BoxesAsync {
         child5: BoxesAsync,
         child6: BoxesAsync,
     ) -> Self {
-        self.children
-            .extend([child1, child2, child3, child4, child5, child6]);
-        self
+        self.with_children([child1, child2, child3, child4, child5, child6])
     }
 
     #[must_use]
     #[allow(clippy::too_many_arguments)]
     pub fn with_children7(
-        mut self,
+        self,
         child1: BoxesAsync,
         child2: BoxesAsync,
         child3: BoxesAsync,
@@ -294,9 +366,7 @@ impl BoxesAsync {
         child6: BoxesAsync,
         child7: BoxesAsync,
     ) -> Self {
-        self.children
-            .extend([child1, child2, child3, child4, child5, child6, child7]);
-        self
+        self.with_children([child1, child2, child3, child4, child5, child6, child7])
     }
 
     // Size returns the total size of the box including children.
@@ -312,6 +382,14 @@ impl BoxesAsync {
         total
     }
 
+    // Returns a JSON description of the tree with the
+    // type, size and key fields of every box.
+    #[must_use]
+    pub fn describe(&self) -> serde_json::Value {
+        let children = self.children.iter().map(Self::describe).collect();
+        describe(self.mp4_box.as_ref(), self.size(), children)
+    }
+
     // Marshal box including children.
     pub async fn marshal(
         &self,
@@ -624,33 +702,7 @@ impl ImmutableBoxAsync for Ctts {
 pub const TYPE_DINF: BoxType = *b"dinf";
 
 pub struct Dinf;
-impl_from!(Dinf);
-
-impl ImmutableBox for Dinf {
-    fn box_type(&self) -> BoxType {
-        TYPE_DINF
-    }
-
-    fn size(&self) -> usize {
-        0
-    }
-}
-
-impl ImmutableBoxSync for Dinf {
-    fn marshal(&self, _: &mut dyn Write) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
-
-#[async_trait]
-impl ImmutableBoxAsync for Dinf {
-    async fn marshal(
-        &self,
-        _: &mut (dyn AsyncWrite + Unpin + Send + Sync),
-    ) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
+impl_empty_box!(Dinf, TYPE_DINF);
 
 /*************************** dref ****************************/
 
@@ -748,33 +800,7 @@ impl ImmutableBoxAsync for Url {
 pub const TYPE_EDTS: BoxType = *b"edts";
 
 pub struct Edts;
-impl_from!(Edts);
-
-impl ImmutableBox for Edts {
-    fn box_type(&self) -> BoxType {
-        TYPE_EDTS
-    }
-
-    fn size(&self) -> usize {
-        0
-    }
-}
-
-impl ImmutableBoxSync for Edts {
-    fn marshal(&self, _: &mut dyn Write) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
-
-#[async_trait]
-impl ImmutableBoxAsync for Edts {
-    async fn marshal(
-        &self,
-        _: &mut (dyn AsyncWrite + Unpin + Send + Sync),
-    ) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
+impl_empty_box!(Edts, TYPE_EDTS);
 
 /*************************** elst ****************************/
 
@@ -950,6 +976,24 @@ impl ImmutableBox for Ftyp {
     fn size(&self) -> usize {
         8 + self.compatible_brands.len() * 4
     }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([
+            (
+                "major_brand".to_owned(),
+                box_type_str(self.major_brand).into(),
+            ),
+            ("minor_version".to_owned(), self.minor_version.into()),
+            (
+                "compatible_brands".to_owned(),
+                self.compatible_brands
+                    .iter()
+                    .map(|v| box_type_str(v.0))
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+        ])
+    }
 }
 
 impl ImmutableBoxSync for Ftyp {
@@ -1003,6 +1047,16 @@ impl ImmutableBox for Hdlr {
     fn size(&self) -> usize {
         25 + self.name.len()
     }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([
+            (
+                "handler_type".to_owned(),
+                box_type_str(self.handler_type).into(),
+            ),
+            ("name".to_owned(), self.name.clone().into()),
+        ])
+    }
 }
 
 impl ImmutableBoxSync for Hdlr {
@@ -1075,33 +1129,7 @@ impl ImmutableBoxAsync for Mdat {
 pub const TYPE_MDIA: BoxType = *b"mdia";
 
 pub struct Mdia;
-impl_from!(Mdia);
-
-impl ImmutableBox for Mdia {
-    fn box_type(&self) -> BoxType {
-        TYPE_MDIA
-    }
-
-    fn size(&self) -> usize {
-        0
-    }
-}
-
-impl ImmutableBoxSync for Mdia {
-    fn marshal(&self, _: &mut dyn Write) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
-
-#[async_trait]
-impl ImmutableBoxAsync for Mdia {
-    async fn marshal(
-        &self,
-        _: &mut (dyn AsyncWrite + Unpin + Send + Sync),
-    ) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
+impl_empty_box!(Mdia, TYPE_MDIA);
 
 /*************************** mdhd ****************************/
 
@@ -1154,6 +1182,21 @@ impl ImmutableBox for Mdhd {
             MdhdVersion::V1(_) => 36,
         }
     }
+
+    fn fields(&self) -> Fields {
+        let duration = match &self.version {
+            MdhdVersion::V0(v) => u64::from(v.duration),
+            MdhdVersion::V1(v) => v.duration,
+        };
+        Fields::from_iter([
+            ("timescale".to_owned(), self.timescale.into()),
+            ("duration".to_owned(), duration.into()),
+            (
+                "language".to_owned(),
+                String::from_utf8_lossy(&self.language).into_owned().into(),
+            ),
+        ])
+    }
 }
 
 impl ImmutableBoxSync for Mdhd {
@@ -1255,6 +1298,10 @@ impl ImmutableBox for Mfhd {
     fn size(&self) -> usize {
         8
     }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([("sequence_number".to_owned(), self.sequence_number.into())])
+    }
 }
 
 impl ImmutableBoxSync for Mfhd {
@@ -1282,132 +1329,28 @@ impl ImmutableBoxAsync for Mfhd {
 pub const TYPE_MINF: BoxType = *b"minf";
 
 pub struct Minf;
-impl_from!(Minf);
-
-impl ImmutableBox for Minf {
-    fn box_type(&self) -> BoxType {
-        TYPE_MINF
-    }
-
-    fn size(&self) -> usize {
-        0
-    }
-}
-
-impl ImmutableBoxSync for Minf {
-    fn marshal(&self, _: &mut dyn Write) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
-
-#[async_trait]
-impl ImmutableBoxAsync for Minf {
-    async fn marshal(
-        &self,
-        _: &mut (dyn AsyncWrite + Unpin + Send + Sync),
-    ) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
+impl_empty_box!(Minf, TYPE_MINF);
 
 /*************************** moof ****************************/
 
 pub const TYPE_MOOF: BoxType = *b"moof";
 
 pub struct Moof;
-impl_from!(Moof);
-
-impl ImmutableBox for Moof {
-    fn box_type(&self) -> BoxType {
-        TYPE_MOOF
-    }
-
-    fn size(&self) -> usize {
-        0
-    }
-}
-
-impl ImmutableBoxSync for Moof {
-    fn marshal(&self, _: &mut dyn Write) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
-
-#[async_trait]
-impl ImmutableBoxAsync for Moof {
-    async fn marshal(
-        &self,
-        _: &mut (dyn AsyncWrite + Unpin + Send + Sync),
-    ) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
+impl_empty_box!(Moof, TYPE_MOOF);
 
 /*************************** moov ****************************/
 
 pub const TYPE_MOOV: BoxType = *b"moov";
 
 pub struct Moov;
-impl_from!(Moov);
-
-impl ImmutableBox for Moov {
-    fn box_type(&self) -> BoxType {
-        TYPE_MOOV
-    }
-
-    fn size(&self) -> usize {
-        0
-    }
-}
-
-impl ImmutableBoxSync for Moov {
-    fn marshal(&self, _: &mut dyn Write) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
-
-#[async_trait]
-impl ImmutableBoxAsync for Moov {
-    async fn marshal(
-        &self,
-        _: &mut (dyn AsyncWrite + Unpin + Send + Sync),
-    ) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
+impl_empty_box!(Moov, TYPE_MOOV);
 
 /*************************** mvex ****************************/
 
 pub const TYPE_MVEX: BoxType = *b"mvex";
 
 pub struct Mvex;
-impl_from!(Mvex);
-
-impl ImmutableBox for Mvex {
-    fn box_type(&self) -> BoxType {
-        TYPE_MVEX
-    }
-
-    fn size(&self) -> usize {
-        0
-    }
-}
-
-impl ImmutableBoxSync for Mvex {
-    fn marshal(&self, _: &mut dyn Write) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
-
-#[async_trait]
-impl ImmutableBoxAsync for Mvex {
-    async fn marshal(
-        &self,
-        _: &mut (dyn AsyncWrite + Unpin + Send + Sync),
-    ) -> Result<(), Mp4Error> {
-        Ok(())
-    }
-}
+impl_empty_box!(Mvex, TYPE_MVEX);
 
 /*************************** mvhd ****************************/
 
@@ -1463,6 +1406,18 @@ impl ImmutableBox for Mvhd {
             MvhdVersion::V1(_) => 112,
         }
     }
+
+    fn fields(&self) -> Fields {
+        let duration = match &self.version {
+            MvhdVersion::V0(v) => u64::from(v.duration),
+            MvhdVersion::V1(v) => v.duration,
+        };
+        Fields::from_iter([
+            ("timescale".to_owned(), self.timescale.into()),
+            ("duration".to_owned(), duration.into()),
+            ("next_track_id".to_owned(), self.next_track_id.into()),
+        ])
+    }
 }
 
 impl ImmutableBoxSync for Mvhd {
@@ -1551,6 +1506,96 @@ impl ImmutableBoxAsync for Mvhd {
     }
 }
 
+/*************************** pssh ****************************/
+
+pub const TYPE_PSSH: BoxType = *b"pssh";
+
+// Protection system specific header. The key ids are only marshaled if the
+// version is greater than zero.
+#[derive(Default)]
+pub struct Pssh {
+    pub full_box: FullBox,
+    pub system_id: [u8; 16],
+    pub kids: Vec<[u8; 16]>,
+    pub data: Vec<u8>,
+}
+impl_from!(Pssh);
+
+impl ImmutableBox for Pssh {
+    fn box_type(&self) -> BoxType {
+        TYPE_PSSH
+    }
+
+    fn size(&self) -> usize {
+        let mut total = 4 + 16 + 4 + self.data.len();
+        if self.full_box.version > 0 {
+            total += 4 + self.kids.len() * 16;
+        }
+        total
+    }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([
+            ("kid_count".to_owned(), self.kids.len().into()),
+            ("data_size".to_owned(), self.data.len().into()),
+        ])
+    }
+}
+
+impl ImmutableBoxSync for Pssh {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field(w)?;
+        w.write_all(&self.system_id)?;
+        if self.full_box.version > 0 {
+            w.write_all(
+                &u32::try_from(self.kids.len())
+                    .map_err(|e| Mp4Error::FromInt("pssh".to_owned(), e))?
+                    .to_be_bytes(),
+            )?;
+            for kid in &self.kids {
+                w.write_all(kid)?;
+            }
+        }
+        w.write_all(
+            &u32::try_from(self.data.len())
+                .map_err(|e| Mp4Error::FromInt("pssh".to_owned(), e))?
+                .to_be_bytes(),
+        )?;
+        w.write_all(&self.data)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ImmutableBoxAsync for Pssh {
+    async fn marshal(
+        &self,
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+    ) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field2(w).await?;
+        w.write_all(&self.system_id).await?;
+        if self.full_box.version > 0 {
+            w.write_all(
+                &u32::try_from(self.kids.len())
+                    .map_err(|e| Mp4Error::FromInt("pssh".to_owned(), e))?
+                    .to_be_bytes(),
+            )
+            .await?;
+            for kid in &self.kids {
+                w.write_all(kid).await?;
+            }
+        }
+        w.write_all(
+            &u32::try_from(self.data.len())
+                .map_err(|e| Mp4Error::FromInt("pssh".to_owned(), e))?
+                .to_be_bytes(),
+        )
+        .await?;
+        w.write_all(&self.data).await?;
+        Ok(())
+    }
+}
+
 /*********************** SampleEntry *************************/
 
 #[derive(Default)]
@@ -1611,6 +1656,13 @@ impl ImmutableBox for Avc1 {
     fn size(&self) -> usize {
         78
     }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([
+            ("width".to_owned(), self.width.into()),
+            ("height".to_owned(), self.height.into()),
+        ])
+    }
 }
 
 impl ImmutableBoxSync for Avc1 {
@@ -1659,20 +1711,60 @@ impl ImmutableBoxAsync for Avc1 {
     }
 }
 
-/**************** AVCDecoderConfiguration ****************.*/
-pub const AVC_BASELINE_PROFILE: u8 = 66; // 0x42
-pub const AVC_MAIN_PROFILE: u8 = 77; // 0x4d
-pub const AVC_EXTENDED_PROFILE: u8 = 88; // 0x58
-pub const AVC_HIGH_PROFILE: u8 = 100; // 0x64
-pub const AVC_HIGH_10_PROFILE: u8 = 110; // 0x6e
-pub const AVC_HIGH_422_PROFILE: u8 = 122; // 0x7a
+/*********************** encv *************************/
 
-pub struct AvcParameterSet(Vec<u8>);
+pub const TYPE_ENCV: BoxType = *b"encv";
 
-impl AvcParameterSet {
-    fn field_size(&self) -> usize {
-        self.0.len() + 2
-    }
+// Encrypted visual sample entry, the fields are the same as the avc1 box.
+// The original format is stored in the frma box of the sinf child.
+#[derive(Default)]
+pub struct Encv(pub Avc1);
+impl_from!(Encv);
+
+impl ImmutableBox for Encv {
+    fn box_type(&self) -> BoxType {
+        TYPE_ENCV
+    }
+
+    fn size(&self) -> usize {
+        self.0.size()
+    }
+
+    fn fields(&self) -> Fields {
+        self.0.fields()
+    }
+}
+
+impl ImmutableBoxSync for Encv {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        ImmutableBoxSync::marshal(&self.0, w)
+    }
+}
+
+#[async_trait]
+impl ImmutableBoxAsync for Encv {
+    async fn marshal(
+        &self,
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+    ) -> Result<(), Mp4Error> {
+        ImmutableBoxAsync::marshal(&self.0, w).await
+    }
+}
+
+/**************** AVCDecoderConfiguration ****************.*/
+pub const AVC_BASELINE_PROFILE: u8 = 66; // 0x42
+pub const AVC_MAIN_PROFILE: u8 = 77; // 0x4d
+pub const AVC_EXTENDED_PROFILE: u8 = 88; // 0x58
+pub const AVC_HIGH_PROFILE: u8 = 100; // 0x64
+pub const AVC_HIGH_10_PROFILE: u8 = 110; // 0x6e
+pub const AVC_HIGH_422_PROFILE: u8 = 122; // 0x7a
+
+pub struct AvcParameterSet(Vec<u8>);
+
+impl AvcParameterSet {
+    fn field_size(&self) -> usize {
+        self.0.len() + 2
+    }
 
     fn marshal_field(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
         w.write_all(
@@ -1834,39 +1926,819 @@ impl ImmutableBoxAsync for AvcC {
     }
 }
 
-/*************************** stbl ****************************/
+/*********************** mp4a *************************/
 
-pub const TYPE_STBL: BoxType = *b"stbl";
+pub const TYPE_MP4A: BoxType = *b"mp4a";
 
-pub struct Stbl;
-impl_from!(Stbl);
+// AAC audio sample entry, the esds box is a child.
+#[derive(Default)]
+pub struct Mp4a {
+    pub sample_entry: SampleEntry,
+    pub reserved: [u32; 2],
+    pub channel_count: u16,
+    pub sample_size: u16,
+    pub pre_defined: u16,
+    pub reserved2: u16,
+    // 16.16 fixed point.
+    pub sample_rate: u32,
+}
+impl_from!(Mp4a);
+
+impl ImmutableBox for Mp4a {
+    fn box_type(&self) -> BoxType {
+        TYPE_MP4A
+    }
+
+    fn size(&self) -> usize {
+        28
+    }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([
+            ("channel_count".to_owned(), self.channel_count.into()),
+            ("sample_rate".to_owned(), (self.sample_rate >> 16).into()),
+        ])
+    }
+}
+
+impl ImmutableBoxSync for Mp4a {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        self.sample_entry.marshal(w)?;
+        for reserved in &self.reserved {
+            w.write_all(&reserved.to_be_bytes())?;
+        }
+        w.write_all(&self.channel_count.to_be_bytes())?;
+        w.write_all(&self.sample_size.to_be_bytes())?;
+        w.write_all(&self.pre_defined.to_be_bytes())?;
+        w.write_all(&self.reserved2.to_be_bytes())?;
+        w.write_all(&self.sample_rate.to_be_bytes())?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ImmutableBoxAsync for Mp4a {
+    async fn marshal(
+        &self,
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+    ) -> Result<(), Mp4Error> {
+        self.sample_entry.marshal2(w).await?;
+        for reserved in &self.reserved {
+            w.write_all(&reserved.to_be_bytes()).await?;
+        }
+        w.write_all(&self.channel_count.to_be_bytes()).await?;
+        w.write_all(&self.sample_size.to_be_bytes()).await?;
+        w.write_all(&self.pre_defined.to_be_bytes()).await?;
+        w.write_all(&self.reserved2.to_be_bytes()).await?;
+        w.write_all(&self.sample_rate.to_be_bytes()).await?;
+        Ok(())
+    }
+}
+
+/*************************** esds ****************************/
+
+pub const TYPE_ESDS: BoxType = *b"esds";
+
+const ES_DESCRIPTOR_TAG: u8 = 0x03;
+const DECODER_CONFIG_DESCRIPTOR_TAG: u8 = 0x04;
+const DECODER_SPECIFIC_INFO_TAG: u8 = 0x05;
+const SL_CONFIG_DESCRIPTOR_TAG: u8 = 0x06;
+
+// MPEG-4 audio.
+const OBJECT_TYPE_INDICATION_AUDIO: u8 = 0x40;
+// Audio stream, upstream is always 0, reserved is always 1.
+const STREAM_TYPE_AUDIO: u8 = (0x05 << 2) | 1;
+
+// Elementary stream descriptor of a MPEG-4 audio track. The descriptor
+// sizes are encoded in a single byte, the decoder specific info is the
+// AudioSpecificConfig and can be at most 104 bytes.
+#[derive(Default)]
+pub struct Esds {
+    pub full_box: FullBox,
+    pub es_id: u16,
+    pub buffer_size_db: u32, // 24 bits.
+    pub max_bitrate: u32,
+    pub avg_bitrate: u32,
+    pub decoder_specific_info: Vec<u8>,
+}
+impl_from!(Esds);
+
+impl Esds {
+    fn decoder_config_size(&self) -> usize {
+        13 + 2 + self.decoder_specific_info.len()
+    }
+
+    fn es_descriptor_size(&self) -> usize {
+        3 + 2 + self.decoder_config_size() + 3
+    }
+
+    fn encode_descriptors(&self) -> Result<Vec<u8>, Mp4Error> {
+        let descriptor_size = |size: usize| -> Result<u8, Mp4Error> {
+            let size = i8::try_from(size).map_err(|e| Mp4Error::FromInt("esds".to_owned(), e))?;
+            Ok(size.to_be_bytes()[0])
+        };
+        let mut buf = Vec::with_capacity(2 + self.es_descriptor_size());
+
+        buf.push(ES_DESCRIPTOR_TAG);
+        buf.push(descriptor_size(self.es_descriptor_size())?);
+        buf.extend_from_slice(&self.es_id.to_be_bytes());
+        buf.push(0); // Flags.
+
+        buf.push(DECODER_CONFIG_DESCRIPTOR_TAG);
+        buf.push(descriptor_size(self.decoder_config_size())?);
+        buf.push(OBJECT_TYPE_INDICATION_AUDIO);
+        buf.push(STREAM_TYPE_AUDIO);
+        buf.extend_from_slice(&self.buffer_size_db.to_be_bytes()[1..]);
+        buf.extend_from_slice(&self.max_bitrate.to_be_bytes());
+        buf.extend_from_slice(&self.avg_bitrate.to_be_bytes());
+
+        buf.push(DECODER_SPECIFIC_INFO_TAG);
+        buf.push(descriptor_size(self.decoder_specific_info.len())?);
+        buf.extend_from_slice(&self.decoder_specific_info);
+
+        buf.push(SL_CONFIG_DESCRIPTOR_TAG);
+        buf.push(1);
+        buf.push(2); // Predefined, reserved for use in MP4 files.
+        Ok(buf)
+    }
+}
+
+impl ImmutableBox for Esds {
+    fn box_type(&self) -> BoxType {
+        TYPE_ESDS
+    }
+
+    fn size(&self) -> usize {
+        4 + 2 + self.es_descriptor_size()
+    }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([(
+            "decoder_specific_info_size".to_owned(),
+            self.decoder_specific_info.len().into(),
+        )])
+    }
+}
+
+impl ImmutableBoxSync for Esds {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field(w)?;
+        w.write_all(&self.encode_descriptors()?)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ImmutableBoxAsync for Esds {
+    async fn marshal(
+        &self,
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+    ) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field2(w).await?;
+        w.write_all(&self.encode_descriptors()?).await?;
+        Ok(())
+    }
+}
+
+/*************************** saio ****************************/
+
+pub const TYPE_SAIO: BoxType = *b"saio";
+
+pub struct Saio {
+    pub flags: [u8; 3],
+
+    // Only marshaled if the `SAIO_AUX_INFO_TYPE_PRESENT` flag is set.
+    pub aux_info_type: u32,
+    pub aux_info_type_parameter: u32,
+
+    pub offsets: SaioOffsets,
+}
+impl_from!(Saio);
+
+pub const SAIO_AUX_INFO_TYPE_PRESENT: u32 = 0x0000_0001;
+
+pub enum SaioOffsets {
+    V0(Vec<u32>),
+    V1(Vec<u64>),
+}
+
+impl ImmutableBox for Saio {
+    fn box_type(&self) -> BoxType {
+        TYPE_SAIO
+    }
+
+    fn size(&self) -> usize {
+        let mut total = 8;
+        if check_fullbox_flag(self.flags, SAIO_AUX_INFO_TYPE_PRESENT) {
+            total += 8;
+        }
+        total
+            + match &self.offsets {
+                SaioOffsets::V0(v) => v.len() * 4,
+                SaioOffsets::V1(v) => v.len() * 8,
+            }
+    }
+
+    fn fields(&self) -> Fields {
+        let entry_count = match &self.offsets {
+            SaioOffsets::V0(v) => v.len(),
+            SaioOffsets::V1(v) => v.len(),
+        };
+        Fields::from_iter([("entry_count".to_owned(), entry_count.into())])
+    }
+}
+
+impl ImmutableBoxSync for Saio {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        let version = match self.offsets {
+            SaioOffsets::V0(_) => 0,
+            SaioOffsets::V1(_) => 1,
+        };
+        w.write_all(&[version])?;
+        w.write_all(&self.flags)?;
+        if check_fullbox_flag(self.flags, SAIO_AUX_INFO_TYPE_PRESENT) {
+            w.write_all(&self.aux_info_type.to_be_bytes())?;
+            w.write_all(&self.aux_info_type_parameter.to_be_bytes())?;
+        }
+        match &self.offsets {
+            SaioOffsets::V0(offsets) => {
+                w.write_all(
+                    &u32::try_from(offsets.len())
+                        .map_err(|e| Mp4Error::FromInt("saio".to_owned(), e))?
+                        .to_be_bytes(),
+                )?;
+                for offset in offsets {
+                    w.write_all(&offset.to_be_bytes())?;
+                }
+            }
+            SaioOffsets::V1(offsets) => {
+                w.write_all(
+                    &u32::try_from(offsets.len())
+                        .map_err(|e| Mp4Error::FromInt("saio".to_owned(), e))?
+                        .to_be_bytes(),
+                )?;
+                for offset in offsets {
+                    w.write_all(&offset.to_be_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ImmutableBoxAsync for Saio {
+    async fn marshal(
+        &self,
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+    ) -> Result<(), Mp4Error> {
+        let version = match self.offsets {
+            SaioOffsets::V0(_) => 0,
+            SaioOffsets::V1(_) => 1,
+        };
+        w.write_all(&[version]).await?;
+        w.write_all(&self.flags).await?;
+        if check_fullbox_flag(self.flags, SAIO_AUX_INFO_TYPE_PRESENT) {
+            w.write_all(&self.aux_info_type.to_be_bytes()).await?;
+            w.write_all(&self.aux_info_type_parameter.to_be_bytes())
+                .await?;
+        }
+        match &self.offsets {
+            SaioOffsets::V0(offsets) => {
+                w.write_all(
+                    &u32::try_from(offsets.len())
+                        .map_err(|e| Mp4Error::FromInt("saio".to_owned(), e))?
+                        .to_be_bytes(),
+                )
+                .await?;
+                for offset in offsets {
+                    w.write_all(&offset.to_be_bytes()).await?;
+                }
+            }
+            SaioOffsets::V1(offsets) => {
+                w.write_all(
+                    &u32::try_from(offsets.len())
+                        .map_err(|e| Mp4Error::FromInt("saio".to_owned(), e))?
+                        .to_be_bytes(),
+                )
+                .await?;
+                for offset in offsets {
+                    w.write_all(&offset.to_be_bytes()).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/*************************** saiz ****************************/
+
+pub const TYPE_SAIZ: BoxType = *b"saiz";
+
+// The per sample sizes are only marshaled if
+// `default_sample_info_size` is zero.
+#[derive(Default)]
+pub struct Saiz {
+    pub full_box: FullBox,
+
+    // Only marshaled if the `SAIZ_AUX_INFO_TYPE_PRESENT` flag is set.
+    pub aux_info_type: u32,
+    pub aux_info_type_parameter: u32,
+
+    pub default_sample_info_size: u8,
+    pub sample_count: u32,
+    pub sample_info_sizes: Vec<u8>,
+}
+impl_from!(Saiz);
+
+pub const SAIZ_AUX_INFO_TYPE_PRESENT: u32 = 0x0000_0001;
+
+impl ImmutableBox for Saiz {
+    fn box_type(&self) -> BoxType {
+        TYPE_SAIZ
+    }
+
+    fn size(&self) -> usize {
+        let mut total = 9;
+        if self.full_box.check_flag(SAIZ_AUX_INFO_TYPE_PRESENT) {
+            total += 8;
+        }
+        if self.default_sample_info_size == 0 {
+            total += self.sample_info_sizes.len();
+        }
+        total
+    }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([
+            (
+                "default_sample_info_size".to_owned(),
+                self.default_sample_info_size.into(),
+            ),
+            ("sample_count".to_owned(), self.sample_count.into()),
+        ])
+    }
+}
+
+impl ImmutableBoxSync for Saiz {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field(w)?;
+        if self.full_box.check_flag(SAIZ_AUX_INFO_TYPE_PRESENT) {
+            w.write_all(&self.aux_info_type.to_be_bytes())?;
+            w.write_all(&self.aux_info_type_parameter.to_be_bytes())?;
+        }
+        w.write_all(&[self.default_sample_info_size])?;
+        w.write_all(&self.sample_count.to_be_bytes())?;
+        if self.default_sample_info_size == 0 {
+            w.write_all(&self.sample_info_sizes)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ImmutableBoxAsync for Saiz {
+    async fn marshal(
+        &self,
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+    ) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field2(w).await?;
+        if self.full_box.check_flag(SAIZ_AUX_INFO_TYPE_PRESENT) {
+            w.write_all(&self.aux_info_type.to_be_bytes()).await?;
+            w.write_all(&self.aux_info_type_parameter.to_be_bytes())
+                .await?;
+        }
+        w.write_all(&[self.default_sample_info_size]).await?;
+        w.write_all(&self.sample_count.to_be_bytes()).await?;
+        if self.default_sample_info_size == 0 {
+            w.write_all(&self.sample_info_sizes).await?;
+        }
+        Ok(())
+    }
+}
+
+/*************************** senc ****************************/
+
+pub const TYPE_SENC: BoxType = *b"senc";
+
+// Sample encryption box. The size of every initialization vector
+// must match the `default_per_sample_iv_size` in the tenc box.
+#[derive(Default)]
+pub struct Senc {
+    pub full_box: FullBox,
+    pub samples: Vec<SencSample>,
+}
+impl_from!(Senc);
+
+pub const SENC_USE_SUBSAMPLE_ENCRYPTION: u32 = 0x0000_0002;
+
+#[derive(Clone, Default)]
+pub struct SencSample {
+    pub iv: Vec<u8>,
 
-impl ImmutableBox for Stbl {
+    // Only marshaled if the `SENC_USE_SUBSAMPLE_ENCRYPTION` flag is set.
+    pub subsamples: Vec<SencSubsample>,
+}
+
+#[derive(Clone, Copy)]
+pub struct SencSubsample {
+    pub bytes_of_clear_data: u16,
+    pub bytes_of_protected_data: u32,
+}
+
+impl SencSample {
+    fn size(&self, use_subsamples: bool) -> usize {
+        if use_subsamples {
+            self.iv.len() + 2 + self.subsamples.len() * 6
+        } else {
+            self.iv.len()
+        }
+    }
+
+    fn marshal(&self, w: &mut dyn Write, use_subsamples: bool) -> Result<(), Mp4Error> {
+        w.write_all(&self.iv)?;
+        if use_subsamples {
+            w.write_all(
+                &u16::try_from(self.subsamples.len())
+                    .map_err(|e| Mp4Error::FromInt("senc".to_owned(), e))?
+                    .to_be_bytes(),
+            )?;
+            for subsample in &self.subsamples {
+                w.write_all(&subsample.bytes_of_clear_data.to_be_bytes())?;
+                w.write_all(&subsample.bytes_of_protected_data.to_be_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn marshal2(
+        &self,
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+        use_subsamples: bool,
+    ) -> Result<(), Mp4Error> {
+        w.write_all(&self.iv).await?;
+        if use_subsamples {
+            w.write_all(
+                &u16::try_from(self.subsamples.len())
+                    .map_err(|e| Mp4Error::FromInt("senc".to_owned(), e))?
+                    .to_be_bytes(),
+            )
+            .await?;
+            for subsample in &self.subsamples {
+                w.write_all(&subsample.bytes_of_clear_data.to_be_bytes())
+                    .await?;
+                w.write_all(&subsample.bytes_of_protected_data.to_be_bytes())
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ImmutableBox for Senc {
     fn box_type(&self) -> BoxType {
-        TYPE_STBL
+        TYPE_SENC
     }
 
     fn size(&self) -> usize {
-        0
+        let use_subsamples = self.full_box.check_flag(SENC_USE_SUBSAMPLE_ENCRYPTION);
+        8 + self
+            .samples
+            .iter()
+            .map(|v| v.size(use_subsamples))
+            .sum::<usize>()
+    }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([("sample_count".to_owned(), self.samples.len().into())])
     }
 }
 
-impl ImmutableBoxSync for Stbl {
-    fn marshal(&self, _: &mut dyn Write) -> Result<(), Mp4Error> {
+impl ImmutableBoxSync for Senc {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        let use_subsamples = self.full_box.check_flag(SENC_USE_SUBSAMPLE_ENCRYPTION);
+        self.full_box.marshal_field(w)?;
+        w.write_all(
+            &u32::try_from(self.samples.len())
+                .map_err(|e| Mp4Error::FromInt("senc".to_owned(), e))?
+                .to_be_bytes(),
+        )?;
+        for sample in &self.samples {
+            sample.marshal(w, use_subsamples)?;
+        }
         Ok(())
     }
 }
 
 #[async_trait]
-impl ImmutableBoxAsync for Stbl {
+impl ImmutableBoxAsync for Senc {
     async fn marshal(
         &self,
-        _: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
     ) -> Result<(), Mp4Error> {
+        let use_subsamples = self.full_box.check_flag(SENC_USE_SUBSAMPLE_ENCRYPTION);
+        self.full_box.marshal_field2(w).await?;
+        w.write_all(
+            &u32::try_from(self.samples.len())
+                .map_err(|e| Mp4Error::FromInt("senc".to_owned(), e))?
+                .to_be_bytes(),
+        )
+        .await?;
+        for sample in &self.samples {
+            sample.marshal2(w, use_subsamples).await?;
+        }
+        Ok(())
+    }
+}
+
+/*************************** sinf ****************************/
+
+pub const TYPE_SINF: BoxType = *b"sinf";
+
+// Protection scheme information, child of encrypted sample entries.
+pub struct Sinf;
+impl_empty_box!(Sinf, TYPE_SINF);
+
+/*************************** frma ****************************/
+
+pub const TYPE_FRMA: BoxType = *b"frma";
+
+// Original format of a encrypted sample entry.
+pub struct Frma {
+    pub data_format: BoxType,
+}
+impl_from!(Frma);
+
+impl ImmutableBox for Frma {
+    fn box_type(&self) -> BoxType {
+        TYPE_FRMA
+    }
+
+    fn size(&self) -> usize {
+        4
+    }
+}
+
+impl ImmutableBoxSync for Frma {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        w.write_all(&self.data_format)?;
         Ok(())
     }
 }
 
+#[async_trait]
+impl ImmutableBoxAsync for Frma {
+    async fn marshal(
+        &self,
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+    ) -> Result<(), Mp4Error> {
+        w.write_all(&self.data_format).await?;
+        Ok(())
+    }
+}
+
+/*************************** schm ****************************/
+
+pub const TYPE_SCHM: BoxType = *b"schm";
+
+pub const SCHEME_TYPE_CENC: [u8; 4] = *b"cenc";
+pub const SCHEME_TYPE_CBCS: [u8; 4] = *b"cbcs";
+
+// Scheme type. The scheme uri is not supported.
+#[derive(Default)]
+pub struct Schm {
+    pub full_box: FullBox,
+    pub scheme_type: [u8; 4],
+    pub scheme_version: u32,
+}
+impl_from!(Schm);
+
+impl ImmutableBox for Schm {
+    fn box_type(&self) -> BoxType {
+        TYPE_SCHM
+    }
+
+    fn size(&self) -> usize {
+        12
+    }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([("scheme_version".to_owned(), self.scheme_version.into())])
+    }
+}
+
+impl ImmutableBoxSync for Schm {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field(w)?;
+        w.write_all(&self.scheme_type)?;
+        w.write_all(&self.scheme_version.to_be_bytes())?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ImmutableBoxAsync for Schm {
+    async fn marshal(
+        &self,
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+    ) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field2(w).await?;
+        w.write_all(&self.scheme_type).await?;
+        w.write_all(&self.scheme_version.to_be_bytes()).await?;
+        Ok(())
+    }
+}
+
+/*************************** schi ****************************/
+
+pub const TYPE_SCHI: BoxType = *b"schi";
+
+pub struct Schi;
+impl_empty_box!(Schi, TYPE_SCHI);
+
+/*************************** sidx ****************************/
+
+pub const TYPE_SIDX: BoxType = *b"sidx";
+
+// Segment index, the byte ranges and durations of the subsegments
+// that follow the box. Players use it to seek without parsing every moof.
+pub struct Sidx {
+    pub flags: [u8; 3],
+    pub reference_id: u32,
+    pub timescale: u32,
+    pub times: SidxTimes,
+    pub references: Vec<SidxReference>,
+}
+impl_from!(Sidx);
+
+pub enum SidxTimes {
+    V0 {
+        earliest_presentation_time: u32,
+        first_offset: u32,
+    },
+    V1 {
+        earliest_presentation_time: u64,
+        first_offset: u64,
+    },
+}
+
+#[derive(Clone, Default)]
+pub struct SidxReference {
+    pub reference_type: bool,
+    // 31 bits.
+    pub referenced_size: u32,
+    pub subsegment_duration: u32,
+    pub starts_with_sap: bool,
+    // 3 bits.
+    pub sap_type: u8,
+    // 28 bits.
+    pub sap_delta_time: u32,
+}
+
+impl SidxReference {
+    fn encode(&self) -> [u8; 12] {
+        let size = (u32::from(self.reference_type) << 31) | (self.referenced_size & 0x7fff_ffff);
+        let sap = (u32::from(self.starts_with_sap) << 31)
+            | (u32::from(self.sap_type & 0x7) << 28)
+            | (self.sap_delta_time & 0x0fff_ffff);
+        let mut buf = [0; 12];
+        buf[..4].copy_from_slice(&size.to_be_bytes());
+        buf[4..8].copy_from_slice(&self.subsegment_duration.to_be_bytes());
+        buf[8..].copy_from_slice(&sap.to_be_bytes());
+        buf
+    }
+}
+
+impl Sidx {
+    // Everything before the references.
+    fn encode_header(&self) -> Result<Vec<u8>, Mp4Error> {
+        let mut buf = Vec::with_capacity(32);
+        match self.times {
+            SidxTimes::V0 { .. } => buf.push(0),
+            SidxTimes::V1 { .. } => buf.push(1),
+        }
+        buf.extend_from_slice(&self.flags);
+        buf.extend_from_slice(&self.reference_id.to_be_bytes());
+        buf.extend_from_slice(&self.timescale.to_be_bytes());
+        match self.times {
+            SidxTimes::V0 {
+                earliest_presentation_time,
+                first_offset,
+            } => {
+                buf.extend_from_slice(&earliest_presentation_time.to_be_bytes());
+                buf.extend_from_slice(&first_offset.to_be_bytes());
+            }
+            SidxTimes::V1 {
+                earliest_presentation_time,
+                first_offset,
+            } => {
+                buf.extend_from_slice(&earliest_presentation_time.to_be_bytes());
+                buf.extend_from_slice(&first_offset.to_be_bytes());
+            }
+        }
+        buf.extend_from_slice(&[0, 0]); // Reserved.
+        buf.extend_from_slice(
+            &u16::try_from(self.references.len())
+                .map_err(|e| Mp4Error::FromInt("sidx".to_owned(), e))?
+                .to_be_bytes(),
+        ); // Reference count.
+        Ok(buf)
+    }
+}
+
+impl ImmutableBox for Sidx {
+    fn box_type(&self) -> BoxType {
+        TYPE_SIDX
+    }
+
+    fn size(&self) -> usize {
+        let times_size = match self.times {
+            SidxTimes::V0 { .. } => 8,
+            SidxTimes::V1 { .. } => 16,
+        };
+        16 + times_size + self.references.len() * 12
+    }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([("reference_count".to_owned(), self.references.len().into())])
+    }
+}
+
+impl ImmutableBoxSync for Sidx {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        w.write_all(&self.encode_header()?)?;
+        for reference in &self.references {
+            w.write_all(&reference.encode())?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ImmutableBoxAsync for Sidx {
+    async fn marshal(
+        &self,
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+    ) -> Result<(), Mp4Error> {
+        w.write_all(&self.encode_header()?).await?;
+        for reference in &self.references {
+            w.write_all(&reference.encode()).await?;
+        }
+        Ok(())
+    }
+}
+
+/*************************** smhd ****************************/
+
+pub const TYPE_SMHD: BoxType = *b"smhd";
+
+#[derive(Default)]
+pub struct Smhd {
+    pub full_box: FullBox,
+    pub balance: i16, // template=0
+    pub reserved: u16,
+}
+impl_from!(Smhd);
+
+impl ImmutableBox for Smhd {
+    fn box_type(&self) -> BoxType {
+        TYPE_SMHD
+    }
+
+    fn size(&self) -> usize {
+        8
+    }
+}
+
+impl ImmutableBoxSync for Smhd {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field(w)?;
+        w.write_all(&self.balance.to_be_bytes())?;
+        w.write_all(&self.reserved.to_be_bytes())?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ImmutableBoxAsync for Smhd {
+    async fn marshal(
+        &self,
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+    ) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field2(w).await?;
+        w.write_all(&self.balance.to_be_bytes()).await?;
+        w.write_all(&self.reserved.to_be_bytes()).await?;
+        Ok(())
+    }
+}
+
+/*************************** stbl ****************************/
+
+pub const TYPE_STBL: BoxType = *b"stbl";
+
+pub struct Stbl;
+impl_empty_box!(Stbl, TYPE_STBL);
+
 /*************************** stco ****************************/
 
 pub const TYPE_STCO: BoxType = *b"stco";
@@ -1886,6 +2758,10 @@ impl ImmutableBox for Stco {
     fn size(&self) -> usize {
         8 + (self.chunk_offsets.len()) * 4
     }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([("entry_count".to_owned(), self.chunk_offsets.len().into())])
+    }
 }
 
 impl ImmutableBoxSync for Stco {
@@ -1968,6 +2844,10 @@ impl ImmutableBox for Stsc {
     fn size(&self) -> usize {
         8 + self.entries.len() * 12
     }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([("entry_count".to_owned(), self.entries.len().into())])
+    }
 }
 
 impl ImmutableBoxSync for Stsc {
@@ -2023,6 +2903,10 @@ impl ImmutableBox for Stsd {
     fn size(&self) -> usize {
         8
     }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([("entry_count".to_owned(), self.entry_count.into())])
+    }
 }
 
 impl ImmutableBoxSync for Stsd {
@@ -2063,6 +2947,10 @@ impl ImmutableBox for Stss {
     fn size(&self) -> usize {
         8 + self.sample_numbers.len() * 4
     }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([("entry_count".to_owned(), self.sample_numbers.len().into())])
+    }
 }
 
 impl ImmutableBoxSync for Stss {
@@ -2121,6 +3009,13 @@ impl ImmutableBox for Stsz {
     fn size(&self) -> usize {
         12 + self.entry_sizes.len() * 4
     }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([
+            ("sample_size".to_owned(), self.sample_size.into()),
+            ("sample_count".to_owned(), self.sample_count.into()),
+        ])
+    }
 }
 
 impl ImmutableBoxSync for Stsz {
@@ -2192,6 +3087,10 @@ impl ImmutableBox for Stts {
     fn size(&self) -> usize {
         8 + self.entries.len() * 8
     }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([("entry_count".to_owned(), self.entries.len().into())])
+    }
 }
 
 impl ImmutableBoxSync for Stts {
@@ -2255,6 +3154,17 @@ impl ImmutableBox for Tfdt {
             TfdtBaseMediaDecodeTime::V1(_) => 12,
         }
     }
+
+    fn fields(&self) -> Fields {
+        let base_media_decode_time = match self.base_media_decode_time {
+            TfdtBaseMediaDecodeTime::V0(v) => u64::from(v),
+            TfdtBaseMediaDecodeTime::V1(v) => v,
+        };
+        Fields::from_iter([(
+            "base_media_decode_time".to_owned(),
+            base_media_decode_time.into(),
+        )])
+    }
 }
 
 impl ImmutableBoxSync for Tfdt {
@@ -2351,6 +3261,10 @@ impl ImmutableBox for Tfhd {
         }
         total
     }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([("track_id".to_owned(), self.track_id.into())])
+    }
 }
 
 impl ImmutableBoxSync for Tfhd {
@@ -2475,6 +3389,20 @@ impl ImmutableBox for Tkhd {
             TkhdVersion::V1(_) => 96,
         }
     }
+
+    fn fields(&self) -> Fields {
+        let duration = match &self.version {
+            TkhdVersion::V0(v) => u64::from(v.duration),
+            TkhdVersion::V1(v) => v.duration,
+        };
+        // Width and height are fixed-point 16.16.
+        Fields::from_iter([
+            ("track_id".to_owned(), self.track_id.into()),
+            ("duration".to_owned(), duration.into()),
+            ("width".to_owned(), (self.width >> 16).into()),
+            ("height".to_owned(), (self.height >> 16).into()),
+        ])
+    }
 }
 
 impl ImmutableBoxSync for Tkhd {
@@ -2561,72 +3489,119 @@ impl ImmutableBoxAsync for Tkhd {
     }
 }
 
-/*************************** traf ****************************/
-
-pub const TYPE_TRAF: BoxType = *b"traf";
-
-pub struct Traf;
-impl_from!(Traf);
+/*************************** tenc ****************************/
 
-impl ImmutableBox for Traf {
-    fn box_type(&self) -> BoxType {
-        TYPE_TRAF
-    }
+pub const TYPE_TENC: BoxType = *b"tenc";
 
-    fn size(&self) -> usize {
-        0
-    }
+// Track encryption box. The crypt and skip byte blocks are only marshaled
+// if the version is greater than zero. The constant initialization vector
+// is only marshaled if the track is protected without per sample IVs.
+#[derive(Default)]
+pub struct Tenc {
+    pub full_box: FullBox,
+    pub default_crypt_byte_block: u8,
+    pub default_skip_byte_block: u8,
+    pub default_is_protected: u8,
+    pub default_per_sample_iv_size: u8,
+    pub default_kid: [u8; 16],
+    pub default_constant_iv: Vec<u8>,
 }
+impl_from!(Tenc);
 
-impl ImmutableBoxSync for Traf {
-    fn marshal(&self, _: &mut dyn Write) -> Result<(), Mp4Error> {
-        Ok(())
+impl Tenc {
+    fn has_constant_iv(&self) -> bool {
+        self.default_is_protected == 1 && self.default_per_sample_iv_size == 0
     }
-}
 
-#[async_trait]
-impl ImmutableBoxAsync for Traf {
-    async fn marshal(
-        &self,
-        _: &mut (dyn AsyncWrite + Unpin + Send + Sync),
-    ) -> Result<(), Mp4Error> {
-        Ok(())
+    fn pattern_byte(&self) -> u8 {
+        if self.full_box.version == 0 {
+            0
+        } else {
+            (self.default_crypt_byte_block << 4) | (self.default_skip_byte_block & 0x0f)
+        }
     }
 }
 
-/*************************** trak ****************************/
-
-pub const TYPE_TRAK: BoxType = *b"trak";
-
-pub struct Trak;
-impl_from!(Trak);
-
-impl ImmutableBox for Trak {
+impl ImmutableBox for Tenc {
     fn box_type(&self) -> BoxType {
-        TYPE_TRAK
+        TYPE_TENC
     }
 
     fn size(&self) -> usize {
-        0
+        let mut total = 24;
+        if self.has_constant_iv() {
+            total += 1 + self.default_constant_iv.len();
+        }
+        total
+    }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([
+            (
+                "default_is_protected".to_owned(),
+                self.default_is_protected.into(),
+            ),
+            (
+                "default_per_sample_iv_size".to_owned(),
+                self.default_per_sample_iv_size.into(),
+            ),
+        ])
     }
 }
 
-impl ImmutableBoxSync for Trak {
-    fn marshal(&self, _: &mut dyn Write) -> Result<(), Mp4Error> {
+impl ImmutableBoxSync for Tenc {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field(w)?;
+        w.write_all(&[0])?; // Reserved.
+        w.write_all(&[self.pattern_byte()])?;
+        w.write_all(&[self.default_is_protected])?;
+        w.write_all(&[self.default_per_sample_iv_size])?;
+        w.write_all(&self.default_kid)?;
+        if self.has_constant_iv() {
+            w.write_all(&[u8::try_from(self.default_constant_iv.len())
+                .map_err(|e| Mp4Error::FromInt("tenc".to_owned(), e))?])?;
+            w.write_all(&self.default_constant_iv)?;
+        }
         Ok(())
     }
 }
 
 #[async_trait]
-impl ImmutableBoxAsync for Trak {
+impl ImmutableBoxAsync for Tenc {
     async fn marshal(
         &self,
-        _: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
     ) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field2(w).await?;
+        w.write_all(&[0]).await?; // Reserved.
+        w.write_all(&[self.pattern_byte()]).await?;
+        w.write_all(&[self.default_is_protected]).await?;
+        w.write_all(&[self.default_per_sample_iv_size]).await?;
+        w.write_all(&self.default_kid).await?;
+        if self.has_constant_iv() {
+            w.write_all(&[u8::try_from(self.default_constant_iv.len())
+                .map_err(|e| Mp4Error::FromInt("tenc".to_owned(), e))?])
+                .await?;
+            w.write_all(&self.default_constant_iv).await?;
+        }
         Ok(())
     }
 }
 
+/*************************** traf ****************************/
+
+pub const TYPE_TRAF: BoxType = *b"traf";
+
+pub struct Traf;
+impl_empty_box!(Traf, TYPE_TRAF);
+
+/*************************** trak ****************************/
+
+pub const TYPE_TRAK: BoxType = *b"trak";
+
+pub struct Trak;
+impl_empty_box!(Trak, TYPE_TRAK);
+
 /*************************** trex ****************************/
 
 pub const TYPE_TREX: BoxType = *b"trex";
@@ -2650,6 +3625,10 @@ impl ImmutableBox for Trex {
     fn size(&self) -> usize {
         24
     }
+
+    fn fields(&self) -> Fields {
+        Fields::from_iter([("track_id".to_owned(), self.track_id.into())])
+    }
 }
 
 impl ImmutableBoxSync for Trex {
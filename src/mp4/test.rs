@@ -161,6 +161,32 @@ use test_case::test_case;
             0x00, 0x00, 0x01, // flags
         ]; "url"
     )]
+#[test_case(
+        Esds{
+            full_box: FullBox::default(),
+            es_id: 0x0123,
+            buffer_size_db: 0x0012_3456,
+            max_bitrate: 0x1234_5678,
+            avg_bitrate: 0x2345_6789,
+            decoder_specific_info: vec![0x12, 0x10],
+        },
+        &[
+            0,                // version
+            0x00, 0x00, 0x00, // flags
+            0x03, 0x19, // es descriptor tag, size
+            0x01, 0x23, // es id
+            0x00, // flags
+            0x04, 0x11, // decoder config descriptor tag, size
+            0x40, // object type indication
+            0x15, // stream type
+            0x12, 0x34, 0x56, // buffer size db
+            0x12, 0x34, 0x56, 0x78, // max bitrate
+            0x23, 0x45, 0x67, 0x89, // avg bitrate
+            0x05, 0x02, // decoder specific info tag, size
+            0x12, 0x10, // decoder specific info
+            0x06, 0x01, 0x02, // sl config descriptor
+        ]; "esds"
+    )]
 #[test_case(
         Ftyp{
             major_brand:   [b'a', b'b', b'e', b'm'],
@@ -316,6 +342,28 @@ use test_case::test_case;
 #[test_case(Minf{}, &[]; "minf")]
 #[test_case(Moof{}, &[]; "moof")]
 #[test_case(Moov{}, &[]; "moov")]
+#[test_case(
+        Mp4a{
+            sample_entry: SampleEntry{
+                reserved: [0, 0, 0, 0, 0, 0],
+                data_reference_index: 0x0123,
+            },
+            channel_count: 0x0002,
+            sample_size: 0x0010,
+            sample_rate: 0xac44_0000,
+            ..Default::default()
+        },
+        &[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // reserved
+            0x01, 0x23, // data reference index
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // reserved
+            0x00, 0x02, // channel count
+            0x00, 0x10, // sample size
+            0x00, 0x00, // pre defined
+            0x00, 0x00, // reserved
+            0xac, 0x44, 0x00, 0x00, // sample rate
+        ]; "mp4a"
+    )]
 #[test_case(Mvex{}, &[]; "mvex")]
 #[test_case(
         Mvhd{
@@ -583,6 +631,147 @@ use test_case::test_case;
             0x12, 0x34, 0x56, // nalUnit
         ]; "AvcC high profile new spec"
     )]
+#[test_case(
+        Encv(Avc1{
+            sample_entry: SampleEntry{
+                reserved: [0; 6],
+                data_reference_index: 1,
+            },
+            width: 0x0102,
+            height: 0x0103,
+            ..Default::default()
+        }),
+        &[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // reserved
+            0x00, 0x01, // data reference index
+            0x00, 0x00, // pre_defined
+            0x00, 0x00, // reserved
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, // pre_defined2
+            0x01, 0x02, // width
+            0x01, 0x03, // height
+            0x00, 0x00, 0x00, 0x00, // horiz_resolution
+            0x00, 0x00, 0x00, 0x00, // vert_resolution
+            0x00, 0x00, 0x00, 0x00, // reserved2
+            0x00, 0x00, // frame_count
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // compressor_name
+            0x00, 0x00, // depth
+            0x00, 0x00, // pre_defined3
+        ]; "encv"
+    )]
+#[test_case(
+        Senc{
+            full_box: FullBox{
+                version: 0,
+                flags: u32_to_flags(SENC_USE_SUBSAMPLE_ENCRYPTION),
+            },
+            samples: vec![SencSample{
+                iv: vec![0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef],
+                subsamples: vec![
+                    SencSubsample{
+                        bytes_of_clear_data: 0x0123,
+                        bytes_of_protected_data: 0x4567_89ab,
+                    },
+                    SencSubsample{
+                        bytes_of_clear_data: 0x0004,
+                        bytes_of_protected_data: 0x0000_0010,
+                    },
+                ],
+            }],
+        },
+        &[
+            0,                // version
+            0x00, 0x00, 0x02, // flags
+            0x00, 0x00, 0x00, 0x01, // sample count
+            0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, // iv
+            0x00, 0x02, // subsample count
+            0x01, 0x23, // bytes of clear data
+            0x45, 0x67, 0x89, 0xab, // bytes of protected data
+            0x00, 0x04, // bytes of clear data
+            0x00, 0x00, 0x00, 0x10, // bytes of protected data
+        ]; "senc: subsamples"
+    )]
+#[test_case(Sinf{}, &[]; "sinf")]
+#[test_case(Frma{data_format: *b"avc1"}, b"avc1"; "frma")]
+#[test_case(
+        Schm{
+            full_box: FullBox::default(),
+            scheme_type: SCHEME_TYPE_CENC,
+            scheme_version: 0x0001_0000,
+        },
+        &[
+            0,                // version
+            0x00, 0x00, 0x00, // flags
+            b'c', b'e', b'n', b'c', // scheme type
+            0x00, 0x01, 0x00, 0x00, // scheme version
+        ]; "schm"
+    )]
+#[test_case(Schi{}, &[]; "schi")]
+#[test_case(
+        Sidx{
+            flags: [0, 0, 0],
+            reference_id: 0x0123_4567,
+            timescale: 0x1234_5678,
+            times: SidxTimes::V1{
+                earliest_presentation_time: 0x0123_4567_89ab_cdef,
+                first_offset: 0x1234_5678_9abc_def0,
+            },
+            references: vec![
+                SidxReference{
+                    reference_type: false,
+                    referenced_size: 0x0123_4567,
+                    subsegment_duration: 0x1234_5678,
+                    starts_with_sap: true,
+                    sap_type: 1,
+                    sap_delta_time: 0,
+                },
+                SidxReference{
+                    reference_type: true,
+                    referenced_size: 0x0234_5678,
+                    subsegment_duration: 0x2345_6789,
+                    starts_with_sap: false,
+                    sap_type: 0,
+                    sap_delta_time: 0x0345_6789,
+                },
+            ],
+        },
+        &[
+            1,                // version
+            0x00, 0x00, 0x00, // flags
+            0x01, 0x23, 0x45, 0x67, // reference ID
+            0x12, 0x34, 0x56, 0x78, // timescale
+            0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, // earliest presentation time
+            0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0, // first offset
+            0x00, 0x00, // reserved
+            0x00, 0x02, // reference count
+            0x01, 0x23, 0x45, 0x67, // reference type, referenced size
+            0x12, 0x34, 0x56, 0x78, // subsegment duration
+            0x90, 0x00, 0x00, 0x00, // starts with sap, sap type, sap delta time
+            0x82, 0x34, 0x56, 0x78, // reference type, referenced size
+            0x23, 0x45, 0x67, 0x89, // subsegment duration
+            0x03, 0x45, 0x67, 0x89, // starts with sap, sap type, sap delta time
+        ]; "sidx"
+    )]
+#[test_case(
+        Smhd{
+            full_box: FullBox{
+                version: 0,
+                flags:   [0, 0, 0],
+            },
+            balance: -0x0123,
+            reserved: 0,
+        },
+        &[
+            0,                // version
+            0x00, 0x00, 0x00, // flags
+            0xfe, 0xdd, // balance
+            0x00, 0x00, // reserved
+        ]; "smhd"
+    )]
 #[test_case(Stbl{}, &[]; "stbl")]
 #[test_case(
         Stco{
@@ -862,6 +1051,35 @@ use test_case::test_case;
             0x04, 0x38, 0x00, 0x00, // height
         ]; "tkhd version 1"
     )]
+#[test_case(
+        Tenc{
+            full_box: FullBox{
+                version: 1,
+                flags: [0, 0, 0],
+            },
+            default_crypt_byte_block: 1,
+            default_skip_byte_block: 9,
+            default_is_protected: 1,
+            default_per_sample_iv_size: 0,
+            default_kid: [
+                0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
+                0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+            ],
+            default_constant_iv: vec![0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef],
+        },
+        &[
+            1,                // version
+            0x00, 0x00, 0x00, // flags
+            0x00, // reserved
+            0x19, // default crypt byte block & default skip byte block
+            0x01, // default is protected
+            0x00, // default per sample iv size
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, // default kid
+            0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+            0x08, // default constant iv size
+            0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, // default constant iv
+        ]; "tenc: version 1 constant iv"
+    )]
 #[test_case(Traf{}, &[]; "traf")]
 #[test_case(Trak{}, &[]; "trak")]
 #[test_case(
@@ -1064,3 +1282,105 @@ async fn test_box_types<T: Into<Box<dyn ImmutableBoxBoth>>>(src: T, bin: &[u8])
     assert_eq!({ size }, buf.len());
     assert_eq!(bin, buf);
 }
+
+#[tokio::test]
+async fn test_with_children() {
+    let btrt = || Btrt {
+        buffer_size_db: 1,
+        max_bitrate: 2,
+        avg_bitrate: 3,
+    };
+    let want_size = 8 + 9 * (8 + btrt().size());
+
+    let boxes = Boxes::new(Moov).with_children((0..9).map(|_| Boxes::new(btrt())));
+    assert_eq!(want_size, boxes.size());
+    let mut buf = Vec::new();
+    boxes.marshal(&mut buf).unwrap();
+    assert_eq!(want_size, buf.len());
+
+    let boxes = BoxesAsync::new(Moov).with_children((0..9).map(|_| BoxesAsync::new(btrt())));
+    assert_eq!(want_size, boxes.size());
+    let mut buf_async = Vec::new();
+    boxes.marshal(&mut buf_async).await.unwrap();
+    assert_eq!(buf, buf_async);
+
+    // The fixed arity variants preserve the order.
+    let boxes =
+        Boxes::new(Moov).with_children3(Boxes::new(Trak), Boxes::new(Mvex), Boxes::new(Traf));
+    let types: Vec<BoxType> = boxes
+        .children
+        .iter()
+        .map(|c| c.mp4_box.box_type())
+        .collect();
+    assert_eq!(vec![TYPE_TRAK, TYPE_MVEX, TYPE_TRAF], types);
+}
+
+#[test]
+fn test_describe() {
+    let boxes = Boxes::new(Moov).with_children2(
+        Boxes::new(Mvhd {
+            timescale: 1000,
+            version: MvhdVersion::V0(MvhdV0 {
+                duration: 1234,
+                ..Default::default()
+            }),
+            next_track_id: 2,
+            ..Default::default()
+        }),
+        Boxes::new(Trak).with_child(Boxes::new(Tkhd {
+            track_id: 1,
+            width: 640 << 16,
+            height: 480 << 16,
+            ..Default::default()
+        })),
+    );
+
+    let got = boxes.describe();
+    assert_eq!("moov", got["type"]);
+    assert_eq!(boxes.size(), got["size"]);
+    assert_eq!(1234, got["children"][0]["fields"]["duration"]);
+    assert_eq!("tkhd", got["children"][1]["children"][0]["type"]);
+    assert_eq!(640, got["children"][1]["children"][0]["fields"]["width"]);
+    assert!(got["children"][1]["children"][0].get("children").is_none());
+
+    let async_boxes = BoxesAsync::new(Moov).with_child(BoxesAsync::new(Trak));
+    assert_eq!(
+        serde_json::json!({
+            "type": "moov",
+            "size": 16,
+            "children": [{ "type": "trak", "size": 8 }],
+        }),
+        async_boxes.describe()
+    );
+}
+
+#[test]
+fn test_dump() {
+    #[rustfmt::skip]
+    let file = [
+        0, 0, 0, 16, b'm', b'o', b'o', b'v',
+        0, 0, 0, 8, b'x', b'y', b'z', 0xff, // Unknown type.
+        0, 0, 0, 1, b'm', b'd', b'a', b't',
+        0, 0, 0, 0, 0, 0, 0, 20, // Large size.
+        1, 2, 3, 4,
+        0, 0, 0, 0, b'f', b'r', b'e', b'e', // Extends to the end.
+        5, 6,
+    ];
+    let mut out = Vec::new();
+    dump(&mut std::io::Cursor::new(file), &mut out).unwrap();
+
+    let want = "\
+[moov] offset=0 size=16
+  [xyz\\xff] offset=8 size=8
+[mdat] offset=16 size=20
+[free] offset=36 size=10
+";
+    assert_eq!(want, String::from_utf8(out).unwrap());
+}
+
+#[test]
+fn test_dump_invalid_size() {
+    let file = [0, 0, 0, 9, b'm', b'o', b'o', b'v'];
+    let err = dump(&mut std::io::Cursor::new(file), &mut Vec::new()).unwrap_err();
+    assert_eq!("box 'moov' at offset 0: invalid size 9", err.to_string());
+}
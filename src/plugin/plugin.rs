@@ -6,7 +6,8 @@ use async_trait::async_trait;
 use axum::Router;
 use common::{
     monitor::{ArcMonitor, ArcMonitorManager, MonitorConfig, MonitorHooks},
-    ArcAuth, ArcLogger, DynEnvConfig, EnvPlugin, Event, LogEntry, LogLevel, LogSource,
+    ArcAuth, ArcLogger, ArcTranscoder, DynEnvConfig, EnvPlugin, Event, LogEntry, LogLevel,
+    LogSource,
 };
 use libloading::{Library, Symbol};
 use sentryshot_util::Frame;
@@ -18,7 +19,7 @@ use std::{
 use thiserror::Error;
 use tokio::{self, runtime::Handle, sync::mpsc};
 use tokio_util::sync::CancellationToken;
-use types::{Assets, NewAuthFn, Templates};
+use types::{Assets, CheckConfigFn, NewAuthFn, Templates};
 
 pub trait PreLoadPlugin {
     fn add_log_source(&self) -> Option<LogSource> {
@@ -27,6 +28,9 @@ pub trait PreLoadPlugin {
     fn set_new_auth(&self) -> Option<NewAuthFn> {
         None
     }
+    fn check_config(&self) -> Option<CheckConfigFn> {
+        None
+    }
 }
 #[async_trait]
 pub trait Plugin {
@@ -50,6 +54,9 @@ pub trait Plugin {
         frame
     }
     async fn on_event(&self, _event: Event, _config: MonitorConfig) {}
+    fn transcoder(&self) -> Option<ArcTranscoder> {
+        None
+    }
 }
 
 pub trait Application {
@@ -66,6 +73,7 @@ pub trait Application {
 pub struct PreLoadedPlugins {
     log_sources: Vec<LogSource>,
     new_auth_fn: Option<NewAuthFn>,
+    check_config_fns: Vec<(String, CheckConfigFn)>,
     load_fns: Vec<(String, LoadFn)>,
 }
 
@@ -148,6 +156,12 @@ pub fn pre_load_plugins(
                 if let Some(source) = plugin.add_log_source() {
                     pre_loaded_plugins.log_sources.push(source);
                 }
+
+                if let Some(check_config_fn) = plugin.check_config() {
+                    pre_loaded_plugins
+                        .check_config_fns
+                        .push((plugin_name.to_owned(), check_config_fn));
+                }
             }
 
             let load_fn: Symbol<LoadFn> = dylib.get(b"load").map_err(LoadSymbol)?;
@@ -178,6 +192,18 @@ impl PreLoadedPlugins {
         };
         new_auth_fn
     }
+
+    // Runs the config checks of the plugins.
+    #[must_use]
+    pub fn check_config(&self, configs_dir: &Path) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (name, check_config_fn) in &self.check_config_fns {
+            for problem in check_config_fn(configs_dir) {
+                problems.push(format!("plugin '{name}': {problem}"));
+            }
+        }
+        problems
+    }
 }
 
 #[derive(Default)]
@@ -219,6 +245,12 @@ impl PluginManager {
         }
         router
     }
+
+    // Returns the transcoder of the first plugin that provides one.
+    #[must_use]
+    pub fn transcoder(&self) -> Option<ArcTranscoder> {
+        self.plugins.iter().find_map(|v| v.transcoder())
+    }
 }
 
 #[async_trait]
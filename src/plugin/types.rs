@@ -26,11 +26,22 @@ pub enum NewAuthError {
 
     #[error("parse file: {0}")]
     ParseFile(serde_json::Error),
+
+    #[error("parse env config: {0}")]
+    ParseEnvConfig(String),
 }
 
 // Authenticator constructor function.
-pub type NewAuthFn =
-    fn(rt_handle: Handle, configs_dir: &Path, logger: ArcLogger) -> Result<ArcAuth, NewAuthError>;
+pub type NewAuthFn = fn(
+    rt_handle: Handle,
+    configs_dir: &Path,
+    raw_env_config: &str,
+    logger: ArcLogger,
+) -> Result<ArcAuth, NewAuthError>;
+
+// Validates the plugin config without starting the plugin,
+// returns the problems that were found.
+pub type CheckConfigFn = fn(configs_dir: &Path) -> Vec<String>;
 
 /// Main account definition.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
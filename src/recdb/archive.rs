@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use chrono::{Datelike, NaiveDate};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("read dir {0:?}: {1}")]
+    ReadDir(PathBuf, std::io::Error),
+
+    #[error("create dir {0:?}: {1}")]
+    CreateDir(PathBuf, std::io::Error),
+
+    #[error("move {0:?}: {1}")]
+    Move(PathBuf, std::io::Error),
+}
+
+// Moves the files from the days before `cutoff` to the same relative
+// path in the archive directory. Files that belong to an active
+// recording are skipped. Returns the days that files were moved from.
+pub(crate) fn archive_days(
+    recordings_dir: &Path,
+    archive_dir: &Path,
+    cutoff: NaiveDate,
+    active_recordings: &HashSet<String>,
+) -> Result<Vec<PathBuf>, ArchiveError> {
+    let mut archived = Vec::new();
+    for (year, year_dir) in list_dirs::<i32>(recordings_dir)? {
+        if year > cutoff.year() {
+            continue;
+        }
+        for (month, month_dir) in list_dirs::<u32>(&year_dir)? {
+            if (year, month) > (cutoff.year(), cutoff.month()) {
+                continue;
+            }
+            for (day, day_dir) in list_dirs::<u32>(&month_dir)? {
+                let Some(date) = NaiveDate::from_ymd_opt(year, month, day) else {
+                    continue;
+                };
+                if date >= cutoff {
+                    continue;
+                }
+                if archive_day(recordings_dir, archive_dir, &day_dir, active_recordings)? {
+                    archived.push(day_dir.clone());
+                }
+                // Fails if an active recording is left.
+                let _ = std::fs::remove_dir(&day_dir);
+            }
+            if (year, month) < (cutoff.year(), cutoff.month()) {
+                let _ = std::fs::remove_dir(&month_dir);
+            }
+        }
+        if year < cutoff.year() {
+            let _ = std::fs::remove_dir(&year_dir);
+        }
+    }
+    Ok(archived)
+}
+
+// Returns true if any files were moved.
+fn archive_day(
+    recordings_dir: &Path,
+    archive_dir: &Path,
+    day_dir: &Path,
+    active_recordings: &HashSet<String>,
+) -> Result<bool, ArchiveError> {
+    use ArchiveError::*;
+    let mut moved = false;
+    for (_, monitor_dir) in list_dirs::<String>(day_dir)? {
+        let relative_dir = monitor_dir
+            .strip_prefix(recordings_dir)
+            .expect("monitor dir should be in recordings dir");
+        let dst_dir = archive_dir.join(relative_dir);
+
+        let entries =
+            std::fs::read_dir(&monitor_dir).map_err(|e| ReadDir(monitor_dir.clone(), e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| ReadDir(monitor_dir.clone(), e))?;
+            let path = entry.path();
+            let Some(file_stem) = path.file_stem().and_then(|v| v.to_str()) else {
+                continue;
+            };
+            if active_recordings.contains(file_stem) {
+                continue;
+            }
+            std::fs::create_dir_all(&dst_dir).map_err(|e| CreateDir(dst_dir.clone(), e))?;
+            move_file(&path, &dst_dir.join(entry.file_name())).map_err(|e| Move(path, e))?;
+            moved = true;
+        }
+        let _ = std::fs::remove_dir(&monitor_dir);
+    }
+    Ok(moved)
+}
+
+// Returns the sub directories that can be parsed as `T`, sorted by path.
+fn list_dirs<T: FromStr>(dir: &Path) -> Result<Vec<(T, PathBuf)>, ArchiveError> {
+    use ArchiveError::*;
+    let mut dirs = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(|e| ReadDir(dir.to_path_buf(), e))? {
+        let entry = entry.map_err(|e| ReadDir(dir.to_path_buf(), e))?;
+        if !entry.file_type().is_ok_and(|v| v.is_dir()) {
+            continue;
+        }
+        let Some(value) = entry.file_name().to_str().and_then(|v| v.parse().ok()) else {
+            continue;
+        };
+        dirs.push((value, entry.path()));
+    }
+    dirs.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(dirs)
+}
+
+// Renames the file, or copies and removes it if the
+// archive directory is on a different file system.
+fn move_file(src: &Path, dst: &Path) -> Result<(), std::io::Error> {
+    if std::fs::rename(src, dst).is_ok() {
+        return Ok(());
+    }
+    copy_and_remove(src, dst)
+}
+
+// The copy is written to a temporary file first so an interrupted
+// move never leaves a truncated file at the destination.
+fn copy_and_remove(src: &Path, dst: &Path) -> Result<(), std::io::Error> {
+    let mut tmp_path = dst.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    std::fs::copy(src, &tmp_path)?;
+    std::fs::File::open(&tmp_path)?.sync_all()?;
+    std::fs::rename(&tmp_path, dst)?;
+    if let Some(parent) = dst.parent() {
+        std::fs::File::open(parent)?.sync_all()?;
+    }
+    std::fs::remove_file(src)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    fn write_file(path: &Path) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "x").unwrap();
+    }
+
+    #[test]
+    fn test_archive_days() {
+        let recordings_dir = tempdir().unwrap();
+        let archive_dir = tempdir().unwrap();
+        let rec = |p: &str| recordings_dir.path().join(p);
+        let arc = |p: &str| archive_dir.path().join(p);
+
+        write_file(&rec("2000/01/01/m1/2000-01-01_01-01-01_m1.meta"));
+        write_file(&rec("2000/01/01/m1/2000-01-01_01-01-01_m1.mdat"));
+        write_file(&rec("2000/01/02/m1/2000-01-02_01-01-01_m1.meta"));
+        write_file(&rec("2000/01/02/m2/2000-01-02_01-01-01_m2.meta"));
+        write_file(&rec("2000/01/03/m1/2000-01-03_01-01-01_m1.meta"));
+
+        let active = HashSet::from(["2000-01-02_01-01-01_m2".to_owned()]);
+        let cutoff = NaiveDate::from_ymd_opt(2000, 1, 3).unwrap();
+        let archived =
+            archive_days(recordings_dir.path(), archive_dir.path(), cutoff, &active).unwrap();
+        assert_eq!(vec![rec("2000/01/01"), rec("2000/01/02")], archived);
+
+        assert!(arc("2000/01/01/m1/2000-01-01_01-01-01_m1.meta").exists());
+        assert!(arc("2000/01/01/m1/2000-01-01_01-01-01_m1.mdat").exists());
+        assert!(arc("2000/01/02/m1/2000-01-02_01-01-01_m1.meta").exists());
+        assert!(!rec("2000/01/01").exists());
+
+        // Active recordings and days after the cutoff are kept.
+        assert!(rec("2000/01/02/m2/2000-01-02_01-01-01_m2.meta").exists());
+        assert!(!rec("2000/01/02/m1").exists());
+        assert!(rec("2000/01/03/m1/2000-01-03_01-01-01_m1.meta").exists());
+        assert!(!arc("2000/01/03").exists());
+    }
+
+    #[test]
+    fn test_copy_and_remove() {
+        let temp_dir = tempdir().unwrap();
+        let src = temp_dir.path().join("a");
+        let dst = temp_dir.path().join("b");
+        std::fs::write(&src, "x").unwrap();
+
+        copy_and_remove(&src, &dst).unwrap();
+        assert!(!src.exists());
+        assert!(!temp_dir.path().join("b.tmp").exists());
+        assert_eq!("x", std::fs::read_to_string(dst).unwrap());
+    }
+}
@@ -23,6 +23,8 @@ use thiserror::Error;
 //             ├── YYYY-MM-DD_hh-mm-ss_monitor2.jpeg  // Thumbnail.
 //             ├── YYYY-MM-DD_hh-mm-ss_monitor2.meta  // Video metadata.
 //             ├── YYYY-MM-DD_hh-mm-ss_monitor2.mdat  // Raw video data.
+//             ├── YYYY-MM-DD_hh-mm-ss_monitor2.ameta // Audio metadata, optional.
+//             ├── YYYY-MM-DD_hh-mm-ss_monitor2.amdat // Raw audio data, optional.
 //             └── YYYY-MM-DD_hh-mm-ss_monitor2.json  // Event data.
 //
 // Event data is only generated if video was saved successfully.
@@ -0,0 +1,885 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+// Index of the recording files so that queries don't have to read every
+// directory. The index is kept in memory and changes are appended to a
+// file, one per line. A line is the relative path of a added file or a
+// removed file or directory prefixed by `-`. Only the files that the
+// crawler looks at are indexed.
+//
+// 2000/01/01/m1/2000-01-01_01-01-11_m1.meta
+// 2000/01/01/m1/2000-01-01_01-01-11_m1.json
+// -2000/01/01/m1/2000-01-01_01-01-11_m1.json
+//
+// The file is compacted into a list of the indexed files once enough
+// lines have been appended. Queries run the regular crawler against a
+// snapshot of the index, the results are therefore identical. The index
+// is rebuilt by crawling the recordings directory if the file is missing.
+// Otherwise the directories are crawled in the background after the file
+// is loaded to pick up changes made while the program wasn't running.
+// Archived recordings keep their relative path and are read from the
+// archive directory instead.
+
+use common::{ArcLogger, LogEntry, LogLevel};
+use fs::{Dir, DynFs, Entry, File, Fs, FsError, Open};
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+
+// The file is compacted when more lines than the number
+// of indexed files have been appended, but not before this.
+const COMPACT_MIN_LINES: usize = 1000;
+
+#[derive(Clone, PartialEq, Eq)]
+enum Node {
+    Dir(BTreeMap<String, Arc<Node>>),
+    File,
+}
+
+impl Node {
+    fn new_dir() -> Self {
+        Self::Dir(BTreeMap::new())
+    }
+
+    // Adds a file and the missing parent directories.
+    fn insert(&mut self, path: &[&str]) {
+        let Node::Dir(children) = self else {
+            return;
+        };
+        let Some((name, rest)) = path.split_first() else {
+            return;
+        };
+        if rest.is_empty() {
+            children.insert((*name).to_owned(), Arc::new(Node::File));
+            return;
+        }
+        let child = children
+            .entry((*name).to_owned())
+            .or_insert_with(|| Arc::new(Node::new_dir()));
+        Arc::make_mut(child).insert(rest);
+    }
+
+    fn get(&self, path: &[&str]) -> Option<&Node> {
+        let Some((name, rest)) = path.split_first() else {
+            return Some(self);
+        };
+        let Node::Dir(children) = self else {
+            return None;
+        };
+        children.get(*name)?.get(rest)
+    }
+
+    // Removes a file or directory and the parent directories that become empty.
+    fn remove(&mut self, path: &[&str]) {
+        let Node::Dir(children) = self else {
+            return;
+        };
+        let Some((name, rest)) = path.split_first() else {
+            return;
+        };
+        if rest.is_empty() {
+            children.remove(*name);
+            return;
+        }
+        let Some(child) = children.get_mut(*name) else {
+            return;
+        };
+        let child = Arc::make_mut(child);
+        child.remove(rest);
+        if matches!(child, Node::Dir(v) if v.is_empty()) {
+            children.remove(*name);
+        }
+    }
+
+    // Appends the path of every file to `out`, one per line.
+    // Returns the number of files.
+    fn write_paths(&self, prefix: &str, out: &mut String) -> usize {
+        match self {
+            Node::Dir(children) => {
+                let mut n = 0;
+                for (name, child) in children {
+                    if prefix.is_empty() {
+                        n += child.write_paths(name, out);
+                    } else {
+                        n += child.write_paths(&format!("{prefix}/{name}"), out);
+                    }
+                }
+                n
+            }
+            Node::File => {
+                out.push_str(prefix);
+                out.push('\n');
+                1
+            }
+        }
+    }
+}
+
+// Change to the index. The path is relative to the roots.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Op {
+    Insert(String),
+    Remove(String),
+}
+
+impl Op {
+    fn parse(line: &str) -> Option<Self> {
+        let (remove, path) = match line.strip_prefix('-') {
+            Some(path) => (true, path),
+            None => (false, line),
+        };
+        if path
+            .split('/')
+            .any(|v| v.is_empty() || v == "." || v == "..")
+        {
+            return None;
+        }
+        Some(if remove {
+            Op::Remove(path.to_owned())
+        } else {
+            Op::Insert(path.to_owned())
+        })
+    }
+
+    fn apply(&self, root: &mut Node) {
+        match self {
+            Op::Insert(path) => root.insert(&path.split('/').collect::<Vec<_>>()),
+            Op::Remove(path) => root.remove(&path.split('/').collect::<Vec<_>>()),
+        }
+    }
+
+    fn write_line(&self, out: &mut String) {
+        match self {
+            Op::Insert(path) => out.push_str(path),
+            Op::Remove(path) => {
+                out.push('-');
+                out.push_str(path);
+            }
+        }
+        out.push('\n');
+    }
+}
+
+fn is_indexed(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(OsStr::to_str),
+        Some("meta" | "json")
+    )
+}
+
+fn components(path: &Path) -> Option<Vec<&str>> {
+    path.iter().map(OsStr::to_str).collect()
+}
+
+#[derive(Debug, Error)]
+pub enum LoadIndexError {
+    #[error("read index: {0}")]
+    Read(std::io::Error),
+
+    #[error("crawl recordings: {0}")]
+    Crawl(std::io::Error),
+
+    #[error("save index: {0}")]
+    Save(#[from] SaveIndexError),
+}
+
+#[derive(Debug, Error)]
+pub enum SaveIndexError {
+    #[error("write temporary file: {0}")]
+    Write(std::io::Error),
+
+    #[error("rename temporary file: {0}")]
+    Rename(std::io::Error),
+
+    #[error("append to index: {0}")]
+    Append(std::io::Error),
+}
+
+struct IndexState {
+    root: Arc<Node>,
+
+    // Applied changes that haven't been written to the file yet.
+    unwritten: Vec<Op>,
+
+    // Lines appended since the file was compacted.
+    appended: usize,
+
+    // Number of files when the file was compacted.
+    compacted_files: usize,
+
+    // Changes made while the directories are crawled at startup.
+    pending: Option<Vec<Op>>,
+}
+
+pub(crate) struct RecordingIndex {
+    logger: ArcLogger,
+    // The recordings directory followed by the archive directory.
+    roots: Arc<Vec<PathBuf>>,
+    path: PathBuf,
+    state: std::sync::Mutex<IndexState>,
+
+    // Held while the index file is written.
+    save_lock: tokio::sync::Mutex<()>,
+
+    // Cancelled when the startup crawl is done.
+    reconciled: CancellationToken,
+}
+
+impl RecordingIndex {
+    // Reads the index file at `path`. The recordings and
+    // archive directories are crawled if the file doesn't exist.
+    pub(crate) async fn load(
+        logger: ArcLogger,
+        recordings_dir: PathBuf,
+        archive_dir: Option<PathBuf>,
+        path: PathBuf,
+    ) -> Result<Arc<Self>, LoadIndexError> {
+        use LoadIndexError::*;
+        let roots: Arc<Vec<PathBuf>> =
+            Arc::new(std::iter::once(recordings_dir).chain(archive_dir).collect());
+        let (root, rebuilt) = match tokio::fs::read_to_string(&path).await {
+            Ok(raw) => (parse_index(&raw), false),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let roots = roots.clone();
+                let root = tokio::task::spawn_blocking(move || crawl(&roots))
+                    .await
+                    .expect("join")
+                    .map_err(Crawl)?;
+                (root, true)
+            }
+            Err(e) => return Err(Read(e)),
+        };
+
+        let index = Arc::new(Self {
+            logger,
+            roots,
+            path,
+            state: std::sync::Mutex::new(IndexState {
+                root: Arc::new(root),
+                unwritten: Vec::new(),
+                appended: 0,
+                compacted_files: 0,
+                pending: (!rebuilt).then(Vec::new),
+            }),
+            save_lock: tokio::sync::Mutex::new(()),
+            reconciled: CancellationToken::new(),
+        });
+        if rebuilt {
+            index.logger.log(LogEntry::new(
+                LogLevel::Info,
+                "app",
+                None,
+                "rebuilt recording index".to_owned(),
+            ));
+            index.compact().await?;
+            index.reconciled.cancel();
+        } else {
+            tokio::spawn(index.clone().reconcile());
+        }
+        Ok(index)
+    }
+
+    // Crawls the directories and replaces the loaded index with the
+    // result. Changes made during the crawl are applied on top.
+    async fn reconcile(self: Arc<Self>) {
+        let roots = self.roots.clone();
+        let res = tokio::task::spawn_blocking(move || crawl(&roots))
+            .await
+            .expect("join");
+        let changed = {
+            let mut state = self.state.lock().expect("not poisoned");
+            let pending = state.pending.take().unwrap_or_default();
+            res.map(|mut root| {
+                for op in &pending {
+                    op.apply(&mut root);
+                }
+                let changed = *state.root != root;
+                state.root = Arc::new(root);
+                changed
+            })
+        };
+        match changed {
+            Ok(true) => {
+                self.logger.log(LogEntry::new(
+                    LogLevel::Info,
+                    "app",
+                    None,
+                    "recording index was out of date, updated it".to_owned(),
+                ));
+                if let Err(e) = self.compact().await {
+                    self.log_save_error(&e);
+                }
+            }
+            Ok(false) => {}
+            Err(e) => self.logger.log(LogEntry::new(
+                LogLevel::Error,
+                "app",
+                None,
+                format!("crawl recordings for index: {e}"),
+            )),
+        }
+        self.reconciled.cancel();
+    }
+
+    // Returns a snapshot of the index that the crawler can read.
+    pub(crate) fn fs(&self) -> DynFs {
+        Box::new(IndexFs {
+            roots: self.roots.clone(),
+            root: self.state.lock().expect("not poisoned").root.clone(),
+        })
+    }
+
+    // Adds the file at `path` if it's a file that should be indexed.
+    pub(crate) async fn insert(&self, path: &Path) {
+        if !is_indexed(path) {
+            return;
+        }
+        self.update(path, |path| vec![Op::Insert(path.join("/"))])
+            .await;
+    }
+
+    // Removes the indexed files of the recording at `path`, without extension.
+    pub(crate) async fn remove_recording(&self, path: &Path) {
+        self.update(path, |path| {
+            let path = path.join("/");
+            ["meta", "json"]
+                .into_iter()
+                .map(|ext| Op::Remove(format!("{path}.{ext}")))
+                .collect()
+        })
+        .await;
+    }
+
+    // Removes the directory at `path` and everything in it. The archive
+    // directory may have files with the same relative paths, those are kept.
+    pub(crate) async fn remove_dir(&self, path: &Path) {
+        if self.roots.len() == 1 {
+            self.update(path, |path| vec![Op::Remove(path.join("/"))])
+                .await;
+            return;
+        }
+
+        let Some(rel_path) = path.strip_prefix(&self.roots[0]).ok().and_then(components) else {
+            return;
+        };
+        let mut files = String::new();
+        if let Some(node) = self.state.lock().expect("not poisoned").root.get(&rel_path) {
+            node.write_paths(&rel_path.join("/"), &mut files);
+        }
+        let roots = self.roots.clone();
+        let removed: Vec<Op> = tokio::task::spawn_blocking(move || {
+            files
+                .lines()
+                .filter(|file| !roots[1..].iter().any(|root| root.join(file).exists()))
+                .map(|file| Op::Remove(file.to_owned()))
+                .collect()
+        })
+        .await
+        .expect("join");
+        if removed.is_empty() {
+            return;
+        }
+        self.update(path, |_| removed).await;
+    }
+
+    async fn update(&self, path: &Path, ops: impl FnOnce(&[&str]) -> Vec<Op>) {
+        let Ok(path) = path.strip_prefix(&self.roots[0]) else {
+            return;
+        };
+        let Some(path) = components(path) else {
+            return;
+        };
+        if path.is_empty() {
+            return;
+        }
+        let ops = ops(&path);
+        {
+            let mut state = self.state.lock().expect("not poisoned");
+            let root = Arc::make_mut(&mut state.root);
+            for op in &ops {
+                op.apply(root);
+            }
+            if let Some(pending) = &mut state.pending {
+                pending.extend(ops.iter().cloned());
+            }
+            state.unwritten.extend(ops);
+        }
+        if let Err(e) = self.flush().await {
+            self.log_save_error(&e);
+        }
+    }
+
+    fn log_save_error(&self, e: &SaveIndexError) {
+        self.logger.log(LogEntry::new(
+            LogLevel::Error,
+            "app",
+            None,
+            format!("save recording index: {e}"),
+        ));
+    }
+
+    // Appends the unwritten changes to the file, in the order they
+    // were applied, or compacts the file if it has grown too much.
+    async fn flush(&self) -> Result<(), SaveIndexError> {
+        let _guard = self.save_lock.lock().await;
+        let (ops, compact) = {
+            let mut state = self.state.lock().expect("not poisoned");
+            let ops = std::mem::take(&mut state.unwritten);
+            state.appended += ops.len();
+            let limit = std::cmp::max(state.compacted_files, COMPACT_MIN_LINES);
+            (ops, state.appended > limit)
+        };
+        if compact {
+            return self.write_snapshot().await;
+        }
+        if ops.is_empty() {
+            return Ok(());
+        }
+        let mut raw = String::new();
+        for op in ops {
+            op.write_line(&mut raw);
+        }
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(raw.as_bytes())
+        })
+        .await
+        .expect("join")
+        .map_err(SaveIndexError::Append)
+    }
+
+    async fn compact(&self) -> Result<(), SaveIndexError> {
+        let _guard = self.save_lock.lock().await;
+        self.write_snapshot().await
+    }
+
+    // Writes every indexed file to a temporary file and renames
+    // it over the index. The save lock must be held.
+    async fn write_snapshot(&self) -> Result<(), SaveIndexError> {
+        use SaveIndexError::*;
+        let root = {
+            let mut state = self.state.lock().expect("not poisoned");
+            // The snapshot includes the unwritten changes.
+            state.unwritten.clear();
+            state.appended = 0;
+            state.root.clone()
+        };
+        let path = self.path.clone();
+        let files = tokio::task::spawn_blocking(move || {
+            let mut raw = String::new();
+            let files = root.write_paths("", &mut raw);
+            let temp_path = path.with_extension("tmp");
+            std::fs::write(&temp_path, raw).map_err(Write)?;
+            std::fs::rename(&temp_path, &path).map_err(Rename)?;
+            Ok(files)
+        })
+        .await
+        .expect("join")?;
+        self.state.lock().expect("not poisoned").compacted_files = files;
+        Ok(())
+    }
+}
+
+// Lines are applied in order. The last line is incomplete
+// if the program stopped while it was being appended.
+fn parse_index(raw: &str) -> Node {
+    let mut root = Node::new_dir();
+    for line in raw.split_inclusive('\n') {
+        let Some(line) = line.strip_suffix('\n') else {
+            break;
+        };
+        if let Some(op) = Op::parse(line) {
+            op.apply(&mut root);
+        }
+    }
+    root
+}
+
+fn crawl(roots: &[PathBuf]) -> Result<Node, std::io::Error> {
+    let mut root = Node::new_dir();
+    for recordings_dir in roots {
+        let mut dirs = vec![PathBuf::new()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(recordings_dir.join(&dir))? {
+                let entry = entry?;
+                let path = dir.join(entry.file_name());
+                if entry.file_type()?.is_dir() {
+                    dirs.push(path);
+                } else if is_indexed(&path) {
+                    if let Some(path) = components(&path) {
+                        root.insert(&path);
+                    }
+                }
+            }
+        }
+    }
+    Ok(root)
+}
+
+#[derive(Clone)]
+struct IndexFs {
+    roots: Arc<Vec<PathBuf>>,
+    root: Arc<Node>,
+}
+
+impl Fs for IndexFs {
+    fn open(&self, path: &Path) -> Result<Open, FsError> {
+        let mut node = &self.root;
+        if path != Path::new(".") {
+            for name in path {
+                let Node::Dir(children) = &**node else {
+                    return Err(FsError::NotADirectory);
+                };
+                node = name
+                    .to_str()
+                    .and_then(|name| children.get(name))
+                    .ok_or(FsError::OpenNotExist)?;
+            }
+        }
+        let name = PathBuf::from(path.file_name().unwrap_or(path.as_os_str()));
+        let path = path.to_path_buf();
+        let roots = self.roots.clone();
+        Ok(match &**node {
+            Node::Dir(_) => Open::Dir(Box::new(IndexDir {
+                name,
+                path,
+                roots,
+                node: node.clone(),
+            })),
+            Node::File => Open::File(Box::new(IndexFile { name, path, roots })),
+        })
+    }
+
+    fn clone(&self) -> DynFs {
+        Box::new(Clone::clone(self))
+    }
+}
+
+// Paths are relative to the roots.
+struct IndexDir {
+    name: PathBuf,
+    path: PathBuf,
+    roots: Arc<Vec<PathBuf>>,
+    node: Arc<Node>,
+}
+
+impl Dir for IndexDir {
+    fn name(&self) -> &Path {
+        &self.name
+    }
+
+    fn read_dir_file(&mut self) -> Result<Vec<Entry>, FsError> {
+        let Node::Dir(children) = &*self.node else {
+            return Err(FsError::NotADirectory);
+        };
+        Ok(children
+            .iter()
+            .map(|(name, node)| {
+                let path = self.path.join(name);
+                let name = PathBuf::from(name);
+                let roots = self.roots.clone();
+                match &**node {
+                    Node::Dir(_) => Entry::Dir(Box::new(IndexDir {
+                        name,
+                        path,
+                        roots,
+                        node: node.clone(),
+                    })),
+                    Node::File => Entry::File(Box::new(IndexFile { name, path, roots })),
+                }
+            })
+            .collect())
+    }
+}
+
+// Indexed files are read from the first root that has them.
+struct IndexFile {
+    name: PathBuf,
+    path: PathBuf,
+    roots: Arc<Vec<PathBuf>>,
+}
+
+impl File for IndexFile {
+    fn name(&self) -> &Path {
+        &self.name
+    }
+
+    fn read(&mut self) -> Result<Vec<u8>, FsError> {
+        for root in self.roots.iter() {
+            match std::fs::read(root.join(&self.path)) {
+                Ok(v) => return Ok(v),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(FsError::OpenNotExist)
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crawler::Crawler, RecDbQuery};
+    use common::{recording::RecordingId, DummyLogger};
+    use fs::dir_fs;
+    use std::{collections::HashSet, num::NonZeroUsize};
+    use tempfile::tempdir;
+
+    fn r_id(s: &str) -> RecordingId {
+        s.to_owned().try_into().unwrap()
+    }
+
+    // Creates 100 recordings across two years and two monitors.
+    // Every seventh recording is incomplete.
+    fn create_recordings(recordings_dir: &Path) -> Vec<RecordingId> {
+        let mut ids = Vec::new();
+        for i in 0..100 {
+            let year = 2000 + i / 50;
+            let month = 1 + (i / 10) % 5;
+            let day = 1 + (i / 2) % 5;
+            let monitor = if i % 3 == 0 { "m2" } else { "m1" };
+            let id = r_id(&format!(
+                "{year}-{month:02}-{day:02}_{:02}-{:02}-00_{monitor}",
+                i % 24,
+                i % 60,
+            ));
+
+            let path = recordings_dir.join(id.as_full_path());
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path.with_extension("meta"), "").unwrap();
+            std::fs::write(path.with_extension("mdat"), "").unwrap();
+            if i % 7 != 0 {
+                let data = format!("{{\"start\":{i},\"end\":{},\"events\":[]}}", i + 1);
+                std::fs::write(path.with_extension("json"), data).unwrap();
+            }
+            ids.push(id);
+        }
+        ids
+    }
+
+    #[tokio::test]
+    async fn test_recording_index_matches_crawler() {
+        let temp_dir = tempdir().unwrap();
+        let recordings_dir = temp_dir.path().join("recordings");
+        let index_path = temp_dir.path().join("recording_index");
+        let ids = create_recordings(&recordings_dir);
+
+        let index = RecordingIndex::load(
+            DummyLogger::new(),
+            recordings_dir.clone(),
+            None,
+            index_path.clone(),
+        )
+        .await
+        .unwrap();
+        assert!(index_path.exists());
+
+        let crawler = Crawler::new(dir_fs(recordings_dir));
+        let index_crawler = Crawler::new(index.fs());
+        let active_recordings = HashSet::from([ids[99].clone()]);
+
+        let mut probes: Vec<RecordingId> = ids.iter().step_by(9).cloned().collect();
+        probes.extend([
+            r_id("0000-01-01_00-00-00_x"),
+            r_id("2000-03-03_12-00-00_x"),
+            r_id("2000-12-01_00-00-00_x"),
+            r_id("9999-01-01_00-00-00_x"),
+        ]);
+        for recording_id in probes {
+            for reverse in [false, true] {
+                for (limit, end, monitors, include_data) in [
+                    (1, None, Vec::new(), false),
+                    (7, None, vec!["m2".to_owned()], true),
+                    (200, None, Vec::new(), true),
+                    (200, Some(ids[40].clone()), Vec::new(), false),
+                ] {
+                    let query = RecDbQuery {
+                        recording_id: recording_id.clone(),
+                        end,
+                        limit: NonZeroUsize::new(limit).unwrap(),
+                        reverse,
+                        monitors,
+                        include_data,
+                    };
+                    let want = crawler
+                        .recordings_by_query(query.clone(), active_recordings.clone())
+                        .await
+                        .unwrap();
+                    let got = index_crawler
+                        .recordings_by_query(query.clone(), active_recordings.clone())
+                        .await
+                        .unwrap();
+                    assert_eq!(format!("{want:?}"), format!("{got:?}"), "{query:?}");
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recording_index_update() {
+        let temp_dir = tempdir().unwrap();
+        let recordings_dir = temp_dir.path().join("recordings");
+        let index_path = temp_dir.path().join("recording_index");
+        std::fs::create_dir(&recordings_dir).unwrap();
+
+        let index = RecordingIndex::load(
+            DummyLogger::new(),
+            recordings_dir.clone(),
+            None,
+            index_path.clone(),
+        )
+        .await
+        .unwrap();
+
+        let rec = recordings_dir.join("2000/01/01/m1/2000-01-01_01-01-01_m1");
+        index.insert(&rec.with_extension("meta")).await;
+        index.insert(&rec.with_extension("json")).await;
+        index.insert(&rec.with_extension("mdat")).await;
+        index
+            .insert(&recordings_dir.join("2000/01/02/m1/2000-01-02_01-01-01_m1.meta"))
+            .await;
+        index.remove_recording(&rec).await;
+        index.remove_dir(&recordings_dir.join("2000/01/02")).await;
+        assert_eq!(
+            "2000/01/01/m1/2000-01-01_01-01-01_m1.meta\n\
+             2000/01/01/m1/2000-01-01_01-01-01_m1.json\n\
+             2000/01/02/m1/2000-01-02_01-01-01_m1.meta\n\
+             -2000/01/01/m1/2000-01-01_01-01-01_m1.meta\n\
+             -2000/01/01/m1/2000-01-01_01-01-01_m1.json\n\
+             -2000/01/02\n",
+            std::fs::read_to_string(&index_path).unwrap(),
+        );
+        let Open::Dir(mut dir) = index.fs().open(Path::new(".")).unwrap() else {
+            panic!("expected dir");
+        };
+        assert!(dir.read_dir_file().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_recording_index_remove_dir_archive() {
+        let temp_dir = tempdir().unwrap();
+        let recordings_dir = temp_dir.path().join("recordings");
+        let archive_dir = temp_dir.path().join("archive");
+        let index_path = temp_dir.path().join("recording_index");
+
+        // The day has recordings in both directories.
+        let day = "2000/01/02/m1";
+        std::fs::create_dir_all(recordings_dir.join(day)).unwrap();
+        std::fs::create_dir_all(archive_dir.join(day)).unwrap();
+        let recent = format!("{day}/2000-01-02_02-01-01_m1.meta");
+        let archived = format!("{day}/2000-01-02_01-01-01_m1.meta");
+        std::fs::write(recordings_dir.join(&recent), "").unwrap();
+        std::fs::write(archive_dir.join(&archived), "").unwrap();
+
+        let index = RecordingIndex::load(
+            DummyLogger::new(),
+            recordings_dir.clone(),
+            Some(archive_dir),
+            index_path.clone(),
+        )
+        .await
+        .unwrap();
+
+        std::fs::remove_dir_all(recordings_dir.join("2000")).unwrap();
+        index.remove_dir(&recordings_dir.join("2000/01/02")).await;
+
+        let mut got = String::new();
+        index.state.lock().unwrap().root.write_paths("", &mut got);
+        assert_eq!(format!("{archived}\n"), got);
+        assert!(std::fs::read_to_string(&index_path)
+            .unwrap()
+            .ends_with(&format!("-{recent}\n")));
+    }
+
+    #[tokio::test]
+    async fn test_recording_index_compact() {
+        let temp_dir = tempdir().unwrap();
+        let recordings_dir = temp_dir.path().join("recordings");
+        let index_path = temp_dir.path().join("recording_index");
+        std::fs::create_dir(&recordings_dir).unwrap();
+
+        let index = RecordingIndex::load(
+            DummyLogger::new(),
+            recordings_dir.clone(),
+            None,
+            index_path.clone(),
+        )
+        .await
+        .unwrap();
+
+        let path = recordings_dir.join("2000/01/01/m1/2000-01-01_01-01-01_m1.meta");
+        for _ in 0..COMPACT_MIN_LINES {
+            index.insert(&path).await;
+        }
+        let raw = std::fs::read_to_string(&index_path).unwrap();
+        assert_eq!(COMPACT_MIN_LINES, raw.lines().count());
+
+        index.insert(&path).await;
+        assert_eq!(
+            "2000/01/01/m1/2000-01-01_01-01-01_m1.meta\n",
+            std::fs::read_to_string(&index_path).unwrap(),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recording_index_reconcile() {
+        let temp_dir = tempdir().unwrap();
+        let recordings_dir = temp_dir.path().join("recordings");
+        let index_path = temp_dir.path().join("recording_index");
+        let ids = create_recordings(&recordings_dir);
+
+        // Missing recordings and a recording that was deleted.
+        std::fs::write(
+            &index_path,
+            "1999/01/01/m1/1999-01-01_00-00-00_m1.meta\n\
+             -2000/01\n",
+        )
+        .unwrap();
+
+        let index = RecordingIndex::load(
+            DummyLogger::new(),
+            recordings_dir.clone(),
+            None,
+            index_path.clone(),
+        )
+        .await
+        .unwrap();
+        index.reconciled.cancelled().await;
+
+        let mut want = String::new();
+        crawl(std::slice::from_ref(&recordings_dir))
+            .unwrap()
+            .write_paths("", &mut want);
+        let mut got = String::new();
+        index.state.lock().unwrap().root.write_paths("", &mut got);
+        assert_eq!(want, got);
+        assert_eq!(want, std::fs::read_to_string(&index_path).unwrap());
+        assert!(got.contains(&ids[0].as_full_path().to_string_lossy().to_string()));
+    }
+
+    #[test]
+    fn test_parse_index() {
+        let root = parse_index(
+            "a/1.meta\n\
+             b/2.meta\n\
+             b/3.meta\n\
+             -a/1.meta\n\
+             -b/2.meta\n\
+             ../x.meta\n\
+             -b",
+        );
+        let mut got = String::new();
+        root.write_paths("", &mut got);
+        assert_eq!("b/3.meta\n", got);
+    }
+}
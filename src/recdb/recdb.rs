@@ -1,24 +1,31 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
+mod archive;
 mod crawler;
 mod disk;
+mod index;
 
+pub use archive::ArchiveError;
 use chrono::{DateTime, Utc};
 pub use crawler::CrawlerError;
-pub use disk::Disk;
+pub use disk::{Disk, UsageError};
+pub use index::{LoadIndexError, SaveIndexError};
 
+use archive::archive_days;
 use common::recording::{RecordingData, RecordingId, RecordingIdError};
 use common::{
-    time::{Duration, UnixH264},
-    ArcLogger, LogEntry, LogLevel, MonitorId,
+    time::{Duration, UnixH264, UnixNano},
+    ArcLogger, ArchiveConfig, LogEntry, LogLevel, MasterKey, MonitorId,
 };
 use crawler::Crawler;
 use csv::deserialize_csv_option;
-use disk::UsageError;
-use fs::dir_fs;
+use fs::{dir_fs, union_fs};
+use index::RecordingIndex;
+use recording::{RecordingKey, UnwrapKeyError};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashSet,
+    ffi::OsStr,
     num::NonZeroUsize,
     ops::{Deref, DerefMut},
     path::{Path, PathBuf},
@@ -92,6 +99,14 @@ pub struct RecordingFinalized {
     data: Option<RecordingData>,
 }
 
+impl RecordingFinalized {
+    // Only set if the query included data.
+    #[must_use]
+    pub fn data(&self) -> Option<&RecordingData> {
+        self.data.as_ref()
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct RecordingIncomplete {
     id: RecordingId,
@@ -102,6 +117,9 @@ pub struct RecDb {
     recordings_dir: PathBuf,
     crawler: Crawler,
     disk: Disk,
+    index: Option<Arc<RecordingIndex>>,
+    archive: Option<ArchiveConfig>,
+    master_key: Option<MasterKey>,
 
     // There should only be one active recording per monitor.
     active_recordings: Arc<std::sync::Mutex<HashSet<RecordingId>>>,
@@ -122,6 +140,31 @@ pub enum NewRecordingError {
     RecordingId(#[from] RecordingIdError),
 }
 
+#[derive(Debug, Error)]
+pub enum RecordingKeyError {
+    #[error("recording is encrypted but no recording key is configured")]
+    NoMasterKey,
+
+    #[error("read key file: {0}")]
+    Read(std::io::Error),
+
+    #[error("unwrap key: {0}")]
+    Unwrap(#[from] UnwrapKeyError),
+}
+
+// Reads and unwraps the key file of a encrypted recording.
+pub async fn read_recording_key(
+    master_key: Option<&MasterKey>,
+    path: &Path,
+) -> Result<RecordingKey, RecordingKeyError> {
+    use RecordingKeyError::*;
+    let Some(master_key) = master_key else {
+        return Err(NoMasterKey);
+    };
+    let wrapped = tokio::fs::read(path).await.map_err(Read)?;
+    Ok(RecordingKey::from_wrapped(master_key, &wrapped)?)
+}
+
 #[derive(Debug, Error)]
 pub enum DeleteRecordingError {
     #[error("deleting active recordings is not implemented")]
@@ -140,18 +183,92 @@ pub enum DeleteRecordingError {
     Delete(std::io::Error),
 }
 
+#[derive(Debug, Error)]
+pub enum LockRecordingError {
+    #[error("recording doesn't exist")]
+    NotExist,
+
+    #[error("create lock file: {0}")]
+    Create(std::io::Error),
+
+    #[error("remove lock file: {0}")]
+    Remove(std::io::Error),
+}
+
 impl RecDb {
     #[must_use]
     pub fn new(logger: ArcLogger, recording_dir: PathBuf, disk: Disk) -> Self {
+        Self::with_archive(logger, recording_dir, disk, None)
+    }
+
+    // Old recordings are moved to the archive directory by `archive_loop`.
+    // Queries include the recordings in both directories.
+    #[must_use]
+    pub fn with_archive(
+        logger: ArcLogger,
+        recording_dir: PathBuf,
+        disk: Disk,
+        archive: Option<ArchiveConfig>,
+    ) -> Self {
+        let fs = match &archive {
+            Some(archive) => union_fs(vec![
+                dir_fs(recording_dir.clone()),
+                dir_fs(archive.dir.clone()),
+            ]),
+            None => dir_fs(recording_dir.clone()),
+        };
         Self {
             logger,
-            recordings_dir: recording_dir.clone(),
-            crawler: Crawler::new(dir_fs(recording_dir)),
+            recordings_dir: recording_dir,
+            crawler: Crawler::new(fs),
             disk,
+            index: None,
+            archive,
+            master_key: None,
             active_recordings: Arc::new(std::sync::Mutex::new(HashSet::new())),
         }
     }
 
+    // Queries are answered from an index stored at `index_path`
+    // instead of reading the recordings directory.
+    pub async fn with_index(
+        logger: ArcLogger,
+        recording_dir: PathBuf,
+        disk: Disk,
+        archive: Option<ArchiveConfig>,
+        index_path: PathBuf,
+    ) -> Result<Self, LoadIndexError> {
+        let archive_dir = archive.as_ref().map(|v| v.dir.clone());
+        let index = RecordingIndex::load(
+            logger.clone(),
+            recording_dir.clone(),
+            archive_dir,
+            index_path,
+        )
+        .await?;
+        Ok(Self {
+            index: Some(index),
+            ..Self::with_archive(logger, recording_dir, disk, archive)
+        })
+    }
+
+    // Key used to wrap the keys of encrypted recordings.
+    #[must_use]
+    pub fn with_master_key(mut self, master_key: Option<MasterKey>) -> Self {
+        self.master_key = master_key;
+        self
+    }
+
+    #[must_use]
+    pub fn master_key(&self) -> Option<&MasterKey> {
+        self.master_key.as_ref()
+    }
+
+    // The recordings directory followed by the archive directory.
+    fn roots(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.recordings_dir).chain(self.archive.as_ref().map(|v| &v.dir))
+    }
+
     // finds the best matching recording and
     // returns limit number of subsequent recorings.
     pub async fn recordings_by_query(
@@ -160,23 +277,48 @@ impl RecDb {
     ) -> Result<Vec<RecordingResponse>, CrawlerError> {
         // Do not hold onto the lock.
         let active_recordings = self.active_recordings.lock().expect("not poisoned").clone();
+        if let Some(index) = &self.index {
+            return Crawler::new(index.fs())
+                .recordings_by_query(query.clone(), active_recordings)
+                .await;
+        }
         self.crawler
             .recordings_by_query(query.clone(), active_recordings)
             .await
     }
 
     // Returns the full path of file tied to recording id by file extension.
+    // The recordings directory is checked before the archive directory.
     pub async fn recording_file_by_ext(&self, rec_id: &RecordingId, ext: &str) -> Option<PathBuf> {
         let full_relative_path = rec_id.as_full_path();
-        let mut path = self.recordings_dir.join(full_relative_path);
-        path.set_extension(ext);
-        let path = tokio::fs::canonicalize(path).await.ok()?;
+        for root in self.roots() {
+            let mut path = root.join(&full_relative_path);
+            path.set_extension(ext);
+            let Ok(path) = tokio::fs::canonicalize(path).await else {
+                continue;
+            };
+
+            let is_path_safe = path.starts_with(root);
+            if !is_path_safe {
+                return None;
+            };
+            return Some(path);
+        }
+        None
+    }
 
-        let is_path_safe = path.starts_with(&self.recordings_dir);
-        if !is_path_safe {
-            return None;
+    // Returns the key of an encrypted recording, or None if
+    // the recording doesn't have a key file.
+    pub async fn recording_key(
+        &self,
+        rec_id: &RecordingId,
+    ) -> Result<Option<RecordingKey>, RecordingKeyError> {
+        let Some(path) = self.recording_file_by_ext(rec_id, "key").await else {
+            return Ok(None);
         };
-        Some(path)
+        Ok(Some(
+            read_recording_key(self.master_key.as_ref(), &path).await?,
+        ))
     }
 
     // Returns full path to the thumbnail file for specified recording id.
@@ -223,6 +365,8 @@ impl RecDb {
 
         Ok(RecordingHandle {
             active_recordings: self.active_recordings.clone(),
+            index: self.index.clone(),
+            master_key: self.master_key.clone(),
             id: recording_id,
             path: path.clone(),
             open_files: Arc::new(std::sync::Mutex::new(HashSet::new())),
@@ -240,41 +384,91 @@ impl RecDb {
             return Err(Active);
         }
 
-        let Some(path) = self.recording_file_by_ext(&rec_id, "meta").await else {
+        if self.recording_file_by_ext(&rec_id, "meta").await.is_none() {
             return Err(NotExist);
         };
-        let dir = path
-            .parent()
-            .expect("path should have a parent")
-            .to_path_buf();
+        // The files may be split between the recordings and archive directory.
+        let dirs: Vec<PathBuf> = self
+            .roots()
+            .map(|root| {
+                root.join(rec_id.as_full_path())
+                    .parent()
+                    .expect("path should have a parent")
+                    .to_path_buf()
+            })
+            .collect();
 
+        let rec_path = self.recordings_dir.join(rec_id.as_full_path());
         tokio::task::spawn_blocking(move || {
             let mut res = Ok(());
-            for file in dir.read_dir().map_err(ReadDir)? {
-                let file = match file {
-                    Ok(v) => v,
-                    Err(e) => {
-                        res = Err(DirEntry(e));
-                        continue;
-                    }
-                };
-                let path = file.path();
-                let Some(file_name) = path.file_name() else {
+            for dir in dirs {
+                if !dir.exists() {
                     continue;
-                };
-                let Some(file_name) = file_name.to_str() else {
-                    continue;
-                };
-                if file_name.starts_with(rec_id.as_str()) {
-                    if let Err(e) = std::fs::remove_file(path) {
-                        res = Err(Delete(e));
+                }
+                for file in dir.read_dir().map_err(ReadDir)? {
+                    let file = match file {
+                        Ok(v) => v,
+                        Err(e) => {
+                            res = Err(DirEntry(e));
+                            continue;
+                        }
                     };
+                    let path = file.path();
+                    let Some(file_name) = path.file_name() else {
+                        continue;
+                    };
+                    let Some(file_name) = file_name.to_str() else {
+                        continue;
+                    };
+                    if file_name.starts_with(rec_id.as_str()) {
+                        if let Err(e) = std::fs::remove_file(path) {
+                            res = Err(Delete(e));
+                        };
+                    }
                 }
             }
             res
         })
         .await
-        .expect("join")
+        .expect("join")?;
+
+        if let Some(index) = &self.index {
+            index.remove_recording(&rec_path).await;
+        }
+        Ok(())
+    }
+
+    // Locked recordings are skipped by `prune()`. The lock is a
+    // `.lock` file next to the other files of the recording.
+    pub async fn set_locked(
+        &self,
+        rec_id: &RecordingId,
+        locked: bool,
+    ) -> Result<(), LockRecordingError> {
+        use LockRecordingError::*;
+        let Some(mut path) = self.recording_file_by_ext(rec_id, "meta").await else {
+            return Err(NotExist);
+        };
+        path.set_extension("lock");
+        if locked {
+            OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .await
+                .map_err(Create)?;
+            return Ok(());
+        }
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Remove(e)),
+        }
+    }
+
+    pub async fn is_locked(&self, rec_id: &RecordingId) -> bool {
+        self.recording_file_by_ext(rec_id, "lock").await.is_some()
     }
 
     pub async fn test_recording(&self) -> RecordingHandle {
@@ -300,6 +494,17 @@ impl RecDb {
         .len()
     }
 
+    // Returns the number of bytes used by the storage directory.
+    // The value is cached for up to 10 minutes.
+    pub async fn disk_usage(&self) -> Result<u64, UsageError> {
+        Ok(self.disk.usage(Duration::from_minutes(10)).await?.used)
+    }
+
+    // Percentage of the max disk usage that's used.
+    pub async fn disk_usage_percent(&self) -> Result<f32, UsageError> {
+        Ok(self.disk.usage(Duration::from_minutes(10)).await?.percent)
+    }
+
     // Runs `prune()` on an interval until the token is canceled.
     pub async fn prune_loop(&self, token: CancellationToken, interval: std::time::Duration) {
         loop {
@@ -319,7 +524,70 @@ impl RecDb {
         }
     }
 
+    // Runs `archive()` on an interval until the token is canceled.
+    pub async fn archive_loop(&self, token: CancellationToken, interval: std::time::Duration) {
+        if self.archive.is_none() {
+            return;
+        }
+        loop {
+            tokio::select! {
+                () = token.cancelled() => return,
+                () = tokio::time::sleep(interval) => {
+                    if let Err(e) = self.archive().await {
+                        self.logger.log(LogEntry::new(
+                            LogLevel::Error,
+                            "app",
+                            None,
+                            format!("failed to archive recordings: {e}"),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    // Moves the days that are older than the archive max age to the
+    // archive directory. Active recordings are left in place.
+    pub(crate) async fn archive(&self) -> Result<(), ArchiveError> {
+        let Some(archive) = &self.archive else {
+            return Ok(());
+        };
+        let Some(cutoff) = i64::try_from(archive.max_age.as_nanos())
+            .ok()
+            .and_then(|max_age| UnixNano::now().checked_sub(UnixNano::new(max_age)))
+        else {
+            return Ok(());
+        };
+        let cutoff = DateTime::<Utc>::from(cutoff).date_naive();
+
+        let active_recordings: HashSet<String> = self
+            .active_recordings
+            .lock()
+            .expect("not poisoned")
+            .iter()
+            .map(|v| v.as_str().to_owned())
+            .collect();
+        let recordings_dir = self.recordings_dir.clone();
+        let archive_dir = archive.dir.clone();
+        let archived = tokio::task::spawn_blocking(move || {
+            archive_days(&recordings_dir, &archive_dir, cutoff, &active_recordings)
+        })
+        .await
+        .expect("join")?;
+
+        for path in archived {
+            self.logger.log(LogEntry::new(
+                LogLevel::Info,
+                "app",
+                None,
+                format!("archived {path:?}"),
+            ));
+        }
+        Ok(())
+    }
+
     // Checks if disk usage is above 99% and if true deletes all files from the oldest day.
+    // Locked recordings are kept, days that only contain locked recordings are skipped.
     #[allow(clippy::items_after_statements)]
     pub(crate) async fn prune(&self) -> Result<(), PruneError> {
         use PruneError::*;
@@ -331,59 +599,157 @@ impl RecDb {
 
         const DAY_DEPTH: u8 = 3;
 
-        // Find the oldest day.
-        let mut path = self.recordings_dir.clone();
+        // Directories that only contain locked recordings.
+        let mut locked_dirs = HashSet::new();
 
-        let mut depth = 1;
-        while depth <= DAY_DEPTH {
-            let path2 = path.clone();
-            let entries = tokio::task::spawn_blocking(move || std::fs::read_dir(path2))
-                .await
-                .expect("join")
-                .map_err(ReadDir)?;
+        loop {
+            // Find the oldest day.
+            let mut path = self.recordings_dir.clone();
 
-            let mut list = Vec::new();
-            for entry in entries {
-                list.push(entry.map_err(DirEntry)?);
-            }
+            let mut depth = 1;
+            while depth <= DAY_DEPTH {
+                let path2 = path.clone();
+                let entries = tokio::task::spawn_blocking(move || std::fs::read_dir(path2))
+                    .await
+                    .expect("join")
+                    .map_err(ReadDir)?;
+
+                let mut list = Vec::new();
+                let mut is_empty = true;
+                for entry in entries {
+                    let entry = entry.map_err(DirEntry)?;
+                    is_empty = false;
+                    if !locked_dirs.contains(&entry.path()) {
+                        list.push(entry);
+                    }
+                }
+
+                if list.is_empty() {
+                    // Don't delete the recordings directory.
+                    if depth == 1 {
+                        return Ok(());
+                    }
 
-            if list.is_empty() {
-                // Don't delete the recordings directory.
-                if depth == 1 {
-                    return Ok(());
+                    let dir = std::mem::replace(&mut path, self.recordings_dir.clone());
+                    if is_empty {
+                        // Remove empty directory.
+                        tokio::fs::remove_dir_all(&dir)
+                            .await
+                            .map_err(RemoveDirAll)?;
+                    } else {
+                        locked_dirs.insert(dir);
+                    }
+                    depth = 1;
+                    continue;
                 }
 
-                // Remove empty directory.
+                list.sort_by_key(std::fs::DirEntry::path);
+                let first_file = list[0].file_name();
+                path = path.join(first_file);
+
+                depth += 1;
+            }
+
+            let path2 = path.clone();
+            let locked = tokio::task::spawn_blocking(move || locked_recordings(&path2))
+                .await
+                .expect("join")?;
+
+            if locked.is_empty() {
+                self.logger.log(LogEntry::new(
+                    LogLevel::Info,
+                    "app",
+                    None,
+                    format!("pruning storage: deleting {path:?}"),
+                ));
+
+                // Delete all files from that day
                 tokio::fs::remove_dir_all(&path)
                     .await
                     .map_err(RemoveDirAll)?;
 
-                path = self.recordings_dir.clone();
-                depth = 1;
-                continue;
+                if let Some(index) = &self.index {
+                    index.remove_dir(&path).await;
+                }
+                return Ok(());
             }
 
-            list.sort_by_key(std::fs::DirEntry::path);
-            let first_file = list[0].file_name();
-            path = path.join(first_file);
+            let path2 = path.clone();
+            let deleted =
+                tokio::task::spawn_blocking(move || delete_unlocked_recordings(&path2, &locked))
+                    .await
+                    .expect("join")?;
 
-            depth += 1;
-        }
+            if deleted.is_empty() {
+                locked_dirs.insert(path);
+                continue;
+            }
 
-        self.logger.log(LogEntry::new(
-            LogLevel::Info,
-            "app",
-            None,
-            format!("pruning storage: deleting {path:?}"),
-        ));
+            self.logger.log(LogEntry::new(
+                LogLevel::Info,
+                "app",
+                None,
+                format!("pruning storage: deleting unlocked recordings in {path:?}"),
+            ));
+            if let Some(index) = &self.index {
+                for rec_path in deleted {
+                    index.remove_recording(&rec_path).await;
+                }
+            }
+            return Ok(());
+        }
+    }
+}
 
-        // Delete all files from that day
-        tokio::fs::remove_dir_all(&path)
-            .await
-            .map_err(RemoveDirAll)?;
+// Returns the paths, without extension, of the
+// locked recordings in the monitor directories of a day.
+fn locked_recordings(day_dir: &Path) -> Result<HashSet<PathBuf>, PruneError> {
+    use PruneError::*;
+    let mut locked = HashSet::new();
+    for monitor_dir in std::fs::read_dir(day_dir).map_err(ReadDir)? {
+        let monitor_dir = monitor_dir.map_err(DirEntry)?.path();
+        if !monitor_dir.is_dir() {
+            continue;
+        }
+        for file in std::fs::read_dir(&monitor_dir).map_err(ReadDir)? {
+            let path = file.map_err(DirEntry)?.path();
+            if path.extension() == Some(OsStr::new("lock")) {
+                locked.insert(path.with_extension(""));
+            }
+        }
+    }
+    Ok(locked)
+}
 
-        Ok(())
+// Deletes the files of every recording in the day that isn't locked.
+// Returns the paths, without extension, of the deleted recordings.
+fn delete_unlocked_recordings(
+    day_dir: &Path,
+    locked: &HashSet<PathBuf>,
+) -> Result<Vec<PathBuf>, PruneError> {
+    use PruneError::*;
+    let mut deleted = Vec::new();
+    for monitor_dir in std::fs::read_dir(day_dir).map_err(ReadDir)? {
+        let monitor_dir = monitor_dir.map_err(DirEntry)?.path();
+        if !monitor_dir.is_dir() {
+            continue;
+        }
+        for file in std::fs::read_dir(&monitor_dir).map_err(ReadDir)? {
+            let path = file.map_err(DirEntry)?.path();
+            if !path.is_file() {
+                continue;
+            }
+            let rec_path = path.with_extension("");
+            if locked.contains(&rec_path) {
+                continue;
+            }
+            std::fs::remove_file(&path).map_err(RemoveFile)?;
+            if !deleted.contains(&rec_path) {
+                deleted.push(rec_path);
+            }
+        }
     }
+    Ok(deleted)
 }
 
 #[derive(Debug, Error)]
@@ -399,10 +765,15 @@ pub(crate) enum PruneError {
 
     #[error("remove dir all: {0}")]
     RemoveDirAll(std::io::Error),
+
+    #[error("remove file: {0}")]
+    RemoveFile(std::io::Error),
 }
 
 pub struct RecordingHandle {
     active_recordings: Arc<std::sync::Mutex<HashSet<RecordingId>>>,
+    index: Option<Arc<RecordingIndex>>,
+    master_key: Option<MasterKey>,
     id: RecordingId,
 
     path: PathBuf,
@@ -418,12 +789,39 @@ pub enum OpenFileError {
     OpenFile(PathBuf, std::io::Error),
 }
 
+#[derive(Debug, Error)]
+pub enum NewKeyError {
+    #[error("no recording key is configured")]
+    NoMasterKey,
+
+    #[error("{0}")]
+    OpenFile(#[from] OpenFileError),
+
+    #[error("write key file: {0}")]
+    Write(std::io::Error),
+}
+
 impl RecordingHandle {
     #[must_use]
     pub fn id(&self) -> &RecordingId {
         &self.id
     }
 
+    // Generates a key for the recording and stores
+    // it, wrapped with the master key, in the key file.
+    pub async fn new_key(&self) -> Result<RecordingKey, NewKeyError> {
+        use tokio::io::AsyncWriteExt;
+        use NewKeyError::*;
+        let Some(master_key) = &self.master_key else {
+            return Err(NoMasterKey);
+        };
+        let key = RecordingKey::generate();
+        let mut file = self.new_file("key").await?;
+        file.write_all(&key.wrap(master_key)).await.map_err(Write)?;
+        file.sync_all().await.map_err(Write)?;
+        Ok(key)
+    }
+
     pub async fn new_file(&self, ext: &str) -> Result<FileHandle, OpenFileError> {
         let mut options = OpenOptions::new();
         let options = options.create_new(true).write(true);
@@ -451,6 +849,10 @@ impl RecordingHandle {
             .await
             .map_err(|e| OpenFile(path.clone(), e))?;
 
+        if let Some(index) = &self.index {
+            index.insert(&path).await;
+        }
+
         {
             let mut open_files = self.open_files.lock().expect("not poisoned");
             if open_files.contains(&ext) {
@@ -589,6 +991,35 @@ mod tests {
         recording.open_file("json").await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_recording_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let master_key = MasterKey::new([1; 32]);
+
+        let rec_db = new_test_recdb(temp_dir.path());
+        let recording = rec_db.test_recording().await;
+        assert!(matches!(
+            recording.new_key().await,
+            Err(NewKeyError::NoMasterKey)
+        ));
+        drop(recording);
+
+        let rec_db = new_test_recdb(temp_dir.path()).with_master_key(Some(master_key));
+        let recording = rec_db.test_recording().await;
+        let rec_id = recording.id().clone();
+        assert!(rec_db.recording_key(&rec_id).await.unwrap().is_none());
+
+        recording.new_key().await.unwrap();
+        assert!(rec_db.recording_key(&rec_id).await.unwrap().is_some());
+
+        // The key can't be read without the master key.
+        let rec_db = new_test_recdb(temp_dir.path());
+        assert!(matches!(
+            rec_db.recording_key(&rec_id).await,
+            Err(RecordingKeyError::NoMasterKey)
+        ));
+    }
+
     #[tokio::test]
     async fn test_delete_recording() {
         let recordings_dir = TempDir::new().unwrap();
@@ -623,6 +1054,49 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_archive() {
+        let recordings_dir = TempDir::new().unwrap();
+        let archive_dir = TempDir::new().unwrap();
+        let rec_dir = recordings_dir.path().join("2000/01/01/m1");
+        let rec_id = "2000-01-01_02-02-02_m1";
+        std::fs::create_dir_all(&rec_dir).unwrap();
+        create_files(&rec_dir, &[rec_id.to_owned() + ".meta"]);
+
+        let disk = Disk::new(recordings_dir.path().to_path_buf(), ByteSize(0));
+        let archive = ArchiveConfig {
+            dir: archive_dir.path().to_path_buf(),
+            max_age: std::time::Duration::from_secs(24 * 60 * 60),
+        };
+        let rec_db = RecDb::with_archive(
+            DummyLogger::new(),
+            recordings_dir.path().to_path_buf(),
+            disk,
+            Some(archive),
+        );
+        // Active recordings aren't moved.
+        let recording = rec_db.test_recording().await;
+        recording.new_file("meta").await.unwrap();
+        assert_eq!(rec_db.count_recordings().await, 2);
+
+        rec_db.archive().await.unwrap();
+        assert!(!recordings_dir.path().join("2000").exists());
+        assert!(recordings_dir.path().join("1970").exists());
+        assert_eq!(rec_db.count_recordings().await, 2);
+
+        let archived_path = archive_dir
+            .path()
+            .join("2000/01/01/m1")
+            .join(rec_id.to_owned() + ".meta");
+        let rec_id: RecordingId = rec_id.to_owned().try_into().unwrap();
+        assert_eq!(
+            Some(archived_path),
+            rec_db.recording_file_by_ext(&rec_id, "meta").await,
+        );
+        rec_db.delete_recording(rec_id).await.unwrap();
+        assert_eq!(rec_db.count_recordings().await, 1);
+    }
+
     fn create_files(dir: &Path, files: &[String]) {
         for file in files {
             std::fs::OpenOptions::new()
@@ -689,6 +1163,72 @@ mod tests {
         assert_eq!(after, list_empty_dirs(temp_dir.path()));
     }
 
+    #[tokio::test]
+    async fn test_prune_locked() {
+        let recordings_dir = TempDir::new().unwrap();
+        let day1 = recordings_dir.path().join("2000/01/01/m1");
+        let day2 = recordings_dir.path().join("2000/01/02/m1");
+        std::fs::create_dir_all(&day1).unwrap();
+        std::fs::create_dir_all(&day2).unwrap();
+        let locked = "2000-01-01_01-01-01_m1";
+        let unlocked = "2000-01-01_02-02-02_m1";
+        create_files(
+            &day1,
+            &[
+                locked.to_owned() + ".meta",
+                locked.to_owned() + ".mdat",
+                unlocked.to_owned() + ".meta",
+                unlocked.to_owned() + ".mdat",
+            ],
+        );
+        create_files(&day2, &["2000-01-02_01-01-01_m1.meta".to_owned()]);
+
+        let disk = Disk::with_disk_usage(
+            recordings_dir.path().to_path_buf(),
+            ByteSize(GB),
+            Box::new(StubDiskUsageBytes(1_000_000_000)),
+        );
+        let rec_db = RecDb::new(
+            DummyLogger::new(),
+            recordings_dir.path().to_path_buf(),
+            disk,
+        );
+        let locked_id: RecordingId = locked.to_owned().try_into().unwrap();
+        rec_db.set_locked(&locked_id, true).await.unwrap();
+        assert!(rec_db.is_locked(&locked_id).await);
+        assert_eq!(rec_db.count_recordings().await, 3);
+
+        // The unlocked recording is deleted first, then the next day.
+        rec_db.prune().await.unwrap();
+        assert_eq!(
+            vec![
+                locked.to_owned() + ".lock",
+                locked.to_owned() + ".mdat",
+                locked.to_owned() + ".meta",
+            ],
+            list_directory(&day1)
+        );
+        assert!(day2.exists());
+
+        rec_db.prune().await.unwrap();
+        assert!(!recordings_dir.path().join("2000/01/02").exists());
+        assert_eq!(rec_db.count_recordings().await, 1);
+
+        // Only locked recordings are left.
+        rec_db.prune().await.unwrap();
+        assert_eq!(rec_db.count_recordings().await, 1);
+
+        rec_db.set_locked(&locked_id, false).await.unwrap();
+        assert!(!rec_db.is_locked(&locked_id).await);
+        rec_db.prune().await.unwrap();
+        assert_eq!(rec_db.count_recordings().await, 0);
+
+        assert!(matches!(
+            rec_db.set_locked(&locked_id, true).await,
+            Err(LockRecordingError::NotExist)
+        ));
+    }
+
     fn write_empty_dirs(base: &Path, paths: &[&str]) {
         for path in paths {
             std::fs::create_dir_all(base.join(path)).unwrap();
@@ -0,0 +1,294 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::{
+    video::{read_meta, ReadMetaError},
+    video_reader::{sample_stream, ReadSampleError},
+};
+use futures_lite::StreamExt;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, AsyncWriteExt};
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+const NAL_TYPE_SPS: u8 = 7;
+const NAL_TYPE_PPS: u8 = 8;
+
+#[derive(Debug, Error)]
+pub enum AvccToAnnexbError {
+    #[error("nalu size {0} is larger than the remaining {1} bytes")]
+    NaluSize(usize, usize),
+
+    #[error("trailing bytes: {0}")]
+    Trailing(usize),
+}
+
+// Converts a AVCC sample with 4 byte length prefixes to Annex-B. Every NAL
+// unit is kept as is, including AUD and SEI, only the prefixes are replaced.
+pub fn avcc_to_annexb(mut avcc: &[u8], out: &mut Vec<u8>) -> Result<(), AvccToAnnexbError> {
+    use AvccToAnnexbError::*;
+    while avcc.len() >= 4 {
+        let (size, rest) = avcc.split_at(4);
+        let size = usize::try_from(u32::from_be_bytes([size[0], size[1], size[2], size[3]]))
+            .unwrap_or(usize::MAX);
+        if size > rest.len() {
+            return Err(NaluSize(size, rest.len()));
+        }
+        let (nalu, rest) = rest.split_at(size);
+        out.extend_from_slice(&START_CODE);
+        out.extend_from_slice(nalu);
+        avcc = rest;
+    }
+    if !avcc.is_empty() {
+        return Err(Trailing(avcc.len()));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum ParseAvcCError {
+    #[error("too short")]
+    TooShort,
+
+    #[error("unsupported nalu length size: {0}")]
+    LengthSize(u8),
+}
+
+// Returns the SPS and PPS NAL units from a AVCDecoderConfigurationRecord.
+fn parameter_sets(avcc: &[u8]) -> Result<Vec<&[u8]>, ParseAvcCError> {
+    use ParseAvcCError::*;
+    let length_size = avcc.get(4).ok_or(TooShort)? & 0b11;
+    if length_size != 3 {
+        return Err(LengthSize(length_size + 1));
+    }
+
+    let mut sets = Vec::new();
+    let mut buf = avcc.get(5..).ok_or(TooShort)?;
+    // SPS count is 5 bits, PPS count is 8 bits.
+    for mask in [0x1f, 0xff] {
+        let (count, rest) = buf.split_first().ok_or(TooShort)?;
+        buf = rest;
+        for _ in 0..(count & mask) {
+            let size = buf.get(..2).ok_or(TooShort)?;
+            let size = usize::from(u16::from_be_bytes([size[0], size[1]]));
+            sets.push(buf.get(2..2 + size).ok_or(TooShort)?);
+            buf = &buf[2 + size..];
+        }
+    }
+    Ok(sets)
+}
+
+fn avcc_has_parameter_sets(mut avcc: &[u8]) -> bool {
+    while avcc.len() > 4 {
+        let size = usize::try_from(u32::from_be_bytes([avcc[0], avcc[1], avcc[2], avcc[3]]))
+            .unwrap_or(usize::MAX);
+        let nal_type = avcc[4] & 0x1f;
+        if nal_type == NAL_TYPE_SPS || nal_type == NAL_TYPE_PPS {
+            return true;
+        }
+        let Some(rest) = avcc.get(4_usize.saturating_add(size)..) else {
+            return false;
+        };
+        avcc = rest;
+    }
+    false
+}
+
+#[derive(Debug, Error)]
+pub enum ExportAnnexbError {
+    #[error("read meta: {0}")]
+    ReadMeta(#[from] ReadMetaError),
+
+    #[error("parse avcC: {0}")]
+    ParseAvcC(#[from] ParseAvcCError),
+
+    #[error("convert sample {0}: {1}")]
+    Convert(usize, AvccToAnnexbError),
+
+    #[error("read sample: {0}")]
+    ReadSample(#[from] ReadSampleError),
+
+    #[error("write: {0}")]
+    Write(std::io::Error),
+}
+
+// Writes a recording as a Annex-B elementary stream. Samples are stored
+// with all their NAL units, so the output is identical to the received
+// stream. If `insert_parameter_sets` is true, the SPS and PPS from the meta
+// header are inserted before random access samples that don't carry them.
+pub async fn export_annexb<M, D, W>(
+    meta: M,
+    meta_size: u64,
+    mdat: D,
+    out: &mut W,
+    insert_parameter_sets: bool,
+) -> Result<(), ExportAnnexbError>
+where
+    M: AsyncRead + Unpin,
+    D: AsyncRead + AsyncSeek + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    use ExportAnnexbError::*;
+    let (header, samples) = read_meta(meta, meta_size).await?;
+    let parameter_sets = if insert_parameter_sets {
+        parameter_sets(&header.extra_data)?
+    } else {
+        Vec::new()
+    };
+
+    let samples = sample_stream(mdat, samples).enumerate();
+    tokio::pin!(samples);
+    let mut annexb = Vec::new();
+    while let Some((i, sample)) = samples.next().await {
+        let sample = sample?;
+        let avcc: &[u8] = &sample.avcc;
+
+        annexb.clear();
+        avcc_to_annexb(avcc, &mut annexb).map_err(|e| Convert(i, e))?;
+        if sample.random_access_present
+            && !parameter_sets.is_empty()
+            && !avcc_has_parameter_sets(avcc)
+        {
+            for set in &parameter_sets {
+                out.write_all(&START_CODE).await.map_err(Write)?;
+                out.write_all(set).await.map_err(Write)?;
+            }
+        }
+        out.write_all(&annexb).await.map_err(Write)?;
+    }
+    out.flush().await.map_err(Write)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MetaHeader, VideoWriter};
+    use common::{
+        time::{DtsOffset, DurationH264, UnixH264},
+        PartFinalized, VideoSample,
+    };
+    use pretty_assertions::assert_eq;
+    use sentryshot_padded_bytes::PaddedBytes;
+    use std::{io::Cursor, sync::Arc};
+
+    const AUD: &[u8] = &[0x09, 0xf0];
+    const SEI: &[u8] = &[0x06, 0x05, 0x02, 0x00, 0x03, 0x80];
+    const SPS: &[u8] = &[0x67, 0x64, 0x00, 0x1f];
+    const PPS: &[u8] = &[0x68, 0xee, 0x3c];
+    const IDR: &[u8] = &[0x65, 0x88, 0x84, 0x00, 0x00, 0x03, 0x01];
+    const SLICE: &[u8] = &[0x41, 0x9a, 0x00];
+
+    fn avcc(nalus: &[&[u8]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for nalu in nalus {
+            out.extend_from_slice(&u32::try_from(nalu.len()).unwrap().to_be_bytes());
+            out.extend_from_slice(nalu);
+        }
+        out
+    }
+
+    fn annexb(nalus: &[&[u8]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for nalu in nalus {
+            out.extend_from_slice(&START_CODE);
+            out.extend_from_slice(nalu);
+        }
+        out
+    }
+
+    fn extra_data() -> Vec<u8> {
+        let mut out = vec![1, 0x64, 0, 0x1f, 0xff, 0xe1];
+        out.extend_from_slice(&u16::try_from(SPS.len()).unwrap().to_be_bytes());
+        out.extend_from_slice(SPS);
+        out.push(1);
+        out.extend_from_slice(&u16::try_from(PPS.len()).unwrap().to_be_bytes());
+        out.extend_from_slice(PPS);
+        out
+    }
+
+    fn sample(pts: i64, random_access_present: bool, nalus: &[&[u8]]) -> VideoSample {
+        VideoSample {
+            pts: UnixH264::new(pts),
+            dts_offset: DtsOffset::new(0),
+            random_access_present,
+            avcc: Arc::new(PaddedBytes::new(avcc(nalus))),
+            duration: DurationH264::new(10),
+        }
+    }
+
+    async fn write_recording(samples: Vec<VideoSample>) -> (Vec<u8>, Vec<u8>) {
+        let mut meta = Vec::new();
+        let mut mdat = Vec::new();
+        let header = MetaHeader {
+            start_time: UnixH264::new(0),
+            width: 1,
+            height: 1,
+            extra_data: extra_data(),
+            audio: None,
+        };
+        let mut w = VideoWriter::new(&mut meta, &mut mdat, header)
+            .await
+            .unwrap();
+        let parts = vec![Arc::new(PartFinalized {
+            video_samples: Arc::new(samples),
+            ..Default::default()
+        })];
+        w.write_parts(&parts).await.unwrap();
+        (meta, mdat)
+    }
+
+    async fn export(meta: &[u8], mdat: &[u8], insert_parameter_sets: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        export_annexb(
+            Cursor::new(meta),
+            u64::try_from(meta.len()).unwrap(),
+            Cursor::new(mdat),
+            &mut out,
+            insert_parameter_sets,
+        )
+        .await
+        .unwrap();
+        out
+    }
+
+    #[tokio::test]
+    async fn test_export_annexb_identical() {
+        let (meta, mdat) = write_recording(vec![
+            sample(0, true, &[AUD, SEI, SPS, PPS, IDR]),
+            sample(10, false, &[AUD, SEI, SLICE]),
+        ])
+        .await;
+
+        let want = annexb(&[AUD, SEI, SPS, PPS, IDR, AUD, SEI, SLICE]);
+        assert_eq!(want, export(&meta, &mdat, false).await);
+        assert_eq!(want, export(&meta, &mdat, true).await);
+    }
+
+    #[tokio::test]
+    async fn test_export_annexb_insert_parameter_sets() {
+        let (meta, mdat) = write_recording(vec![
+            sample(0, true, &[AUD, IDR]),
+            sample(10, false, &[AUD, SLICE]),
+        ])
+        .await;
+
+        let want = annexb(&[AUD, IDR, AUD, SLICE]);
+        assert_eq!(want, export(&meta, &mdat, false).await);
+
+        let want = annexb(&[SPS, PPS, AUD, IDR, AUD, SLICE]);
+        assert_eq!(want, export(&meta, &mdat, true).await);
+    }
+
+    #[test]
+    fn test_avcc_to_annexb_invalid() {
+        let mut out = Vec::new();
+        assert!(matches!(
+            avcc_to_annexb(&[0, 0, 0, 5, 1], &mut out),
+            Err(AvccToAnnexbError::NaluSize(5, 1))
+        ));
+        assert!(matches!(
+            avcc_to_annexb(&[0, 0, 0, 1, 1, 2], &mut out),
+            Err(AvccToAnnexbError::Trailing(1))
+        ));
+    }
+}
@@ -0,0 +1,514 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use common::MasterKey;
+use std::{
+    fmt,
+    future::Future,
+    io::{self, SeekFrom},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use thiserror::Error;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, BufReader,
+    ReadBuf,
+};
+
+// Encrypted mdat file.
+//
+// chunk {
+//     size u32 // Size of the ciphertext, including the tag.
+//     ciphertext [size]u8
+// }
+//
+// The nonce of a chunk is its index in the file, a key is only used for a
+// single recording. The data offsets in the meta file point to the plaintext.
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+const CHUNK_HEADER_SIZE: u64 = 4;
+
+// A chunk is written on every flush or when it reaches this size.
+const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+// Buffer size used when reading the chunk headers.
+const HEADER_BUF_SIZE: usize = 64 * 1024;
+
+// Key of a single recording. It's stored next to
+// the recording, encrypted by the master key.
+#[derive(Clone)]
+pub struct RecordingKey(Key<Aes256Gcm>);
+
+// The key must never end up in the logs.
+impl fmt::Debug for RecordingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RecordingKey(..)")
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum UnwrapKeyError {
+    #[error("invalid size: {0}")]
+    InvalidSize(usize),
+
+    #[error("wrong master key or corrupt key file")]
+    Decrypt,
+}
+
+impl RecordingKey {
+    #[must_use]
+    pub fn generate() -> Self {
+        Self(Aes256Gcm::generate_key(OsRng))
+    }
+
+    // Returns the key encrypted by the master key, prefixed by the nonce.
+    #[must_use]
+    pub fn wrap(&self, master_key: &MasterKey) -> Vec<u8> {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(master_key.as_bytes()));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, self.0.as_slice())
+            .expect("encrypting a key should not fail");
+        let mut out = nonce.to_vec();
+        out.extend_from_slice(&ciphertext);
+        out
+    }
+
+    pub fn from_wrapped(master_key: &MasterKey, wrapped: &[u8]) -> Result<Self, UnwrapKeyError> {
+        use UnwrapKeyError::*;
+        if wrapped.len() != NONCE_SIZE + KEY_SIZE + TAG_SIZE {
+            return Err(InvalidSize(wrapped.len()));
+        }
+        let (nonce, ciphertext) = wrapped.split_at(NONCE_SIZE);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(master_key.as_bytes()));
+        let key = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| Decrypt)?;
+        Ok(Self(*Key::<Aes256Gcm>::from_slice(&key)))
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.0)
+    }
+}
+
+fn chunk_nonce(index: u64) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0; NONCE_SIZE];
+    nonce[4..].copy_from_slice(&index.to_be_bytes());
+    nonce
+}
+
+// Encrypts the data written to the inner writer. The data is buffered
+// until it's flushed, every flush writes a chunk.
+pub struct EncryptWriter<W> {
+    inner: W,
+    cipher: Aes256Gcm,
+    chunk_index: u64,
+
+    // Plaintext of the next chunk.
+    buf: Vec<u8>,
+
+    // Encrypted chunk that is being written.
+    out: Vec<u8>,
+    out_pos: usize,
+}
+
+impl<W: AsyncWrite + Unpin> EncryptWriter<W> {
+    pub fn new(inner: W, key: &RecordingKey) -> Self {
+        Self {
+            inner,
+            cipher: key.cipher(),
+            chunk_index: 0,
+            buf: Vec::new(),
+            out: Vec::new(),
+            out_pos: 0,
+        }
+    }
+
+    fn seal_chunk(&mut self) -> io::Result<()> {
+        let nonce = chunk_nonce(self.chunk_index);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), self.buf.as_slice())
+            .map_err(|_| io::Error::other("encrypt chunk"))?;
+        let size = u32::try_from(ciphertext.len()).expect("chunk size should fit u32");
+        self.out.extend_from_slice(&size.to_be_bytes());
+        self.out.extend_from_slice(&ciphertext);
+        self.buf.clear();
+        self.chunk_index += 1;
+        Ok(())
+    }
+
+    fn poll_write_out(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.out_pos < self.out.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.out[self.out_pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.out_pos += n;
+        }
+        self.out.clear();
+        self.out_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for EncryptWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_out(cx))?;
+        if this.buf.len() >= MAX_CHUNK_SIZE {
+            this.seal_chunk()?;
+            ready!(this.poll_write_out(cx))?;
+        }
+        let n = std::cmp::min(data.len(), MAX_CHUNK_SIZE - this.buf.len());
+        this.buf.extend_from_slice(&data[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_out(cx))?;
+        if !this.buf.is_empty() {
+            this.seal_chunk()?;
+            ready!(this.poll_write_out(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Chunk {
+    plain_start: u64,
+    offset: u64,
+    size: u32,
+}
+
+impl Chunk {
+    fn plain_end(&self) -> u64 {
+        self.plain_start + u64::from(self.size) - u64::try_from(TAG_SIZE).expect("u64 fit usize")
+    }
+}
+
+type LoadChunkFut<R> = Pin<Box<dyn Future<Output = (R, io::Result<Vec<u8>>)> + Send + Sync>>;
+
+enum DecryptState<R> {
+    // The last decrypted chunk is kept.
+    Idle(R, Option<(usize, Vec<u8>)>),
+    Loading(usize, LoadChunkFut<R>),
+    Empty,
+}
+
+// Reads the plaintext of a encrypted mdat file. Seeking is cheap,
+// only the chunk at the position is read and decrypted.
+pub struct DecryptReader<R> {
+    cipher: Aes256Gcm,
+    chunks: Vec<Chunk>,
+    pos: u64,
+    state: DecryptState<R>,
+}
+
+impl<R> fmt::Debug for DecryptReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DecryptReader")
+            .field("chunks", &self.chunks.len())
+            .field("pos", &self.pos)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync + 'static> DecryptReader<R> {
+    // Reads the chunk headers. A incomplete chunk at the end of
+    // the file, from a recording in progress, is ignored.
+    pub async fn new(inner: R, key: &RecordingKey) -> io::Result<Self> {
+        // Chunks are often small, the buffer
+        // avoids a seek and read for each one.
+        let mut inner = BufReader::with_capacity(HEADER_BUF_SIZE, inner);
+        let file_size = inner.seek(SeekFrom::End(0)).await?;
+        inner.seek(SeekFrom::Start(0)).await?;
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+        let mut plain_start = 0;
+        while offset + CHUNK_HEADER_SIZE <= file_size {
+            let size = inner.read_u32().await?;
+            if usize::try_from(size).expect("usize fit u32") < TAG_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "chunk smaller than tag",
+                ));
+            }
+            let chunk = Chunk {
+                plain_start,
+                offset: offset + CHUNK_HEADER_SIZE,
+                size,
+            };
+            offset = chunk.offset + u64::from(size);
+            if offset > file_size {
+                break;
+            }
+            skip(&mut inner, size).await?;
+            plain_start = chunk.plain_end();
+            chunks.push(chunk);
+        }
+        Ok(Self {
+            cipher: key.cipher(),
+            chunks,
+            pos: 0,
+            state: DecryptState::Idle(inner.into_inner(), None),
+        })
+    }
+
+    // Size of the plaintext.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.chunks.last().map_or(0, Chunk::plain_end)
+    }
+}
+
+// Skips the ciphertext of a chunk, without seeking if it's buffered.
+async fn skip<R: AsyncRead + AsyncSeek + Unpin>(
+    reader: &mut BufReader<R>,
+    size: u32,
+) -> io::Result<()> {
+    let size = usize::try_from(size).expect("usize fit u32");
+    if size <= reader.buffer().len() {
+        reader.consume(size);
+        return Ok(());
+    }
+    let size = i64::try_from(size).expect("i64 fit usize");
+    reader.seek(SeekFrom::Current(size)).await?;
+    Ok(())
+}
+
+async fn load_chunk<R: AsyncRead + AsyncSeek + Unpin>(
+    mut inner: R,
+    cipher: Aes256Gcm,
+    index: usize,
+    chunk: Chunk,
+) -> (R, io::Result<Vec<u8>>) {
+    let res = async {
+        inner.seek(SeekFrom::Start(chunk.offset)).await?;
+        let mut ciphertext = vec![0; usize::try_from(chunk.size).expect("usize fit u32")];
+        inner.read_exact(&mut ciphertext).await?;
+        let nonce = chunk_nonce(u64::try_from(index).expect("u64 fit usize"));
+        cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "decrypt chunk"))
+    }
+    .await;
+    (inner, res)
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync + 'static> AsyncRead for DecryptReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            match &mut this.state {
+                DecryptState::Idle(_, Some((i, data)))
+                    if this.chunks[*i].plain_start <= this.pos
+                        && this.pos < this.chunks[*i].plain_end() =>
+                {
+                    let start = usize::try_from(this.pos - this.chunks[*i].plain_start)
+                        .expect("usize fit u64");
+                    let amt = std::cmp::min(buf.remaining(), data.len() - start);
+                    buf.put_slice(&data[start..][..amt]);
+                    this.pos += u64::try_from(amt).expect("u64 fit usize");
+                    return Poll::Ready(Ok(()));
+                }
+                DecryptState::Idle(..) => {
+                    let i = this.chunks.partition_point(|v| v.plain_end() <= this.pos);
+                    let Some(chunk) = this.chunks.get(i).copied() else {
+                        // EOF.
+                        return Poll::Ready(Ok(()));
+                    };
+                    let DecryptState::Idle(inner, _) =
+                        std::mem::replace(&mut this.state, DecryptState::Empty)
+                    else {
+                        unreachable!()
+                    };
+                    let fut = load_chunk(inner, this.cipher.clone(), i, chunk);
+                    this.state = DecryptState::Loading(i, Box::pin(fut));
+                }
+                DecryptState::Loading(i, fut) => {
+                    let i = *i;
+                    let (inner, res) = ready!(fut.as_mut().poll(cx));
+                    match res {
+                        Ok(data) => this.state = DecryptState::Idle(inner, Some((i, data))),
+                        Err(e) => {
+                            this.state = DecryptState::Idle(inner, None);
+                            return Poll::Ready(Err(e));
+                        }
+                    }
+                }
+                DecryptState::Empty => unreachable!(),
+            }
+        }
+    }
+}
+
+impl<R: Unpin> AsyncSeek for DecryptReader<R> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let size = this.chunks.last().map_or(0, Chunk::plain_end);
+        let pos = match position {
+            SeekFrom::Start(v) => Some(v),
+            SeekFrom::Current(v) => this.pos.checked_add_signed(v),
+            SeekFrom::End(v) => size.checked_add_signed(v),
+        };
+        this.pos = pos.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.pos))
+    }
+}
+
+// Mdat file of a recording, decrypted on read if the recording has a key.
+#[derive(Debug)]
+pub enum MdatFile {
+    Plain(tokio::fs::File),
+    Encrypted(Box<DecryptReader<tokio::fs::File>>),
+}
+
+impl MdatFile {
+    pub async fn new(file: tokio::fs::File, key: Option<&RecordingKey>) -> io::Result<Self> {
+        Ok(match key {
+            Some(key) => Self::Encrypted(Box::new(DecryptReader::new(file, key).await?)),
+            None => Self::Plain(file),
+        })
+    }
+}
+
+impl AsyncRead for MdatFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(v) => Pin::new(v).poll_read(cx, buf),
+            Self::Encrypted(v) => Pin::new(v).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncSeek for MdatFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match self.get_mut() {
+            Self::Plain(v) => Pin::new(v).start_seek(position),
+            Self::Encrypted(v) => Pin::new(v).start_seek(position),
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        match self.get_mut() {
+            Self::Plain(v) => Pin::new(v).poll_complete(cx),
+            Self::Encrypted(v) => Pin::new(v).poll_complete(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn test_wrap_key() {
+        let master_key = MasterKey::new([1; 32]);
+        let key = RecordingKey::generate();
+        let wrapped = key.wrap(&master_key);
+
+        let got = RecordingKey::from_wrapped(&master_key, &wrapped).unwrap();
+        assert_eq!(key.0, got.0);
+
+        let wrong_key = MasterKey::new([2; 32]);
+        assert!(matches!(
+            RecordingKey::from_wrapped(&wrong_key, &wrapped),
+            Err(UnwrapKeyError::Decrypt)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_decrypt() {
+        let key = RecordingKey::generate();
+        let mut encrypted = Vec::new();
+        let mut w = EncryptWriter::new(&mut encrypted, &key);
+        w.write_all(b"abc").await.unwrap();
+        w.write_all(b"de").await.unwrap();
+        w.flush().await.unwrap();
+        w.write_all(b"fgh").await.unwrap();
+        w.flush().await.unwrap();
+        // Empty flushes don't write chunks.
+        w.flush().await.unwrap();
+        assert_eq!(2 * (4 + TAG_SIZE) + 8, encrypted.len());
+        assert!(!encrypted.windows(3).any(|v| v == b"abc"));
+
+        let mut r = DecryptReader::new(Cursor::new(encrypted.clone()), &key)
+            .await
+            .unwrap();
+        assert_eq!(8, r.size());
+        let mut got = Vec::new();
+        r.read_to_end(&mut got).await.unwrap();
+        assert_eq!(b"abcdefgh", got.as_slice());
+
+        // Read across the chunk boundary.
+        r.seek(SeekFrom::Start(4)).await.unwrap();
+        let mut got = [0; 3];
+        r.read_exact(&mut got).await.unwrap();
+        assert_eq!(b"efg", &got);
+
+        // Incomplete chunk.
+        encrypted.extend_from_slice(&[0, 0, 0, 100, 1]);
+        let r = DecryptReader::new(Cursor::new(encrypted), &key)
+            .await
+            .unwrap();
+        assert_eq!(8, r.size());
+    }
+
+    #[tokio::test]
+    async fn test_decrypt_chunks_larger_than_buffer() {
+        let key = RecordingKey::generate();
+        let mut encrypted = Vec::new();
+        let mut w = EncryptWriter::new(&mut encrypted, &key);
+        let big = vec![7; HEADER_BUF_SIZE * 2];
+        w.write_all(&big).await.unwrap();
+        w.flush().await.unwrap();
+        w.write_all(b"ab").await.unwrap();
+        w.flush().await.unwrap();
+        w.write_all(b"c").await.unwrap();
+        w.flush().await.unwrap();
+
+        let mut r = DecryptReader::new(Cursor::new(encrypted), &key)
+            .await
+            .unwrap();
+        assert_eq!(3, r.chunks.len());
+        r.seek(SeekFrom::End(-3)).await.unwrap();
+        let mut got = Vec::new();
+        r.read_to_end(&mut got).await.unwrap();
+        assert_eq!(b"abc", got.as_slice());
+    }
+}
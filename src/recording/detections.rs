@@ -0,0 +1,165 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+// Detections sidecar, stored next to the recording with the ".det" extension.
+// Each line is a JSON object with the detections of a single frame. The file
+// is only appended to, a partially written last line is ignored when reading.
+
+use common::{time::UnixH264, Detections};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+
+// Buffered lines are flushed to disk at least this often.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FrameDetections {
+    // Presentation time of the frame.
+    pub pts: UnixH264,
+    pub detections: Detections,
+}
+
+#[derive(Debug, Error)]
+pub enum WriteDetectionsError {
+    #[error("serialize: {0}")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("write: {0}")]
+    Write(std::io::Error),
+
+    #[error("flush: {0}")]
+    Flush(std::io::Error),
+}
+
+pub struct DetectionWriter<W: AsyncWrite + Unpin> {
+    w: BufWriter<W>,
+    // Time of the oldest line that hasn't been flushed.
+    unflushed: Option<Instant>,
+}
+
+impl<W: AsyncWrite + Unpin> DetectionWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self {
+            w: BufWriter::new(w),
+            unflushed: None,
+        }
+    }
+
+    pub async fn write(&mut self, frame: &FrameDetections) -> Result<(), WriteDetectionsError> {
+        use WriteDetectionsError::*;
+        let mut line = serde_json::to_vec(frame)?;
+        line.push(b'\n');
+        self.w.write_all(&line).await.map_err(Write)?;
+
+        let unflushed = *self.unflushed.get_or_insert_with(Instant::now);
+        if unflushed.elapsed() >= FLUSH_INTERVAL {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn flush(&mut self) -> Result<(), WriteDetectionsError> {
+        self.w.flush().await.map_err(WriteDetectionsError::Flush)?;
+        self.unflushed = None;
+        Ok(())
+    }
+
+    // Returns when `flush` should be called if no more lines are
+    // written, None if there is nothing to flush.
+    #[must_use]
+    pub fn flush_deadline(&self) -> Option<Instant> {
+        Some(self.unflushed? + FLUSH_INTERVAL)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ReadDetectionsError {
+    #[error("read: {0}")]
+    Read(#[from] std::io::Error),
+
+    #[error("line {0}: {1}")]
+    Deserialize(usize, serde_json::Error),
+}
+
+// Reads all frames from a sidecar file.
+pub async fn read_detections<R: AsyncRead + Unpin>(
+    r: R,
+) -> Result<Vec<FrameDetections>, ReadDetectionsError> {
+    let mut lines = BufReader::new(r).lines();
+    let mut frames = Vec::new();
+    let mut prev_err = None;
+    while let Some(line) = lines.next_line().await? {
+        // Only the last line may be corrupt.
+        if let Some(e) = prev_err.take() {
+            return Err(e);
+        }
+        match serde_json::from_str(&line) {
+            Ok(v) => frames.push(v),
+            Err(e) => prev_err = Some(ReadDetectionsError::Deserialize(frames.len() + 1, e)),
+        }
+    }
+    Ok(frames)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{Detection, Region};
+    use pretty_assertions::assert_eq;
+
+    fn frame(pts: i64, label: &str) -> FrameDetections {
+        FrameDetections {
+            pts: UnixH264::new(pts),
+            detections: vec![Detection {
+                label: label.to_owned().try_into().unwrap(),
+                score: 50.0,
+                region: Region::default(),
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_detections_round_trip() {
+        let mut buf = Vec::new();
+        let mut w = DetectionWriter::new(&mut buf);
+        w.write(&frame(1, "a")).await.unwrap();
+        w.write(&frame(2, "b")).await.unwrap();
+        w.flush().await.unwrap();
+
+        // Crashed while writing.
+        buf.extend_from_slice(b"{\"pts\":3,\"detec");
+
+        let got = read_detections(buf.as_slice()).await.unwrap();
+        assert_eq!(vec![frame(1, "a"), frame(2, "b")], got);
+    }
+
+    #[tokio::test]
+    async fn test_detections_flush_deadline() {
+        let mut buf = Vec::new();
+        let mut w = DetectionWriter::new(&mut buf);
+        assert!(w.flush_deadline().is_none());
+
+        let before = Instant::now();
+        w.write(&frame(1, "a")).await.unwrap();
+        let deadline = w.flush_deadline().unwrap();
+        assert!(deadline >= before + FLUSH_INTERVAL);
+
+        // The deadline is kept until the lines are flushed.
+        w.write(&frame(2, "b")).await.unwrap();
+        assert_eq!(Some(deadline), w.flush_deadline());
+
+        w.flush().await.unwrap();
+        assert!(w.flush_deadline().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_detections_corrupt() {
+        let buf = b"{\"pts\":1,\"detections\":[]}\nx\n{\"pts\":2,\"detections\":[]}\n";
+        assert!(matches!(
+            read_detections(buf.as_slice()).await,
+            Err(ReadDetectionsError::Deserialize(2, _))
+        ));
+    }
+}
@@ -1,15 +1,34 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
+mod annexb;
 mod cache;
+mod crypt;
+mod detections;
 mod mp4_muxer;
 mod video;
 mod video_reader;
 
+pub use annexb::{
+    avcc_to_annexb, export_annexb, AvccToAnnexbError, ExportAnnexbError, ParseAvcCError,
+};
 pub use cache::VideoCache;
+pub use common::AudioParameters;
+pub use crypt::{DecryptReader, EncryptWriter, MdatFile, RecordingKey, UnwrapKeyError};
+pub use detections::{
+    read_detections, DetectionWriter, FrameDetections, ReadDetectionsError, WriteDetectionsError,
+};
 pub use hls::VIDEO_TRACK_ID;
-pub use mp4_muxer::{generate_mp4, GenerateMp4Error, Mp4Muxer};
+pub use mp4_muxer::{
+    generate_fragments, generate_init, generate_mp4, generate_mp4_multi, generate_mp4_sync,
+    generate_sidx, AudioTrack, Fragment, GenerateMp4Error, Mp4Branding, Mp4Header, Mp4Muxer,
+    ProtectionSystem, Timescale, TrackEncryption,
+};
 pub use video::{
-    read_meta, CreateVideoWriterError, MetaHeader, MetaReader, ReadMetaError, Sample,
-    TrackParameters, VideoWriter, WriteSampleError,
+    index_lookup, read_audio_meta, read_index, read_meta, seek_keyframe, select_keyframes,
+    AudioWriter, CreateMetaReaderError, CreateVideoWriterError, IndexEntry, MetaHeader, MetaReader,
+    ReadIndexError, ReadMetaError, Sample, SeekKeyframeError, TrackParameters, VideoWriter,
+    WriteSampleError,
+};
+pub use video_reader::{
+    new_sample_stream, new_video_reader, sample_stream, CreateVideoReaderError, ReadSampleError,
 };
-pub use video_reader::{new_video_reader, CreateVideoReaderError};
@@ -2,10 +2,17 @@
 
 use crate::video::{Sample, TrackParameters};
 use async_trait::async_trait;
-use common::time::{DurationH264, UnixH264, H264_TIMESCALE};
+use common::{
+    time::{DurationH264, UnixH264, H264_TIMESCALE},
+    AudioParameters,
+};
 use hls::VIDEO_TRACK_ID;
-use mp4::{FullBox, ImmutableBox, ImmutableBoxAsync, Mp4Error};
-use std::{num::TryFromIntError, sync::Arc};
+use mp4::{FullBox, ImmutableBox, ImmutableBoxAsync, ImmutableBoxSync, Mp4Error};
+use std::{
+    io::Write,
+    num::{NonZeroU32, TryFromIntError},
+    sync::Arc,
+};
 use thiserror::Error;
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
@@ -32,12 +39,30 @@ pub enum GenerateMp4Error {
     #[error("generate trak: {0}")]
     GenerateTrak(#[from] GenerateTrakError),
 
+    #[error("sample count: {0} {1}")]
+    SampleCount(usize, TryFromIntError),
+
     #[error("mvhd duration: {0} {1}")]
     MvhdDuration(i64, TryFromIntError),
 
     #[error("moov size: {0} {1}")]
     MoovSize(usize, TryFromIntError),
 
+    #[error("sidx referenced size: {0}")]
+    SidxReferencedSize(usize),
+
+    #[error("convert time {0} to timescale: {1}")]
+    ConvertTime(i64, TryFromIntError),
+
+    #[error("sample encryption count: {0}, samples: {1}")]
+    SampleEncryptionCount(usize, usize),
+
+    #[error("sample info size: {0} {1}")]
+    SampleInfoSize(usize, TryFromIntError),
+
+    #[error("audio sample rate is zero")]
+    ZeroSampleRate,
+
     #[error("write: {0}")]
     Write(#[from] std::io::Error),
 }
@@ -50,122 +75,443 @@ pub struct Mp4Muxer {
     pub stsc: Vec<mp4::StscEntry>,
     pub stsz: Vec<u32>,
     pub stco: Arc<std::sync::Mutex<Vec<u32>>>,
+    pub branding: Mp4Branding,
+    pub timescale: Timescale,
+    pub encryption: Option<TrackEncryption>,
+}
+
+// Media timescale of the video tracks. Sample times are stored with the
+// 90khz H264 timescale and are converted when muxed. The movie timescale
+// in the mvhd and tkhd boxes is always 1000. Only rec2mp4 can change it,
+// the vod endpoints use the default and the live HLS muxer is fixed at 90khz.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timescale(NonZeroU32);
+
+impl Default for Timescale {
+    fn default() -> Self {
+        Self(NonZeroU32::new(H264_TIMESCALE).expect("nonzero"))
+    }
+}
+
+impl Timescale {
+    #[must_use]
+    pub fn new(v: NonZeroU32) -> Self {
+        Self(v)
+    }
+
+    #[must_use]
+    pub fn get(self) -> u32 {
+        self.0.get()
+    }
+
+    // Converts a 90khz time to the timescale, rounded down.
+    fn convert(self, v: i64) -> Result<i64, TryFromIntError> {
+        let v = i128::from(v) * i128::from(self.0.get());
+        i64::try_from(v.div_euclid(i128::from(H264_TIMESCALE)))
+    }
+}
+
+// Returns the decode time relative to `start_time` and the duration of the
+// sample in the timescale. The duration is the difference between the
+// converted start and end times so that rounding errors don't accumulate.
+fn decode_time_and_duration(
+    timescale: Timescale,
+    start_time: UnixH264,
+    dts_shift: UnixH264,
+    sample: &Sample,
+) -> Result<(i64, u32), GenerateMp4Error> {
+    use GenerateMp4Error::*;
+    let dts = *sample
+        .dts()
+        .ok_or(Sub)?
+        .checked_add(dts_shift)
+        .ok_or(Add)?
+        .checked_sub(start_time)
+        .ok_or(Sub)?;
+    let end = dts.checked_add(*sample.duration).ok_or(Add)?;
+
+    let dts = timescale.convert(dts).map_err(|v| ConvertTime(dts, v))?;
+    let end = timescale.convert(end).map_err(|v| ConvertTime(end, v))?;
+    let duration =
+        u32::try_from(end.checked_sub(dts).ok_or(Sub)?).map_err(|v| Delta(sample.duration, v))?;
+    Ok((dts, duration))
+}
+
+// Converts a presentation time relative to `start_time` to the timescale.
+fn presentation_time(
+    timescale: Timescale,
+    start_time: UnixH264,
+    sample: &Sample,
+) -> Result<i64, GenerateMp4Error> {
+    let pts = *sample
+        .pts
+        .checked_sub(start_time)
+        .ok_or(GenerateMp4Error::Sub)?;
+    timescale
+        .convert(pts)
+        .map_err(|v| GenerateMp4Error::ConvertTime(pts, v))
+}
+
+// Names written to the video track, some players and tools display these.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mp4Branding {
+    // Avc1 compressor name, stored as a length prefixed
+    // string and truncated to 31 bytes.
+    pub compressor_name: String,
+
+    // Hdlr name, truncated at the first null byte.
+    pub handler_name: String,
+}
+
+impl Default for Mp4Branding {
+    fn default() -> Self {
+        Self {
+            compressor_name: String::new(),
+            handler_name: "VideoHandler".to_owned(),
+        }
+    }
+}
+
+impl Mp4Branding {
+    fn compressor_name_field(&self) -> [u8; 32] {
+        let name = self.compressor_name.as_bytes();
+        let name = &name[..name.len().min(31)];
+        let mut field = [0; 32];
+        field[0] = u8::try_from(name.len()).expect("at most 31");
+        field[1..=name.len()].copy_from_slice(name);
+        field
+    }
+
+    fn handler_name_field(&self) -> String {
+        self.handler_name
+            .split('\0')
+            .next()
+            .unwrap_or_default()
+            .to_owned()
+    }
+}
+
+// Common encryption of the video track. Only the boxes are written,
+// the sample data must be encrypted by the caller. Unencrypted if None.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrackEncryption {
+    // `mp4::SCHEME_TYPE_CENC` or `mp4::SCHEME_TYPE_CBCS`.
+    pub scheme_type: [u8; 4],
+    pub kid: [u8; 16],
+
+    // Size of the initialization vector of every sample, 0, 8 or 16.
+    // The constant IV is used if the size is zero.
+    pub per_sample_iv_size: u8,
+    pub constant_iv: Vec<u8>,
+
+    // Pattern encryption, the tenc box is version 1 if either is set.
+    pub crypt_byte_block: u8,
+    pub skip_byte_block: u8,
+
+    // Written as pssh boxes in the moov box.
+    pub protection_systems: Vec<ProtectionSystem>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProtectionSystem {
+    pub system_id: [u8; 16],
+    pub kids: Vec<[u8; 16]>,
+    pub data: Vec<u8>,
+}
+
+impl TrackEncryption {
+    fn tenc(&self) -> mp4::Tenc {
+        let pattern = self.crypt_byte_block != 0 || self.skip_byte_block != 0;
+        mp4::Tenc {
+            full_box: mp4::FullBox {
+                version: u8::from(pattern),
+                flags: [0, 0, 0],
+            },
+            default_crypt_byte_block: self.crypt_byte_block,
+            default_skip_byte_block: self.skip_byte_block,
+            default_is_protected: 1,
+            default_per_sample_iv_size: self.per_sample_iv_size,
+            default_kid: self.kid,
+            default_constant_iv: self.constant_iv.clone(),
+        }
+    }
+}
+
+impl ProtectionSystem {
+    // The key ids require version 1.
+    fn pssh(&self) -> mp4::Pssh {
+        mp4::Pssh {
+            full_box: mp4::FullBox {
+                version: u8::from(!self.kids.is_empty()),
+                flags: [0, 0, 0],
+            },
+            system_id: self.system_id,
+            kids: self.kids.clone(),
+            data: self.data.clone(),
+        }
+    }
+}
+
+// Tree of boxes that can be marshaled either synchronously or asynchronously.
+// Allows the same box tree to be generated for both writers.
+pub trait BoxTree: Sized {
+    fn leaf<T: ImmutableBoxSync + ImmutableBoxAsync + 'static>(mp4_box: T) -> Self;
+    #[must_use]
+    fn with_children<I: IntoIterator<Item = Self>>(self, children: I) -> Self;
+    fn size(&self) -> usize;
+}
+
+impl BoxTree for mp4::Boxes {
+    fn leaf<T: ImmutableBoxSync + ImmutableBoxAsync + 'static>(mp4_box: T) -> Self {
+        let mp4_box: Box<dyn ImmutableBoxSync> = Box::new(mp4_box);
+        Self::new(mp4_box)
+    }
+
+    fn with_children<I: IntoIterator<Item = Self>>(self, children: I) -> Self {
+        self.with_children(children)
+    }
+
+    fn size(&self) -> usize {
+        self.size()
+    }
+}
+
+impl BoxTree for mp4::BoxesAsync {
+    fn leaf<T: ImmutableBoxSync + ImmutableBoxAsync + 'static>(mp4_box: T) -> Self {
+        let mp4_box: Box<dyn ImmutableBoxAsync> = Box::new(mp4_box);
+        Self::new(mp4_box)
+    }
+
+    fn with_children<I: IntoIterator<Item = Self>>(self, children: I) -> Self {
+        self.with_children(children)
+    }
+
+    fn size(&self) -> usize {
+        self.size()
+    }
 }
 
-#[allow(
-    clippy::items_after_statements,
-    clippy::similar_names,
-    clippy::too_many_lines
-)]
-pub async fn generate_mp4<'a, S>(
-    out: &'a mut (dyn AsyncWrite + Unpin + Send + Sync),
+// AAC track placed after the video tracks, the media timescale is the
+// sample rate. The sample data must be written after the video data.
+#[derive(Clone, Copy)]
+pub struct AudioTrack<'a> {
+    pub samples: &'a [Sample],
+    pub params: &'a AudioParameters,
+}
+
+fn ftyp() -> mp4::Ftyp {
+    mp4::Ftyp {
+        major_brand: *b"iso4",
+        minor_version: 512,
+        compatible_brands: vec![mp4::CompatibleBrandElem(*b"iso4")],
+    }
+}
+
+// Generates the mp4 header and mdat box header, the samples must
+// be written directly after. Returns the mdat size without header.
+// The default branding is used if `branding` is None.
+pub async fn generate_mp4<'a, S, W>(
+    out: &mut W,
     start_time: UnixH264,
     samples: S,
     params: &'a TrackParameters,
+    branding: Option<&Mp4Branding>,
+    timescale: Timescale,
 ) -> Result<u32, GenerateMp4Error>
 where
     S: Iterator<Item = &'a Sample>,
+    W: AsyncWrite + Unpin + Send + Sync,
 {
-    use GenerateMp4Error::*;
+    generate_mp4_multi(
+        out,
+        start_time,
+        vec![(samples, params)],
+        None,
+        branding,
+        timescale,
+    )
+    .await
+}
 
-    let mut m = Mp4Muxer {
-        stco: Arc::new(std::sync::Mutex::new(vec![0])),
-        ..Default::default()
-    };
-    let mut mdat_pos: u32 = 0;
-    let mut end_time = UnixH264::new(0);
-    let mut dts_shift = DurationH264::new(0);
+// Like `generate_mp4` but with a video track for every item in `tracks`
+// and a optional audio track. The sample data of every track must be
+// written after the mdat header in the same order as the tracks.
+pub async fn generate_mp4_multi<'a, S, W>(
+    out: &mut W,
+    start_time: UnixH264,
+    tracks: Vec<(S, &'a TrackParameters)>,
+    audio: Option<AudioTrack<'_>>,
+    branding: Option<&Mp4Branding>,
+    timescale: Timescale,
+) -> Result<u32, GenerateMp4Error>
+where
+    S: Iterator<Item = &'a Sample>,
+    W: AsyncWrite + Unpin + Send + Sync,
+{
+    let header = Mp4Header::new(start_time, tracks, audio, branding, timescale)?;
+    header.write(out).await?;
+    Ok(header.mdat_size())
+}
 
-    for sample in samples {
-        let delta = sample
-            .duration
-            .as_u32()
-            .map_err(|v| Delta(sample.duration, v))?;
-        match m.stts.last_mut() {
-            Some(last) if last.sample_delta == delta => {
-                last.sample_count += 1;
-            }
-            _ => m.stts.push(mp4::SttsEntry {
-                sample_count: 1,
-                sample_delta: delta,
-            }),
-        }
+// Mp4 header up to and including the mdat box header. The size is known
+// before anything is written, this allows the header to be streamed
+// straight to the output after the size has been used.
+pub struct Mp4Header {
+    moov: mp4::BoxesAsync,
+    mdat_size: u32,
+}
 
-        let pts = DurationH264::from(sample.pts.checked_sub(start_time).ok_or(Sub)?);
-        let dts = DurationH264::from(
-            sample
-                .dts()
-                .ok_or(Sub)?
-                .checked_sub(start_time)
-                .ok_or(Sub)?,
-        );
+impl Mp4Header {
+    pub fn new<'a, S>(
+        start_time: UnixH264,
+        tracks: Vec<(S, &'a TrackParameters)>,
+        audio: Option<AudioTrack<'_>>,
+        branding: Option<&Mp4Branding>,
+        timescale: Timescale,
+    ) -> Result<Self, GenerateMp4Error>
+    where
+        S: Iterator<Item = &'a Sample>,
+    {
+        let (moov, mdat_size) = generate_moov(start_time, tracks, audio, branding, timescale)?;
+        Ok(Self { moov, mdat_size })
+    }
 
-        let first_sample = m.stsz.is_empty();
-        if first_sample {
-            dts_shift = pts.checked_sub(dts).ok_or(Sub)?;
-        }
+    // Number of bytes written by `write`.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        8 + ftyp().size() + self.moov.size() + 8
+    }
 
-        let cts = *pts
-            .checked_sub(dts.checked_add(dts_shift).ok_or(Add)?)
-            .ok_or(Add)?;
-        let cts = i32::try_from(cts).map_err(|v| Cts(cts, v))?;
-        //cts := pts - (dts + m.dtsShift)
+    // Size of the sample data that must follow the header.
+    #[must_use]
+    pub fn mdat_size(&self) -> u32 {
+        self.mdat_size
+    }
 
-        match m.ctts.last_mut() {
-            Some(last) if last.sample_offset == cts => {
-                last.sample_count += 1;
-            }
-            _ => m.ctts.push(mp4::CttsEntryV1 {
-                sample_count: 1,
-                sample_offset: cts,
-            }),
-        }
+    // Writes the header box by box.
+    pub async fn write<W>(&self, out: &mut W) -> Result<(), GenerateMp4Error>
+    where
+        W: AsyncWrite + Unpin + Send + Sync,
+    {
+        mp4::write_single_box2(out, &ftyp()).await?;
+        self.moov.marshal(out).await?;
 
-        mdat_pos += sample.data_size;
-        m.stsz.push(sample.data_size);
+        let mdat_box_size = self.mdat_size.checked_add(8).ok_or(GenerateMp4Error::Add)?;
+        out.write_all(&mdat_box_size.to_be_bytes()).await?;
+        out.write_all(b"mdat").await?;
+        Ok(())
+    }
+}
 
-        if sample.random_access_present {
-            m.stss
-                .push(u32::try_from(m.stsz.len()).map_err(|v| StszLen(m.stts.len(), v))?);
-        }
+// Synchronous version of `generate_mp4` with a optional audio track,
+// the output is identical if there is no audio.
+pub fn generate_mp4_sync<'a, S, W>(
+    out: &mut W,
+    start_time: UnixH264,
+    samples: S,
+    params: &'a TrackParameters,
+    audio: Option<AudioTrack<'_>>,
+    branding: Option<&Mp4Branding>,
+    timescale: Timescale,
+) -> Result<u32, GenerateMp4Error>
+where
+    S: Iterator<Item = &'a Sample>,
+    W: Write,
+{
+    let (moov, mdat_pos) = generate_moov::<mp4::Boxes, S>(
+        start_time,
+        vec![(samples, params)],
+        audio,
+        branding,
+        timescale,
+    )?;
+
+    mp4::write_single_box(out, &ftyp())?;
+    moov.marshal(out)?;
+
+    out.write_all(&(mdat_pos.checked_add(8).ok_or(GenerateMp4Error::Add)?).to_be_bytes())?;
+    out.write_all(b"mdat")?;
+
+    Ok(mdat_pos)
+}
+
+#[allow(clippy::items_after_statements)]
+fn generate_moov<'a, B, S>(
+    start_time: UnixH264,
+    tracks: Vec<(S, &'a TrackParameters)>,
+    audio: Option<AudioTrack<'_>>,
+    branding: Option<&Mp4Branding>,
+    timescale: Timescale,
+) -> Result<(B, u32), GenerateMp4Error>
+where
+    B: BoxTree,
+    S: Iterator<Item = &'a Sample>,
+{
+    use GenerateMp4Error::*;
+    let mut mdat_pos: u32 = 0;
+    let mut max_duration = DurationH264::new(0);
+    let mut traks = Vec::with_capacity(tracks.len());
+    let mut chunk_offsets = Vec::with_capacity(tracks.len());
+    let mut track_id = VIDEO_TRACK_ID;
 
-        end_time = sample
-            .dts()
-            .ok_or(Sub)?
-            .checked_add(sample.duration.into())
-            .ok_or(Add)?;
+    for (samples, params) in tracks {
+        // Every track is a single chunk placed directly after the previous track.
+        let mut m = Mp4Muxer {
+            stco: Arc::new(std::sync::Mutex::new(vec![mdat_pos])),
+            branding: branding.cloned().unwrap_or_default(),
+            timescale,
+            ..Default::default()
+        };
+        let (end_time, data_size) = m.mux_samples(start_time, samples)?;
+        mdat_pos = mdat_pos.checked_add(data_size).ok_or(Add)?;
+
+        let duration = DurationH264::from(end_time.checked_sub(start_time).ok_or(Sub)?);
+        //duration := time.Duration(m.endTime - m.startTime)
+        max_duration = max_duration.max(duration);
+
+        traks.push(m.generate_trak(track_id, duration, params)?);
+        chunk_offsets.push(m.stco);
+        track_id = track_id.checked_add(1).ok_or(Add)?;
     }
 
-    m.stsc.push(mp4::StscEntry {
-        first_chunk: 1,
-        samples_per_chunk: u32::try_from(m.stsz.len()).map_err(|e| StszLen(m.stsz.len(), e))?,
-        sample_description_index: 1,
-    });
+    if let Some(audio) = audio.filter(|v| !v.samples.is_empty()) {
+        let sample_rate = NonZeroU32::new(audio.params.sample_rate).ok_or(ZeroSampleRate)?;
+        let mut m = Mp4Muxer {
+            stco: Arc::new(std::sync::Mutex::new(vec![mdat_pos])),
+            timescale: Timescale::new(sample_rate),
+            ..Default::default()
+        };
+        let (end_time, data_size) = m.mux_samples(start_time, audio.samples.iter())?;
+        mdat_pos = mdat_pos.checked_add(data_size).ok_or(Add)?;
 
-    let duration = DurationH264::from(
-        end_time
-            .checked_sub(start_time)
-            .ok_or(GenerateMp4Error::Sub)?,
-    );
-    //duration := time.Duration(m.endTime - m.startTime)
-
-    let moov = mp4::BoxesAsync::new(mp4::Moov {}).with_children2(
-        // Mvhd.
-        mp4::BoxesAsync::new(mp4::Mvhd {
-            timescale: 1000,
-            version: mp4::MvhdVersion::V0(mp4::MvhdV0 {
-                duration: u32::try_from(duration.as_millis())
-                    .map_err(|v| MvhdDuration(duration.as_millis(), v))?,
+        let duration = DurationH264::from(end_time.checked_sub(start_time).ok_or(Sub)?);
+        max_duration = max_duration.max(duration);
+
+        traks.push(m.generate_audio_trak(track_id, duration, audio.params)?);
+        chunk_offsets.push(m.stco);
+        track_id = track_id.checked_add(1).ok_or(Add)?;
+    }
+
+    let moov = B::leaf(mp4::Moov {}).with_children(
+        [
+            // Mvhd.
+            B::leaf(mp4::Mvhd {
+                timescale: 1000,
+                version: mp4::MvhdVersion::V0(mp4::MvhdV0 {
+                    duration: u32::try_from(max_duration.as_millis())
+                        .map_err(|v| MvhdDuration(max_duration.as_millis(), v))?,
+                    ..Default::default()
+                }),
+                rate: 65536,
+                volume: 256,
+                matrix: [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000],
+                next_track_id: track_id,
                 ..Default::default()
             }),
-            rate: 65536,
-            volume: 256,
-            matrix: [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000],
-            next_track_id: VIDEO_TRACK_ID + 1,
-            ..Default::default()
-        }),
-        // Trak.
-        m.generate_trak(duration, params)?,
+        ]
+        .into_iter()
+        // Traks.
+        .chain(traks),
     );
 
     const FTYP_SIZE: u32 = 20;
@@ -174,37 +520,462 @@ where
         + u32::try_from(moov.size()).map_err(|v| MoovSize(moov.size(), v))?
         + MDAT_HEADER_SIZE;
 
+    for stco in chunk_offsets {
+        let mut stco = stco.lock().expect("not poisoned");
+        for offset in stco.iter_mut() {
+            *offset += mdat_offset;
+        }
+    }
+
+    Ok((moov, mdat_pos))
+}
+
+impl Mp4Muxer {
+    // Adds the samples to the sample tables as a single chunk.
+    // Returns the end time of the last sample and the data size.
+    #[allow(clippy::similar_names)]
+    fn mux_samples<'a, S>(
+        &mut self,
+        start_time: UnixH264,
+        samples: S,
+    ) -> Result<(UnixH264, u32), GenerateMp4Error>
+    where
+        S: Iterator<Item = &'a Sample>,
     {
-        let mut stco = m.stco.lock().expect("not poisoned");
-        for i in 0..stco.len() {
-            stco[i] += mdat_offset;
+        use GenerateMp4Error::*;
+        let mut data_size: u32 = 0;
+        let mut end_time = UnixH264::new(0);
+        let mut dts_shift = 0;
+
+        for sample in samples {
+            let (dts, delta) =
+                decode_time_and_duration(self.timescale, start_time, UnixH264::new(0), sample)?;
+            match self.stts.last_mut() {
+                Some(last) if last.sample_delta == delta => {
+                    last.sample_count += 1;
+                }
+                _ => self.stts.push(mp4::SttsEntry {
+                    sample_count: 1,
+                    sample_delta: delta,
+                }),
+            }
+
+            let pts = presentation_time(self.timescale, start_time, sample)?;
+
+            let first_sample = self.stsz.is_empty();
+            if first_sample {
+                dts_shift = pts.checked_sub(dts).ok_or(Sub)?;
+            }
+
+            let cts = pts
+                .checked_sub(dts.checked_add(dts_shift).ok_or(Add)?)
+                .ok_or(Add)?;
+            let cts = i32::try_from(cts).map_err(|v| Cts(cts, v))?;
+            //cts := pts - (dts + m.dtsShift)
+
+            match self.ctts.last_mut() {
+                Some(last) if last.sample_offset == cts => {
+                    last.sample_count += 1;
+                }
+                _ => self.ctts.push(mp4::CttsEntryV1 {
+                    sample_count: 1,
+                    sample_offset: cts,
+                }),
+            }
+
+            data_size += sample.data_size;
+            self.stsz.push(sample.data_size);
+
+            if sample.random_access_present {
+                self.stss
+                    .push(u32::try_from(self.stsz.len()).map_err(|v| StszLen(self.stts.len(), v))?);
+            }
+
+            end_time = sample
+                .dts()
+                .ok_or(Sub)?
+                .checked_add(sample.duration.into())
+                .ok_or(Add)?;
         }
-        drop(stco);
+
+        self.stsc.push(mp4::StscEntry {
+            first_chunk: 1,
+            samples_per_chunk: u32::try_from(self.stsz.len())
+                .map_err(|e| StszLen(self.stsz.len(), e))?,
+            sample_description_index: 1,
+        });
+
+        Ok((end_time, data_size))
     }
+}
 
+// Generates the init segment of a fragmented mp4.
+// The sample entry is encv instead of avc1 if `encryption` is set.
+pub fn generate_init(
+    params: &TrackParameters,
+    timescale: Timescale,
+    encryption: Option<&TrackEncryption>,
+) -> Result<Vec<u8>, GenerateMp4Error> {
     /*
        ftyp
        moov
        - mvhd
+       - pssh (encrypted only)
        - trak (video)
-       mdat
+       - mvex
+         - trex (video)
     */
-
     let ftyp = mp4::Ftyp {
-        major_brand: *b"iso4",
+        major_brand: *b"iso5",
         minor_version: 512,
-        compatible_brands: vec![mp4::CompatibleBrandElem(*b"iso4")],
+        compatible_brands: vec![
+            mp4::CompatibleBrandElem(*b"iso5"),
+            mp4::CompatibleBrandElem(*b"dash"),
+        ],
     };
 
-    mp4::write_single_box2(out, &ftyp).await?;
+    let pssh = encryption
+        .iter()
+        .flat_map(|v| &v.protection_systems)
+        .map(|v| mp4::Boxes::new(v.pssh()));
 
-    moov.marshal(out).await?;
+    let moov = mp4::Boxes::new(mp4::Moov).with_children(
+        [
+            // Mvhd.
+            mp4::Boxes::new(mp4::Mvhd {
+                timescale: 1000,
+                rate: 65536,
+                volume: 256,
+                matrix: [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000],
+                next_track_id: VIDEO_TRACK_ID + 1,
+                ..Default::default()
+            }),
+        ]
+        .into_iter()
+        // Pssh.
+        .chain(pssh)
+        .chain([
+            // Trak, the sample tables are empty.
+            Mp4Muxer {
+                timescale,
+                encryption: encryption.cloned(),
+                ..Default::default()
+            }
+            .generate_trak(VIDEO_TRACK_ID, DurationH264::new(0), params)?,
+            // Mvex.
+            mp4::Boxes::new(mp4::Mvex).with_child(
+                // Trex.
+                mp4::Boxes::new(mp4::Trex {
+                    track_id: VIDEO_TRACK_ID,
+                    default_sample_description_index: 1,
+                    ..Default::default()
+                }),
+            ),
+        ]),
+    );
 
-    out.write_all(&(mdat_pos.checked_add(8).ok_or(GenerateMp4Error::Add)?).to_be_bytes())
-        .await?;
-    out.write_all(b"mdat").await?;
+    let mut buf = Vec::with_capacity(8 + ftyp.size() + moov.size());
+    mp4::write_single_box(&mut buf, &ftyp)?;
+    moov.marshal(&mut buf)?;
+    Ok(buf)
+}
 
-    Ok(mdat_pos)
+// Fragment of a fragmented mp4.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Fragment {
+    // Index of the first sample.
+    pub first_sample: usize,
+    pub n_samples: usize,
+
+    // The moof box and mdat header, the sample data must be written directly after.
+    pub header: Vec<u8>,
+}
+
+// Splits the samples into fragments that start at random access samples.
+// Decode times are shifted like `generate_mp4` so the first sample is presented at `start_time`.
+// If `encryption` is set it must contain the IV and subsamples of every sample,
+// they're written to the senc box of the fragments.
+pub fn generate_fragments(
+    start_time: UnixH264,
+    samples: &[&Sample],
+    timescale: Timescale,
+    encryption: Option<&[mp4::SencSample]>,
+) -> Result<Vec<Fragment>, GenerateMp4Error> {
+    use GenerateMp4Error::*;
+    if let Some(encryption) = encryption {
+        if encryption.len() != samples.len() {
+            return Err(SampleEncryptionCount(encryption.len(), samples.len()));
+        }
+    }
+    let Some(first) = samples.first() else {
+        return Ok(Vec::new());
+    };
+    let dts_shift = first.pts.checked_sub(first.dts().ok_or(Sub)?).ok_or(Sub)?;
+
+    let mut fragments = Vec::new();
+    let mut first_sample = 0;
+    for i in 1..=samples.len() {
+        if i != samples.len() && !samples[i].random_access_present {
+            continue;
+        }
+        let fragment_samples = &samples[first_sample..i];
+        let sequence_number =
+            u32::try_from(fragments.len() + 1).map_err(|e| SampleCount(fragments.len(), e))?;
+        fragments.push(Fragment {
+            first_sample,
+            n_samples: fragment_samples.len(),
+            header: generate_fragment_header(
+                sequence_number,
+                start_time,
+                dts_shift,
+                fragment_samples,
+                timescale,
+                encryption.map(|v| &v[first_sample..i]),
+            )?,
+        });
+        first_sample = i;
+    }
+    Ok(fragments)
+}
+
+// Segment index of the fragments, it must be placed directly before the first
+// fragment. Every fragment starts with a IDR so all references start with a SAP.
+pub fn generate_sidx(
+    start_time: UnixH264,
+    samples: &[&Sample],
+    fragments: &[Fragment],
+    timescale: Timescale,
+) -> Result<Vec<u8>, GenerateMp4Error> {
+    use GenerateMp4Error::*;
+    let Some(first) = samples.first() else {
+        return Ok(Vec::new());
+    };
+    let dts_shift = first.pts.checked_sub(first.dts().ok_or(Sub)?).ok_or(Sub)?;
+    let earliest_presentation_time = presentation_time(timescale, start_time, first)?;
+    let earliest_presentation_time = u64::try_from(earliest_presentation_time).map_err(|_| Sub)?;
+
+    let mut references = Vec::with_capacity(fragments.len());
+    for fragment in fragments {
+        let mut size = fragment.header.len();
+        let mut duration: u32 = 0;
+        for sample in &samples[fragment.first_sample..][..fragment.n_samples] {
+            size += usize::try_from(sample.data_size).expect("usize fit u32");
+            let (_, sample_duration) =
+                decode_time_and_duration(timescale, start_time, dts_shift, sample)?;
+            duration = duration.checked_add(sample_duration).ok_or(Add)?;
+        }
+        let referenced_size = u32::try_from(size)
+            .ok()
+            .filter(|v| *v <= 0x7fff_ffff)
+            .ok_or(SidxReferencedSize(size))?;
+        references.push(mp4::SidxReference {
+            reference_type: false,
+            referenced_size,
+            subsegment_duration: duration,
+            starts_with_sap: true,
+            sap_type: 1,
+            sap_delta_time: 0,
+        });
+    }
+
+    let sidx = mp4::Boxes::new(mp4::Sidx {
+        flags: [0, 0, 0],
+        reference_id: VIDEO_TRACK_ID,
+        timescale: timescale.get(),
+        times: mp4::SidxTimes::V1 {
+            earliest_presentation_time,
+            first_offset: 0,
+        },
+        references,
+    });
+    let mut buf = Vec::with_capacity(sidx.size());
+    sidx.marshal(&mut buf)?;
+    Ok(buf)
+}
+
+fn generate_fragment_header(
+    sequence_number: u32,
+    start_time: UnixH264,
+    dts_shift: UnixH264,
+    samples: &[&Sample],
+    timescale: Timescale,
+    encryption: Option<&[mp4::SencSample]>,
+) -> Result<Vec<u8>, GenerateMp4Error> {
+    use GenerateMp4Error::*;
+    /*
+       moof
+       - mfhd
+       - traf (video)
+         - tfhd
+         - tfdt
+         - trun
+         - saiz (encrypted only)
+         - saio (encrypted only)
+         - senc (encrypted only)
+       mdat
+    */
+    let mut trun_entries = Vec::with_capacity(samples.len());
+    let mut mdat_size: u32 = 0;
+    let mut base_media_decode_time = None;
+    for sample in samples {
+        let (dts, duration) = decode_time_and_duration(timescale, start_time, dts_shift, sample)?;
+        base_media_decode_time.get_or_insert(dts);
+        let cts = presentation_time(timescale, start_time, sample)?
+            .checked_sub(dts)
+            .ok_or(Sub)?;
+        trun_entries.push(mp4::TrunEntryV1 {
+            sample_duration: duration,
+            sample_size: sample.data_size,
+            sample_flags: if sample.random_access_present {
+                0
+            } else {
+                1 << 16 // sample_is_non_sync_sample
+            },
+            sample_composition_time_offset: i32::try_from(cts).map_err(|v| Cts(cts, v))?,
+        });
+        mdat_size = mdat_size.checked_add(sample.data_size).ok_or(Add)?;
+    }
+
+    let base_media_decode_time = base_media_decode_time.ok_or(Sub)?;
+    let base_media_decode_time = u64::try_from(base_media_decode_time).map_err(|_| Sub)?;
+
+    // The trun box is the last box of the unencrypted moof.
+    let trun_end = 88 + (samples.len() * 16);
+    let aux_info = encryption
+        .map(|v| sample_aux_info(trun_end, v))
+        .transpose()?;
+
+    // The data offset is relative to the start of the moof box.
+    let moof_size = trun_end + aux_info.as_ref().map_or(0, |v| v.size);
+    let data_offset = i32::try_from(moof_size + 8).map_err(|v| MoovSize(moof_size, v))?;
+
+    let moof = mp4::Boxes::new(mp4::Moof).with_children2(
+        // Mfhd.
+        mp4::Boxes::new(mp4::Mfhd {
+            full_box: mp4::FullBox::default(),
+            sequence_number,
+        }),
+        // Traf.
+        mp4::Boxes::new(mp4::Traf)
+            .with_children3(
+                // Tfhd, default-base-is-moof.
+                mp4::Boxes::new(mp4::Tfhd {
+                    full_box: mp4::FullBox {
+                        version: 0,
+                        flags: [2, 0, 0],
+                    },
+                    track_id: VIDEO_TRACK_ID,
+                    ..Default::default()
+                }),
+                // Tfdt.
+                mp4::Boxes::new(mp4::Tfdt {
+                    flags: [0, 0, 0],
+                    base_media_decode_time: mp4::TfdtBaseMediaDecodeTime::V1(
+                        base_media_decode_time,
+                    ),
+                }),
+                // Trun.
+                mp4::Boxes::new(mp4::Trun {
+                    flags: mp4::u32_to_flags(
+                        mp4::TRUN_DATA_OFFSET_PRESENT
+                            | mp4::TRUN_SAMPLE_DURATION_PRESENT
+                            | mp4::TRUN_SAMPLE_SIZE_PRESENT
+                            | mp4::TRUN_SAMPLE_FLAGS_PRESENT
+                            | mp4::TRUN_SAMPLE_COMPOSITION_TIME_OFFSET_PRESENT,
+                    ),
+                    data_offset,
+                    first_sample_flags: 0,
+                    entries: mp4::TrunEntries::V1(trun_entries),
+                }),
+            )
+            // Saiz, saio and senc.
+            .with_children(aux_info.into_iter().flat_map(|v| v.boxes)),
+    );
+    debug_assert_eq!(moof_size, moof.size());
+
+    let mut buf = Vec::with_capacity(moof_size + 8);
+    moof.marshal(&mut buf)?;
+    buf.extend_from_slice(&mdat_size.checked_add(8).ok_or(Add)?.to_be_bytes());
+    buf.extend_from_slice(b"mdat");
+    Ok(buf)
+}
+
+// Sample auxiliary information boxes of a encrypted fragment.
+struct SampleAuxInfo {
+    boxes: [mp4::Boxes; 3],
+
+    // Total size of the boxes.
+    size: usize,
+}
+
+// Returns the saiz, saio and senc boxes. `pos` is the position of the
+// saiz box relative to the start of the moof box. The saio offset
+// points to the first IV in the senc box.
+fn sample_aux_info(
+    pos: usize,
+    samples: &[mp4::SencSample],
+) -> Result<SampleAuxInfo, GenerateMp4Error> {
+    use GenerateMp4Error::*;
+    let use_subsamples = samples.iter().any(|v| !v.subsamples.is_empty());
+    let sample_info_sizes = samples
+        .iter()
+        .map(|v| {
+            let mut size = v.iv.len();
+            if use_subsamples {
+                size += 2 + v.subsamples.len() * 6;
+            }
+            u8::try_from(size).map_err(|e| SampleInfoSize(size, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let sample_count = u32::try_from(samples.len()).map_err(|e| SampleCount(samples.len(), e))?;
+
+    // Use the default size if all samples have the same size.
+    let default_sample_info_size = match sample_info_sizes.first() {
+        Some(first) if sample_info_sizes.iter().all(|v| v == first) => *first,
+        _ => 0,
+    };
+    let saiz = mp4::Saiz {
+        default_sample_info_size,
+        sample_count,
+        sample_info_sizes,
+        ..Default::default()
+    };
+
+    let senc = mp4::Senc {
+        full_box: mp4::FullBox {
+            version: 0,
+            flags: if use_subsamples {
+                mp4::u32_to_flags(mp4::SENC_USE_SUBSAMPLE_ENCRYPTION)
+            } else {
+                [0, 0, 0]
+            },
+        },
+        samples: samples.to_vec(),
+    };
+
+    // The size of the saio box doesn't depend on the offset.
+    let mut saio = mp4::Saio {
+        flags: [0, 0, 0],
+        aux_info_type: 0,
+        aux_info_type_parameter: 0,
+        offsets: mp4::SaioOffsets::V0(vec![0]),
+    };
+    let senc_pos = pos + 8 + saiz.size() + 8 + saio.size();
+
+    // Box header, full box and sample count.
+    let offset = senc_pos + 16;
+    saio.offsets =
+        mp4::SaioOffsets::V0(vec![u32::try_from(offset).map_err(|e| MoovSize(offset, e))?]);
+
+    let size = senc_pos + 8 + senc.size() - pos;
+    Ok(SampleAuxInfo {
+        boxes: [
+            mp4::Boxes::new(saiz),
+            mp4::Boxes::new(saio),
+            mp4::Boxes::new(senc),
+        ],
+        size,
+    })
 }
 
 #[derive(Debug, Error)]
@@ -221,11 +992,12 @@ pub enum GenerateTrakError {
 
 impl Mp4Muxer {
     #[allow(clippy::let_and_return)]
-    pub fn generate_trak(
+    fn generate_trak<B: BoxTree>(
         &self,
+        track_id: u32,
         duration: DurationH264,
         params: &TrackParameters,
-    ) -> Result<mp4::BoxesAsync, GenerateTrakError> {
+    ) -> Result<B, GenerateTrakError> {
         use GenerateTrakError::*;
         /*
            trak
@@ -236,11 +1008,11 @@ impl Mp4Muxer {
              - minf
         */
 
-        let trak = mp4::BoxesAsync::new(mp4::Trak).with_children2(
+        let trak = B::leaf(mp4::Trak).with_children([
             // Tkhd.
-            mp4::BoxesAsync::new(mp4::Tkhd {
+            B::leaf(mp4::Tkhd {
                 flags: [0, 0, 3],
-                track_id: VIDEO_TRACK_ID,
+                track_id,
                 version: mp4::TkhdVersion::V0(mp4::TkhdV0 {
                     duration: u32::try_from(duration.as_millis())
                         .map_err(|v| TkhdDuration(duration.as_millis(), v))?,
@@ -252,36 +1024,37 @@ impl Mp4Muxer {
                 ..Default::default()
             }),
             // Mdia.
-            mp4::BoxesAsync::new(mp4::Mdia).with_children3(
+            B::leaf(mp4::Mdia).with_children([
                 // Mdhd.
-                mp4::BoxesAsync::new(mp4::Mdhd {
-                    timescale: H264_TIMESCALE,
+                B::leaf(mp4::Mdhd {
+                    timescale: self.timescale.get(),
                     language: *b"und",
                     version: mp4::MdhdVersion::V0(mp4::MdhdV0 {
-                        duration: duration.as_u32().map_err(|v| MdhdDuration(duration, v))?,
+                        duration: self
+                            .timescale
+                            .convert(*duration)
+                            .and_then(u32::try_from)
+                            .map_err(|v| MdhdDuration(duration, v))?,
                         ..Default::default()
                     }),
                     ..Default::default()
                 }),
                 // Hdlr.
-                mp4::BoxesAsync::new(mp4::Hdlr {
+                B::leaf(mp4::Hdlr {
                     handler_type: *b"vide",
-                    name: "VideoHandler".to_owned(),
+                    name: self.branding.handler_name_field(),
                     ..Default::default()
                 }),
                 // Minf.
                 self.generate_minf(params)?,
-            ),
-        );
+            ]),
+        ]);
 
         Ok(trak)
     }
 
     #[allow(clippy::let_and_return)]
-    fn generate_minf(
-        &self,
-        params: &TrackParameters,
-    ) -> Result<mp4::BoxesAsync, GenerateTrakError> {
+    fn generate_minf<B: BoxTree>(&self, params: &TrackParameters) -> Result<B, GenerateTrakError> {
         /*
            minf
            - vmhd
@@ -298,31 +1071,31 @@ impl Mp4Muxer {
                - stco
         */
 
-        let stbl = mp4::BoxesAsync::new(mp4::Stbl {}).with_children7(
+        let stbl = B::leaf(mp4::Stbl {}).with_children([
             // Stsd.
-            generate_stsd(params),
+            generate_stsd::<B>(params, &self.branding, self.encryption.as_ref()),
             // Stts.
-            mp4::BoxesAsync::new(mp4::Stts {
+            B::leaf(mp4::Stts {
                 full_box: mp4::FullBox::default(),
                 entries: self.stts.clone(),
             }),
             // Stss.
-            mp4::BoxesAsync::new(mp4::Stss {
+            B::leaf(mp4::Stss {
                 full_box: mp4::FullBox::default(),
                 sample_numbers: self.stss.clone(),
             }),
             // Ctts.
-            mp4::BoxesAsync::new(mp4::Ctts {
+            B::leaf(mp4::Ctts {
                 flags: [0, 0, 0],
                 entries: mp4::CttsEntries::V1(self.ctts.clone()),
             }),
             // Stsc.
-            mp4::BoxesAsync::new(mp4::Stsc {
+            B::leaf(mp4::Stsc {
                 full_box: mp4::FullBox::default(),
                 entries: self.stsc.clone(),
             }),
             // Stsz.
-            mp4::BoxesAsync::new(mp4::Stsz {
+            B::leaf(mp4::Stsz {
                 full_box: mp4::FullBox::default(),
                 sample_size: 0,
                 sample_count: u32::try_from(self.stsz.len())
@@ -330,74 +1103,246 @@ impl Mp4Muxer {
                 entry_sizes: self.stsz.clone(),
             }),
             // Stco.
-            mp4::BoxesAsync::new(MyStco {
+            B::leaf(MyStco {
                 full_box: mp4::FullBox::default(),
                 chunk_offsets: self.stco.clone(),
             }),
-        );
+        ]);
 
-        let minf = mp4::BoxesAsync::new(mp4::Minf).with_children3(
+        let minf = B::leaf(mp4::Minf).with_children([
             // Vmhd.
-            mp4::BoxesAsync::new(mp4::Vmhd::default()),
+            B::leaf(mp4::Vmhd::default()),
             // Dinf.
-            mp4::BoxesAsync::new(mp4::Dinf).with_child(
-                // Dref.
-                mp4::BoxesAsync::new(mp4::Dref {
-                    full_box: mp4::FullBox::default(),
-                    entry_count: 1,
-                })
-                .with_child(
-                    // Url.
-                    mp4::BoxesAsync::new(mp4::Url {
-                        full_box: mp4::FullBox {
-                            version: 0,
-                            flags: [0, 0, 1],
-                        },
-                        location: String::new(),
-                    }),
-                ),
-            ),
+            generate_dinf(),
             // Stbl.
             stbl,
-        );
+        ]);
 
         Ok(minf)
     }
+
+    // The samples are muxed with the sample rate as timescale.
+    #[allow(clippy::let_and_return)]
+    fn generate_audio_trak<B: BoxTree>(
+        &self,
+        track_id: u32,
+        duration: DurationH264,
+        params: &AudioParameters,
+    ) -> Result<B, GenerateTrakError> {
+        use GenerateTrakError::*;
+        /*
+           trak
+           - tkhd
+           - mdia
+             - mdhd
+             - hdlr
+             - minf
+               - smhd
+               - dinf
+                 - dref
+                   - url
+               - stbl
+                 - stsd
+                   - mp4a
+                     - esds
+                 - stts
+                 - stsc
+                 - stsz
+                 - stco
+        */
+
+        // Rates above 65535 don't fit, the AudioSpecificConfig has the real rate.
+        let sample_rate = u16::try_from(params.sample_rate).map_or(0, |v| u32::from(v) << 16);
+        let mp4a = B::leaf(mp4::Mp4a {
+            sample_entry: mp4::SampleEntry {
+                data_reference_index: 1,
+                ..Default::default()
+            },
+            channel_count: params.channel_count,
+            sample_size: 16,
+            sample_rate,
+            ..Default::default()
+        })
+        .with_children([
+            // Esds.
+            B::leaf(mp4::Esds {
+                decoder_specific_info: params.config.clone(),
+                ..Default::default()
+            }),
+        ]);
+
+        let stbl = B::leaf(mp4::Stbl {}).with_children([
+            // Stsd.
+            B::leaf(mp4::Stsd {
+                full_box: mp4::FullBox::default(),
+                entry_count: 1,
+            })
+            .with_children([mp4a]),
+            // Stts.
+            B::leaf(mp4::Stts {
+                full_box: mp4::FullBox::default(),
+                entries: self.stts.clone(),
+            }),
+            // Stsc.
+            B::leaf(mp4::Stsc {
+                full_box: mp4::FullBox::default(),
+                entries: self.stsc.clone(),
+            }),
+            // Stsz.
+            B::leaf(mp4::Stsz {
+                full_box: mp4::FullBox::default(),
+                sample_size: 0,
+                sample_count: u32::try_from(self.stsz.len())
+                    .map_err(|v| StszLen(self.stsz.len(), v))?,
+                entry_sizes: self.stsz.clone(),
+            }),
+            // Stco.
+            B::leaf(MyStco {
+                full_box: mp4::FullBox::default(),
+                chunk_offsets: self.stco.clone(),
+            }),
+        ]);
+
+        let trak = B::leaf(mp4::Trak).with_children([
+            // Tkhd.
+            B::leaf(mp4::Tkhd {
+                flags: [0, 0, 3],
+                track_id,
+                version: mp4::TkhdVersion::V0(mp4::TkhdV0 {
+                    duration: u32::try_from(duration.as_millis())
+                        .map_err(|v| TkhdDuration(duration.as_millis(), v))?,
+                    ..Default::default()
+                }),
+                alternate_group: 1,
+                volume: 256,
+                matrix: [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000],
+                ..Default::default()
+            }),
+            // Mdia.
+            B::leaf(mp4::Mdia).with_children([
+                // Mdhd.
+                B::leaf(mp4::Mdhd {
+                    timescale: self.timescale.get(),
+                    language: *b"und",
+                    version: mp4::MdhdVersion::V0(mp4::MdhdV0 {
+                        duration: self
+                            .timescale
+                            .convert(*duration)
+                            .and_then(u32::try_from)
+                            .map_err(|v| MdhdDuration(duration, v))?,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                // Hdlr.
+                B::leaf(mp4::Hdlr {
+                    handler_type: *b"soun",
+                    name: "SoundHandler".to_owned(),
+                    ..Default::default()
+                }),
+                // Minf.
+                B::leaf(mp4::Minf).with_children([
+                    // Smhd.
+                    B::leaf(mp4::Smhd::default()),
+                    // Dinf.
+                    generate_dinf(),
+                    // Stbl.
+                    stbl,
+                ]),
+            ]),
+        ]);
+
+        Ok(trak)
+    }
+}
+
+fn generate_dinf<B: BoxTree>() -> B {
+    B::leaf(mp4::Dinf).with_children([
+        // Dref.
+        B::leaf(mp4::Dref {
+            full_box: mp4::FullBox::default(),
+            entry_count: 1,
+        })
+        .with_children([
+            // Url.
+            B::leaf(mp4::Url {
+                full_box: mp4::FullBox {
+                    version: 0,
+                    flags: [0, 0, 1],
+                },
+                location: String::new(),
+            }),
+        ]),
+    ])
 }
 
 #[allow(clippy::let_and_return)]
-fn generate_stsd(params: &TrackParameters) -> mp4::BoxesAsync {
+fn generate_stsd<B: BoxTree>(
+    params: &TrackParameters,
+    branding: &Mp4Branding,
+    encryption: Option<&TrackEncryption>,
+) -> B {
     /*
        - stsd
-         - avc1
+         - avc1 (encv if encrypted)
            - avcC
+           - sinf (encrypted only)
+             - frma
+             - schm
+             - schi
+               - tenc
     */
 
-    let stsd = mp4::BoxesAsync::new(mp4::Stsd {
+    let avc1 = mp4::Avc1 {
+        sample_entry: mp4::SampleEntry {
+            data_reference_index: 1,
+            ..Default::default()
+        },
+        width: params.width,
+        height: params.height,
+        horiz_resolution: 4_718_592,
+        vert_resolution: 4_718_592,
+        frame_count: 1,
+        compressor_name: branding.compressor_name_field(),
+        depth: 24,
+        pre_defined3: -1,
+        ..Default::default()
+    };
+    // AvcC.
+    let avcc = B::leaf(MyAvcC(params.extra_data.clone()));
+
+    let sample_entry = match encryption {
+        // Avc1.
+        None => B::leaf(avc1).with_children([avcc]),
+        // Encv.
+        Some(encryption) => B::leaf(mp4::Encv(avc1)).with_children([
+            avcc,
+            // Sinf.
+            B::leaf(mp4::Sinf).with_children([
+                // Frma.
+                B::leaf(mp4::Frma {
+                    data_format: mp4::TYPE_AVC1,
+                }),
+                // Schm.
+                B::leaf(mp4::Schm {
+                    full_box: mp4::FullBox::default(),
+                    scheme_type: encryption.scheme_type,
+                    scheme_version: 0x0001_0000,
+                }),
+                // Schi.
+                B::leaf(mp4::Schi).with_children([
+                    // Tenc.
+                    B::leaf(encryption.tenc()),
+                ]),
+            ]),
+        ]),
+    };
+
+    let stsd = B::leaf(mp4::Stsd {
         full_box: mp4::FullBox::default(),
         entry_count: 1,
     })
-    .with_child(
-        // Avc1.
-        mp4::BoxesAsync::new(mp4::Avc1 {
-            sample_entry: mp4::SampleEntry {
-                data_reference_index: 1,
-                ..Default::default()
-            },
-            width: params.width,
-            height: params.height,
-            horiz_resolution: 4_718_592,
-            vert_resolution: 4_718_592,
-            frame_count: 1,
-            depth: 24,
-            pre_defined3: -1,
-            ..Default::default()
-        })
-        .with_child(
-            // AvcC.
-            mp4::BoxesAsync::new(MyAvcC(params.extra_data.clone())),
-        ),
-    );
+    .with_children([sample_entry]);
 
     stsd
 }
@@ -413,6 +1358,13 @@ impl ImmutableBox for MyAvcC {
         self.0.len()
     }
 }
+impl ImmutableBoxSync for MyAvcC {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        w.write_all(&self.0)?;
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl ImmutableBoxAsync for MyAvcC {
     async fn marshal(
@@ -424,12 +1376,6 @@ impl ImmutableBoxAsync for MyAvcC {
     }
 }
 
-impl From<MyAvcC> for Box<dyn ImmutableBoxAsync> {
-    fn from(value: MyAvcC) -> Self {
-        Box::new(value)
-    }
-}
-
 pub struct MyStco {
     pub full_box: FullBox,
     pub chunk_offsets: Arc<std::sync::Mutex<Vec<u32>>>,
@@ -445,6 +1391,22 @@ impl ImmutableBox for MyStco {
     }
 }
 
+impl ImmutableBoxSync for MyStco {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field(w)?;
+        let chunk_offsets = self.chunk_offsets.lock().expect("not posioned");
+        w.write_all(
+            &u32::try_from(chunk_offsets.len())
+                .map_err(|e| Mp4Error::FromInt("stco".to_owned(), e))?
+                .to_be_bytes(),
+        )?;
+        for offset in chunk_offsets.iter() {
+            w.write_all(&offset.to_be_bytes())?;
+        }
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl ImmutableBoxAsync for MyStco {
     async fn marshal(
@@ -466,12 +1428,6 @@ impl ImmutableBoxAsync for MyStco {
     }
 }
 
-impl From<MyStco> for Box<dyn ImmutableBoxAsync> {
-    fn from(value: MyStco) -> Self {
-        Box::new(value)
-    }
-}
-
 #[allow(clippy::unwrap_used)]
 #[cfg(test)]
 mod tests {
@@ -480,6 +1436,7 @@ mod tests {
     use pretty_assertions::assert_eq;
     use pretty_hex::pretty_hex;
     use std::io::Cursor;
+    use tokio::io::AsyncReadExt;
 
     #[tokio::test]
     #[allow(clippy::too_many_lines)]
@@ -539,9 +1496,16 @@ mod tests {
         };
 
         let start_time = UnixH264::new(1);
-        let mdat_size = generate_mp4(&mut buf, start_time, samples.iter(), &params)
-            .await
-            .unwrap();
+        let mdat_size = generate_mp4(
+            &mut buf,
+            start_time,
+            samples.iter(),
+            &params,
+            None,
+            Timescale::default(),
+        )
+        .await
+        .unwrap();
         assert_eq!(6, mdat_size);
 
         let want = vec![
@@ -699,5 +1663,530 @@ mod tests {
         ];
 
         assert_eq!(pretty_hex(&want), pretty_hex(&buf.into_inner()));
+
+        let mut buf = Vec::new();
+        let mdat_size = generate_mp4_sync(
+            &mut buf,
+            start_time,
+            samples.iter(),
+            &params,
+            None,
+            None,
+            Timescale::default(),
+        )
+        .unwrap();
+        assert_eq!(6, mdat_size);
+        assert_eq!(pretty_hex(&want), pretty_hex(&buf));
+    }
+
+    #[tokio::test]
+    async fn test_generate_mp4_sync_async_equal() {
+        let samples: Vec<_> = (0..100)
+            .map(|i| Sample {
+                random_access_present: i % 30 == 0,
+                pts: UnixH264::new(i * 10 + 5),
+                dts_offset: DtsOffset::new(i32::try_from(i % 3).unwrap()),
+                duration: DurationH264::new(10 + (i % 2)),
+                data_size: u32::try_from(i).unwrap() + 1,
+                data_offset: 0,
+            })
+            .collect();
+        let params = TrackParameters {
+            width: 1920,
+            height: 1080,
+            extra_data: vec![1, 0x64, 0, 0x28, 0xff, 0xe0, 0],
+        };
+        let start_time = UnixH264::new(0);
+
+        let mut async_buf = Vec::new();
+        let async_size = generate_mp4(
+            &mut async_buf,
+            start_time,
+            samples.iter(),
+            &params,
+            None,
+            Timescale::default(),
+        )
+        .await
+        .unwrap();
+
+        let mut sync_buf = Vec::new();
+        let sync_size = generate_mp4_sync(
+            &mut sync_buf,
+            start_time,
+            samples.iter(),
+            &params,
+            None,
+            None,
+            Timescale::default(),
+        )
+        .unwrap();
+
+        assert_eq!(async_size, sync_size);
+        assert_eq!(pretty_hex(&async_buf), pretty_hex(&sync_buf));
+    }
+
+    #[test]
+    fn test_generate_mp4_branding() {
+        let samples = [Sample {
+            random_access_present: true,
+            pts: UnixH264::new(0),
+            dts_offset: DtsOffset::new(0),
+            duration: DurationH264::new(9),
+            data_size: 2,
+            data_offset: 0,
+        }];
+        let params = TrackParameters {
+            width: 64,
+            height: 64,
+            extra_data: vec![1, 0x64, 0, 0x28, 0xff, 0xe0, 0],
+        };
+        let branding = Mp4Branding {
+            compressor_name: "SentryShot H.264 encoder, with a long name".to_owned(),
+            handler_name: "SentryShot\0ignored".to_owned(),
+        };
+
+        let mut buf = Vec::new();
+        generate_mp4_sync(
+            &mut buf,
+            UnixH264::new(0),
+            samples.iter(),
+            &params,
+            None,
+            Some(&branding),
+            Timescale::default(),
+        )
+        .unwrap();
+
+        let find = |needle: &[u8]| buf.windows(needle.len()).position(|v| v == needle);
+
+        let mut want_hdlr = Vec::new();
+        want_hdlr.extend_from_slice(b"vide");
+        want_hdlr.extend_from_slice(&[0; 12]); // Reserved.
+        want_hdlr.extend_from_slice(b"SentryShot\0");
+        assert!(find(&want_hdlr).is_some());
+        assert!(find(b"ignored").is_none());
+
+        let mut want_compressor_name = vec![0, 1]; // Frame count.
+        want_compressor_name.push(31);
+        want_compressor_name.extend_from_slice(b"SentryShot H.264 encoder, with ");
+        want_compressor_name.extend_from_slice(&[0, 0x18]); // Depth.
+        assert!(find(&want_compressor_name).is_some());
+
+        // Defaults.
+        let mut buf = Vec::new();
+        generate_mp4_sync(
+            &mut buf,
+            UnixH264::new(0),
+            samples.iter(),
+            &params,
+            None,
+            None,
+            Timescale::default(),
+        )
+        .unwrap();
+        let find = |needle: &[u8]| buf.windows(needle.len()).position(|v| v == needle);
+        assert!(find(b"vide\0\0\0\0\0\0\0\0\0\0\0\0VideoHandler\0").is_some());
+        let mut want_compressor_name = vec![0; 32];
+        want_compressor_name.extend_from_slice(&[0, 0x18]); // Depth.
+        assert!(find(&want_compressor_name).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_mp4_header_streaming() {
+        let samples: Vec<_> = (0..3)
+            .map(|i| Sample {
+                random_access_present: i == 0,
+                pts: UnixH264::new(i * 10),
+                dts_offset: DtsOffset::new(0),
+                duration: DurationH264::new(10),
+                data_size: 2,
+                data_offset: u32::try_from(i * 2).unwrap(),
+            })
+            .collect();
+        let params = TrackParameters {
+            width: 64,
+            height: 64,
+            extra_data: vec![1, 0x64, 0, 0x28, 0xff, 0xe0, 0],
+        };
+        let header = Mp4Header::new(
+            UnixH264::new(0),
+            vec![(samples.iter(), &params)],
+            None,
+            None,
+            Timescale::default(),
+        )
+        .unwrap();
+        assert_eq!(6, header.mdat_size());
+
+        let mut buffered = Vec::new();
+        generate_mp4_sync(
+            &mut buffered,
+            UnixH264::new(0),
+            samples.iter(),
+            &params,
+            None,
+            None,
+            Timescale::default(),
+        )
+        .unwrap();
+
+        // Stream through a pipe that's smaller than the header.
+        let (mut w, mut r) = tokio::io::duplex(16);
+        let reader = tokio::spawn(async move {
+            let mut streamed = Vec::new();
+            r.read_to_end(&mut streamed).await.unwrap();
+            streamed
+        });
+        header.write(&mut w).await.unwrap();
+        drop(w);
+        let streamed = reader.await.unwrap();
+
+        assert_eq!(header.size(), streamed.len());
+        assert_eq!(pretty_hex(&buffered), pretty_hex(&streamed));
+    }
+
+    #[tokio::test]
+    async fn test_generate_mp4_audio() {
+        let video = [Sample {
+            random_access_present: true,
+            pts: UnixH264::new(0),
+            dts_offset: DtsOffset::new(0),
+            duration: DurationH264::new(5760),
+            data_size: 2,
+            data_offset: 0,
+        }];
+        // 1024 sample frames at 48khz.
+        let audio: Vec<_> = (0..3)
+            .map(|i| Sample {
+                random_access_present: true,
+                pts: UnixH264::new(i * 1920),
+                dts_offset: DtsOffset::new(0),
+                duration: DurationH264::new(1920),
+                data_size: 3,
+                data_offset: u32::try_from(i * 3).unwrap(),
+            })
+            .collect();
+        let params = TrackParameters {
+            width: 64,
+            height: 64,
+            extra_data: vec![1, 0x64, 0, 0x28, 0xff, 0xe0, 0],
+        };
+        let audio_params = AudioParameters {
+            channel_count: 1,
+            sample_rate: 48000,
+            config: vec![0x11, 0x88],
+        };
+        let audio_track = AudioTrack {
+            samples: &audio,
+            params: &audio_params,
+        };
+
+        let header = Mp4Header::new(
+            UnixH264::new(0),
+            vec![(video.iter(), &params)],
+            Some(audio_track),
+            None,
+            Timescale::default(),
+        )
+        .unwrap();
+        assert_eq!(2 + 9, header.mdat_size());
+        let mut streamed = Vec::new();
+        header.write(&mut streamed).await.unwrap();
+
+        let mut buf = Vec::new();
+        generate_mp4_sync(
+            &mut buf,
+            UnixH264::new(0),
+            video.iter(),
+            &params,
+            Some(audio_track),
+            None,
+            Timescale::default(),
+        )
+        .unwrap();
+        assert_eq!(pretty_hex(&streamed), pretty_hex(&buf));
+        buf.extend_from_slice(&[0; 11]);
+
+        let mut out = Vec::new();
+        mp4::dump(&mut Cursor::new(&buf), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\n        [smhd] offset="), "{out}");
+        assert!(out.contains("\n              [esds] offset="), "{out}");
+
+        let find = |want: &[u8]| buf.windows(want.len()).any(|v| v == want);
+        assert!(find(b"soun"));
+        // Mdhd timescale is the sample rate.
+        assert!(find(&48000_u32.to_be_bytes()));
+        // Single stts entry with three 1024 sample frames.
+        assert!(find(&[0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 4, 0]));
+
+        // The audio chunk starts after the video data.
+        let stco_offsets: Vec<usize> = out
+            .lines()
+            .filter_map(|v| v.trim_start().strip_prefix("[stco] offset="))
+            .map(|v| v.split(' ').next().unwrap().parse().unwrap())
+            .collect();
+        let chunk_offset = |stco: usize| {
+            let offset = u32::from_be_bytes(buf[stco + 16..stco + 20].try_into().unwrap());
+            usize::try_from(offset).unwrap()
+        };
+        assert_eq!(2, stco_offsets.len());
+        assert_eq!(header.size(), chunk_offset(stco_offsets[0]));
+        assert_eq!(header.size() + 2, chunk_offset(stco_offsets[1]));
+    }
+
+    #[test]
+    fn test_generate_fragments() {
+        let samples: Vec<_> = (0..5)
+            .map(|i| Sample {
+                random_access_present: i == 0 || i == 3,
+                pts: UnixH264::new(i * 10 + 5),
+                dts_offset: DtsOffset::new(0),
+                duration: DurationH264::new(10),
+                data_size: 2,
+                data_offset: 0,
+            })
+            .collect();
+        let samples: Vec<_> = samples.iter().collect();
+
+        let fragments =
+            generate_fragments(UnixH264::new(5), &samples, Timescale::default(), None).unwrap();
+        let got: Vec<_> = fragments
+            .iter()
+            .map(|v| (v.first_sample, v.n_samples, v.header.len()))
+            .collect();
+        assert_eq!(vec![(0, 3, 144), (3, 2, 128)], got);
+
+        let header = &fragments[1].header;
+        assert_eq!(b"moof", &header[4..8]);
+        // Sequence number.
+        assert_eq!([0, 0, 0, 2], header[20..24]);
+        // Base media decode time.
+        assert_eq!([0, 0, 0, 0, 0, 0, 0, 30], header[60..68]);
+        // Data offset.
+        assert_eq!([0, 0, 0, 128], header[84..88]);
+        // Mdat.
+        assert_eq!([0, 0, 0, 12, b'm', b'd', b'a', b't'], header[120..]);
+
+        let sidx =
+            generate_sidx(UnixH264::new(5), &samples, &fragments, Timescale::default()).unwrap();
+        assert_eq!(64, sidx.len());
+        assert_eq!(b"sidx", &sidx[4..8]);
+        // Earliest presentation time.
+        assert_eq!([0, 0, 0, 0, 0, 0, 0, 0], sidx[20..28]);
+        // Referenced size and subsegment duration.
+        assert_eq!([0, 0, 0, 144 + 6, 0, 0, 0, 30], sidx[40..48]);
+        assert_eq!([0, 0, 0, 128 + 4, 0, 0, 0, 20], sidx[52..60]);
+        // Starts with SAP.
+        assert_eq!([0x90, 0, 0, 0], sidx[60..64]);
+    }
+
+    // Returns the content of the first box of the type.
+    fn find_box(buf: &[u8], typ: [u8; 4]) -> &[u8] {
+        let pos = buf.windows(4).position(|v| v == typ).unwrap();
+        let size = u32::from_be_bytes(buf[pos - 4..pos].try_into().unwrap());
+        &buf[pos + 4..pos - 4 + usize::try_from(size).unwrap()]
+    }
+
+    fn be_u32(buf: &[u8], pos: usize) -> u32 {
+        u32::from_be_bytes(buf[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_generate_mp4_timescale() {
+        // Reordered samples like `test_generate_mp4`.
+        let samples = [
+            (false, 54000, -27000),
+            (false, 81000, 27000),
+            (true, 42000, 15000),
+        ]
+        .map(|(random_access_present, pts, dts_offset)| Sample {
+            random_access_present,
+            pts: UnixH264::new(pts),
+            dts_offset: DtsOffset::new(dts_offset),
+            duration: DurationH264::new(3000),
+            data_size: 2,
+            data_offset: 0,
+        });
+        let params = TrackParameters {
+            width: 64,
+            height: 64,
+            extra_data: vec![1, 0x64, 0, 0x28, 0xff, 0xe0, 0],
+        };
+
+        // Mdhd timescale and duration, stts deltas and ctts offsets.
+        let mux = |timescale: u32| {
+            let mut buf = Vec::new();
+            generate_mp4_sync(
+                &mut buf,
+                UnixH264::new(3000),
+                samples.iter(),
+                &params,
+                None,
+                None,
+                Timescale::new(NonZeroU32::new(timescale).unwrap()),
+            )
+            .unwrap();
+            let mdhd = find_box(&buf, *b"mdhd");
+            let stts = find_box(&buf, *b"stts");
+            let ctts = find_box(&buf, *b"ctts");
+            let entries = |v: &[u8]| -> Vec<u32> {
+                (0..be_u32(v, 4))
+                    .map(|i| be_u32(v, 12 + usize::try_from(i).unwrap() * 8))
+                    .collect()
+            };
+            (
+                be_u32(mdhd, 12),
+                be_u32(mdhd, 16),
+                entries(stts),
+                entries(ctts),
+            )
+        };
+        assert_eq!(
+            (90000, 27000, vec![3000], vec![0, 54000, 42000]),
+            mux(90000)
+        );
+        assert_eq!((30000, 9000, vec![1000], vec![0, 18000, 14000]), mux(30000));
+
+        // Sidx timescale, decode time and first sample duration
+        // of the second fragment and duration of the first fragment.
+        let samples: Vec<_> = (0..4)
+            .map(|i| Sample {
+                random_access_present: i % 2 == 0,
+                pts: UnixH264::new(i * 3000),
+                dts_offset: DtsOffset::new(0),
+                duration: DurationH264::new(3000),
+                data_size: 2,
+                data_offset: 0,
+            })
+            .collect();
+        let samples: Vec<_> = samples.iter().collect();
+        let fragment = |timescale: u32| {
+            let timescale = Timescale::new(NonZeroU32::new(timescale).unwrap());
+            let fragments =
+                generate_fragments(UnixH264::new(0), &samples, timescale, None).unwrap();
+            let sidx = generate_sidx(UnixH264::new(0), &samples, &fragments, timescale).unwrap();
+            let header = &fragments[1].header;
+            (
+                be_u32(&sidx, 16),
+                be_u32(header, 64),
+                be_u32(header, 88),
+                be_u32(&sidx, 44),
+            )
+        };
+        assert_eq!((90000, 6000, 3000, 6000), fragment(90000));
+        assert_eq!((30000, 2000, 1000, 2000), fragment(30000));
+
+        // Rounding errors don't accumulate.
+        assert_eq!((1000, 66, 34, 66), fragment(1000));
+    }
+
+    #[test]
+    fn test_generate_init_encryption() {
+        let params = TrackParameters {
+            width: 64,
+            height: 64,
+            extra_data: vec![1, 0x64, 0, 0x28, 0xff, 0xe0, 0],
+        };
+        let encryption = TrackEncryption {
+            scheme_type: mp4::SCHEME_TYPE_CENC,
+            kid: [1; 16],
+            per_sample_iv_size: 8,
+            protection_systems: vec![ProtectionSystem {
+                system_id: [2; 16],
+                kids: Vec::new(),
+                data: vec![3, 4],
+            }],
+            ..Default::default()
+        };
+        let buf = generate_init(&params, Timescale::default(), Some(&encryption)).unwrap();
+
+        // Ftyp is 24 bytes and the moov box is the rest.
+        assert_eq!(buf.len() - 24, usize::try_from(be_u32(&buf, 24)).unwrap());
+        // Sample entry, avcC and sinf.
+        assert_eq!(78 + 15 + 80, find_box(&buf, *b"encv").len());
+        assert_eq!(b"avc1", find_box(&buf, *b"frma"));
+        assert_eq!(
+            [0, 0, 0, 0, b'c', b'e', b'n', b'c', 0, 1, 0, 0],
+            find_box(&buf, *b"schm")
+        );
+        let mut want_tenc = vec![0, 0, 0, 0, 0, 0, 1, 8];
+        want_tenc.extend([1; 16]);
+        assert_eq!(want_tenc, find_box(&buf, *b"tenc"));
+        let mut want_pssh = vec![0, 0, 0, 0];
+        want_pssh.extend([2; 16]);
+        want_pssh.extend([0, 0, 0, 2, 3, 4]);
+        assert_eq!(want_pssh, find_box(&buf, *b"pssh"));
+
+        let buf = generate_init(&params, Timescale::default(), None).unwrap();
+        assert!(!buf.windows(4).any(|v| v == b"encv" || v == b"sinf"));
+    }
+
+    #[test]
+    fn test_generate_fragments_encryption() {
+        let samples: Vec<_> = (0..3)
+            .map(|i| Sample {
+                random_access_present: i == 0,
+                pts: UnixH264::new(i * 10),
+                dts_offset: DtsOffset::new(0),
+                duration: DurationH264::new(10),
+                data_size: 32,
+                data_offset: 0,
+            })
+            .collect();
+        let samples: Vec<_> = samples.iter().collect();
+        let encryption: Vec<_> = (0..3)
+            .map(|i| mp4::SencSample {
+                iv: vec![i; 8],
+                subsamples: if i == 1 {
+                    vec![mp4::SencSubsample {
+                        bytes_of_clear_data: 5,
+                        bytes_of_protected_data: 16,
+                    }]
+                } else {
+                    Vec::new()
+                },
+            })
+            .collect();
+
+        let fragments = generate_fragments(
+            UnixH264::new(0),
+            &samples,
+            Timescale::default(),
+            Some(&encryption),
+        )
+        .unwrap();
+        assert_eq!(1, fragments.len());
+        let header = &fragments[0].header;
+
+        // Trun, saiz, saio, senc and mdat.
+        assert_eq!(136 + 20 + 20 + 52 + 8, header.len());
+        // Data offset.
+        assert_eq!(236, be_u32(header, 84));
+        // Per sample info sizes.
+        assert_eq!(
+            [0, 0, 0, 0, 0, 0, 0, 0, 3, 10, 16, 10],
+            find_box(header, *b"saiz")
+        );
+        // Subsample encryption flag.
+        assert_eq!([0, 0, 0, 2], find_box(header, *b"senc")[..4]);
+
+        // The saio offset points to the first IV.
+        let offset = be_u32(find_box(header, *b"saio"), 8);
+        assert_eq!(192, offset);
+        let offset = usize::try_from(offset).unwrap();
+        assert_eq!([0; 8], header[offset..offset + 8]);
+        assert_eq!([0, 1, 0, 5, 0, 0, 0, 16], header[offset + 18..offset + 26]);
+
+        assert!(matches!(
+            generate_fragments(
+                UnixH264::new(0),
+                &samples,
+                Timescale::default(),
+                Some(&encryption[1..]),
+            ),
+            Err(GenerateMp4Error::SampleEncryptionCount(2, 3))
+        ));
     }
 }
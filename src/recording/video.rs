@@ -2,11 +2,11 @@
 
 use common::{
     time::{DtsOffset, DurationH264, UnixH264},
-    PartFinalized, VideoSample,
+    AudioParameters, AudioSample, PartFinalized, VideoSample,
 };
 use std::sync::Arc;
 use thiserror::Error;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 
 // Sample flags.
 const FLAG_RANDOM_ACCESS_PRESENT: u8 = 0b1000_0000;
@@ -81,11 +81,15 @@ impl Sample {
 
 // Writes videos in our custom format.
 #[allow(clippy::module_name_repetitions)]
-pub struct VideoWriter<'a, W: AsyncWrite + Unpin> {
-    meta: &'a mut W, // Output file.
-    mdat: &'a mut W, // Output file.
+pub struct VideoWriter<'a, W: AsyncWrite + Unpin, W2: AsyncWrite + Unpin = W> {
+    meta: &'a mut W,  // Output file.
+    mdat: &'a mut W2, // Output file, may be encrypted.
 
     mdat_pos: u32,
+    sample_count: u32,
+
+    // Random access samples, written to the index file on request.
+    index: Vec<IndexEntry>,
 }
 
 #[derive(Debug, Error)]
@@ -112,21 +116,29 @@ pub enum WriteSampleError {
     Sub,
 }
 
-impl<'a, W: AsyncWrite + Unpin> VideoWriter<'a, W> {
+impl<'a, W: AsyncWrite + Unpin, W2: AsyncWrite + Unpin> VideoWriter<'a, W, W2> {
     // Creates a new writer and writes the header.
     pub async fn new(
         meta: &'a mut W,
-        mdat: &'a mut W,
+        mdat: &'a mut W2,
         header: MetaHeader,
-    ) -> Result<VideoWriter<'a, W>, CreateVideoWriterError> {
+    ) -> Result<VideoWriter<'a, W, W2>, CreateVideoWriterError> {
         meta.write_all(&header.marshal()?).await?;
         Ok(Self {
             meta,
             mdat,
             mdat_pos: 0,
+            sample_count: 0,
+            index: Vec::new(),
         })
     }
 
+    // Number of bytes written to the mdat file.
+    #[must_use]
+    pub fn mdat_size(&self) -> u32 {
+        self.mdat_pos
+    }
+
     // Writes HLS parts in the custom format to the output files.
     pub async fn write_parts(
         &mut self,
@@ -162,12 +174,209 @@ impl<'a, W: AsyncWrite + Unpin> VideoWriter<'a, W> {
 
         self.meta.write_all(&s.encode()?).await.map_err(Write)?;
 
+        if s.random_access_present {
+            self.index.push(IndexEntry {
+                pts: s.pts,
+                sample_index: self.sample_count,
+            });
+        }
+        self.sample_count += 1;
+
         Ok(())
     }
+
+    // Writes the index of all random access samples written so far.
+    pub async fn write_index<I: AsyncWrite + Unpin>(
+        &self,
+        out: &mut I,
+    ) -> Result<(), std::io::Error> {
+        let mut buf = Vec::with_capacity(1 + self.index.len() * INDEX_ENTRY_SIZE);
+        buf.push(INDEX_API_VERSION);
+        for entry in &self.index {
+            buf.extend_from_slice(&entry.encode());
+        }
+        out.write_all(&buf).await?;
+        out.flush().await
+    }
+}
+
+// Writes the audio samples of a recording. The audio meta file has no
+// header, it only contains samples in the same format as the meta file.
+// The sample data is written to a separate audio mdat file.
+pub struct AudioWriter<'a, W: AsyncWrite + Unpin> {
+    meta: &'a mut W,
+    mdat: &'a mut W,
+    mdat_pos: u32,
+}
+
+impl<'a, W: AsyncWrite + Unpin> AudioWriter<'a, W> {
+    #[must_use]
+    pub fn new(meta: &'a mut W, mdat: &'a mut W) -> Self {
+        Self {
+            meta,
+            mdat,
+            mdat_pos: 0,
+        }
+    }
+
+    // Writes the audio samples of the HLS parts to the output files.
+    pub async fn write_parts(
+        &mut self,
+        parts: &Vec<Arc<PartFinalized>>,
+    ) -> Result<(), WriteSampleError> {
+        use WriteSampleError::*;
+
+        for part in parts {
+            for sample in part.audio_samples.iter() {
+                self.write_sample(sample).await?;
+            }
+        }
+        self.mdat.flush().await.map_err(Flush)?;
+        self.meta.flush().await.map_err(Flush)?;
+        Ok(())
+    }
+
+    pub async fn write_sample(&mut self, sample: &AudioSample) -> Result<(), WriteSampleError> {
+        use WriteSampleError::*;
+
+        let s = Sample {
+            random_access_present: true,
+            pts: sample.pts,
+            dts_offset: DtsOffset::new(0),
+            duration: sample.duration,
+            data_offset: self.mdat_pos,
+            data_size: u32::try_from(sample.data.len())?,
+        };
+
+        self.mdat.write_all(&sample.data).await.map_err(Write)?;
+        self.mdat_pos += s.data_size;
+
+        self.meta.write_all(&s.encode()?).await.map_err(Write)?;
+        Ok(())
+    }
+}
+
+// Reads all samples in a audio meta file.
+pub async fn read_audio_meta<T>(mut file: T, file_size: u64) -> Result<Vec<Sample>, ReadMetaError>
+where
+    T: AsyncRead + Unpin,
+{
+    let sample_count = usize::try_from(file_size / u64::from(SAMPLE_SIZE_U8))?;
+    let mut buf = [0; SAMPLE_SIZE];
+    let mut samples = Vec::with_capacity(sample_count);
+    for _ in 0..sample_count {
+        file.read_exact(&mut buf)
+            .await
+            .map_err(ReadMetaError::Read)?;
+        samples.push(Sample::from_bytes(&buf));
+    }
+    Ok(samples)
+}
+
+// Index file.
+//
+// header {
+//     version u8
+// }
+// entry {
+//     pts i64
+//     sampleIndex u32
+// }
+const INDEX_API_VERSION: u8 = 1;
+const INDEX_ENTRY_SIZE: usize = 12;
+
+// Maps the time of a random access sample to its position in the meta file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub pts: UnixH264,
+    pub sample_index: u32,
+}
+
+impl IndexEntry {
+    fn encode(&self) -> [u8; INDEX_ENTRY_SIZE] {
+        let mut out = [0; INDEX_ENTRY_SIZE];
+        out[..8].copy_from_slice(&self.pts.to_be_bytes());
+        out[8..].copy_from_slice(&self.sample_index.to_be_bytes());
+        out
+    }
+
+    fn from_bytes(b: &[u8; INDEX_ENTRY_SIZE]) -> Self {
+        Self {
+            pts: UnixH264::new(i64::from_be_bytes([
+                b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+            ])),
+            sample_index: u32::from_be_bytes([b[8], b[9], b[10], b[11]]),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ReadIndexError {
+    #[error("unsupported version")]
+    UnsupportedVersion,
+
+    #[error("read: {0}")]
+    Read(#[from] std::io::Error),
+
+    #[error("{0}")]
+    TryFromInt(#[from] std::num::TryFromIntError),
+}
+
+// Reads all entries in a index file.
+pub async fn read_index<T>(mut file: T, file_size: u64) -> Result<Vec<IndexEntry>, ReadIndexError>
+where
+    T: AsyncRead + Unpin,
+{
+    let mut api_version = [0];
+    file.read_exact(&mut api_version).await?;
+    if api_version[0] != INDEX_API_VERSION {
+        return Err(ReadIndexError::UnsupportedVersion);
+    }
+
+    let entry_count = usize::try_from((file_size - 1) / u64::try_from(INDEX_ENTRY_SIZE)?)?;
+    let mut buf = [0; INDEX_ENTRY_SIZE];
+    let mut entries = Vec::with_capacity(entry_count);
+    for _ in 0..entry_count {
+        file.read_exact(&mut buf).await?;
+        entries.push(IndexEntry::from_bytes(&buf));
+    }
+    Ok(entries)
+}
+
+// Returns the index of the last random access sample at or before `time`.
+#[must_use]
+pub fn index_lookup(index: &[IndexEntry], time: UnixH264) -> Option<u32> {
+    let i = index.partition_point(|entry| entry.pts <= time);
+    Some(index.get(i.checked_sub(1)?)?.sample_index)
+}
+
+// Selects `count` evenly spaced keyframes between `start` and `end`, used to
+// build contact sheets. Each slot uses the last keyframe at or before the slot
+// time, or the first keyframe. Short recordings may return fewer keyframes.
+#[must_use]
+pub fn select_keyframes(
+    index: &[IndexEntry],
+    start: UnixH264,
+    end: UnixH264,
+    count: u32,
+) -> Vec<IndexEntry> {
+    let duration = (*end - *start).max(0);
+    let mut selected: Vec<IndexEntry> = Vec::new();
+    for slot in 0..count {
+        let time =
+            UnixH264::new(*start + duration.saturating_mul(i64::from(slot)) / i64::from(count));
+        let i = index.partition_point(|entry| entry.pts <= time);
+        let Some(entry) = index.get(i.saturating_sub(1)) else {
+            break;
+        };
+        if selected.last() != Some(entry) {
+            selected.push(*entry);
+        }
+    }
+    selected
 }
 
 // Reads a single meta file.
-#[allow(dead_code)]
 pub struct MetaReader<T: AsyncRead + AsyncSeek + Unpin> {
     file: T,
 
@@ -220,6 +429,133 @@ impl<T: AsyncRead + AsyncSeek + Unpin> MetaReader<T> {
             header,
         ))
     }
+
+    #[must_use]
+    pub fn sample_count(&self) -> usize {
+        self.sample_count
+    }
+
+    // Reads a single sample, returns None if `i` is out of bounds.
+    pub async fn read_sample(&mut self, i: usize) -> Result<Option<Sample>, ReadMetaError> {
+        if i >= self.sample_count {
+            return Ok(None);
+        }
+        self.seek_sample(i).await?;
+        let mut buf = [0; SAMPLE_SIZE];
+        self.file
+            .read_exact(&mut buf)
+            .await
+            .map_err(ReadMetaError::Read)?;
+        Ok(Some(Sample::from_bytes(&buf)))
+    }
+
+    // Reads samples starting from sample `start` until `pred` returns false.
+    pub async fn read_samples_while<F>(
+        &mut self,
+        start: usize,
+        mut pred: F,
+    ) -> Result<Vec<Sample>, ReadMetaError>
+    where
+        F: FnMut(&Sample) -> bool,
+    {
+        use ReadMetaError::*;
+        let mut samples = Vec::new();
+        if start >= self.sample_count {
+            return Ok(samples);
+        }
+        self.seek_sample(start).await?;
+
+        let mut buf = [0; SAMPLE_SIZE];
+        for _ in start..self.sample_count {
+            self.file.read_exact(&mut buf).await.map_err(Read)?;
+            let sample = Sample::from_bytes(&buf);
+            if !pred(&sample) {
+                break;
+            }
+            samples.push(sample);
+        }
+        Ok(samples)
+    }
+
+    // Returns the index of the first sample for which `pred` returns false.
+    // The samples must be partitioned by `pred`, only the samples visited
+    // by the binary search are read.
+    pub async fn partition_point<F>(&mut self, mut pred: F) -> Result<usize, ReadMetaError>
+    where
+        F: FnMut(&Sample) -> bool,
+    {
+        use ReadMetaError::*;
+        let mut buf = [0; SAMPLE_SIZE];
+        let (mut low, mut high) = (0, self.sample_count);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            self.seek_sample(mid).await?;
+            self.file.read_exact(&mut buf).await.map_err(Read)?;
+            if pred(&Sample::from_bytes(&buf)) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(low)
+    }
+
+    async fn seek_sample(&mut self, i: usize) -> Result<(), ReadMetaError> {
+        let offset = self.header_size + u64::try_from(i * SAMPLE_SIZE)?;
+        self.file
+            .seek(std::io::SeekFrom::Start(offset))
+            .await
+            .map_err(ReadMetaError::Seek)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SeekKeyframeError {
+    #[error("create meta reader: {0}")]
+    CreateMetaReader(#[from] CreateMetaReaderError),
+
+    #[error("read meta: {0}")]
+    ReadMeta(#[from] ReadMetaError),
+
+    #[error("{0}")]
+    TryFromInt(#[from] std::num::TryFromIntError),
+}
+
+// Returns the last random access sample at or before `time`.
+// The index is used if available to avoid reading the whole meta file,
+// the meta file is scanned if the index doesn't match the samples.
+pub async fn seek_keyframe<T>(
+    file: T,
+    file_size: u64,
+    index: Option<&[IndexEntry]>,
+    time: UnixH264,
+) -> Result<Option<Sample>, SeekKeyframeError>
+where
+    T: AsyncRead + AsyncSeek + Unpin,
+{
+    let (mut reader, _) = MetaReader::new(file, file_size).await?;
+    if let Some(index) = index {
+        let i = index.partition_point(|entry| entry.pts <= time);
+        let Some(entry) = i.checked_sub(1).and_then(|i| index.get(i)) else {
+            return Ok(None);
+        };
+        if let Some(sample) = reader
+            .read_sample(usize::try_from(entry.sample_index)?)
+            .await?
+        {
+            if sample.random_access_present && sample.pts == entry.pts {
+                return Ok(Some(sample));
+            }
+        }
+        // Stale index.
+    }
+
+    // Keyframes are in presentation order, stop at the first one after `time`.
+    let samples = reader
+        .read_samples_while(0, |s| !(s.random_access_present && s.pts > time))
+        .await?;
+    Ok(samples.into_iter().rev().find(|s| s.random_access_present))
 }
 
 // Reads header and all samples.
@@ -246,6 +582,10 @@ where
     Ok((header, samples))
 }
 
+// Meta file versions, version 2 is only used if the recording has audio.
+const META_API_VERSION: u8 = 1;
+const META_API_VERSION_AUDIO: u8 = 2;
+
 // Recording meta file header.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct MetaHeader {
@@ -253,6 +593,14 @@ pub struct MetaHeader {
     pub width: u16,
     pub height: u16,
     pub extra_data: Vec<u8>,
+
+    // The audio samples are stored in the ameta and amdat files.
+    pub audio: Option<AudioParameters>,
+}
+
+// Marshaled size of the audio parameters in the header.
+fn audio_parameters_size(audio: &AudioParameters) -> usize {
+    8 + audio.config.len()
 }
 
 #[derive(Debug, Error)]
@@ -276,10 +624,10 @@ impl MetaHeader {
     ) -> Result<Self, HeaderFromReaderError> {
         let mut api_version = [0];
         r.read_exact(&mut api_version).await?;
-        if api_version[0] == 0 {
-            return Err(HeaderFromReaderError::OldVersion);
-        } else if api_version[0] != 1 {
-            return Err(HeaderFromReaderError::UnsupportedVersion);
+        match api_version[0] {
+            0 => return Err(HeaderFromReaderError::OldVersion),
+            META_API_VERSION | META_API_VERSION_AUDIO => {}
+            _ => return Err(HeaderFromReaderError::UnsupportedVersion),
         }
 
         // Start time.
@@ -306,26 +654,48 @@ impl MetaHeader {
         let mut extra_data = vec![0; size.into()];
         r.read_exact(&mut extra_data).await?;
 
+        let audio = if api_version[0] == META_API_VERSION_AUDIO {
+            let mut buf = [0; 8];
+            r.read_exact(&mut buf).await?;
+            let channel_count = u16::from_be_bytes([buf[0], buf[1]]);
+            let sample_rate = u32::from_be_bytes([buf[2], buf[3], buf[4], buf[5]]);
+            let size = u16::from_be_bytes([buf[6], buf[7]]);
+            let mut config = vec![0; size.into()];
+            r.read_exact(&mut config).await?;
+            Some(AudioParameters {
+                channel_count,
+                sample_rate,
+                config,
+            })
+        } else {
+            None
+        };
+
         Ok(MetaHeader {
             start_time,
             width,
             height,
             extra_data,
+            audio,
         })
     }
 
     // Marshaled size.
     #[must_use]
     pub fn size(&self) -> usize {
-        15 + self.extra_data.len()
+        15 + self.extra_data.len() + self.audio.as_ref().map_or(0, audio_parameters_size)
     }
 
+    // Headers without audio are written as version 1
+    // so they can be read by older versions.
     pub fn marshal(&self) -> Result<Vec<u8>, std::num::TryFromIntError> {
-        const API_VERSION: u8 = 1;
-
         let mut out = Vec::with_capacity(self.size());
 
-        out.push(API_VERSION);
+        if self.audio.is_some() {
+            out.push(META_API_VERSION_AUDIO);
+        } else {
+            out.push(META_API_VERSION);
+        }
 
         // Start time.
         out.extend_from_slice(&self.start_time.to_be_bytes());
@@ -341,6 +711,15 @@ impl MetaHeader {
         out.extend_from_slice(&extra_data_size.to_be_bytes());
         out.extend_from_slice(&self.extra_data);
 
+        // Audio.
+        if let Some(audio) = &self.audio {
+            out.extend_from_slice(&audio.channel_count.to_be_bytes());
+            out.extend_from_slice(&audio.sample_rate.to_be_bytes());
+            let config_size = u16::try_from(audio.config.len())?;
+            out.extend_from_slice(&config_size.to_be_bytes());
+            out.extend_from_slice(&audio.config);
+        }
+
         Ok(out)
     }
 
@@ -381,6 +760,7 @@ mod tests {
             width: 1920,
             height: 1080,
             extra_data: vec![0, 1],
+            audio: None,
         };
 
         let mut w = VideoWriter::new(&mut meta, &mut mdat, test_header.clone())
@@ -466,4 +846,244 @@ mod tests {
 
         assert_eq!(want_samples, samples.as_slice());
     }
+
+    fn test_sample(pts: i64, random_access_present: bool) -> VideoSample {
+        VideoSample {
+            pts: UnixH264::new(pts),
+            dts_offset: DtsOffset::new(0),
+            random_access_present,
+            avcc: Arc::new(PaddedBytes::new(vec![0])),
+            duration: DurationH264::new(10),
+        }
+    }
+
+    #[test]
+    fn test_select_keyframes() {
+        let index: Vec<IndexEntry> = (0..10)
+            .map(|i| IndexEntry {
+                pts: UnixH264::new(i * 10),
+                sample_index: u32::try_from(i * 3).unwrap(),
+            })
+            .collect();
+        let pts = |entries: Vec<IndexEntry>| -> Vec<i64> {
+            entries.iter().map(|entry| *entry.pts).collect()
+        };
+        let start = UnixH264::new(0);
+        let end = UnixH264::new(100);
+
+        assert_eq!(
+            vec![0, 20, 50, 70],
+            pts(select_keyframes(&index, start, end, 4))
+        );
+        assert_eq!(
+            vec![0, 10, 20, 30, 40, 50, 60, 70, 80, 90],
+            pts(select_keyframes(&index, start, end, 20))
+        );
+        assert!(select_keyframes(&[], start, end, 4).is_empty());
+        assert!(select_keyframes(&index, start, end, 0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_meta_header_audio() {
+        let video_only = MetaHeader {
+            start_time: UnixH264::new(1),
+            width: 2,
+            height: 3,
+            extra_data: vec![4, 5],
+            audio: None,
+        };
+        let header = MetaHeader {
+            audio: Some(AudioParameters {
+                channel_count: 2,
+                sample_rate: 48000,
+                config: vec![0x11, 0x90],
+            }),
+            ..video_only.clone()
+        };
+
+        // Headers without audio don't change.
+        assert_eq!(1, video_only.marshal().unwrap()[0]);
+
+        #[rustfmt::skip]
+        let want = vec![
+            2, // Version.
+            0, 0, 0, 0, 0, 0, 0, 1, // Start time.
+            0, 2, // Width.
+            0, 3, // Height.
+            0, 2, // Extra data size.
+            4, 5, // Extra data.
+            0, 2, // Channel count.
+            0, 0, 0xbb, 0x80, // Sample rate.
+            0, 2, // Config size.
+            0x11, 0x90, // Config.
+        ];
+        let buf = header.marshal().unwrap();
+        assert_eq!(want, buf);
+        assert_eq!(header.size(), buf.len());
+
+        for header in [video_only, header] {
+            let mut meta = Vec::new();
+            let mut mdat = Vec::new();
+            let mut w = VideoWriter::new(&mut meta, &mut mdat, header.clone())
+                .await
+                .unwrap();
+            let parts = vec![Arc::new(PartFinalized {
+                video_samples: Arc::new(vec![test_sample(10, true)]),
+                ..Default::default()
+            })];
+            w.write_parts(&parts).await.unwrap();
+
+            let meta_size = u64::try_from(meta.len()).unwrap();
+            let (got, samples) = read_meta(Cursor::new(&meta), meta_size).await.unwrap();
+            assert_eq!(header, got);
+            assert_eq!(1, samples.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audio_writer() {
+        let mut meta = Vec::new();
+        let mut mdat = Vec::new();
+        let mut w = AudioWriter::new(&mut meta, &mut mdat);
+
+        let sample = |pts, data: &[u8]| AudioSample {
+            pts: UnixH264::new(pts),
+            duration: DurationH264::new(1920),
+            data: data.to_vec().into(),
+        };
+        let parts = vec![Arc::new(PartFinalized {
+            audio_samples: Arc::new(vec![sample(10, &[1, 2]), sample(1930, &[3, 4, 5])]),
+            ..Default::default()
+        })];
+        w.write_parts(&parts).await.unwrap();
+
+        #[rustfmt::skip]
+        let want_meta = vec![
+            // Sample 1.
+            0b1000_0000, // Flags.
+            0, 0, 0, 0, 0, 0, 0, 10, // PTS.
+            0, 0, 0, 0, // DTS offset.
+            0, 0, 7, 0x80, // Duration.
+            0, 0, 0, 0, // Offset.
+            0, 0, 0, 2, // Size.
+            //
+            // Sample 2.
+            0b1000_0000, // Flags.
+            0, 0, 0, 0, 0, 0, 7, 0x8a, // PTS.
+            0, 0, 0, 0, // DTS offset.
+            0, 0, 7, 0x80, // Duration.
+            0, 0, 0, 2, // Offset.
+            0, 0, 0, 3, // Size.
+        ];
+        assert_eq!(pretty_hex(&want_meta), pretty_hex(&meta));
+        assert_eq!(vec![1, 2, 3, 4, 5], mdat);
+
+        let meta_size = u64::try_from(meta.len()).unwrap();
+        let samples = read_audio_meta(Cursor::new(&meta), meta_size)
+            .await
+            .unwrap();
+        assert_eq!(2, samples.len());
+        assert_eq!(UnixH264::new(1930), samples[1].pts);
+        assert_eq!(2, samples[1].data_offset);
+    }
+
+    #[tokio::test]
+    async fn test_seek_keyframe() {
+        let mut meta = Vec::new();
+        let mut mdat = Vec::new();
+        let mut idx = Vec::new();
+
+        let header = MetaHeader {
+            start_time: UnixH264::new(0),
+            width: 1,
+            height: 1,
+            extra_data: vec![0, 1],
+            audio: None,
+        };
+        let mut w = VideoWriter::new(&mut meta, &mut mdat, header)
+            .await
+            .unwrap();
+        let parts = vec![Arc::new(PartFinalized {
+            video_samples: Arc::new(vec![
+                test_sample(10, true),
+                test_sample(20, false),
+                test_sample(30, true),
+                test_sample(40, false),
+                test_sample(50, true),
+            ]),
+            ..Default::default()
+        })];
+        w.write_parts(&parts).await.unwrap();
+        w.write_index(&mut idx).await.unwrap();
+
+        let meta_size = u64::try_from(meta.len()).unwrap();
+        let idx_size = u64::try_from(idx.len()).unwrap();
+        let index = read_index(Cursor::new(&idx), idx_size).await.unwrap();
+        let want_index = vec![
+            IndexEntry {
+                pts: UnixH264::new(10),
+                sample_index: 0,
+            },
+            IndexEntry {
+                pts: UnixH264::new(30),
+                sample_index: 2,
+            },
+            IndexEntry {
+                pts: UnixH264::new(50),
+                sample_index: 4,
+            },
+        ];
+        assert_eq!(want_index, index);
+
+        for (time, want) in [(5, None), (10, Some(10)), (45, Some(30)), (99, Some(50))] {
+            let time = UnixH264::new(time);
+            let got = seek_keyframe(Cursor::new(&meta), meta_size, Some(&index), time)
+                .await
+                .unwrap()
+                .map(|s| *s.pts);
+            assert_eq!(want, got, "index {time:?}");
+
+            // Without index.
+            let got = seek_keyframe(Cursor::new(&meta), meta_size, None, time)
+                .await
+                .unwrap()
+                .map(|s| *s.pts);
+            assert_eq!(want, got, "no index {time:?}");
+        }
+
+        let got = seek_keyframe(
+            Cursor::new(&meta),
+            meta_size,
+            Some(&index),
+            UnixH264::new(45),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let want = Sample {
+            random_access_present: true,
+            pts: UnixH264::new(30),
+            dts_offset: DtsOffset::new(0),
+            duration: DurationH264::new(10),
+            data_size: 1,
+            data_offset: 2,
+        };
+        assert_eq!(want, got);
+
+        // Stale index.
+        let stale = [IndexEntry {
+            pts: UnixH264::new(20),
+            sample_index: 1,
+        }];
+        let got = seek_keyframe(
+            Cursor::new(&meta),
+            meta_size,
+            Some(&stale),
+            UnixH264::new(45),
+        )
+        .await
+        .unwrap()
+        .map(|s| *s.pts);
+        assert_eq!(Some(30), got);
+    }
 }
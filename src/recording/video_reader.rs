@@ -1,11 +1,15 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
 use crate::{
-    mp4_muxer::{generate_mp4, GenerateMp4Error},
-    video::{read_meta, ReadMetaError},
+    crypt::{MdatFile, RecordingKey},
+    mp4_muxer::{generate_mp4, GenerateMp4Error, Timescale},
+    video::{read_meta, ReadMetaError, Sample},
     VideoCache,
 };
+use common::VideoSample;
+use futures_lite::Stream;
 use pin_project::pin_project;
+use sentryshot_padded_bytes::PaddedBytes;
 use std::{
     io::{self, SeekFrom},
     path::{Path, PathBuf},
@@ -15,7 +19,7 @@ use std::{
 };
 use thiserror::Error;
 use tokio::{
-    io::{AsyncRead, AsyncSeek, BufReader, ReadBuf},
+    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, BufReader, ReadBuf},
     sync::Mutex,
 };
 
@@ -65,12 +69,14 @@ pub enum CreateVideoReaderError {
     TryFromInt(#[from] std::num::TryFromIntError),
 }
 
+// The mdat file is decrypted if the recording has a key.
 #[allow(clippy::module_name_repetitions)]
 pub async fn new_video_reader(
     recording_path: PathBuf,
     cache_id: u32,
     cache: &Option<Arc<Mutex<VideoCache>>>,
-) -> Result<VideoReader<MetaCursor, tokio::fs::File>, CreateVideoReaderError> {
+    key: Option<&RecordingKey>,
+) -> Result<VideoReader<MetaCursor, MdatFile>, CreateVideoReaderError> {
     use CreateVideoReaderError::*;
     let mut meta_path = recording_path.clone();
     meta_path.set_extension("meta");
@@ -98,6 +104,7 @@ pub async fn new_video_reader(
         .open(mdat_path)
         .await
         .map_err(OpenMdat)?;
+    let mdat = MdatFile::new(mdat, key).await.map_err(OpenMdat)?;
 
     Ok(VideoReader {
         mdat,
@@ -110,6 +117,89 @@ pub async fn new_video_reader(
     })
 }
 
+#[derive(Debug, Error)]
+pub enum ReadSampleError {
+    #[error("meta metadata: {0}")]
+    Metadata(std::io::Error),
+
+    #[error("open meta file: {0}")]
+    OpenMeta(std::io::Error),
+
+    #[error("read meta: {0}")]
+    ReadMeta(#[from] ReadMetaError),
+
+    #[error("open mdat: {0}")]
+    OpenMdat(std::io::Error),
+
+    #[error("seek mdat: {0}")]
+    Seek(std::io::Error),
+
+    #[error("read mdat: {0}")]
+    Read(std::io::Error),
+}
+
+// Opens a recording and returns its samples with their data, the data
+// is read from the mdat file as the stream is polled. The data is
+// decrypted if the recording has a key.
+pub async fn new_sample_stream(
+    recording_path: PathBuf,
+    key: Option<RecordingKey>,
+) -> Result<impl Stream<Item = Result<VideoSample, ReadSampleError>> + Send, ReadSampleError> {
+    use ReadSampleError::*;
+    let mut meta_path = recording_path.clone();
+    meta_path.set_extension("meta");
+    let mut mdat_path = recording_path;
+    mdat_path.set_extension("mdat");
+
+    let meta_size = tokio::fs::metadata(&meta_path)
+        .await
+        .map_err(Metadata)?
+        .len();
+    let meta = BufReader::new(tokio::fs::File::open(&meta_path).await.map_err(OpenMeta)?);
+    let (_, samples) = read_meta(meta, meta_size).await?;
+    let mdat = tokio::fs::File::open(&mdat_path).await.map_err(OpenMdat)?;
+    let mdat = MdatFile::new(mdat, key.as_ref()).await.map_err(OpenMdat)?;
+    Ok(sample_stream(mdat, samples))
+}
+
+// Returns `samples` with their data read from `mdat` as the stream
+// is polled. The stream ends after the first error.
+pub fn sample_stream<D>(
+    mdat: D,
+    samples: Vec<Sample>,
+) -> impl Stream<Item = Result<VideoSample, ReadSampleError>>
+where
+    D: AsyncRead + AsyncSeek + Unpin,
+{
+    futures_lite::stream::unfold(Some((mdat, samples.into_iter())), |state| async move {
+        let (mut mdat, mut samples) = state?;
+        let sample = samples.next()?;
+        match read_sample(&mut mdat, sample).await {
+            Ok(v) => Some((Ok(v), Some((mdat, samples)))),
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+}
+
+async fn read_sample<D>(mdat: &mut D, sample: Sample) -> Result<VideoSample, ReadSampleError>
+where
+    D: AsyncRead + AsyncSeek + Unpin,
+{
+    use ReadSampleError::*;
+    mdat.seek(SeekFrom::Start(u64::from(sample.data_offset)))
+        .await
+        .map_err(Seek)?;
+    let mut data = vec![0; usize::try_from(sample.data_size).expect("usize fit u32")];
+    mdat.read_exact(&mut data).await.map_err(Read)?;
+    Ok(VideoSample {
+        pts: sample.pts,
+        dts_offset: sample.dts_offset,
+        avcc: Arc::new(PaddedBytes::new(data)),
+        random_access_present: sample.random_access_present,
+        duration: sample.duration,
+    })
+}
+
 impl<RS1, RS2> AsyncRead for VideoReader<RS1, RS2>
 where
     RS1: AsyncRead + AsyncSeek,
@@ -378,7 +468,15 @@ async fn read_video_metadata(meta_path: &Path) -> Result<VideoMetadata, ReadVide
     let params = header.params();
 
     let mut meta_buf = Vec::new();
-    let mdat_size = generate_mp4(&mut meta_buf, header.start_time, samples.iter(), &params).await?;
+    let mdat_size = generate_mp4(
+        &mut meta_buf,
+        header.start_time,
+        samples.iter(),
+        &params,
+        None,
+        Timescale::default(),
+    )
+    .await?;
 
     Ok(VideoMetadata {
         buf: meta_buf,
@@ -497,9 +595,12 @@ mod tests {
     use std::{io::Cursor, time::UNIX_EPOCH};
 
     use super::*;
+    use crate::{EncryptWriter, MetaHeader, VideoWriter};
+    use common::time::{DtsOffset, DurationH264, UnixH264};
+    use futures_lite::StreamExt;
     use pretty_assertions::assert_eq;
     use tempfile::tempdir;
-    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+    use tokio::io::AsyncWriteExt;
 
     #[tokio::test]
     async fn test_new_video_reader() {
@@ -529,7 +630,7 @@ mod tests {
         std::fs::write(meta_path, test_meta).unwrap();
         std::fs::write(mdat_path, [0, 0, 0, 0]).unwrap();
 
-        let mut video = new_video_reader(path, 0, &None).await.unwrap();
+        let mut video = new_video_reader(path, 0, &None, None).await.unwrap();
 
         // Read 1000 bytes.
         let mut buf = vec![0; 100];
@@ -539,6 +640,132 @@ mod tests {
         //require.Greater(t, n, int64(1000))*/
     }
 
+    #[tokio::test]
+    async fn test_sample_stream() {
+        let temp_dir = tempdir().unwrap();
+
+        let path = temp_dir.path().join("x");
+        let mut test_meta = MetaHeader {
+            start_time: UnixH264::new(0),
+            width: 1920,
+            height: 1080,
+            extra_data: vec![0, 1],
+            audio: None,
+        }
+        .marshal()
+        .unwrap();
+        for (pts, offset, size) in [(0, 0, 2), (1, 2, 3)] {
+            let sample = Sample {
+                random_access_present: pts == 0,
+                pts: UnixH264::new(pts),
+                dts_offset: DtsOffset::new(0),
+                duration: DurationH264::new(1),
+                data_size: size,
+                data_offset: offset,
+            };
+            test_meta.extend(sample.encode().unwrap());
+        }
+        std::fs::write(temp_dir.path().join("x.meta"), test_meta).unwrap();
+        std::fs::write(temp_dir.path().join("x.mdat"), [1, 2, 3, 4, 5]).unwrap();
+
+        let samples: Vec<VideoSample> = new_sample_stream(path.clone(), None)
+            .await
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(2, samples.len());
+        assert!(samples[0].random_access_present);
+        assert!(!samples[1].random_access_present);
+
+        // The sample data matches the mdat part of the video.
+        let mut video = new_video_reader(path, 0, &None, None).await.unwrap();
+        let mut buf = Vec::new();
+        video.read_to_end(&mut buf).await.unwrap();
+        let data: Vec<u8> = samples.iter().flat_map(|v| v.avcc.to_vec()).collect();
+        assert_eq!(buf[buf.len() - 5..], data);
+
+        assert!(matches!(
+            new_sample_stream(temp_dir.path().join("y"), None).await,
+            Err(ReadSampleError::Metadata(_))
+        ));
+
+        // Read errors are returned as items.
+        let meta = Cursor::new(std::fs::read(temp_dir.path().join("x.meta")).unwrap());
+        let meta_size = u64::try_from(meta.get_ref().len()).unwrap();
+        let (_, samples) = read_meta(meta, meta_size).await.unwrap();
+        let mut stream = Box::pin(sample_stream(Cursor::new(vec![1, 2, 3]), samples));
+        assert!(stream.next().await.unwrap().is_ok());
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(ReadSampleError::Read(_)))
+        ));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_round_trip() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("x");
+        let key = RecordingKey::generate();
+
+        let samples: Vec<VideoSample> = (0..3)
+            .map(|i| VideoSample {
+                pts: UnixH264::new(i),
+                dts_offset: DtsOffset::new(0),
+                avcc: Arc::new(PaddedBytes::new(vec![u8::try_from(i).unwrap(); 10])),
+                random_access_present: i == 0,
+                duration: DurationH264::new(1),
+            })
+            .collect();
+        {
+            let header = MetaHeader {
+                start_time: UnixH264::new(0),
+                width: 1920,
+                height: 1080,
+                extra_data: vec![0, 1],
+                audio: None,
+            };
+            let mut meta = tokio::fs::File::create(path.with_extension("meta"))
+                .await
+                .unwrap();
+            let mdat = tokio::fs::File::create(path.with_extension("mdat"))
+                .await
+                .unwrap();
+            let mut mdat = EncryptWriter::new(mdat, &key);
+            let mut w = VideoWriter::new(&mut meta, &mut mdat, header)
+                .await
+                .unwrap();
+            for sample in &samples {
+                w.write_sample(sample).await.unwrap();
+            }
+            drop(w);
+            meta.flush().await.unwrap();
+            mdat.flush().await.unwrap();
+        }
+        let data: Vec<u8> = samples.iter().flat_map(|v| v.avcc.to_vec()).collect();
+        let mdat = std::fs::read(path.with_extension("mdat")).unwrap();
+        assert!(mdat.len() > data.len());
+
+        let got: Vec<VideoSample> = new_sample_stream(path.clone(), Some(key.clone()))
+            .await
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(samples.len(), got.len());
+        for (want, got) in samples.iter().zip(&got) {
+            assert_eq!(want.pts, got.pts);
+            assert_eq!(want.random_access_present, got.random_access_present);
+            assert_eq!(want.avcc.to_vec(), got.avcc.to_vec());
+        }
+
+        let mut video = new_video_reader(path, 0, &None, Some(&key)).await.unwrap();
+        let mut buf = Vec::new();
+        video.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf[buf.len() - data.len()..], data);
+    }
+
     #[tokio::test]
     async fn test_video_reader() {
         let mut r = VideoReader {
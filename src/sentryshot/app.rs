@@ -1,5 +1,6 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
+use crate::tls::{reload_on_sighup, serve_tls, LoadTlsError, ReloadableAcceptor};
 use axum::{
     middleware,
     response::Html,
@@ -8,8 +9,8 @@ use axum::{
 };
 use bytesize::ByteSize;
 use common::{
-    monitor::ArcMonitorManager, time::Duration, ArcAuth, DynEnvConfig, EnvConfig, ILogger,
-    LogEntry, LogLevel,
+    monitor::ArcMonitorManager, time::Duration, ArcAuth, ArcLogger, DynEnvConfig, EnvConfig,
+    ILogger, ListenAddr, LogEntry, LogLevel,
 };
 use env::{EnvConf, EnvConfigNewError};
 use hls::HlsServer;
@@ -25,13 +26,15 @@ use plugin::{
     Application, PluginManager, PreLoadPluginsError, PreLoadedPlugins,
 };
 use rand::{distributions::Alphanumeric, Rng};
-use recdb::{Disk, RecDb};
+use recdb::{Disk, LoadIndexError, RecDb};
 use recording::VideoCache;
 use rust_embed::RustEmbed;
 use std::{
     collections::HashMap,
     ffi::OsStr,
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    future::IntoFuture,
+    net::SocketAddr,
+    num::NonZeroU64,
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -44,7 +47,7 @@ use tokio::{
 };
 use tokio_util::sync::CancellationToken;
 use tower::ServiceBuilder;
-use vod::VodCache;
+use vod::{NewDiskCacheError, VodCache, VodConfig};
 use web::{minify, Templater};
 
 #[allow(clippy::wildcard_imports)]
@@ -78,11 +81,23 @@ pub enum RunError {
     #[error("create monitor groups: {0}")]
     CreateMonitorGroups(#[from] CreateMonitorGroupsError),
 
+    #[error("create vod disk cache: {0}")]
+    NewVodDiskCache(#[from] NewDiskCacheError),
+
+    #[error("load recording index: {0}")]
+    LoadRecordingIndex(#[from] LoadIndexError),
+
     #[error("determine time zone")]
     TimeZone,
 
     #[error("listen on sigterm: {0}")]
     SigTermListener(std::io::Error),
+
+    #[error("load tls certificate: {0}")]
+    LoadTls(#[from] LoadTlsError),
+
+    #[error("bind {0}: {1}")]
+    Bind(ListenAddr, std::io::Error),
 }
 
 pub async fn run(rt_handle: Handle, config_path: &PathBuf) -> Result<(), RunError> {
@@ -112,10 +127,12 @@ pub struct App {
     monitor_manager: ArcMonitorManager,
     monitor_groups: ArcMonitorGroups,
     recdb: Arc<RecDb>,
+    vod_cache: VodCache,
     router: Router,
 }
 
 impl App {
+    #[allow(clippy::too_many_lines)]
     pub async fn new(
         rt_handle: Handle,
         config_path: &PathBuf,
@@ -126,7 +143,11 @@ impl App {
         let pre_loaded_plugins = pre_load_plugins(env.plugin_dir(), env.plugins())?;
         let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel::<()>(1);
 
-        let logger = Arc::new(Logger::new(pre_loaded_plugins.log_sources().to_owned()));
+        let logger = Arc::new(
+            Logger::new(pre_loaded_plugins.log_sources().to_owned())
+                .with_format(env.log_format())
+                .with_levels(env.log_levels()),
+        );
 
         let log_dir = env.storage_dir().join("logs");
         let log_db = Arc::new(LogDb::new(
@@ -134,6 +155,9 @@ impl App {
             log_dir,
             env.max_disk_usage(),
             ByteSize::mb(100),
+            env.log_max_age(),
+            env.log_dedupe_window(),
+            env.log_sync_interval(),
         )?);
 
         {
@@ -150,16 +174,42 @@ impl App {
             tokio::spawn(async move {
                 log_db2.prune_loop(token2, logger2).await;
             });
+
+            let log_db2 = log_db.clone();
+            let token2 = token.clone();
+            let logger2 = logger.clone();
+            tokio::spawn(async move {
+                log_db2.sync_loop(token2, logger2).await;
+            });
         }
 
         let new_auth = pre_loaded_plugins.new_auth_fn();
-        let auth = new_auth(rt_handle.clone(), env.config_dir(), logger.clone())?;
-
-        let rec_db = Arc::new(RecDb::new(
+        let auth = new_auth(
+            rt_handle.clone(),
+            env.config_dir(),
+            env.raw(),
             logger.clone(),
-            env.recordings_dir().to_path_buf(),
-            Disk::new(env.storage_dir().to_path_buf(), env.max_disk_usage()),
-        ));
+        )?;
+
+        let disk = Disk::new(env.storage_dir().to_path_buf(), env.max_disk_usage());
+        let rec_db = if env.recording_index() {
+            RecDb::with_index(
+                logger.clone(),
+                env.recordings_dir().to_path_buf(),
+                disk,
+                env.archive().cloned(),
+                env.storage_dir().join("recording_index"),
+            )
+            .await?
+        } else {
+            RecDb::with_archive(
+                logger.clone(),
+                env.recordings_dir().to_path_buf(),
+                disk,
+                env.archive().cloned(),
+            )
+        };
+        let rec_db = Arc::new(rec_db.with_master_key(env.recording_key().cloned()));
 
         let hls_server = Arc::new(HlsServer::new(token.clone(), logger.clone()));
 
@@ -173,6 +223,20 @@ impl App {
 
         let monitor_groups = Arc::new(MonitorGroups::new(env.storage_dir()).await?);
 
+        let vod_cache = VodCache::with_config(
+            env.vod_cache_entries()
+                .unwrap_or(VodCache::DEFAULT_MAX_ENTRIES),
+            env.vod_cache_ttl(),
+        );
+        let vod_cache = match env.vod_disk_cache_size() {
+            Some(size) => {
+                vod_cache
+                    .with_disk(env.storage_dir().join("vodcache"), size, &vod_config(&env))
+                    .await?
+            }
+            None => vod_cache,
+        };
+
         let router = Router::new();
 
         Ok((
@@ -189,6 +253,7 @@ impl App {
                 monitor_manager,
                 monitor_groups,
                 recdb: rec_db,
+                vod_cache,
                 router,
             },
             pre_loaded_plugins,
@@ -210,6 +275,17 @@ impl App {
 
         let tpls = Tpls::load();
 
+        let vod_state = VodHandlerState {
+            logger: self.logger.clone(),
+            recdb: self.recdb.clone(),
+            cache: self.vod_cache.clone(),
+            config: vod_config(&self.env),
+            rate_limiter: self
+                .env
+                .vod_rate_limit()
+                .map(|v| Arc::new(RateLimiter::new(v))),
+        };
+
         let mut templates = {
             fn to_string(input: &[u8]) -> String {
                 String::from_utf8(input.to_vec()).expect("file to only contain valid characters")
@@ -301,10 +377,44 @@ impl App {
             .route(
                 "/vod/vod.mp4",
                 get(vod_handler)
-                    .with_state(VodHandlerState {
-                        logger: self.logger.clone(),
-                        recdb: self.recdb.clone(),
-                        cache: VodCache::new(),
+                    .with_state(vod_state.clone())
+                    .layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/vod/hls.m3u8",
+                get(vod_hls_playlist_handler)
+                    .with_state(vod_state.clone())
+                    .layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/vod/hls/init.mp4",
+                get(vod_hls_init_handler)
+                    .with_state(vod_state.clone())
+                    .layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/vod/hls/segment.m4s",
+                get(vod_hls_segment_handler)
+                    .with_state(vod_state.clone())
+                    .layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/vod/multi.mp4",
+                get(multi_vod_handler)
+                    .with_state(vod_state.clone())
+                    .layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/vod/transcoded.mp4",
+                get(transcoded_vod_handler)
+                    .with_state(TranscodedVodHandlerState {
+                        vod: vod_state,
+                        transcoder: plugin_manager.transcoder(),
                     })
                     .layer(middleware::from_fn_with_state(self.auth.clone(), user))
                     .with_state(self.auth.clone()),
@@ -365,6 +475,28 @@ impl App {
                     .route_layer(middleware::from_fn_with_state(self.auth.clone(), admin))
                     .with_state(self.auth.clone()),
             )
+            // Detection WebSocket feed.
+            .route(
+                "/api/detection/feed",
+                get(detection_feed_handler)
+                    .with_state(DetectionFeedHandlerState {
+                        auth: self.auth.clone(),
+                        monitor_manager: self.monitor_manager.clone(),
+                    })
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            // Log Server-Sent Events stream.
+            .route(
+                "/api/log/stream",
+                get(log_stream_handler)
+                    .with_state(LogStreamHandlerState {
+                        log_db: self.log_db.clone(),
+                        token: self.token.clone(),
+                    })
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), admin))
+                    .with_state(self.auth.clone()),
+            )
             // Log query.
             .route(
                 "/api/log/query",
@@ -373,6 +505,14 @@ impl App {
                     .route_layer(middleware::from_fn_with_state(self.auth.clone(), admin))
                     .with_state(self.auth.clone()),
             )
+            // Log export.
+            .route(
+                "/api/log/export",
+                get(log_export_handler)
+                    .with_state(self.log_db.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), admin))
+                    .with_state(self.auth.clone()),
+            )
             // Monitor.
             .route(
                 "/api/monitor",
@@ -406,6 +546,55 @@ impl App {
                     .route_layer(middleware::from_fn_with_state(self.auth.clone(), admin))
                     .with_state(self.auth.clone()),
             )
+            // Monitors status.
+            .route(
+                "/api/monitors/status",
+                get(monitors_status_handler)
+                    .with_state(self.monitor_manager.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), admin))
+                    .with_state(self.auth.clone()),
+            )
+            // Monitor snapshot.
+            .route(
+                "/api/monitor/:id/snapshot",
+                get(monitor_snapshot_handler)
+                    .with_state(self.monitor_manager.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            // Health check, doesn't require authentication.
+            .route(
+                "/healthz",
+                get(healthz_handler).with_state(HealthHandlerState {
+                    monitor_manager: self.monitor_manager.clone(),
+                    log_db: self.log_db.clone(),
+                    rec_db: self.recdb.clone(),
+                }),
+            )
+            // Health check with the failing subsystems.
+            .route(
+                "/api/health",
+                get(health_handler)
+                    .with_state(HealthHandlerState {
+                        monitor_manager: self.monitor_manager.clone(),
+                        log_db: self.log_db.clone(),
+                        rec_db: self.recdb.clone(),
+                    })
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), admin))
+                    .with_state(self.auth.clone()),
+            )
+            // Prometheus metrics.
+            .route(
+                "/api/metrics",
+                get(metrics_handler)
+                    .with_state(MetricsHandlerState {
+                        monitor_manager: self.monitor_manager.clone(),
+                        log_db: self.log_db.clone(),
+                        rec_db: self.recdb.clone(),
+                    })
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), admin))
+                    .with_state(self.auth.clone()),
+            )
             // Monitor groups.
             .route(
                 "/api/monitor-groups",
@@ -423,6 +612,16 @@ impl App {
                     .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
                     .with_state(self.auth.clone()),
             )
+            // Recording lock.
+            .route(
+                "/api/recording/lock/*id",
+                get(recording_lock_get_handler)
+                    .put(recording_lock_put_handler)
+                    .delete(recording_lock_delete_handler)
+                    .with_state(self.recdb.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
             // Recording thumbnail.
             .route(
                 "/api/recording/thumbnail/*id",
@@ -444,7 +643,14 @@ impl App {
                     .with_state(self.auth.clone()),
             );
 
-        self.router = plugin_manager.router_hooks(router);
+        let mut router = plugin_manager.router_hooks(router);
+        if let Some(config) = self.env.cors() {
+            router = router.layer(middleware::from_fn_with_state(
+                Arc::new(config.clone()),
+                cors,
+            ));
+        }
+        self.router = router;
 
         Ok(())
     }
@@ -459,11 +665,19 @@ impl App {
                 .await;
         });
 
+        let rec_db = self.recdb.clone();
+        let token2 = self.token.clone();
+        tokio::spawn(async move {
+            rec_db
+                .archive_loop(token2, Duration::from_minutes(10).as_std().expect(""))
+                .await;
+        });
+
         self.logger.log(LogEntry {
             level: LogLevel::Info,
             source: "app".try_into().expect("valid"),
             monitor_id: None,
-            message: format!("Serving app on port {}", self.env.port())
+            message: format!("Serving app on {}", self.env.listen())
                 .try_into()
                 .expect("not empty"),
         });
@@ -480,14 +694,40 @@ impl App {
             drop(shutdown_complete_tx);
         });
 
-        let (server_exited_tx, server_exited_rx) = oneshot::channel();
-        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), self.env.port());
+        let tls = match self.env.tls() {
+            Some(config) => {
+                let acceptor = Arc::new(ReloadableAcceptor::new(config.clone())?);
+                let token = self.token.clone();
+                let acceptor2 = acceptor.clone();
+                let logger: ArcLogger = self.logger.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = reload_on_sighup(token, acceptor2, logger.clone()).await {
+                        logger.log(LogEntry::new(
+                            LogLevel::Error,
+                            "app",
+                            None,
+                            format!("listen on sighup: {e}"),
+                        ));
+                    }
+                });
+                Some(acceptor)
+            }
+            None => None,
+        };
+
+        let listen = self.env.listen();
+        let listener = bind(listen)
+            .await
+            .map_err(|e| RunError::Bind(listen.clone(), e))?;
 
+        let (server_exited_tx, server_exited_rx) = oneshot::channel();
         tokio::spawn(start_server(
             self.token.child_token(),
             self.shutdown_complete_tx.clone(),
             server_exited_tx,
-            addr,
+            self.logger.clone(),
+            listener,
+            tls,
             self.router,
         ));
 
@@ -540,32 +780,63 @@ impl Application for App {
     }
 }
 
-#[derive(Debug, Error)]
-enum ServerError {
-    #[error("bind: {0}")]
-    Bind(std::io::Error),
+// VOD limits from the environment config, missing values use the defaults.
+fn vod_config(env: &EnvConf) -> VodConfig {
+    let mut config = VodConfig::default();
+    if let Some(max_duration) = env.vod_max_duration() {
+        config.max_duration = max_duration;
+    }
+    if let Some(max_recordings) = env.vod_max_recordings() {
+        config.max_recordings = max_recordings;
+    }
+    config.max_samples = env.vod_max_samples();
+    config.max_bytes = env.vod_max_size().and_then(|v| NonZeroU64::new(v.as_u64()));
+    config
+}
 
-    #[error("{0}")]
-    Server(std::io::Error),
+// Host names are resolved and the first address that can be bound is used.
+async fn bind(addr: &ListenAddr) -> Result<TcpListener, std::io::Error> {
+    match addr {
+        ListenAddr::Ip(addr) => TcpListener::bind(addr).await,
+        ListenAddr::Host(host, port) => TcpListener::bind((host.as_str(), *port)).await,
+    }
 }
 
+// In-flight responses, like VOD downloads, are given
+// this long to finish after shutdown has started.
+const DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+// New connections are refused once the token is cancelled
+// and the server exits when the open connections are done.
 async fn start_server(
     token: CancellationToken,
     _shutdown_complete: mpsc::Sender<()>,
-    on_exit: oneshot::Sender<Result<(), ServerError>>,
-    addr: SocketAddr,
+    on_exit: oneshot::Sender<Result<(), std::io::Error>>,
+    logger: ArcLogger,
+    listener: TcpListener,
+    tls: Option<Arc<ReloadableAcceptor>>,
     router: Router,
 ) {
-    let listener = match TcpListener::bind(addr).await {
-        Ok(v) => v,
-        Err(e) => {
-            let _ = on_exit.send(Err(ServerError::Bind(e)));
-            return;
-        }
+    if let Some(acceptor) = tls {
+        serve_tls(token, logger, listener, acceptor, router, DRAIN_TIMEOUT).await;
+        let _ = on_exit.send(Ok(()));
+        return;
+    }
+    let token2 = token.clone();
+    let graceful = axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move { token2.cancelled().await });
+    let drain_deadline = async {
+        token.cancelled().await;
+        tokio::time::sleep(DRAIN_TIMEOUT).await;
     };
-    let graceful = axum::serve(listener, router)
-        .with_graceful_shutdown(async move { token.cancelled().await });
-    let _ = on_exit.send(graceful.await.map_err(ServerError::Server));
+    let result = tokio::select! {
+        res = graceful.into_future() => res,
+        () = drain_deadline => Ok(()),
+    };
+    let _ = on_exit.send(result);
 }
 
 // TimeZone returns system time zone location.
@@ -628,3 +899,93 @@ fn time_zone() -> Option<String> {
     }
     zone.map(|v| v.to_string_lossy().to_string().trim().to_owned())
 }
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use common::DummyLogger;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+    use tokio_util::io::ReaderStream;
+
+    #[tokio::test]
+    async fn test_start_server_drain() {
+        // The test controls when the response body is complete.
+        let (mut body_writer, body_reader) = tokio::io::duplex(64);
+        let body_reader = Arc::new(std::sync::Mutex::new(Some(body_reader)));
+        let router = Router::new().route(
+            "/vod",
+            get(move || {
+                let body_reader = body_reader.lock().unwrap().take().unwrap();
+                async move { Body::from_stream(ReaderStream::new(body_reader)) }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let token = CancellationToken::new();
+        let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel::<()>(1);
+        let (on_exit_tx, on_exit_rx) = oneshot::channel();
+        tokio::spawn(start_server(
+            token.clone(),
+            shutdown_complete_tx,
+            on_exit_tx,
+            DummyLogger::new(),
+            listener,
+            None,
+            router,
+        ));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /vod HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        body_writer.write_all(b"abc").await.unwrap();
+
+        // Wait until the response has started.
+        let mut response = Vec::new();
+        while !String::from_utf8_lossy(&response).contains("abc") {
+            let mut buf = [0; 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_ne!(0, n);
+            response.extend_from_slice(&buf[..n]);
+        }
+
+        token.cancel();
+        tokio::task::yield_now().await;
+        assert!(matches!(
+            shutdown_complete_rx.try_recv(),
+            Err(mpsc::error::TryRecvError::Empty)
+        ));
+
+        // Finish the slow read.
+        body_writer.write_all(b"def").await.unwrap();
+        drop(body_writer);
+        stream.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("def"), "{response}");
+        assert!(response.ends_with("0\r\n\r\n"), "{response}");
+
+        on_exit_rx.await.unwrap().unwrap();
+        assert_eq!(None, shutdown_complete_rx.recv().await);
+    }
+
+    #[tokio::test]
+    async fn test_bind() {
+        let listener = bind(&ListenAddr::Host("localhost".to_owned(), 0))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert!(addr.ip().is_loopback());
+
+        // Address in use.
+        let err = bind(&ListenAddr::Ip(addr)).await.unwrap_err();
+        assert_eq!(std::io::ErrorKind::AddrInUse, err.kind());
+    }
+}
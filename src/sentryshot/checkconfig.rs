@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use common::EnvConfig;
+use env::EnvConf;
+use monitor::{check_recording_key, read_monitor_configs};
+use plugin::pre_load_plugins;
+use std::path::{Path, PathBuf};
+
+// Loads and validates the config the same way `run` does, but
+// doesn't start anything. Returns the problems that were found.
+#[must_use]
+pub fn check_config(config_path: &PathBuf) -> Vec<String> {
+    if !config_path.exists() {
+        return vec![format!(
+            "config file not found: {}",
+            config_path.to_string_lossy()
+        )];
+    }
+    let env = match EnvConf::new(config_path) {
+        Ok(v) => v,
+        Err(e) => return vec![format!("env config: {e}")],
+    };
+
+    let mut problems = Vec::new();
+
+    let mut dirs = vec![env.storage_dir(), env.recordings_dir()];
+    if let Some(archive) = env.archive() {
+        dirs.push(archive.dir.as_path());
+    }
+    for dir in dirs {
+        if let Err(e) = check_writable(dir) {
+            problems.push(format!(
+                "directory is not writable: {} {e}",
+                dir.to_string_lossy()
+            ));
+        }
+    }
+
+    let monitors_dir = env.config_dir().join("monitors");
+    if monitors_dir.exists() {
+        match read_monitor_configs(&monitors_dir) {
+            Ok(configs) => {
+                if let Err(e) = check_recording_key(&configs, env.recording_key().is_some()) {
+                    problems.push(format!("monitor configs: {e}"));
+                }
+            }
+            Err(e) => problems.push(format!("monitor configs: {e}")),
+        }
+    }
+
+    match pre_load_plugins(env.plugin_dir(), env.plugins()) {
+        Ok(plugins) => problems.extend(plugins.check_config(env.config_dir())),
+        Err(e) => problems.push(format!("load plugins: {e}")),
+    }
+
+    problems
+}
+
+fn check_writable(dir: &Path) -> Result<(), std::io::Error> {
+    let path = dir.join(".checkconfig");
+    std::fs::write(&path, b"")?;
+    std::fs::remove_file(path)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::{tempdir, TempDir};
+
+    fn write_config(temp_dir: &TempDir) -> PathBuf {
+        let dir = temp_dir.path();
+        std::fs::create_dir(dir.join("configs")).unwrap();
+        std::fs::create_dir(dir.join("plugins")).unwrap();
+        let config = format!(
+            "
+            port = 2020
+            storage_dir = \"{0}/storage\"
+            config_dir = \"{0}/configs\"
+            plugin_dir = \"{0}/plugins\"
+            max_disk_usage = 1
+            ",
+            dir.to_str().unwrap()
+        );
+        let config_path = dir.join("configs").join("sentryshot.toml");
+        std::fs::write(&config_path, config).unwrap();
+        config_path
+    }
+
+    #[test]
+    fn test_check_config_ok() {
+        let temp_dir = tempdir().unwrap();
+        let config_path = write_config(&temp_dir);
+        assert_eq!(Vec::<String>::new(), check_config(&config_path));
+    }
+
+    #[test]
+    fn test_check_config_not_found() {
+        let temp_dir = tempdir().unwrap();
+        let config_path = temp_dir.path().join("x.toml");
+        assert_eq!(1, check_config(&config_path).len());
+        assert!(!config_path.exists());
+    }
+
+    #[test]
+    fn test_check_config_invalid_monitor() {
+        let temp_dir = tempdir().unwrap();
+        let config_path = write_config(&temp_dir);
+        let monitors_dir = temp_dir.path().join("configs").join("monitors");
+        std::fs::create_dir(&monitors_dir).unwrap();
+        std::fs::write(monitors_dir.join("x.json"), "{").unwrap();
+
+        let problems = check_config(&config_path);
+        assert_eq!(1, problems.len());
+        assert!(problems[0].starts_with("monitor configs: deserialize config 'x.json'"));
+    }
+
+    #[test]
+    fn test_check_config_encrypt_without_key() {
+        let temp_dir = tempdir().unwrap();
+        let config_path = write_config(&temp_dir);
+        let monitors_dir = temp_dir.path().join("configs").join("monitors");
+        std::fs::create_dir(&monitors_dir).unwrap();
+        let config = serde_json::json!({
+            "id": "x",
+            "name": "x",
+            "enable": false,
+            "alwaysRecord": false,
+            "videoLength": 0,
+            "source": "rtsp",
+            "sourcertsp": {
+                "protocol": "tcp",
+                "mainStream": "rtsp://x",
+            },
+            "encryptRecordings": true,
+        });
+        std::fs::write(monitors_dir.join("x.json"), config.to_string()).unwrap();
+
+        assert_eq!(
+            vec!["monitor configs: monitor 'x' encrypts recordings but recording_key is not set"],
+            check_config(&config_path)
+        );
+    }
+}
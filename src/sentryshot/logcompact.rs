@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use bytesize::ByteSize;
+use common::EnvConfig;
+use env::{EnvConf, EnvConfigNewError};
+use log::log_db::{CompactChunkError, LogDb, NewLogDbError};
+use std::path::PathBuf;
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Error)]
+pub enum LogCompactError {
+    #[error("create env config: {0}")]
+    NewEnvConfig(#[from] EnvConfigNewError),
+
+    #[error("create log db: {0}")]
+    NewLogDb(#[from] NewLogDbError),
+
+    #[error("list chunks: {0}")]
+    ListChunks(std::io::Error),
+
+    #[error("compact chunk {0}: {1}")]
+    CompactChunk(String, CompactChunkError),
+}
+
+// Compacts every chunk in the log database. The program must not
+// be running since the database doesn't support multiple writers.
+pub async fn log_compact(config_path: &PathBuf) -> Result<(), LogCompactError> {
+    use LogCompactError::*;
+    let env = EnvConf::new(config_path)?;
+
+    let (shutdown_complete_tx, _) = mpsc::channel::<()>(1);
+    let log_db = LogDb::new(
+        shutdown_complete_tx,
+        env.storage_dir().join("logs"),
+        env.max_disk_usage(),
+        ByteSize::mb(100),
+        None,
+        None,
+        None,
+    )?;
+
+    let mut size_before = 0;
+    let mut size_after = 0;
+    for chunk_id in log_db.list_chunks().await.map_err(ListChunks)? {
+        let stats = log_db
+            .compact_chunk(&chunk_id)
+            .await
+            .map_err(|e| CompactChunk(chunk_id.clone(), e))?;
+        println!(
+            "chunk {chunk_id}: {} entries, {} removed, {} -> {}",
+            stats.entries_kept, stats.entries_removed, stats.size_before, stats.size_after
+        );
+        size_before += stats.size_before.as_u64();
+        size_after += stats.size_after.as_u64();
+    }
+    println!(
+        "reclaimed {}",
+        ByteSize(size_before.saturating_sub(size_after))
+    );
+    Ok(())
+}
@@ -0,0 +1,221 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use bytesize::ByteSize;
+use common::{EnvConfig, LogLevel, LogSource, MonitorId};
+use env::{EnvConf, EnvConfigNewError};
+use log::{
+    log_db::{LogDb, LogDbHandle, LogQuery, NewLogDbError, QueryLogsError},
+    UnixMicro,
+};
+use std::{
+    io::{BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+/// Filter applied to the exported logs.
+#[derive(Debug, Default)]
+pub struct LogExportFilter {
+    pub levels: Vec<LogLevel>,
+    pub sources: Vec<LogSource>,
+    pub monitors: Vec<MonitorId>,
+
+    /// Only export entries at or after this time.
+    pub since: Option<UnixMicro>,
+
+    /// Only export entries before this time.
+    pub until: Option<UnixMicro>,
+}
+
+#[derive(Debug, Error)]
+pub enum LogExportError {
+    #[error("create env config: {0}")]
+    NewEnvConfig(#[from] EnvConfigNewError),
+
+    #[error("create log db: {0}")]
+    NewLogDb(#[from] NewLogDbError),
+
+    #[error("{0}")]
+    Export(#[from] ExportLogsError),
+}
+
+// Writes all log entries matching the filter to stdout as NDJSON.
+pub async fn log_export(
+    config_path: &PathBuf,
+    filter: LogExportFilter,
+) -> Result<(), LogExportError> {
+    let env = EnvConf::new(config_path)?;
+
+    let (shutdown_complete_tx, _) = mpsc::channel::<()>(1);
+    let log_db = LogDb::new(
+        shutdown_complete_tx,
+        env.storage_dir().join("logs"),
+        env.max_disk_usage(),
+        ByteSize::mb(100),
+        None,
+        None,
+        None,
+    )?;
+
+    let mut stdout = BufWriter::new(std::io::stdout().lock());
+    export_logs(&log_db, filter, &mut stdout).await?;
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum ExportLogsError {
+    #[error("query: {0}")]
+    Query(#[from] QueryLogsError),
+
+    #[error("serialize: {0}")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("write: {0}")]
+    Write(#[from] std::io::Error),
+}
+
+// Queries the database one page at a time, newest entry first,
+// and writes each entry as a JSON line. Returns the number of written entries.
+pub async fn export_logs<W: Write>(
+    log_db: &LogDbHandle,
+    filter: LogExportFilter,
+    out: &mut W,
+) -> Result<usize, ExportLogsError> {
+    let mut pages = log_db.query_pages(LogQuery {
+        levels: filter.levels,
+        sources: filter.sources,
+        time: filter.until,
+        since: filter.since,
+        monitors: filter.monitors,
+        limit: None,
+    });
+    let mut n_written = 0;
+    while let Some(entries) = pages.next().await? {
+        for entry in entries {
+            serde_json::to_writer(&mut *out, &entry)?;
+            out.write_all(b"\n")?;
+            n_written += 1;
+        }
+    }
+    out.flush()?;
+    Ok(n_written)
+}
+
+// Parses a comma separated list of values.
+pub fn parse_csv<T: TryFrom<String>>(input: &str) -> Result<Vec<T>, T::Error> {
+    input
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| T::try_from(s.to_owned()))
+        .collect()
+}
+
+// Same as `parse_csv` but for types that implement `FromStr`.
+pub fn parse_csv2<T: FromStr>(input: &str) -> Result<Vec<T>, T::Err> {
+    input
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(T::from_str)
+        .collect()
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::LogEntryWithTime;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    async fn export_to_string(log_db: &LogDbHandle, filter: LogExportFilter) -> String {
+        let mut buf = Vec::new();
+        export_logs(log_db, filter, &mut buf).await.unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_logs() {
+        let temp_dir = tempdir().unwrap();
+        let db = LogDb::new_testing(temp_dir.path().to_owned());
+
+        db.save_log_testing(LogEntryWithTime::new_testing(1, LogLevel::Info))
+            .await;
+        db.save_log_testing(LogEntryWithTime::new_testing(2, LogLevel::Error))
+            .await;
+        db.save_log_testing(LogEntryWithTime::new_testing(3, LogLevel::Info))
+            .await;
+
+        let want = "\
+{\"level\":\"info\",\"source\":\"app\",\"message\":\"3\",\"time\":3}
+{\"level\":\"error\",\"source\":\"app\",\"message\":\"2\",\"time\":2}
+{\"level\":\"info\",\"source\":\"app\",\"message\":\"1\",\"time\":1}
+";
+        assert_eq!(
+            want,
+            export_to_string(&db, LogExportFilter::default()).await
+        );
+
+        let filter = LogExportFilter {
+            levels: vec![LogLevel::Info],
+            ..Default::default()
+        };
+        let want = "\
+{\"level\":\"info\",\"source\":\"app\",\"message\":\"3\",\"time\":3}
+{\"level\":\"info\",\"source\":\"app\",\"message\":\"1\",\"time\":1}
+";
+        assert_eq!(want, export_to_string(&db, filter).await);
+
+        let filter = LogExportFilter {
+            since: Some(UnixMicro::new(2)),
+            until: Some(UnixMicro::new(3)),
+            ..Default::default()
+        };
+        let want = "{\"level\":\"error\",\"source\":\"app\",\"message\":\"2\",\"time\":2}\n";
+        assert_eq!(want, export_to_string(&db, filter).await);
+    }
+
+    #[tokio::test]
+    async fn test_export_logs_paginate() {
+        const PAGE_SIZE: usize = 1000;
+        let temp_dir = tempdir().unwrap();
+        let db = LogDb::new_testing(temp_dir.path().to_owned());
+
+        let n_entries = u64::try_from(PAGE_SIZE * 2 + 1).unwrap();
+        for i in 1..=n_entries {
+            db.save_log_testing(LogEntryWithTime::new_testing(i, LogLevel::Info))
+                .await;
+        }
+
+        let mut buf = Vec::new();
+        let n_written = export_logs(&db, LogExportFilter::default(), &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(PAGE_SIZE * 2 + 1, n_written);
+
+        let times: Vec<u64> = String::from_utf8(buf)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let v: serde_json::Value = serde_json::from_str(line).unwrap();
+                v["time"].as_u64().unwrap()
+            })
+            .collect();
+        let want: Vec<u64> = (1..=n_entries).rev().collect();
+        assert_eq!(want, times);
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let want: Vec<MonitorId> = vec!["a".to_owned().try_into().unwrap()];
+        assert_eq!(want, parse_csv::<MonitorId>("a,").unwrap());
+        assert!(parse_csv::<MonitorId>("a,@").is_err());
+
+        assert_eq!(
+            vec![LogLevel::Error, LogLevel::Info],
+            parse_csv2::<LogLevel>("error,info").unwrap()
+        );
+        assert!(parse_csv2::<LogLevel>("error,x").is_err());
+    }
+}
@@ -0,0 +1,191 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use bytesize::ByteSize;
+use common::EnvConfig;
+use env::{EnvConf, EnvConfigNewError};
+use log::{
+    log_db::{LogDb, LogDbHandle, NewLogDbError, SaveLogError},
+    LogEntryWithTime,
+};
+use std::{collections::BTreeMap, io::BufRead, path::PathBuf};
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Error)]
+pub enum LogImportError {
+    #[error("create env config: {0}")]
+    NewEnvConfig(#[from] EnvConfigNewError),
+
+    #[error("create log db: {0}")]
+    NewLogDb(#[from] NewLogDbError),
+
+    #[error("{0}")]
+    Import(#[from] ImportLogsError),
+}
+
+// Reads NDJSON log entries from stdin and saves them to the log database.
+pub async fn log_import(config_path: &PathBuf) -> Result<(), LogImportError> {
+    let env = EnvConf::new(config_path)?;
+
+    let (shutdown_complete_tx, _) = mpsc::channel::<()>(1);
+    let log_db = LogDb::new(
+        shutdown_complete_tx,
+        env.storage_dir().join("logs"),
+        env.max_disk_usage(),
+        ByteSize::mb(100),
+        None,
+        None,
+        None,
+    )?;
+
+    let n_written = import_logs(&log_db, std::io::stdin().lock()).await?;
+    for (chunk_id, n) in &n_written {
+        println!("chunk {chunk_id}: {n} entries");
+    }
+    println!("imported {} entries", n_written.values().sum::<usize>());
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum ImportLogsError {
+    #[error("read line {0}: {1}")]
+    Read(usize, std::io::Error),
+
+    #[error("deserialize line {0}: {1}")]
+    Deserialize(usize, serde_json::Error),
+
+    #[error("save log: {0}")]
+    SaveLog(#[from] SaveLogError),
+}
+
+// Parses all entries before saving them oldest first, the database only
+// accepts entries in chronological order. Entries with duplicate
+// timestamps are incremented by the database like any other entry.
+// Returns the number of written entries per chunk ID.
+pub async fn import_logs<R: BufRead>(
+    log_db: &LogDbHandle,
+    input: R,
+) -> Result<BTreeMap<String, usize>, ImportLogsError> {
+    use ImportLogsError::*;
+    let mut entries = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| Read(i + 1, e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: LogEntryWithTime =
+            serde_json::from_str(&line).map_err(|e| Deserialize(i + 1, e))?;
+        entries.push(entry);
+    }
+    entries.sort_by_key(|entry| entry.time);
+
+    let mut n_written = BTreeMap::new();
+    for entry in entries {
+        let chunk_id = log_db.save_log(entry).await?;
+        *n_written.entry(chunk_id).or_default() += 1;
+    }
+    Ok(n_written)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logexport::{export_logs, LogExportFilter};
+    use common::LogLevel;
+    use log::log_db::LogQuery;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    fn new_test_entry(time: u64, level: LogLevel, monitor_id: Option<&str>) -> LogEntryWithTime {
+        LogEntryWithTime {
+            monitor_id: monitor_id.map(|v| v.to_owned().try_into().unwrap()),
+            ..LogEntryWithTime::new_testing(time, level)
+        }
+    }
+
+    fn query_all() -> LogQuery {
+        LogQuery {
+            levels: Vec::new(),
+            sources: Vec::new(),
+            time: None,
+            since: None,
+            monitors: Vec::new(),
+            limit: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_logs_round_trip() {
+        // Entries are spread over two chunks.
+        const CHUNK2: u64 = 100_000_000_000;
+
+        let src_dir = tempdir().unwrap();
+        let src_db = LogDb::new_testing(src_dir.path().to_owned());
+        src_db
+            .save_log_testing(new_test_entry(1, LogLevel::Info, None))
+            .await;
+        src_db
+            .save_log_testing(new_test_entry(2, LogLevel::Error, Some("a")))
+            .await;
+        src_db
+            .save_log_testing(new_test_entry(CHUNK2, LogLevel::Debug, None))
+            .await;
+
+        let mut exported = Vec::new();
+        export_logs(&src_db, LogExportFilter::default(), &mut exported)
+            .await
+            .unwrap();
+
+        let dst_dir = tempdir().unwrap();
+        let dst_db = LogDb::new_testing(dst_dir.path().to_owned());
+        let n_written = import_logs(&dst_db, exported.as_slice()).await.unwrap();
+
+        let want = BTreeMap::from([("00000".to_owned(), 2), ("00001".to_owned(), 1)]);
+        assert_eq!(want, n_written);
+        assert_eq!(
+            src_db.query(query_all()).await.unwrap(),
+            dst_db.query(query_all()).await.unwrap(),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_logs_out_of_order() {
+        let temp_dir = tempdir().unwrap();
+        let db = LogDb::new_testing(temp_dir.path().to_owned());
+
+        let input = "\
+{\"level\":\"info\",\"source\":\"app\",\"message\":\"a\",\"time\":5}
+
+{\"level\":\"info\",\"source\":\"app\",\"message\":\"b\",\"time\":3}
+{\"level\":\"info\",\"source\":\"app\",\"message\":\"c\",\"time\":3}
+";
+        import_logs(&db, input.as_bytes()).await.unwrap();
+
+        let got: Vec<(String, u64)> = db
+            .query(query_all())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.message.to_string(), *e.time))
+            .collect();
+        let want = vec![
+            ("a".to_owned(), 5),
+            ("c".to_owned(), 4),
+            ("b".to_owned(), 3),
+        ];
+        assert_eq!(want, got);
+    }
+
+    #[tokio::test]
+    async fn test_import_logs_invalid() {
+        let temp_dir = tempdir().unwrap();
+        let db = LogDb::new_testing(temp_dir.path().to_owned());
+
+        let input = "{\"level\":\"info\",\"source\":\"app\",\"message\":\"a\",\"time\":1}\nx\n";
+        assert!(matches!(
+            import_logs(&db, input.as_bytes()).await,
+            Err(ImportLogsError::Deserialize(2, _))
+        ));
+    }
+}
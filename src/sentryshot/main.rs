@@ -1,13 +1,35 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
 mod app;
+mod checkconfig;
+mod logcompact;
+mod logexport;
+mod logimport;
+mod rec2h264;
 mod rec2mp4;
+mod tls;
+mod verifyrec;
 
 use app::run;
+pub use checkconfig::check_config;
+pub use logcompact::log_compact;
+pub use logexport::{log_export, parse_csv, parse_csv2, LogExportFilter};
+pub use logimport::log_import;
+pub use rec2h264::rec_to_h264;
 pub use rec2mp4::rec_to_mp4;
+pub use verifyrec::{verify_rec, VerifyRecError, VerifySummary};
 
-use std::{path::PathBuf, process::ExitCode};
+use common::{EnvConfig, LogLevel, MasterKey};
+use env::EnvConf;
+use log::UnixMicro;
+use recording::Timescale;
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
+#[allow(clippy::too_many_lines)]
 #[tokio::main]
 async fn main() -> ExitCode {
     #[cfg(tokio_unstable)]
@@ -47,16 +69,165 @@ async fn main() -> ExitCode {
                 return ExitCode::FAILURE;
             };
         }
+        "checkconfig" => {
+            if pargs.contains(["-h", "--help"]) {
+                print!("{HELP_CHECKCONFIG}");
+                return ExitCode::SUCCESS;
+            }
+            let config = pargs
+                .value_from_str("--config")
+                .unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG_PATH));
+            return check_config_cmd(&config);
+        }
         "rec2mp4" => {
             if pargs.contains(["-h", "--help"]) {
                 print!("{HELP_REC2MP4}");
                 return ExitCode::SUCCESS;
             }
+            let output_dir = match pargs.opt_value_from_str("--output-dir") {
+                Ok(v) => v,
+                Err(e) => {
+                    println!("invalid args: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let jobs = match pargs.opt_value_from_str("--jobs") {
+                Ok(v) => v.unwrap_or_else(default_jobs),
+                Err(e) => {
+                    println!("invalid args: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let timescale = match pargs.opt_value_from_str::<_, NonZeroU32>("--timescale") {
+                Ok(v) => v.map(Timescale::new).unwrap_or_default(),
+                Err(e) => {
+                    println!("invalid args: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let master_key = match read_master_key(&mut pargs) {
+                Ok(v) => v,
+                Err(e) => {
+                    println!("{e}");
+                    return ExitCode::FAILURE;
+                }
+            };
             let Ok(path) = pargs.free_from_str() else {
                 println!("missing path");
                 return ExitCode::FAILURE;
             };
-            if let Err(e) = rec_to_mp4(path).await {
+            match rec_to_mp4(path, output_dir, jobs, master_key, timescale).await {
+                Ok(summary) if summary.failed != 0 => return ExitCode::FAILURE,
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("error: {e}");
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        "rec2h264" => {
+            if pargs.contains(["-h", "--help"]) {
+                print!("{HELP_REC2H264}");
+                return ExitCode::SUCCESS;
+            }
+            let output = match pargs.opt_value_from_str::<_, PathBuf>("--output") {
+                Ok(v) => v,
+                Err(e) => {
+                    println!("invalid args: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let insert_parameter_sets = pargs.contains("--parameter-sets");
+            let master_key = match read_master_key(&mut pargs) {
+                Ok(v) => v,
+                Err(e) => {
+                    println!("{e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let Ok(path) = pargs.free_from_str::<PathBuf>() else {
+                println!("missing path");
+                return ExitCode::FAILURE;
+            };
+            let output = output.unwrap_or_else(|| path.with_extension("h264"));
+            if let Err(e) =
+                rec_to_h264(&path, &output, master_key.as_ref(), insert_parameter_sets).await
+            {
+                eprintln!("error: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+        "verifyrec" => {
+            if pargs.contains(["-h", "--help"]) {
+                print!("{HELP_VERIFYREC}");
+                return ExitCode::SUCCESS;
+            }
+            let master_key = match read_master_key(&mut pargs) {
+                Ok(v) => v,
+                Err(e) => {
+                    println!("{e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let Ok(path) = pargs.free_from_str::<PathBuf>() else {
+                println!("missing path");
+                return ExitCode::FAILURE;
+            };
+            return verify_rec_cmd(&path, master_key.as_ref()).await;
+        }
+        "mp4dump" => {
+            if pargs.contains(["-h", "--help"]) {
+                print!("{HELP_MP4DUMP}");
+                return ExitCode::SUCCESS;
+            }
+            let Ok(path) = pargs.free_from_str::<PathBuf>() else {
+                println!("missing path");
+                return ExitCode::FAILURE;
+            };
+            return mp4_dump_cmd(&path);
+        }
+        "logexport" => {
+            if pargs.contains(["-h", "--help"]) {
+                print!("{HELP_LOGEXPORT}");
+                return ExitCode::SUCCESS;
+            }
+            let config = pargs
+                .value_from_str("--config")
+                .unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG_PATH));
+            let filter = match parse_log_export_filter(&mut pargs) {
+                Ok(v) => v,
+                Err(e) => {
+                    println!("invalid args: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            if let Err(e) = log_export(&config, filter).await {
+                eprintln!("error: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+        "logimport" => {
+            if pargs.contains(["-h", "--help"]) {
+                print!("{HELP_LOGIMPORT}");
+                return ExitCode::SUCCESS;
+            }
+            let config = pargs
+                .value_from_str("--config")
+                .unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG_PATH));
+            if let Err(e) = log_import(&config).await {
+                eprintln!("error: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+        "logcompact" => {
+            if pargs.contains(["-h", "--help"]) {
+                print!("{HELP_LOGCOMPACT}");
+                return ExitCode::SUCCESS;
+            }
+            let config = pargs
+                .value_from_str("--config")
+                .unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG_PATH));
+            if let Err(e) = log_compact(&config).await {
                 eprintln!("error: {e}");
                 return ExitCode::FAILURE;
             }
@@ -69,15 +240,98 @@ async fn main() -> ExitCode {
     ExitCode::SUCCESS
 }
 
+// Number of CPUs.
+fn default_jobs() -> NonZeroUsize {
+    std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)
+}
+
+fn check_config_cmd(config_path: &PathBuf) -> ExitCode {
+    let problems = check_config(config_path);
+    if problems.is_empty() {
+        println!("[OK] {}", config_path.to_string_lossy());
+        return ExitCode::SUCCESS;
+    }
+    for problem in problems {
+        println!("[ERR] {problem}");
+    }
+    ExitCode::FAILURE
+}
+
+// Returns the recording key from the env config if `--config` is set,
+// it's only needed for encrypted recordings.
+fn read_master_key(pargs: &mut pico_args::Arguments) -> Result<Option<MasterKey>, String> {
+    let config: Option<PathBuf> = pargs
+        .opt_value_from_str("--config")
+        .map_err(|e| format!("invalid args: {e}"))?;
+    let Some(config) = config else {
+        return Ok(None);
+    };
+    let env = EnvConf::new(&config).map_err(|e| format!("env config: {e}"))?;
+    Ok(env.recording_key().cloned())
+}
+
+async fn verify_rec_cmd(path: &Path, master_key: Option<&MasterKey>) -> ExitCode {
+    match verify_rec(path, master_key).await {
+        Ok(summary) => {
+            println!("[OK] {}: {summary}", path.to_string_lossy());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("[ERR] {}: {e}", path.to_string_lossy());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn mp4_dump_cmd(path: &Path) -> ExitCode {
+    let mut file = match std::fs::File::open(path) {
+        Ok(v) => std::io::BufReader::new(v),
+        Err(e) => {
+            eprintln!("error: open {}: {e}", path.to_string_lossy());
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = mp4::dump(&mut file, &mut std::io::stdout().lock()) {
+        eprintln!("error: {e}");
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn parse_log_export_filter(
+    pargs: &mut pico_args::Arguments,
+) -> Result<LogExportFilter, pico_args::Error> {
+    Ok(LogExportFilter {
+        levels: pargs
+            .opt_value_from_fn("--level", parse_csv2::<LogLevel>)?
+            .unwrap_or_default(),
+        sources: pargs
+            .opt_value_from_fn("--source", parse_csv)?
+            .unwrap_or_default(),
+        monitors: pargs
+            .opt_value_from_fn("--monitor", parse_csv)?
+            .unwrap_or_default(),
+        since: pargs.opt_value_from_str("--since")?.map(UnixMicro::new),
+        until: pargs.opt_value_from_str("--until")?.map(UnixMicro::new),
+    })
+}
+
 const DEFAULT_CONFIG_PATH: &str = "./configs/sentryshot.toml";
 
 const HELP: &str = "\
 Usage: sentryshot [OPTIONS] <COMMAND>
 
 Commands:
-  run      Run the program
-  rec2mp4  Convert recordings into mp4 videos
-  help     Print this message or the help of the given subcommand(s)
+  run         Run the program
+  checkconfig Validate the config without running the program
+  rec2mp4     Convert recordings into mp4 videos
+  rec2h264    Export a recording as a raw H264 stream
+  verifyrec   Check a recording for corruption
+  mp4dump     Print the box tree of a mp4 file
+  logexport   Export logs as newline delimited JSON
+  logimport   Import logs from newline delimited JSON
+  logcompact  Reclaim space from the log database
+  help        Print this message or the help of the given subcommand(s)
 
 Options:
       --config <CONFIG>  [default: ./configs/sentryshot.toml]
@@ -95,14 +349,106 @@ Options:
   -h, --help             Print help
 ";
 
+const HELP_CHECKCONFIG: &str = "\
+Load and validate the config without starting any servers or monitors,
+prints the problems that were found
+
+Usage: sentryshot checkconfig [OPTIONS]
+
+Options:
+      --config <CONFIG>  [default: ./configs/sentryshot.toml]
+  -h, --help             Print help
+";
+
 const HELP_REC2MP4: &str = "\
 Convert recordings into mp4 videos
 
-Usage: sentryshot rec2mp4 <PATH>
+Usage: sentryshot rec2mp4 [OPTIONS] <PATH>
+
+Arguments:
+  <PATH>  Recording or directory of recordings
+
+Options:
+      --output-dir <DIR>  Write the mp4 files here instead of next to the recordings
+      --jobs <N>          Convert up to N recordings at the same time [default: number of CPUs]
+      --timescale <HZ>    Media timescale of the video track [default: 90000]
+      --config <CONFIG>   Read the recording_key for encrypted recordings from this config
+  -h, --help              Print help
+";
+
+const HELP_REC2H264: &str = "\
+Export a recording as a Annex-B H264 elementary stream, the NAL units
+are identical to the received stream
+
+Usage: sentryshot rec2h264 [OPTIONS] <PATH>
+
+Arguments:
+  <PATH>  Recording path, with or without extension
+
+Options:
+      --output <FILE>     [default: the recording path with the .h264 extension]
+      --parameter-sets    Insert the SPS and PPS before keyframes that don't carry them
+      --config <CONFIG>   Read the recording_key for encrypted recordings from this config
+  -h, --help              Print help
+";
+
+const HELP_VERIFYREC: &str = "\
+Check that a recording's meta and mdat files are intact
+
+Usage: sentryshot verifyrec [OPTIONS] <PATH>
+
+Arguments:
+  <PATH>  Recording path, with or without extension
+
+Options:
+      --config <CONFIG>  Read the recording_key for encrypted recordings from this config
+  -h, --help             Print help
+";
+
+const HELP_MP4DUMP: &str = "\
+Print the box types, offsets and sizes of a mp4 file
+
+Usage: sentryshot mp4dump <PATH>
 
 Arguments:
-  <PATH>
+  <PATH>  Path to the mp4 file
 
 Options:
   -h, --help  Print help
 ";
+
+const HELP_LOGEXPORT: &str = "\
+Export logs as newline delimited JSON, newest entry first
+
+Usage: sentryshot logexport [OPTIONS]
+
+Options:
+      --config <CONFIG>    [default: ./configs/sentryshot.toml]
+      --level <LEVELS>     Comma separated list of levels [error,warning,info,debug]
+      --source <SOURCES>   Comma separated list of sources
+      --monitor <IDS>      Comma separated list of monitor IDs
+      --since <TIME>       Only export entries at or after this time, unix microseconds
+      --until <TIME>       Only export entries before this time, unix microseconds
+  -h, --help               Print help
+";
+
+const HELP_LOGIMPORT: &str = "\
+Import logs from newline delimited JSON on stdin
+
+Usage: sentryshot logimport [OPTIONS]
+
+Options:
+      --config <CONFIG>  [default: ./configs/sentryshot.toml]
+  -h, --help             Print help
+";
+
+const HELP_LOGCOMPACT: &str = "\
+Rewrite the log database without corrupt entries and unused message bytes,
+the program must not be running
+
+Usage: sentryshot logcompact [OPTIONS]
+
+Options:
+      --config <CONFIG>  [default: ./configs/sentryshot.toml]
+  -h, --help             Print help
+";
@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use common::MasterKey;
+use recdb::{read_recording_key, RecordingKeyError};
+use recording::{export_annexb, DecryptReader, ExportAnnexbError};
+use std::path::Path;
+use thiserror::Error;
+use tokio::io::{BufReader, BufWriter};
+
+#[derive(Debug, Error)]
+pub enum RecToH264Error {
+    #[error("open meta file: {0}")]
+    OpenMeta(std::io::Error),
+
+    #[error("meta file metadata: {0}")]
+    MetaMetadata(std::io::Error),
+
+    #[error("{0}")]
+    RecordingKey(#[from] RecordingKeyError),
+
+    #[error("open mdat file: {0}")]
+    OpenMdat(std::io::Error),
+
+    #[error("decrypt mdat: {0}")]
+    Decrypt(std::io::Error),
+
+    #[error("create file: {0}")]
+    CreateFile(std::io::Error),
+
+    #[error("export: {0}")]
+    Export(#[from] ExportAnnexbError),
+}
+
+// Writes a recording to `output` as a Annex-B H264 elementary stream, the
+// NAL units are identical to the received stream. The path may be given
+// with or without extension. Encrypted recordings are decrypted with the
+// master key. See `export_annexb` for `insert_parameter_sets`.
+pub async fn rec_to_h264(
+    path: &Path,
+    output: &Path,
+    master_key: Option<&MasterKey>,
+    insert_parameter_sets: bool,
+) -> Result<(), RecToH264Error> {
+    use RecToH264Error::*;
+    let meta = tokio::fs::File::open(path.with_extension("meta"))
+        .await
+        .map_err(OpenMeta)?;
+    let meta_size = meta.metadata().await.map_err(MetaMetadata)?.len();
+    let meta = BufReader::new(meta);
+
+    let key_path = path.with_extension("key");
+    let key = if key_path.exists() {
+        Some(read_recording_key(master_key, &key_path).await?)
+    } else {
+        None
+    };
+    let mdat = tokio::fs::File::open(path.with_extension("mdat"))
+        .await
+        .map_err(OpenMdat)?;
+
+    let out = tokio::fs::File::create(output).await.map_err(CreateFile)?;
+    let mut out = BufWriter::new(out);
+
+    if let Some(key) = key {
+        let mdat = DecryptReader::new(mdat, &key).await.map_err(Decrypt)?;
+        export_annexb(meta, meta_size, mdat, &mut out, insert_parameter_sets).await?;
+    } else {
+        let mdat = BufReader::new(mdat);
+        export_annexb(meta, meta_size, mdat, &mut out, insert_parameter_sets).await?;
+    }
+    Ok(())
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::time::{DtsOffset, DurationH264, UnixH264};
+    use pretty_assertions::assert_eq;
+    use recording::{EncryptWriter, MetaHeader, RecordingKey, Sample};
+    use tempfile::tempdir;
+    use tokio::io::AsyncWriteExt;
+
+    const AUD: &[u8] = &[0x09, 0xf0];
+    const SEI: &[u8] = &[0x06, 0x05, 0x02, 0x00, 0x03, 0x80];
+    const SPS: &[u8] = &[0x67, 0x64, 0x00, 0x1f];
+    const PPS: &[u8] = &[0x68, 0xee, 0x3c];
+    const IDR: &[u8] = &[0x65, 0x88, 0x84, 0x00, 0x00, 0x03, 0x01];
+    const SLICE: &[u8] = &[0x41, 0x9a, 0x00];
+
+    // Returns the meta file and the mdat with two samples.
+    fn recording() -> (Vec<u8>, Vec<u8>) {
+        let header = MetaHeader {
+            start_time: UnixH264::new(1000),
+            width: 640,
+            height: 480,
+            extra_data: vec![1, 0x64, 0, 0x28, 0xff, 0xe0, 0],
+            audio: None,
+        };
+        let mut meta = header.marshal().unwrap();
+        let mut mdat = Vec::new();
+        for (i, nalus) in [[AUD, SEI, SPS, PPS, IDR].as_slice(), &[AUD, SEI, SLICE]]
+            .into_iter()
+            .enumerate()
+        {
+            let data_offset = u32::try_from(mdat.len()).unwrap();
+            for nalu in nalus {
+                mdat.extend_from_slice(&u32::try_from(nalu.len()).unwrap().to_be_bytes());
+                mdat.extend_from_slice(nalu);
+            }
+            let sample = Sample {
+                random_access_present: i == 0,
+                pts: UnixH264::new(1000 + i64::try_from(i).unwrap() * 10),
+                dts_offset: DtsOffset::new(0),
+                duration: DurationH264::new(10),
+                data_size: u32::try_from(mdat.len()).unwrap() - data_offset,
+                data_offset,
+            };
+            meta.extend(sample.encode().unwrap());
+        }
+        (meta, mdat)
+    }
+
+    fn annexb(nalus: &[&[u8]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for nalu in nalus {
+            out.extend_from_slice(&[0, 0, 0, 1]);
+            out.extend_from_slice(nalu);
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn test_rec_to_h264() {
+        let temp_dir = tempdir().unwrap();
+        let rec = temp_dir.path().join("2001-02-03_04-05-06_x");
+        let (meta, mdat) = recording();
+        std::fs::write(rec.with_extension("meta"), meta).unwrap();
+        std::fs::write(rec.with_extension("mdat"), mdat).unwrap();
+
+        let output = rec.with_extension("h264");
+        rec_to_h264(&rec, &output, None, false).await.unwrap();
+
+        let want = annexb(&[AUD, SEI, SPS, PPS, IDR, AUD, SEI, SLICE]);
+        assert_eq!(want, std::fs::read(output).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_rec_to_h264_encrypted() {
+        let temp_dir = tempdir().unwrap();
+        let rec = temp_dir.path().join("2001-02-03_04-05-06_x");
+        let (meta, mdat) = recording();
+        std::fs::write(rec.with_extension("meta"), meta).unwrap();
+
+        let master_key = MasterKey::new([1; 32]);
+        let key = RecordingKey::generate();
+        std::fs::write(rec.with_extension("key"), key.wrap(&master_key)).unwrap();
+        let mut encrypted = Vec::new();
+        let mut w = EncryptWriter::new(&mut encrypted, &key);
+        w.write_all(&mdat).await.unwrap();
+        w.flush().await.unwrap();
+        std::fs::write(rec.with_extension("mdat"), encrypted).unwrap();
+
+        let output = rec.with_extension("h264");
+        assert!(rec_to_h264(&rec, &output, None, false).await.is_err());
+
+        rec_to_h264(&rec, &output, Some(&master_key), false)
+            .await
+            .unwrap();
+        let want = annexb(&[AUD, SEI, SPS, PPS, IDR, AUD, SEI, SLICE]);
+        assert_eq!(want, std::fs::read(output).unwrap());
+    }
+}
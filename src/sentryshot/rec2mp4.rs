@@ -1,12 +1,22 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
-use recording::{new_video_reader, CreateVideoReaderError};
+use common::MasterKey;
+use recdb::{read_recording_key, RecordingKeyError};
+use recording::{
+    generate_mp4_sync, read_audio_meta, read_meta, AudioTrack, DecryptReader, GenerateMp4Error,
+    MetaHeader, ReadMetaError, Sample, Timescale,
+};
 use std::{
     collections::VecDeque,
+    fs::OpenOptions,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    num::NonZeroUsize,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 use thiserror::Error;
-use tokio::sync::mpsc;
+use tokio::sync::Semaphore;
+use tokio_util::io::SyncIoBridge;
 
 #[derive(Debug, Error)]
 pub enum RecToMp4Error {
@@ -18,12 +28,106 @@ pub enum RecToMp4Error {
 
     #[error("metadata: {0}")]
     Metadata(std::io::Error),
+
+    #[error("create output dir: {0}")]
+    CreateOutputDir(std::io::Error),
 }
 
-#[allow(clippy::unwrap_used)]
-pub async fn rec_to_mp4(path: PathBuf) -> Result<(), RecToMp4Error> {
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RecToMp4Summary {
+    pub converted: usize,
+    pub failed: usize,
+}
+
+// Converts a single recording or every recording in a directory tree. The
+// mp4 files are written next to the recordings unless `output_dir` is set.
+// A failed recording is reported and doesn't stop the other conversions.
+// Up to `jobs` recordings are converted at the same time, the results are
+// printed in path order. Encrypted recordings are decrypted with
+// the master key and fail to convert if it isn't set.
+// The video tracks use `timescale` as the media timescale.
+pub async fn rec_to_mp4(
+    path: PathBuf,
+    output_dir: Option<PathBuf>,
+    jobs: NonZeroUsize,
+    master_key: Option<MasterKey>,
+    timescale: Timescale,
+) -> Result<RecToMp4Summary, RecToMp4Error> {
+    use RecToMp4Error::*;
+
+    let recording_paths = find_recordings(path)?;
+    let n_recordings = recording_paths.len();
+    println!("Found {n_recordings} recordings");
+
+    let mut summary = RecToMp4Summary::default();
+    if n_recordings == 0 {
+        return Ok(summary);
+    }
+    if let Some(output_dir) = &output_dir {
+        std::fs::create_dir_all(output_dir).map_err(CreateOutputDir)?;
+    }
+
+    // Files are only opened after a permit is acquired.
+    let permits = Arc::new(Semaphore::new(jobs.get()));
+    let mut tasks = Vec::with_capacity(n_recordings);
+    for recording_path in recording_paths {
+        let permits = permits.clone();
+        let master_key = master_key.clone();
+        let mp4_path = mp4_path(&recording_path, output_dir.as_deref());
+        tasks.push(tokio::spawn(async move {
+            let _permit = permits.acquire().await.expect("semaphore not closed");
+            ConvertResult {
+                recording_path: recording_path.clone(),
+                res: convert(recording_path, mp4_path.clone(), master_key, timescale).await,
+                mp4_path,
+            }
+        }));
+    }
+
+    for (i, task) in tasks.into_iter().enumerate() {
+        let i = i + 1;
+        let result = match task.await {
+            Ok(v) => v,
+            Err(e) => {
+                println!("[{i}/{n_recordings}] [ERR] {e}");
+                summary.failed += 1;
+                continue;
+            }
+        };
+        if let Err(e) = result.res {
+            let path = result.recording_path.to_string_lossy();
+            println!("[{i}/{n_recordings}] [ERR] {} {}", path, &e);
+            summary.failed += 1;
+            continue;
+        }
+        let path = result.mp4_path.to_string_lossy();
+        println!("[{i}/{n_recordings}] [OK] {path}");
+        summary.converted += 1;
+    }
+    println!(
+        "Converted {} recordings, {} failed",
+        summary.converted, summary.failed
+    );
+
+    Ok(summary)
+}
+
+// Returns the paths of all recordings, without extension, that have
+// both a meta and mdat file. `path` may also be a single recording.
+fn find_recordings(path: PathBuf) -> Result<Vec<PathBuf>, RecToMp4Error> {
     use RecToMp4Error::*;
 
+    if !path.is_dir() {
+        let recording_path = path.with_extension("");
+        let is_recording = recording_path.with_extension("meta").exists()
+            && recording_path.with_extension("mdat").exists();
+        return Ok(if is_recording {
+            vec![recording_path]
+        } else {
+            Vec::new()
+        });
+    }
+
     let mut recording_paths = Vec::new();
     let mut dirs_to_visit = VecDeque::new();
     dirs_to_visit.push_back(path);
@@ -61,76 +165,370 @@ pub async fn rec_to_mp4(path: PathBuf) -> Result<(), RecToMp4Error> {
             recording_paths.push(recording_path);
         }
     }
+    recording_paths.sort();
+    Ok(recording_paths)
+}
 
-    let n_recordings = recording_paths.len();
-    println!("Found {n_recordings} new recordings");
-
-    if n_recordings == 0 {
-        return Ok(());
-    }
-
-    let (results_tx, mut results_rx) = mpsc::channel(n_recordings);
-
-    for recording_path in recording_paths {
-        let results_tx = results_tx.clone();
-        tokio::spawn(async move {
-            results_tx
-                .send(ConvertResult {
-                    recording_path: recording_path.clone(),
-                    res: convert(recording_path).await,
-                })
-                .await
-        });
-    }
-
-    for i in 1..=n_recordings {
-        println!("[{i}/{n_recordings}]");
-        let result = results_rx.recv().await.unwrap();
-        let path = result.recording_path.to_string_lossy();
-        if let Err(e) = result.res {
-            println!("[ERR] {} {}", path, &e);
-            continue;
-        }
-        println!("[OK] {path}.mp4");
-    }
-
-    Ok(())
+fn mp4_path(recording_path: &Path, output_dir: Option<&Path>) -> PathBuf {
+    let mp4_path = match (output_dir, recording_path.file_name()) {
+        (Some(output_dir), Some(file_name)) => output_dir.join(file_name),
+        _ => recording_path.to_path_buf(),
+    };
+    mp4_path.with_extension("mp4")
 }
 
 struct ConvertResult {
     recording_path: PathBuf,
+    mp4_path: PathBuf,
     res: Result<(), ConvertError>,
 }
 
 #[derive(Debug, Error)]
 enum ConvertError {
-    #[error("create video reader: {0}")]
-    NewVideoReader(#[from] CreateVideoReaderError),
+    #[error("open meta file: {0}")]
+    OpenMeta(std::io::Error),
+
+    #[error("meta file metadata: {0}")]
+    MetaMetadata(std::io::Error),
+
+    #[error("read meta: {0}")]
+    ReadMeta(#[from] ReadMetaError),
+
+    #[error("open mdat file: {0}")]
+    OpenMdat(std::io::Error),
+
+    #[error("open audio file: {0}")]
+    OpenAudio(std::io::Error),
+
+    #[error("{0}")]
+    RecordingKey(#[from] RecordingKeyError),
 
     #[error("open file: {0}")]
     OpenFile(std::io::Error),
 
+    #[error("generate mp4: {0}")]
+    GenerateMp4(#[from] GenerateMp4Error),
+
     #[error("copy: {0}")]
     Copy(std::io::Error),
+
+    #[error("mdat size: expected {0} got {1}")]
+    MdatSize(u32, u64),
+
+    #[error("flush: {0}")]
+    Flush(std::io::Error),
+
+    #[error("join: {0}")]
+    Join(#[from] tokio::task::JoinError),
 }
 
-async fn convert(recording_path: PathBuf) -> Result<(), ConvertError> {
+async fn convert(
+    recording_path: PathBuf,
+    mp4_path: PathBuf,
+    master_key: Option<MasterKey>,
+    timescale: Timescale,
+) -> Result<(), ConvertError> {
     use ConvertError::*;
-    let mut video_reader = new_video_reader(recording_path.clone(), 0, &None).await?;
+    let mut meta_path = recording_path.clone();
+    meta_path.set_extension("meta");
+
+    let meta = tokio::fs::File::open(&meta_path).await.map_err(OpenMeta)?;
+    let meta_size = meta.metadata().await.map_err(MetaMetadata)?.len();
+    let (header, samples) = read_meta(tokio::io::BufReader::new(meta), meta_size).await?;
+
+    let key_path = recording_path.with_extension("key");
+
+    // Encrypted recordings don't have audio.
+    let audio = if header.audio.is_some() && !key_path.exists() {
+        read_audio(&recording_path, &header).await?
+    } else {
+        None
+    };
+
+    let mdat = tokio::fs::File::open(recording_path.with_extension("mdat"))
+        .await
+        .map_err(OpenMdat)?;
+    let mdat: Box<dyn Read + Send> = if key_path.exists() {
+        let key = read_recording_key(master_key.as_ref(), &key_path).await?;
+        let mdat = DecryptReader::new(mdat, &key).await.map_err(OpenMdat)?;
+        Box::new(SyncIoBridge::new(mdat))
+    } else {
+        Box::new(mdat.into_std().await)
+    };
+
+    tokio::task::spawn_blocking(move || {
+        write_mp4(mdat, audio, &mp4_path, &header, &samples, timescale)
+    })
+    .await?
+}
+
+// Returns the audio data file and the audio samples that don't start
+// before the recording, None if the recording has no audio files.
+async fn read_audio(
+    recording_path: &Path,
+    header: &MetaHeader,
+) -> Result<Option<(std::fs::File, Vec<Sample>)>, ConvertError> {
+    use ConvertError::*;
+    let ameta_path = recording_path.with_extension("ameta");
+    let amdat_path = recording_path.with_extension("amdat");
+    if !ameta_path.exists() || !amdat_path.exists() {
+        return Ok(None);
+    }
 
-    let mut mp4_path = recording_path.clone();
-    mp4_path.set_extension("mp4");
+    let ameta = tokio::fs::File::open(&ameta_path)
+        .await
+        .map_err(OpenAudio)?;
+    let ameta_size = ameta.metadata().await.map_err(OpenAudio)?.len();
+    let samples = read_audio_meta(tokio::io::BufReader::new(ameta), ameta_size)
+        .await?
+        .into_iter()
+        .filter(|v| header.start_time <= v.pts)
+        .collect();
+
+    let amdat = std::fs::File::open(amdat_path).map_err(OpenAudio)?;
+    Ok(Some((amdat, samples)))
+}
 
-    let mut file = tokio::fs::OpenOptions::new()
+// The mp4 header is marshaled synchronously straight into the buffered
+// file, followed by the raw mdat and the audio data.
+fn write_mp4(
+    mdat: Box<dyn Read + Send>,
+    audio: Option<(std::fs::File, Vec<Sample>)>,
+    mp4_path: &Path,
+    header: &MetaHeader,
+    samples: &[Sample],
+    timescale: Timescale,
+) -> Result<(), ConvertError> {
+    use ConvertError::*;
+    let file = OpenOptions::new()
         .create(true)
         .write(true)
+        .truncate(true)
         .open(mp4_path)
-        .await
         .map_err(OpenFile)?;
+    let mut out = BufWriter::new(file);
+
+    let params = header.params();
+    let audio_track = match (&audio, &header.audio) {
+        (Some((_, samples)), Some(params)) => Some(AudioTrack { samples, params }),
+        _ => None,
+    };
+    let mdat_size = generate_mp4_sync(
+        &mut out,
+        header.start_time,
+        samples.iter(),
+        &params,
+        audio_track,
+        None,
+        timescale,
+    )?;
+
+    let audio_size: u32 = audio_track.map_or(0, |v| v.samples.iter().map(|v| v.data_size).sum());
+    let video_size = mdat_size - audio_size;
+    let n = std::io::copy(&mut mdat.take(u64::from(video_size)), &mut out).map_err(Copy)?;
+    if n != u64::from(video_size) {
+        return Err(MdatSize(video_size, n));
+    }
+
+    // The audio samples are contiguous from the first sample.
+    if let Some((mut amdat, samples)) = audio.filter(|(_, samples)| !samples.is_empty()) {
+        let first_offset = samples.first().map_or(0, |v| v.data_offset);
+        amdat
+            .seek(SeekFrom::Start(u64::from(first_offset)))
+            .map_err(Copy)?;
+        let n = std::io::copy(&mut amdat.take(u64::from(audio_size)), &mut out).map_err(Copy)?;
+        if n != u64::from(audio_size) {
+            return Err(MdatSize(audio_size, n));
+        }
+    }
+    out.flush().map_err(Flush)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::time::{DtsOffset, DurationH264, UnixH264};
+    use pretty_assertions::assert_eq;
+    use recording::{AudioParameters, EncryptWriter, RecordingKey};
+    use std::num::NonZeroU32;
+    use tempfile::tempdir;
+    use tokio::io::AsyncWriteExt;
+
+    fn write_recording(path: &Path, audio: Option<AudioParameters>) {
+        let header = MetaHeader {
+            start_time: UnixH264::new(1000),
+            width: 640,
+            height: 480,
+            extra_data: vec![1, 0x64, 0, 0x28, 0xff, 0xe0, 0],
+            audio,
+        };
+        let mut meta = header.marshal().unwrap();
+        for i in 0..3 {
+            let sample = Sample {
+                random_access_present: i == 0,
+                pts: UnixH264::new(1000 + i * 10),
+                dts_offset: DtsOffset::new(0),
+                duration: DurationH264::new(10),
+                data_size: 4,
+                data_offset: u32::try_from(i * 4).unwrap(),
+            };
+            meta.extend(sample.encode().unwrap());
+        }
+        std::fs::write(path.with_extension("meta"), meta).unwrap();
+        std::fs::write(path.with_extension("mdat"), [0; 12]).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rec_to_mp4_jobs() {
+        let temp_dir = tempdir().unwrap();
+        let recordings = temp_dir.path().join("recordings");
+        let day = recordings.join("2001").join("02").join("03").join("x");
+        std::fs::create_dir_all(&day).unwrap();
+        write_recording(&day.join("2001-02-03_04-05-06_x"), None);
+        write_recording(&day.join("2001-02-03_04-05-07_x"), None);
+
+        // Corrupt recording.
+        std::fs::write(day.join("2001-02-03_04-05-08_x.meta"), [1, 2, 3]).unwrap();
+        std::fs::write(day.join("2001-02-03_04-05-08_x.mdat"), []).unwrap();
+
+        let output_dir = temp_dir.path().join("out");
+        let jobs = NonZeroUsize::new(2).unwrap();
+        let summary = rec_to_mp4(
+            recordings,
+            Some(output_dir.clone()),
+            jobs,
+            None,
+            Timescale::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            RecToMp4Summary {
+                converted: 2,
+                failed: 1
+            },
+            summary
+        );
+
+        let mut got: Vec<_> = std::fs::read_dir(&output_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        got.sort();
+        assert_eq!(
+            vec!["2001-02-03_04-05-06_x.mp4", "2001-02-03_04-05-07_x.mp4"],
+            got
+        );
+        let mp4 = std::fs::read(output_dir.join("2001-02-03_04-05-06_x.mp4")).unwrap();
+        assert_eq!(b"ftyp", &mp4[4..8]);
+    }
+
+    #[tokio::test]
+    async fn test_rec_to_mp4_encrypted() {
+        let temp_dir = tempdir().unwrap();
+        let rec = temp_dir.path().join("2001-02-03_04-05-06_x");
+        write_recording(&rec, None);
+
+        let master_key = MasterKey::new([1; 32]);
+        let key = RecordingKey::generate();
+        std::fs::write(rec.with_extension("key"), key.wrap(&master_key)).unwrap();
+        let mut mdat = Vec::new();
+        let mut w = EncryptWriter::new(&mut mdat, &key);
+        w.write_all(&[1; 12]).await.unwrap();
+        w.flush().await.unwrap();
+        std::fs::write(rec.with_extension("mdat"), mdat).unwrap();
+
+        let jobs = NonZeroUsize::MIN;
+        let summary = rec_to_mp4(rec.clone(), None, jobs, None, Timescale::default())
+            .await
+            .unwrap();
+        assert_eq!(1, summary.failed);
 
-    tokio::io::copy(&mut video_reader, &mut file)
+        let summary = rec_to_mp4(
+            rec.clone(),
+            None,
+            jobs,
+            Some(master_key),
+            Timescale::default(),
+        )
         .await
-        .map_err(Copy)?;
+        .unwrap();
+        assert_eq!(1, summary.converted);
+        let mp4 = std::fs::read(rec.with_extension("mp4")).unwrap();
+        assert_eq!(&[1; 12], &mp4[mp4.len() - 12..]);
+    }
+
+    #[tokio::test]
+    async fn test_rec_to_mp4_timescale() {
+        let temp_dir = tempdir().unwrap();
+        let rec = temp_dir.path().join("2001-02-03_04-05-06_x");
+        write_recording(&rec, None);
 
-    Ok(())
+        let timescale = Timescale::new(NonZeroU32::new(30000).unwrap());
+        let summary = rec_to_mp4(rec.clone(), None, NonZeroUsize::MIN, None, timescale)
+            .await
+            .unwrap();
+        assert_eq!(1, summary.converted);
+
+        // Mdhd timescale and duration.
+        let mp4 = std::fs::read(rec.with_extension("mp4")).unwrap();
+        let pos = mp4.windows(4).position(|v| v == b"mdhd").unwrap() + 4;
+        assert_eq!([0, 0, 0x75, 0x30, 0, 0, 0, 10], mp4[pos + 12..pos + 20]);
+    }
+
+    #[tokio::test]
+    async fn test_rec_to_mp4_audio() {
+        let temp_dir = tempdir().unwrap();
+        let rec = temp_dir.path().join("2001-02-03_04-05-06_x");
+        let params = AudioParameters {
+            channel_count: 2,
+            sample_rate: 48000,
+            config: vec![0x11, 0x90],
+        };
+        write_recording(&rec, Some(params));
+
+        // The first sample starts before the video and is dropped.
+        let mut ameta = Vec::new();
+        for i in 0..3 {
+            let sample = Sample {
+                random_access_present: true,
+                pts: UnixH264::new(990 + i * 10),
+                dts_offset: DtsOffset::new(0),
+                duration: DurationH264::new(10),
+                data_size: 2,
+                data_offset: u32::try_from(i * 2).unwrap(),
+            };
+            ameta.extend(sample.encode().unwrap());
+        }
+        std::fs::write(rec.with_extension("ameta"), ameta).unwrap();
+        std::fs::write(rec.with_extension("amdat"), [9, 9, 1, 2, 3, 4]).unwrap();
+
+        let summary = rec_to_mp4(
+            rec.clone(),
+            None,
+            NonZeroUsize::MIN,
+            None,
+            Timescale::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(1, summary.converted);
+
+        let mp4 = std::fs::read(rec.with_extension("mp4")).unwrap();
+        assert!(mp4.windows(4).any(|v| v == b"mp4a"));
+        let mut want = vec![0; 12];
+        want.extend([1, 2, 3, 4]);
+        assert_eq!(want, mp4[mp4.len() - 16..]);
+    }
+
+    #[test]
+    fn test_mp4_path() {
+        let rec = Path::new("/a/b/rec");
+        assert_eq!(PathBuf::from("/a/b/rec.mp4"), mp4_path(rec, None));
+        assert_eq!(
+            PathBuf::from("/out/rec.mp4"),
+            mp4_path(rec, Some(Path::new("/out")))
+        );
+    }
 }
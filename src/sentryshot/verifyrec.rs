@@ -0,0 +1,277 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use common::{time::UnixH264, MasterKey};
+use recdb::{read_recording_key, RecordingKeyError};
+use recording::{read_meta, DecryptReader, MetaHeader, ReadMetaError, Sample};
+use std::{fmt, path::Path};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum VerifyRecError {
+    #[error("open meta file: {0}")]
+    OpenMeta(std::io::Error),
+
+    #[error("meta file metadata: {0}")]
+    MetaMetadata(std::io::Error),
+
+    #[error("read meta: {0}")]
+    ReadMeta(#[from] ReadMetaError),
+
+    #[error("mdat file metadata: {0}")]
+    MdatMetadata(std::io::Error),
+
+    #[error("{0}")]
+    RecordingKey(#[from] RecordingKeyError),
+
+    #[error("open mdat file: {0}")]
+    OpenMdat(std::io::Error),
+
+    #[error("decrypt mdat: {0}")]
+    Decrypt(std::io::Error),
+
+    #[error("{0}")]
+    Check(#[from] CheckError),
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum CheckError {
+    #[error("sample {0}: dts overflow")]
+    DtsOverflow(usize),
+
+    #[error("sample {index}: dts {dts} is not after the previous dts {prev}")]
+    DtsNotMonotonic {
+        index: usize,
+        prev: UnixH264,
+        dts: UnixH264,
+    },
+
+    #[error("sample {index}: data ends at byte {end} but the mdat is only {mdat_size} bytes")]
+    DataOutOfBounds {
+        index: usize,
+        end: u64,
+        mdat_size: u64,
+    },
+
+    #[error("no keyframe")]
+    NoKeyframe,
+}
+
+// Summary of a recording that passed verification.
+#[derive(Debug, PartialEq, Eq)]
+pub struct VerifySummary {
+    pub width: u16,
+    pub height: u16,
+    pub samples: usize,
+    pub keyframes: usize,
+    pub mdat_size: u64,
+}
+
+impl fmt::Display for VerifySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{}, {} samples, {} keyframes, {} byte mdat",
+            self.width, self.height, self.samples, self.keyframes, self.mdat_size
+        )
+    }
+}
+
+// Reads the meta and mdat files of a recording and checks that all
+// samples can be muxed. The path may be given with or without extension.
+// Encrypted recordings are fully decrypted which also checks the mdat.
+pub async fn verify_rec(
+    path: &Path,
+    master_key: Option<&MasterKey>,
+) -> Result<VerifySummary, VerifyRecError> {
+    use VerifyRecError::*;
+    let meta_path = path.with_extension("meta");
+    let mdat_path = path.with_extension("mdat");
+
+    let meta = tokio::fs::File::open(&meta_path).await.map_err(OpenMeta)?;
+    let meta_size = meta.metadata().await.map_err(MetaMetadata)?.len();
+    let (header, samples) = read_meta(tokio::io::BufReader::new(meta), meta_size).await?;
+
+    let key_path = path.with_extension("key");
+    let mdat_size = if key_path.exists() {
+        let key = read_recording_key(master_key, &key_path).await?;
+        let mdat = tokio::fs::File::open(&mdat_path).await.map_err(OpenMdat)?;
+        let mut mdat = DecryptReader::new(mdat, &key).await.map_err(Decrypt)?;
+        tokio::io::copy(&mut mdat, &mut tokio::io::sink())
+            .await
+            .map_err(Decrypt)?
+    } else {
+        tokio::fs::metadata(&mdat_path)
+            .await
+            .map_err(MdatMetadata)?
+            .len()
+    };
+
+    Ok(check_samples(&header, &samples, mdat_size)?)
+}
+
+fn check_samples(
+    header: &MetaHeader,
+    samples: &[Sample],
+    mdat_size: u64,
+) -> Result<VerifySummary, CheckError> {
+    use CheckError::*;
+    let mut prev_dts = None;
+    let mut keyframes = 0;
+    for (index, sample) in samples.iter().enumerate() {
+        let dts = sample.dts().ok_or(DtsOverflow(index))?;
+        if let Some(prev) = prev_dts {
+            if dts <= prev {
+                return Err(DtsNotMonotonic { index, prev, dts });
+            }
+        }
+        prev_dts = Some(dts);
+
+        let end = u64::from(sample.data_offset) + u64::from(sample.data_size);
+        if end > mdat_size {
+            return Err(DataOutOfBounds {
+                index,
+                end,
+                mdat_size,
+            });
+        }
+
+        if sample.random_access_present {
+            keyframes += 1;
+        }
+    }
+    if keyframes == 0 {
+        return Err(NoKeyframe);
+    }
+    Ok(VerifySummary {
+        width: header.width,
+        height: header.height,
+        samples: samples.len(),
+        keyframes,
+        mdat_size,
+    })
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::time::{DtsOffset, DurationH264};
+    use pretty_assertions::assert_eq;
+    use recording::{EncryptWriter, RecordingKey};
+    use tempfile::tempdir;
+    use tokio::io::AsyncWriteExt;
+
+    fn write_recording(path: &Path, mdat_size: usize) {
+        let header = MetaHeader {
+            start_time: UnixH264::new(1000),
+            width: 640,
+            height: 480,
+            extra_data: vec![1, 2],
+            audio: None,
+        };
+        let mut meta = header.marshal().unwrap();
+        for i in 0..3 {
+            let sample = Sample {
+                random_access_present: i == 0,
+                pts: UnixH264::new(1000 + i * 10),
+                dts_offset: DtsOffset::new(0),
+                duration: DurationH264::new(10),
+                data_size: 4,
+                data_offset: u32::try_from(i * 4).unwrap(),
+            };
+            meta.extend(sample.encode().unwrap());
+        }
+        std::fs::write(path.with_extension("meta"), meta).unwrap();
+        std::fs::write(path.with_extension("mdat"), vec![0; mdat_size]).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_verify_rec_ok() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("rec");
+        write_recording(&path, 12);
+
+        let want = VerifySummary {
+            width: 640,
+            height: 480,
+            samples: 3,
+            keyframes: 1,
+            mdat_size: 12,
+        };
+        assert_eq!(want, verify_rec(&path, None).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_verify_rec_truncated() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("rec");
+        write_recording(&path, 10);
+
+        let err = verify_rec(&path, None).await.unwrap_err();
+        assert_eq!(
+            "sample 2: data ends at byte 12 but the mdat is only 10 bytes",
+            err.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_rec_encrypted() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("rec");
+        write_recording(&path, 0);
+
+        let master_key = MasterKey::new([1; 32]);
+        let key = RecordingKey::generate();
+        std::fs::write(path.with_extension("key"), key.wrap(&master_key)).unwrap();
+        let mut mdat = Vec::new();
+        let mut w = EncryptWriter::new(&mut mdat, &key);
+        w.write_all(&[0; 12]).await.unwrap();
+        w.flush().await.unwrap();
+        std::fs::write(path.with_extension("mdat"), &mdat).unwrap();
+
+        assert!(matches!(
+            verify_rec(&path, None).await,
+            Err(VerifyRecError::RecordingKey(RecordingKeyError::NoMasterKey))
+        ));
+        let summary = verify_rec(&path, Some(&master_key)).await.unwrap();
+        assert_eq!(12, summary.mdat_size);
+
+        // Corrupt ciphertext.
+        let last = mdat.len() - 1;
+        mdat[last] ^= 1;
+        std::fs::write(path.with_extension("mdat"), &mdat).unwrap();
+        assert!(matches!(
+            verify_rec(&path, Some(&master_key)).await,
+            Err(VerifyRecError::Decrypt(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_samples() {
+        let header = MetaHeader {
+            start_time: UnixH264::new(0),
+            width: 0,
+            height: 0,
+            extra_data: Vec::new(),
+            audio: None,
+        };
+        let sample = |pts, random_access_present| Sample {
+            random_access_present,
+            pts: UnixH264::new(pts),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Err(CheckError::DtsNotMonotonic {
+                index: 1,
+                prev: UnixH264::new(5),
+                dts: UnixH264::new(5),
+            }),
+            check_samples(&header, &[sample(5, true), sample(5, false)], 0)
+        );
+        assert_eq!(
+            Err(CheckError::NoKeyframe),
+            check_samples(&header, &[sample(1, false), sample(2, false)], 0)
+        );
+    }
+}
@@ -1,49 +1,125 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
-use crate::QueryResult;
-use crate::VodQuery;
-use std::{collections::HashMap, sync::Arc};
+use crate::{EmptySamplePolicy, FragmentHeader, QueryResult, Rec, VodConfig, VodQuery};
+use bytesize::ByteSize;
+use common::time::UnixNano;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use thiserror::Error;
 use tokio::sync::Mutex;
 
-// Caches the n most recent vod readers.
+// Caches the n most recently used vod readers. Optionally
+// backed by a disk cache that survives restarts.
 #[derive(Clone)]
 #[allow(clippy::module_name_repetitions)]
-pub struct VodCache(Arc<Mutex<State>>);
+pub struct VodCache {
+    memory: Arc<Mutex<State>>,
+    disk: Option<Arc<DiskCache>>,
+}
 
 struct State {
     items: HashMap<VodQuery, CacheItem>,
     age: usize,
 
     max_size: usize,
+    // Items older than this are removed.
+    ttl: Option<Duration>,
+    // Keys in the order they were added, only used with a ttl.
+    // May contain keys that have already been evicted.
+    added: VecDeque<(Instant, VodQuery)>,
 }
 
 struct CacheItem {
     age: usize,
+    added: Instant,
     data: Arc<QueryResult>,
 }
 
-const VOD_CACHE_SIZE: usize = 10;
-
 impl VodCache {
+    pub const DEFAULT_MAX_ENTRIES: NonZeroUsize = match NonZeroUsize::new(10) {
+        Some(v) => v,
+        None => unreachable!(),
+    };
+
     #[must_use]
     pub fn new() -> Self {
-        Self::with_capacity(VOD_CACHE_SIZE)
+        Self::with_capacity(Self::DEFAULT_MAX_ENTRIES.get(), None)
     }
 
-    fn with_capacity(max_size: usize) -> Self {
-        Self(Arc::new(Mutex::new(State {
-            items: HashMap::new(),
-            age: 0,
-            max_size,
-        })))
+    // Keeps up to `max_entries` results in memory, results are
+    // queried again once they're older than `ttl`.
+    #[must_use]
+    pub fn with_config(max_entries: NonZeroUsize, ttl: Option<Duration>) -> Self {
+        Self::with_capacity(max_entries.get(), ttl)
+    }
+
+    // Also stores the results in `dir` which is limited to `max_size`
+    // by removing the oldest files. The ttl also applies to the files.
+    // Results generated with other limits than `config` are not used.
+    pub async fn with_disk(
+        mut self,
+        dir: PathBuf,
+        max_size: ByteSize,
+        config: &VodConfig,
+    ) -> Result<Self, NewDiskCacheError> {
+        let ttl = self.memory.lock().await.ttl;
+        self.disk = Some(Arc::new(
+            DiskCache::new(dir, max_size.as_u64(), ttl, config).await?,
+        ));
+        Ok(self)
+    }
+
+    fn with_capacity(max_size: usize, ttl: Option<Duration>) -> Self {
+        Self {
+            memory: Arc::new(Mutex::new(State {
+                items: HashMap::new(),
+                age: 0,
+                max_size,
+                ttl,
+                added: VecDeque::new(),
+            })),
+            disk: None,
+        }
     }
 
     pub(crate) async fn add(&self, key: VodQuery, res: Arc<QueryResult>) {
-        self.0.lock().await.add(key, res);
+        if let Some(disk) = &self.disk {
+            disk.add(&key, &res).await;
+        }
+        self.memory.lock().await.add(key, res, Instant::now());
     }
 
     pub(crate) async fn get(&self, key: &VodQuery) -> Option<Arc<QueryResult>> {
-        self.0.lock().await.get(key)
+        if let Some(res) = self.memory.lock().await.get(key, Instant::now()) {
+            return Some(res);
+        }
+        let res = self.disk.as_ref()?.get(key).await?;
+        self.memory
+            .lock()
+            .await
+            .add(key.clone(), res.clone(), Instant::now());
+        Some(res)
+    }
+
+    // Returns true if the query is cached, the disk cache entry may be outdated.
+    pub async fn contains(&self, key: &VodQuery) -> bool {
+        if self.memory.lock().await.contains(key, Instant::now()) {
+            return true;
+        }
+        let Some(disk) = &self.disk else {
+            return false;
+        };
+        disk.state
+            .lock()
+            .await
+            .files
+            .contains_key(&disk.file_name(key))
     }
 }
 
@@ -54,7 +130,9 @@ impl Default for VodCache {
 }
 
 impl State {
-    fn add(&mut self, key: VodQuery, res: Arc<QueryResult>) {
+    fn add(&mut self, key: VodQuery, res: Arc<QueryResult>, now: Instant) {
+        self.remove_expired(now);
+
         // Ignore duplicate keys.
         if self.items.contains_key(&key) {
             return;
@@ -72,32 +150,375 @@ impl State {
             self.items.remove(&key.to_owned());
         }
 
+        if self.ttl.is_some() {
+            self.added.push_back((now, key.clone()));
+        }
         self.items.insert(
             key,
             CacheItem {
                 age: self.age,
+                added: now,
                 data: res,
             },
         );
     }
 
-    fn get(&mut self, key: &VodQuery) -> Option<Arc<QueryResult>> {
-        for (item_key, item) in &mut self.items {
-            if item_key == key {
-                self.age += 1;
-                item.age = self.age;
-                return Some(item.data.clone());
+    fn get(&mut self, key: &VodQuery, now: Instant) -> Option<Arc<QueryResult>> {
+        self.remove_expired(now);
+        let item = self.items.get_mut(key)?;
+        self.age += 1;
+        item.age = self.age;
+        Some(item.data.clone())
+    }
+
+    fn contains(&mut self, key: &VodQuery, now: Instant) -> bool {
+        self.remove_expired(now);
+        self.items.contains_key(key)
+    }
+
+    // Dropping the items frees the results unless they're in use.
+    // Only the expired items are visited since they're at the front.
+    fn remove_expired(&mut self, now: Instant) {
+        let Some(ttl) = self.ttl else {
+            return;
+        };
+        while let Some((added, _)) = self.added.front() {
+            if now.saturating_duration_since(*added) < ttl {
+                return;
+            }
+            let (added, key) = self.added.pop_front().expect("front exists");
+            // The key may have been evicted and added again.
+            if self.items.get(&key).is_some_and(|item| item.added == added) {
+                self.items.remove(&key);
             }
         }
-        None
     }
 }
 
+#[derive(Debug, Error)]
+pub enum NewDiskCacheError {
+    #[error("create directory: {0}")]
+    CreateDir(std::io::Error),
+
+    #[error("read directory: {0}")]
+    ReadDir(std::io::Error),
+
+    #[error("read file metadata: {0}")]
+    Metadata(std::io::Error),
+
+    #[error("remove file: {0}")]
+    RemoveFile(std::io::Error),
+}
+
+const DISK_CACHE_VERSION: u8 = 3;
+
+// Only the generated meta is stored, the sample data is read from the
+// recordings. A cached result is invalid if any of its mdat files have
+// been modified. The LRU order is rebuilt from the file modification
+// times on startup.
+struct DiskCache {
+    dir: PathBuf,
+    max_size: u64,
+    ttl: Option<Duration>,
+    // Added to the file names, the results depend on the limits.
+    limits: String,
+    state: Mutex<DiskState>,
+}
+
+#[derive(Default)]
+struct DiskState {
+    // File name and size of each cached result.
+    files: HashMap<String, DiskItem>,
+    age: usize,
+    size: u64,
+}
+
+struct DiskItem {
+    age: usize,
+    size: u64,
+}
+
+// Everything in `QueryResult` except the meta, it's stored as raw bytes.
+#[derive(Deserialize, Serialize)]
+struct DiskCacheHeader {
+    size: usize,
+    recs: Vec<Rec>,
+    fragments: Vec<FragmentHeader>,
+    init_size: usize,
+    last_modified: Option<UnixNano>,
+    #[serde(default)]
+    truncated: bool,
+    mdat_mtimes: Vec<(PathBuf, SystemTime)>,
+    created: SystemTime,
+}
+
+impl DiskCache {
+    async fn new(
+        dir: PathBuf,
+        max_size: u64,
+        ttl: Option<Duration>,
+        config: &VodConfig,
+    ) -> Result<Self, NewDiskCacheError> {
+        use NewDiskCacheError::*;
+        tokio::fs::create_dir_all(&dir).await.map_err(CreateDir)?;
+
+        let mut files = Vec::new();
+        let mut entries = tokio::fs::read_dir(&dir).await.map_err(ReadDir)?;
+        while let Some(entry) = entries.next_entry().await.map_err(ReadDir)? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.path().extension().is_some_and(|ext| ext == "tmp") {
+                // Interrupted write.
+                tokio::fs::remove_file(entry.path())
+                    .await
+                    .map_err(RemoveFile)?;
+                continue;
+            }
+            let metadata = entry.metadata().await.map_err(Metadata)?;
+            let modified = metadata.modified().map_err(Metadata)?;
+            files.push((modified, name, metadata.len()));
+        }
+
+        // Oldest first.
+        files.sort();
+        let mut state = DiskState::default();
+        for (_, name, size) in files {
+            state.insert(name, size);
+        }
+        for name in state.evict(max_size) {
+            tokio::fs::remove_file(dir.join(name))
+                .await
+                .map_err(RemoveFile)?;
+        }
+
+        Ok(Self {
+            dir,
+            max_size,
+            ttl,
+            limits: cache_limits(config),
+            state: Mutex::new(state),
+        })
+    }
+
+    fn file_name(&self, key: &VodQuery) -> String {
+        format!("{}_{}", cache_file_name(key), self.limits)
+    }
+
+    async fn get(&self, key: &VodQuery) -> Option<Arc<QueryResult>> {
+        let name = self.file_name(key);
+        if !self.state.lock().await.touch(&name) {
+            return None;
+        }
+        let path = self.dir.join(&name);
+        let Some(res) = read_cache_file(&path, self.ttl).await else {
+            // Corrupt, outdated or expired.
+            self.remove(&name).await;
+            return None;
+        };
+        // The LRU order is rebuilt from the modification times.
+        _ = tokio::task::spawn_blocking(move || {
+            std::fs::File::options()
+                .write(true)
+                .open(path)?
+                .set_modified(SystemTime::now())
+        })
+        .await;
+        Some(Arc::new(res))
+    }
+
+    // The disk cache is best effort, errors are ignored.
+    async fn add(&self, key: &VodQuery, res: &QueryResult) {
+        let name = self.file_name(key);
+        if self.state.lock().await.files.contains_key(&name) {
+            return;
+        }
+        let Some(buf) = encode_cache_file(res).await else {
+            return;
+        };
+
+        let path = self.dir.join(&name);
+        let mut temp_path = path.clone();
+        temp_path.set_file_name(name.clone() + ".tmp");
+        if tokio::fs::write(&temp_path, &buf).await.is_err() {
+            _ = tokio::fs::remove_file(&temp_path).await;
+            return;
+        }
+        if tokio::fs::rename(&temp_path, &path).await.is_err() {
+            return;
+        }
+
+        let evicted = {
+            let mut state = self.state.lock().await;
+            state.insert(name, u64::try_from(buf.len()).expect("u64 fit usize"));
+            state.evict(self.max_size)
+        };
+        for name in evicted {
+            _ = tokio::fs::remove_file(self.dir.join(name)).await;
+        }
+    }
+
+    async fn remove(&self, name: &str) {
+        self.state.lock().await.remove(name);
+        _ = tokio::fs::remove_file(self.dir.join(name)).await;
+    }
+}
+
+impl DiskState {
+    fn insert(&mut self, name: String, size: u64) {
+        self.age += 1;
+        self.size += size;
+        let old = self.files.insert(
+            name,
+            DiskItem {
+                age: self.age,
+                size,
+            },
+        );
+        if let Some(old) = old {
+            self.size -= old.size;
+        }
+    }
+
+    // Marks the file as recently used, returns false if it doesn't exist.
+    fn touch(&mut self, name: &str) -> bool {
+        let Some(item) = self.files.get_mut(name) else {
+            return false;
+        };
+        self.age += 1;
+        item.age = self.age;
+        true
+    }
+
+    fn remove(&mut self, name: &str) {
+        if let Some(item) = self.files.remove(name) {
+            self.size -= item.size;
+        }
+    }
+
+    // Removes the least recently used files until the
+    // size is within the limit and returns their names.
+    fn evict(&mut self, max_size: u64) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.size > max_size {
+            let Some(name) = self
+                .files
+                .iter()
+                .min_by_key(|(_, v)| v.age)
+                .map(|(name, _)| name.to_owned())
+            else {
+                break;
+            };
+            self.remove(&name);
+            evicted.push(name);
+        }
+        evicted
+    }
+}
+
+fn cache_file_name(q: &VodQuery) -> String {
+    let empty_samples = match q.empty_samples {
+        EmptySamplePolicy::Skip => "skip",
+        EmptySamplePolicy::Error => "error",
+    };
+    let fragmented = if q.fragmented { "_fragmented" } else { "" };
+    format!(
+        "{}_{}_{}_{}_{empty_samples}{fragmented}",
+        q.monitor_id, *q.start, *q.end, q.cache_id
+    )
+}
+
+fn cache_limits(config: &VodConfig) -> String {
+    format!(
+        "{}_{}_{}",
+        config.max_recordings,
+        config.max_samples.map_or(0, NonZeroUsize::get),
+        config.max_bytes.map_or(0, std::num::NonZeroU64::get),
+    )
+}
+
+// Version byte, big endian header size, json header and the meta.
+async fn encode_cache_file(res: &QueryResult) -> Option<Vec<u8>> {
+    let header = DiskCacheHeader {
+        size: res.size,
+        recs: res.recs.clone(),
+        fragments: res.fragments.clone(),
+        init_size: res.init_size,
+        last_modified: res.last_modified,
+        truncated: res.truncated,
+        mdat_mtimes: mdat_mtimes(&res.recs).await?,
+        created: SystemTime::now(),
+    };
+    let header = serde_json::to_vec(&header).ok()?;
+
+    let mut buf = Vec::with_capacity(5 + header.len() + res.meta.len());
+    buf.push(DISK_CACHE_VERSION);
+    buf.extend_from_slice(&u32::try_from(header.len()).ok()?.to_be_bytes());
+    buf.extend_from_slice(&header);
+    buf.extend_from_slice(&res.meta);
+    Some(buf)
+}
+
+// Returns None if the file is invalid, older than the
+// ttl or if any of the mdat files have changed.
+async fn read_cache_file(path: &Path, ttl: Option<Duration>) -> Option<QueryResult> {
+    let buf = tokio::fs::read(path).await.ok()?;
+    let (version, buf) = buf.split_first()?;
+    if *version != DISK_CACHE_VERSION || buf.len() < 4 {
+        return None;
+    }
+    let (header_size, buf) = buf.split_at(4);
+    let header_size = usize::try_from(u32::from_be_bytes(header_size.try_into().ok()?)).ok()?;
+    if buf.len() < header_size {
+        return None;
+    }
+    let (header, meta) = buf.split_at(header_size);
+    let header: DiskCacheHeader = serde_json::from_slice(header).ok()?;
+
+    if let Some(ttl) = ttl {
+        let age = SystemTime::now()
+            .duration_since(header.created)
+            .unwrap_or_default();
+        if age >= ttl {
+            return None;
+        }
+    }
+
+    if mdat_mtimes(&header.recs).await? != header.mdat_mtimes {
+        return None;
+    }
+
+    Some(QueryResult {
+        meta: meta.to_vec(),
+        meta_size: meta.len(),
+        size: header.size,
+        recs: header.recs,
+        fragments: header.fragments,
+        init_size: header.init_size,
+        last_modified: header.last_modified,
+        truncated: header.truncated,
+    })
+}
+
+// Modification times of the unique mdat files in order.
+async fn mdat_mtimes(recs: &[Rec]) -> Option<Vec<(PathBuf, SystemTime)>> {
+    let mut mtimes: Vec<(PathBuf, SystemTime)> = Vec::new();
+    for rec in recs {
+        if mtimes.iter().any(|(path, _)| *path == rec.mdat_path) {
+            continue;
+        }
+        let mtime = tokio::fs::metadata(&rec.mdat_path)
+            .await
+            .ok()?
+            .modified()
+            .ok()?;
+        mtimes.push((rec.mdat_path.clone(), mtime));
+    }
+    Some(mtimes)
+}
+
 #[cfg(test)]
 #[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
-    use common::time::UnixNano;
 
     fn key(v: u32) -> VodQuery {
         VodQuery {
@@ -105,6 +526,9 @@ mod tests {
             start: UnixNano::new(0),
             end: UnixNano::new(0),
             cache_id: v,
+            empty_samples: crate::EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         }
     }
 
@@ -114,12 +538,16 @@ mod tests {
             meta_size: 0,
             size: 0,
             recs: Vec::new(),
+            fragments: Vec::new(),
+            init_size: 0,
+            last_modified: None,
+            truncated: false,
         })
     }
 
     #[tokio::test]
     async fn test_video_reader_cache() {
-        let cache = VodCache::with_capacity(3);
+        let cache = VodCache::with_capacity(3, None);
 
         // Fill cache.
         cache.add(key(1), empty()).await;
@@ -139,6 +567,10 @@ mod tests {
             meta_size: 0,
             size: 100,
             recs: Vec::new(),
+            fragments: Vec::new(),
+            init_size: 0,
+            last_modified: None,
+            truncated: false,
         });
         cache.add(key(5), e.clone()).await;
         assert!(cache.get(&key(3)).await.is_none());
@@ -156,4 +588,257 @@ mod tests {
         let e2 = cache.get(&key(5)).await.unwrap();
         assert_eq!(e, e2);
     }
+
+    #[test]
+    fn test_memory_cache_max_entries() {
+        let cache =
+            VodCache::with_config(NonZeroUsize::new(2).unwrap(), Some(Duration::from_secs(60)));
+        let mut state = cache.memory.try_lock().unwrap();
+        let now = Instant::now();
+
+        let e = empty();
+        state.add(key(1), e.clone(), now);
+        state.add(key(2), empty(), now);
+        state.add(key(3), empty(), now);
+        assert_eq!(2, state.items.len());
+        assert!(state.get(&key(1), now).is_none());
+
+        // The evicted result is dropped.
+        assert_eq!(1, Arc::strong_count(&e));
+    }
+
+    #[test]
+    fn test_memory_cache_ttl() {
+        let ttl = Duration::from_secs(60);
+        let cache = VodCache::with_config(NonZeroUsize::new(10).unwrap(), Some(ttl));
+        let mut state = cache.memory.try_lock().unwrap();
+        let now = Instant::now();
+
+        let e = empty();
+        state.add(key(1), e.clone(), now);
+        state.add(key(2), empty(), now + Duration::from_secs(30));
+        assert!(state.get(&key(1), now + Duration::from_secs(59)).is_some());
+
+        // Getting an item doesn't extend its lifetime.
+        assert!(state.get(&key(1), now + ttl).is_none());
+        assert!(state.contains(&key(2), now + ttl));
+        assert!(!state.contains(&key(2), now + ttl + Duration::from_secs(30)));
+        assert!(state.items.is_empty());
+        assert_eq!(1, Arc::strong_count(&e));
+    }
+
+    #[test]
+    fn test_memory_cache_ttl_readded() {
+        let ttl = Duration::from_secs(60);
+        let cache = VodCache::with_config(NonZeroUsize::new(1).unwrap(), Some(ttl));
+        let mut state = cache.memory.try_lock().unwrap();
+        let now = Instant::now();
+
+        // Evict 1 and add it again.
+        state.add(key(1), empty(), now);
+        state.add(key(2), empty(), now);
+        state.add(key(1), empty(), now + Duration::from_secs(30));
+
+        // The first entry of 1 shouldn't remove the second.
+        assert!(state.get(&key(1), now + ttl).is_some());
+        assert!(state
+            .get(&key(1), now + ttl + Duration::from_secs(30))
+            .is_none());
+        assert!(state.added.is_empty());
+    }
+
+    fn disk_result(mdat_path: PathBuf) -> Arc<QueryResult> {
+        Arc::new(QueryResult {
+            meta: vec![1, 2, 3, 4],
+            meta_size: 4,
+            size: 10,
+            recs: vec![Rec {
+                mdat_path,
+                key_path: None,
+                data_start: 4,
+                size: 6,
+                start: 0,
+                end: 6,
+            }],
+            fragments: Vec::new(),
+            init_size: 0,
+            last_modified: Some(UnixNano::new(5)),
+            truncated: false,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_restart() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mdat_path = temp_dir.path().join("x.mdat");
+        std::fs::write(&mdat_path, [0; 6]).unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+        let want = disk_result(mdat_path);
+
+        let cache = VodCache::new()
+            .with_disk(cache_dir.clone(), ByteSize::mb(1), &VodConfig::default())
+            .await
+            .unwrap();
+        cache.add(key(1), want.clone()).await;
+        drop(cache);
+
+        // New instance with an empty memory cache.
+        let cache = VodCache::new()
+            .with_disk(cache_dir, ByteSize::mb(1), &VodConfig::default())
+            .await
+            .unwrap();
+        let got = cache.get(&key(1)).await.unwrap();
+        assert_eq!(want, got);
+        assert!(cache.get(&key(2)).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_mdat_modified() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mdat_path = temp_dir.path().join("x.mdat");
+        std::fs::write(&mdat_path, [0; 6]).unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+
+        let cache = VodCache::new()
+            .with_disk(cache_dir.clone(), ByteSize::mb(1), &VodConfig::default())
+            .await
+            .unwrap();
+        cache.add(key(1), disk_result(mdat_path.clone())).await;
+        drop(cache);
+
+        let file = std::fs::File::options()
+            .write(true)
+            .open(&mdat_path)
+            .unwrap();
+        let mtime = file.metadata().unwrap().modified().unwrap();
+        file.set_modified(mtime + Duration::from_secs(1)).unwrap();
+
+        let cache = VodCache::new()
+            .with_disk(cache_dir.clone(), ByteSize::mb(1), &VodConfig::default())
+            .await
+            .unwrap();
+        assert!(cache.get(&key(1)).await.is_none());
+
+        // The outdated file should be removed.
+        assert_eq!(0, std::fs::read_dir(cache_dir).unwrap().count());
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_ttl() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mdat_path = temp_dir.path().join("x.mdat");
+        std::fs::write(&mdat_path, [0; 6]).unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+        let ttl = Some(Duration::from_secs(60));
+        let config = VodConfig::default();
+        let new_cache = |ttl| {
+            VodCache::with_config(NonZeroUsize::new(10).unwrap(), ttl).with_disk(
+                cache_dir.clone(),
+                ByteSize::mb(1),
+                &config,
+            )
+        };
+
+        let cache = new_cache(ttl).await.unwrap();
+        cache.add(key(1), disk_result(mdat_path)).await;
+        drop(cache);
+
+        let cache = new_cache(ttl).await.unwrap();
+        assert!(cache.get(&key(1)).await.is_some());
+        drop(cache);
+
+        // Expired.
+        let cache = new_cache(Some(Duration::ZERO)).await.unwrap();
+        assert!(cache.get(&key(1)).await.is_none());
+        assert_eq!(0, std::fs::read_dir(&cache_dir).unwrap().count());
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_hit_sets_mtime() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mdat_path = temp_dir.path().join("x.mdat");
+        std::fs::write(&mdat_path, [0; 6]).unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+
+        let cache = VodCache::new()
+            .with_disk(cache_dir.clone(), ByteSize::mb(1), &VodConfig::default())
+            .await
+            .unwrap();
+        cache.add(key(1), disk_result(mdat_path)).await;
+
+        let disk = cache.disk.as_ref().unwrap();
+        let path = cache_dir.join(disk.file_name(&key(1)));
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        disk.get(&key(1)).await.unwrap();
+        let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+        assert!(mtime > old);
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_limits_changed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mdat_path = temp_dir.path().join("x.mdat");
+        std::fs::write(&mdat_path, [0; 6]).unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+
+        let cache = VodCache::new()
+            .with_disk(cache_dir.clone(), ByteSize::mb(1), &VodConfig::default())
+            .await
+            .unwrap();
+        cache.add(key(1), disk_result(mdat_path)).await;
+        drop(cache);
+
+        // The result may have been truncated differently.
+        let config = VodConfig {
+            max_samples: NonZeroUsize::new(10),
+            ..VodConfig::default()
+        };
+        let cache = VodCache::new()
+            .with_disk(cache_dir, ByteSize::mb(1), &config)
+            .await
+            .unwrap();
+        assert!(cache.get(&key(1)).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_eviction() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mdat_path = temp_dir.path().join("x.mdat");
+        std::fs::write(&mdat_path, [0; 6]).unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+
+        let file_size = encode_cache_file(&disk_result(mdat_path.clone()))
+            .await
+            .unwrap()
+            .len();
+        let max_size = ByteSize::b(u64::try_from(file_size * 2).unwrap());
+
+        let cache = VodCache::new()
+            .with_disk(cache_dir.clone(), max_size, &VodConfig::default())
+            .await
+            .unwrap();
+        cache.add(key(1), disk_result(mdat_path.clone())).await;
+        cache.add(key(2), disk_result(mdat_path.clone())).await;
+
+        // Use 1 to make 2 the oldest item.
+        let disk = cache.disk.as_ref().unwrap();
+        disk.get(&key(1)).await.unwrap();
+        cache.add(key(3), disk_result(mdat_path)).await;
+        drop(cache);
+
+        let cache = VodCache::new()
+            .with_disk(cache_dir, max_size, &VodConfig::default())
+            .await
+            .unwrap();
+        assert!(cache.get(&key(1)).await.is_some());
+        assert!(cache.get(&key(2)).await.is_none());
+        assert!(cache.get(&key(3)).await.is_some());
+    }
 }
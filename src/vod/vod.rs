@@ -2,28 +2,36 @@
 
 mod cache;
 
-pub use cache::VodCache;
+pub use cache::{NewDiskCacheError, VodCache};
 use common::{
     recording::{RecordingId, RecordingIdError},
-    time::{Duration, UnixH264, UnixNano, HOUR},
-    MonitorId,
+    time::{Duration, UnixH264, UnixNano, MILLISECOND, SECOND},
+    ArcTranscoder, Event, MasterKey, MonitorId, TranscodeError,
 };
+use csv::deserialize_csv_option;
 use pin_project::pin_project;
-use recdb::{CrawlerError, RecDb, RecDbQuery, RecordingResponse};
-use recording::{generate_mp4, read_meta, GenerateMp4Error, ReadMetaError, Sample};
-use serde::Deserialize;
+use recdb::{read_recording_key, CrawlerError, RecDb, RecDbQuery, RecordingResponse};
+use recording::{
+    generate_fragments, generate_init, generate_sidx, index_lookup, read_audio_meta, read_index,
+    AudioParameters, AudioTrack, CreateMetaReaderError, GenerateMp4Error, IndexEntry, MdatFile,
+    MetaHeader, MetaReader, Mp4Header, ReadMetaError, Sample, Timescale, TrackParameters,
+};
+use serde::{Deserialize, Serialize};
 use std::{
+    fmt::Write,
     future::Future,
     io::SeekFrom,
-    num::NonZeroUsize,
+    num::{NonZeroU16, NonZeroU64, NonZeroUsize},
     path::PathBuf,
     pin::{pin, Pin},
     sync::Arc,
     task::{Context, Poll},
+    time::{SystemTime, UNIX_EPOCH},
 };
 use thiserror::Error;
 use tokio::{
     io::{AsyncRead, AsyncSeek, BufReader},
+    sync::{Semaphore, SemaphorePermit},
     task::JoinHandle,
 };
 
@@ -36,6 +44,109 @@ pub struct VodQuery {
 
     #[serde(rename = "cache-id")]
     cache_id: u32,
+
+    #[serde(rename = "empty-samples", default)]
+    pub empty_samples: EmptySamplePolicy,
+
+    // Respond with a WebVTT sidecar of the events instead of the video.
+    #[serde(default)]
+    pub events: bool,
+
+    // Fragmented mp4 for DASH, a init segment followed
+    // by a moof and mdat pair for every IDR.
+    #[serde(default)]
+    pub fragmented: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct VodConfig {
+    // Longest allowed query, the whole meta is kept in memory.
+    pub max_duration: std::time::Duration,
+
+    // Most recordings read by a single query.
+    pub max_recordings: NonZeroUsize,
+
+    // The video is truncated once the samples reach either budget.
+    pub max_samples: Option<NonZeroUsize>,
+    pub max_bytes: Option<NonZeroU64>,
+}
+
+impl Default for VodConfig {
+    fn default() -> Self {
+        Self {
+            max_duration: std::time::Duration::from_secs(12 * 60 * 60),
+            max_recordings: NonZeroUsize::new(100_000).expect("nonzero"),
+            max_samples: None,
+            max_bytes: None,
+        }
+    }
+}
+
+impl VodQuery {
+    // The output is deterministic for a given query, the cache ID
+    // changes when the client wants a fresh copy.
+    #[must_use]
+    pub fn etag(&self) -> String {
+        let suffix = if self.fragmented { "-fragmented" } else { "" };
+        format!(
+            "W/\"{}-{}-{}-{}{suffix}\"",
+            self.monitor_id, *self.start, *self.end, self.cache_id
+        )
+    }
+}
+
+// Maximum number of monitors in a `MultiVodQuery`.
+pub const MAX_MULTI_VOD_MONITORS: usize = 4;
+
+// Recordings from several monitors muxed into a single mp4 with a
+// video track per monitor, every track is aligned to `start`.
+#[derive(Clone, Deserialize, Hash, PartialEq, Eq)]
+pub struct MultiVodQuery {
+    #[serde(rename = "monitor-ids", deserialize_with = "deserialize_csv_option")]
+    pub monitor_ids: Vec<MonitorId>,
+    pub start: UnixNano,
+    pub end: UnixNano,
+
+    #[serde(rename = "cache-id")]
+    cache_id: u32,
+}
+
+impl MultiVodQuery {
+    #[must_use]
+    pub fn etag(&self) -> String {
+        let monitor_ids: Vec<String> = self.monitor_ids.iter().map(ToString::to_string).collect();
+        format!(
+            "W/\"{}-{}-{}-{}\"",
+            monitor_ids.join(","),
+            *self.start,
+            *self.end,
+            self.cache_id
+        )
+    }
+
+    fn monitor_query(&self, monitor_id: &MonitorId) -> VodQuery {
+        VodQuery {
+            monitor_id: monitor_id.clone(),
+            start: self.start,
+            end: self.end,
+            cache_id: self.cache_id,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
+        }
+    }
+}
+
+// How to handle samples without any data, some players can't play them.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Hash, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmptySamplePolicy {
+    // Drop the sample, the previous sample is extended to fill the gap.
+    #[default]
+    Skip,
+
+    // Fail the query with `EmptySampleData`.
+    Error,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -44,8 +155,55 @@ struct QueryResult {
     meta_size: usize,
     size: usize,
     recs: Vec<Rec>,
+
+    // Only used in fragmented mode, ordered by position.
+    fragments: Vec<FragmentHeader>,
+
+    // Only used in fragmented mode, size of the ftyp
+    // and moov boxes at the start of the meta.
+    init_size: usize,
+
+    // End time of the last recording.
+    last_modified: Option<UnixNano>,
+
+    // The query reached a budget in `VodConfig` and the video
+    // ends before the end of the query.
+    truncated: bool,
+}
+
+impl QueryResult {
+    // Returns the rest of the fragment header at the position.
+    fn fragment_header_at(&self, pos: usize) -> Option<&[u8]> {
+        let i = self.fragments.partition_point(|v| v.start <= pos);
+        let fragment = &self.fragments[i.checked_sub(1)?];
+        fragment
+            .header
+            .get(pos - fragment.start..)
+            .filter(|v| !v.is_empty())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct FragmentHeader {
+    // Position of the moof box.
+    start: usize,
+
+    // Presentation time of the first sample.
+    time: UnixNano,
+
+    // Sum of the sample durations.
+    duration: Duration,
+
+    // Moof box and mdat header.
+    header: Vec<u8>,
 }
 
+// Maximum number of mdat files that can be open at
+// the same time across all readers, to avoid running
+// out of file descriptors. Readers wait for a free slot.
+const MAX_OPEN_MDAT_FILES: usize = 256;
+static OPEN_MDAT_FILES: Semaphore = Semaphore::const_new(MAX_OPEN_MDAT_FILES);
+
 #[pin_project]
 #[derive(Debug)]
 pub struct VodReader {
@@ -54,6 +212,9 @@ pub struct VodReader {
     #[pin]
     file_state: FileState,
     pos: usize,
+
+    open_files: &'static Semaphore,
+    master_key: Option<MasterKey>,
 }
 
 #[derive(Debug, Error)]
@@ -61,8 +222,11 @@ pub enum CreateVodReaderError {
     #[error("duration is negative")]
     NegativeDuration,
 
-    #[error("max duration is 12 hours, it's easy to extend if anyone wants it")]
-    MaxDuration,
+    #[error("duration {requested:?} exceeds the max duration {max:?}")]
+    MaxDuration {
+        requested: std::time::Duration,
+        max: std::time::Duration,
+    },
 
     #[error("query recordings: {0}")]
     QueryRecordings(#[from] CrawlerError),
@@ -88,60 +252,319 @@ pub enum CreateVodReaderError {
     #[error("read meta: {0}")]
     ReadMeta(#[from] ReadMetaError),
 
+    #[error("create meta reader: {0}")]
+    CreateMetaReader(#[from] CreateMetaReaderError),
+
     #[error("dts")]
     Dts,
 
     #[error("end")]
     End,
 
+    #[error("last sample at {0:?} can't end at {1:?}")]
+    LastSampleEnd(UnixH264, UnixH264),
+
     #[error("generate mp4: {0}")]
     GenerateMp4(#[from] GenerateMp4Error),
+
+    #[error("sample {0} has no data")]
+    EmptySampleData(usize),
+
+    #[error("no monitors")]
+    NoMonitors,
+
+    #[error("{requested} monitors exceeds the max of {max}")]
+    TooManyMonitors { requested: usize, max: usize },
 }
 
 impl VodReader {
     pub async fn new(
         recdb: &RecDb,
         cache: &VodCache,
+        config: &VodConfig,
         q: VodQuery,
     ) -> Result<Option<Self>, CreateVodReaderError> {
+        // Checked before the cache since the limit may have changed.
+        check_duration(config, &q)?;
         let r = {
             if let Some(r) = cache.get(&q).await {
                 r
             } else {
-                let Some(r) = execute_query(recdb, &q).await? else {
+                let Some(r) = execute_query(recdb, config, &q).await? else {
                     return Ok(None);
                 };
                 cache.add(q, r.clone()).await;
                 r
             }
         };
+        Ok(Some(Self::from_result(r, recdb.master_key().cloned())))
+    }
 
-        Ok(Some(Self {
+    // Multi monitor queries aren't cached. Also returns the monitor of
+    // each track in track order, monitors without samples have no track.
+    pub async fn new_multi(
+        recdb: &RecDb,
+        config: &VodConfig,
+        q: &MultiVodQuery,
+    ) -> Result<Option<(Self, Vec<MonitorId>)>, CreateVodReaderError> {
+        let Some((r, track_monitors)) = execute_multi_query(recdb, config, q).await? else {
+            return Ok(None);
+        };
+        let reader = Self::from_result(r, recdb.master_key().cloned());
+        Ok(Some((reader, track_monitors)))
+    }
+
+    fn from_result(r: Arc<QueryResult>, master_key: Option<MasterKey>) -> Self {
+        Self {
             r,
             file_state: FileState::Close(CloseReadState::State1),
             pos: 0,
-        }))
+            open_files: &OPEN_MDAT_FILES,
+            master_key,
+        }
     }
 
     pub fn size(&self) -> u64 {
         u64::try_from(self.r.size).expect("u64 fit usize")
     }
+
+    // Returns the position of the fragment that contains the time.
+    // Times before the first fragment snap to the first fragment.
+    // Returns None if the reader isn't fragmented.
+    pub fn fragment_offset(&self, time: UnixNano) -> Option<u64> {
+        let fragments = &self.r.fragments;
+        let i = fragments.partition_point(|v| v.time <= time);
+        let fragment = fragments.get(i.saturating_sub(1))?;
+        Some(u64::try_from(fragment.start).expect("u64 fit usize"))
+    }
+
+    pub fn last_modified(&self) -> Option<SystemTime> {
+        let nanos = u64::try_from(*self.r.last_modified?).ok()?;
+        Some(UNIX_EPOCH + std::time::Duration::from_nanos(nanos))
+    }
+
+    // Returns true if the video ends early because the query was too large.
+    pub fn truncated(&self) -> bool {
+        self.r.truncated
+    }
+
+    // Returns the ftyp and moov boxes, None if the reader isn't fragmented.
+    pub fn hls_init(&self) -> Option<&[u8]> {
+        if self.r.fragments.is_empty() {
+            return None;
+        }
+        Some(&self.r.meta[..self.r.init_size])
+    }
+
+    // Every fragment is a segment, segments start with a IDR.
+    // Returns None if the reader isn't fragmented.
+    pub fn hls_segments(&self) -> Option<Vec<HlsSegment>> {
+        let fragments = &self.r.fragments;
+        if fragments.is_empty() {
+            return None;
+        }
+        let ends = fragments
+            .iter()
+            .skip(1)
+            .map(|v| v.start)
+            .chain([self.r.size]);
+        Some(
+            fragments
+                .iter()
+                .zip(ends)
+                .map(|(fragment, end)| HlsSegment {
+                    offset: u64::try_from(fragment.start).expect("u64 fit usize"),
+                    size: u64::try_from(end - fragment.start).expect("u64 fit usize"),
+                    duration: fragment.duration,
+                })
+                .collect(),
+        )
+    }
+
+    // Returns a HLS media playlist with a segment for every fragment,
+    // `segment_uri` returns the URI of the segment at the index.
+    // Returns None if the reader isn't fragmented.
+    pub fn hls_playlist<F>(&self, init_uri: &str, segment_uri: F) -> Option<String>
+    where
+        F: Fn(usize) -> String,
+    {
+        let segments = self.hls_segments()?;
+        let target_duration = segments
+            .iter()
+            .map(|v| (*v.duration + SECOND / 2) / SECOND)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+
+        let mut out = format!(
+            "#EXTM3U\n\
+            #EXT-X-VERSION:7\n\
+            #EXT-X-TARGETDURATION:{target_duration}\n\
+            #EXT-X-MEDIA-SEQUENCE:0\n\
+            #EXT-X-PLAYLIST-TYPE:VOD\n\
+            #EXT-X-INDEPENDENT-SEGMENTS\n\
+            #EXT-X-MAP:URI=\"{init_uri}\"\n"
+        );
+        for (i, segment) in segments.iter().enumerate() {
+            let millis = *segment.duration / MILLISECOND;
+            _ = writeln!(
+                out,
+                "#EXTINF:{}.{:03},\n{}",
+                millis / 1000,
+                millis % 1000,
+                segment_uri(i)
+            );
+        }
+        out.push_str("#EXT-X-ENDLIST\n");
+        Some(out)
+    }
 }
 
-#[allow(clippy::too_many_lines)]
-async fn execute_query(
+// A moof and mdat pair of a fragmented reader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HlsSegment {
+    pub offset: u64,
+    pub size: u64,
+    pub duration: Duration,
+}
+
+async fn read_samples(
+    meta_path: PathBuf,
+    index_path: Option<PathBuf>,
+    start: UnixNano,
+    end: UnixNano,
+) -> Result<(MetaHeader, Vec<Sample>), CreateVodReaderError> {
+    use CreateVodReaderError::*;
+    let meta_size = tokio::fs::metadata(&meta_path)
+        .await
+        .map_err(Metadata)?
+        .len();
+
+    let meta = BufReader::new(
+        tokio::fs::OpenOptions::new()
+            .read(true)
+            .open(meta_path)
+            .await
+            .map_err(OpenFile)?,
+    );
+
+    let index = match index_path {
+        Some(path) => read_index_file(path).await,
+        None => None,
+    };
+    read_samples_between(meta, meta_size, index.as_deref(), start, end).await
+}
+
+// Reads the samples of a single recording that may be inside the window.
+// Reading starts at the keyframe preceding `start` if the recording has a
+// index, otherwise the first sample at or after `start` is found using a
+// binary search. Reading stops at the first sample after `end`.
+async fn read_samples_between<T>(
+    meta: T,
+    meta_size: u64,
+    index: Option<&[IndexEntry]>,
+    start: UnixNano,
+    end: UnixNano,
+) -> Result<(MetaHeader, Vec<Sample>), CreateVodReaderError>
+where
+    T: AsyncRead + AsyncSeek + Unpin,
+{
+    let dts = |s: &Sample| s.dts().map(UnixNano::from);
+
+    let (mut reader, header) = MetaReader::new(meta, meta_size).await?;
+    let first_sample = match index.and_then(|index| index_lookup(index, UnixH264::from(start))) {
+        Some(v) => usize::try_from(v).expect("usize fit u32"),
+        None => {
+            reader
+                .partition_point(|s| dts(s).is_some_and(|dts| dts < start))
+                .await?
+        }
+    };
+    let samples = reader
+        .read_samples_while(first_sample, |s| !dts(s).is_some_and(|dts| dts > end))
+        .await?;
+    Ok((header, samples))
+}
+
+// Returns the audio samples of a recording that are inside the window and
+// after `last_dts`, the audio files are optional and are read from the start.
+async fn read_audio_samples(
     recdb: &RecDb,
-    q: &VodQuery,
-) -> Result<Option<Arc<QueryResult>>, CreateVodReaderError> {
+    rec_id: &RecordingId,
+    start: UnixH264,
+    end: UnixH264,
+    last_dts: Option<UnixH264>,
+) -> Result<Option<RecPartWithSamples>, CreateVodReaderError> {
     use CreateVodReaderError::*;
+    let Some(meta_path) = recdb.recording_file_by_ext(rec_id, "ameta").await else {
+        return Ok(None);
+    };
+    let Some(mdat_path) = recdb.recording_file_by_ext(rec_id, "amdat").await else {
+        return Ok(None);
+    };
+    let meta_size = tokio::fs::metadata(&meta_path)
+        .await
+        .map_err(Metadata)?
+        .len();
+    let meta = BufReader::new(tokio::fs::File::open(meta_path).await.map_err(OpenFile)?);
+
+    let samples: Vec<_> = read_audio_meta(meta, meta_size)
+        .await?
+        .into_iter()
+        .filter(|v| start <= v.pts && v.end().is_some_and(|end2| end2 <= end))
+        .skip_while(|v| last_dts.is_some_and(|last| v.pts <= last))
+        .collect();
+
+    let Some(first) = samples.first() else {
+        return Ok(None);
+    };
+    Ok(Some(RecPartWithSamples {
+        rec: Rec {
+            mdat_path,
+            key_path: None,
+            data_start: usize::try_from(first.data_offset).expect("usize fit u32"),
+            size: samples
+                .iter()
+                .map(|v| usize::try_from(v.data_size).expect("u32 fit usize"))
+                .sum(),
+            start: 0,
+            end: 0,
+        },
+        samples,
+    }))
+}
 
-    let duration = q.end - q.start;
-    if duration.is_negative() {
+// A missing or broken index isn't an error, the meta file is read instead.
+async fn read_index_file(path: PathBuf) -> Option<Vec<IndexEntry>> {
+    let size = tokio::fs::metadata(&path).await.ok()?.len();
+    let file = BufReader::new(tokio::fs::File::open(path).await.ok()?);
+    read_index(file, size).await.ok()
+}
+
+fn check_duration(config: &VodConfig, q: &VodQuery) -> Result<(), CreateVodReaderError> {
+    use CreateVodReaderError::*;
+    let Some(duration) = Duration::from(q.end - q.start).as_std() else {
         return Err(NegativeDuration);
+    };
+    if duration > config.max_duration {
+        return Err(MaxDuration {
+            requested: duration,
+            max: config.max_duration,
+        });
     }
-    if duration > UnixNano::new(HOUR * 12) {
-        return Err(MaxDuration);
-    }
+    Ok(())
+}
+
+// Returns the recordings that overlap the query window and
+// true if there are more than `max_recordings` recordings.
+async fn query_recordings(
+    recdb: &RecDb,
+    config: &VodConfig,
+    q: &VodQuery,
+    include_data: bool,
+) -> Result<(Vec<RecordingResponse>, bool), CreateVodReaderError> {
+    use CreateVodReaderError::*;
+    check_duration(config, q)?;
 
     // Find first recording by seeking backwards.
     let end_minus_1 = q
@@ -155,7 +578,7 @@ async fn execute_query(
             limit: NonZeroUsize::new(1).expect("nonzero"),
             reverse: false,
             monitors: vec![q.monitor_id.to_string()],
-            include_data: false,
+            include_data,
         })
         .await?;
 
@@ -169,25 +592,183 @@ async fn execute_query(
         .end
         .checked_add(Duration::from_secs(1).into())
         .ok_or(Add)?;
-    recordings.extend(
-        recdb
-            .recordings_by_query(&RecDbQuery {
-                recording_id: first_rec_id.clone(),
-                end: Some(RecordingId::from_nanos(end_plus_1, &q.monitor_id)?),
-                limit: NonZeroUsize::new(100_000).expect("nonzero"),
-                reverse: true,
-                monitors: vec![q.monitor_id.to_string()],
-                include_data: false,
-            })
-            .await?,
-    );
+    let more_recordings = recdb
+        .recordings_by_query(&RecDbQuery {
+            recording_id: first_rec_id.clone(),
+            end: Some(RecordingId::from_nanos(end_plus_1, &q.monitor_id)?),
+            limit: config.max_recordings,
+            reverse: true,
+            monitors: vec![q.monitor_id.to_string()],
+            include_data,
+        })
+        .await?;
+    let truncated = more_recordings.len() >= config.max_recordings.get();
+    recordings.extend(more_recordings);
+    Ok((recordings, truncated))
+}
 
-    if recordings.is_empty() {
+async fn execute_query(
+    recdb: &RecDb,
+    config: &VodConfig,
+    q: &VodQuery,
+) -> Result<Option<Arc<QueryResult>>, CreateVodReaderError> {
+    let (mut recs, params, truncated, audio) = query_samples(recdb, config, q).await?;
+
+    let last_modified = last_sample_end(&recs);
+    let end = video_end(&recs, truncated, q.end);
+    if !align_samples(&mut recs, q.start, end)? {
         return Ok(None);
     }
 
+    // Fragmented videos are video only.
+    let audio = match audio {
+        Some((mut audio_recs, audio_params)) if !q.fragmented => {
+            align_samples(&mut audio_recs, q.start, end)?.then_some((audio_recs, audio_params))
+        }
+        _ => None,
+    };
+
+    let params = params.expect("should be Some");
+    let mut r = if q.fragmented {
+        fragmented_query_result(q.start, &recs, &params)?
+    } else {
+        query_result(q.start, vec![(recs, params)], audio).await?
+    };
+    r.last_modified = last_modified;
+    r.truncated = truncated;
+    Ok(Some(Arc::new(r)))
+}
+
+async fn execute_multi_query(
+    recdb: &RecDb,
+    config: &VodConfig,
+    q: &MultiVodQuery,
+) -> Result<Option<(Arc<QueryResult>, Vec<MonitorId>)>, CreateVodReaderError> {
+    use CreateVodReaderError::*;
+    if q.monitor_ids.is_empty() {
+        return Err(NoMonitors);
+    }
+    if q.monitor_ids.len() > MAX_MULTI_VOD_MONITORS {
+        return Err(TooManyMonitors {
+            requested: q.monitor_ids.len(),
+            max: MAX_MULTI_VOD_MONITORS,
+        });
+    }
+
+    let mut tracks = Vec::new();
+    let mut track_monitors = Vec::new();
+    let mut last_modified = None;
+    let mut truncated = false;
+    for monitor_id in &q.monitor_ids {
+        // The audio is dropped, there is only room for a single audio track.
+        let (mut recs, params, track_truncated, _) =
+            query_samples(recdb, config, &q.monitor_query(monitor_id)).await?;
+
+        let rec_last_modified = last_sample_end(&recs);
+        let end = video_end(&recs, track_truncated, q.end);
+        // Monitors without any samples in the window don't get a track.
+        if !align_samples(&mut recs, q.start, end)? {
+            continue;
+        }
+        last_modified = last_modified.max(rec_last_modified);
+        truncated |= track_truncated;
+        tracks.push((recs, params.expect("should be Some")));
+        track_monitors.push(monitor_id.clone());
+    }
+    if tracks.is_empty() {
+        return Ok(None);
+    }
+
+    let mut r = query_result(q.start, tracks, None).await?;
+    r.last_modified = last_modified;
+    r.truncated = truncated;
+    Ok(Some((Arc::new(r), track_monitors)))
+}
+
+// End time of the last recording.
+fn last_sample_end(recs: &[RecPartWithSamples]) -> Option<UnixNano> {
+    recs.last()
+        .and_then(|v| v.samples.last())
+        .and_then(Sample::end)
+        .map(UnixNano::from)
+}
+
+// A truncated video ends after the last sample instead of at the end of the query.
+fn video_end(recs: &[RecPartWithSamples], truncated: bool, end: UnixNano) -> UnixH264 {
+    let last_end = recs
+        .last()
+        .and_then(|v| v.samples.last())
+        .and_then(Sample::end);
+    match last_end {
+        Some(last_end) if truncated => last_end,
+        _ => end.into(),
+    }
+}
+
+// Shifts the first sample to the start time and pads the sample durations
+// to fill any gaps, the last sample is extended to the end time.
+// Returns false if there are no samples.
+fn align_samples(
+    recs: &mut [RecPartWithSamples],
+    start: UnixNano,
+    end: UnixH264,
+) -> Result<bool, CreateVodReaderError> {
+    use CreateVodReaderError::*;
+    let mut samples: Vec<_> = recs.iter_mut().flat_map(|v| &mut v.samples).collect();
+
+    // Shift first sample to start time.
+    let Some(first) = samples.first_mut() else {
+        return Ok(false);
+    };
+    first.pts = start.into();
+
+    // Pad durations to fill any gaps.
+    for i in 1..samples.len() {
+        let s0_dts = samples[i - 1].dts().ok_or(Dts)?;
+        let s1_dts = samples[i].dts().ok_or(Dts)?;
+        let diff = s1_dts - s0_dts;
+        samples[i - 1].duration = diff.into();
+    }
+    let last = samples.last_mut().expect("should exist");
+    last.duration = (end - last.pts).into();
+    if *last.duration < 0 || last.end().ok_or(End)? != end {
+        return Err(LastSampleEnd(last.pts, end));
+    }
+    Ok(true)
+}
+
+type AudioRecs = (Vec<RecPartWithSamples>, AudioParameters);
+
+type QuerySamplesResult = (
+    Vec<RecPartWithSamples>,
+    Option<TrackParameters>,
+    bool,
+    Option<AudioRecs>,
+);
+
+// Returns the samples of every recording in the query window. Recordings
+// may overlap, for example after a restart, samples that don't have a
+// greater DTS than the previous recording are dropped. Reading stops once
+// the samples reach the budget in the config and true is returned.
+// The audio samples are limited to the video of the same recording,
+// recordings with different audio parameters than the first are skipped.
+async fn query_samples(
+    recdb: &RecDb,
+    config: &VodConfig,
+    q: &VodQuery,
+) -> Result<QuerySamplesResult, CreateVodReaderError> {
+    use CreateVodReaderError::*;
+
+    let (recordings, mut truncated) = query_recordings(recdb, config, q, false).await?;
+
     let mut recs = Vec::new();
     let mut params = None;
+    let mut n_samples = 0;
+    let mut n_bytes = 0;
+    let mut last_dts: Option<UnixH264> = None;
+    let mut audio_recs = Vec::new();
+    let mut audio_params: Option<AudioParameters> = None;
+    let mut last_audio_dts: Option<UnixH264> = None;
 
     for rec in &recordings {
         let RecordingResponse::Finalized(rec) = rec else {
@@ -201,23 +782,15 @@ async fn execute_query(
             continue;
         };
 
-        let meta_size = tokio::fs::metadata(&meta_path)
-            .await
-            .map_err(Metadata)?
-            .len();
-
-        let mut meta = BufReader::new(
-            tokio::fs::OpenOptions::new()
-                .read(true)
-                .open(meta_path)
-                .await
-                .map_err(OpenFile)?,
-        );
-
-        let (header, samples) = read_meta(&mut meta, meta_size).await?;
+        let index_path = recdb.recording_file_by_ext(&rec.id, "idx").await;
+        let key_path = recdb.recording_file_by_ext(&rec.id, "key").await;
+        let (header, samples) = read_samples(meta_path, index_path, q.start, q.end).await?;
         params = Some(header.params());
+        let header_audio = header
+            .audio
+            .filter(|v| !audio_params.as_ref().is_some_and(|params| params != v));
 
-        let samples: Vec<_> = samples
+        let mut samples: Vec<_> = samples
             .into_iter()
             .map(|s| {
                 let dts = UnixNano::from(s.dts().ok_or(Dts)?);
@@ -228,10 +801,23 @@ async fn execute_query(
             .collect::<Result<Vec<(Sample, bool)>, CreateVodReaderError>>()?
             .into_iter()
             .filter_map(|(v, keep)| keep.then_some(v))
+            .filter(|v| v.data_size != 0 || q.empty_samples == EmptySamplePolicy::Error)
+            // Skip samples that overlap the previous recording.
+            .skip_while(|v| last_dts.is_some_and(|last| v.dts().is_some_and(|dts| dts <= last)))
             // Skip until first IDR.
             .skip_while(|v| !v.random_access_present)
             .collect();
 
+        if let Some(i) = samples.iter().position(|v| v.data_size == 0) {
+            return Err(EmptySampleData(n_samples + i));
+        }
+        if let Some(i) = budget_exceeded_at(config, &samples, n_samples, n_bytes) {
+            samples.truncate(i);
+            truncated = true;
+        }
+        n_samples += samples.len();
+        n_bytes += samples.iter().map(|v| u64::from(v.data_size)).sum::<u64>();
+
         if let Some(first) = samples.first() {
             let data_start = usize::try_from(first.data_offset).expect("usize fit u32");
             let data_size: usize = samples
@@ -242,6 +828,7 @@ async fn execute_query(
             recs.push(RecPartWithSamples {
                 rec: Rec {
                     mdat_path: mdat_path.clone(),
+                    key_path,
                     data_start,
                     size: data_size,
                     start: 0,
@@ -250,55 +837,278 @@ async fn execute_query(
                 samples: samples.clone(),
             });
         }
+        if let (Some(first), Some(last), Some(header_audio)) =
+            (samples.first(), samples.last(), header_audio)
+        {
+            let start = first.dts().ok_or(Dts)?;
+            let end = last.end().ok_or(End)?;
+            if let Some(audio_rec) =
+                read_audio_samples(recdb, &rec.id, start, end, last_audio_dts).await?
+            {
+                last_audio_dts = audio_rec.samples.last().map(|v| v.pts);
+                audio_params = Some(header_audio);
+                audio_recs.push(audio_rec);
+            }
+        }
+        if let Some(last) = samples.last() {
+            last_dts = Some(last.dts().ok_or(Dts)?);
+        }
+        if truncated {
+            break;
+        }
     }
+    let audio = audio_params.map(|params| (audio_recs, params));
+    Ok((recs, params, truncated, audio))
+}
 
-    let mut samples: Vec<_> = recs.iter_mut().flat_map(|v| &mut v.samples).collect();
-
-    // Shift first sample to start time.
-    let Some(first) = samples.first_mut() else {
-        return Ok(None);
-    };
-    first.pts = q.start.into();
-
-    // Pad durations to fill any gaps.
-    for i in 1..samples.len() {
-        let s0_dts = samples[i - 1].dts().ok_or(Dts)?;
-        let s1_dts = samples[i].dts().ok_or(Dts)?;
-        let diff = s1_dts - s0_dts;
-        samples[i - 1].duration = diff.into();
-    }
-    let last = samples.last_mut().expect("should exist");
-    last.duration = (UnixH264::from(q.end) - last.pts).into();
-    assert_eq!(last.end().ok_or(End)?, q.end.into());
+// Returns the index of the first sample that doesn't fit in the budget.
+fn budget_exceeded_at(
+    config: &VodConfig,
+    samples: &[Sample],
+    n_samples: usize,
+    n_bytes: u64,
+) -> Option<usize> {
+    let mut n_samples = n_samples;
+    let mut n_bytes = n_bytes;
+    samples.iter().position(|v| {
+        n_samples += 1;
+        n_bytes += u64::from(v.data_size);
+        config.max_samples.is_some_and(|max| n_samples > max.get())
+            || config.max_bytes.is_some_and(|max| n_bytes > max.get())
+    })
+}
 
-    let mut meta = Vec::new();
-    let mdat_size = usize::try_from(
-        generate_mp4(
-            &mut meta,
-            q.start.into(),
-            recs.iter().flat_map(|v| &v.samples),
-            &params.expect("should be Some"),
-        )
-        .await?,
-    )
-    .expect("u32 fit usize");
-    let meta_size = meta.len();
+// Every item in `tracks` becomes a video track, the sample data of the
+// tracks is placed in the mdat one after the other followed by the audio.
+async fn query_result(
+    start: UnixNano,
+    tracks: Vec<(Vec<RecPartWithSamples>, TrackParameters)>,
+    audio: Option<AudioRecs>,
+) -> Result<QueryResult, CreateVodReaderError> {
+    let audio_samples: Vec<Sample> = audio
+        .iter()
+        .flat_map(|(recs, _)| recs.iter().flat_map(|v| v.samples.iter().cloned()))
+        .collect();
+    let header = Mp4Header::new(
+        start.into(),
+        tracks
+            .iter()
+            .map(|(recs, params)| (recs.iter().flat_map(|v| &v.samples), params))
+            .collect(),
+        audio.as_ref().map(|(_, params)| AudioTrack {
+            samples: &audio_samples,
+            params,
+        }),
+        None,
+        Timescale::default(),
+    )?;
+    let meta_size = header.size();
+    let mdat_size = usize::try_from(header.mdat_size()).expect("u32 fit usize");
+
+    let mut meta = Vec::with_capacity(meta_size);
+    header.write(&mut meta).await?;
+    debug_assert_eq!(meta_size, meta.len());
 
     // Calculate recordings offsets.
     let mut pos = meta_size;
-    let mut recs: Vec<_> = recs.into_iter().map(|v| v.rec).collect();
+    let mut recs: Vec<_> = tracks
+        .into_iter()
+        .flat_map(|(recs, _)| recs)
+        .chain(audio.into_iter().flat_map(|(recs, _)| recs))
+        .map(|v| v.rec)
+        .collect();
     for rec in &mut recs {
         rec.start = pos;
         rec.end = pos + rec.size;
         pos += rec.size;
     }
 
-    Ok(Some(Arc::new(QueryResult {
-        meta: meta.clone(),
-        meta_size: meta.len(),
+    Ok(QueryResult {
+        meta,
+        meta_size,
         size: meta_size + mdat_size,
         recs,
-    })))
+        fragments: Vec::new(),
+        init_size: 0,
+        last_modified: None,
+        truncated: false,
+    })
+}
+
+// The init segment and segment index are used as meta and every fragment gets
+// its own `Rec` so the fragment headers can be placed between the sample data.
+fn fragmented_query_result(
+    start: UnixNano,
+    recs: &[RecPartWithSamples],
+    params: &TrackParameters,
+) -> Result<QueryResult, CreateVodReaderError> {
+    let samples: Vec<_> = recs.iter().flat_map(|v| &v.samples).collect();
+    let mp4_fragments = generate_fragments(start.into(), &samples, Timescale::default(), None)?;
+    let mut meta = generate_init(params, Timescale::default(), None)?;
+    let init_size = meta.len();
+    meta.extend(generate_sidx(
+        start.into(),
+        &samples,
+        &mp4_fragments,
+        Timescale::default(),
+    )?);
+
+    // Index of the first sample and the files of every recording.
+    let mut rec_starts = Vec::with_capacity(recs.len());
+    let mut n_samples = 0;
+    for rec in recs {
+        rec_starts.push((n_samples, &rec.rec));
+        n_samples += rec.samples.len();
+    }
+
+    let mut pos = meta.len();
+    let mut out_recs = Vec::new();
+    let mut fragments = Vec::new();
+    for fragment in mp4_fragments {
+        // Recordings start with a IDR, a fragment never spans two recordings.
+        let i = rec_starts.partition_point(|(first, _)| *first <= fragment.first_sample) - 1;
+        let rec = rec_starts[i].1;
+
+        let fragment_samples = &samples[fragment.first_sample..][..fragment.n_samples];
+        let data_start = usize::try_from(fragment_samples[0].data_offset).expect("usize fit u32");
+        let size: usize = fragment_samples
+            .iter()
+            .map(|v| usize::try_from(v.data_size).expect("u32 fit usize"))
+            .sum();
+
+        let duration: i64 = fragment_samples.iter().map(|v| *v.duration).sum();
+        let duration = UnixNano::from(UnixH264::new(duration));
+
+        let header_size = fragment.header.len();
+        fragments.push(FragmentHeader {
+            start: pos,
+            time: fragment_samples[0].pts.into(),
+            duration: duration.into(),
+            header: fragment.header,
+        });
+        pos += header_size;
+
+        out_recs.push(Rec {
+            mdat_path: rec.mdat_path.clone(),
+            key_path: rec.key_path.clone(),
+            data_start,
+            size,
+            start: pos,
+            end: pos + size,
+        });
+        pos += size;
+    }
+
+    Ok(QueryResult {
+        meta_size: meta.len(),
+        meta,
+        size: pos,
+        recs: out_recs,
+        fragments,
+        init_size,
+        last_modified: None,
+        truncated: false,
+    })
+}
+
+// Returns the events of the queried recordings as WebVTT cues.
+pub async fn query_events_vtt(
+    recdb: &RecDb,
+    config: &VodConfig,
+    q: &VodQuery,
+) -> Result<Option<String>, CreateVodReaderError> {
+    let (recordings, _) = query_recordings(recdb, config, q, true).await?;
+    if recordings.is_empty() {
+        return Ok(None);
+    }
+    let events: Vec<&Event> = recordings
+        .iter()
+        .filter_map(|rec| match rec {
+            RecordingResponse::Finalized(rec) => rec.data(),
+            _ => None,
+        })
+        .flat_map(|data| &data.events)
+        .collect();
+    Ok(Some(events_to_vtt(&events, q.start, q.end)))
+}
+
+// Highest constant rate factor accepted by H.264 encoders.
+pub const MAX_CRF: u8 = 51;
+
+#[derive(Debug, Error)]
+pub enum ExportTranscodedError {
+    #[error("crf {0} is above the max of {MAX_CRF}")]
+    InvalidCrf(u8),
+
+    #[error("create vod reader: {0}")]
+    CreateVodReader(#[from] CreateVodReaderError),
+
+    #[error("transcode: {0}")]
+    Transcode(#[from] TranscodeError),
+}
+
+// Re-encodes the queried video to `target_height` with the transcoder
+// provided by a plugin. Returns None if there is no video.
+pub async fn export_transcoded(
+    recdb: &RecDb,
+    cache: &VodCache,
+    config: &VodConfig,
+    transcoder: &ArcTranscoder,
+    mut q: VodQuery,
+    target_height: NonZeroU16,
+    crf: u8,
+) -> Result<Option<Box<dyn AsyncRead + Send + Unpin>>, ExportTranscodedError> {
+    if crf > MAX_CRF {
+        return Err(ExportTranscodedError::InvalidCrf(crf));
+    }
+    q.fragmented = false;
+    let Some(reader) = VodReader::new(recdb, cache, config, q).await? else {
+        return Ok(None);
+    };
+    let output = transcoder
+        .transcode(Box::new(Box::pin(reader)), target_height, crf)
+        .await?;
+    Ok(Some(output))
+}
+
+// Cue times are relative to `start`, which is where `execute_query` places
+// the first sample. Events are clipped to the window, events outside it are
+// dropped.
+fn events_to_vtt(events: &[&Event], start: UnixNano, end: UnixNano) -> String {
+    let mut out = "WEBVTT\n".to_owned();
+    for event in events {
+        let event_end = event.time.saturating_add(*event.duration);
+        let cue_start = (*event.time).max(*start).saturating_sub(*start);
+        let cue_end = event_end.min(*end).saturating_sub(*start);
+        if cue_end <= cue_start {
+            continue;
+        }
+        let labels: Vec<String> = event
+            .detections
+            .iter()
+            .map(|d| format!("{} {:.0}%", d.label, d.score))
+            .collect();
+        _ = write!(
+            out,
+            "\n{} --> {}\n{}\n",
+            vtt_timestamp(cue_start),
+            vtt_timestamp(cue_end),
+            labels.join(", "),
+        );
+    }
+    out
+}
+
+// HH:MM:SS.mmm
+fn vtt_timestamp(nanos: i64) -> String {
+    let millis = nanos / MILLISECOND;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        (millis / 60_000) % 60,
+        (millis / 1000) % 60,
+        millis % 1000,
+    )
 }
 
 #[allow(clippy::too_many_lines)]
@@ -323,6 +1133,14 @@ impl AsyncRead for VodReader {
                             return Poll::Ready(Ok(()));
                         }
 
+                        // Position is within a fragment header.
+                        if let Some(header) = this.r.fragment_header_at(*this.pos) {
+                            let amt = std::cmp::min(header.len(), buf.remaining());
+                            buf.put_slice(&header[..amt]);
+                            *this.pos += amt;
+                            return Poll::Ready(Ok(()));
+                        }
+
                         // Find recording at the position.
                         let Some(i) = this.r.recs.iter().position(|rec| *this.pos < rec.end) else {
                             // EOF.
@@ -340,8 +1158,27 @@ impl AsyncRead for VodReader {
                     }
                     CloseReadStateProj::State4(i, file_pos, amt) => {
                         let mdat_path = this.r.recs[*i].mdat_path.clone();
-                        let open_fut = tokio::task::spawn_blocking(move || {
-                            std::fs::OpenOptions::new().read(true).open(mdat_path)
+                        let key_path = this.r.recs[*i].key_path.clone();
+                        let master_key = this.master_key.clone();
+                        let open_files = *this.open_files;
+                        let open_fut = tokio::spawn(async move {
+                            let permit = open_files.acquire().await.expect("not closed");
+                            let file = tokio::task::spawn_blocking(move || {
+                                std::fs::OpenOptions::new().read(true).open(mdat_path)
+                            })
+                            .await
+                            .expect("join")?;
+                            let key = match key_path {
+                                Some(path) => Some(
+                                    read_recording_key(master_key.as_ref(), &path)
+                                        .await
+                                        .map_err(std::io::Error::other)?,
+                                ),
+                                None => None,
+                            };
+                            let file = MdatFile::new(tokio::fs::File::from_std(file), key.as_ref())
+                                .await?;
+                            Ok((file, permit))
                         });
                         *read_state = CloseReadState::State5(open_fut, *i, *file_pos, *amt);
                         continue;
@@ -350,22 +1187,33 @@ impl AsyncRead for VodReader {
                         //
                         match open_fut.poll(cx) {
                             Poll::Ready(res) => {
-                                let file = tokio::fs::File::from_std(res??);
+                                let (file, permit) = res??;
                                 if *file_pos != 0 {
                                     let state = OpenReadState::State6(*file_pos, *amt);
-                                    *this.file_state = FileState::Open(state, file, 0, *i);
+                                    *this.file_state = FileState::Open(state, file, 0, *i, permit);
                                     continue;
                                 }
 
-                                *this.file_state =
-                                    FileState::Open(OpenReadState::State3(*amt), file, 0, *i);
+                                *this.file_state = FileState::Open(
+                                    OpenReadState::State3(*amt),
+                                    file,
+                                    0,
+                                    *i,
+                                    permit,
+                                );
                                 continue;
                             }
                             Poll::Pending => return Poll::Pending,
                         }
                     }
                 },
-                FileStateProj::Open(mut read_state, open_file, open_file_pos, open_file_index) => {
+                FileStateProj::Open(
+                    mut read_state,
+                    open_file,
+                    open_file_pos,
+                    open_file_index,
+                    _,
+                ) => {
                     match read_state.as_mut().project() {
                         OpenReadStateProj::State1 => {
                             // Position is within meta.
@@ -378,6 +1226,14 @@ impl AsyncRead for VodReader {
                                 return Poll::Ready(Ok(()));
                             }
 
+                            // Position is within a fragment header.
+                            if let Some(header) = this.r.fragment_header_at(*this.pos) {
+                                let amt = std::cmp::min(header.len(), buf.remaining());
+                                buf.put_slice(&header[..amt]);
+                                *this.pos += amt;
+                                return Poll::Ready(Ok(()));
+                            }
+
                             // Find recording at the position.
                             let Some(i) = this.r.recs.iter().position(|rec| *this.pos < rec.end)
                             else {
@@ -465,7 +1321,14 @@ impl AsyncRead for VodReader {
 #[pin_project(project = FileStateProj)]
 enum FileState {
     Close(#[pin] CloseReadState),
-    Open(#[pin] OpenReadState, #[pin] tokio::fs::File, usize, usize),
+    // The permit is released when the file is closed.
+    Open(
+        #[pin] OpenReadState,
+        #[pin] MdatFile,
+        usize,
+        usize,
+        SemaphorePermit<'static>,
+    ),
 }
 
 #[derive(Debug)]
@@ -486,7 +1349,7 @@ enum OpenReadState {
     State7(usize),
 }
 
-type OpenFut = JoinHandle<Result<std::fs::File, std::io::Error>>;
+type OpenFut = JoinHandle<Result<(MdatFile, SemaphorePermit<'static>), std::io::Error>>;
 
 impl AsyncSeek for VodReader {
     fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
@@ -585,9 +1448,12 @@ struct RecPartWithSamples {
     samples: Vec<Sample>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 struct Rec {
     mdat_path: PathBuf,
+    // Only set if the recording is encrypted.
+    #[serde(default)]
+    key_path: Option<PathBuf>,
     data_start: usize,
     size: usize,
     start: usize,
@@ -602,15 +1468,57 @@ mod tests {
     use common::{
         recording::RecordingData,
         time::{DtsOffset, DurationH264, UnixH264, UnixNano, HOUR, MINUTE, SECOND},
-        DummyLogger, PaddedBytes, VideoSample,
+        AudioSample, DummyLogger, PaddedBytes, VideoSample,
     };
     use pretty_assertions::assert_eq;
     use pretty_hex::pretty_hex;
     use recdb::{Disk, RecDb};
-    use recording::{MetaHeader, VideoWriter};
+    use recording::{generate_mp4, read_meta, AudioWriter, MetaHeader, VideoWriter};
     use std::sync::Arc;
     use tempfile::TempDir;
-    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use test_case::test_case;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    #[test]
+    fn test_align_samples() {
+        let sample = |pts| Sample {
+            random_access_present: true,
+            pts: UnixH264::new(pts),
+            dts_offset: DtsOffset::new(0),
+            duration: DurationH264::new(1),
+            data_size: 1,
+            data_offset: 0,
+        };
+        let new_recs = || {
+            vec![RecPartWithSamples {
+                rec: Rec {
+                    mdat_path: PathBuf::new(),
+                    key_path: None,
+                    data_start: 0,
+                    size: 0,
+                    start: 0,
+                    end: 0,
+                },
+                samples: vec![sample(10), sample(20)],
+            }]
+        };
+
+        let mut recs = new_recs();
+        assert!(align_samples(&mut recs, UnixNano::new(0), UnixH264::new(30)).unwrap());
+        let got: Vec<_> = recs[0]
+            .samples
+            .iter()
+            .map(|v| (*v.pts, *v.duration))
+            .collect();
+        assert_eq!(vec![(0, 20), (20, 10)], got);
+
+        // The last sample starts after the end.
+        let mut recs = new_recs();
+        assert!(matches!(
+            align_samples(&mut recs, UnixNano::new(0), UnixH264::new(15)),
+            Err(CreateVodReaderError::LastSampleEnd(_, _))
+        ));
+    }
 
     #[tokio::test]
     async fn test_vod_simple1() {
@@ -624,6 +1532,9 @@ mod tests {
             start: start_time.into(),
             end: UnixNano::from(start_time + UnixH264::new(7)) + UnixNano::new(1),
             cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         };
         let got = new_vod_reader_read_all(&rec_db, query).await;
 
@@ -682,6 +1593,122 @@ mod tests {
         assert_eq!(pretty_hex(&want), pretty_hex(&got));
     }
 
+    #[tokio::test]
+    async fn test_vod_dump() {
+        let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let start_time: UnixH264 = year_2000 + UnixNano::new(10 * MINUTE).into();
+
+        let (_tmp_dir, rec_db) = single_recording(start_time).await;
+
+        let query = VodQuery {
+            monitor_id: "x".to_owned().try_into().unwrap(),
+            start: start_time.into(),
+            end: UnixNano::from(start_time + UnixH264::new(7)) + UnixNano::new(1),
+            cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
+        };
+        let got = new_vod_reader_read_all(&rec_db, query).await;
+
+        let mut out = Vec::new();
+        mp4::dump(&mut std::io::Cursor::new(got), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let top_level: Vec<&str> = out
+            .lines()
+            .filter(|v| !v.starts_with(' '))
+            .map(|v| &v[..6])
+            .collect();
+        assert_eq!(vec!["[ftyp]", "[moov]", "[mdat]"], top_level);
+        assert!(out.contains("\n              [avcC] offset="), "{out}");
+        assert!(out.contains("\n          [stco] offset="), "{out}");
+        assert!(!out.contains("[mp4a]"), "{out}");
+    }
+
+    #[tokio::test]
+    async fn test_vod_audio() {
+        let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let start_time: UnixH264 = year_2000 + UnixNano::new(10 * MINUTE).into();
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let mut rec_db = RecDb::new(
+            DummyLogger::new(),
+            path.clone(),
+            Disk::new(path, ByteSize(0)),
+        );
+        let video_sample = |pts, data| VideoSample {
+            pts: start_time + UnixH264::new(pts),
+            dts_offset: DtsOffset::new(0),
+            avcc: Arc::new(PaddedBytes::new(vec![data])),
+            random_access_present: true,
+            duration: DurationH264::new(2),
+        };
+        let audio_sample = |pts, data: Vec<u8>| AudioSample {
+            pts: start_time + UnixH264::new(pts),
+            duration: DurationH264::new(2),
+            data: data.into(),
+        };
+        // Same timescale as the video.
+        let audio_params = AudioParameters {
+            channel_count: 1,
+            sample_rate: 90000,
+            config: vec![0x13, 0x08],
+        };
+        save_recording_with_audio(
+            &mut rec_db,
+            "x",
+            start_time,
+            start_time + UnixH264::new(7),
+            vec![video_sample(3, 1), video_sample(5, 2)],
+            Some((
+                audio_params,
+                vec![
+                    // Before the first video sample.
+                    audio_sample(1, vec![9, 9]),
+                    audio_sample(3, vec![3, 4]),
+                    audio_sample(5, vec![5, 6]),
+                ],
+            )),
+        )
+        .await;
+
+        let query = VodQuery {
+            monitor_id: "x".to_owned().try_into().unwrap(),
+            start: start_time.into(),
+            end: UnixNano::from(start_time + UnixH264::new(7)) + UnixNano::new(1),
+            cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
+        };
+        let got = new_vod_reader_read_all(&rec_db, query).await;
+
+        let mut out = Vec::new();
+        mp4::dump(&mut std::io::Cursor::new(got.clone()), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\n            [mp4a] offset="), "{out}");
+        assert!(out.contains("\n              [esds] offset="), "{out}");
+
+        // The audio data follows the video data.
+        assert_eq!(&[1, 2, 3, 4, 5, 6], &got[got.len() - 6..]);
+
+        // And the chunk offset of each track points to its data.
+        let chunk_offsets: Vec<_> = got
+            .windows(4)
+            .enumerate()
+            .filter(|(_, v)| v == b"stco")
+            .map(|(i, _)| {
+                let offset = u32::from_be_bytes(got[i + 12..i + 16].try_into().unwrap());
+                usize::try_from(offset).unwrap()
+            })
+            .collect();
+        assert_eq!(2, chunk_offsets.len());
+        assert_eq!(&[1, 2], &got[chunk_offsets[0]..][..2]);
+        assert_eq!(&[3, 4, 5, 6], &got[chunk_offsets[1]..][..4]);
+    }
+
     #[tokio::test]
     async fn test_vod_simple2() {
         let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
@@ -694,6 +1721,9 @@ mod tests {
             start: (start_time + UnixH264::new(4)).into(), // Second sample.
             end: UnixNano::from(start_time + UnixH264::new(7)) + UnixNano::new(1),
             cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         };
         let got = new_vod_reader_read_all(&rec_db, query).await;
 
@@ -761,6 +1791,9 @@ mod tests {
             start: (start_time + UnixH264::new(5)).into(), // Third sample.
             end: (start_time + UnixH264::new(1_000_000)).into(),
             cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         };
         let got = new_vod_reader_read_all(&rec_db, query).await;
 
@@ -828,11 +1861,16 @@ mod tests {
             start: (start_time + UnixH264::new(6)).into(), // Last sample.
             end: (start_time + UnixH264::new(1_000_000)).into(),
             cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         };
-        assert!(VodReader::new(&rec_db, &VodCache::new(), query)
-            .await
-            .unwrap()
-            .is_none());
+        assert!(
+            VodReader::new(&rec_db, &VodCache::new(), &VodConfig::default(), query)
+                .await
+                .unwrap()
+                .is_none()
+        );
     }
 
     #[tokio::test]
@@ -847,6 +1885,9 @@ mod tests {
             start: start_time.into(),
             end: (start_time + UnixH264::new(SECOND)).into(),
             cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         };
         let got = new_vod_reader_read_all(&rec_db, query).await;
 
@@ -919,6 +1960,9 @@ mod tests {
             start: start_time.into(),
             end: UnixNano::from(start_time + UnixH264::new(6)) + UnixNano::new(1), // Third sample.
             cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         };
         let got = new_vod_reader_read_all(&rec_db, query).await;
 
@@ -988,8 +2032,11 @@ mod tests {
             start: (start_time + UnixH264::new(20)).into(),
             end: start_time.into(),
             cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         };
-        VodReader::new(&rec_db, &VodCache::new(), query)
+        VodReader::new(&rec_db, &VodCache::new(), &VodConfig::default(), query)
             .await
             .unwrap_err();
     }
@@ -1006,9 +2053,200 @@ mod tests {
             start: start_time.into(),
             end: UnixNano::from(start_time) + UnixNano::new(HOUR * 13),
             cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
+        };
+        let result = VodReader::new(&rec_db, &VodCache::new(), &VodConfig::default(), query).await;
+        assert!(matches!(
+            result,
+            Err(CreateVodReaderError::MaxDuration { .. })
+        ));
+    }
+
+    #[test_case(HOUR, true; "max")]
+    #[test_case(HOUR + 1, false; "max_plus_one")]
+    #[tokio::test]
+    async fn test_vod_custom_max_duration(duration: i64, ok: bool) {
+        let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let start_time: UnixH264 = year_2000 + UnixNano::new(10 * MINUTE).into();
+
+        let (_tmp_dir, rec_db) = single_recording(start_time).await;
+
+        let query = VodQuery {
+            monitor_id: "x".to_owned().try_into().unwrap(),
+            start: start_time.into(),
+            end: UnixNano::from(start_time) + UnixNano::new(duration),
+            cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
+        };
+        let config = VodConfig {
+            max_duration: std::time::Duration::from_secs(60 * 60),
+            ..VodConfig::default()
+        };
+        let result = VodReader::new(&rec_db, &VodCache::new(), &config, query).await;
+        if ok {
+            assert!(result.is_ok());
+            return;
+        }
+        let Err(CreateVodReaderError::MaxDuration { requested, max }) = result else {
+            panic!("expected max duration error");
+        };
+        assert_eq!(
+            std::time::Duration::from_nanos(60 * 60 * 1_000_000_000 + 1),
+            requested
+        );
+        assert_eq!(std::time::Duration::from_secs(60 * 60), max);
+    }
+
+    // Recording where the second sample has no data.
+    async fn empty_sample_recording(start_time: UnixH264, include_empty: bool) -> (TempDir, RecDb) {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let mut rec_db = RecDb::new(
+            DummyLogger::new(),
+            path.clone(),
+            Disk::new(path, ByteSize(0)),
+        );
+
+        let mut samples = vec![
+            VideoSample {
+                pts: start_time + UnixH264::new(3),
+                avcc: Arc::new(PaddedBytes::new(vec![0x1])),
+                random_access_present: true,
+                duration: DurationH264::new(1),
+                ..Default::default()
+            },
+            VideoSample {
+                pts: start_time + UnixH264::new(4),
+                avcc: Arc::new(PaddedBytes::new(Vec::new())),
+                duration: DurationH264::new(1),
+                ..Default::default()
+            },
+            VideoSample {
+                pts: start_time + UnixH264::new(5),
+                avcc: Arc::new(PaddedBytes::new(vec![0x3])),
+                duration: DurationH264::new(1),
+                ..Default::default()
+            },
+        ];
+        if !include_empty {
+            samples.remove(1);
+            samples[0].duration = DurationH264::new(2);
+        }
+        save_recording(
+            &mut rec_db,
+            start_time,
+            start_time + UnixH264::new(6),
+            samples,
+        )
+        .await;
+        (temp_dir, rec_db)
+    }
+
+    #[tokio::test]
+    async fn test_vod_empty_sample() {
+        let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let start_time: UnixH264 = year_2000 + UnixNano::new(10 * MINUTE).into();
+
+        let query = |empty_samples| VodQuery {
+            monitor_id: "x".to_owned().try_into().unwrap(),
+            start: start_time.into(),
+            end: UnixNano::from(start_time + UnixH264::new(6)),
+            cache_id: 0,
+            empty_samples,
+            events: false,
+            fragmented: false,
+        };
+
+        // Skipped sample is the same as if it never existed.
+        let (_tmp_dir, rec_db) = empty_sample_recording(start_time, false).await;
+        let want = new_vod_reader_read_all(&rec_db, query(EmptySamplePolicy::Skip)).await;
+        let (_tmp_dir, rec_db) = empty_sample_recording(start_time, true).await;
+        let got = new_vod_reader_read_all(&rec_db, query(EmptySamplePolicy::Skip)).await;
+        assert_eq!(pretty_hex(&want), pretty_hex(&got));
+
+        assert!(matches!(
+            VodReader::new(
+                &rec_db,
+                &VodCache::new(),
+                &VodConfig::default(),
+                query(EmptySamplePolicy::Error)
+            )
+            .await,
+            Err(CreateVodReaderError::EmptySampleData(1))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_vod_max_open_files() {
+        let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let start_time: UnixH264 = year_2000 + UnixNano::new(10 * MINUTE).into();
+
+        let (_tmp_dir, rec_db) = single_recording(start_time).await;
+        let query = VodQuery {
+            monitor_id: "x".to_owned().try_into().unwrap(),
+            start: start_time.into(),
+            end: (start_time + UnixH264::new(1_000_000)).into(),
+            cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         };
-        let result = VodReader::new(&rec_db, &VodCache::new(), query).await;
-        assert!(matches!(result, Err(CreateVodReaderError::MaxDuration)));
+        let cache = VodCache::new();
+        let open_files: &'static Semaphore = Box::leak(Box::new(Semaphore::new(1)));
+        let new_reader = || async {
+            let mut reader = VodReader::new(&rec_db, &cache, &VodConfig::default(), query.clone())
+                .await
+                .unwrap()
+                .unwrap();
+            reader.open_files = open_files;
+            reader
+        };
+        let want = new_vod_reader_read_all(&rec_db, query.clone()).await;
+        let mdat_start = u64::try_from(want.len() - 4).unwrap();
+
+        // The first reader keeps its file open.
+        let mut reader1 = new_reader().await;
+        reader1.seek(SeekFrom::Start(mdat_start)).await.unwrap();
+        let mut buf = [0];
+        reader1.read_exact(&mut buf).await.unwrap();
+        assert_eq!(0, open_files.available_permits());
+
+        // The second reader has to wait for a slot.
+        let mut reader2 = new_reader().await;
+        let mut got = Vec::new();
+        let read = tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            reader2.read_to_end(&mut got),
+        )
+        .await;
+        assert!(read.is_err());
+        drop(reader2);
+
+        drop(reader1);
+        let mut reader3 = new_reader().await;
+        let mut got = Vec::new();
+        reader3.read_to_end(&mut got).await.unwrap();
+        assert_eq!(want, got);
+        drop(reader3);
+
+        // More readers than slots.
+        let mut handles = Vec::new();
+        for _ in 0..4 {
+            let mut reader = new_reader().await;
+            handles.push(tokio::spawn(async move {
+                let mut got = Vec::new();
+                reader.read_to_end(&mut got).await.unwrap();
+                got
+            }));
+        }
+        for handle in handles {
+            assert_eq!(want, handle.await.unwrap());
+        }
+        assert_eq!(1, open_files.available_permits());
     }
 
     async fn single_recording(start_time: UnixH264) -> (TempDir, RecDb) {
@@ -1053,80 +2291,428 @@ mod tests {
                 },
             ],
         )
-        .await;
-        (temp_dir, rec_db)
+        .await;
+        (temp_dir, rec_db)
+    }
+
+    #[tokio::test]
+    async fn test_vod_two() {
+        let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let start_time = year_2000 + UnixNano::new(10 * MINUTE).into() + UnixH264::new(89998);
+
+        let (_tmp_dir, rec_db) = two_recordings(start_time).await;
+
+        let query = VodQuery {
+            monitor_id: "x".to_owned().try_into().unwrap(),
+            start: start_time.into(),
+            end: (start_time + UnixH264::new(16)).into(),
+            cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
+        };
+        let got = new_vod_reader_read_all(&rec_db, query).await;
+
+        #[rustfmt::skip]
+        let want: Vec<u8> = [
+            test_mp4(
+                [0, 0, 2, 0x90],
+                [0, 0, 0, 0],
+                [0, 0, 2, 0x1c],
+                [0, 0, 1, 0xb8],
+                [0, 0, 0, 0x10],
+                [0, 0, 1, 0x63],
+                [0, 0, 1, 0x23],
+            ).as_slice(),
+            &[
+                0, 0, 0, 0x28, b's', b't', b't', b's',
+                0, 0, 0, 0, // FullBox.
+                0, 0, 0, 3, // Entry count.
+                0, 0, 0, 1, // Entry1 sample count.
+                0, 0, 0, 2, // Entry1 sample delta.
+                0, 0, 0, 2, // Entry2 sample count.
+                0, 0, 0, 1, // Entry2 sample delta.
+                0, 0, 0, 1, // Entry3 sample count.
+                0, 0, 0, 0xc, // Entry3 sample delta.
+                0, 0, 0, 0x18, b's', b't', b's', b's',
+                0, 0, 0, 0, // FullBox.
+                0, 0, 0, 2, // Entry count.
+                0, 0, 0, 1, // Entry1.
+                0, 0, 0, 3, // Entry2.
+                0, 0, 0, 0x18, b'c', b't', b't', b's',
+                1, 0, 0, 0, // FullBox.
+                0, 0, 0, 1, // Entry count.
+                0, 0, 0, 4, // Entry1 sample count.
+                0, 0, 0, 0, // Entry1 sample offset
+                0, 0, 0, 0x1c, b's', b't', b's', b'c',
+                0, 0, 0, 0, // FullBox.
+                0, 0, 0, 1, // Entry count.
+                0, 0, 0, 1, // Entry1 first chunk.
+                0, 0, 0, 4, // Entry1 samples per chunk.
+                0, 0, 0, 1, // Entry1 sample description index.
+                0, 0, 0, 0x24, b's', b't', b's', b'z',
+                0, 0, 0, 0, // FullBox.
+                0, 0, 0, 0, // Sample size.
+                0, 0, 0, 4, // Sample count.
+                0, 0, 0, 1, // Entry1 size.
+                0, 0, 0, 1, // Entry2 size.
+                0, 0, 0, 1, // Entry3 size.
+                0, 0, 0, 1, // Entry4 size.
+                0, 0, 0, 0x14, b's', b't', b'c', b'o',
+                0, 0, 0, 0, // FullBox.
+                0, 0, 0, 1, // Entry count.
+                0, 0, 2, 0xac, // Chunk offset1.
+                //
+                0, 0, 0, 0x0c, b'm', b'd', b'a', b't',
+                1, 2, 3, 4 // Data.
+            ]
+        ].into_iter().flatten().copied().collect();
+        assert_eq!(pretty_hex(&want), pretty_hex(&got));
+    }
+
+    fn fragmented_query(start_time: UnixH264) -> VodQuery {
+        VodQuery {
+            monitor_id: "x".to_owned().try_into().unwrap(),
+            start: start_time.into(),
+            end: (start_time + UnixH264::new(7)).into(),
+            cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vod_fragmented() {
+        let start_time: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let (_tmp_dir, rec_db) = single_recording(start_time).await;
+
+        let query = fragmented_query(start_time);
+        let reader = VodReader::new(
+            &rec_db,
+            &VodCache::new(),
+            &VodConfig::default(),
+            query.clone(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let got = new_vod_reader_read_all(&rec_db, query.clone()).await;
+
+        // Init segment.
+        assert_eq!(b"ftyp", &got[4..8]);
+        assert_eq!(reader.r.meta, got[..reader.r.meta_size]);
+
+        // A fragment for each IDR.
+        let fragments = &reader.r.fragments;
+        assert_eq!(2, fragments.len());
+        assert_eq!(reader.r.meta_size, fragments[0].start);
+        assert_eq!(query.start, fragments[0].time);
+        assert_eq!(
+            UnixNano::from(start_time + UnixH264::new(5)),
+            fragments[1].time
+        );
+        for (fragment, data) in fragments.iter().zip([[1, 2], [3, 4]]) {
+            let header_end = fragment.start + fragment.header.len();
+            assert_eq!(b"moof", &got[fragment.start + 4..][..4]);
+            assert_eq!(fragment.header, got[fragment.start..header_end]);
+            assert_eq!(b"mdat", &got[header_end - 4..header_end]);
+            assert_eq!(data, got[header_end..][..2]);
+        }
+        let end = UnixNano::from(start_time + UnixH264::new(7));
+        let want = UNIX_EPOCH + std::time::Duration::from_nanos(u64::try_from(*end).unwrap());
+        assert_eq!(Some(want), reader.last_modified());
+        assert_eq!(
+            format!("W/\"x-{}-{}-0-fragmented\"", *query.start, *end),
+            query.etag()
+        );
+
+        assert_eq!(fragments[1].start, fragments[0].start + 128 + 2);
+        assert_eq!(got.len(), fragments[1].start + 128 + 2);
+    }
+
+    #[tokio::test]
+    async fn test_vod_fragmented_sidx() {
+        let start_time: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let (_tmp_dir, rec_db) = single_recording(start_time).await;
+
+        let query = fragmented_query(start_time);
+        let reader = VodReader::new(
+            &rec_db,
+            &VodCache::new(),
+            &VodConfig::default(),
+            query.clone(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let got = new_vod_reader_read_all(&rec_db, query).await;
+
+        // Ftyp, moov, sidx, then the first moof.
+        let mut pos = 0;
+        let mut boxes = Vec::new();
+        while pos < reader.r.fragments[0].start {
+            let size = u32::from_be_bytes(got[pos..][..4].try_into().unwrap());
+            boxes.push((&got[pos + 4..][..4], pos));
+            pos += usize::try_from(size).unwrap();
+        }
+        assert_eq!(pos, reader.r.fragments[0].start);
+        let types: Vec<_> = boxes.iter().map(|v| v.0).collect();
+        assert_eq!(vec![b"ftyp", b"moov", b"sidx"], types);
+
+        // The references cover the fragments.
+        let sidx = &got[boxes[2].1..pos];
+        assert_eq!([0, 2], sidx[38..40]);
+        let fragments = &reader.r.fragments;
+        let fragment_sizes = [
+            fragments[1].start - fragments[0].start,
+            got.len() - fragments[1].start,
+        ];
+        for (i, size) in fragment_sizes.into_iter().enumerate() {
+            let reference = &sidx[40 + i * 12..][..12];
+            let size = u32::try_from(size).unwrap();
+            assert_eq!(size.to_be_bytes(), reference[..4]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vod_fragmented_seek() {
+        let start_time: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let (_tmp_dir, rec_db) = single_recording(start_time).await;
+
+        let query = fragmented_query(start_time);
+        let all = new_vod_reader_read_all(&rec_db, query.clone()).await;
+        let mut reader = VodReader::new(&rec_db, &VodCache::new(), &VodConfig::default(), query)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Times snap to the start of the fragment that contains them.
+        let second = reader.r.fragments[1].start;
+        let first = reader.r.fragments[0].start;
+        let offset = |v: i64| reader.fragment_offset((start_time + UnixH264::new(v)).into());
+        assert_eq!(Some(first as u64), offset(-1));
+        assert_eq!(Some(first as u64), offset(4));
+        assert_eq!(Some(second as u64), offset(5));
+        assert_eq!(Some(second as u64), offset(6));
+
+        let pos = offset(6).unwrap();
+        reader.seek(SeekFrom::Start(pos)).await.unwrap();
+        let mut got = Vec::new();
+        reader.read_to_end(&mut got).await.unwrap();
+        assert_eq!(b"moof", &got[4..8]);
+        assert_eq!(all[second..], got);
+        assert_eq!([3, 4], got[got.len() - 2..]);
+    }
+
+    // A sample every half second with IDRs at 0s, 2s and 4.5s.
+    async fn hls_recording(start_time: UnixH264) -> (TempDir, RecDb) {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let mut rec_db = RecDb::new(
+            DummyLogger::new(),
+            path.clone(),
+            Disk::new(path, ByteSize(0)),
+        );
+        let half_second = DurationH264::from(UnixH264::from(UnixNano::new(SECOND / 2)));
+        let samples = (0..12)
+            .map(|i: u8| VideoSample {
+                pts: start_time + UnixH264::new(*half_second * i64::from(i)),
+                dts_offset: DtsOffset::new(0),
+                avcc: Arc::new(PaddedBytes::new(vec![i])),
+                random_access_present: [0, 4, 9].contains(&i),
+                duration: half_second,
+            })
+            .collect();
+        let end = start_time + UnixH264::new(*half_second * 12);
+        save_recording(&mut rec_db, start_time, end, samples).await;
+        (temp_dir, rec_db)
+    }
+
+    #[tokio::test]
+    async fn test_vod_hls_playlist() {
+        let start_time: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let (_tmp_dir, rec_db) = hls_recording(start_time).await;
+
+        let query = VodQuery {
+            end: (start_time + UnixNano::new(6 * SECOND).into()).into(),
+            ..fragmented_query(start_time)
+        };
+        let all = new_vod_reader_read_all(&rec_db, query.clone()).await;
+        let reader = VodReader::new(&rec_db, &VodCache::new(), &VodConfig::default(), query)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let segments = reader.hls_segments().unwrap();
+        let durations: Vec<_> = segments.iter().map(|v| *v.duration).collect();
+        assert_eq!(vec![2 * SECOND, 5 * SECOND / 2, 3 * SECOND / 2], durations);
+
+        // The init segment and the segments cover the whole file.
+        let init = reader.hls_init().unwrap();
+        assert_eq!(b"ftyp", &init[4..8]);
+        assert_eq!(all[..init.len()], *init);
+        let mut pos = reader.r.meta_size;
+        for (segment, data) in segments.iter().zip([0..4, 4..9, 9..12]) {
+            assert_eq!(pos as u64, segment.offset);
+            let segment_data = &all[pos..][..usize::try_from(segment.size).unwrap()];
+            assert_eq!(b"moof", &segment_data[4..8]);
+            let data: Vec<u8> = data.collect();
+            assert_eq!(data, segment_data[segment_data.len() - data.len()..]);
+            pos += segment_data.len();
+        }
+        assert_eq!(all.len(), pos);
+
+        let playlist = reader
+            .hls_playlist("init.mp4", |i| format!("segment{i}.m4s"))
+            .unwrap();
+        let want = "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-TARGETDURATION:3
+#EXT-X-MEDIA-SEQUENCE:0
+#EXT-X-PLAYLIST-TYPE:VOD
+#EXT-X-INDEPENDENT-SEGMENTS
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXTINF:2.000,
+segment0.m4s
+#EXTINF:2.500,
+segment1.m4s
+#EXTINF:1.500,
+segment2.m4s
+#EXT-X-ENDLIST
+";
+        assert_eq!(want, playlist);
+    }
+
+    #[tokio::test]
+    async fn test_vod_hls_not_fragmented() {
+        let start_time: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let (_tmp_dir, rec_db) = single_recording(start_time).await;
+
+        let query = VodQuery {
+            fragmented: false,
+            ..fragmented_query(start_time)
+        };
+        let reader = VodReader::new(&rec_db, &VodCache::new(), &VodConfig::default(), query)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(reader.hls_init().is_none());
+        assert!(reader.hls_segments().is_none());
+        assert!(reader.hls_playlist("", |_| String::new()).is_none());
+    }
+
+    // Counts the reads from the inner reader.
+    struct CountingReader {
+        inner: std::io::Cursor<Vec<u8>>,
+        reads: usize,
+    }
+
+    impl AsyncRead for CountingReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            self.reads += 1;
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncSeek for CountingReader {
+        fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+            Pin::new(&mut self.inner).start_seek(position)
+        }
+
+        fn poll_complete(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<u64>> {
+            Pin::new(&mut self.inner).poll_complete(cx)
+        }
+    }
+
+    async fn window_mp4(
+        header: &MetaHeader,
+        samples: Vec<Sample>,
+        start: UnixNano,
+        end: UnixNano,
+    ) -> Vec<u8> {
+        let samples: Vec<_> = samples
+            .into_iter()
+            .filter(|s| {
+                start <= UnixNano::from(s.dts().unwrap()) && UnixNano::from(s.end().unwrap()) <= end
+            })
+            .skip_while(|s| !s.random_access_present)
+            .collect();
+        let mut out = Vec::new();
+        generate_mp4(
+            &mut out,
+            start.into(),
+            samples.iter(),
+            &header.params(),
+            None,
+            Timescale::default(),
+        )
+        .await
+        .unwrap();
+        out
     }
 
     #[tokio::test]
-    async fn test_vod_two() {
-        let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
-        let start_time = year_2000 + UnixNano::new(10 * MINUTE).into() + UnixH264::new(89998);
+    async fn test_read_samples_between() {
+        const N_SAMPLES: i64 = 10_000;
+        let start_time = UnixH264::new(1_000_000);
 
-        let (_tmp_dir, rec_db) = two_recordings(start_time).await;
+        let mut meta = Vec::new();
+        let mut mdat = Vec::new();
+        let header = MetaHeader {
+            start_time,
+            width: 640,
+            height: 480,
+            extra_data: vec![0x33],
+            audio: None,
+        };
+        let mut w = VideoWriter::new(&mut meta, &mut mdat, header)
+            .await
+            .unwrap();
+        for i in 0..N_SAMPLES {
+            w.write_sample(&VideoSample {
+                pts: start_time + UnixH264::new(i * 10),
+                dts_offset: DtsOffset::new(0),
+                avcc: Arc::new(PaddedBytes::new(vec![1])),
+                random_access_present: i % 30 == 0,
+                duration: DurationH264::new(10),
+            })
+            .await
+            .unwrap();
+        }
+        let meta_size = u64::try_from(meta.len()).unwrap();
 
-        let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
-            start: start_time.into(),
-            end: (start_time + UnixH264::new(16)).into(),
-            cache_id: 0,
+        // Window in the middle of the recording, not starting on a keyframe.
+        let start = UnixNano::from(start_time + UnixH264::new(5005 * 10));
+        let end = start + UnixNano::from(UnixH264::new(100 * 10));
+
+        let mut reader = CountingReader {
+            inner: std::io::Cursor::new(meta.clone()),
+            reads: 0,
         };
-        let got = new_vod_reader_read_all(&rec_db, query).await;
+        let (_, want_samples) = read_meta(&mut reader, meta_size).await.unwrap();
+        assert!(reader.reads > 10_000, "{}", reader.reads);
 
-        #[rustfmt::skip]
-        let want: Vec<u8> = [
-            test_mp4(
-                [0, 0, 2, 0x90],
-                [0, 0, 0, 0],
-                [0, 0, 2, 0x1c],
-                [0, 0, 1, 0xb8],
-                [0, 0, 0, 0x10],
-                [0, 0, 1, 0x63],
-                [0, 0, 1, 0x23],
-            ).as_slice(),
-            &[
-                0, 0, 0, 0x28, b's', b't', b't', b's',
-                0, 0, 0, 0, // FullBox.
-                0, 0, 0, 3, // Entry count.
-                0, 0, 0, 1, // Entry1 sample count.
-                0, 0, 0, 2, // Entry1 sample delta.
-                0, 0, 0, 2, // Entry2 sample count.
-                0, 0, 0, 1, // Entry2 sample delta.
-                0, 0, 0, 1, // Entry3 sample count.
-                0, 0, 0, 0xc, // Entry3 sample delta.
-                0, 0, 0, 0x18, b's', b't', b's', b's',
-                0, 0, 0, 0, // FullBox.
-                0, 0, 0, 2, // Entry count.
-                0, 0, 0, 1, // Entry1.
-                0, 0, 0, 3, // Entry2.
-                0, 0, 0, 0x18, b'c', b't', b't', b's',
-                1, 0, 0, 0, // FullBox.
-                0, 0, 0, 1, // Entry count.
-                0, 0, 0, 4, // Entry1 sample count.
-                0, 0, 0, 0, // Entry1 sample offset
-                0, 0, 0, 0x1c, b's', b't', b's', b'c',
-                0, 0, 0, 0, // FullBox.
-                0, 0, 0, 1, // Entry count.
-                0, 0, 0, 1, // Entry1 first chunk.
-                0, 0, 0, 4, // Entry1 samples per chunk.
-                0, 0, 0, 1, // Entry1 sample description index.
-                0, 0, 0, 0x24, b's', b't', b's', b'z',
-                0, 0, 0, 0, // FullBox.
-                0, 0, 0, 0, // Sample size.
-                0, 0, 0, 4, // Sample count.
-                0, 0, 0, 1, // Entry1 size.
-                0, 0, 0, 1, // Entry2 size.
-                0, 0, 0, 1, // Entry3 size.
-                0, 0, 0, 1, // Entry4 size.
-                0, 0, 0, 0x14, b's', b't', b'c', b'o',
-                0, 0, 0, 0, // FullBox.
-                0, 0, 0, 1, // Entry count.
-                0, 0, 2, 0xac, // Chunk offset1.
-                //
-                0, 0, 0, 0x0c, b'm', b'd', b'a', b't',
-                1, 2, 3, 4 // Data.
-            ]
-        ].into_iter().flatten().copied().collect();
-        assert_eq!(pretty_hex(&want), pretty_hex(&got));
+        let mut reader = CountingReader {
+            inner: std::io::Cursor::new(meta),
+            reads: 0,
+        };
+        let (header, got_samples) = read_samples_between(&mut reader, meta_size, None, start, end)
+            .await
+            .unwrap();
+        assert!(reader.reads < 150, "{}", reader.reads);
+
+        assert_eq!(
+            window_mp4(&header, want_samples, start, end).await,
+            window_mp4(&header, got_samples, start, end).await,
+        );
     }
 
     async fn two_recordings(start_time: UnixH264) -> (TempDir, RecDb) {
@@ -1244,6 +2830,9 @@ mod tests {
             start: start_time.into(),
             end: (start_time + UnixH264::new(16)).into(),
             cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         };
         let got = new_vod_reader_read_all(&rec_db, query).await;
 
@@ -1306,6 +2895,81 @@ mod tests {
         assert_eq!(pretty_hex(&want), pretty_hex(&got));
     }
 
+    #[tokio::test]
+    async fn test_vod_overlap() {
+        let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let start_time: UnixH264 = year_2000 + UnixNano::new(10 * MINUTE).into();
+        // Half a second steps.
+        let t = |i: i64| start_time + UnixH264::new(45000 * i);
+        let sample = |pts: UnixH264, data: u8, random_access_present: bool| VideoSample {
+            pts,
+            dts_offset: DtsOffset::new(0),
+            avcc: Arc::new(PaddedBytes::new(vec![data])),
+            random_access_present,
+            duration: DurationH264::new(45000),
+        };
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let mut rec_db = RecDb::new(
+            DummyLogger::new(),
+            path.clone(),
+            Disk::new(path, ByteSize(0)),
+        );
+        save_recording(
+            &mut rec_db,
+            t(0),
+            t(4),
+            vec![
+                sample(t(0), 1, true),
+                sample(t(1), 2, false),
+                sample(t(2), 3, false),
+                sample(t(3), 4, false),
+            ],
+        )
+        .await;
+
+        // Starts one second before the first recording ends.
+        save_recording(
+            &mut rec_db,
+            t(2),
+            t(6),
+            vec![
+                sample(t(2), 5, true),
+                sample(t(3), 6, false),
+                sample(t(4), 7, true),
+                sample(t(5), 8, false),
+            ],
+        )
+        .await;
+
+        let query = VodQuery {
+            monitor_id: "x".to_owned().try_into().unwrap(),
+            start: start_time.into(),
+            end: t(6).into(),
+            cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
+        };
+        let (recs, _, _, _) = query_samples(&rec_db, &VodConfig::default(), &query)
+            .await
+            .unwrap();
+        let got: Vec<_> = recs
+            .iter()
+            .flat_map(|v| &v.samples)
+            .map(|v| v.dts().unwrap())
+            .collect();
+        let want: Vec<_> = (0..6).map(t).collect();
+        assert_eq!(want, got);
+
+        // The overlapping samples shouldn't be included.
+        assert_eq!(2, recs[1].rec.size);
+
+        let got = new_vod_reader_read_all(&rec_db, query).await;
+        assert_eq!(&[1, 2, 3, 4, 7, 8], &got[got.len() - 6..]);
+    }
+
     #[tokio::test]
     async fn test_vod_multiple() {
         let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
@@ -1318,6 +2982,9 @@ mod tests {
             end: UnixNano::from(start_time + UnixNano::new(SECOND * 10).into() + UnixH264::new(1))
                 + UnixNano::new(1),
             cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         };
         let got = new_vod_reader_read_all(&rec_db, query).await;
 
@@ -1381,6 +3048,9 @@ mod tests {
             start: (start_time + UnixNano::new(SECOND * 9).into()).into(),
             end: (start_time + UnixNano::new(SECOND * 11).into()).into(),
             cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         };
         let got = new_vod_reader_read_all(&rec_db, query).await;
 
@@ -1444,6 +3114,9 @@ mod tests {
             start: (start_time + UnixNano::new(SECOND * 8).into()).into(),
             end: (start_time + UnixNano::new(SECOND * 12).into()).into(),
             cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         };
         let got = new_vod_reader_read_all(&rec_db, query).await;
 
@@ -1496,6 +3169,80 @@ mod tests {
         assert_eq!(pretty_hex(&want), pretty_hex(&got));
     }
 
+    // Recordings one second apart with a single sample each.
+    async fn small_recordings(start_time: UnixH264, n: u8) -> (TempDir, RecDb) {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let mut recdb = RecDb::new(
+            DummyLogger::new(),
+            path.clone(),
+            Disk::new(path, ByteSize(0)),
+        );
+        for i in 0..n {
+            let rec = start_time + UnixNano::new(SECOND * i64::from(i)).into();
+            save_recording(
+                &mut recdb,
+                rec,
+                rec + UnixH264::new(1),
+                vec![VideoSample {
+                    pts: rec,
+                    dts_offset: DtsOffset::new(0),
+                    avcc: Arc::new(PaddedBytes::new(vec![i])),
+                    random_access_present: true,
+                    duration: DurationH264::new(1),
+                }],
+            )
+            .await;
+        }
+        (temp_dir, recdb)
+    }
+
+    #[test_case(NonZeroUsize::new(3), None; "samples")]
+    #[test_case(None, NonZeroU64::new(3); "bytes")]
+    #[tokio::test]
+    async fn test_vod_truncated(max_samples: Option<NonZeroUsize>, max_bytes: Option<NonZeroU64>) {
+        let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let start_time: UnixH264 = year_2000 + UnixNano::new(10 * MINUTE).into();
+
+        let (_tmp_dir, rec_db) = small_recordings(start_time, 20).await;
+        let query = |end: UnixNano| VodQuery {
+            monitor_id: "x".to_owned().try_into().unwrap(),
+            start: start_time.into(),
+            end,
+            cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
+        };
+
+        // The budget is reached after the third recording.
+        let config = VodConfig {
+            max_samples,
+            max_bytes,
+            ..VodConfig::default()
+        };
+        let mut reader = VodReader::new(
+            &rec_db,
+            &VodCache::new(),
+            &config,
+            query(UnixNano::from(start_time) + UnixNano::new(SECOND * 30)),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(reader.truncated());
+        let mut got = Vec::new();
+        reader.read_to_end(&mut got).await.unwrap();
+        assert_eq!(got.len() as u64, reader.size());
+
+        // Same video as a query that ends after the third sample.
+        let third_end = start_time + UnixNano::new(SECOND * 2).into() + UnixH264::new(1);
+        let want =
+            new_vod_reader_read_all(&rec_db, query(UnixNano::from(third_end) + UnixNano::new(1)))
+                .await;
+        assert_eq!(pretty_hex(&want), pretty_hex(&got));
+    }
+
     async fn multiple_recordings(start_time: UnixH264) -> (TempDir, RecDb) {
         let temp_dir = TempDir::new().unwrap();
         let path = temp_dir.path().to_path_buf();
@@ -1590,6 +3337,9 @@ mod tests {
             start: start_time.into(),
             end: UnixNano::from(start_time + UnixH264::new(12)) + UnixNano::new(1),
             cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: false,
         };
         let got = new_vod_reader_read_all(&rec_db, query).await;
 
@@ -1650,9 +3400,30 @@ mod tests {
         start_time: UnixH264,
         end_time: UnixH264,
         samples: Vec<VideoSample>,
+    ) {
+        save_monitor_recording(rec_db, "x", start_time, end_time, samples).await;
+    }
+
+    async fn save_monitor_recording(
+        rec_db: &mut RecDb,
+        monitor_id: &str,
+        start_time: UnixH264,
+        end_time: UnixH264,
+        samples: Vec<VideoSample>,
+    ) {
+        save_recording_with_audio(rec_db, monitor_id, start_time, end_time, samples, None).await;
+    }
+
+    async fn save_recording_with_audio(
+        rec_db: &mut RecDb,
+        monitor_id: &str,
+        start_time: UnixH264,
+        end_time: UnixH264,
+        samples: Vec<VideoSample>,
+        audio: Option<(AudioParameters, Vec<AudioSample>)>,
     ) {
         let rec = rec_db
-            .new_recording("x".to_owned().try_into().unwrap(), start_time)
+            .new_recording(monitor_id.to_owned().try_into().unwrap(), start_time)
             .await
             .unwrap();
 
@@ -1663,6 +3434,7 @@ mod tests {
             width: 640,
             height: 480,
             extra_data: vec![0x33],
+            audio: audio.as_ref().map(|(params, _)| params.clone()),
         };
 
         let mut w = VideoWriter::new(&mut *meta, &mut *mdat, header)
@@ -1672,6 +3444,15 @@ mod tests {
             w.write_sample(&sample).await.unwrap();
         }
 
+        if let Some((_, audio_samples)) = audio {
+            let mut ameta = rec.new_file("ameta").await.unwrap();
+            let mut amdat = rec.new_file("amdat").await.unwrap();
+            let mut aw = AudioWriter::new(&mut *ameta, &mut *amdat);
+            for sample in &audio_samples {
+                aw.write_sample(sample).await.unwrap();
+            }
+        }
+
         let data = RecordingData {
             start: start_time.into(),
             end: end_time.into(),
@@ -1800,9 +3581,194 @@ mod tests {
         ]].into_iter().flatten().copied().collect()
     }
 
+    #[tokio::test]
+    async fn test_vod_multi() {
+        let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let start_time: UnixH264 = year_2000 + UnixNano::new(10 * MINUTE).into();
+
+        let (_tmp_dir, mut rec_db) = single_recording(start_time).await;
+        save_monitor_recording(
+            &mut rec_db,
+            "y",
+            start_time,
+            start_time + UnixH264::new(7),
+            vec![
+                VideoSample {
+                    pts: start_time + UnixH264::new(3),
+                    dts_offset: DtsOffset::new(0),
+                    avcc: Arc::new(PaddedBytes::new(vec![0x5])),
+                    random_access_present: true,
+                    duration: DurationH264::new(1),
+                },
+                VideoSample {
+                    pts: start_time + UnixH264::new(5),
+                    dts_offset: DtsOffset::new(0),
+                    avcc: Arc::new(PaddedBytes::new(vec![0x6])),
+                    random_access_present: true,
+                    duration: DurationH264::new(1),
+                },
+            ],
+        )
+        .await;
+
+        let m_id = |v: &str| -> MonitorId { v.to_owned().try_into().unwrap() };
+
+        // Monitor "z" doesn't have any recordings.
+        let query = MultiVodQuery {
+            monitor_ids: vec![m_id("x"), m_id("z"), m_id("y")],
+            start: start_time.into(),
+            end: UnixNano::from(start_time + UnixH264::new(7)) + UnixNano::new(1),
+            cache_id: 0,
+        };
+        let (mut reader, track_monitors) =
+            VodReader::new_multi(&rec_db, &VodConfig::default(), &query)
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(vec![m_id("x"), m_id("y")], track_monitors);
+        let mut got = Vec::new();
+        reader.read_to_end(&mut got).await.unwrap();
+        assert_eq!(got.len() as u64, reader.size());
+
+        let moov = child_box(&got, *b"moov");
+        let traks: Vec<_> = child_boxes(moov)
+            .into_iter()
+            .filter(|(typ, _)| typ == b"trak")
+            .map(|(_, trak)| trak)
+            .collect();
+        assert_eq!(2, traks.len());
+
+        let want = [(1, [1, 2, 3, 4].as_slice()), (2, [5, 6].as_slice())];
+        for (trak, (want_track_id, want_data)) in traks.into_iter().zip(want) {
+            let tkhd = child_box(trak, *b"tkhd");
+            let track_id = u32::from_be_bytes(tkhd[12..16].try_into().unwrap());
+            assert_eq!(want_track_id, track_id);
+
+            let stbl = child_box(child_box(child_box(trak, *b"mdia"), *b"minf"), *b"stbl");
+            let stsz = child_box(stbl, *b"stsz");
+            let sample_count = u32::from_be_bytes(stsz[8..12].try_into().unwrap());
+            assert_eq!(want_data.len(), sample_count as usize);
+
+            // Every track is a single chunk.
+            let stco = child_box(stbl, *b"stco");
+            let chunk_offset = u32::from_be_bytes(stco[8..12].try_into().unwrap()) as usize;
+            assert_eq!(want_data, &got[chunk_offset..][..want_data.len()]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vod_multi_too_many_monitors() {
+        let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let (_tmp_dir, rec_db) = single_recording(year_2000).await;
+        let query = MultiVodQuery {
+            monitor_ids: ["a", "b", "c", "d", "e"]
+                .into_iter()
+                .map(|v| v.to_owned().try_into().unwrap())
+                .collect(),
+            start: year_2000.into(),
+            end: UnixNano::from(year_2000) + UnixNano::new(SECOND),
+            cache_id: 0,
+        };
+        assert!(matches!(
+            VodReader::new_multi(&rec_db, &VodConfig::default(), &query).await,
+            Err(CreateVodReaderError::TooManyMonitors {
+                requested: 5,
+                max: 4
+            })
+        ));
+    }
+
+    // Returns the type and content of every box in `data`.
+    fn child_boxes(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut boxes = Vec::new();
+        while data.len() >= 8 {
+            let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+            boxes.push((data[4..8].try_into().unwrap(), &data[8..size]));
+            data = &data[size..];
+        }
+        boxes
+    }
+
+    fn child_box(data: &[u8], typ: [u8; 4]) -> &[u8] {
+        child_boxes(data)
+            .into_iter()
+            .find(|(v, _)| *v == typ)
+            .unwrap()
+            .1
+    }
+
+    // Returns the input unchanged with the parameters appended.
+    struct StubTranscoder;
+
+    #[async_trait::async_trait]
+    impl common::Transcoder for StubTranscoder {
+        async fn transcode(
+            &self,
+            mut input: Box<dyn AsyncRead + Send + Unpin>,
+            height: NonZeroU16,
+            crf: u8,
+        ) -> Result<Box<dyn AsyncRead + Send + Unpin>, TranscodeError> {
+            let mut buf = Vec::new();
+            input.read_to_end(&mut buf).await.unwrap();
+            buf.extend(height.get().to_be_bytes());
+            buf.push(crf);
+            Ok(Box::new(std::io::Cursor::new(buf)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_transcoded() {
+        let start_time: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let (_tmp_dir, rec_db) = single_recording(start_time).await;
+        let query = VodQuery {
+            monitor_id: "x".to_owned().try_into().unwrap(),
+            start: start_time.into(),
+            end: UnixNano::from(start_time + UnixH264::new(7)) + UnixNano::new(1),
+            cache_id: 0,
+            empty_samples: EmptySamplePolicy::Skip,
+            events: false,
+            fragmented: true,
+        };
+        let transcoder: ArcTranscoder = Arc::new(StubTranscoder);
+        let export = |q: VodQuery, crf| {
+            let rec_db = &rec_db;
+            let transcoder = &transcoder;
+            async move {
+                let cache = VodCache::new();
+                let config = VodConfig::default();
+                let height = NonZeroU16::new(480).unwrap();
+                export_transcoded(rec_db, &cache, &config, transcoder, q, height, crf).await
+            }
+        };
+
+        // The transcoder gets the regular mp4.
+        let mut got = Vec::new();
+        export(query.clone(), 23)
+            .await
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut got)
+            .await
+            .unwrap();
+        let mut query2 = query.clone();
+        query2.fragmented = false;
+        let mut want = new_vod_reader_read_all(&rec_db, query2).await;
+        want.extend([1, 0xe0, 23]);
+        assert_eq!(want, got);
+
+        assert!(matches!(
+            export(query.clone(), MAX_CRF + 1).await,
+            Err(ExportTranscodedError::InvalidCrf(52))
+        ));
+
+        let mut query3 = query;
+        query3.monitor_id = "y".to_owned().try_into().unwrap();
+        assert!(export(query3, 23).await.unwrap().is_none());
+    }
+
     async fn new_vod_reader_read_all(rec_db: &RecDb, query: VodQuery) -> Vec<u8> {
         let mut out = Vec::new();
-        let mut reader = VodReader::new(rec_db, &VodCache::new(), query)
+        let mut reader = VodReader::new(rec_db, &VodCache::new(), &VodConfig::default(), query)
             .await
             .unwrap()
             .unwrap();
@@ -1810,4 +3776,39 @@ mod tests {
         assert_eq!(out.len() as u64, reader.size());
         out
     }
+
+    #[test]
+    fn test_events_to_vtt() {
+        let event = |time: i64, duration: i64, label: &str| Event {
+            time: UnixNano::new(time),
+            duration: common::time::Duration::new(duration),
+            rec_duration: common::time::Duration::new(0),
+            detections: vec![common::Detection {
+                label: label.to_owned().try_into().unwrap(),
+                score: 87.4,
+                region: common::Region::default(),
+            }],
+            source: None,
+        };
+        let start = UnixNano::new(HOUR);
+        let end = start + UnixNano::new(MINUTE);
+
+        let events = [
+            // Starts 10 seconds before the window.
+            event(HOUR - 10 * SECOND, 15 * SECOND, "a"),
+            event(HOUR + 61_500 * MILLISECOND, SECOND, "outside"),
+            event(HOUR + 30 * SECOND + 250 * MILLISECOND, 40 * SECOND, "b"),
+        ];
+        let events: Vec<&Event> = events.iter().collect();
+
+        let want = "WEBVTT
+
+00:00:00.000 --> 00:00:05.000
+a 87%
+
+00:00:30.250 --> 00:01:00.000
+b 87%
+";
+        assert_eq!(want, events_to_vtt(&events, start, end));
+    }
 }
@@ -29,8 +29,8 @@ use tokio_util::io::ReaderStream;
 // request includes an If-Modified-Since header, serve_mp4_content uses
 // modtime to decide whether the content needs to be sent at all.
 //
-// If the caller has set w's ETag header formatted per RFC 7232, section 2.3,
-// ServeMP4Content uses it to handle requests using If-Match, If-None-Match, or If-Range.
+// If etag is set, it must be formatted per RFC 7232, section 2.3. It's included
+// in a ETag header and used to handle requests using If-Match, If-None-Match, or If-Range.
 //
 // Content must be seeked to the beginning of the file.
 #[allow(clippy::too_many_lines, clippy::unwrap_used)]
@@ -38,6 +38,7 @@ pub async fn serve_mp4_content<RS>(
     method: &Method,
     headers: &HeaderMap,
     last_modified: Option<std::time::SystemTime>,
+    etag: Option<&str>,
     size: u64,
     mut content: RS,
 ) -> Response
@@ -50,12 +51,20 @@ where
     if let Some(last_modified) = &last_modified {
         set_last_modified(&mut response_headers, last_modified);
     }
+    if let Some(etag) = etag.and_then(|v| HeaderValue::from_str(v).ok()) {
+        response_headers.insert(header::ETAG, etag);
+    }
 
-    let range_req =
-        match check_preconditions(method, headers, response_headers.clone(), &last_modified) {
-            PreconditionsResult::Done(response) => return response,
-            PreconditionsResult::Range(v) => v,
-        };
+    let range_req = match check_preconditions(
+        method,
+        headers,
+        response_headers.clone(),
+        &last_modified,
+        etag,
+    ) {
+        PreconditionsResult::Done(response) => return response,
+        PreconditionsResult::Range(v) => v,
+    };
 
     response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("video/mp4"));
 
@@ -237,9 +246,10 @@ fn check_preconditions(
     headers: &HeaderMap,
     response_headers: HeaderMap,
     last_modified: &Option<LastModified>,
+    etag: Option<&str>,
 ) -> PreconditionsResult {
     // This function carefully follows RFC 7232 section 6.
-    let mut ch = check_if_match(headers);
+    let mut ch = check_if_match(headers, etag);
     if ch == CondResult::None {
         ch = check_if_unmodified_since(headers, last_modified);
     }
@@ -249,7 +259,7 @@ fn check_preconditions(
         );
     }
 
-    match check_if_none_match(headers) {
+    match check_if_none_match(headers, etag) {
         CondResult::None => {
             if check_if_modified_since(method, headers, last_modified) == CondResult::False {
                 return PreconditionsResult::Done(not_modified_response(response_headers));
@@ -272,7 +282,8 @@ fn check_preconditions(
     }
 
     let mut range_header = get_header(headers, header::RANGE);
-    if range_header.is_some() && check_if_range(method, headers, last_modified) == CondResult::False
+    if range_header.is_some()
+        && check_if_range(method, headers, last_modified, etag) == CondResult::False
     {
         range_header = None;
     }
@@ -351,7 +362,7 @@ fn get_header(headers: &HeaderMap, name: HeaderName) -> Option<String> {
     Some(header.to_owned())
 }
 
-fn check_if_match(headers: &HeaderMap) -> CondResult {
+fn check_if_match(headers: &HeaderMap, etag2: Option<&str>) -> CondResult {
     let mut im = get_header(headers, header::IF_MATCH);
     if im.is_none() {
         return CondResult::None;
@@ -373,9 +384,8 @@ fn check_if_match(headers: &HeaderMap) -> CondResult {
             break;
         };
 
-        let etag2 = get_header(headers, header::ETAG);
         if let Some(etag2) = etag2 {
-            if etag_strong_match(&etag, &etag2) {
+            if etag_strong_match(&etag, etag2) {
                 return CondResult::True;
             }
         }
@@ -407,7 +417,7 @@ fn check_if_unmodified_since(headers: &HeaderMap, modified: &Option<LastModified
     CondResult::None
 }
 
-fn check_if_none_match(headers: &HeaderMap) -> CondResult {
+fn check_if_none_match(headers: &HeaderMap, etag2: Option<&str>) -> CondResult {
     let Some(inm) = get_header(headers, header::IF_NONE_MATCH) else {
         return CondResult::None;
     };
@@ -432,8 +442,8 @@ fn check_if_none_match(headers: &HeaderMap) -> CondResult {
             break;
         };
 
-        if let Some(etag2) = get_header(headers, header::ETAG) {
-            if etag_weak_match(&etag, &etag2) {
+        if let Some(etag2) = etag2 {
+            if etag_weak_match(&etag, etag2) {
                 return CondResult::False;
             }
         }
@@ -471,7 +481,8 @@ fn check_if_modified_since(
 fn check_if_range(
     method: &Method,
     headers: &HeaderMap,
-    _modified: &Option<LastModified>,
+    modified: &Option<LastModified>,
+    etag2: Option<&str>,
 ) -> CondResult {
     if method != Method::GET && method != Method::HEAD {
         return CondResult::None;
@@ -481,9 +492,9 @@ fn check_if_range(
         return CondResult::None;
     };
 
-    if let Some((etag, _)) = scan_etag(ir) {
-        if let Some(etag2) = get_header(headers, header::ETAG) {
-            if etag_strong_match(&etag, &etag2) {
+    if let Some((etag, _)) = scan_etag(ir.clone()) {
+        if let Some(etag2) = etag2 {
+            if etag_strong_match(&etag, etag2) {
                 return CondResult::True;
             }
         }
@@ -492,16 +503,15 @@ fn check_if_range(
 
     // The If-Range value is typically the ETag value, but it may also be
     // the modtime date. See golang.org/issue/8367.
-    /*if modtime.IsZero() {
-        return condFalse
-    }
-    t, err := http.ParseTime(ir)
-    if err != nil {
-        return condFalse
+    let Some(modified) = modified else {
+        return CondResult::False;
+    };
+    let Ok(t) = httpdate::parse_http_date(&ir) else {
+        return CondResult::False;
+    };
+    if HttpDate::from(t) == modified.0 {
+        return CondResult::True;
     }
-    if t.Unix() == modtime.Unix() {
-        return condTrue
-    }*/
     CondResult::False
 }
 
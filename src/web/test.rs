@@ -6,7 +6,10 @@ use crate::serve_mp4_content;
 use axum::body::to_bytes;
 use http::{header, HeaderMap, HeaderValue, Method, StatusCode};
 
-use std::{io::Cursor, time::UNIX_EPOCH};
+use std::{
+    io::Cursor,
+    time::{Duration, UNIX_EPOCH},
+};
 use test_case::test_case;
 
 #[tokio::test]
@@ -18,6 +21,7 @@ async fn test_serve_mp4() {
         &Method::GET,
         &headers,
         Some(UNIX_EPOCH),
+        None,
         10,
         Cursor::new(file.clone()),
     )
@@ -72,6 +76,7 @@ async fn test_serve_mp4_range(r: &str, code: StatusCode, ranges: Vec<WantRange>)
         &Method::GET,
         &headers,
         Some(UNIX_EPOCH),
+        None,
         11,
         Cursor::new(file.to_owned()),
     )
@@ -116,3 +121,63 @@ async fn test_serve_mp4_range(r: &str, code: StatusCode, ranges: Vec<WantRange>)
         );
     }
 }
+
+const TEST_ETAG: &str = "W/\"x-1-2-0\"";
+
+async fn serve_with_validators(headers: &[(header::HeaderName, &str)]) -> axum::response::Response {
+    let headers: HeaderMap = headers
+        .iter()
+        .map(|(k, v)| (k.clone(), HeaderValue::from_str(v).unwrap()))
+        .collect();
+    serve_mp4_content(
+        &Method::GET,
+        &headers,
+        Some(UNIX_EPOCH + Duration::from_secs(1000)),
+        Some(TEST_ETAG),
+        11,
+        Cursor::new(b"0123456789.".to_owned()),
+    )
+    .await
+}
+
+const TEST_LAST_MODIFIED: &str = "Thu, 01 Jan 1970 00:16:40 GMT";
+
+#[test_case(&[], StatusCode::OK; "none")]
+#[test_case(&[(header::IF_NONE_MATCH, TEST_ETAG)], StatusCode::NOT_MODIFIED; "if none match")]
+#[test_case(&[(header::IF_NONE_MATCH, "\"x-1-2-0\"")], StatusCode::NOT_MODIFIED; "if none match strong")]
+#[test_case(&[(header::IF_NONE_MATCH, "W/\"y\"")], StatusCode::OK; "if none match other")]
+#[test_case(&[(header::IF_MODIFIED_SINCE, TEST_LAST_MODIFIED)], StatusCode::NOT_MODIFIED; "if modified since")]
+#[test_case(&[(header::IF_MODIFIED_SINCE, "Thu, 01 Jan 1970 00:16:39 GMT")], StatusCode::OK; "modified")]
+#[tokio::test]
+async fn test_serve_mp4_not_modified(headers: &[(header::HeaderName, &str)], code: StatusCode) {
+    let response = serve_with_validators(headers).await;
+    assert_eq!(code, response.status());
+    assert_eq!(TEST_ETAG, response.headers()[header::ETAG]);
+    if code == StatusCode::OK {
+        assert_eq!(
+            TEST_LAST_MODIFIED,
+            response.headers()[header::LAST_MODIFIED]
+        );
+    }
+}
+
+// Weak ETags can't be used with If-Range, clients send the Last-Modified date instead.
+#[test_case(TEST_LAST_MODIFIED, StatusCode::PARTIAL_CONTENT; "last modified")]
+#[test_case("Thu, 01 Jan 1970 00:16:39 GMT", StatusCode::OK; "old date")]
+#[test_case(TEST_ETAG, StatusCode::OK; "weak etag")]
+#[tokio::test]
+async fn test_serve_mp4_if_range(if_range: &str, code: StatusCode) {
+    let response =
+        serve_with_validators(&[(header::RANGE, "bytes=2-4"), (header::IF_RANGE, if_range)]).await;
+    assert_eq!(code, response.status());
+
+    let want: &[u8] = if code == StatusCode::PARTIAL_CONTENT {
+        b"234"
+    } else {
+        b"0123456789."
+    };
+    assert_eq!(
+        want,
+        to_bytes(response.into_body(), usize::MAX).await.unwrap()
+    );
+}
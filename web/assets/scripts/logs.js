@@ -25,6 +25,7 @@ const LevelDebug = "debug";
  * @property {string} monitorID
  * @property {string} message
  * @property {Number} time
+ * @property {Number=} repeatCount
  */
 
 /**
@@ -77,8 +78,8 @@ function newLogger(formatLog) {
 	};
 
 	let lastLog = false;
-	/** @type {Number} */
-	let currentTime;
+	/** @type {string} */
+	let cursor;
 	/** @type {string[]} */
 	let levels;
 	/** @type {string[]} */
@@ -113,7 +114,7 @@ function newLogger(formatLog) {
 				levels: levels,
 				sources: sources,
 				monitors: monitors,
-				time: currentTime,
+				cursor: cursor,
 				limit: 20,
 			})
 		);
@@ -132,18 +133,18 @@ function newLogger(formatLog) {
 				alert(`could not get logs: ${response.status}, ${await response.text()}`);
 				return;
 			}
-			const logs = await response.json();
+			const { entries, nextCursor } = await response.json();
 
-			if (logs.length === 0) {
+			for (const log of entries) {
+				$logList.append(createSpan(formatLog(log)));
+			}
+
+			if (nextCursor === null) {
 				lastLog = true;
 				console.log("last log.");
 				return;
 			}
-
-			for (const log of logs) {
-				currentTime = log.time;
-				$logList.append(createSpan(formatLog(log)));
-			}
+			cursor = nextCursor;
 		} catch (error) {
 			if (error instanceof DOMException && error.name === "AbortError") {
 				return;
@@ -179,7 +180,7 @@ function newLogger(formatLog) {
 		}
 
 		lastLog = false;
-		currentTime = undefined;
+		cursor = undefined;
 		$logList.innerHTML = "";
 
 		stopped = false;
@@ -271,6 +272,10 @@ function newFormater(monitorNameByID, timeZone) {
 		}
 
 		output += log.message;
+
+		if (log.repeatCount > 1) {
+			output += ` (x${log.repeatCount})`;
+		}
 		return output;
 	};
 }
@@ -56,6 +56,13 @@ describe("logger", () => {
 		log.level = "debug";
 		expect(format(log)).toBe("[DEBUG] 1970-01-01_00:00:00 0: m0: 0");
 	});
+	test("repeatCount", () => {
+		const format = newFormater(monitorIDtoName, "utc");
+		const log = newTestLog();
+		// @ts-ignore
+		log.repeatCount = 5;
+		expect(format(log)).toBe("[ERROR] 1970-01-01_00:00:00 0: m0: 0 (x5)");
+	});
 });
 
 /* eslint-disable no-useless-escape */
@@ -1236,6 +1236,10 @@ function newSourceField(options, getField) {
  * @property {Field<string>} protocol
  * @property {Field<string>} mainStream
  * @property {Field<string>} subStream
+ * @property {Field<boolean>} seiRecoveryPoint
+ * @property {Field<boolean>} audio
+ * @property {Field<number>} connectTimeout
+ * @property {Field<number>} readTimeout
  */
 
 /** @returns {Field<string>} */
@@ -1263,6 +1267,10 @@ function newSourceRTSP() {
 				placeholder: "rtsp://x.x.x.x/sub (optional)",
 			}
 		),
+		seiRecoveryPoint: fieldTemplate.toggle("SEI recovery points", false),
+		audio: fieldTemplate.toggle("Audio", false),
+		connectTimeout: fieldTemplate.integer("Connect timeout (sec)", "20", 20),
+		readTimeout: fieldTemplate.integer("Read timeout (sec)", "20", 20),
 	};
 
 	const form = newForm(fields);
@@ -1372,6 +1380,16 @@ function init() {
 	monitorFields.sourcertsp = newSourceRTSP();
 	monitorFields.alwaysRecord = fieldTemplate.toggle("Always record", false);
 	monitorFields.videoLength = fieldTemplate.number("Video length (min)", "15", 15);
+	monitorFields.videoMaxSize = fieldTemplate.number("Max video size (MB)", "0", 0);
+	monitorFields.recordIndex = fieldTemplate.toggle("Seek index", false);
+	monitorFields.encryptRecordings = fieldTemplate.toggle("Encrypt recordings", false);
+	monitorFields.thumbMaxSize = fieldTemplate.integer("Thumbnail max size (px)", "0", 0);
+	monitorFields.thumbQuality = fieldTemplate.integer("Thumbnail quality", "75", 75);
+	monitorFields.keyframeWarnInterval = fieldTemplate.integer(
+		"Keyframe warning interval (s)",
+		"10",
+		10
+	);
 	//timestampOffset: fieldTemplate.integer("Timestamp offset (ms)", "500", "500"),
 	/* SETTINGS_LAST_MONITOR_FIELD */
 